@@ -226,7 +226,7 @@ fn check_payment_err(send_err: PaymentSendFailure) {
 		PaymentSendFailure::AllFailedRetrySafe(per_path_results) => {
 			for api_err in per_path_results { check_api_err(api_err); }
 		},
-		PaymentSendFailure::PartialFailure(per_path_results) => {
+		PaymentSendFailure::PartialFailure { results: per_path_results, .. } => {
 			for res in per_path_results { if let Err(api_err) = res { check_api_err(api_err); } }
 		},
 	}
@@ -247,7 +247,7 @@ fn send_payment(source: &ChanMan, dest: &ChanMan, dest_chan_id: u64, amt: u64, p
 			fee_msat: amt,
 			cltv_expiry_delta: 200,
 		}]],
-	}, PaymentHash(payment_hash.into_inner()), &None) {
+	}, PaymentHash(payment_hash.into_inner()), &None, &[]) {
 		check_payment_err(err);
 		false
 	} else { true }
@@ -272,7 +272,7 @@ fn send_hop_payment(source: &ChanMan, middle: &ChanMan, middle_chan_id: u64, des
 			fee_msat: amt,
 			cltv_expiry_delta: 200,
 		}]],
-	}, PaymentHash(payment_hash.into_inner()), &None) {
+	}, PaymentHash(payment_hash.into_inner()), &None, &[]) {
 		check_payment_err(err);
 		false
 	} else { true }
@@ -532,7 +532,7 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 						fee_msat: 10_000_000,
 						cltv_expiry_delta: 200,
 					}]],
-				}, PaymentHash(payment_hash.into_inner()), &Some(PaymentSecret(payment_secret.into_inner()))) {
+				}, PaymentHash(payment_hash.into_inner()), &Some(PaymentSecret(payment_secret.into_inner())), &[]) {
 					check_payment_err(err);
 				}
 			} }
@@ -689,7 +689,7 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 				let had_events = !events.is_empty();
 				for event in events.drain(..) {
 					match event {
-						events::Event::PaymentReceived { payment_hash, payment_secret, amt } => {
+						events::Event::PaymentReceived { payment_hash, payment_secret, amt, .. } => {
 							if claim_set.insert(payment_hash.0) {
 								if $fail {
 									assert!(nodes[$node].fail_htlc_backwards(&payment_hash, &payment_secret));
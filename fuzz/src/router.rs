@@ -19,6 +19,7 @@ use lightning::routing::router::{get_route, RouteHint};
 use lightning::util::logger::Logger;
 use lightning::util::ser::Readable;
 use lightning::routing::network_graph::{NetworkGraph, RoutingFees};
+use lightning::routing::scorer::Scorer;
 
 use bitcoin::secp256k1::key::PublicKey;
 use bitcoin::network::constants::Network;
@@ -205,13 +206,27 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 							first_hops_vec.push(ChannelDetails {
 								channel_id: [0; 32],
 								short_channel_id: Some(scid),
+								scid_alias: None,
+								counterparty_scid_alias: None,
 								remote_network_id: *rnid,
 								counterparty_features: InitFeatures::known(),
 								channel_value_satoshis: slice_to_be64(get_slice!(8)),
 								user_id: 0,
 								inbound_capacity_msat: 0,
 								is_live: true,
-								outbound_capacity_msat: 0,
+								balance_msat: 0,
+								counterparty_balance_msat: 0,
+								holder_channel_reserve_satoshis: Some(0),
+								counterparty_channel_reserve_satoshis: 0,
+								confirmations: 6,
+								confirmations_required: Some(6),
+								is_public: true,
+								is_funding_locked: true,
+								feerate_per_kw: 253,
+								counterparty_forwarding_info: None,
+								holder_to_self_delay: Some(144),
+								counterparty_to_self_delay: 144,
+								outbound_capacity_msat: slice_to_be64(get_slice!(8)),
 							});
 						}
 						Some(&first_hops_vec[..])
@@ -232,15 +247,17 @@ pub fn do_test<Out: test_logger::Output>(data: &[u8], out: Out) {
 							},
 							cltv_expiry_delta: slice_to_be16(get_slice!(2)),
 							htlc_minimum_msat: slice_to_be64(get_slice!(8)),
+							htlc_maximum_msat: if get_slice!(1)[0] & 1 == 1 { Some(slice_to_be64(get_slice!(8))) } else { None },
 						});
 					}
 				}
 				let last_hops = &last_hops_vec[..];
+				let scorer = Scorer::with_fixed_penalty(0);
 				for target in node_pks.iter() {
 					let _ = get_route(&our_pubkey, &net_graph, target,
 						first_hops.map(|c| c.iter().collect::<Vec<_>>()).as_ref().map(|a| a.as_slice()),
 						&last_hops.iter().collect::<Vec<_>>(),
-						slice_to_be64(get_slice!(8)), slice_to_be32(get_slice!(4)), Arc::clone(&logger));
+						slice_to_be64(get_slice!(8)), slice_to_be32(get_slice!(4)), Arc::clone(&logger), &scorer);
 				}
 			},
 		}
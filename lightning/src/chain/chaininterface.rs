@@ -11,9 +11,11 @@
 //! blockchain.
 //!
 //! Includes traits for monitoring and receiving notifications of new blocks and block
-//! disconnections, transaction broadcasting, and feerate information requests.
+//! disconnections, transaction broadcasting, feerate information requests and wallet UTXOs used
+//! for fee-bumping and for funding dual-funded channels.
 
-use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::blockdata::transaction::{Transaction, TxOut, OutPoint};
+use bitcoin::blockdata::script::Script;
 
 /// An interface to send a transaction to the Bitcoin network.
 pub trait BroadcasterInterface: Sync + Send {
@@ -51,3 +53,71 @@ pub trait FeeEstimator: Sync + Send {
 
 /// Minimum relay fee as required by bitcoin network mempool policy.
 pub const MIN_RELAY_FEE_SAT_PER_1000_WEIGHT: u64 = 4000;
+
+/// A confirmed output controlled by the user's on-chain wallet, which may be spent to bump the
+/// feerate of one of our transactions or contributed to a funding transaction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletUtxo {
+	/// The transaction containing the output. The full transaction is sent to our counterparty
+	/// when the output is contributed to a funding transaction, so that they can check its value.
+	pub prev_tx: Transaction,
+	/// The index of the output in `prev_tx`, which must be one of its outputs.
+	pub prev_vout: u32,
+	/// An upper bound on the weight of the scriptSig and witness needed to spend the output, used
+	/// to compute the fee of the spending transaction before it is signed.
+	pub satisfaction_weight: u64,
+}
+
+impl WalletUtxo {
+	/// Gets the outpoint of the output.
+	pub fn outpoint(&self) -> OutPoint {
+		OutPoint { txid: self.prev_tx.txid(), vout: self.prev_vout }
+	}
+
+	/// Gets the output itself, which is needed to sign a spend of it.
+	pub fn output(&self) -> &TxOut {
+		&self.prev_tx.output[self.prev_vout as usize]
+	}
+}
+
+/// A source of on-chain wallet UTXOs, used for:
+///  * bumping the feerate of a broadcast holder commitment transaction with a
+///    child-pays-for-parent transaction if it doesn't confirm in time, and paying the fee of our
+///    HTLC transactions. This only applies to channels using option_anchors_zero_fee_htlc_tx, as
+///    other commitment transactions have no output we can spend before they confirm, and other
+///    HTLC transactions pay their own fee.
+///  * contributing funds to dual-funded channels and splices, see
+///    ChannelManager::create_dual_funded_channel, ChannelManager::splice_in and
+///    ChannelHandshakeConfig::dual_funding_contribution_satoshis.
+///
+/// The same source should be given to ChainMonitor::with_utxo_source for the former and returned
+/// by KeysInterface::get_utxo_source for the latter.
+///
+/// Any value left over after paying fees (and funding a channel) is sent back to the wallet in a
+/// change output. Each time a child or HTLC transaction is bumped, a new one is generated which
+/// replaces the previous one, so the same UTXOs may (and should, if still available) be returned
+/// again. UTXOs contributed to a funding transaction, however, must not be returned again until
+/// the channel open or splice fails.
+///
+/// Only SegWit outputs may be contributed to funding transactions, as our counterparty would
+/// otherwise be able to malleate them. We only accept P2WPKH inputs to funding transactions from
+/// our counterparty, so peers running this library will likewise reject any other UTXOs.
+///
+/// Note that all of the functions implemented here *must* be reentrant-safe (they're called from
+/// inside the library in response to chain and P2P events).
+pub trait UtxoSource: Sync + Send {
+	/// Gets a set of confirmed wallet UTXOs with a total value of at least `target_value_sat`.
+	///
+	/// If not enough funds are available, the UTXOs returned will be used to bump the feerate of a
+	/// transaction only if their total value is enough to pay the fee of the child or HTLC
+	/// transaction, and we refuse to open, accept or splice a channel we can't contribute to.
+	fn get_utxos(&self, target_value_sat: u64) -> Vec<WalletUtxo>;
+
+	/// Gets a script to which the change of a child, HTLC or funding transaction will be sent.
+	fn get_change_script(&self) -> Script;
+
+	/// Gets the witness spending `utxo` at input `input_idx` of the given transaction. The
+	/// signature(s) must commit to all inputs and outputs (ie use SIGHASH_ALL), as other inputs
+	/// of `tx` are signed independently.
+	fn sign_input(&self, tx: &Transaction, input_idx: usize, utxo: &WalletUtxo) -> Result<Vec<Vec<u8>>, ()>;
+}
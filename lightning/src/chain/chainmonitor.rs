@@ -17,6 +17,10 @@
 //! [`chain::Filter`] trait. This provides a mechanism to signal new relevant outputs back to light
 //! clients, such that transactions spending those outputs are included in block data.
 //!
+//! It is also parameterized by an optional UTXO source, which must implement the [`UtxoSource`]
+//! trait. This provides wallet funds to bump the feerate of holder commitment transactions which
+//! don't confirm in time through child-pays-for-parent.
+//!
 //! `ChainMonitor` may be used directly to monitor channels locally or as a part of a distributed
 //! setup to monitor channels remotely. In the latter case, a custom `chain::Watch` implementation
 //! would be responsible for routing each update to a remote server and for retrieving monitor
@@ -28,12 +32,13 @@
 //! [`chain::Watch`]: ../trait.Watch.html
 //! [`ChannelMonitor`]: ../channelmonitor/struct.ChannelMonitor.html
 //! [`MonitorEvent`]: ../channelmonitor/enum.MonitorEvent.html
+//! [`UtxoSource`]: ../chaininterface/trait.UtxoSource.html
 
 use bitcoin::blockdata::block::BlockHeader;
 
 use chain;
 use chain::Filter;
-use chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use chain::chaininterface::{BroadcasterInterface, FeeEstimator, UtxoSource};
 use chain::channelmonitor;
 use chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateErr, MonitorEvent, Persist};
 use chain::transaction::{OutPoint, TransactionData};
@@ -56,12 +61,13 @@ use std::ops::Deref;
 /// [`chain::Watch`]: ../trait.Watch.html
 /// [`ChannelManager`]: ../../ln/channelmanager/struct.ChannelManager.html
 /// [module-level documentation]: index.html
-pub struct ChainMonitor<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref>
+pub struct ChainMonitor<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref, U: Deref = &'static dyn UtxoSource>
 	where C::Target: chain::Filter,
         T::Target: BroadcasterInterface,
         F::Target: FeeEstimator,
         L::Target: Logger,
         P::Target: channelmonitor::Persist<ChanSigner>,
+        U::Target: UtxoSource,
 {
 	/// The monitors
 	pub monitors: Mutex<HashMap<OutPoint, ChannelMonitor<ChanSigner>>>,
@@ -70,14 +76,16 @@ pub struct ChainMonitor<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L
 	logger: L,
 	fee_estimator: F,
 	persister: P,
+	utxo_source: Option<U>,
 }
 
-impl<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref> ChainMonitor<ChanSigner, C, T, F, L, P>
+impl<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref, U: Deref> ChainMonitor<ChanSigner, C, T, F, L, P, U>
 where C::Target: chain::Filter,
 	    T::Target: BroadcasterInterface,
 	    F::Target: FeeEstimator,
 	    L::Target: Logger,
 	    P::Target: channelmonitor::Persist<ChanSigner>,
+	    U::Target: UtxoSource,
 {
 	/// Dispatches to per-channel monitors, which are responsible for updating their on-chain view
 	/// of a channel and reacting accordingly based on transactions in the connected block. See
@@ -89,13 +97,23 @@ where C::Target: chain::Filter,
 	/// descendants of such transactions. It is not necessary to re-fetch the block to obtain
 	/// updated `txdata`.
 	///
+	/// If a UTXO source was provided, also bumps any holder commitment or HTLC transaction which
+	/// is due for it. See [`ChannelMonitor::bump_holder_commitment_with_cpfp`] and
+	/// [`ChannelMonitor::bump_holder_htlc_txn`] for details.
+	///
 	/// [`ChannelMonitor::block_connected`]: ../channelmonitor/struct.ChannelMonitor.html#method.block_connected
+	/// [`ChannelMonitor::bump_holder_commitment_with_cpfp`]: ../channelmonitor/struct.ChannelMonitor.html#method.bump_holder_commitment_with_cpfp
+	/// [`ChannelMonitor::bump_holder_htlc_txn`]: ../channelmonitor/struct.ChannelMonitor.html#method.bump_holder_htlc_txn
 	/// [`chain::Watch::release_pending_monitor_events`]: ../trait.Watch.html#tymethod.release_pending_monitor_events
 	/// [`chain::Filter`]: ../trait.Filter.html
 	pub fn block_connected(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
 		let mut monitors = self.monitors.lock().unwrap();
 		for monitor in monitors.values_mut() {
 			let mut txn_outputs = monitor.block_connected(header, txdata, height, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			if let Some(ref utxo_source) = self.utxo_source {
+				monitor.bump_holder_commitment_with_cpfp(height, &**utxo_source, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+				monitor.bump_holder_htlc_txn(height, &**utxo_source, &*self.broadcaster, &*self.fee_estimator, &*self.logger);
+			}
 
 			if let Some(ref chain_source) = self.chain_source {
 				for (txid, outputs) in txn_outputs.drain(..) {
@@ -119,16 +137,18 @@ where C::Target: chain::Filter,
 		}
 	}
 
-	/// Creates a new `ChainMonitor` used to watch on-chain activity pertaining to channels.
+	/// Creates a new `ChainMonitor` which, unlike [`ChainMonitor::new`], may be given a UTXO
+	/// source implementing [`UtxoSource`].
 	///
-	/// When an optional chain source implementing [`chain::Filter`] is provided, the chain monitor
-	/// will call back to it indicating transactions and outputs of interest. This allows clients to
-	/// pre-filter blocks or only fetch blocks matching a compact filter. Otherwise, clients may
-	/// always need to fetch full blocks absent another means for determining which blocks contain
-	/// transactions relevant to the watched channels.
+	/// When one is provided, the chain monitor will use its funds to bump the feerate of holder
+	/// commitment transactions of channels using option_anchors_zero_fee_htlc_tx. Otherwise, these
+	/// transactions confirm at the feerate agreed upon with our counterparty, which may take too
+	/// long during fee spikes. Holder commitment transactions of channels without anchor outputs
+	/// can never be bumped, as none of their outputs are spendable by us before they confirm.
 	///
-	/// [`chain::Filter`]: ../trait.Filter.html
-	pub fn new(chain_source: Option<C>, broadcaster: T, logger: L, feeest: F, persister: P) -> Self {
+	/// [`ChainMonitor::new`]: struct.ChainMonitor.html#method.new
+	/// [`UtxoSource`]: ../chaininterface/trait.UtxoSource.html
+	pub fn with_utxo_source(chain_source: Option<C>, broadcaster: T, logger: L, feeest: F, persister: P, utxo_source: Option<U>) -> Self {
 		Self {
 			monitors: Mutex::new(HashMap::new()),
 			chain_source,
@@ -136,16 +156,43 @@ where C::Target: chain::Filter,
 			logger,
 			fee_estimator: feeest,
 			persister,
+			utxo_source,
 		}
 	}
 }
 
-impl<ChanSigner: ChannelKeys, C: Deref + Sync + Send, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, P: Deref + Sync + Send> chain::Watch for ChainMonitor<ChanSigner, C, T, F, L, P>
+impl<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref> ChainMonitor<ChanSigner, C, T, F, L, P>
+where C::Target: chain::Filter,
+	    T::Target: BroadcasterInterface,
+	    F::Target: FeeEstimator,
+	    L::Target: Logger,
+	    P::Target: channelmonitor::Persist<ChanSigner>,
+{
+	/// Creates a new `ChainMonitor` used to watch on-chain activity pertaining to channels.
+	///
+	/// When an optional chain source implementing [`chain::Filter`] is provided, the chain monitor
+	/// will call back to it indicating transactions and outputs of interest. This allows clients to
+	/// pre-filter blocks or only fetch blocks matching a compact filter. Otherwise, clients may
+	/// always need to fetch full blocks absent another means for determining which blocks contain
+	/// transactions relevant to the watched channels.
+	///
+	/// The returned chain monitor never bumps the feerate of holder commitment transactions, see
+	/// [`ChainMonitor::with_utxo_source`] for one which does.
+	///
+	/// [`chain::Filter`]: ../trait.Filter.html
+	/// [`ChainMonitor::with_utxo_source`]: struct.ChainMonitor.html#method.with_utxo_source
+	pub fn new(chain_source: Option<C>, broadcaster: T, logger: L, feeest: F, persister: P) -> Self {
+		Self::with_utxo_source(chain_source, broadcaster, logger, feeest, persister, None)
+	}
+}
+
+impl<ChanSigner: ChannelKeys, C: Deref + Sync + Send, T: Deref + Sync + Send, F: Deref + Sync + Send, L: Deref + Sync + Send, P: Deref + Sync + Send, U: Deref + Sync + Send> chain::Watch for ChainMonitor<ChanSigner, C, T, F, L, P, U>
 where C::Target: chain::Filter,
 	    T::Target: BroadcasterInterface,
 	    F::Target: FeeEstimator,
 	    L::Target: Logger,
 	    P::Target: channelmonitor::Persist<ChanSigner>,
+	    U::Target: UtxoSource,
 {
 	type Keys = ChanSigner;
 
@@ -233,12 +280,13 @@ where C::Target: chain::Filter,
 	}
 }
 
-impl<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref> events::EventsProvider for ChainMonitor<ChanSigner, C, T, F, L, P>
+impl<ChanSigner: ChannelKeys, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref, U: Deref> events::EventsProvider for ChainMonitor<ChanSigner, C, T, F, L, P, U>
 	where C::Target: chain::Filter,
 	      T::Target: BroadcasterInterface,
 	      F::Target: FeeEstimator,
 	      L::Target: Logger,
 	      P::Target: channelmonitor::Persist<ChanSigner>,
+	      U::Target: UtxoSource,
 {
 	fn get_and_clear_pending_events(&self) -> Vec<Event> {
 		let mut pending_events = Vec::new();
@@ -41,7 +41,7 @@ use ln::chan_utils;
 use ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HTLCType, ChannelTransactionParameters, HolderCommitmentTransaction};
 use ln::channelmanager::{HTLCSource, PaymentPreimage, PaymentHash};
 use ln::onchaintx::{OnchainTxHandler, InputDescriptors};
-use chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use chain::chaininterface::{BroadcasterInterface, FeeEstimator, UtxoSource};
 use chain::transaction::{OutPoint, TransactionData};
 use chain::keysinterface::{SpendableOutputDescriptor, ChannelKeys, KeysInterface};
 use util::logger::Logger;
@@ -482,8 +482,11 @@ enum OnchainEvent {
 	},
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+// Version 2 appended the splice and funding double-spend tracking fields, which we leave unset
+// when reading version 1 data. The ChannelTransactionParameters it contains changed layout in
+// version 2, so version 1 readers must refuse it.
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 #[cfg_attr(any(test, feature = "fuzztarget", feature = "_test_utils"), derive(PartialEq))]
 #[derive(Clone)]
@@ -512,6 +515,21 @@ pub(crate) enum ChannelMonitorUpdateStep {
 		/// think we've fallen behind!
 		should_broadcast: bool,
 	},
+	/// Used to indicate that a splice transaction was negotiated, providing the commitment
+	/// transactions spending its funding output. These become the latest commitment transactions
+	/// once the splice transaction can't be reorged out anymore, or once it's locked.
+	SpliceFundingNegotiated {
+		channel_parameters: ChannelTransactionParameters,
+		holder_commitment_tx: HolderCommitmentTransaction,
+		holder_htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
+		counterparty_commitment_txid: Txid,
+		counterparty_htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>,
+	},
+	/// Used to provide the inputs of the funding transaction before it confirms, so that we can
+	/// detect it being double-spent while unconfirmed.
+	FundingTransactionInputs {
+		inputs: Vec<BitcoinOutPoint>,
+	},
 }
 
 impl Writeable for ChannelMonitorUpdateStep {
@@ -551,6 +569,30 @@ impl Writeable for ChannelMonitorUpdateStep {
 				4u8.write(w)?;
 				should_broadcast.write(w)?;
 			},
+			&ChannelMonitorUpdateStep::SpliceFundingNegotiated { ref channel_parameters, ref holder_commitment_tx, ref holder_htlc_outputs, ref counterparty_commitment_txid, ref counterparty_htlc_outputs } => {
+				5u8.write(w)?;
+				channel_parameters.write(w)?;
+				holder_commitment_tx.write(w)?;
+				(holder_htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref signature, ref source) in holder_htlc_outputs.iter() {
+					output.write(w)?;
+					signature.write(w)?;
+					source.write(w)?;
+				}
+				counterparty_commitment_txid.write(w)?;
+				(counterparty_htlc_outputs.len() as u64).write(w)?;
+				for &(ref output, ref source) in counterparty_htlc_outputs.iter() {
+					output.write(w)?;
+					source.as_ref().map(|b| b.as_ref()).write(w)?;
+				}
+			},
+			&ChannelMonitorUpdateStep::FundingTransactionInputs { ref inputs } => {
+				6u8.write(w)?;
+				(inputs.len() as u64).write(w)?;
+				for input in inputs.iter() {
+					input.write(w)?;
+				}
+			},
 		}
 		Ok(())
 	}
@@ -602,11 +644,96 @@ impl Readable for ChannelMonitorUpdateStep {
 					should_broadcast: Readable::read(r)?
 				})
 			},
+			5u8 => {
+				Ok(ChannelMonitorUpdateStep::SpliceFundingNegotiated {
+					channel_parameters: Readable::read(r)?,
+					holder_commitment_tx: Readable::read(r)?,
+					holder_htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, Readable::read(r)?, Readable::read(r)?));
+						}
+						res
+					},
+					counterparty_commitment_txid: Readable::read(r)?,
+					counterparty_htlc_outputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push((Readable::read(r)?, <Option<HTLCSource> as Readable>::read(r)?.map(|o| Box::new(o))));
+						}
+						res
+					},
+				})
+			},
+			6u8 => {
+				Ok(ChannelMonitorUpdateStep::FundingTransactionInputs {
+					inputs: {
+						let len: u64 = Readable::read(r)?;
+						let mut res = Vec::new();
+						for _ in 0..len {
+							res.push(Readable::read(r)?);
+						}
+						res
+					},
+				})
+			},
 			_ => Err(DecodeError::InvalidValue),
 		}
 	}
 }
 
+/// The commitment transactions spending the funding output of a negotiated splice transaction,
+/// waiting for it to confirm.
+#[derive(Clone, PartialEq)]
+struct PendingSpliceFunding {
+	channel_parameters: ChannelTransactionParameters,
+	holder_commitment_tx: HolderCommitmentTransaction,
+	holder_htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>,
+	counterparty_commitment_txid: Txid,
+	// The height at which the splice transaction confirmed, if it did. It may still be reorged
+	// out until it has ANTI_REORG_DELAY confirmations.
+	confirmation_height: Option<u32>,
+}
+
+impl Writeable for PendingSpliceFunding {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.channel_parameters.write(w)?;
+		self.holder_commitment_tx.write(w)?;
+		(self.holder_htlc_outputs.len() as u64).write(w)?;
+		for &(ref output, ref signature, ref source) in self.holder_htlc_outputs.iter() {
+			output.write(w)?;
+			signature.write(w)?;
+			source.write(w)?;
+		}
+		self.counterparty_commitment_txid.write(w)?;
+		self.confirmation_height.write(w)?;
+		Ok(())
+	}
+}
+
+impl Readable for PendingSpliceFunding {
+	fn read<R: ::std::io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_parameters = Readable::read(r)?;
+		let holder_commitment_tx = Readable::read(r)?;
+		let holder_htlc_outputs_len: u64 = Readable::read(r)?;
+		let mut holder_htlc_outputs = Vec::with_capacity(cmp::min(holder_htlc_outputs_len as usize, MAX_ALLOC_SIZE / 128));
+		for _ in 0..holder_htlc_outputs_len {
+			holder_htlc_outputs.push((Readable::read(r)?, Readable::read(r)?, Readable::read(r)?));
+		}
+		let counterparty_commitment_txid = Readable::read(r)?;
+		let confirmation_height = Readable::read(r)?;
+		Ok(PendingSpliceFunding {
+			channel_parameters,
+			holder_commitment_tx,
+			holder_htlc_outputs,
+			counterparty_commitment_txid,
+			confirmation_height,
+		})
+	}
+}
+
 /// A ChannelMonitor handles chain events (blocks connected and disconnected) and generates
 /// on-chain transactions to ensure no loss of funds occurs.
 ///
@@ -709,6 +836,28 @@ pub struct ChannelMonitor<ChanSigner: ChannelKeys> {
 	// remote monitor out-of-order with regards to the block view.
 	holder_tx_signed: bool,
 
+	// The commitment transactions of a negotiated splice, which replace our latest commitment
+	// transactions once the splice transaction is irreversibly confirmed or locked.
+	pending_splice: Option<PendingSpliceFunding>,
+	// The funding output of the last confirmed splice transaction, if any. funding_info is kept
+	// as-is as it identifies the channel.
+	spliced_funding_info: Option<(OutPoint, Script)>,
+
+	// The inputs of the funding transaction, provided by the Channel if it knows the funding
+	// transaction or learned once we see it in a block. A zero-conf channel may be used before its
+	// funding transaction confirms, in which case we use these to detect the funding transaction
+	// being double-spent.
+	funding_tx_inputs: Vec<BitcoinOutPoint>,
+	// The height at which a transaction conflicting with the funding transaction was connected, if
+	// any. No commitment transaction can ever confirm after this, so we fail back outbound HTLCs
+	// instead of broadcasting.
+	funding_double_spent_height: Option<u32>,
+
+	// The sources of the HTLCs we've passed a resolution upstream for via an HTLCEvent. The
+	// ChannelManager may have been persisted before it handled them, so we use these to tell which
+	// of our outbound HTLCs are still pending when it is loaded.
+	htlcs_resolved_on_chain: Vec<HTLCSource>,
+
 	// We simply modify last_block_hash in Channel's block_connected so that serialization is
 	// consistent but hopefully the users' copy handles block_connected in a consistent way.
 	// (we do *not*, however, update them in update_monitor to ensure any local user copies keep
@@ -752,7 +901,12 @@ impl<ChanSigner: ChannelKeys> PartialEq for ChannelMonitor<ChanSigner> {
 			self.onchain_events_waiting_threshold_conf != other.onchain_events_waiting_threshold_conf ||
 			self.outputs_to_watch != other.outputs_to_watch ||
 			self.lockdown_from_offchain != other.lockdown_from_offchain ||
-			self.holder_tx_signed != other.holder_tx_signed
+			self.holder_tx_signed != other.holder_tx_signed ||
+			self.pending_splice != other.pending_splice ||
+			self.spliced_funding_info != other.spliced_funding_info ||
+			self.funding_tx_inputs != other.funding_tx_inputs ||
+			self.funding_double_spent_height != other.funding_double_spent_height ||
+			self.htlcs_resolved_on_chain != other.htlcs_resolved_on_chain
 		{
 			false
 		} else {
@@ -944,6 +1098,26 @@ impl<ChanSigner: ChannelKeys> Writeable for ChannelMonitor<ChanSigner> {
 		self.lockdown_from_offchain.write(writer)?;
 		self.holder_tx_signed.write(writer)?;
 
+		self.pending_splice.write(writer)?;
+		if let Some(ref spliced_funding_info) = self.spliced_funding_info {
+			writer.write_all(&[1; 1])?;
+			spliced_funding_info.0.write(writer)?;
+			spliced_funding_info.1.write(writer)?;
+		} else {
+			writer.write_all(&[0; 1])?;
+		}
+
+		(self.funding_tx_inputs.len() as u64).write(writer)?;
+		for input in self.funding_tx_inputs.iter() {
+			input.write(writer)?;
+		}
+		self.funding_double_spent_height.write(writer)?;
+
+		(self.htlcs_resolved_on_chain.len() as u64).write(writer)?;
+		for source in self.htlcs_resolved_on_chain.iter() {
+			source.write(writer)?;
+		}
+
 		Ok(())
 	}
 }
@@ -959,8 +1133,12 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		assert!(commitment_transaction_number_obscure_factor <= (1 << 48));
 		let our_channel_close_key_hash = WPubkeyHash::hash(&shutdown_pubkey.serialize());
 		let shutdown_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&our_channel_close_key_hash[..]).into_script();
-		let payment_key_hash = WPubkeyHash::hash(&keys.pubkeys().payment_point.serialize());
-		let counterparty_payment_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_key_hash[..]).into_script();
+		let counterparty_payment_script = if channel_parameters.opt_anchors {
+			chan_utils::get_to_countersignatory_with_anchors_redeemscript(&keys.pubkeys().payment_point).to_v0_p2wsh()
+		} else {
+			let payment_key_hash = WPubkeyHash::hash(&keys.pubkeys().payment_point.serialize());
+			Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&payment_key_hash[..]).into_script()
+		};
 
 		let counterparty_channel_parameters = channel_parameters.counterparty_parameters.as_ref().unwrap();
 		let counterparty_delayed_payment_base_key = counterparty_channel_parameters.pubkeys.delayed_payment_basepoint;
@@ -1040,6 +1218,14 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			lockdown_from_offchain: false,
 			holder_tx_signed: false,
 
+			pending_splice: None,
+			spliced_funding_info: None,
+
+			funding_tx_inputs: Vec::new(),
+			funding_double_spent_height: None,
+
+			htlcs_resolved_on_chain: Vec::new(),
+
 			last_block_hash: Default::default(),
 			secp_ctx,
 		}
@@ -1223,9 +1409,18 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		where B::Target: BroadcasterInterface,
 					L::Target: Logger,
 	{
-		for tx in self.get_latest_holder_commitment_txn(logger).iter() {
+		let mut txs = self.get_latest_holder_commitment_txn(logger);
+		// Zero-fee HTLC transactions wouldn't relay, they are broadcast with wallet inputs by
+		// bump_holder_htlc_txn once the commitment transaction confirmed.
+		if self.onchain_tx_handler.channel_transaction_parameters.opt_anchors {
+			txs.truncate(1);
+		}
+		for tx in txs.iter() {
 			broadcaster.broadcast_transaction(tx);
 		}
+		if let Some(commitment_tx) = txs.first() {
+			self.onchain_tx_handler.track_holder_commitment_cpfp(commitment_tx);
+		}
 		self.pending_monitor_events.push(MonitorEvent::CommitmentTxBroadcasted(self.funding_info.0));
 	}
 
@@ -1256,10 +1451,13 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				ChannelMonitorUpdateStep::LatestHolderCommitmentTXInfo { commitment_tx, htlc_outputs } => {
 					log_trace!(logger, "Updating ChannelMonitor with latest holder commitment transaction info");
 					if self.lockdown_from_offchain { panic!(); }
+					// The Channel only moves on from a confirmed splice once it's locked.
+					if self.is_splice_confirmed() { self.lock_in_splice(logger); }
 					self.provide_latest_holder_commitment_tx(commitment_tx.clone(), htlc_outputs.clone())?
 				}
 				ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo { commitment_txid, htlc_outputs, commitment_number, their_revocation_point } => {
 					log_trace!(logger, "Updating ChannelMonitor with latest counterparty commitment transaction info");
+					if self.is_splice_confirmed() { self.lock_in_splice(logger); }
 					self.provide_latest_counterparty_commitment_tx(*commitment_txid, htlc_outputs.clone(), *commitment_number, *their_revocation_point, logger)
 				},
 				ChannelMonitorUpdateStep::PaymentPreimage { payment_preimage } => {
@@ -1274,17 +1472,104 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					log_trace!(logger, "Updating ChannelMonitor: channel force closed, should broadcast: {}", should_broadcast);
 					self.lockdown_from_offchain = true;
 					if *should_broadcast {
+						if self.is_splice_confirmed() { self.lock_in_splice(logger); }
 						self.broadcast_latest_holder_commitment_txn(broadcaster, logger);
 					} else {
 						log_error!(logger, "You have a toxic holder commitment transaction avaible in channel monitor, read comment in ChannelMonitor::get_latest_holder_commitment_txn to be informed of manual action to take");
 					}
-				}
+				},
+				ChannelMonitorUpdateStep::SpliceFundingNegotiated { channel_parameters, holder_commitment_tx, holder_htlc_outputs, counterparty_commitment_txid, counterparty_htlc_outputs } => {
+					log_trace!(logger, "Updating ChannelMonitor with commitment transactions of splice transaction {}", channel_parameters.funding_outpoint.unwrap().txid);
+					if self.lockdown_from_offchain { panic!(); }
+					self.provide_splice_commitment_txn(channel_parameters, holder_commitment_tx, holder_htlc_outputs, *counterparty_commitment_txid, counterparty_htlc_outputs);
+				},
+				ChannelMonitorUpdateStep::FundingTransactionInputs { inputs } => {
+					log_trace!(logger, "Updating ChannelMonitor with the inputs of funding transaction {}", self.funding_info.0.txid);
+					self.provide_funding_tx_inputs(inputs.clone());
+				},
 			}
 		}
 		self.latest_update_id = updates.update_id;
 		Ok(())
 	}
 
+	/// Provides the inputs of the funding transaction, which we otherwise only learn once it
+	/// confirms, see check_funding_double_spend.
+	pub(crate) fn provide_funding_tx_inputs(&mut self, inputs: Vec<BitcoinOutPoint>) {
+		if self.funding_tx_inputs.is_empty() {
+			self.funding_tx_inputs = inputs;
+		}
+	}
+
+	/// Stores the commitment transactions spending the funding output of a negotiated splice
+	/// transaction, until it confirms.
+	fn provide_splice_commitment_txn(&mut self, channel_parameters: &ChannelTransactionParameters, holder_commitment_tx: &HolderCommitmentTransaction, holder_htlc_outputs: &Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>, counterparty_commitment_txid: Txid, counterparty_htlc_outputs: &Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>) {
+		for &(ref htlc, _) in counterparty_htlc_outputs.iter() {
+			if htlc.transaction_output_index.is_some() {
+				self.counterparty_tx_cache.per_htlc.entry(counterparty_commitment_txid).or_insert_with(Vec::new).push(htlc.clone());
+			}
+		}
+		self.counterparty_claimable_outpoints.insert(counterparty_commitment_txid, counterparty_htlc_outputs.clone());
+		self.pending_splice = Some(PendingSpliceFunding {
+			channel_parameters: channel_parameters.clone(),
+			holder_commitment_tx: holder_commitment_tx.clone(),
+			holder_htlc_outputs: holder_htlc_outputs.clone(),
+			counterparty_commitment_txid,
+			confirmation_height: None,
+		});
+	}
+
+	/// Notes the confirmation of the pending splice transaction, returning its funding output to
+	/// watch. As the splice transaction may still be reorged out, we keep using the previous
+	/// funding output until lock_in_splice.
+	fn splice_confirmed<L: Deref>(&mut self, tx: &Transaction, height: u32, logger: &L) -> Option<(Txid, Vec<(u32, TxOut)>)> where L::Target: Logger {
+		let splice = match self.pending_splice {
+			Some(ref mut splice) if splice.channel_parameters.funding_outpoint.unwrap().txid == tx.txid() => splice,
+			_ => return None,
+		};
+		let funding_outpoint = splice.channel_parameters.funding_outpoint.unwrap();
+		log_info!(logger, "Splice transaction {} confirmed at height {}, watching its funding output", funding_outpoint.txid, height);
+		splice.confirmation_height = Some(height);
+		Some((funding_outpoint.txid, vec![(funding_outpoint.index as u32, tx.output[funding_outpoint.index as usize].clone())]))
+	}
+
+	/// Returns true if the pending splice transaction confirmed, in which case commitment
+	/// transactions spending the previous funding output can't confirm anymore, unless it's
+	/// reorged out.
+	fn is_splice_confirmed(&self) -> bool {
+		self.pending_splice.as_ref().map(|splice| splice.confirmation_height.is_some()).unwrap_or(false)
+	}
+
+	/// Switches to the commitment transactions of the pending splice. We only do so once the
+	/// splice transaction has ANTI_REORG_DELAY confirmations, once its funding output is spent, or
+	/// once the Channel provides commitment transactions spending it or needs us to broadcast.
+	fn lock_in_splice<L: Deref>(&mut self, logger: &L) where L::Target: Logger {
+		let splice = match self.pending_splice.take() {
+			Some(splice) => splice,
+			None => return,
+		};
+		let funding_outpoint = splice.channel_parameters.funding_outpoint.unwrap();
+		log_info!(logger, "Moving channel to the funding output of splice transaction {}", funding_outpoint.txid);
+		let funding_script = self.funding_redeemscript.to_v0_p2wsh();
+		self.outputs_to_watch.entry(funding_outpoint.txid).or_insert_with(|| vec![(funding_outpoint.index as u32, funding_script.clone())]);
+
+		self.spliced_funding_info = Some((funding_outpoint, funding_script));
+		self.channel_value_satoshis = splice.channel_parameters.channel_value_satoshis;
+		// Commitment transactions spending the previous funding output can't confirm anymore.
+		if self.provide_latest_holder_commitment_tx(splice.holder_commitment_tx.clone(), splice.holder_htlc_outputs).is_err() {
+			log_error!(logger, "Confirmed a splice transaction after signing a holder commitment transaction, which may not confirm");
+		}
+		self.prev_holder_signed_commitment_tx = None;
+		self.onchain_tx_handler.splice_funding(&splice.channel_parameters, splice.holder_commitment_tx);
+		self.prev_counterparty_commitment_txid = self.current_counterparty_commitment_txid.take();
+		self.current_counterparty_commitment_txid = Some(splice.counterparty_commitment_txid);
+	}
+
+	/// The funding output the channel currently spends its commitment transactions from.
+	fn current_funding_info(&self) -> &(OutPoint, Script) {
+		self.spliced_funding_info.as_ref().unwrap_or(&self.funding_info)
+	}
+
 	/// Gets the update_id from the latest ChannelMonitorUpdate which was applied to this
 	/// ChannelMonitor.
 	pub fn get_latest_update_id(&self) -> u64 {
@@ -1349,6 +1634,41 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		self.current_holder_commitment_number
 	}
 
+	/// Gets the HTLCs we sent which are in any of the commitment transactions which may still
+	/// confirm and for which we haven't yet passed a resolution upstream. Used by ChannelManager
+	/// deserialization to learn of outbound payment paths it may have lost track of.
+	pub(crate) fn get_pending_outbound_htlcs(&self) -> Vec<(HTLCSource, PaymentHash)> {
+		let mut res: Vec<(HTLCSource, PaymentHash)> = Vec::new();
+		{
+			let mut add_htlc = |source: &HTLCSource, payment_hash: &PaymentHash| {
+				if let &HTLCSource::OutboundRoute { .. } = source {
+					// Resolutions still in pending_monitor_events have yet to reach the ChannelManager.
+					let resolution_handled = self.htlcs_resolved_on_chain.contains(source) && !self.pending_monitor_events.iter().any(
+						|update| if let &MonitorEvent::HTLCEvent(ref upd) = update { upd.source == *source } else { false });
+					if !resolution_handled && !res.iter().any(|&(ref s, _)| s == source) {
+						res.push((source.clone(), *payment_hash));
+					}
+				}
+			};
+			for &(ref htlc, _, ref source) in self.current_holder_commitment_tx.htlc_outputs.iter() {
+				if let &Some(ref source) = source { add_htlc(source, &htlc.payment_hash); }
+			}
+			if let Some(ref holder_tx) = self.prev_holder_signed_commitment_tx {
+				for &(ref htlc, _, ref source) in holder_tx.htlc_outputs.iter() {
+					if let &Some(ref source) = source { add_htlc(source, &htlc.payment_hash); }
+				}
+			}
+			for txid in self.current_counterparty_commitment_txid.iter().chain(self.prev_counterparty_commitment_txid.iter()) {
+				if let Some(htlc_outputs) = self.counterparty_claimable_outpoints.get(txid) {
+					for &(ref htlc, ref source) in htlc_outputs.iter() {
+						if let &Some(ref source) = source { add_htlc(source, &htlc.payment_hash); }
+					}
+				}
+			}
+		}
+		res
+	}
+
 	/// Attempts to claim a counterparty commitment transaction's outputs using the revocation key and
 	/// data in counterparty_claimable_outpoints. Will directly claim any HTLC outputs which expire at a
 	/// height > height + CLTV_SHARED_CLAIM_BUFFER. In any case, will install monitoring for
@@ -1722,6 +2042,11 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	/// substantial amount of time (a month or even a year) to get back funds. Best may be to contact
 	/// out-of-band the other node operator to coordinate with him if option is available to you.
 	/// In any-case, choice is up to the user.
+	///
+	/// With option_anchors_zero_fee_htlc_tx, the HTLC transactions returned are zero-fee and won't
+	/// relay on their own, see [`bump_holder_htlc_txn`].
+	///
+	/// [`bump_holder_htlc_txn`]: #method.bump_holder_htlc_txn
 	pub fn get_latest_holder_commitment_txn<L: Deref>(&mut self, logger: &L) -> Vec<Transaction> where L::Target: Logger {
 		log_trace!(logger, "Getting signed latest holder commitment transaction!");
 		self.holder_tx_signed = true;
@@ -1806,16 +2131,27 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		let block_hash = header.block_hash();
 		log_trace!(logger, "Block {} at height {} connected with {} txn matched", block_hash, height, txn_matched.len());
 
+		self.check_funding_double_spend(txdata, height, &logger);
+
 		let mut watch_outputs = Vec::new();
 		let mut claimable_outpoints = Vec::new();
 		for tx in &txn_matched {
+			if let Some(new_outputs) = self.splice_confirmed(tx, height, &logger) {
+				watch_outputs.push(new_outputs);
+			}
 			if tx.input.len() == 1 {
 				// Assuming our keys were not leaked (in which case we're screwed no matter what),
 				// commitment transactions and HTLC transactions will all only ever have one input,
 				// which is an easy way to filter out any potential non-matching txn for lazy
 				// filters.
 				let prevout = &tx.input[0].previous_output;
-				if prevout.txid == self.funding_info.0.txid && prevout.vout == self.funding_info.0.index as u32 {
+				let spends_splice_funding = self.pending_splice.as_ref()
+					.map(|splice| splice.channel_parameters.funding_outpoint.unwrap().into_bitcoin_outpoint() == *prevout).unwrap_or(false);
+				if spends_splice_funding {
+					self.lock_in_splice(&logger);
+				}
+				let funding_outpoint = self.current_funding_info().0;
+				if prevout.txid == funding_outpoint.txid && prevout.vout == funding_outpoint.index as u32 {
 					if (tx.input[0].sequence >> 8*3) as u8 == 0x80 && (tx.lock_time >> 8*3) as u8 == 0x20 {
 						let (mut new_outpoints, new_outputs) = self.check_spend_counterparty_transaction(&tx, height, &logger);
 						if !new_outputs.1.is_empty() {
@@ -1847,9 +2183,19 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 
 			self.is_paying_spendable_output(&tx, height, &logger);
 		}
-		let should_broadcast = self.would_broadcast_at_height(height, &logger);
+		if let Some(confirmation_height) = self.pending_splice.as_ref().and_then(|splice| splice.confirmation_height) {
+			if height >= confirmation_height + ANTI_REORG_DELAY - 1 {
+				self.lock_in_splice(&logger);
+			}
+		}
+		// Once the funding transaction has been double-spent there is nothing left to broadcast.
+		let should_broadcast = self.funding_double_spent_height.is_none() && self.would_broadcast_at_height(height, &logger);
+		if should_broadcast && self.is_splice_confirmed() {
+			self.lock_in_splice(&logger);
+		}
 		if should_broadcast {
-			claimable_outpoints.push(ClaimRequest { absolute_timelock: height, aggregable: false, outpoint: BitcoinOutPoint { txid: self.funding_info.0.txid.clone(), vout: self.funding_info.0.index as u32 }, witness_data: InputMaterial::Funding { funding_redeemscript: self.funding_redeemscript.clone() }});
+			let funding_outpoint = self.current_funding_info().0;
+			claimable_outpoints.push(ClaimRequest { absolute_timelock: height, aggregable: false, outpoint: funding_outpoint.into_bitcoin_outpoint(), witness_data: InputMaterial::Funding { funding_redeemscript: self.funding_redeemscript.clone() }});
 		}
 		if should_broadcast {
 			self.pending_monitor_events.push(MonitorEvent::CommitmentTxBroadcasted(self.funding_info.0));
@@ -1868,6 +2214,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 				match ev {
 					OnchainEvent::HTLCUpdate { htlc_update } => {
 						log_trace!(logger, "HTLC {} failure update has got enough confirmations to be passed upstream", log_bytes!((htlc_update.1).0));
+						self.htlcs_resolved_on_chain.push(htlc_update.0.clone());
 						self.pending_monitor_events.push(MonitorEvent::HTLCEvent(HTLCUpdate {
 							payment_hash: htlc_update.1,
 							payment_preimage: None,
@@ -1910,6 +2257,51 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 		watch_outputs
 	}
 
+	/// Bumps the feerate of the holder commitment transaction we broadcast, if it hasn't confirmed
+	/// yet, with a child-pays-for-parent transaction spending our anchor output and UTXOs from
+	/// `utxo_source`. A new child is only generated when the previous one is due for a bump, which
+	/// happens sooner as the HTLCs in the commitment transaction are closer to expiry.
+	///
+	/// This only applies to channels using option_anchors_zero_fee_htlc_tx and should be called
+	/// after each call to [`block_connected`]. [`ChainMonitor`] does so if it was given a
+	/// [`UtxoSource`]. Holder commitment transactions of other channels can't be bumped: their only
+	/// output of ours is CSV-delayed, and the to_remote output belongs to our counterparty, so they
+	/// confirm at the feerate agreed upon when they were signed.
+	///
+	/// [`block_connected`]: #method.block_connected
+	/// [`ChainMonitor`]: ../chainmonitor/struct.ChainMonitor.html
+	/// [`UtxoSource`]: ../chaininterface/trait.UtxoSource.html
+	pub fn bump_holder_commitment_with_cpfp<U: Deref, B: Deref, F: Deref, L: Deref>(&mut self, height: u32, utxo_source: U, broadcaster: B, fee_estimator: F, logger: L)
+		where U::Target: UtxoSource,
+		      B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		self.onchain_tx_handler.bump_holder_commitment_cpfp(height, &&*utxo_source, &&*broadcaster, &&*fee_estimator, &&*logger);
+	}
+
+	/// Broadcasts the HTLC-Success and HTLC-Timeout transactions claiming HTLC outputs on our
+	/// confirmed commitment transaction, adding UTXOs from `utxo_source` and a change output to
+	/// pay their fee. They are re-generated at a higher feerate when the previous ones are due for
+	/// a bump, which happens sooner as the HTLCs are closer to expiry, until one confirms.
+	///
+	/// This only applies to channels using option_anchors_zero_fee_htlc_tx, whose HTLC transactions
+	/// are zero-fee, and should be called after each call to [`block_connected`]. [`ChainMonitor`]
+	/// does so if it was given a [`UtxoSource`]. HTLC transactions of other channels are broadcast
+	/// by [`block_connected`] with the fee agreed upon when they were signed.
+	///
+	/// [`block_connected`]: #method.block_connected
+	/// [`ChainMonitor`]: ../chainmonitor/struct.ChainMonitor.html
+	/// [`UtxoSource`]: ../chaininterface/trait.UtxoSource.html
+	pub fn bump_holder_htlc_txn<U: Deref, B: Deref, F: Deref, L: Deref>(&mut self, height: u32, utxo_source: U, broadcaster: B, fee_estimator: F, logger: L)
+		where U::Target: UtxoSource,
+		      B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		self.onchain_tx_handler.bump_holder_htlc_claims(height, &&*utxo_source, &&*broadcaster, &&*fee_estimator, &&*logger);
+	}
+
 	/// Determines if the disconnected block contained any transactions of interest and updates
 	/// appropriately.
 	pub fn block_disconnected<B: Deref, F: Deref, L: Deref>(&mut self, header: &BlockHeader, height: u32, broadcaster: B, fee_estimator: F, logger: L)
@@ -1925,12 +2317,60 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 			//- htlc update there as failure-trigger tx (revoked commitment tx, non-revoked commitment tx, HTLC-timeout tx) has been disconnected
 			//- maturing spendable output has transaction paying us has been disconnected
 		}
+		if self.funding_double_spent_height == Some(height) {
+			self.funding_double_spent_height = None;
+		}
+		if let Some(ref mut splice) = self.pending_splice {
+			if splice.confirmation_height == Some(height) {
+				log_info!(logger, "Splice transaction {} was reorged out", splice.channel_parameters.funding_outpoint.unwrap().txid);
+				splice.confirmation_height = None;
+			}
+		}
 
 		self.onchain_tx_handler.block_disconnected(height, broadcaster, fee_estimator, logger);
 
 		self.last_block_hash = block_hash;
 	}
 
+	/// Learns the inputs of the funding transaction once it appears in a block, unless they were
+	/// provided beforehand, and checks for a transaction conflicting with it. This can only happen
+	/// after a reorg (or if the funding transaction never confirmed, as zero-conf channels allow),
+	/// and means no commitment transaction can ever confirm, so we fail back outbound HTLCs once
+	/// the conflicting transaction has ANTI_REORG_DELAY confirmations.
+	fn check_funding_double_spend<L: Deref>(&mut self, txdata: &TransactionData, height: u32, logger: &L) where L::Target: Logger {
+		if self.funding_double_spent_height.is_some() { return; }
+		let funding_txid = self.funding_info.0.txid;
+		for &(_, tx) in txdata.iter() {
+			if tx.txid() == funding_txid {
+				if self.funding_tx_inputs.is_empty() {
+					self.funding_tx_inputs = tx.input.iter().map(|input| input.previous_output).collect();
+				}
+			} else if tx.input.iter().any(|input| self.funding_tx_inputs.contains(&input.previous_output)) {
+				log_info!(logger, "Funding transaction {} was double-spent by {}, failing back outbound HTLCs", funding_txid, tx.txid());
+				self.funding_double_spent_height = Some(height);
+				// No need to check holder commitment txn, symmetric HTLCSource must be present as per-htlc data on counterparty commitment tx
+				let mut htlc_updates: Vec<(HTLCSource, PaymentHash)> = Vec::new();
+				for txid in self.current_counterparty_commitment_txid.iter().chain(self.prev_counterparty_commitment_txid.iter()) {
+					if let Some(outpoints) = self.counterparty_claimable_outpoints.get(txid) {
+						for &(ref htlc, ref source_option) in outpoints.iter() {
+							if let &Some(ref source) = source_option {
+								if htlc_updates.iter().all(|update| update.0 != **source) {
+									htlc_updates.push(((**source).clone(), htlc.payment_hash));
+								}
+							}
+						}
+					}
+				}
+				let events = self.onchain_events_waiting_threshold_conf.entry(height + ANTI_REORG_DELAY - 1).or_insert_with(Vec::new);
+				for htlc_update in htlc_updates.drain(..) {
+					log_trace!(logger, "Failing HTLC with payment_hash {} due to the funding transaction being double-spent, waiting for confirmation (at height {})", log_bytes!((htlc_update.1).0), height + ANTI_REORG_DELAY - 1);
+					events.push(OnchainEvent::HTLCUpdate { htlc_update });
+				}
+				return;
+			}
+		}
+	}
+
 	/// Filters a block's `txdata` for transactions spending watched outputs or for any child
 	/// transactions thereof.
 	fn filter_block<'a>(&self, txdata: &TransactionData<'a>) -> Vec<&'a Transaction> {
@@ -2009,8 +2449,8 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					//      inbound_cltv == height + CLTV_CLAIM_BUFFER
 					//      outbound_cltv + LATENCY_GRACE_PERIOD_BLOCKS + CLTV_CLAIM_BUFFER <= inbound_cltv - CLTV_CLAIM_BUFFER
 					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= inbound_cltv - outbound_cltv
-					//      CLTV_EXPIRY_DELTA <= inbound_cltv - outbound_cltv (by check in ChannelManager::decode_update_add_htlc_onion)
-					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= CLTV_EXPIRY_DELTA
+					//      MIN_CLTV_EXPIRY_DELTA <= inbound_cltv - outbound_cltv (by check in ChannelManager::decode_update_add_htlc_onion)
+					//      LATENCY_GRACE_PERIOD_BLOCKS + 2*CLTV_CLAIM_BUFFER <= MIN_CLTV_EXPIRY_DELTA
 					//  The final, above, condition is checked for statically in channelmanager
 					//  with CHECK_CLTV_EXPIRY_SANITY_2.
 					let htlc_outbound = $holder_tx == htlc.offered;
@@ -2042,12 +2482,13 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 	/// Check if any transaction broadcasted is resolving HTLC output by a success or timeout on a holder
 	/// or counterparty commitment tx, if so send back the source, preimage if found and payment_hash of resolved HTLC
 	fn is_resolving_htlc_output<L: Deref>(&mut self, tx: &Transaction, height: u32, logger: &L) where L::Target: Logger {
+		let opt_anchors = self.onchain_tx_handler.channel_transaction_parameters.opt_anchors;
 		'outer_loop: for input in &tx.input {
 			let mut payment_data = None;
-			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
-				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
-			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len()) == Some(HTLCType::AcceptedHTLC);
-			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len()) == Some(HTLCType::OfferedHTLC);
+			let revocation_sig_claim = (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::OfferedHTLC) && input.witness[1].len() == 33)
+				|| (input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::AcceptedHTLC) && input.witness[1].len() == 33);
+			let accepted_preimage_claim = input.witness.len() == 5 && HTLCType::scriptlen_to_htlctype(input.witness[4].len(), opt_anchors) == Some(HTLCType::AcceptedHTLC);
+			let offered_preimage_claim = input.witness.len() == 3 && HTLCType::scriptlen_to_htlctype(input.witness[2].len(), opt_anchors) == Some(HTLCType::OfferedHTLC);
 
 			macro_rules! log_claim {
 				($tx_info: expr, $holder_tx: expr, $htlc: expr, $source_avail: expr) => {
@@ -2137,6 +2578,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 					if !self.pending_monitor_events.iter().any(
 						|update| if let &MonitorEvent::HTLCEvent(ref upd) = update { upd.source == source } else { false }) {
 						payment_preimage.0.copy_from_slice(&input.witness[3]);
+						self.htlcs_resolved_on_chain.push(source.clone());
 						self.pending_monitor_events.push(MonitorEvent::HTLCEvent(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
@@ -2149,6 +2591,7 @@ impl<ChanSigner: ChannelKeys> ChannelMonitor<ChanSigner> {
 							upd.source == source
 						} else { false }) {
 						payment_preimage.0.copy_from_slice(&input.witness[1]);
+						self.htlcs_resolved_on_chain.push(source.clone());
 						self.pending_monitor_events.push(MonitorEvent::HTLCEvent(HTLCUpdate {
 							source,
 							payment_preimage: Some(payment_preimage),
@@ -2313,7 +2756,7 @@ impl<'a, ChanSigner: ChannelKeys, K: KeysInterface<ChanKeySigner = ChanSigner>>
 			}
 		}
 
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -2537,11 +2980,38 @@ impl<'a, ChanSigner: ChannelKeys, K: KeysInterface<ChanKeySigner = ChanSigner>>
 				return Err(DecodeError::InvalidValue);
 			}
 		}
-		let onchain_tx_handler = ReadableArgs::read(reader, keys_manager)?;
+		let onchain_tx_handler = OnchainTxHandler::read(reader, keys_manager, ver, channel_value_satoshis)?;
 
 		let lockdown_from_offchain = Readable::read(reader)?;
 		let holder_tx_signed = Readable::read(reader)?;
 
+		let mut pending_splice = None;
+		let mut spliced_funding_info = None;
+		let mut funding_tx_inputs = Vec::new();
+		let mut funding_double_spent_height = None;
+		let mut htlcs_resolved_on_chain = Vec::new();
+		if ver >= 2 {
+			pending_splice = Readable::read(reader)?;
+			spliced_funding_info = match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => Some((Readable::read(reader)?, Readable::read(reader)?)),
+				_ => return Err(DecodeError::InvalidValue),
+			};
+
+			let funding_tx_inputs_len: u64 = Readable::read(reader)?;
+			funding_tx_inputs.reserve(cmp::min(funding_tx_inputs_len as usize, MAX_ALLOC_SIZE / mem::size_of::<BitcoinOutPoint>()));
+			for _ in 0..funding_tx_inputs_len {
+				funding_tx_inputs.push(Readable::read(reader)?);
+			}
+			funding_double_spent_height = Readable::read(reader)?;
+
+			let htlcs_resolved_on_chain_len: u64 = Readable::read(reader)?;
+			htlcs_resolved_on_chain.reserve(cmp::min(htlcs_resolved_on_chain_len as usize, MAX_ALLOC_SIZE / 128));
+			for _ in 0..htlcs_resolved_on_chain_len {
+				htlcs_resolved_on_chain.push(Readable::read(reader)?);
+			}
+		}
+
 		Ok((last_block_hash.clone(), ChannelMonitor {
 			latest_update_id,
 			commitment_transaction_number_obscure_factor,
@@ -2586,6 +3056,14 @@ impl<'a, ChanSigner: ChannelKeys, K: KeysInterface<ChanKeySigner = ChanSigner>>
 			lockdown_from_offchain,
 			holder_tx_signed,
 
+			pending_splice,
+			spliced_funding_info,
+
+			funding_tx_inputs,
+			funding_double_spent_height,
+
+			htlcs_resolved_on_chain,
+
 			last_block_hash,
 			secp_ctx: Secp256k1::new(),
 		}))
@@ -2621,7 +3099,7 @@ mod tests {
 		let secp_ctx = Secp256k1::new();
 		let logger = Arc::new(TestLogger::new());
 		let broadcaster = Arc::new(TestBroadcaster{txn_broadcasted: Mutex::new(Vec::new())});
-		let fee_estimator = Arc::new(TestFeeEstimator { sat_per_kw: 253 });
+		let fee_estimator = Arc::new(TestFeeEstimator { sat_per_kw: Mutex::new(253) });
 
 		let dummy_key = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let dummy_tx = Transaction { version: 0, lock_time: 0, input: Vec::new(), output: Vec::new() };
@@ -2699,6 +3177,8 @@ mod tests {
 				selected_contest_delay: 67,
 			}),
 			funding_outpoint: Some(funding_outpoint),
+			opt_anchors: false,
+			channel_value_satoshis: 0,
 		};
 		// Prune with one old state and a holder commitment tx holding a few overlaps with the
 		// old state.
@@ -2770,7 +3250,7 @@ mod tests {
 					payment_hash: PaymentHash([1; 32]),
 					transaction_output_index: Some($idx as u32),
 				};
-				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &pubkey, &pubkey, &pubkey) };
+				let redeem_script = if *$input_type == InputDescriptors::RevokedOutput { chan_utils::get_revokeable_redeemscript(&pubkey, 256, &pubkey) } else { chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, false, &pubkey, &pubkey, &pubkey) };
 				let sighash = hash_to_message!(&$sighash_parts.signature_hash($idx, &redeem_script, $amount, SigHashType::All)[..]);
 				let sig = secp_ctx.sign(&sighash, &privkey);
 				$sighash_parts.access_witness($idx).push(sig.serialize_der().to_vec());
@@ -2820,7 +3300,7 @@ mod tests {
 				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Claim tx with 1 offered HTLCs, 3 received HTLCs
 		claim_tx.input.clear();
@@ -2844,7 +3324,7 @@ mod tests {
 				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false),  claim_tx.get_weight() + /* max_length_sig */ (73 * inputs_des.len() - sum_actual_sigs));
 
 		// Justice tx with 1 revoked HTLC-Success tx output
 		claim_tx.input.clear();
@@ -2866,7 +3346,7 @@ mod tests {
 				sign_input!(sighash_parts, idx, 0, inp, sum_actual_sigs);
 			}
 		}
-		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..]), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
+		assert_eq!(base_weight + OnchainTxHandler::<InMemoryChannelKeys>::get_witnesses_weight(&inputs_des[..], false), claim_tx.get_weight() + /* max_length_isg */ (73 * inputs_des.len() - sum_actual_sigs));
 	}
 
 	// Further testing is done in the ChannelManager integration tests.
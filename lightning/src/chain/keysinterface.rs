@@ -11,10 +11,11 @@
 //! spendable on-chain outputs which the user owns and is responsible for using just as any other
 //! on-chain output which is theirs.
 
-use bitcoin::blockdata::transaction::{Transaction, TxOut, SigHashType};
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut, SigHashType};
 use bitcoin::blockdata::script::{Script, Builder};
 use bitcoin::blockdata::opcodes;
 use bitcoin::network::constants::Network;
+use bitcoin::util::address::Address;
 use bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey, ChildNumber};
 use bitcoin::util::bip143;
 
@@ -29,8 +30,9 @@ use bitcoin::secp256k1::{Secp256k1, Signature, Signing};
 use bitcoin::secp256k1;
 
 use util::byte_utils;
-use util::ser::{Writeable, Writer, Readable};
+use util::ser::{Writeable, Writer, Readable, BigSize, FixedLengthReader};
 
+use chain::chaininterface::UtxoSource;
 use chain::transaction::OutPoint;
 use ln::chan_utils;
 use ln::chan_utils::{HTLCOutputInCommitment, make_funding_redeemscript, ChannelPublicKeys, HolderCommitmentTransaction, ChannelTransactionParameters, CommitmentTransaction};
@@ -40,6 +42,10 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io::Error;
 use ln::msgs::DecodeError;
 
+/// Change below this value is left to the fee by KeysManager::spend_spendable_outputs, as an
+/// output paying it would not be relayed.
+const DUST_LIMIT_SATOSHIS: u64 = 546;
+
 /// When on-chain outputs are created by rust-lightning (which our counterparty is not able to
 /// claim at any point in the future) an event is generated which you must track and be able to
 /// spend on-chain. The information needed to do this is provided in this enum, including the
@@ -111,6 +117,13 @@ pub enum SpendableOutputDescriptor {
 	///
 	/// These are generally the result of our counterparty having broadcast the current state,
 	/// allowing us to claim the non-HTLC-encumbered outputs immediately.
+	///
+	/// If the channel used option_anchors_zero_fee_htlc_tx, the output is instead a P2WSH (see
+	/// chan_utils::get_to_countersignatory_with_anchors_redeemscript) which must be spent with an
+	/// nSequence of 1 and the witness:
+	/// <BIP 143 signature> <witness script>
+	///
+	/// KeysManager::spend_spendable_outputs handles both cases.
 	StaticOutputCounterpartyPayment {
 		/// The outpoint which is spendable
 		outpoint: OutPoint,
@@ -260,6 +273,19 @@ pub trait ChannelKeys : Send+Clone + Writeable {
 	/// commitment_tx.
 	fn sign_holder_commitment_htlc_transactions<T: secp256k1::Signing + secp256k1::Verification>(&self, commitment_tx: &HolderCommitmentTransaction, secp_ctx: &Secp256k1<T>) -> Result<Vec<Signature>, ()>;
 
+	/// Create a signature for the given input of a transaction spending the HTLC at index
+	/// `htlc_index` in the set of HTLCs of a holder commitment transaction, through the
+	/// HTLC-Success or HTLC-Timeout path.
+	///
+	/// This is only used with option_anchors_zero_fee_htlc_tx, where HTLC transactions are zero-fee
+	/// and our counterparty's signature only commits to the HTLC input and its matching output, so
+	/// that we can attach inputs and a change output to pay the fee. Thus, unlike the signatures
+	/// returned by sign_holder_commitment_htlc_transactions, this one commits to the whole
+	/// transaction. It may be called multiple times for the same HTLC if a fee-bump is needed.
+	///
+	/// As for sign_holder_commitment_htlc_transactions, commitment_tx will never be revoked.
+	fn sign_holder_htlc_input<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, commitment_tx: &HolderCommitmentTransaction, htlc_index: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
 	/// Create a signature for the given input in a transaction spending an HTLC or commitment
 	/// transaction output when our counterparty broadcasts an old state.
 	///
@@ -299,6 +325,15 @@ pub trait ChannelKeys : Send+Clone + Writeable {
 	/// BIP 143 signature.
 	fn sign_counterparty_htlc_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, amount: u64, per_commitment_point: &PublicKey, htlc: &HTLCOutputInCommitment, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
 
+	/// Create a signature for the given input in a transaction spending the anchor output which
+	/// pays to our funding key on a holder commitment transaction of an
+	/// option_anchors_zero_fee_htlc_tx channel, used to fee-bump the commitment transaction with
+	/// CPFP.
+	///
+	/// The anchor output's value is committed to in the BIP 143 signature, and its witness
+	/// script is given by chan_utils::get_anchor_redeemscript.
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
 	/// Create a signature for a (proposed) closing transaction.
 	///
 	/// Note that, due to rounding, there may be one "missing" satoshi, and either party may have
@@ -322,8 +357,27 @@ pub trait ChannelKeys : Send+Clone + Writeable {
 	///
 	/// We bind holder_selected_contest_delay late here for API convenience.
 	///
+	/// channel_parameters.channel_value_satoshis supersedes the value given to
+	/// KeysInterface::get_channel_keys, as it may have grown during dual-funded channel opening.
+	///
 	/// Will be called before any signatures are applied.
 	fn ready_channel(&mut self, channel_parameters: &ChannelTransactionParameters);
+
+	/// Create a signature for the input at index `input` of a splice transaction, which spends the
+	/// channel's current funding output into a new one.
+	///
+	/// The value of the current funding output is committed to in the BIP 143 signature. Note that
+	/// a splice changes the channel value and the balance of one or both parties, which should be
+	/// checked against the splice transaction before signing.
+	fn sign_splice_funding_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()>;
+
+	/// Moves the channel to the funding output of a splice transaction. channel_parameters holds
+	/// the same static channel data given to ready_channel, except for the funding outpoint and
+	/// channel value of the splice transaction.
+	///
+	/// This is called on a copy of the keys once a splice has been negotiated, the original keys
+	/// being kept for the current funding output until the splice transaction confirms.
+	fn splice_channel(&mut self, channel_parameters: &ChannelTransactionParameters);
 }
 
 /// A trait to describe an object which can get user secrets and key material.
@@ -352,6 +406,13 @@ pub trait KeysInterface: Send + Sync {
 	/// contain no versioning scheme. You may wish to include your own version prefix and ensure
 	/// you've read all of the provided bytes to ensure no corruption occurred.
 	fn read_chan_signer(&self, reader: &[u8]) -> Result<Self::ChanKeySigner, DecodeError>;
+
+	/// Gets the wallet used to contribute funds to dual-funded channels and splices, if any. This
+	/// is usually the same UtxoSource given to ChainMonitor::with_utxo_source.
+	///
+	/// If None is returned (the default), dual-funded channels may still be accepted, but we never
+	/// contribute any funds to them, and ChannelManager::create_dual_funded_channel will fail.
+	fn get_utxo_source(&self) -> Option<&UtxoSource> { None }
 }
 
 #[derive(Clone)]
@@ -452,6 +513,10 @@ impl InMemoryChannelKeys {
 	/// Will panic if ready_channel wasn't called.
 	pub fn funding_outpoint(&self) -> &OutPoint { self.get_channel_parameters().funding_outpoint.as_ref().unwrap() }
 
+	/// Whether the channel uses the option_anchors_zero_fee_htlc_tx commitment format.
+	/// Will panic if ready_channel wasn't called.
+	pub fn opt_anchors(&self) -> bool { self.get_channel_parameters().opt_anchors }
+
 	/// Obtain a ChannelTransactionParameters for this channel, to be used when verifying or
 	/// building transactions.
 	///
@@ -487,9 +552,10 @@ impl ChannelKeys for InMemoryChannelKeys {
 
 		let mut htlc_sigs = Vec::with_capacity(commitment_tx.htlcs().len());
 		for htlc in commitment_tx.htlcs() {
-			let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, commitment_tx.feerate_per_kw(), self.holder_selected_contest_delay(), htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
-			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
-			let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]);
+			let opt_anchors = self.opt_anchors();
+			let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, commitment_tx.feerate_per_kw(), self.holder_selected_contest_delay(), htlc, opt_anchors, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, opt_anchors, &keys);
+			let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_tx_counterparty_sighash_type(opt_anchors))[..]);
 			let holder_htlc_key = match chan_utils::derive_private_key(&secp_ctx, &keys.per_commitment_point, &self.htlc_base_key) {
 				Ok(s) => s,
 				Err(_) => return Err(()),
@@ -520,6 +586,17 @@ impl ChannelKeys for InMemoryChannelKeys {
 		trusted_tx.get_htlc_sigs(&self.htlc_base_key, &channel_parameters.as_holder_broadcastable(), secp_ctx)
 	}
 
+	fn sign_holder_htlc_input<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, commitment_tx: &HolderCommitmentTransaction, htlc_index: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		let trusted_tx = commitment_tx.trust();
+		let keys = trusted_tx.keys();
+		let htlc = trusted_tx.htlcs().get(htlc_index).ok_or(())?;
+		let htlc_key = chan_utils::derive_private_key(&secp_ctx, &keys.per_commitment_point, &self.htlc_base_key).map_err(|_| ())?;
+		let witness_script = chan_utils::get_htlc_redeemscript(&htlc, self.opt_anchors(), &keys);
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(htlc_tx)
+			.signature_hash(input, &witness_script, htlc.amount_msat / 1000, SigHashType::All)[..]);
+		Ok(secp_ctx.sign(&sighash, &htlc_key))
+	}
+
 	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		let revocation_key = match chan_utils::derive_private_revocation_key(&secp_ctx, &per_commitment_key, &self.revocation_base_key) {
 			Ok(revocation_key) => revocation_key,
@@ -539,7 +616,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 				Ok(holder_htlcpubkey) => holder_htlcpubkey,
 				Err(_) => return Err(())
 			};
-			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &counterparty_htlcpubkey, &holder_htlcpubkey, &revocation_pubkey)
+			chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors(), &counterparty_htlcpubkey, &holder_htlcpubkey, &revocation_pubkey)
 		} else {
 			let counterparty_delayedpubkey = match chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.counterparty_pubkeys().delayed_payment_basepoint) {
 				Ok(counterparty_delayedpubkey) => counterparty_delayedpubkey,
@@ -557,7 +634,7 @@ impl ChannelKeys for InMemoryChannelKeys {
 			let witness_script = if let Ok(revocation_pubkey) = chan_utils::derive_public_revocation_key(&secp_ctx, &per_commitment_point, &self.pubkeys().revocation_basepoint) {
 				if let Ok(counterparty_htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.counterparty_pubkeys().htlc_basepoint) {
 					if let Ok(htlcpubkey) = chan_utils::derive_public_key(&secp_ctx, &per_commitment_point, &self.pubkeys().htlc_basepoint) {
-						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &counterparty_htlcpubkey, &htlcpubkey, &revocation_pubkey)
+						chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, self.opt_anchors(), &counterparty_htlcpubkey, &htlcpubkey, &revocation_pubkey)
 					} else { return Err(()) }
 				} else { return Err(()) }
 			} else { return Err(()) };
@@ -568,6 +645,14 @@ impl ChannelKeys for InMemoryChannelKeys {
 		Err(())
 	}
 
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		let funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.funding_key);
+		let witness_script = chan_utils::get_anchor_redeemscript(&funding_pubkey);
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(anchor_tx)
+			.signature_hash(input, &witness_script, chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI, SigHashType::All)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
 	fn sign_closing_transaction<T: secp256k1::Signing>(&self, closing_tx: &Transaction, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		if closing_tx.input.len() != 1 { return Err(()); }
 		if closing_tx.input[0].witness.len() != 0 { return Err(()); }
@@ -589,6 +674,29 @@ impl ChannelKeys for InMemoryChannelKeys {
 	fn ready_channel(&mut self, channel_parameters: &ChannelTransactionParameters) {
 		assert!(self.channel_parameters.is_none(), "Acceptance already noted");
 		assert!(channel_parameters.is_populated(), "Channel parameters must be fully populated");
+		self.channel_value_satoshis = channel_parameters.channel_value_satoshis;
+		self.channel_parameters = Some(channel_parameters.clone());
+	}
+
+	fn sign_splice_funding_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		let funding_outpoint = self.funding_outpoint().into_bitcoin_outpoint();
+		match splice_tx.input.get(input) {
+			Some(txin) if txin.previous_output == funding_outpoint && txin.witness.is_empty() => {},
+			_ => return Err(()),
+		}
+
+		let funding_pubkey = PublicKey::from_secret_key(secp_ctx, &self.funding_key);
+		let channel_funding_redeemscript = make_funding_redeemscript(&funding_pubkey, &self.counterparty_pubkeys().funding_pubkey);
+
+		let sighash = hash_to_message!(&bip143::SigHashCache::new(splice_tx)
+			.signature_hash(input, &channel_funding_redeemscript, self.channel_value_satoshis, SigHashType::All)[..]);
+		Ok(secp_ctx.sign(&sighash, &self.funding_key))
+	}
+
+	fn splice_channel(&mut self, channel_parameters: &ChannelTransactionParameters) {
+		assert!(self.channel_parameters.is_some(), "Splicing a channel which was never ready");
+		assert!(channel_parameters.is_populated(), "Channel parameters must be fully populated");
+		self.channel_value_satoshis = channel_parameters.channel_value_satoshis;
 		self.channel_parameters = Some(channel_parameters.clone());
 	}
 }
@@ -618,8 +726,28 @@ impl Readable for InMemoryChannelKeys {
 		let delayed_payment_base_key = Readable::read(reader)?;
 		let htlc_base_key = Readable::read(reader)?;
 		let commitment_seed = Readable::read(reader)?;
-		let counterparty_channel_data = Readable::read(reader)?;
+		// The channel parameters are length-prefixed as an Option, so we can tell whether they were
+		// written before opt_anchors and channel_value_satoshis were appended to them.
+		let mut legacy_channel_parameters = false;
+		let mut counterparty_channel_data = match BigSize::read(reader)?.0 {
+			0 => None,
+			len => {
+				let mut params_reader = FixedLengthReader::new(&mut *reader, len - 1);
+				let mut channel_parameters = ChannelTransactionParameters::read_v1(&mut params_reader)?;
+				if params_reader.bytes_remain() {
+					channel_parameters.opt_anchors = Readable::read(&mut params_reader)?;
+					channel_parameters.channel_value_satoshis = Readable::read(&mut params_reader)?;
+				} else {
+					legacy_channel_parameters = true;
+				}
+				params_reader.eat_remaining()?;
+				Some(channel_parameters)
+			},
+		};
 		let channel_value_satoshis = Readable::read(reader)?;
+		if legacy_channel_parameters {
+			counterparty_channel_data.as_mut().unwrap().channel_value_satoshis = channel_value_satoshis;
+		}
 		let secp_ctx = Secp256k1::signing_only();
 		let holder_channel_pubkeys =
 			InMemoryChannelKeys::make_holder_keys(&secp_ctx, &funding_key, &revocation_base_key,
@@ -653,6 +781,7 @@ impl Readable for InMemoryChannelKeys {
 pub struct KeysManager {
 	secp_ctx: Secp256k1<secp256k1::SignOnly>,
 	node_secret: SecretKey,
+	destination_key: SecretKey,
 	destination_script: Script,
 	shutdown_pubkey: PublicKey,
 	channel_master_key: ExtendedPrivKey,
@@ -690,14 +819,12 @@ impl KeysManager {
 		match ExtendedPrivKey::new_master(network.clone(), seed) {
 			Ok(master_key) => {
 				let node_secret = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(0).unwrap()).expect("Your RNG is busted").private_key.key;
-				let destination_script = match master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(1).unwrap()) {
-					Ok(destination_key) => {
-						let wpubkey_hash = WPubkeyHash::hash(&ExtendedPubKey::from_private(&secp_ctx, &destination_key).public_key.to_bytes());
-						Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
-						              .push_slice(&wpubkey_hash.into_inner())
-						              .into_script()
-					},
-					Err(_) => panic!("Your RNG is busted"),
+				let destination_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(1).unwrap()).expect("Your RNG is busted");
+				let destination_script = {
+					let wpubkey_hash = WPubkeyHash::hash(&ExtendedPubKey::from_private(&secp_ctx, &destination_key).public_key.to_bytes());
+					Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0)
+					              .push_slice(&wpubkey_hash.into_inner())
+					              .into_script()
 				};
 				let shutdown_pubkey = match master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(2).unwrap()) {
 					Ok(shutdown_key) => ExtendedPubKey::from_private(&secp_ctx, &shutdown_key).public_key.key,
@@ -709,6 +836,7 @@ impl KeysManager {
 				KeysManager {
 					secp_ctx,
 					node_secret,
+					destination_key: destination_key.private_key.key,
 					destination_script,
 					shutdown_pubkey,
 					channel_master_key,
@@ -784,6 +912,109 @@ impl KeysManager {
 			(params_1, params_2),
 		)
 	}
+
+	/// Creates a Transaction which spends the given descriptors to the given outputs, plus an
+	/// output to the given change destination if enough change value remains. The transaction
+	/// will have a feerate of at least feerate_sat_per_1000_weight.
+	///
+	/// Returns Err(()) if the output value is greater than the input value minus the required
+	/// fee, if a descriptor was duplicated, or if a StaticOutput descriptor doesn't pay to our
+	/// destination script.
+	///
+	/// We do not enforce that outputs meet the dust limit or that any output scripts are standard.
+	///
+	/// May panic if the descriptors were not generated by channels which used this KeysManager.
+	pub fn spend_spendable_outputs<C: Signing>(&self, descriptors: &[&SpendableOutputDescriptor], outputs: Vec<TxOut>, change_destination_script: Script, feerate_sat_per_1000_weight: u32, secp_ctx: &Secp256k1<C>) -> Result<Transaction, ()> {
+		// For each input, the key signing it, the script code committed to in the BIP 143
+		// signature, the value it spends and the witness elements following the signature.
+		let mut signing_info: Vec<(SecretKey, Script, u64, Vec<Vec<u8>>)> = Vec::with_capacity(descriptors.len());
+		let mut input = Vec::with_capacity(descriptors.len());
+		let mut input_value = 0;
+		let mut witness_weight = 0;
+		for descriptor in descriptors {
+			let (outpoint, sequence, key, script_code, value, witness_suffix) = match **descriptor {
+				SpendableOutputDescriptor::StaticOutput { ref outpoint, ref output } => {
+					if output.script_pubkey != self.destination_script { return Err(()); }
+					let pubkey = PublicKey::from_secret_key(&self.secp_ctx, &self.destination_key);
+					let script_code = Address::p2pkh(&::bitcoin::PublicKey { compressed: true, key: pubkey }, Network::Testnet).script_pubkey();
+					(outpoint, 0, self.destination_key, script_code, output.value, vec![pubkey.serialize().to_vec()])
+				},
+				SpendableOutputDescriptor::DynamicOutputP2WSH { ref outpoint, ref per_commitment_point, ref to_self_delay, ref output, ref key_derivation_params, ref revocation_pubkey } => {
+					let keys = self.derive_channel_keys(0, key_derivation_params.0, key_derivation_params.1);
+					let delayed_payment_key = chan_utils::derive_private_key(&self.secp_ctx, per_commitment_point, &keys.delayed_payment_base_key).map_err(|_| ())?;
+					let delayed_payment_pubkey = PublicKey::from_secret_key(&self.secp_ctx, &delayed_payment_key);
+					let witness_script = chan_utils::get_revokeable_redeemscript(revocation_pubkey, *to_self_delay, &delayed_payment_pubkey);
+					let witness_script_bytes = witness_script.to_bytes();
+					// The empty vector selects the non-revocation branch (MINIMALIF standard rule)
+					(outpoint, *to_self_delay as u32, delayed_payment_key, witness_script, output.value, vec![vec![], witness_script_bytes])
+				},
+				SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, ref output, ref key_derivation_params } => {
+					let keys = self.derive_channel_keys(0, key_derivation_params.0, key_derivation_params.1);
+					let payment_point = keys.pubkeys().payment_point;
+					let anchors_witness_script = chan_utils::get_to_countersignatory_with_anchors_redeemscript(&payment_point);
+					if output.script_pubkey == anchors_witness_script.to_v0_p2wsh() {
+						// The 1 OP_CSV in the witness script requires an nSequence of 1
+						let witness_script_bytes = anchors_witness_script.to_bytes();
+						(outpoint, 1, keys.payment_key, anchors_witness_script, output.value, vec![witness_script_bytes])
+					} else {
+						let script_code = Address::p2pkh(&::bitcoin::PublicKey { compressed: true, key: payment_point }, Network::Testnet).script_pubkey();
+						(outpoint, 0, keys.payment_key, script_code, output.value, vec![payment_point.serialize().to_vec()])
+					}
+				},
+			};
+			if input.iter().any(|txin: &TxIn| txin.previous_output == outpoint.into_bitcoin_outpoint()) { return Err(()); }
+			input.push(TxIn {
+				previous_output: outpoint.into_bitcoin_outpoint(),
+				script_sig: Script::new(),
+				sequence,
+				witness: Vec::new(),
+			});
+			input_value += value;
+			// The number of witness elements and a signature with its sighash flag, followed by
+			// the other elements, each with its length.
+			witness_weight += 1 + 1 + 73 + witness_suffix.iter().map(|element| 1 + element.len()).sum::<usize>();
+			signing_info.push((key, script_code, value, witness_suffix));
+		}
+
+		let output_value = outputs.iter().map(|output| output.value).sum::<u64>();
+		let mut spend_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input,
+			output: outputs,
+		};
+		// The segwit marker and flag add 2 to the weight. Round the fee up so that we never go
+		// below the requested feerate.
+		let fee = |tx: &Transaction| ((tx.get_weight() + 2 + witness_weight) as u64 * feerate_sat_per_1000_weight as u64 + 999) / 1000;
+		if input_value < output_value + fee(&spend_tx) {
+			return Err(());
+		}
+		spend_tx.output.push(TxOut { script_pubkey: change_destination_script, value: 0 });
+		let change_fee = fee(&spend_tx);
+		if input_value >= output_value + change_fee + DUST_LIMIT_SATOSHIS {
+			spend_tx.output.last_mut().unwrap().value = input_value - output_value - change_fee;
+		} else {
+			// Otherwise change would be dust, leave it to the fee instead
+			spend_tx.output.pop();
+		}
+
+		let mut witnesses = Vec::with_capacity(signing_info.len());
+		{
+			let mut sighash_cache = bip143::SigHashCache::new(&spend_tx);
+			for (idx, (key, script_code, value, witness_suffix)) in signing_info.drain(..).enumerate() {
+				let sighash = hash_to_message!(&sighash_cache.signature_hash(idx, &script_code, value, SigHashType::All)[..]);
+				let mut sig = secp_ctx.sign(&sighash, &key).serialize_der().to_vec();
+				sig.push(SigHashType::All as u8);
+				let mut witness = vec![sig];
+				witness.extend(witness_suffix);
+				witnesses.push(witness);
+			}
+		}
+		for (input, witness) in spend_tx.input.iter_mut().zip(witnesses.drain(..)) {
+			input.witness = witness;
+		}
+		Ok(spend_tx)
+	}
 }
 
 impl KeysInterface for KeysManager {
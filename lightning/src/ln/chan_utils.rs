@@ -50,6 +50,41 @@ const _EXCESS_BUFFER_SIZE: usize = MAX_BUF_SIZE - MAX_HTLCS as usize * HTLC_OUTP
 pub(super) const HTLC_SUCCESS_TX_WEIGHT: u64 = 703;
 pub(super) const HTLC_TIMEOUT_TX_WEIGHT: u64 = 663;
 
+/// The HTLC-Success and HTLC-Timeout transactions of option_anchors_zero_fee_htlc_tx channels spend
+/// the longer HTLC scripts carrying the 1 OP_CSV encumbrance.
+pub(super) const HTLC_SUCCESS_ANCHOR_TX_WEIGHT: u64 = 706;
+pub(super) const HTLC_TIMEOUT_ANCHOR_TX_WEIGHT: u64 = 666;
+
+/// The value of each of the two anchor outputs of an option_anchors_zero_fee_htlc_tx commitment
+/// transaction.
+pub const ANCHOR_OUTPUT_VALUE_SATOSHI: u64 = 330;
+
+/// Gets the weight of an HTLC-Timeout (if offered) or HTLC-Success transaction.
+#[inline]
+pub(crate) fn htlc_tx_weight(offered: bool, opt_anchors: bool) -> u64 {
+	match (offered, opt_anchors) {
+		(true, false) => HTLC_TIMEOUT_TX_WEIGHT,
+		(false, false) => HTLC_SUCCESS_TX_WEIGHT,
+		(true, true) => HTLC_TIMEOUT_ANCHOR_TX_WEIGHT,
+		(false, true) => HTLC_SUCCESS_ANCHOR_TX_WEIGHT,
+	}
+}
+
+/// Gets the fee the broadcaster's HTLC-Timeout (if offered) or HTLC-Success transaction pays at the
+/// given feerate. HTLC transactions of option_anchors_zero_fee_htlc_tx channels are zero-fee and
+/// must be fee-bumped by adding inputs, as they are signed by the countersignatory with
+/// SIGHASH_SINGLE|SIGHASH_ANYONECANPAY.
+#[inline]
+pub(crate) fn htlc_tx_fee_sat(feerate_per_kw: u32, offered: bool, opt_anchors: bool) -> u64 {
+	if opt_anchors { 0 } else { feerate_per_kw as u64 * htlc_tx_weight(offered, false) / 1000 }
+}
+
+/// Gets the sighash type the countersignatory uses for its HTLC transaction signatures.
+#[inline]
+pub(crate) fn htlc_tx_counterparty_sighash_type(opt_anchors: bool) -> SigHashType {
+	if opt_anchors { SigHashType::SinglePlusAnyoneCanPay } else { SigHashType::All }
+}
+
 #[derive(PartialEq)]
 pub(crate) enum HTLCType {
 	AcceptedHTLC,
@@ -58,10 +93,12 @@ pub(crate) enum HTLCType {
 
 impl HTLCType {
 	/// Check if a given tx witnessScript len matchs one of a pre-signed HTLC
-	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize) ->  Option<HTLCType> {
-		if witness_script_len == 133 {
+	pub(crate) fn scriptlen_to_htlctype(witness_script_len: usize, opt_anchors: bool) ->  Option<HTLCType> {
+		// option_anchors_zero_fee_htlc_tx adds 1 OP_CSV OP_DROP to both HTLC scripts
+		let anchor_len = if opt_anchors { 3 } else { 0 };
+		if witness_script_len == 133 + anchor_len {
 			Some(HTLCType::OfferedHTLC)
-		} else if witness_script_len >= 136 && witness_script_len <= 139 {
+		} else if witness_script_len >= 136 + anchor_len && witness_script_len <= 139 + anchor_len {
 			Some(HTLCType::AcceptedHTLC)
 		} else {
 			None
@@ -430,9 +467,9 @@ impl_writeable!(HTLCOutputInCommitment, HTLC_OUTPUT_IN_COMMITMENT_SIZE, {
 });
 
 #[inline]
-pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, broadcaster_htlc_key: &PublicKey, countersignatory_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
+pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommitment, opt_anchors: bool, broadcaster_htlc_key: &PublicKey, countersignatory_htlc_key: &PublicKey, revocation_key: &PublicKey) -> Script {
 	let payment_hash160 = Ripemd160::hash(&htlc.payment_hash.0[..]).into_inner();
-	if htlc.offered {
+	let builder = if htlc.offered {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
 		              .push_slice(&PubkeyHash::hash(&revocation_key.serialize())[..])
@@ -458,8 +495,6 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_EQUALVERIFY)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
 	} else {
 		Builder::new().push_opcode(opcodes::all::OP_DUP)
 		              .push_opcode(opcodes::all::OP_HASH160)
@@ -489,16 +524,45 @@ pub(crate) fn get_htlc_redeemscript_with_explicit_keys(htlc: &HTLCOutputInCommit
 		              .push_opcode(opcodes::all::OP_DROP)
 		              .push_opcode(opcodes::all::OP_CHECKSIG)
 		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .push_opcode(opcodes::all::OP_ENDIF)
-		              .into_script()
-	}
+	};
+	let builder = if opt_anchors {
+		builder.push_int(1)
+		       .push_opcode(opcodes::all::OP_CSV)
+		       .push_opcode(opcodes::all::OP_DROP)
+	} else { builder };
+	builder.push_opcode(opcodes::all::OP_ENDIF).into_script()
 }
 
 /// Gets the witness redeemscript for an HTLC output in a commitment transaction. Note that htlc
 /// does not need to have its previous_output_index filled.
 #[inline]
-pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, keys: &TxCreationKeys) -> Script {
-	get_htlc_redeemscript_with_explicit_keys(htlc, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key)
+pub fn get_htlc_redeemscript(htlc: &HTLCOutputInCommitment, opt_anchors: bool, keys: &TxCreationKeys) -> Script {
+	get_htlc_redeemscript_with_explicit_keys(htlc, opt_anchors, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key)
+}
+
+/// Gets the witness redeemscript for the to_remote output of an option_anchors_zero_fee_htlc_tx
+/// commitment transaction, which is encumbered by a 1 block relative timelock so that it can't be
+/// used to CPFP the commitment transaction before it confirms.
+pub fn get_to_countersignatory_with_anchors_redeemscript(payment_point: &PublicKey) -> Script {
+	Builder::new().push_slice(&payment_point.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIGVERIFY)
+	              .push_int(1)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .into_script()
+}
+
+/// Gets the witness redeemscript for an anchor output of an option_anchors_zero_fee_htlc_tx
+/// commitment transaction. It is spendable by the owner of the funding key at any time, or by
+/// anyone once the commitment transaction has 16 confirmations.
+pub fn get_anchor_redeemscript(funding_pubkey: &PublicKey) -> Script {
+	Builder::new().push_slice(&funding_pubkey.serialize()[..])
+	              .push_opcode(opcodes::all::OP_CHECKSIG)
+	              .push_opcode(opcodes::all::OP_IFDUP)
+	              .push_opcode(opcodes::all::OP_NOTIF)
+	              .push_int(16)
+	              .push_opcode(opcodes::all::OP_CSV)
+	              .push_opcode(opcodes::all::OP_ENDIF)
+	              .into_script()
 }
 
 /// Gets the redeemscript for a funding output from the two funding public keys.
@@ -518,7 +582,10 @@ pub fn make_funding_redeemscript(broadcaster: &PublicKey, countersignatory: &Pub
 }
 
 /// panics if htlc.transaction_output_index.is_none()!
-pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_delay: u16, htlc: &HTLCOutputInCommitment, broadcaster_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
+///
+/// If opt_anchors is set, the transaction is zero-fee and its input sets the nSequence of 1
+/// required by the HTLC output's 1 OP_CSV encumbrance.
+pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_delay: u16, htlc: &HTLCOutputInCommitment, opt_anchors: bool, broadcaster_delayed_payment_key: &PublicKey, revocation_key: &PublicKey) -> Transaction {
 	let mut txins: Vec<TxIn> = Vec::new();
 	txins.push(TxIn {
 		previous_output: OutPoint {
@@ -526,15 +593,11 @@ pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_del
 			vout: htlc.transaction_output_index.expect("Can't build an HTLC transaction for a dust output"),
 		},
 		script_sig: Script::new(),
-		sequence: 0,
+		sequence: if opt_anchors { 1 } else { 0 },
 		witness: Vec::new(),
 	});
 
-	let total_fee = if htlc.offered {
-			feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000
-		} else {
-			feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000
-		};
+	let total_fee = htlc_tx_fee_sat(feerate_per_kw, htlc.offered, opt_anchors);
 
 	let mut txouts: Vec<TxOut> = Vec::new();
 	txouts.push(TxOut {
@@ -555,7 +618,7 @@ pub fn build_htlc_transaction(prev_hash: &Txid, feerate_per_kw: u32, contest_del
 ///
 /// Normally, this is converted to the broadcaster/countersignatory-organized DirectedChannelTransactionParameters
 /// before use, via the as_holder_broadcastable and as_counterparty_broadcastable functions.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct ChannelTransactionParameters {
 	/// Holder public keys
 	pub holder_pubkeys: ChannelPublicKeys,
@@ -569,10 +632,18 @@ pub struct ChannelTransactionParameters {
 	pub counterparty_parameters: Option<CounterpartyChannelTransactionParameters>,
 	/// The late-bound funding outpoint
 	pub funding_outpoint: Option<chain::transaction::OutPoint>,
+	/// Whether the channel uses the option_anchors_zero_fee_htlc_tx commitment format, with two
+	/// anchor outputs, 1 OP_CSV encumbered to_remote and HTLC outputs and zero-fee HTLC
+	/// transactions.
+	pub opt_anchors: bool,
+	/// The value of the funding output. For dual-funded channels we initiate, this is only known
+	/// once our counterparty added its contribution, so it may differ from the value given to
+	/// KeysInterface::get_channel_keys.
+	pub channel_value_satoshis: u64,
 }
 
 /// Late-bound per-channel counterparty data used to build transactions.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct CounterpartyChannelTransactionParameters {
 	/// Counter-party public keys
 	pub pubkeys: ChannelPublicKeys,
@@ -621,9 +692,28 @@ impl_writeable!(ChannelTransactionParameters, 0, {
 	holder_selected_contest_delay,
 	is_outbound_from_holder,
 	counterparty_parameters,
-	funding_outpoint
+	funding_outpoint,
+	opt_anchors,
+	channel_value_satoshis
 });
 
+impl ChannelTransactionParameters {
+	/// Reads ChannelTransactionParameters written before opt_anchors and channel_value_satoshis
+	/// were added, ie by version 1 Channels and ChannelMonitors. Such channels never use anchor
+	/// outputs, while channel_value_satoshis is left at 0 for the caller to fill in.
+	pub(crate) fn read_v1<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		Ok(ChannelTransactionParameters {
+			holder_pubkeys: Readable::read(reader)?,
+			holder_selected_contest_delay: Readable::read(reader)?,
+			is_outbound_from_holder: Readable::read(reader)?,
+			counterparty_parameters: Readable::read(reader)?,
+			funding_outpoint: Readable::read(reader)?,
+			opt_anchors: false,
+			channel_value_satoshis: 0,
+		})
+	}
+}
+
 /// Static channel fields used to build transactions given per-commitment fields, organized by
 /// broadcaster/countersignatory.
 ///
@@ -674,6 +764,11 @@ impl<'a> DirectedChannelTransactionParameters<'a> {
 	pub fn funding_outpoint(&self) -> OutPoint {
 		self.inner.funding_outpoint.unwrap().into_bitcoin_outpoint()
 	}
+
+	/// Whether the channel uses the option_anchors_zero_fee_htlc_tx commitment format
+	pub fn opt_anchors(&self) -> bool {
+		self.inner.opt_anchors
+	}
 }
 
 /// Information needed to build and sign a holder's commitment transaction.
@@ -734,7 +829,9 @@ impl HolderCommitmentTransaction {
 			holder_selected_contest_delay: 0,
 			is_outbound_from_holder: false,
 			counterparty_parameters: Some(CounterpartyChannelTransactionParameters { pubkeys: channel_pubkeys.clone(), selected_contest_delay: 0 }),
-			funding_outpoint: Some(chain::transaction::OutPoint { txid: Default::default(), index: 0 })
+			funding_outpoint: Some(chain::transaction::OutPoint { txid: Default::default(), index: 0 }),
+			opt_anchors: false,
+			channel_value_satoshis: 3000,
 		};
 		let mut htlcs_with_aux: Vec<(_, ())> = Vec::new();
 		let inner = CommitmentTransaction::new_with_auxiliary_htlc_data(0, 0, 0, keys, 0, &mut htlcs_with_aux, &channel_parameters.as_counterparty_broadcastable());
@@ -939,13 +1036,19 @@ impl CommitmentTransaction {
 	//   caller needs to have sorted together with the HTLCs so it can keep track of the output index
 	// - building of a bitcoin transaction during a verify() call, in which case T is just ()
 	fn internal_build_outputs<T>(keys: &TxCreationKeys, to_broadcaster_value_sat: u64, to_countersignatory_value_sat: u64, htlcs_with_aux: &mut Vec<(HTLCOutputInCommitment, T)>, channel_parameters: &DirectedChannelTransactionParameters) -> Result<(Vec<TxOut>, Vec<HTLCOutputInCommitment>), ()> {
+		let broadcaster_pubkeys = channel_parameters.broadcaster_pubkeys();
 		let countersignatory_pubkeys = channel_parameters.countersignatory_pubkeys();
 		let contest_delay = channel_parameters.contest_delay();
+		let opt_anchors = channel_parameters.opt_anchors();
 
 		let mut txouts: Vec<(TxOut, Option<&mut HTLCOutputInCommitment>)> = Vec::new();
 
 		if to_countersignatory_value_sat > 0 {
-			let script = script_for_p2wpkh(&countersignatory_pubkeys.payment_point);
+			let script = if opt_anchors {
+				get_to_countersignatory_with_anchors_redeemscript(&countersignatory_pubkeys.payment_point).to_v0_p2wsh()
+			} else {
+				script_for_p2wpkh(&countersignatory_pubkeys.payment_point)
+			};
 			txouts.push((
 				TxOut {
 					script_pubkey: script.clone(),
@@ -970,9 +1073,32 @@ impl CommitmentTransaction {
 			));
 		}
 
+		if opt_anchors {
+			// Each party's anchor is only included if it has something at stake in the
+			// transaction, either a balance output or any untrimmed HTLC.
+			if to_broadcaster_value_sat > 0 || !htlcs_with_aux.is_empty() {
+				txouts.push((
+					TxOut {
+						script_pubkey: get_anchor_redeemscript(&broadcaster_pubkeys.funding_pubkey).to_v0_p2wsh(),
+						value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+					},
+					None,
+				));
+			}
+			if to_countersignatory_value_sat > 0 || !htlcs_with_aux.is_empty() {
+				txouts.push((
+					TxOut {
+						script_pubkey: get_anchor_redeemscript(&countersignatory_pubkeys.funding_pubkey).to_v0_p2wsh(),
+						value: ANCHOR_OUTPUT_VALUE_SATOSHI,
+					},
+					None,
+				));
+			}
+		}
+
 		let mut htlcs = Vec::with_capacity(htlcs_with_aux.len());
 		for (htlc, _) in htlcs_with_aux {
-			let script = chan_utils::get_htlc_redeemscript(&htlc, &keys);
+			let script = chan_utils::get_htlc_redeemscript(&htlc, opt_anchors, &keys);
 			let txout = TxOut {
 				script_pubkey: script.to_v0_p2wsh(),
 				value: htlc.amount_msat / 1000,
@@ -1136,9 +1262,9 @@ impl<'a> TrustedCommitmentTransaction<'a> {
 
 		for this_htlc in inner.htlcs.iter() {
 			assert!(this_htlc.transaction_output_index.is_some());
-			let htlc_tx = build_htlc_transaction(&txid, inner.feerate_per_kw, channel_parameters.contest_delay(), &this_htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+			let htlc_tx = build_htlc_transaction(&txid, inner.feerate_per_kw, channel_parameters.contest_delay(), &this_htlc, channel_parameters.opt_anchors(), &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
 
-			let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key);
+			let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc, channel_parameters.opt_anchors(), &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key);
 
 			let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.amount_msat / 1000, SigHashType::All)[..]);
 			ret.push(secp_ctx.sign(&sighash, &holder_htlc_key));
@@ -1158,27 +1284,40 @@ impl<'a> TrustedCommitmentTransaction<'a> {
 		// Further, we should never be provided the preimage for an HTLC-Timeout transaction.
 		if  this_htlc.offered && preimage.is_some() { unreachable!(); }
 
-		let mut htlc_tx = build_htlc_transaction(&txid, inner.feerate_per_kw, channel_parameters.contest_delay(), &this_htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
-
-		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc, &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key);
+		let mut htlc_tx = build_htlc_transaction(&txid, inner.feerate_per_kw, channel_parameters.contest_delay(), &this_htlc, channel_parameters.opt_anchors(), &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+		htlc_tx.input[0].witness = self.get_htlc_input_witness(channel_parameters, htlc_index, counterparty_signature, signature, preimage);
+		htlc_tx
+	}
 
+	/// Gets the witness of the input spending an HTLC output, given a preimage (for !htlc.offered)
+	/// and the holder HTLC transaction signature.
+	///
+	/// With option_anchors_zero_fee_htlc_tx, this allows to spend the HTLC output in a transaction
+	/// with more inputs and outputs than the one built by get_signed_htlc_tx, as our counterparty's
+	/// signature only commits to the HTLC input and the output at the same index.
+	pub(crate) fn get_htlc_input_witness(&self, channel_parameters: &DirectedChannelTransactionParameters, htlc_index: usize, counterparty_signature: &Signature, signature: &Signature, preimage: &Option<PaymentPreimage>) -> Vec<Vec<u8>> {
+		let keys = &self.inner.keys;
+		let this_htlc = &self.inner.htlcs[htlc_index];
+		let htlc_redeemscript = get_htlc_redeemscript_with_explicit_keys(&this_htlc, channel_parameters.opt_anchors(), &keys.broadcaster_htlc_key, &keys.countersignatory_htlc_key, &keys.revocation_key);
+
+		let mut witness = Vec::with_capacity(5);
 		// First push the multisig dummy, note that due to BIP147 (NULLDUMMY) it must be a zero-length element.
-		htlc_tx.input[0].witness.push(Vec::new());
+		witness.push(Vec::new());
 
-		htlc_tx.input[0].witness.push(counterparty_signature.serialize_der().to_vec());
-		htlc_tx.input[0].witness.push(signature.serialize_der().to_vec());
-		htlc_tx.input[0].witness[1].push(SigHashType::All as u8);
-		htlc_tx.input[0].witness[2].push(SigHashType::All as u8);
+		witness.push(counterparty_signature.serialize_der().to_vec());
+		witness.push(signature.serialize_der().to_vec());
+		witness[1].push(htlc_tx_counterparty_sighash_type(channel_parameters.opt_anchors()) as u8);
+		witness[2].push(SigHashType::All as u8);
 
 		if this_htlc.offered {
 			// Due to BIP146 (MINIMALIF) this must be a zero-length element to relay.
-			htlc_tx.input[0].witness.push(Vec::new());
+			witness.push(Vec::new());
 		} else {
-			htlc_tx.input[0].witness.push(preimage.unwrap().0.to_vec());
+			witness.push(preimage.unwrap().0.to_vec());
 		}
 
-		htlc_tx.input[0].witness.push(htlc_redeemscript.as_bytes().to_vec());
-		htlc_tx
+		witness.push(htlc_redeemscript.as_bytes().to_vec());
+		witness
 	}
 }
 
@@ -25,14 +25,17 @@ use bitcoin::secp256k1;
 use ln::features::{ChannelFeatures, InitFeatures};
 use ln::msgs;
 use ln::msgs::{DecodeError, OptionalField, DataLossProtect};
-use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
-use ln::chan_utils::{CounterpartyCommitmentSecrets, TxCreationKeys, HTLCOutputInCommitment, HTLC_SUCCESS_TX_WEIGHT, HTLC_TIMEOUT_TX_WEIGHT, make_funding_redeemscript, ChannelPublicKeys, CommitmentTransaction, HolderCommitmentTransaction, ChannelTransactionParameters, CounterpartyChannelTransactionParameters, MAX_HTLCS, get_commitment_transaction_number_obscure_factor};
+use ln::channelmanager::{PendingHTLCStatus, HTLCSource, HTLCFailReason, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, PaymentPreimage, PaymentHash, BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA, CounterpartyForwardingInfo, HTLCDirection, PendingHTLCState, PendingHTLCDetails};
+use ln::chan_utils::{CounterpartyCommitmentSecrets, TxCreationKeys, HTLCOutputInCommitment, ANCHOR_OUTPUT_VALUE_SATOSHI, make_funding_redeemscript, ChannelPublicKeys, CommitmentTransaction, HolderCommitmentTransaction, ChannelTransactionParameters, CounterpartyChannelTransactionParameters, MAX_HTLCS, get_commitment_transaction_number_obscure_factor};
 use ln::chan_utils;
-use chain::chaininterface::{FeeEstimator,ConfirmationTarget};
+use ln::interactivetx::{FundingContribution, FundingTxSigning, InteractiveTxConstructor, InteractiveTxMessage, SHARED_INPUT_WITNESS_WEIGHT};
+use chain::chaininterface::{FeeEstimator,ConfirmationTarget,WalletUtxo};
 use chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER};
 use chain::transaction::{OutPoint, TransactionData};
 use chain::keysinterface::{ChannelKeys, KeysInterface};
+use util::byte_utils;
 use util::transaction_utils;
+use util::events::{MessageSendEvent, ClosureReason};
 use util::ser::{Readable, ReadableArgs, Writeable, Writer, VecWriter};
 use util::logger::Logger;
 use util::errors::APIError;
@@ -258,6 +261,109 @@ enum UpdateStatus {
 	DisabledStaged,
 }
 
+/// A message we send to agree on quiescence and start a splice.
+pub(super) enum SpliceMessage {
+	Stfu(msgs::Stfu),
+	Splice(msgs::Splice),
+	Abort(msgs::TxAbort),
+}
+
+impl SpliceMessage {
+	pub(super) fn into_msg_send_event(self, node_id: PublicKey) -> MessageSendEvent {
+		match self {
+			SpliceMessage::Stfu(msg) => MessageSendEvent::SendStfu { node_id, msg },
+			SpliceMessage::Splice(msg) => MessageSendEvent::SendSplice { node_id, msg },
+			SpliceMessage::Abort(msg) => MessageSendEvent::SendTxAbort { node_id, msg },
+		}
+	}
+}
+
+/// The channel parameters our counterparty selected in its open_channel or open_channel2 message.
+struct InboundChannelParams<'a> {
+	temporary_channel_id: [u8; 32],
+	channel_value_satoshis: u64,
+	/// Our balance in the initial commitment transactions, ie the push_msat of a single-funded
+	/// channel or our own contribution to a dual-funded one.
+	holder_value_msat: u64,
+	dust_limit_satoshis: u64,
+	max_htlc_value_in_flight_msat: u64,
+	channel_reserve_satoshis: u64,
+	htlc_minimum_msat: u64,
+	feerate_per_kw: u32,
+	to_self_delay: u16,
+	max_accepted_htlcs: u16,
+	pubkeys: ChannelPublicKeys,
+	first_per_commitment_point: PublicKey,
+	channel_flags: u8,
+	shutdown_scriptpubkey: &'a OptionalField<Script>,
+}
+
+/// The channel parameters our counterparty selected in its accept_channel or accept_channel2
+/// message.
+struct AcceptedChannelParams<'a> {
+	dust_limit_satoshis: u64,
+	max_htlc_value_in_flight_msat: u64,
+	channel_reserve_satoshis: u64,
+	htlc_minimum_msat: u64,
+	minimum_depth: u32,
+	to_self_delay: u16,
+	max_accepted_htlcs: u16,
+	pubkeys: ChannelPublicKeys,
+	first_per_commitment_point: PublicKey,
+	shutdown_scriptpubkey: &'a OptionalField<Script>,
+}
+
+/// The funding of the channel on the other side of a splice: the new funding output while the
+/// splice transaction is pending, swapped with the current one whenever we build commitment
+/// transactions spending the new funding output.
+struct SpliceFunding<ChanSigner: ChannelKeys> {
+	channel_value_satoshis: u64,
+	value_to_self_msat: u64,
+	channel_transaction_parameters: ChannelTransactionParameters,
+	holder_keys: ChanSigner,
+}
+
+/// A splice negotiated with our counterparty, until its transaction confirms and both peers sent
+/// splice_locked. No HTLC or fee updates happen on the channel until then, so that we only need
+/// to sign a single commitment transaction spending the new funding output.
+struct PendingSplice<ChanSigner: ChannelKeys> {
+	is_initiator: bool,
+	holder_relative_satoshis: i64,
+	/// Unknown until we get splice_ack if we initiated the splice.
+	counterparty_relative_satoshis: Option<i64>,
+	/// Set once the splice transaction is constructed.
+	funding: Option<SpliceFunding<ChanSigner>>,
+	received_commitment_signed: bool,
+	funding_transaction: Option<Transaction>,
+	funding_tx_confirmations: u64,
+	funding_tx_confirmed_in: Option<BlockHash>,
+	short_channel_id: Option<u64>,
+	sent_splice_locked: bool,
+	received_splice_locked: bool,
+}
+
+impl<ChanSigner: ChannelKeys> PendingSplice<ChanSigner> {
+	fn new(is_initiator: bool, holder_relative_satoshis: i64, counterparty_relative_satoshis: Option<i64>) -> Self {
+		PendingSplice {
+			is_initiator,
+			holder_relative_satoshis,
+			counterparty_relative_satoshis,
+			funding: None,
+			received_commitment_signed: false,
+			funding_transaction: None,
+			funding_tx_confirmations: 0,
+			funding_tx_confirmed_in: None,
+			short_channel_id: None,
+			sent_splice_locked: false,
+			received_splice_locked: false,
+		}
+	}
+
+	fn funding_txo(&self) -> Option<OutPoint> {
+		self.funding.as_ref().and_then(|funding| funding.channel_transaction_parameters.funding_outpoint)
+	}
+}
+
 // TODO: We should refactor this to be an Inbound/OutboundChannel until initial setup handshaking
 // has been completed, and then turn into a Channel to get compiler-time enforcement of things like
 // calling channel_id() before we're set up or things like get_outbound_funding_signed on an
@@ -267,6 +373,10 @@ enum UpdateStatus {
 // Counterparty designates channel data owned by the another channel participant entity.
 pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	config: ChannelConfig,
+	/// The config we used before the last update_config call, along with the number of timer ticks
+	/// since. HTLCs paying its forwarding policy are still accepted until it expires after
+	/// EXPIRE_PREV_CONFIG_TICKS, giving its channel_update time to propagate.
+	prev_config: Option<(ChannelConfig, usize)>,
 
 	user_id: u64,
 
@@ -386,13 +496,80 @@ pub(super) struct Channel<ChanSigner: ChannelKeys> {
 	commitment_secrets: CounterpartyCommitmentSecrets,
 
 	network_sync: UpdateStatus,
+
+	/// Our contribution to the funding transaction of a dual-funded channel we initiated, until
+	/// our counterparty accepts the channel and we start constructing the transaction.
+	dual_funding_contribution: Option<FundingContribution>,
+	interactive_tx_constructor: Option<InteractiveTxConstructor>,
+	/// The funding transaction of a dual-funded channel or the transaction of a splice, until
+	/// both peers have signed it.
+	funding_tx_signing: Option<FundingTxSigning>,
+
+	/// The confirmed funding transaction, which a splice transaction spends.
+	funding_transaction: Option<Transaction>,
+	/// The funding outpoint the channel was opened with, which keeps identifying the channel and
+	/// its ChannelMonitor once the channel has been spliced.
+	original_funding_txo: Option<OutPoint>,
+	/// The splice we want to initiate once the channel is quiescent, until our counterparty
+	/// accepts it with splice_ack.
+	splice_contribution: Option<FundingContribution>,
+	sent_stfu: bool,
+	received_stfu: bool,
+	/// Whether we initiated quiescence, and thus send splice once it's reached.
+	holder_is_quiescence_initiator: bool,
+	pending_splice: Option<PendingSplice<ChanSigner>>,
+	/// The short_channel_ids the channel had before being spliced, along with the id of the next
+	/// HTLC our counterparty would send at the time. HTLCs received before a splice are known by
+	/// the short_channel_id we received them over, so it keeps being recognized until they're
+	/// resolved.
+	pre_splice_short_channel_ids: Vec<(u64, u64)>,
+	/// The largest value this channel may be funded or spliced to, which is only above
+	/// MAX_FUNDING_SATOSHIS_NO_WUMBO if our counterparty supports option_support_large_channel.
+	max_funding_satoshis: u64,
+	/// A random alias for short_channel_id which we send in funding_locked and recognize in HTLCs
+	/// forwarded to us, letting the channel be used before (or without) a confirmed funding
+	/// transaction.
+	scid_alias: u64,
+	/// The alias our counterparty sent in funding_locked, to be used in route hints to it.
+	counterparty_scid_alias: Option<u64>,
+	/// A destination given when we began closing the channel, paid instead of the script derived
+	/// from shutdown_pubkey.
+	shutdown_scriptpubkey: Option<Script>,
+	/// The feerate we propose and the most total fee we accept when negotiating the closing
+	/// transaction, if given when we began closing the channel. Otherwise we use our
+	/// FeeEstimator.
+	closing_target_feerate_per_kw: Option<u32>,
+	closing_max_fee_satoshis: Option<u64>,
+	/// Set on inbound channels opened while UserConfig::manually_accept_inbound_channels is set,
+	/// until the user accepts the channel. This isn't persisted as we never write channels which
+	/// haven't been funded.
+	inbound_awaiting_accept: bool,
+	/// The height by which the funding transaction of an inbound channel must have confirmed, set
+	/// once we start waiting for it to confirm.
+	funding_conf_deadline: Option<u32>,
+	/// The number of timer ticks this channel has spent waiting for its funding flow to complete.
+	/// This isn't persisted as we never write channels which haven't been funded.
+	unfunded_channel_age_ticks: usize,
+	/// The forwarding policy from the latest channel_update our counterparty sent for this
+	/// channel.
+	counterparty_forwarding_info: Option<CounterpartyForwardingInfo>,
 }
 
 pub const OUR_MAX_HTLCS: u16 = 50; //TODO
+/// The number of timer ticks (see ChannelManager::timer_chan_freshness_every_min) after updating
+/// a channel's config during which we keep accepting HTLCs paying its previous forwarding policy.
+pub(crate) const EXPIRE_PREV_CONFIG_TICKS: usize = 5;
+/// The number of timer ticks (see ChannelManager::timer_chan_freshness_every_min) after which we
+/// give up on a channel which hasn't exchanged funding_created/funding_signed yet and forget it.
+pub(crate) const UNFUNDED_CHANNEL_AGE_LIMIT_TICKS: usize = 60;
 /// Confirmation count threshold at which we close a channel. Ideally we'd keep the channel around
 /// on ice until the funding transaction gets more confirmations, but the LN protocol doesn't
 /// really allow for this, so instead we're stuck closing it out at that point.
 const UNCONF_THRESHOLD: u32 = 6;
+/// The number of blocks after which we give up on the funding transaction of an inbound channel
+/// confirming and forget the channel. We can't do anything with the channel until then, and our
+/// counterparty may never broadcast the funding transaction at all.
+pub const FUNDING_CONF_DEADLINE_BLOCKS: u32 = 2016;
 const SPENDING_INPUT_FOR_A_OUTPUT_WEIGHT: u64 = 79; // prevout: 36, nSequence: 4, script len: 1, witness lengths: (3+1)/4, sig: 73/4, if-selector: 1, redeemScript: (6 ops + 2*33 pubkeys + 1*2 delay)/4
 const B_OUTPUT_PLUS_SPENDING_INPUT_WEIGHT: u64 = 104; // prevout: 40, nSequence: 4, script len: 1, witness lengths: 3/4, sig: 73/4, pubkey: 33/4, output: 31 (TODO: Wrong? Useless?)
 
@@ -401,19 +578,31 @@ const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
 #[cfg(test)]
 pub const COMMITMENT_TX_BASE_WEIGHT: u64 = 724;
 #[cfg(not(test))]
+const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+#[cfg(test)]
+pub const COMMITMENT_TX_BASE_ANCHOR_WEIGHT: u64 = 1124;
+#[cfg(not(test))]
 const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
 #[cfg(test)]
 pub const COMMITMENT_TX_WEIGHT_PER_HTLC: u64 = 172;
 
-/// Maximmum `funding_satoshis` value, according to the BOLT #2 specification
-/// it's 2^24.
-pub const MAX_FUNDING_SATOSHIS: u64 = 1 << 24;
+/// Maximum `funding_satoshis` value for channels with peers which don't support
+/// `option_support_large_channel`, according to the BOLT #2 specification it's 2^24 - 1.
+pub const MAX_FUNDING_SATOSHIS_NO_WUMBO: u64 = (1 << 24) - 1;
+
+/// Total bitcoin supply in satoshis, the upper bound for the value of any channel.
+pub const TOTAL_BITCOIN_SUPPLY_SATOSHIS: u64 = 21_000_000 * 1_0000_0000;
+
+/// The current block subsidy, used to scale the funding transaction depth we require for large
+/// channels.
+const BLOCK_SUBSIDY_SATOSHIS: u64 = 625_000_000;
 
 /// Used to return a simple Error back to ChannelManager. Will get converted to a
-/// msgs::ErrorAction::SendErrorMessage or msgs::ErrorAction::IgnoreError as appropriate with our
-/// channel_id in ChannelManager.
+/// msgs::ErrorAction::SendErrorMessage, msgs::ErrorAction::DisconnectPeerWithWarning or
+/// msgs::ErrorAction::IgnoreError as appropriate with our channel_id in ChannelManager.
 pub(super) enum ChannelError {
 	Ignore(String),
+	Warn(String),
 	Close(String),
 	CloseDelayBroadcast(String),
 }
@@ -422,6 +611,7 @@ impl fmt::Debug for ChannelError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		match self {
 			&ChannelError::Ignore(ref e) => write!(f, "Ignore : {}", e),
+			&ChannelError::Warn(ref e) => write!(f, "Warn : {}", e),
 			&ChannelError::Close(ref e) => write!(f, "Close : {}", e),
 			&ChannelError::CloseDelayBroadcast(ref e) => write!(f, "CloseDelayBroadcast : {}", e)
 		}
@@ -456,8 +646,55 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		cmp::max(at_open_background_feerate as u64 * B_OUTPUT_PLUS_SPENDING_INPUT_WEIGHT / 1000, 546) //TODO
 	}
 
+	/// Decides whether a new channel uses option_anchors_zero_fee_htlc_tx. We only advertise the
+	/// feature if the user opted into it, so we use it iff we did and the peer advertises it too.
+	fn negotiate_anchor_outputs(config: &UserConfig, their_features: &InitFeatures) -> Result<bool, ChannelError> {
+		if config.own_channel_config.negotiate_anchor_outputs {
+			Ok(their_features.supports_anchors_zero_fee_htlc_tx())
+		} else if their_features.requires_anchors_zero_fee_htlc_tx() {
+			Err(ChannelError::Close("Peer requires option_anchors_zero_fee_htlc_tx but we have not enabled it".to_owned()))
+		} else {
+			Ok(false)
+		}
+	}
+
+	/// Gets the largest value a new channel may be funded or spliced to. Without
+	/// option_support_large_channel this is MAX_FUNDING_SATOSHIS_NO_WUMBO, otherwise it's only
+	/// limited by the user's ChannelHandshakeLimits::max_funding_satoshis for inbound channels.
+	fn get_max_funding_satoshis(config: &UserConfig, their_features: &InitFeatures, outbound: bool) -> u64 {
+		let max_funding_satoshis = if their_features.supports_wumbo() { TOTAL_BITCOIN_SUPPLY_SATOSHIS } else { MAX_FUNDING_SATOSHIS_NO_WUMBO };
+		if outbound {
+			max_funding_satoshis
+		} else {
+			cmp::min(max_funding_satoshis, config.peer_channel_config_limits.max_funding_satoshis)
+		}
+	}
+
+	/// Gets the number of confirmations we require for the funding transaction of an inbound
+	/// channel. Per BOLT 2, large channels should wait for more confirmations, so we scale our
+	/// configured minimum_depth up to one confirmation per block subsidy worth of channel value
+	/// times six, making double-spending the funding transaction with a reorg cost a miner about
+	/// six times as much as the channel value.
+	fn get_minimum_depth(config: &UserConfig, channel_value_satoshis: u64) -> u32 {
+		let minimum_depth = config.own_channel_config.minimum_depth;
+		if channel_value_satoshis <= MAX_FUNDING_SATOSHIS_NO_WUMBO {
+			return minimum_depth;
+		}
+		let scaled_depth = (channel_value_satoshis * 6 + BLOCK_SUBSIDY_SATOSHIS - 1) / BLOCK_SUBSIDY_SATOSHIS;
+		cmp::max(minimum_depth as u64, scaled_depth) as u32
+	}
+
+	fn commitment_tx_base_weight(opt_anchors: bool) -> u64 {
+		if opt_anchors { COMMITMENT_TX_BASE_ANCHOR_WEIGHT } else { COMMITMENT_TX_BASE_WEIGHT }
+	}
+
+	/// The value of the anchor outputs the funder pays for in each commitment transaction.
+	fn anchors_value_msat(opt_anchors: bool) -> u64 {
+		if opt_anchors { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000 } else { 0 }
+	}
+
 	// Constructors:
-	pub fn new_outbound<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
+	pub fn new_outbound<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, their_features: &InitFeatures, channel_value_satoshis: u64, push_msat: u64, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
 	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
 	      F::Target: FeeEstimator,
 	{
@@ -465,8 +702,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let chan_keys = keys_provider.get_channel_keys(false, channel_value_satoshis);
 		let pubkeys = chan_keys.pubkeys().clone();
 
-		if channel_value_satoshis >= MAX_FUNDING_SATOSHIS {
-			return Err(APIError::APIMisuseError{err: format!("funding_value must be smaller than {}, it was {}", MAX_FUNDING_SATOSHIS, channel_value_satoshis)});
+		let max_funding_satoshis = Channel::<ChanSigner>::get_max_funding_satoshis(config, their_features, true);
+		if channel_value_satoshis > max_funding_satoshis {
+			return Err(APIError::APIMisuseError{err: format!("funding_value must not exceed {}, it was {}", max_funding_satoshis, channel_value_satoshis)});
 		}
 		let channel_value_msat = channel_value_satoshis * 1000;
 		if push_msat > channel_value_msat {
@@ -485,6 +723,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(Channel {
 			user_id,
 			config: config.channel_options.clone(),
+			prev_config: None,
 
 			channel_id: keys_provider.get_secure_random_bytes(),
 			channel_state: ChannelState::OurInitSent as u32,
@@ -545,7 +784,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				holder_selected_contest_delay: config.own_channel_config.our_to_self_delay,
 				is_outbound_from_holder: true,
 				counterparty_parameters: None,
-				funding_outpoint: None
+				funding_outpoint: None,
+				opt_anchors: false, // Filled in in accept_channel
+				channel_value_satoshis,
 			},
 			counterparty_cur_commitment_point: None,
 
@@ -557,9 +798,50 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			commitment_secrets: CounterpartyCommitmentSecrets::new(),
 
 			network_sync: UpdateStatus::Fresh,
+
+			dual_funding_contribution: None,
+			interactive_tx_constructor: None,
+			funding_tx_signing: None,
+
+			funding_transaction: None,
+			original_funding_txo: None,
+			splice_contribution: None,
+			sent_stfu: false,
+			received_stfu: false,
+			holder_is_quiescence_initiator: false,
+			pending_splice: None,
+			pre_splice_short_channel_ids: Vec::new(),
+			max_funding_satoshis,
+			scid_alias: byte_utils::slice_to_be64(&keys_provider.get_secure_random_bytes()[24..32]),
+			counterparty_scid_alias: None,
+			shutdown_scriptpubkey: None,
+			closing_target_feerate_per_kw: None,
+			closing_max_fee_satoshis: None,
+			inbound_awaiting_accept: false,
+			funding_conf_deadline: None,
+			unfunded_channel_age_ticks: 0,
+			counterparty_forwarding_info: None,
 		})
 	}
 
+	/// Creates a new outbound dual-funded channel, contributing channel_value_satoshis from the
+	/// KeysInterface's funding wallet. Our counterparty may add to the channel value when it
+	/// accepts the channel.
+	pub fn new_outbound_dual_funded<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, their_features: &InitFeatures, channel_value_satoshis: u64, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, APIError>
+	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+	      F::Target: FeeEstimator,
+	{
+		let mut chan = Channel::new_outbound(fee_estimator, keys_provider, counterparty_node_id, their_features, channel_value_satoshis, 0, user_id, config)?;
+		let wallet = match keys_provider.get_utxo_source() {
+			Some(wallet) => wallet,
+			None => return Err(APIError::APIMisuseError { err: "Opening a dual-funded channel requires a funding wallet".to_owned() }),
+		};
+		let funding_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		chan.dual_funding_contribution = Some(FundingContribution::new(wallet, channel_value_satoshis, funding_feerate, true)
+			.map_err(|err| APIError::APIMisuseError { err })?);
+		Ok(chan)
+	}
+
 	fn check_remote_fee<F: Deref>(fee_estimator: &F, feerate_per_kw: u32) -> Result<(), ChannelError>
 		where F::Target: FeeEstimator
 	{
@@ -580,15 +862,38 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
           F::Target: FeeEstimator
 	{
-		let chan_keys = keys_provider.get_channel_keys(true, msg.funding_satoshis);
-		let pubkeys = chan_keys.pubkeys().clone();
-		let counterparty_pubkeys = ChannelPublicKeys {
-			funding_pubkey: msg.funding_pubkey,
-			revocation_basepoint: msg.revocation_basepoint,
-			payment_point: msg.payment_point,
-			delayed_payment_basepoint: msg.delayed_payment_basepoint,
-			htlc_basepoint: msg.htlc_basepoint
+		let params = InboundChannelParams {
+			temporary_channel_id: msg.temporary_channel_id,
+			channel_value_satoshis: msg.funding_satoshis,
+			holder_value_msat: msg.push_msat,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: msg.channel_reserve_satoshis,
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			feerate_per_kw: msg.feerate_per_kw,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			pubkeys: ChannelPublicKeys {
+				funding_pubkey: msg.funding_pubkey,
+				revocation_basepoint: msg.revocation_basepoint,
+				payment_point: msg.payment_point,
+				delayed_payment_basepoint: msg.delayed_payment_basepoint,
+				htlc_basepoint: msg.htlc_basepoint
+			},
+			first_per_commitment_point: msg.first_per_commitment_point,
+			channel_flags: msg.channel_flags,
+			shutdown_scriptpubkey: &msg.shutdown_scriptpubkey,
 		};
+		Channel::new_inbound(fee_estimator, keys_provider, counterparty_node_id, their_features, params, user_id, config)
+	}
+
+	fn new_inbound<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, their_features: InitFeatures, params: InboundChannelParams, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
+		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+          F::Target: FeeEstimator
+	{
+		let chan_keys = keys_provider.get_channel_keys(true, params.channel_value_satoshis);
+		let pubkeys = chan_keys.pubkeys().clone();
+		let counterparty_pubkeys = params.pubkeys.clone();
 		let mut local_config = (*config).channel_options.clone();
 
 		if config.own_channel_config.our_to_self_delay < BREAKDOWN_TIMEOUT {
@@ -596,65 +901,72 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 
 		// Check sanity of message fields:
-		if msg.funding_satoshis >= MAX_FUNDING_SATOSHIS {
-			return Err(ChannelError::Close(format!("Funding must be smaller than {}. It was {}", MAX_FUNDING_SATOSHIS, msg.funding_satoshis)));
+		if params.channel_value_satoshis > MAX_FUNDING_SATOSHIS_NO_WUMBO && !their_features.supports_wumbo() {
+			return Err(ChannelError::Close(format!("Funding must not exceed {} without option_support_large_channel. It was {}", MAX_FUNDING_SATOSHIS_NO_WUMBO, params.channel_value_satoshis)));
 		}
-		if msg.channel_reserve_satoshis > msg.funding_satoshis {
-			return Err(ChannelError::Close(format!("Bogus channel_reserve_satoshis ({}). Must be not greater than funding_satoshis: {}", msg.channel_reserve_satoshis, msg.funding_satoshis)));
+		if params.channel_value_satoshis > TOTAL_BITCOIN_SUPPLY_SATOSHIS {
+			return Err(ChannelError::Close(format!("Funding must not exceed the total bitcoin supply. It was {}", params.channel_value_satoshis)));
 		}
-		let funding_value = (msg.funding_satoshis - msg.channel_reserve_satoshis) * 1000;
-		if msg.push_msat > funding_value {
-			return Err(ChannelError::Close(format!("push_msat {} was larger than funding value {}", msg.push_msat, funding_value)));
+		let max_funding_satoshis = Channel::<ChanSigner>::get_max_funding_satoshis(config, &their_features, false);
+		if params.channel_value_satoshis > max_funding_satoshis {
+			return Err(ChannelError::Close(format!("Per our config, funding must not exceed {}. It was {}", max_funding_satoshis, params.channel_value_satoshis)));
 		}
-		if msg.dust_limit_satoshis > msg.funding_satoshis {
-			return Err(ChannelError::Close(format!("dust_limit_satoshis {} was larger than funding_satoshis {}. Peer never wants payout outputs?", msg.dust_limit_satoshis, msg.funding_satoshis)));
+		if params.channel_reserve_satoshis > params.channel_value_satoshis {
+			return Err(ChannelError::Close(format!("Bogus channel_reserve_satoshis ({}). Must be not greater than funding_satoshis: {}", params.channel_reserve_satoshis, params.channel_value_satoshis)));
 		}
-		if msg.dust_limit_satoshis > msg.channel_reserve_satoshis {
-			return Err(ChannelError::Close(format!("Bogus; channel reserve ({}) is less than dust limit ({})", msg.channel_reserve_satoshis, msg.dust_limit_satoshis)));
+		let funding_value = (params.channel_value_satoshis - params.channel_reserve_satoshis) * 1000;
+		if params.holder_value_msat > funding_value {
+			return Err(ChannelError::Close(format!("push_msat {} was larger than funding value {}", params.holder_value_msat, funding_value)));
 		}
-		let full_channel_value_msat = (msg.funding_satoshis - msg.channel_reserve_satoshis) * 1000;
-		if msg.htlc_minimum_msat >= full_channel_value_msat {
-			return Err(ChannelError::Close(format!("Minimum htlc value ({}) was larger than full channel value ({})", msg.htlc_minimum_msat, full_channel_value_msat)));
+		if params.dust_limit_satoshis > params.channel_value_satoshis {
+			return Err(ChannelError::Close(format!("dust_limit_satoshis {} was larger than funding_satoshis {}. Peer never wants payout outputs?", params.dust_limit_satoshis, params.channel_value_satoshis)));
 		}
-		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.feerate_per_kw)?;
+		if params.dust_limit_satoshis > params.channel_reserve_satoshis {
+			return Err(ChannelError::Close(format!("Bogus; channel reserve ({}) is less than dust limit ({})", params.channel_reserve_satoshis, params.dust_limit_satoshis)));
+		}
+		let full_channel_value_msat = (params.channel_value_satoshis - params.channel_reserve_satoshis) * 1000;
+		if params.htlc_minimum_msat >= full_channel_value_msat {
+			return Err(ChannelError::Close(format!("Minimum htlc value ({}) was larger than full channel value ({})", params.htlc_minimum_msat, full_channel_value_msat)));
+		}
+		Channel::<ChanSigner>::check_remote_fee(fee_estimator, params.feerate_per_kw)?;
 
 		let max_counterparty_selected_contest_delay = u16::min(config.peer_channel_config_limits.their_to_self_delay, MAX_LOCAL_BREAKDOWN_TIMEOUT);
-		if msg.to_self_delay > max_counterparty_selected_contest_delay {
-			return Err(ChannelError::Close(format!("They wanted our payments to be delayed by a needlessly long period. Upper limit: {}. Actual: {}", max_counterparty_selected_contest_delay, msg.to_self_delay)));
+		if params.to_self_delay > max_counterparty_selected_contest_delay {
+			return Err(ChannelError::Close(format!("They wanted our payments to be delayed by a needlessly long period. Upper limit: {}. Actual: {}", max_counterparty_selected_contest_delay, params.to_self_delay)));
 		}
-		if msg.max_accepted_htlcs < 1 {
+		if params.max_accepted_htlcs < 1 {
 			return Err(ChannelError::Close("0 max_accepted_htlcs makes for a useless channel".to_owned()));
 		}
-		if msg.max_accepted_htlcs > MAX_HTLCS {
-			return Err(ChannelError::Close(format!("max_accepted_htlcs was {}. It must not be larger than {}", msg.max_accepted_htlcs, MAX_HTLCS)));
+		if params.max_accepted_htlcs > MAX_HTLCS {
+			return Err(ChannelError::Close(format!("max_accepted_htlcs was {}. It must not be larger than {}", params.max_accepted_htlcs, MAX_HTLCS)));
 		}
 
 		// Now check against optional parameters as set by config...
-		if msg.funding_satoshis < config.peer_channel_config_limits.min_funding_satoshis {
-			return Err(ChannelError::Close(format!("Funding satoshis ({}) is less than the user specified limit ({})", msg.funding_satoshis, config.peer_channel_config_limits.min_funding_satoshis)));
+		if params.channel_value_satoshis < config.peer_channel_config_limits.min_funding_satoshis {
+			return Err(ChannelError::Close(format!("Funding satoshis ({}) is less than the user specified limit ({})", params.channel_value_satoshis, config.peer_channel_config_limits.min_funding_satoshis)));
 		}
-		if msg.htlc_minimum_msat > config.peer_channel_config_limits.max_htlc_minimum_msat {
-			return Err(ChannelError::Close(format!("htlc_minimum_msat ({}) is higher than the user specified limit ({})", msg.htlc_minimum_msat,  config.peer_channel_config_limits.max_htlc_minimum_msat)));
+		if params.htlc_minimum_msat > config.peer_channel_config_limits.max_htlc_minimum_msat {
+			return Err(ChannelError::Close(format!("htlc_minimum_msat ({}) is higher than the user specified limit ({})", params.htlc_minimum_msat,  config.peer_channel_config_limits.max_htlc_minimum_msat)));
 		}
-		if msg.max_htlc_value_in_flight_msat < config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat {
-			return Err(ChannelError::Close(format!("max_htlc_value_in_flight_msat ({}) is less than the user specified limit ({})", msg.max_htlc_value_in_flight_msat, config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat)));
+		if params.max_htlc_value_in_flight_msat < config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat {
+			return Err(ChannelError::Close(format!("max_htlc_value_in_flight_msat ({}) is less than the user specified limit ({})", params.max_htlc_value_in_flight_msat, config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat)));
 		}
-		if msg.channel_reserve_satoshis > config.peer_channel_config_limits.max_channel_reserve_satoshis {
-			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is higher than the user specified limit ({})", msg.channel_reserve_satoshis, config.peer_channel_config_limits.max_channel_reserve_satoshis)));
+		if params.channel_reserve_satoshis > config.peer_channel_config_limits.max_channel_reserve_satoshis {
+			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is higher than the user specified limit ({})", params.channel_reserve_satoshis, config.peer_channel_config_limits.max_channel_reserve_satoshis)));
 		}
-		if msg.max_accepted_htlcs < config.peer_channel_config_limits.min_max_accepted_htlcs {
-			return Err(ChannelError::Close(format!("max_accepted_htlcs ({}) is less than the user specified limit ({})", msg.max_accepted_htlcs, config.peer_channel_config_limits.min_max_accepted_htlcs)));
+		if params.max_accepted_htlcs < config.peer_channel_config_limits.min_max_accepted_htlcs {
+			return Err(ChannelError::Close(format!("max_accepted_htlcs ({}) is less than the user specified limit ({})", params.max_accepted_htlcs, config.peer_channel_config_limits.min_max_accepted_htlcs)));
 		}
-		if msg.dust_limit_satoshis < config.peer_channel_config_limits.min_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is less than the user specified limit ({})", msg.dust_limit_satoshis, config.peer_channel_config_limits.min_dust_limit_satoshis)));
+		if params.dust_limit_satoshis < config.peer_channel_config_limits.min_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is less than the user specified limit ({})", params.dust_limit_satoshis, config.peer_channel_config_limits.min_dust_limit_satoshis)));
 		}
-		if msg.dust_limit_satoshis > config.peer_channel_config_limits.max_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is greater than the user specified limit ({})", msg.dust_limit_satoshis, config.peer_channel_config_limits.max_dust_limit_satoshis)));
+		if params.dust_limit_satoshis > config.peer_channel_config_limits.max_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is greater than the user specified limit ({})", params.dust_limit_satoshis, config.peer_channel_config_limits.max_dust_limit_satoshis)));
 		}
 
 		// Convert things into internal flags and prep our state:
 
-		let announce = if (msg.channel_flags & 1) == 1 { true } else { false };
+		let announce = if (params.channel_flags & 1) == 1 { true } else { false };
 		if config.peer_channel_config_limits.force_announced_channel_preference {
 			if local_config.announced_channel != announce {
 				return Err(ChannelError::Close("Peer tried to open channel but their announcement preference is different from ours".to_owned()));
@@ -666,33 +978,35 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let background_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
 
 		let holder_dust_limit_satoshis = Channel::<ChanSigner>::derive_holder_dust_limit_satoshis(background_feerate);
-		let holder_selected_channel_reserve_satoshis = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(msg.funding_satoshis);
+		let holder_selected_channel_reserve_satoshis = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(params.channel_value_satoshis);
 		if holder_selected_channel_reserve_satoshis < holder_dust_limit_satoshis {
 			return Err(ChannelError::Close(format!("Suitable channel reserve not found. remote_channel_reserve was ({}). dust_limit_satoshis is ({}).", holder_selected_channel_reserve_satoshis, holder_dust_limit_satoshis)));
 		}
-		if msg.channel_reserve_satoshis < holder_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is smaller than our dust limit ({})", msg.channel_reserve_satoshis, holder_dust_limit_satoshis)));
+		if params.channel_reserve_satoshis < holder_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is smaller than our dust limit ({})", params.channel_reserve_satoshis, holder_dust_limit_satoshis)));
 		}
-		if holder_selected_channel_reserve_satoshis < msg.dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("Dust limit ({}) too high for the channel reserve we require the remote to keep ({})", msg.dust_limit_satoshis, holder_selected_channel_reserve_satoshis)));
+		if holder_selected_channel_reserve_satoshis < params.dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("Dust limit ({}) too high for the channel reserve we require the remote to keep ({})", params.dust_limit_satoshis, holder_selected_channel_reserve_satoshis)));
 		}
 
+		let opt_anchors = Channel::<ChanSigner>::negotiate_anchor_outputs(config, &their_features)?;
+
 		// check if the funder's amount for the initial commitment tx is sufficient
-		// for full fee payment
-		let funders_amount_msat = msg.funding_satoshis * 1000 - msg.push_msat;
-		let lower_limit = background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT;
+		// for full fee payment (and the anchor outputs, if any)
+		let funders_amount_msat = params.channel_value_satoshis * 1000 - params.holder_value_msat;
+		let lower_limit = background_feerate as u64 * Channel::<ChanSigner>::commitment_tx_base_weight(opt_anchors) + Channel::<ChanSigner>::anchors_value_msat(opt_anchors);
 		if funders_amount_msat < lower_limit {
 			return Err(ChannelError::Close(format!("Insufficient funding amount ({}) for initial commitment. Must be at least {}", funders_amount_msat, lower_limit)));
 		}
 
-		let to_local_msat = msg.push_msat;
-		let to_remote_msat = funders_amount_msat - background_feerate as u64 * COMMITMENT_TX_BASE_WEIGHT;
-		if to_local_msat <= msg.channel_reserve_satoshis * 1000 && to_remote_msat <= holder_selected_channel_reserve_satoshis * 1000 {
+		let to_local_msat = params.holder_value_msat;
+		let to_remote_msat = funders_amount_msat - lower_limit;
+		if to_local_msat <= params.channel_reserve_satoshis * 1000 && to_remote_msat <= holder_selected_channel_reserve_satoshis * 1000 {
 			return Err(ChannelError::Close("Insufficient funding amount for initial commitment".to_owned()));
 		}
 
 		let counterparty_shutdown_scriptpubkey = if their_features.supports_upfront_shutdown_script() {
-			match &msg.shutdown_scriptpubkey {
+			match params.shutdown_scriptpubkey {
 				&OptionalField::Present(ref script) => {
 					// Peer is signaling upfront_shutdown and has provided a non-accepted scriptpubkey format. We enforce it while receiving shutdown msg
 					if script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wsh() || script.is_v0_p2wpkh() {
@@ -715,8 +1029,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let chan = Channel {
 			user_id,
 			config: local_config,
+			prev_config: None,
 
-			channel_id: msg.temporary_channel_id,
+			channel_id: params.temporary_channel_id,
 			channel_state: (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32),
 			secp_ctx: Secp256k1::new(),
 
@@ -728,7 +1043,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 			cur_holder_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
 			cur_counterparty_commitment_transaction_number: INITIAL_COMMITMENT_NUMBER,
-			value_to_self_msat: msg.push_msat,
+			value_to_self_msat: params.holder_value_msat,
 
 			pending_inbound_htlcs: Vec::new(),
 			pending_outbound_htlcs: Vec::new(),
@@ -748,9 +1063,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			monitor_pending_failures: Vec::new(),
 
 			#[cfg(debug_assertions)]
-			holder_max_commitment_tx_output: ::std::sync::Mutex::new((msg.push_msat, msg.funding_satoshis * 1000 - msg.push_msat)),
+			holder_max_commitment_tx_output: ::std::sync::Mutex::new((params.holder_value_msat, params.channel_value_satoshis * 1000 - params.holder_value_msat)),
 			#[cfg(debug_assertions)]
-			counterparty_max_commitment_tx_output: ::std::sync::Mutex::new((msg.push_msat, msg.funding_satoshis * 1000 - msg.push_msat)),
+			counterparty_max_commitment_tx_output: ::std::sync::Mutex::new((params.holder_value_msat, params.channel_value_satoshis * 1000 - params.holder_value_msat)),
 
 			last_sent_closing_fee: None,
 
@@ -759,28 +1074,30 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			last_block_connected: Default::default(),
 			funding_tx_confirmations: 0,
 
-			feerate_per_kw: msg.feerate_per_kw,
-			channel_value_satoshis: msg.funding_satoshis,
-			counterparty_dust_limit_satoshis: msg.dust_limit_satoshis,
+			feerate_per_kw: params.feerate_per_kw,
+			channel_value_satoshis: params.channel_value_satoshis,
+			counterparty_dust_limit_satoshis: params.dust_limit_satoshis,
 			holder_dust_limit_satoshis,
-			counterparty_max_htlc_value_in_flight_msat: cmp::min(msg.max_htlc_value_in_flight_msat, msg.funding_satoshis * 1000),
-			counterparty_selected_channel_reserve_satoshis: msg.channel_reserve_satoshis,
-			counterparty_htlc_minimum_msat: msg.htlc_minimum_msat,
+			counterparty_max_htlc_value_in_flight_msat: cmp::min(params.max_htlc_value_in_flight_msat, params.channel_value_satoshis * 1000),
+			counterparty_selected_channel_reserve_satoshis: params.channel_reserve_satoshis,
+			counterparty_htlc_minimum_msat: params.htlc_minimum_msat,
 			holder_htlc_minimum_msat: if config.own_channel_config.our_htlc_minimum_msat == 0 { 1 } else { config.own_channel_config.our_htlc_minimum_msat },
-			counterparty_max_accepted_htlcs: msg.max_accepted_htlcs,
-			minimum_depth: config.own_channel_config.minimum_depth,
+			counterparty_max_accepted_htlcs: params.max_accepted_htlcs,
+			minimum_depth: Channel::<ChanSigner>::get_minimum_depth(config, params.channel_value_satoshis),
 
 			channel_transaction_parameters: ChannelTransactionParameters {
 				holder_pubkeys: pubkeys,
 				holder_selected_contest_delay: config.own_channel_config.our_to_self_delay,
 				is_outbound_from_holder: false,
 				counterparty_parameters: Some(CounterpartyChannelTransactionParameters {
-					selected_contest_delay: msg.to_self_delay,
+					selected_contest_delay: params.to_self_delay,
 					pubkeys: counterparty_pubkeys,
 				}),
-				funding_outpoint: None
+				funding_outpoint: None,
+				opt_anchors,
+				channel_value_satoshis: params.channel_value_satoshis,
 			},
-			counterparty_cur_commitment_point: Some(msg.first_per_commitment_point),
+			counterparty_cur_commitment_point: Some(params.first_per_commitment_point),
 
 			counterparty_prev_commitment_point: None,
 			counterparty_node_id,
@@ -790,11 +1107,89 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			commitment_secrets: CounterpartyCommitmentSecrets::new(),
 
 			network_sync: UpdateStatus::Fresh,
+
+			dual_funding_contribution: None,
+			interactive_tx_constructor: None,
+			funding_tx_signing: None,
+
+			funding_transaction: None,
+			original_funding_txo: None,
+			splice_contribution: None,
+			sent_stfu: false,
+			received_stfu: false,
+			holder_is_quiescence_initiator: false,
+			pending_splice: None,
+			pre_splice_short_channel_ids: Vec::new(),
+			max_funding_satoshis,
+			scid_alias: byte_utils::slice_to_be64(&keys_provider.get_secure_random_bytes()[24..32]),
+			counterparty_scid_alias: None,
+			shutdown_scriptpubkey: None,
+			closing_target_feerate_per_kw: None,
+			closing_max_fee_satoshis: None,
+			inbound_awaiting_accept: config.manually_accept_inbound_channels,
+			funding_conf_deadline: None,
+			unfunded_channel_age_ticks: 0,
+			counterparty_forwarding_info: None,
 		};
 
 		Ok(chan)
 	}
 
+	/// Creates a new dual-funded channel from a remote sides' request for one, contributing
+	/// ChannelHandshakeConfig::dual_funding_contribution_satoshis to it. Fails if we can't
+	/// contribute that much.
+	/// Assumes chain_hash has already been checked and corresponds with what we expect!
+	pub fn new_from_req_v2<K: Deref, F: Deref>(fee_estimator: &F, keys_provider: &K, counterparty_node_id: PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2, user_id: u64, config: &UserConfig) -> Result<Channel<ChanSigner>, ChannelError>
+		where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+          F::Target: FeeEstimator
+	{
+		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.funding_feerate_perkw)?;
+		let contribution_satoshis = config.own_channel_config.dual_funding_contribution_satoshis;
+		let contribution = if contribution_satoshis == 0 {
+			FundingContribution::empty(msg.funding_feerate_perkw)
+		} else {
+			let max_funding_satoshis = Channel::<ChanSigner>::get_max_funding_satoshis(config, &their_features, false);
+			if msg.funding_satoshis + contribution_satoshis > max_funding_satoshis {
+				return Err(ChannelError::Close(format!("Failed to contribute {} sat to the channel: the channel value would exceed {} sat", contribution_satoshis, max_funding_satoshis)));
+			}
+			let wallet = keys_provider.get_utxo_source()
+				.ok_or_else(|| ChannelError::Close(format!("Failed to contribute {} sat to the channel: we have no funding wallet", contribution_satoshis)))?;
+			FundingContribution::new(wallet, contribution_satoshis, msg.funding_feerate_perkw, false)
+				.map_err(|e| ChannelError::Close(format!("Failed to contribute {} sat to the channel: {}", contribution_satoshis, e)))?
+		};
+
+		// Both peers' contributions make up the channel value, with ours being our initial balance.
+		let channel_value_satoshis = msg.funding_satoshis + contribution.value_satoshis;
+		// Omitting the upfront shutdown script opts out of it, like a 0-length script in open_channel
+		let shutdown_scriptpubkey = OptionalField::Present(msg.shutdown_scriptpubkey.clone().unwrap_or_else(Script::new));
+		let params = InboundChannelParams {
+			temporary_channel_id: msg.temporary_channel_id,
+			channel_value_satoshis,
+			holder_value_msat: contribution.value_satoshis * 1000,
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel_value_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			feerate_per_kw: msg.commitment_feerate_perkw,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			pubkeys: ChannelPublicKeys {
+				funding_pubkey: msg.funding_pubkey,
+				revocation_basepoint: msg.revocation_basepoint,
+				payment_point: msg.payment_point,
+				delayed_payment_basepoint: msg.delayed_payment_basepoint,
+				htlc_basepoint: msg.htlc_basepoint
+			},
+			first_per_commitment_point: msg.first_per_commitment_point,
+			channel_flags: msg.channel_flags,
+			shutdown_scriptpubkey: &shutdown_scriptpubkey,
+		};
+		let mut chan = Channel::new_inbound(fee_estimator, keys_provider, counterparty_node_id, their_features, params, user_id, config)?;
+		let funding_script = chan.get_funding_redeemscript().to_v0_p2wsh();
+		chan.interactive_tx_constructor = Some(InteractiveTxConstructor::new(chan.channel_id, false, msg.locktime, funding_script, channel_value_satoshis, msg.funding_satoshis as i64, None, contribution));
+		Ok(chan)
+	}
+
 	/// Transaction nomenclature is somewhat confusing here as there are many different cases - a
 	/// transaction is referred to as "a's transaction" implying that a will be able to broadcast
 	/// the transaction. Thus, b will generally be sending a signature over such a transaction to
@@ -819,6 +1214,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		let mut included_non_dust_htlcs: Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)> = Vec::with_capacity(num_htlcs);
 
 		let broadcaster_dust_limit_satoshis = if local { self.holder_dust_limit_satoshis } else { self.counterparty_dust_limit_satoshis };
+		let opt_anchors = self.channel_transaction_parameters.opt_anchors;
 		let mut remote_htlc_total_msat = 0;
 		let mut local_htlc_total_msat = 0;
 		let mut value_to_self_msat_offset = 0;
@@ -841,7 +1237,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			($htlc: expr, $outbound: expr, $source: expr, $state_name: expr) => {
 				if $outbound == local { // "offered HTLC output"
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, true);
-					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_TIMEOUT_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + chan_utils::htlc_tx_fee_sat(feerate_per_kw, true, opt_anchors) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						included_non_dust_htlcs.push((htlc_in_tx, $source));
 					} else {
@@ -850,7 +1246,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 					}
 				} else {
 					let htlc_in_tx = get_htlc_in_commitment!($htlc, false);
-					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + (feerate_per_kw as u64 * HTLC_SUCCESS_TX_WEIGHT / 1000) {
+					if $htlc.amount_msat / 1000 >= broadcaster_dust_limit_satoshis + chan_utils::htlc_tx_fee_sat(feerate_per_kw, false, opt_anchors) {
 						log_trace!(logger, "   ...including {} {} HTLC {} (hash {}) with value {}", if $outbound { "outbound" } else { "inbound" }, $state_name, $htlc.htlc_id, log_bytes!($htlc.payment_hash.0), $htlc.amount_msat);
 						included_non_dust_htlcs.push((htlc_in_tx, $source));
 					} else {
@@ -940,7 +1336,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			broadcaster_max_commitment_tx_output.1 = cmp::max(broadcaster_max_commitment_tx_output.1, value_to_remote_msat as u64);
 		}
 
-		let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (included_non_dust_htlcs.len() as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
+		// The funder also pays for the anchor outputs, if any
+		let total_fee = feerate_per_kw as u64 * (Channel::<ChanSigner>::commitment_tx_base_weight(opt_anchors) + (included_non_dust_htlcs.len() as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
+			+ Channel::<ChanSigner>::anchors_value_msat(opt_anchors) / 1000;
 		let (value_to_self, value_to_remote) = if self.is_outbound() {
 			(value_to_self_msat / 1000 - total_fee as i64, value_to_remote_msat / 1000)
 		} else {
@@ -985,6 +1383,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	#[inline]
 	fn get_closing_scriptpubkey(&self) -> Script {
+		if let Some(ref script) = self.shutdown_scriptpubkey {
+			return script.clone();
+		}
 		let channel_close_key_hash = WPubkeyHash::hash(&self.shutdown_pubkey.serialize());
 		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&channel_close_key_hash[..]).into_script()
 	}
@@ -1093,18 +1494,88 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(secp_check!(TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, delayed_payment_base, htlc_basepoint, &counterparty_pubkeys.revocation_basepoint, &counterparty_pubkeys.htlc_basepoint), "Local tx keys generation got bogus keys".to_owned()))
 	}
 
+	/// Gets the open_channel2 message for a dual-funded channel we initiated.
+	pub fn get_open_channel_v2(&self, chain_hash: BlockHash) -> msgs::OpenChannelV2 {
+		let open_channel = self.get_open_channel(chain_hash);
+		let funding_feerate_perkw = match self.dual_funding_contribution {
+			Some(ref contribution) => contribution.feerate_per_kw,
+			None => panic!("Tried to send an open_channel2 for a single-funded channel"),
+		};
+
+		msgs::OpenChannelV2 {
+			chain_hash,
+			temporary_channel_id: self.channel_id,
+			funding_feerate_perkw,
+			commitment_feerate_perkw: open_channel.feerate_per_kw,
+			funding_satoshis: open_channel.funding_satoshis,
+			dust_limit_satoshis: open_channel.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: open_channel.max_htlc_value_in_flight_msat,
+			htlc_minimum_msat: open_channel.htlc_minimum_msat,
+			to_self_delay: open_channel.to_self_delay,
+			max_accepted_htlcs: open_channel.max_accepted_htlcs,
+			locktime: 0,
+			funding_pubkey: open_channel.funding_pubkey,
+			revocation_basepoint: open_channel.revocation_basepoint,
+			payment_point: open_channel.payment_point,
+			delayed_payment_basepoint: open_channel.delayed_payment_basepoint,
+			htlc_basepoint: open_channel.htlc_basepoint,
+			first_per_commitment_point: open_channel.first_per_commitment_point,
+			second_per_commitment_point: self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number - 1, &self.secp_ctx),
+			channel_flags: open_channel.channel_flags,
+			shutdown_scriptpubkey: self.get_upfront_shutdown_scriptpubkey_v2(),
+		}
+	}
+
+	pub fn get_accept_channel_v2(&self) -> msgs::AcceptChannelV2 {
+		if self.interactive_tx_constructor.is_none() {
+			panic!("Tried to send accept_channel2 for a single-funded channel");
+		}
+		let accept_channel = self.get_accept_channel();
+
+		msgs::AcceptChannelV2 {
+			temporary_channel_id: self.channel_id,
+			funding_satoshis: self.value_to_self_msat / 1000,
+			dust_limit_satoshis: accept_channel.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: accept_channel.max_htlc_value_in_flight_msat,
+			htlc_minimum_msat: accept_channel.htlc_minimum_msat,
+			minimum_depth: accept_channel.minimum_depth,
+			to_self_delay: accept_channel.to_self_delay,
+			max_accepted_htlcs: accept_channel.max_accepted_htlcs,
+			funding_pubkey: accept_channel.funding_pubkey,
+			revocation_basepoint: accept_channel.revocation_basepoint,
+			payment_point: accept_channel.payment_point,
+			delayed_payment_basepoint: accept_channel.delayed_payment_basepoint,
+			htlc_basepoint: accept_channel.htlc_basepoint,
+			first_per_commitment_point: accept_channel.first_per_commitment_point,
+			second_per_commitment_point: self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number - 1, &self.secp_ctx),
+			shutdown_scriptpubkey: self.get_upfront_shutdown_scriptpubkey_v2(),
+		}
+	}
+
+	/// Gets the upfront shutdown script TLV of open_channel2 and accept_channel2, which is omitted
+	/// rather than set to a 0-length script when we don't commit to one.
+	fn get_upfront_shutdown_scriptpubkey_v2(&self) -> Option<Script> {
+		if self.config.commit_upfront_shutdown_pubkey { Some(self.get_closing_scriptpubkey()) } else { None }
+	}
+
 	#[inline]
 	/// Creates a set of keys for build_commitment_transaction to generate a transaction which we
 	/// will sign and send to our counterparty.
-	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created)
+	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created and
+	/// get_initial_commitment_signed)
 	fn build_remote_transaction_keys(&self) -> Result<TxCreationKeys, ChannelError> {
+		self.build_counterparty_transaction_keys(&self.counterparty_cur_commitment_point.unwrap())
+	}
+
+	#[inline]
+	fn build_counterparty_transaction_keys(&self, per_commitment_point: &PublicKey) -> Result<TxCreationKeys, ChannelError> {
 		//TODO: Ensure that the payment_key derived here ends up in the library users' wallet as we
 		//may see payments to it!
 		let revocation_basepoint = &self.get_holder_pubkeys().revocation_basepoint;
 		let htlc_basepoint = &self.get_holder_pubkeys().htlc_basepoint;
 		let counterparty_pubkeys = self.get_counterparty_pubkeys();
 
-		Ok(secp_check!(TxCreationKeys::derive_new(&self.secp_ctx, &self.counterparty_cur_commitment_point.unwrap(), &counterparty_pubkeys.delayed_payment_basepoint, &counterparty_pubkeys.htlc_basepoint, revocation_basepoint, htlc_basepoint), "Remote tx keys generation got bogus keys".to_owned()))
+		Ok(secp_check!(TxCreationKeys::derive_new(&self.secp_ctx, per_commitment_point, &counterparty_pubkeys.delayed_payment_basepoint, &counterparty_pubkeys.htlc_basepoint, revocation_basepoint, htlc_basepoint), "Remote tx keys generation got bogus keys".to_owned()))
 	}
 
 	/// Gets the redeemscript for the funding transaction output (ie the funding transaction output
@@ -1118,7 +1589,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// @local is used only to convert relevant internal structures which refer to remote vs local
 	/// to decide value of outputs and direction of HTLCs.
 	fn build_htlc_transaction(&self, prev_hash: &Txid, htlc: &HTLCOutputInCommitment, local: bool, keys: &TxCreationKeys, feerate_per_kw: u32) -> Transaction {
-		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.get_counterparty_selected_contest_delay() } else { self.get_holder_selected_contest_delay() }, htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key)
+		chan_utils::build_htlc_transaction(prev_hash, feerate_per_kw, if local { self.get_counterparty_selected_contest_delay() } else { self.get_holder_selected_contest_delay() }, htlc, self.channel_transaction_parameters.opt_anchors, &keys.broadcaster_delayed_payment_key, &keys.revocation_key)
 	}
 
 	/// Per HTLC, only one get_update_fail_htlc or get_update_fulfill_htlc call may be made.
@@ -1182,7 +1653,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}],
 		};
 
-		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 {
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 || self.is_quiescing() {
 			for pending_update in self.holding_cell_htlc_updates.iter() {
 				match pending_update {
 					&HTLCUpdateAwaitingACK::ClaimHTLC { htlc_id, .. } => {
@@ -1287,7 +1758,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 
 		// Now update local state:
-		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 {
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32)) != 0 || self.is_quiescing() {
 			for pending_update in self.holding_cell_htlc_updates.iter() {
 				match pending_update {
 					&HTLCUpdateAwaitingACK::ClaimHTLC { htlc_id, .. } => {
@@ -1334,62 +1805,86 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state != ChannelState::OurInitSent as u32 {
 			return Err(ChannelError::Close("Got an accept_channel message at a strange time".to_owned()));
 		}
-		if msg.dust_limit_satoshis > 21000000 * 100000000 {
-			return Err(ChannelError::Close(format!("Peer never wants payout outputs? dust_limit_satoshis was {}", msg.dust_limit_satoshis)));
+		if self.dual_funding_contribution.is_some() {
+			return Err(ChannelError::Close("Got an accept_channel message for a dual-funded channel".to_owned()));
+		}
+		self.accept_counterparty_params(AcceptedChannelParams {
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: msg.channel_reserve_satoshis,
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			minimum_depth: msg.minimum_depth,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			pubkeys: ChannelPublicKeys {
+				funding_pubkey: msg.funding_pubkey,
+				revocation_basepoint: msg.revocation_basepoint,
+				payment_point: msg.payment_point,
+				delayed_payment_basepoint: msg.delayed_payment_basepoint,
+				htlc_basepoint: msg.htlc_basepoint
+			},
+			first_per_commitment_point: msg.first_per_commitment_point,
+			shutdown_scriptpubkey: &msg.shutdown_scriptpubkey,
+		}, config, their_features)
+	}
+
+	fn accept_counterparty_params(&mut self, params: AcceptedChannelParams, config: &UserConfig, their_features: InitFeatures) -> Result<(), ChannelError> {
+		if params.dust_limit_satoshis > 21000000 * 100000000 {
+			return Err(ChannelError::Close(format!("Peer never wants payout outputs? dust_limit_satoshis was {}", params.dust_limit_satoshis)));
 		}
-		if msg.channel_reserve_satoshis > self.channel_value_satoshis {
-			return Err(ChannelError::Close(format!("Bogus channel_reserve_satoshis ({}). Must not be greater than ({})", msg.channel_reserve_satoshis, self.channel_value_satoshis)));
+		if params.channel_reserve_satoshis > self.channel_value_satoshis {
+			return Err(ChannelError::Close(format!("Bogus channel_reserve_satoshis ({}). Must not be greater than ({})", params.channel_reserve_satoshis, self.channel_value_satoshis)));
 		}
-		if msg.dust_limit_satoshis > msg.channel_reserve_satoshis {
-			return Err(ChannelError::Close(format!("Bogus channel_reserve ({}) and dust_limit ({})", msg.channel_reserve_satoshis, msg.dust_limit_satoshis)));
+		if params.dust_limit_satoshis > params.channel_reserve_satoshis {
+			return Err(ChannelError::Close(format!("Bogus channel_reserve ({}) and dust_limit ({})", params.channel_reserve_satoshis, params.dust_limit_satoshis)));
 		}
-		if msg.channel_reserve_satoshis < self.holder_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("Peer never wants payout outputs? channel_reserve_satoshis was ({}). dust_limit is ({})", msg.channel_reserve_satoshis, self.holder_dust_limit_satoshis)));
+		if params.channel_reserve_satoshis < self.holder_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("Peer never wants payout outputs? channel_reserve_satoshis was ({}). dust_limit is ({})", params.channel_reserve_satoshis, self.holder_dust_limit_satoshis)));
 		}
 		let remote_reserve = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(self.channel_value_satoshis);
-		if msg.dust_limit_satoshis > remote_reserve {
-			return Err(ChannelError::Close(format!("Dust limit ({}) is bigger than our channel reserve ({})", msg.dust_limit_satoshis, remote_reserve)));
+		if params.dust_limit_satoshis > remote_reserve {
+			return Err(ChannelError::Close(format!("Dust limit ({}) is bigger than our channel reserve ({})", params.dust_limit_satoshis, remote_reserve)));
 		}
-		let full_channel_value_msat = (self.channel_value_satoshis - msg.channel_reserve_satoshis) * 1000;
-		if msg.htlc_minimum_msat >= full_channel_value_msat {
-			return Err(ChannelError::Close(format!("Minimum htlc value ({}) is full channel value ({})", msg.htlc_minimum_msat, full_channel_value_msat)));
+		let full_channel_value_msat = (self.channel_value_satoshis - params.channel_reserve_satoshis) * 1000;
+		if params.htlc_minimum_msat >= full_channel_value_msat {
+			return Err(ChannelError::Close(format!("Minimum htlc value ({}) is full channel value ({})", params.htlc_minimum_msat, full_channel_value_msat)));
 		}
 		let max_delay_acceptable = u16::min(config.peer_channel_config_limits.their_to_self_delay, MAX_LOCAL_BREAKDOWN_TIMEOUT);
-		if msg.to_self_delay > max_delay_acceptable {
-			return Err(ChannelError::Close(format!("They wanted our payments to be delayed by a needlessly long period. Upper limit: {}. Actual: {}", max_delay_acceptable, msg.to_self_delay)));
+		if params.to_self_delay > max_delay_acceptable {
+			return Err(ChannelError::Close(format!("They wanted our payments to be delayed by a needlessly long period. Upper limit: {}. Actual: {}", max_delay_acceptable, params.to_self_delay)));
 		}
-		if msg.max_accepted_htlcs < 1 {
+		if params.max_accepted_htlcs < 1 {
 			return Err(ChannelError::Close("0 max_accepted_htlcs makes for a useless channel".to_owned()));
 		}
-		if msg.max_accepted_htlcs > MAX_HTLCS {
-			return Err(ChannelError::Close(format!("max_accepted_htlcs was {}. It must not be larger than {}", msg.max_accepted_htlcs, MAX_HTLCS)));
+		if params.max_accepted_htlcs > MAX_HTLCS {
+			return Err(ChannelError::Close(format!("max_accepted_htlcs was {}. It must not be larger than {}", params.max_accepted_htlcs, MAX_HTLCS)));
 		}
 
 		// Now check against optional parameters as set by config...
-		if msg.htlc_minimum_msat > config.peer_channel_config_limits.max_htlc_minimum_msat {
-			return Err(ChannelError::Close(format!("htlc_minimum_msat ({}) is higher than the user specified limit ({})", msg.htlc_minimum_msat, config.peer_channel_config_limits.max_htlc_minimum_msat)));
+		if params.htlc_minimum_msat > config.peer_channel_config_limits.max_htlc_minimum_msat {
+			return Err(ChannelError::Close(format!("htlc_minimum_msat ({}) is higher than the user specified limit ({})", params.htlc_minimum_msat, config.peer_channel_config_limits.max_htlc_minimum_msat)));
 		}
-		if msg.max_htlc_value_in_flight_msat < config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat {
-			return Err(ChannelError::Close(format!("max_htlc_value_in_flight_msat ({}) is less than the user specified limit ({})", msg.max_htlc_value_in_flight_msat, config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat)));
+		if params.max_htlc_value_in_flight_msat < config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat {
+			return Err(ChannelError::Close(format!("max_htlc_value_in_flight_msat ({}) is less than the user specified limit ({})", params.max_htlc_value_in_flight_msat, config.peer_channel_config_limits.min_max_htlc_value_in_flight_msat)));
 		}
-		if msg.channel_reserve_satoshis > config.peer_channel_config_limits.max_channel_reserve_satoshis {
-			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is higher than the user specified limit ({})", msg.channel_reserve_satoshis, config.peer_channel_config_limits.max_channel_reserve_satoshis)));
+		if params.channel_reserve_satoshis > config.peer_channel_config_limits.max_channel_reserve_satoshis {
+			return Err(ChannelError::Close(format!("channel_reserve_satoshis ({}) is higher than the user specified limit ({})", params.channel_reserve_satoshis, config.peer_channel_config_limits.max_channel_reserve_satoshis)));
 		}
-		if msg.max_accepted_htlcs < config.peer_channel_config_limits.min_max_accepted_htlcs {
-			return Err(ChannelError::Close(format!("max_accepted_htlcs ({}) is less than the user specified limit ({})", msg.max_accepted_htlcs, config.peer_channel_config_limits.min_max_accepted_htlcs)));
+		if params.max_accepted_htlcs < config.peer_channel_config_limits.min_max_accepted_htlcs {
+			return Err(ChannelError::Close(format!("max_accepted_htlcs ({}) is less than the user specified limit ({})", params.max_accepted_htlcs, config.peer_channel_config_limits.min_max_accepted_htlcs)));
 		}
-		if msg.dust_limit_satoshis < config.peer_channel_config_limits.min_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is less than the user specified limit ({})", msg.dust_limit_satoshis, config.peer_channel_config_limits.min_dust_limit_satoshis)));
+		if params.dust_limit_satoshis < config.peer_channel_config_limits.min_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is less than the user specified limit ({})", params.dust_limit_satoshis, config.peer_channel_config_limits.min_dust_limit_satoshis)));
 		}
-		if msg.dust_limit_satoshis > config.peer_channel_config_limits.max_dust_limit_satoshis {
-			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is greater than the user specified limit ({})", msg.dust_limit_satoshis, config.peer_channel_config_limits.max_dust_limit_satoshis)));
+		if params.dust_limit_satoshis > config.peer_channel_config_limits.max_dust_limit_satoshis {
+			return Err(ChannelError::Close(format!("dust_limit_satoshis ({}) is greater than the user specified limit ({})", params.dust_limit_satoshis, config.peer_channel_config_limits.max_dust_limit_satoshis)));
 		}
-		if msg.minimum_depth > config.peer_channel_config_limits.max_minimum_depth {
-			return Err(ChannelError::Close(format!("We consider the minimum depth to be unreasonably large. Expected minimum: ({}). Actual: ({})", config.peer_channel_config_limits.max_minimum_depth, msg.minimum_depth)));
+		if params.minimum_depth > config.peer_channel_config_limits.max_minimum_depth {
+			return Err(ChannelError::Close(format!("We consider the minimum depth to be unreasonably large. Expected minimum: ({}). Actual: ({})", config.peer_channel_config_limits.max_minimum_depth, params.minimum_depth)));
 		}
 
 		let counterparty_shutdown_scriptpubkey = if their_features.supports_upfront_shutdown_script() {
-			match &msg.shutdown_scriptpubkey {
+			match params.shutdown_scriptpubkey {
 				&OptionalField::Present(ref script) => {
 					// Peer is signaling upfront_shutdown and has provided a non-accepted scriptpubkey format. We enforce it while receiving shutdown msg
 					if script.is_p2pkh() || script.is_p2sh() || script.is_v0_p2wsh() || script.is_v0_p2wpkh() {
@@ -1409,27 +1904,22 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		} else { None };
 
-		self.counterparty_dust_limit_satoshis = msg.dust_limit_satoshis;
-		self.counterparty_max_htlc_value_in_flight_msat = cmp::min(msg.max_htlc_value_in_flight_msat, self.channel_value_satoshis * 1000);
-		self.counterparty_selected_channel_reserve_satoshis = msg.channel_reserve_satoshis;
-		self.counterparty_htlc_minimum_msat = msg.htlc_minimum_msat;
-		self.counterparty_max_accepted_htlcs = msg.max_accepted_htlcs;
-		self.minimum_depth = msg.minimum_depth;
+		let opt_anchors = Channel::<ChanSigner>::negotiate_anchor_outputs(config, &their_features)?;
 
-		let counterparty_pubkeys = ChannelPublicKeys {
-			funding_pubkey: msg.funding_pubkey,
-			revocation_basepoint: msg.revocation_basepoint,
-			payment_point: msg.payment_point,
-			delayed_payment_basepoint: msg.delayed_payment_basepoint,
-			htlc_basepoint: msg.htlc_basepoint
-		};
+		self.counterparty_dust_limit_satoshis = params.dust_limit_satoshis;
+		self.counterparty_max_htlc_value_in_flight_msat = cmp::min(params.max_htlc_value_in_flight_msat, self.channel_value_satoshis * 1000);
+		self.counterparty_selected_channel_reserve_satoshis = params.channel_reserve_satoshis;
+		self.counterparty_htlc_minimum_msat = params.htlc_minimum_msat;
+		self.counterparty_max_accepted_htlcs = params.max_accepted_htlcs;
+		self.minimum_depth = params.minimum_depth;
 
 		self.channel_transaction_parameters.counterparty_parameters = Some(CounterpartyChannelTransactionParameters {
-			selected_contest_delay: msg.to_self_delay,
-			pubkeys: counterparty_pubkeys,
+			selected_contest_delay: params.to_self_delay,
+			pubkeys: params.pubkeys,
 		});
+		self.channel_transaction_parameters.opt_anchors = opt_anchors;
 
-		self.counterparty_cur_commitment_point = Some(msg.first_per_commitment_point);
+		self.counterparty_cur_commitment_point = Some(params.first_per_commitment_point);
 		self.counterparty_shutdown_scriptpubkey = counterparty_shutdown_scriptpubkey;
 
 		self.channel_state = ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32;
@@ -1437,6 +1927,137 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(())
 	}
 
+	/// Handles an accept_channel2 message for a dual-funded channel we initiated, returning the
+	/// first message of the funding transaction construction.
+	pub fn accept_channel_v2(&mut self, msg: &msgs::AcceptChannelV2, config: &UserConfig, their_features: InitFeatures) -> Result<InteractiveTxMessage, ChannelError> {
+		if !self.is_outbound() {
+			return Err(ChannelError::Close("Got an accept_channel2 message from an inbound peer".to_owned()));
+		}
+		if self.channel_state != ChannelState::OurInitSent as u32 {
+			return Err(ChannelError::Close("Got an accept_channel2 message at a strange time".to_owned()));
+		}
+		let contribution = match self.dual_funding_contribution.take() {
+			Some(contribution) => contribution,
+			None => return Err(ChannelError::Close("Got an accept_channel2 message for a single-funded channel".to_owned())),
+		};
+		if msg.funding_satoshis > self.max_funding_satoshis - self.channel_value_satoshis {
+			return Err(ChannelError::Close(format!("Peer's contribution of {} sat would make the channel value larger than {}", msg.funding_satoshis, self.max_funding_satoshis)));
+		}
+		self.channel_value_satoshis += msg.funding_satoshis;
+		self.channel_transaction_parameters.channel_value_satoshis = self.channel_value_satoshis;
+
+		// Omitting the upfront shutdown script opts out of it, like a 0-length script in accept_channel
+		let shutdown_scriptpubkey = OptionalField::Present(msg.shutdown_scriptpubkey.clone().unwrap_or_else(Script::new));
+		self.accept_counterparty_params(AcceptedChannelParams {
+			dust_limit_satoshis: msg.dust_limit_satoshis,
+			max_htlc_value_in_flight_msat: msg.max_htlc_value_in_flight_msat,
+			channel_reserve_satoshis: Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(self.channel_value_satoshis),
+			htlc_minimum_msat: msg.htlc_minimum_msat,
+			minimum_depth: msg.minimum_depth,
+			to_self_delay: msg.to_self_delay,
+			max_accepted_htlcs: msg.max_accepted_htlcs,
+			pubkeys: ChannelPublicKeys {
+				funding_pubkey: msg.funding_pubkey,
+				revocation_basepoint: msg.revocation_basepoint,
+				payment_point: msg.payment_point,
+				delayed_payment_basepoint: msg.delayed_payment_basepoint,
+				htlc_basepoint: msg.htlc_basepoint
+			},
+			first_per_commitment_point: msg.first_per_commitment_point,
+			shutdown_scriptpubkey: &shutdown_scriptpubkey,
+		}, config, their_features)?;
+
+		let funding_script = self.get_funding_redeemscript().to_v0_p2wsh();
+		let mut constructor = InteractiveTxConstructor::new(self.channel_id, true, 0, funding_script, self.channel_value_satoshis, msg.funding_satoshis as i64, None, contribution);
+		let first_msg = constructor.next_message();
+		self.interactive_tx_constructor = Some(constructor);
+		Ok(first_msg)
+	}
+
+	/// Handles a tx_add_input message, returning our reply, if any, and our commitment_signed for
+	/// the counterparty's commitment transaction spending the new funding output once the funding
+	/// or splice transaction is constructed. When splicing, our reply may instead be a tx_abort if
+	/// our counterparty made an invalid contribution.
+	pub fn tx_add_input<L: Deref>(&mut self, msg: &msgs::TxAddInput, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = match self.interactive_tx_constructor {
+			Some(ref mut constructor) => constructor.handle_tx_add_input(msg),
+			None => return Err(ChannelError::Close("Got a tx_add_input message while not constructing a funding transaction".to_owned())),
+		};
+		self.interactive_tx_progress(res, logger)
+	}
+
+	/// Handles a tx_add_output message, see tx_add_input.
+	pub fn tx_add_output<L: Deref>(&mut self, msg: &msgs::TxAddOutput, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = match self.interactive_tx_constructor {
+			Some(ref mut constructor) => constructor.handle_tx_add_output(msg),
+			None => return Err(ChannelError::Close("Got a tx_add_output message while not constructing a funding transaction".to_owned())),
+		};
+		self.interactive_tx_progress(res, logger)
+	}
+
+	/// Handles a tx_remove_input message, see tx_add_input.
+	pub fn tx_remove_input<L: Deref>(&mut self, msg: &msgs::TxRemoveInput, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = match self.interactive_tx_constructor {
+			Some(ref mut constructor) => constructor.handle_tx_remove_input(msg),
+			None => return Err(ChannelError::Close("Got a tx_remove_input message while not constructing a funding transaction".to_owned())),
+		};
+		self.interactive_tx_progress(res, logger)
+	}
+
+	/// Handles a tx_remove_output message, see tx_add_input.
+	pub fn tx_remove_output<L: Deref>(&mut self, msg: &msgs::TxRemoveOutput, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = match self.interactive_tx_constructor {
+			Some(ref mut constructor) => constructor.handle_tx_remove_output(msg),
+			None => return Err(ChannelError::Close("Got a tx_remove_output message while not constructing a funding transaction".to_owned())),
+		};
+		self.interactive_tx_progress(res, logger)
+	}
+
+	/// Handles a tx_complete message, see tx_add_input.
+	pub fn tx_complete<L: Deref>(&mut self, msg: &msgs::TxComplete, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		let res = match self.interactive_tx_constructor {
+			Some(ref mut constructor) => constructor.handle_tx_complete(msg),
+			None => return Err(ChannelError::Close("Got a tx_complete message while not constructing a funding transaction".to_owned())),
+		};
+		self.interactive_tx_progress(res, logger)
+	}
+
+	fn interactive_tx_progress<L: Deref>(&mut self, res: Result<(Option<InteractiveTxMessage>, bool), String>, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		if self.inbound_awaiting_accept {
+			return Err(ChannelError::Close("Got an interactive transaction construction message before we accepted the channel".to_owned()));
+		}
+		let (msg, complete) = match res {
+			Ok(res) => res,
+			Err(e) => return self.fail_interactive_tx(e, logger),
+		};
+		if !complete {
+			return Ok((msg, None));
+		}
+		let funding_tx_signing = match self.interactive_tx_constructor.take().unwrap().finish() {
+			Ok(funding_tx_signing) => funding_tx_signing,
+			Err(e) => return self.fail_interactive_tx(e, logger),
+		};
+		let funding_txo = OutPoint { txid: funding_tx_signing.tx.txid(), index: funding_tx_signing.funding_output_index };
+		self.funding_tx_signing = Some(funding_tx_signing);
+		if self.pending_splice.is_some() {
+			log_info!(logger, "Completed construction of splice transaction {} for channel {}", funding_txo.txid, log_bytes!(self.channel_id()));
+			return Ok((msg, Some(self.splice_constructed(funding_txo, logger)?)));
+		}
+		log_info!(logger, "Completed construction of funding transaction {} for channel {}", funding_txo.txid, log_bytes!(self.channel_id()));
+		Ok((msg, Some(self.get_initial_commitment_signed(funding_txo, logger)?)))
+	}
+
+	/// Fails the construction of a funding transaction, which closes a channel pending funding but
+	/// only aborts a splice, telling our counterparty with tx_abort.
+	fn fail_interactive_tx<L: Deref>(&mut self, err: String, logger: &L) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError> where L::Target: Logger {
+		if self.pending_splice.is_none() {
+			return Err(ChannelError::Close(err));
+		}
+		log_info!(logger, "Aborting splice of channel {}: {}", log_bytes!(self.channel_id()), err);
+		self.abort_splice();
+		Ok((Some(InteractiveTxMessage::Abort(msgs::TxAbort { channel_id: self.channel_id, data: err })), None))
+	}
+
 	fn funding_created_signature<L: Deref>(&mut self, sig: &Signature, logger: &L) -> Result<(Txid, CommitmentTransaction, Signature), ChannelError> where L::Target: Logger {
 		let funding_script = self.get_funding_redeemscript();
 
@@ -1473,6 +2094,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.is_outbound() {
 			return Err(ChannelError::Close("Received funding_created for an outbound channel?".to_owned()));
 		}
+		if self.inbound_awaiting_accept {
+			return Err(ChannelError::Close("Received funding_created before we accepted the channel".to_owned()));
+		}
 		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
 			// BOLT 2 says that if we disconnect before we send funding_signed we SHOULD NOT
 			// remember the channel, so it's safe to just send an error_message here and drop the
@@ -1485,6 +2109,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			panic!("Should not have advanced channel commitment tx numbers prior to funding_created");
 		}
 
+		if self.interactive_tx_constructor.is_some() || self.funding_tx_signing.is_some() {
+			return Err(ChannelError::Close("Received funding_created for a dual-funded channel".to_owned()));
+		}
+
 		let funding_txo = OutPoint { txid: msg.funding_txid, index: msg.funding_output_index };
 		self.channel_transaction_parameters.funding_outpoint = Some(funding_txo);
 		// This is an externally observable change before we finish all our checks.  In particular
@@ -1547,6 +2175,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & !(ChannelState::MonitorUpdateFailed as u32) != ChannelState::FundingCreated as u32 {
 			return Err(ChannelError::Close("Received funding_signed in strange state!".to_owned()));
 		}
+		if self.funding_tx_signing.is_some() {
+			return Err(ChannelError::Close("Received funding_signed for a dual-funded channel".to_owned()));
+		}
 		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
 				self.cur_counterparty_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
 				self.cur_holder_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
@@ -1605,10 +2236,628 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		Ok(channel_monitor)
 	}
 
+	/// Called once the funding transaction of a dual-funded channel is constructed, returning our
+	/// commitment_signed for the counterparty's initial commitment transaction.
+	/// Note that channel_id changes during this call!
+	/// If an Err is returned, it is a ChannelError::Close.
+	fn get_initial_commitment_signed<L: Deref>(&mut self, funding_txo: OutPoint, logger: &L) -> Result<msgs::CommitmentSigned, ChannelError> where L::Target: Logger {
+		if self.channel_state != (ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32) {
+			return Err(ChannelError::Close("Constructed a funding transaction at a time other than immediately after initial handshake completion".to_owned()));
+		}
+		if self.commitment_secrets.get_min_seen_secret() != (1 << 48) ||
+				self.cur_counterparty_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER ||
+				self.cur_holder_commitment_transaction_number != INITIAL_COMMITMENT_NUMBER {
+			panic!("Should not have advanced channel commitment tx numbers prior to commitment_signed");
+		}
+
+		self.channel_transaction_parameters.funding_outpoint = Some(funding_txo);
+		self.holder_keys.ready_channel(&self.channel_transaction_parameters);
+
+		let signature = match self.get_outbound_funding_created_signature(logger) {
+			Ok(res) => res,
+			Err(e) => {
+				log_error!(logger, "Got bad signatures: {:?}!", e);
+				self.channel_transaction_parameters.funding_outpoint = None;
+				return Err(e);
+			}
+		};
+
+		self.channel_state = ChannelState::FundingCreated as u32;
+		self.channel_id = funding_txo.to_channel_id();
+
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures: Vec::new(),
+		})
+	}
+
+	/// Returns true if we constructed the funding transaction of a dual-funded channel and are
+	/// waiting for our counterparty's commitment_signed for our initial commitment transaction.
+	pub fn is_awaiting_initial_commitment_signed(&self) -> bool {
+		self.funding_tx_signing.is_some() && self.pending_splice.is_none() &&
+			self.channel_state == ChannelState::FundingCreated as u32
+	}
+
+	/// Handles the commitment_signed message for our initial commitment transaction of a
+	/// dual-funded channel, returning the ChannelMonitor to watch. We only sign the funding
+	/// transaction once the ChannelMonitor is persisted.
+	pub fn initial_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<ChannelMonitor<ChanSigner>, ChannelError> where L::Target: Logger {
+		if !self.is_awaiting_initial_commitment_signed() {
+			return Err(ChannelError::Close("Received initial commitment_signed in strange state!".to_owned()));
+		}
+		if !msg.htlc_signatures.is_empty() {
+			return Err(ChannelError::Close("Received HTLC signatures for an initial commitment transaction".to_owned()));
+		}
+
+		let funding_script = self.get_funding_redeemscript();
+
+		let counterparty_keys = self.build_remote_transaction_keys()?;
+		let counterparty_initial_commitment_tx = self.build_commitment_transaction(self.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, false, self.feerate_per_kw, logger).0;
+		let counterparty_initial_commitment_txid = counterparty_initial_commitment_tx.trust().txid();
+
+		let holder_keys = self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number)?;
+		let initial_commitment_tx = self.build_commitment_transaction(self.cur_holder_commitment_transaction_number, &holder_keys, true, false, self.feerate_per_kw, logger).0;
+		{
+			let trusted_tx = initial_commitment_tx.trust();
+			let initial_commitment_bitcoin_tx = trusted_tx.built_transaction();
+			let sighash = initial_commitment_bitcoin_tx.get_sighash_all(&funding_script, self.channel_value_satoshis);
+			log_trace!(logger, "Checking initial commitment_signed tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.signature.serialize_compact()[..]), log_bytes!(self.counterparty_funding_pubkey().serialize()), encode::serialize_hex(&initial_commitment_bitcoin_tx.transaction), log_bytes!(sighash[..]), encode::serialize_hex(&funding_script));
+			secp_check!(self.secp_ctx.verify(&sighash, &msg.signature, self.counterparty_funding_pubkey()), "Invalid commitment tx signature from peer".to_owned());
+		}
+
+		let holder_commitment_tx = HolderCommitmentTransaction::new(
+			initial_commitment_tx,
+			msg.signature,
+			Vec::new(),
+			&self.get_holder_pubkeys().funding_pubkey,
+			self.counterparty_funding_pubkey()
+		);
+
+		let funding_txo = self.get_funding_txo().unwrap();
+		let funding_txo_script = funding_script.to_v0_p2wsh();
+		let obscure_factor = get_commitment_transaction_number_obscure_factor(&self.get_holder_pubkeys().payment_point, &self.get_counterparty_pubkeys().payment_point, self.is_outbound());
+		let mut channel_monitor = ChannelMonitor::new(self.holder_keys.clone(),
+		                                              &self.shutdown_pubkey, self.get_holder_selected_contest_delay(),
+		                                              &self.destination_script, (funding_txo, funding_txo_script),
+		                                              &self.channel_transaction_parameters,
+		                                              funding_script, self.channel_value_satoshis,
+		                                              obscure_factor,
+		                                              holder_commitment_tx);
+
+		channel_monitor.provide_latest_counterparty_commitment_tx(counterparty_initial_commitment_txid, Vec::new(), self.cur_counterparty_commitment_transaction_number, self.counterparty_cur_commitment_point.unwrap(), logger);
+		let funding_tx_inputs = self.funding_tx_signing.as_ref().unwrap().tx.input.iter().map(|input| input.previous_output).collect();
+		channel_monitor.provide_funding_tx_inputs(funding_tx_inputs);
+
+		self.channel_state = ChannelState::FundingSent as u32;
+		self.cur_holder_commitment_transaction_number -= 1;
+		self.cur_counterparty_commitment_transaction_number -= 1;
+
+		Ok(channel_monitor)
+	}
+
+	/// Returns true if this is a dual-funded channel whose funding transaction is not yet
+	/// constructed.
+	pub fn is_dual_funded_pending_funding(&self) -> bool {
+		self.dual_funding_contribution.is_some() || self.interactive_tx_constructor.is_some()
+	}
+
+	/// Handles a tx_signatures message, filling in our counterparty's witnesses on the funding
+	/// transaction. maybe_sign_funding_tx should be called afterwards to reply with our own.
+	pub fn tx_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(), ChannelError> {
+		if self.channel_state < ChannelState::FundingSent as u32 || self.is_awaiting_splice_commitment_signed() {
+			return Err(ChannelError::Close("Got a tx_signatures message before exchanging initial commitment signatures".to_owned()));
+		}
+		if let Some(ref funding_tx_signing) = self.funding_tx_signing {
+			if let (Some(idx), Some(ref sig)) = (funding_tx_signing.shared_input_index(), msg.shared_input_signature) {
+				let sighash = hash_to_message!(&bip143::SigHashCache::new(&funding_tx_signing.tx).signature_hash(idx, &self.get_funding_redeemscript(), self.channel_value_satoshis, SigHashType::All)[..]);
+				secp_check!(self.secp_ctx.verify(&sighash, sig, self.counterparty_funding_pubkey()), "Invalid signature for the shared input of the splice transaction from peer".to_owned());
+			}
+		}
+		match self.funding_tx_signing {
+			Some(ref mut funding_tx_signing) => funding_tx_signing.provide_counterparty_signatures(msg).map_err(ChannelError::Close),
+			None => Err(ChannelError::Ignore("Got a tx_signatures message for a channel whose funding transaction is already signed".to_owned())),
+		}
+	}
+
+	/// Signs our inputs to the funding transaction of a dual-funded channel or to a splice
+	/// transaction once it's our turn to, returning the tx_signatures to send, if any, and the
+	/// transaction to broadcast once it has been signed by both peers.
+	pub fn maybe_sign_funding_tx<K: Deref>(&mut self, keys_provider: &K) -> Result<(Option<msgs::TxSignatures>, Option<Transaction>), ChannelError> where K::Target: KeysInterface<ChanKeySigner = ChanSigner> {
+		// Only sign away our inputs once we have a persisted ChannelMonitor to claim the funds we
+		// put in the channel.
+		if self.channel_state < ChannelState::FundingSent as u32 || self.channel_state & (ChannelState::MonitorUpdateFailed as u32) != 0 {
+			return Ok((None, None));
+		}
+		// Similarly, only sign away the current funding output once our ChannelMonitor has the
+		// commitment transaction spending the new one.
+		if self.is_awaiting_splice_commitment_signed() {
+			return Ok((None, None));
+		}
+		let mut funding_tx_signing = match self.funding_tx_signing.take() {
+			Some(funding_tx_signing) => funding_tx_signing,
+			None => return Ok((None, None)),
+		};
+		let mut tx_signatures = None;
+		if funding_tx_signing.should_sign() {
+			let shared_input_signature = match funding_tx_signing.shared_input_index() {
+				Some(idx) => Some(self.holder_keys.sign_splice_funding_input(&funding_tx_signing.tx, idx, &self.secp_ctx)
+					.map_err(|_| ChannelError::Close("Failed to sign the shared input of the splice transaction".to_owned()))?),
+				None => None,
+			};
+			funding_tx_signing.sign(keys_provider.get_utxo_source(), shared_input_signature).map_err(ChannelError::Close)?;
+			tx_signatures = funding_tx_signing.get_tx_signatures(self.channel_id);
+		}
+		if funding_tx_signing.is_fully_signed() {
+			let shared_input = funding_tx_signing.shared_input_index().map(|idx| (idx, funding_tx_signing.shared_input_signatures().unwrap()));
+			let mut tx = funding_tx_signing.tx;
+			if let Some((idx, (holder_sig, counterparty_sig))) = shared_input {
+				tx.input[idx].witness = self.build_funding_witness(&counterparty_sig, &holder_sig);
+			}
+			match self.pending_splice {
+				Some(ref mut splice) => splice.funding_transaction = Some(tx.clone()),
+				None => self.funding_transaction = Some(tx.clone()),
+			}
+			return Ok((tx_signatures, Some(tx)));
+		}
+		self.funding_tx_signing = Some(funding_tx_signing);
+		Ok((tx_signatures, None))
+	}
+
+	/// Gets the tx_signatures to send again on reconnection if we signed the funding transaction
+	/// but didn't get our counterparty's signatures yet.
+	pub fn get_funding_tx_signatures_to_resend(&self) -> Option<msgs::TxSignatures> {
+		match self.funding_tx_signing {
+			Some(ref funding_tx_signing) => funding_tx_signing.get_tx_signatures(self.channel_id),
+			None => None,
+		}
+	}
+
+	/// Returns true if we want to, or already did, stop updating the channel for a splice, in
+	/// which case new HTLC and fee updates are kept in the holding cell.
+	fn is_quiescing(&self) -> bool {
+		self.splice_contribution.is_some() || self.sent_stfu || self.received_stfu || self.pending_splice.is_some()
+	}
+
+	/// Returns true if any HTLC or fee update is not yet irrevocably committed by both peers, in
+	/// which case we can't send stfu.
+	fn has_pending_updates(&self) -> bool {
+		if self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::MonitorUpdateFailed as u32) != 0 ||
+				self.pending_update_fee.is_some() {
+			return true;
+		}
+		self.pending_inbound_htlcs.iter().any(|htlc| if let InboundHTLCState::Committed = htlc.state { false } else { true }) ||
+			self.pending_outbound_htlcs.iter().any(|htlc| if let OutboundHTLCState::Committed = htlc.state { false } else { true })
+	}
+
+	fn end_quiescence(&mut self) {
+		self.sent_stfu = false;
+		self.received_stfu = false;
+		self.holder_is_quiescence_initiator = false;
+	}
+
+	/// Aborts the splice being negotiated, if any, and ends quiescence. Returns true if there was
+	/// anything to abort.
+	fn abort_splice(&mut self) -> bool {
+		let was_quiescing = self.sent_stfu || self.received_stfu || self.pending_splice.is_some();
+		if self.pending_splice.take().is_some() {
+			self.interactive_tx_constructor = None;
+			self.funding_tx_signing = None;
+			#[cfg(debug_assertions)]
+			{
+				// We may have built commitment transactions spending the new funding output.
+				*self.holder_max_commitment_tx_output.lock().unwrap() = (0, 0);
+				*self.counterparty_max_commitment_tx_output.lock().unwrap() = (0, 0);
+			}
+		}
+		self.end_quiescence();
+		was_quiescing
+	}
+
+	/// Checks that both peers can afford a splice changing their balances by the given relative
+	/// amounts, returning the new channel value. Pending HTLCs are not counted in either balance,
+	/// and the channel funder pays for the commitment transaction with all of them.
+	fn check_splice_balances(&self, holder_relative_satoshis: i64, counterparty_relative_satoshis: i64) -> Result<u64, String> {
+		let new_value_satoshis = self.channel_value_satoshis as i64 + holder_relative_satoshis + counterparty_relative_satoshis;
+		if new_value_satoshis <= 0 || new_value_satoshis as u64 > self.max_funding_satoshis {
+			return Err(format!("Splice would make the channel value {} sat", new_value_satoshis));
+		}
+		let new_value_satoshis = new_value_satoshis as u64;
+
+		let outbound_htlcs_msat: u64 = self.pending_outbound_htlcs.iter().map(|htlc| htlc.amount_msat).sum();
+		let inbound_htlcs_msat: u64 = self.pending_inbound_htlcs.iter().map(|htlc| htlc.amount_msat).sum();
+		let commit_tx_fee_msat = self.commit_tx_fee_msat(self.pending_outbound_htlcs.len() + self.pending_inbound_htlcs.len()) as i64;
+		let mut holder_balance_msat = self.value_to_self_msat as i64 - outbound_htlcs_msat as i64 + holder_relative_satoshis * 1000;
+		let mut counterparty_balance_msat = (self.channel_value_satoshis * 1000 - self.value_to_self_msat) as i64 - inbound_htlcs_msat as i64 + counterparty_relative_satoshis * 1000;
+		if self.is_outbound() {
+			holder_balance_msat -= commit_tx_fee_msat;
+		} else {
+			counterparty_balance_msat -= commit_tx_fee_msat;
+		}
+
+		// A peer may only take funds out of the channel if it keeps its reserve, which for our
+		// counterparty we select based on the new channel value.
+		if holder_relative_satoshis < 0 && holder_balance_msat < self.counterparty_selected_channel_reserve_satoshis as i64 * 1000 {
+			return Err(format!("Splicing out {} sat would take our balance below the channel reserve", -holder_relative_satoshis));
+		}
+		let counterparty_reserve_msat = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(new_value_satoshis) as i64 * 1000;
+		if counterparty_relative_satoshis < 0 && counterparty_balance_msat < counterparty_reserve_msat {
+			return Err(format!("Splicing out {} sat would take the peer's balance below the channel reserve", -counterparty_relative_satoshis));
+		}
+		Ok(new_value_satoshis)
+	}
+
+	/// Gets the current funding output as the shared input of a splice transaction.
+	fn get_shared_funding_input(&self) -> Result<WalletUtxo, String> {
+		match self.funding_transaction {
+			Some(ref funding_transaction) => Ok(WalletUtxo {
+				prev_tx: funding_transaction.clone(),
+				prev_vout: self.get_current_funding_txo().unwrap().index as u32,
+				satisfaction_weight: SHARED_INPUT_WITNESS_WEIGHT,
+			}),
+			None => Err("The funding transaction of the channel was not seen yet".to_owned()),
+		}
+	}
+
+	fn check_can_splice(&self, holder_relative_satoshis: i64) -> Result<(), APIError> {
+		if !self.is_usable() {
+			return Err(APIError::ChannelUnavailable { err: "Cannot splice a channel which is not funded or is shutting down".to_owned() });
+		}
+		if self.is_quiescing() {
+			return Err(APIError::ChannelUnavailable { err: "A splice is already in progress on this channel".to_owned() });
+		}
+		if self.funding_transaction.is_none() {
+			return Err(APIError::ChannelUnavailable { err: "Cannot splice a channel whose funding transaction was not seen yet".to_owned() });
+		}
+		self.check_splice_balances(holder_relative_satoshis, 0).map_err(|err| APIError::APIMisuseError { err })?;
+		Ok(())
+	}
+
+	/// Starts splicing value_satoshis from the KeysInterface's funding wallet into the channel.
+	/// The splice itself starts once the channel is quiescent, see maybe_send_splice_msg.
+	pub fn splice_in<K: Deref, F: Deref>(&mut self, value_satoshis: u64, fee_estimator: &F, keys_provider: &K) -> Result<(), APIError>
+	where K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+	      F::Target: FeeEstimator,
+	{
+		if value_satoshis > self.max_funding_satoshis {
+			return Err(APIError::APIMisuseError { err: format!("Cannot splice in {} sat, which is more than the maximum channel value", value_satoshis) });
+		}
+		self.check_can_splice(value_satoshis as i64)?;
+		let wallet = match keys_provider.get_utxo_source() {
+			Some(wallet) => wallet,
+			None => return Err(APIError::APIMisuseError { err: "Splicing in requires a funding wallet".to_owned() }),
+		};
+		let feerate_per_kw = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		self.splice_contribution = Some(FundingContribution::splice_in(wallet, value_satoshis, feerate_per_kw)
+			.map_err(|err| APIError::APIMisuseError { err })?);
+		Ok(())
+	}
+
+	/// Starts splicing value_satoshis out of our channel balance to script_pubkey, our balance
+	/// also paying for the splice transaction. The splice itself starts once the channel is
+	/// quiescent, see maybe_send_splice_msg.
+	pub fn splice_out<F: Deref>(&mut self, value_satoshis: u64, script_pubkey: Script, fee_estimator: &F) -> Result<(), APIError>
+	where F::Target: FeeEstimator
+	{
+		if value_satoshis > self.max_funding_satoshis {
+			return Err(APIError::APIMisuseError { err: format!("Cannot splice out {} sat, which is more than the maximum channel value", value_satoshis) });
+		}
+		let feerate_per_kw = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+		let contribution = FundingContribution::splice_out(value_satoshis, script_pubkey, feerate_per_kw)
+			.map_err(|err| APIError::APIMisuseError { err })?;
+		self.check_can_splice(contribution.relative_satoshis())?;
+		self.splice_contribution = Some(contribution);
+		Ok(())
+	}
+
+	/// Gets the next message to send to make progress towards a splice, if any: stfu once the
+	/// channel is quiescent and either we want to splice or our counterparty sent stfu, then
+	/// splice once both peers sent stfu, if we're the quiescence initiator.
+	pub fn maybe_send_splice_msg<L: Deref>(&mut self, logger: &L) -> Option<SpliceMessage> where L::Target: Logger {
+		if !self.is_live() || self.pending_splice.is_some() {
+			return None;
+		}
+		if !self.sent_stfu {
+			if (self.splice_contribution.is_none() && !self.received_stfu) || self.has_pending_updates() {
+				return None;
+			}
+			self.sent_stfu = true;
+			self.holder_is_quiescence_initiator = !self.received_stfu;
+			log_debug!(logger, "Channel {} is quiescent, sending stfu", log_bytes!(self.channel_id()));
+			return Some(SpliceMessage::Stfu(msgs::Stfu {
+				channel_id: self.channel_id,
+				initiator: if self.holder_is_quiescence_initiator { 1 } else { 0 },
+			}));
+		}
+		if !self.received_stfu || !self.holder_is_quiescence_initiator {
+			return None;
+		}
+		let (holder_relative_satoshis, funding_feerate_perkw) = match self.splice_contribution {
+			Some(ref contribution) => (contribution.relative_satoshis(), contribution.feerate_per_kw),
+			None => return None,
+		};
+		// Our balance may have changed since the splice was requested.
+		if let Err(e) = self.check_splice_balances(holder_relative_satoshis, 0) {
+			log_info!(logger, "Aborting splice of channel {}: {}", log_bytes!(self.channel_id()), e);
+			self.splice_contribution = None;
+			self.end_quiescence();
+			return Some(SpliceMessage::Abort(msgs::TxAbort { channel_id: self.channel_id, data: e }));
+		}
+		self.pending_splice = Some(PendingSplice::new(true, holder_relative_satoshis, None));
+		Some(SpliceMessage::Splice(msgs::Splice {
+			channel_id: self.channel_id,
+			relative_satoshis: holder_relative_satoshis,
+			funding_feerate_perkw,
+			locktime: 0,
+		}))
+	}
+
+	pub fn stfu(&mut self, msg: &msgs::Stfu) -> Result<(), ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent stfu when we needed a channel_reestablish".to_owned()));
+		}
+		if !self.is_usable() {
+			return Err(ChannelError::Close("Peer sent stfu for a channel which is not funded or is shutting down".to_owned()));
+		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent stfu while the channel was already quiescent".to_owned()));
+		}
+		if msg.initiator == 0 && !self.sent_stfu {
+			return Err(ChannelError::Close("Peer replied to an stfu we never sent".to_owned()));
+		}
+		self.received_stfu = true;
+		if msg.initiator != 0 && self.holder_is_quiescence_initiator && !self.is_outbound() {
+			// Both peers want to splice, the channel funder goes first.
+			self.holder_is_quiescence_initiator = false;
+		}
+		Ok(())
+	}
+
+	/// Handles a splice message from the quiescence initiator, returning our splice_ack. We don't
+	/// contribute to splices initiated by our counterparty.
+	pub fn splice<F: Deref>(&mut self, msg: &msgs::Splice, fee_estimator: &F) -> Result<msgs::SpliceAck, ChannelError>
+	where F::Target: FeeEstimator
+	{
+		if !self.sent_stfu || !self.received_stfu || self.holder_is_quiescence_initiator || self.pending_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent splice while the channel was not quiescent or without being the quiescence initiator".to_owned()));
+		}
+		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.funding_feerate_perkw)?;
+		let new_value_satoshis = self.check_splice_balances(0, msg.relative_satoshis).map_err(ChannelError::Close)?;
+		let shared_input = self.get_shared_funding_input().map_err(ChannelError::Close)?;
+
+		let funding_script = self.get_funding_redeemscript().to_v0_p2wsh();
+		self.interactive_tx_constructor = Some(InteractiveTxConstructor::new(self.channel_id, false, msg.locktime, funding_script, new_value_satoshis, msg.relative_satoshis, Some(shared_input), FundingContribution::empty(msg.funding_feerate_perkw)));
+		self.pending_splice = Some(PendingSplice::new(false, 0, Some(msg.relative_satoshis)));
+		Ok(msgs::SpliceAck {
+			channel_id: self.channel_id,
+			relative_satoshis: 0,
+		})
+	}
+
+	/// Handles a splice_ack message for a splice we initiated, returning the first message of the
+	/// splice transaction construction.
+	pub fn splice_ack(&mut self, msg: &msgs::SpliceAck) -> Result<InteractiveTxMessage, ChannelError> {
+		let holder_relative_satoshis = match self.pending_splice {
+			Some(ref splice) if splice.is_initiator && splice.counterparty_relative_satoshis.is_none() => splice.holder_relative_satoshis,
+			_ => return Err(ChannelError::Close("Got an unexpected splice_ack message".to_owned())),
+		};
+		let new_value_satoshis = self.check_splice_balances(holder_relative_satoshis, msg.relative_satoshis).map_err(ChannelError::Close)?;
+		let shared_input = self.get_shared_funding_input().map_err(ChannelError::Close)?;
+		let contribution = self.splice_contribution.take().unwrap();
+
+		let funding_script = self.get_funding_redeemscript().to_v0_p2wsh();
+		let mut constructor = InteractiveTxConstructor::new(self.channel_id, true, 0, funding_script, new_value_satoshis, msg.relative_satoshis, Some(shared_input), contribution);
+		let first_msg = constructor.next_message();
+		self.interactive_tx_constructor = Some(constructor);
+		self.pending_splice.as_mut().unwrap().counterparty_relative_satoshis = Some(msg.relative_satoshis);
+		Ok(first_msg)
+	}
+
+	/// Handles a tx_abort message, returning a tx_abort to echo back if we were quiescent or
+	/// negotiating a splice. A dual-funded channel pending funding is closed instead.
+	pub fn tx_abort<L: Deref>(&mut self, msg: &msgs::TxAbort, logger: &L) -> Result<Option<msgs::TxAbort>, ChannelError> where L::Target: Logger {
+		if self.channel_state & (ChannelState::ChannelFunded as u32) == 0 {
+			return Err(ChannelError::Close("Peer aborted the construction of the funding transaction".to_owned()));
+		}
+		if self.is_splice_signed() {
+			return Err(ChannelError::Ignore("Peer sent tx_abort after we signed the splice transaction".to_owned()));
+		}
+		self.splice_contribution = None;
+		if !self.abort_splice() {
+			return Ok(None);
+		}
+		log_info!(logger, "Peer aborted splice of channel {}: {}", log_bytes!(self.channel_id()), msg.data);
+		Ok(Some(msgs::TxAbort {
+			channel_id: self.channel_id,
+			data: "Splice aborted".to_owned(),
+		}))
+	}
+
+	/// Returns true once we sent our signatures for the splice transaction, after which the
+	/// splice can't be aborted.
+	fn is_splice_signed(&self) -> bool {
+		match self.pending_splice {
+			Some(ref splice) => splice.funding_transaction.is_some() ||
+				self.funding_tx_signing.as_ref().map(|funding_tx_signing| funding_tx_signing.is_holder_signed()).unwrap_or(false),
+			None => false,
+		}
+	}
+
+	/// Swaps the current funding of the channel with the one of the pending splice, so that we
+	/// can build the commitment transactions spending the new funding output. Must be called
+	/// again to swap it back, unless the splice is complete.
+	fn swap_splice_funding(&mut self) {
+		let funding = self.pending_splice.as_mut().unwrap().funding.as_mut().unwrap();
+		mem::swap(&mut self.channel_value_satoshis, &mut funding.channel_value_satoshis);
+		mem::swap(&mut self.value_to_self_msat, &mut funding.value_to_self_msat);
+		mem::swap(&mut self.channel_transaction_parameters, &mut funding.channel_transaction_parameters);
+		mem::swap(&mut self.holder_keys, &mut funding.holder_keys);
+	}
+
+	/// Called once the splice transaction is constructed, returning our commitment_signed for the
+	/// counterparty's current commitment transaction spending its funding output.
+	fn splice_constructed<L: Deref>(&mut self, funding_txo: OutPoint, logger: &L) -> Result<msgs::CommitmentSigned, ChannelError> where L::Target: Logger {
+		let (holder_relative_satoshis, counterparty_relative_satoshis) = {
+			let splice = self.pending_splice.as_ref().unwrap();
+			(splice.holder_relative_satoshis, splice.counterparty_relative_satoshis.unwrap())
+		};
+		let channel_value_satoshis = (self.channel_value_satoshis as i64 + holder_relative_satoshis + counterparty_relative_satoshis) as u64;
+		let mut channel_transaction_parameters = self.channel_transaction_parameters.clone();
+		channel_transaction_parameters.funding_outpoint = Some(funding_txo);
+		channel_transaction_parameters.channel_value_satoshis = channel_value_satoshis;
+		let mut holder_keys = self.holder_keys.clone();
+		holder_keys.splice_channel(&channel_transaction_parameters);
+		self.pending_splice.as_mut().unwrap().funding = Some(SpliceFunding {
+			channel_value_satoshis,
+			value_to_self_msat: (self.value_to_self_msat as i64 + holder_relative_satoshis * 1000) as u64,
+			channel_transaction_parameters,
+			holder_keys,
+		});
+
+		self.swap_splice_funding();
+		let res = self.get_splice_commitment_signed(logger);
+		self.swap_splice_funding();
+		res
+	}
+
+	fn get_splice_commitment_signed<L: Deref>(&self, logger: &L) -> Result<msgs::CommitmentSigned, ChannelError> where L::Target: Logger {
+		let (counterparty_commitment_tx, _) = self.build_current_counterparty_commitment_transaction(logger)?;
+		let (signature, htlc_signatures) = self.holder_keys.sign_counterparty_commitment(&counterparty_commitment_tx, &self.secp_ctx)
+			.map_err(|_| ChannelError::Close("Failed to get signatures for the splice commitment_signed".to_owned()))?;
+		log_trace!(logger, "Signed remote commitment tx {} spending splice funding output {} -> {}",
+			counterparty_commitment_tx.trust().txid(),
+			self.get_current_funding_txo().unwrap().txid,
+			log_bytes!(signature.serialize_compact()[..]));
+		Ok(msgs::CommitmentSigned {
+			channel_id: self.channel_id,
+			signature,
+			htlc_signatures,
+		})
+	}
+
+	/// Builds the counterparty's current commitment transaction, ie the last one we signed, which
+	/// is the only one it holds while the channel is quiescent.
+	fn build_current_counterparty_commitment_transaction<L: Deref>(&self, logger: &L) -> Result<(CommitmentTransaction, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>), ChannelError> where L::Target: Logger {
+		let keys = self.build_counterparty_transaction_keys(&self.counterparty_prev_commitment_point.unwrap())?;
+		let (commitment_tx, _, htlcs) = self.build_commitment_transaction(self.cur_counterparty_commitment_transaction_number + 1, &keys, false, true, self.feerate_per_kw, logger);
+		Ok((commitment_tx, htlcs))
+	}
+
+	/// Returns true if we constructed a splice transaction and are waiting for our counterparty's
+	/// commitment_signed for our commitment transaction spending its funding output.
+	pub fn is_awaiting_splice_commitment_signed(&self) -> bool {
+		match self.pending_splice {
+			Some(ref splice) => splice.funding.is_some() && !splice.received_commitment_signed,
+			None => false,
+		}
+	}
+
+	/// Handles the commitment_signed message for our current commitment transaction spending the
+	/// funding output of the splice transaction, returning the ChannelMonitorUpdate which gives
+	/// our ChannelMonitor the commitment transactions spending it. We only sign the splice
+	/// transaction once the ChannelMonitor is updated.
+	pub fn splice_commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<ChannelMonitorUpdate, ChannelError> where L::Target: Logger {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish".to_owned()));
+		}
+		self.swap_splice_funding();
+		let res = self.get_splice_monitor_update_step(msg, logger);
+		self.swap_splice_funding();
+		let update_step = res?;
+
+		self.pending_splice.as_mut().unwrap().received_commitment_signed = true;
+		self.latest_monitor_update_id += 1;
+		Ok(ChannelMonitorUpdate {
+			update_id: self.latest_monitor_update_id,
+			updates: vec![update_step],
+		})
+	}
+
+	fn get_splice_monitor_update_step<L: Deref>(&self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<ChannelMonitorUpdateStep, ChannelError> where L::Target: Logger {
+		let holder_commitment_number = self.cur_holder_commitment_transaction_number + 1;
+		let keys = self.build_holder_transaction_keys(holder_commitment_number)?;
+		let (holder_commitment_tx, holder_htlc_outputs) = self.check_holder_commitment_signatures(msg, holder_commitment_number, &keys, self.feerate_per_kw, logger)?;
+		let (counterparty_commitment_tx, counterparty_htlcs) = self.build_current_counterparty_commitment_transaction(logger)?;
+		Ok(ChannelMonitorUpdateStep::SpliceFundingNegotiated {
+			channel_parameters: self.channel_transaction_parameters.clone(),
+			holder_commitment_tx,
+			holder_htlc_outputs,
+			counterparty_commitment_txid: counterparty_commitment_tx.trust().txid(),
+			counterparty_htlc_outputs: counterparty_htlcs.into_iter().map(|(htlc, source)| (htlc, source.map(|source| Box::new(source.clone())))).collect(),
+		})
+	}
+
+	/// Handles a splice_locked message, completing the splice if we sent ours too.
+	pub fn splice_locked(&mut self, msg: &msgs::SpliceLocked) -> Result<(), ChannelError> {
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent splice_locked when we needed a channel_reestablish".to_owned()));
+		}
+		let pending_splice_txid = self.pending_splice.as_ref().and_then(|splice| splice.funding_transaction.as_ref()).map(|tx| tx.txid());
+		if pending_splice_txid == Some(msg.splice_txid) {
+			self.pending_splice.as_mut().unwrap().received_splice_locked = true;
+			self.maybe_complete_splice();
+			Ok(())
+		} else if self.pending_splice.is_none() && self.original_funding_txo.is_some() && self.get_current_funding_txo().map(|funding_txo| funding_txo.txid) == Some(msg.splice_txid) {
+			// They probably disconnected/reconnected and re-sent the splice_locked
+			Ok(())
+		} else {
+			Err(ChannelError::Close("Peer sent splice_locked for an unknown splice transaction".to_owned()))
+		}
+	}
+
+	/// Moves the channel to the funding output of the splice transaction once both peers sent
+	/// splice_locked, ending quiescence.
+	fn maybe_complete_splice(&mut self) {
+		match self.pending_splice {
+			Some(ref splice) if splice.sent_splice_locked && splice.received_splice_locked => {},
+			_ => return,
+		}
+		if self.original_funding_txo.is_none() {
+			self.original_funding_txo = self.get_current_funding_txo();
+		}
+		self.swap_splice_funding();
+		let splice = self.pending_splice.take().unwrap();
+		self.funding_transaction = splice.funding_transaction;
+		self.funding_tx_confirmations = splice.funding_tx_confirmations;
+		self.funding_tx_confirmed_in = splice.funding_tx_confirmed_in;
+		if let Some(short_channel_id) = self.short_channel_id {
+			if !self.pending_inbound_htlcs.is_empty() {
+				self.pre_splice_short_channel_ids.push((short_channel_id, self.next_counterparty_htlc_id));
+			}
+		}
+		self.short_channel_id = splice.short_channel_id;
+		self.end_quiescence();
+		self.update_time_counter += 1;
+		#[cfg(debug_assertions)]
+		{
+			*self.holder_max_commitment_tx_output.lock().unwrap() = (0, 0);
+			*self.counterparty_max_commitment_tx_output.lock().unwrap() = (0, 0);
+		}
+	}
+
+	/// Gets the splice_locked to (re)send on reconnection: the one for the pending splice if we
+	/// sent it already, or else the one for the last splice of the channel, if any, in case our
+	/// counterparty didn't get it.
+	pub fn get_splice_locked_to_resend(&self) -> Option<msgs::SpliceLocked> {
+		let splice_txid = match self.pending_splice {
+			Some(ref splice) if splice.sent_splice_locked => splice.funding_transaction.as_ref().map(|tx| tx.txid()),
+			Some(_) => None,
+			None if self.original_funding_txo.is_some() => self.get_current_funding_txo().map(|funding_txo| funding_txo.txid),
+			None => None,
+		};
+		splice_txid.map(|splice_txid| msgs::SpliceLocked {
+			channel_id: self.channel_id,
+			splice_txid,
+		})
+	}
+
 	pub fn funding_locked(&mut self, msg: &msgs::FundingLocked) -> Result<(), ChannelError> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent funding_locked when we needed a channel_reestablish".to_owned()));
 		}
+		if msg.short_channel_id_alias.is_some() {
+			self.counterparty_scid_alias = msg.short_channel_id_alias;
+		}
 
 		let non_shutdown_state = self.channel_state & (!MULTI_STATE_FLAGS);
 
@@ -1676,12 +2925,15 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		cmp::max(self.value_to_self_msat as i64 - self.get_outbound_pending_htlc_stats().1 as i64, 0) as u64)
 	}
 
-	// Get the fee cost of a commitment tx with a given number of HTLC outputs.
+	// Get the fee cost of a commitment tx with a given number of HTLC outputs, including the value
+	// of its anchor outputs, if any, which the funder pays for as well.
 	// Note that num_htlcs should not include dust HTLCs.
 	fn commit_tx_fee_msat(&self, num_htlcs: usize) -> u64 {
+		let opt_anchors = self.channel_transaction_parameters.opt_anchors;
 		// Note that we need to divide before multiplying to round properly,
 		// since the lowest denomination of bitcoin on-chain is the satoshi.
-		(COMMITMENT_TX_BASE_WEIGHT + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000
+		(Channel::<ChanSigner>::commitment_tx_base_weight(opt_anchors) + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) * self.feerate_per_kw as u64 / 1000 * 1000
+			+ Channel::<ChanSigner>::anchors_value_msat(opt_anchors)
 	}
 
 	// Get the commitment tx fee for the local (i.e our) next commitment transaction
@@ -1758,6 +3010,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_add_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Warn("Peer sent update_add_htlc while the channel was quiescent".to_owned()));
+		}
 		if msg.amount_msat > self.channel_value_satoshis * 1000 {
 			return Err(ChannelError::Close("Remote side tried to send more than the total value of the channel".to_owned()));
 		}
@@ -1904,6 +3159,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fulfill_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Warn("Peer sent update_fulfill_htlc while the channel was quiescent".to_owned()));
+		}
 
 		let payment_hash = PaymentHash(Sha256::hash(&msg.payment_preimage.0[..]).into_inner());
 		self.mark_outbound_htlc_removed(msg.htlc_id, Some(payment_hash), None).map(|source| source.clone())
@@ -1916,6 +3174,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Warn("Peer sent update_fail_htlc while the channel was quiescent".to_owned()));
+		}
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
 		Ok(())
@@ -1928,39 +3189,21 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_malformed_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Warn("Peer sent update_fail_malformed_htlc while the channel was quiescent".to_owned()));
+		}
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
 		Ok(())
 	}
 
-	pub fn commitment_signed<F: Deref, L: Deref>(&mut self, msg: &msgs::CommitmentSigned, fee_estimator: &F, logger: &L) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>, Option<msgs::ClosingSigned>, ChannelMonitorUpdate), (Option<ChannelMonitorUpdate>, ChannelError)>
-	where F::Target: FeeEstimator,
-				L::Target: Logger
-	{
-		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
-			return Err((None, ChannelError::Close("Got commitment signed message when channel was not in an operational state".to_owned())));
-		}
-		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
-			return Err((None, ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish".to_owned())));
-		}
-		if self.channel_state & BOTH_SIDES_SHUTDOWN_MASK == BOTH_SIDES_SHUTDOWN_MASK && self.last_sent_closing_fee.is_some() {
-			return Err((None, ChannelError::Close("Peer sent commitment_signed after we'd started exchanging closing_signeds".to_owned())));
-		}
-
+	/// Checks our counterparty's signatures for our commitment transaction with the given number,
+	/// returning it along with its HTLCs and their signatures for our ChannelMonitor.
+	fn check_holder_commitment_signatures<L: Deref>(&self, msg: &msgs::CommitmentSigned, commitment_number: u64, keys: &TxCreationKeys, feerate_per_kw: u32, logger: &L) -> Result<(HolderCommitmentTransaction, Vec<(HTLCOutputInCommitment, Option<Signature>, Option<HTLCSource>)>), ChannelError> where L::Target: Logger {
 		let funding_script = self.get_funding_redeemscript();
 
-		let keys = self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number).map_err(|e| (None, e))?;
-
-		let mut update_fee = false;
-		let feerate_per_kw = if !self.is_outbound() && self.pending_update_fee.is_some() {
-			update_fee = true;
-			self.pending_update_fee.unwrap()
-		} else {
-			self.feerate_per_kw
-		};
-
 		let (num_htlcs, mut htlcs_cloned, commitment_tx, commitment_txid) = {
-			let commitment_tx = self.build_commitment_transaction(self.cur_holder_commitment_transaction_number, &keys, true, false, feerate_per_kw, logger);
+			let commitment_tx = self.build_commitment_transaction(commitment_number, keys, true, false, feerate_per_kw, logger);
 			let commitment_txid = {
 				let trusted_tx = commitment_tx.0.trust();
 				let bitcoin_tx = trusted_tx.built_transaction();
@@ -1968,7 +3211,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 				log_trace!(logger, "Checking commitment tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.signature.serialize_compact()[..]), log_bytes!(self.counterparty_funding_pubkey().serialize()), encode::serialize_hex(&bitcoin_tx.transaction), log_bytes!(sighash[..]), encode::serialize_hex(&funding_script));
 				if let Err(_) = self.secp_ctx.verify(&sighash, &msg.signature, &self.counterparty_funding_pubkey()) {
-					return Err((None, ChannelError::Close("Invalid commitment tx signature from peer".to_owned())));
+					return Err(ChannelError::Close("Invalid commitment tx signature from peer".to_owned()));
 				}
 				bitcoin_tx.txid
 			};
@@ -1976,30 +3219,21 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			(commitment_tx.1, htlcs_cloned, commitment_tx.0, commitment_txid)
 		};
 
-		//If channel fee was updated by funder confirm funder can afford the new fee rate when applied to the current local commitment transaction
-		if update_fee {
-			let total_fee = feerate_per_kw as u64 * (COMMITMENT_TX_BASE_WEIGHT + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000;
-
-			let counterparty_reserve_we_require = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(self.channel_value_satoshis);
-			if self.channel_value_satoshis - self.value_to_self_msat / 1000 < total_fee + counterparty_reserve_we_require {
-				return Err((None, ChannelError::Close("Funding remote cannot afford proposed new fee".to_owned())));
-			}
-		}
-
 		if msg.htlc_signatures.len() != num_htlcs {
-			return Err((None, ChannelError::Close(format!("Got wrong number of HTLC signatures ({}) from remote. It must be {}", msg.htlc_signatures.len(), num_htlcs))));
+			return Err(ChannelError::Close(format!("Got wrong number of HTLC signatures ({}) from remote. It must be {}", msg.htlc_signatures.len(), num_htlcs)));
 		}
 
 		// TODO: Sadly, we pass HTLCs twice to ChannelMonitor: once via the HolderCommitmentTransaction and once via the update
 		let mut htlcs_and_sigs = Vec::with_capacity(htlcs_cloned.len());
 		for (idx, (htlc, source)) in htlcs_cloned.drain(..).enumerate() {
 			if let Some(_) = htlc.transaction_output_index {
-				let htlc_tx = self.build_htlc_transaction(&commitment_txid, &htlc, true, &keys, feerate_per_kw);
-				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
-				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]);
+				let opt_anchors = self.channel_transaction_parameters.opt_anchors;
+				let htlc_tx = self.build_htlc_transaction(&commitment_txid, &htlc, true, keys, feerate_per_kw);
+				let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, opt_anchors, keys);
+				let htlc_sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, chan_utils::htlc_tx_counterparty_sighash_type(opt_anchors))[..]);
 				log_trace!(logger, "Checking HTLC tx signature {} by key {} against tx {} (sighash {}) with redeemscript {}", log_bytes!(msg.htlc_signatures[idx].serialize_compact()[..]), log_bytes!(keys.countersignatory_htlc_key.serialize()), encode::serialize_hex(&htlc_tx), log_bytes!(htlc_sighash[..]), encode::serialize_hex(&htlc_redeemscript));
 				if let Err(_) = self.secp_ctx.verify(&htlc_sighash, &msg.htlc_signatures[idx], &keys.countersignatory_htlc_key) {
-					return Err((None, ChannelError::Close("Invalid HTLC tx signature from peer".to_owned())));
+					return Err(ChannelError::Close("Invalid HTLC tx signature from peer".to_owned()));
 				}
 				htlcs_and_sigs.push((htlc, Some(msg.htlc_signatures[idx]), source));
 			} else {
@@ -2014,6 +3248,47 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			&self.get_holder_pubkeys().funding_pubkey,
 			self.counterparty_funding_pubkey()
 		);
+		Ok((holder_commitment_tx, htlcs_and_sigs))
+	}
+
+	pub fn commitment_signed<F: Deref, L: Deref>(&mut self, msg: &msgs::CommitmentSigned, fee_estimator: &F, logger: &L) -> Result<(msgs::RevokeAndACK, Option<msgs::CommitmentSigned>, Option<msgs::ClosingSigned>, ChannelMonitorUpdate), (Option<ChannelMonitorUpdate>, ChannelError)>
+	where F::Target: FeeEstimator,
+				L::Target: Logger
+	{
+		if (self.channel_state & (ChannelState::ChannelFunded as u32)) != (ChannelState::ChannelFunded as u32) {
+			return Err((None, ChannelError::Close("Got commitment signed message when channel was not in an operational state".to_owned())));
+		}
+		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err((None, ChannelError::Close("Peer sent commitment_signed when we needed a channel_reestablish".to_owned())));
+		}
+		if self.channel_state & BOTH_SIDES_SHUTDOWN_MASK == BOTH_SIDES_SHUTDOWN_MASK && self.last_sent_closing_fee.is_some() {
+			return Err((None, ChannelError::Close("Peer sent commitment_signed after we'd started exchanging closing_signeds".to_owned())));
+		}
+
+		let keys = self.build_holder_transaction_keys(self.cur_holder_commitment_transaction_number).map_err(|e| (None, e))?;
+
+		let mut update_fee = false;
+		let feerate_per_kw = if !self.is_outbound() && self.pending_update_fee.is_some() {
+			update_fee = true;
+			self.pending_update_fee.unwrap()
+		} else {
+			self.feerate_per_kw
+		};
+
+		let (holder_commitment_tx, htlcs_and_sigs) = self.check_holder_commitment_signatures(msg, self.cur_holder_commitment_transaction_number, &keys, feerate_per_kw, logger).map_err(|e| (None, e))?;
+
+		//If channel fee was updated by funder confirm funder can afford the new fee rate when applied to the current local commitment transaction
+		if update_fee {
+			let num_htlcs = msg.htlc_signatures.len();
+			let opt_anchors = self.channel_transaction_parameters.opt_anchors;
+			let total_fee = feerate_per_kw as u64 * (Channel::<ChanSigner>::commitment_tx_base_weight(opt_anchors) + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
+				+ Channel::<ChanSigner>::anchors_value_msat(opt_anchors) / 1000;
+
+			let counterparty_reserve_we_require = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(self.channel_value_satoshis);
+			if self.channel_value_satoshis - self.value_to_self_msat / 1000 < total_fee + counterparty_reserve_we_require {
+				return Err((None, ChannelError::Close("Funding remote cannot afford proposed new fee".to_owned())));
+			}
+		}
 
 		let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number - 1, &self.secp_ctx);
 		let per_commitment_secret = self.holder_keys.release_commitment_secret(self.cur_holder_commitment_transaction_number + 1);
@@ -2108,8 +3383,20 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Used to fulfill holding_cell_htlcs when we get a remote ack (or implicitly get it by them
 	/// fulfilling or failing the last pending HTLC)
+	/// Frees the holding cell once a splice completed, unless we're waiting on our counterparty
+	/// or a ChannelMonitor update, in which case it is freed once we're not anymore.
+	pub fn maybe_free_holding_cell_htlcs<L: Deref>(&mut self, logger: &L) -> Result<(Option<(msgs::CommitmentUpdate, ChannelMonitorUpdate)>, Vec<(HTLCSource, PaymentHash)>), ChannelError> where L::Target: Logger {
+		if self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32) != 0 {
+			return Ok((None, Vec::new()));
+		}
+		self.free_holding_cell_htlcs(logger)
+	}
+
 	fn free_holding_cell_htlcs<L: Deref>(&mut self, logger: &L) -> Result<(Option<(msgs::CommitmentUpdate, ChannelMonitorUpdate)>, Vec<(HTLCSource, PaymentHash)>), ChannelError> where L::Target: Logger {
 		assert_eq!(self.channel_state & ChannelState::MonitorUpdateFailed as u32, 0);
+		if self.is_quiescing() {
+			return Ok((None, Vec::new()));
+		}
 		if self.holding_cell_htlc_updates.len() != 0 || self.holding_cell_update_fee.is_some() {
 			log_trace!(logger, "Freeing holding cell with {} HTLC updates{}", self.holding_cell_htlc_updates.len(), if self.holding_cell_update_fee.is_some() { " and a fee update" } else { "" });
 
@@ -2453,7 +3740,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			panic!("Cannot update fee while peer is disconnected/we're awaiting a monitor update (ChannelManager should have caught this)");
 		}
 
-		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) == (ChannelState::AwaitingRemoteRevoke as u32) {
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) == (ChannelState::AwaitingRemoteRevoke as u32) || self.is_quiescing() {
 			self.holding_cell_update_fee = Some(feerate_per_kw);
 			return None;
 		}
@@ -2467,6 +3754,43 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		})
 	}
 
+	/// Gets the feerate we should send update_fee for, if ChannelConfig::auto_update_fee is set
+	/// on this outbound channel and our Normal feerate estimate moved past the configured
+	/// thresholds from its current feerate. Returns None while an update_fee is already in flight.
+	pub fn get_auto_update_feerate<F: Deref>(&self, fee_estimator: &F) -> Option<u32>
+		where F::Target: FeeEstimator
+	{
+		if !self.config.auto_update_fee || !self.is_outbound() || !self.is_live() ||
+				self.pending_update_fee.is_some() || self.holding_cell_update_fee.is_some() {
+			return None;
+		}
+
+		let mut new_feerate = cmp::max(fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal), 253);
+		if let Some(max_feerate) = self.config.max_update_feerate_per_kw {
+			new_feerate = cmp::min(new_feerate, cmp::max(max_feerate, self.feerate_per_kw));
+		}
+		let (new_feerate_scaled, feerate) = (new_feerate as u64 * 100, self.feerate_per_kw as u64);
+		let increase_limit = feerate * (100 + self.config.update_fee_min_increase_percent as u64);
+		let decrease_limit = feerate * (100 - cmp::min(self.config.update_fee_min_decrease_percent, 100) as u64);
+		if (new_feerate as u64 > feerate && new_feerate_scaled >= increase_limit && self.can_afford_feerate(new_feerate)) ||
+				((new_feerate as u64) < feerate && new_feerate_scaled <= decrease_limit) {
+			Some(new_feerate)
+		} else { None }
+	}
+
+	/// Returns whether, as the funder, we can pay the fee of our next commitment transaction at the
+	/// given feerate while keeping the reserve our counterparty requires. Our counterparty closes
+	/// the channel if we send it an update_fee we can't afford.
+	fn can_afford_feerate(&self, feerate_per_kw: u32) -> bool {
+		let mut num_htlcs = self.pending_inbound_htlcs.iter().filter(|htlc| htlc.amount_msat / 1000 > self.holder_dust_limit_satoshis).count();
+		num_htlcs += self.pending_outbound_htlcs.iter().filter(|htlc| htlc.amount_msat / 1000 > self.holder_dust_limit_satoshis).count();
+		let opt_anchors = self.channel_transaction_parameters.opt_anchors;
+		let total_fee = feerate_per_kw as u64 * (Channel::<ChanSigner>::commitment_tx_base_weight(opt_anchors) + (num_htlcs as u64) * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
+			+ Channel::<ChanSigner>::anchors_value_msat(opt_anchors) / 1000;
+		let balance = self.get_inbound_outbound_available_balance_msat().1 / 1000;
+		balance >= total_fee + self.counterparty_selected_channel_reserve_satoshis
+	}
+
 	pub fn send_update_fee_and_commit<L: Deref>(&mut self, feerate_per_kw: u32, logger: &L) -> Result<Option<(msgs::UpdateFee, msgs::CommitmentSigned, ChannelMonitorUpdate)>, ChannelError> where L::Target: Logger {
 		match self.send_update_fee(feerate_per_kw) {
 			Some(update_fee) => {
@@ -2494,6 +3818,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		// Upon reconnect we have to start the closing_signed dance over, but shutdown messages
 		// will be retransmitted.
 		self.last_sent_closing_fee = None;
+		// Quiescence ends on disconnection, and so does the negotiation of a splice we didn't sign
+		// yet. Once we signed it the splice transaction may confirm, so we keep waiting for it.
+		if !self.is_splice_signed() {
+			self.abort_splice();
+		}
+		self.end_quiescence();
 
 		let mut inbound_drop_count = 0;
 		self.pending_inbound_htlcs.retain(|htlc| {
@@ -2575,23 +3905,25 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		assert_eq!(self.channel_state & ChannelState::MonitorUpdateFailed as u32, ChannelState::MonitorUpdateFailed as u32);
 		self.channel_state &= !(ChannelState::MonitorUpdateFailed as u32);
 
-		let needs_broadcast_safe = self.channel_state & (ChannelState::FundingSent as u32) != 0 && self.is_outbound();
+		// The funding transaction of dual-funded channels is broadcast once signed by both sides
+		let needs_broadcast_safe = self.channel_state & (ChannelState::FundingSent as u32) != 0 && self.is_outbound() && self.funding_tx_signing.is_none();
 
 		// Because we will never generate a FundingBroadcastSafe event when we're in
 		// MonitorUpdateFailed, if we assume the user only broadcast the funding transaction when
 		// they received the FundingBroadcastSafe event, we can only ever hit
 		// monitor_pending_funding_locked when we're an inbound channel which failed to persist the
 		// monitor on funding_created, and we even got the funding transaction confirmed before the
-		// monitor was persisted.
+		// monitor was persisted, or for zero-conf channels which lock the funding right away.
 		let funding_locked = if self.monitor_pending_funding_locked {
-			assert!(!self.is_outbound(), "Funding transaction broadcast without FundingBroadcastSafe!");
+			assert!(!self.is_outbound() || self.minimum_depth == 0, "Funding transaction broadcast without FundingBroadcastSafe!");
 			self.monitor_pending_funding_locked = false;
 			let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number, &self.secp_ctx);
 			Some(msgs::FundingLocked {
 				channel_id: self.channel_id(),
 				next_per_commitment_point,
+				short_channel_id_alias: if self.is_zero_conf() { Some(self.scid_alias) } else { None },
 			})
-		} else { None };
+		} else { self.check_get_zero_conf_funding_locked() };
 
 		let mut forwards = Vec::new();
 		mem::swap(&mut forwards, &mut self.monitor_pending_forwards);
@@ -2631,6 +3963,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fee when we needed a channel_reestablish".to_owned()));
 		}
+		if self.received_stfu || self.pending_splice.is_some() {
+			return Err(ChannelError::Warn("Peer sent update_fee while the channel was quiescent".to_owned()));
+		}
 		Channel::<ChanSigner>::check_remote_fee(fee_estimator, msg.feerate_per_kw)?;
 		self.pending_update_fee = Some(msg.feerate_per_kw);
 		self.update_time_counter += 1;
@@ -2695,11 +4030,20 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		log_trace!(logger, "Regenerated latest commitment update with {} update_adds, {} update_fulfills, {} update_fails, and {} update_fail_malformeds",
-				update_add_htlcs.len(), update_fulfill_htlcs.len(), update_fail_htlcs.len(), update_fail_malformed_htlcs.len());
+		// An update_fee we sent is only cleared once our counterparty revokes, so if it's still
+		// pending it was part of the commitment_signed we're regenerating.
+		let update_fee = if self.is_outbound() {
+			self.pending_update_fee.map(|feerate_per_kw| msgs::UpdateFee {
+				channel_id: self.channel_id(),
+				feerate_per_kw,
+			})
+		} else { None };
+
+		log_trace!(logger, "Regenerated latest commitment update with {} update_adds, {} update_fulfills, {} update_fails, {} update_fail_malformeds, and {} update_fee",
+				update_add_htlcs.len(), update_fulfill_htlcs.len(), update_fail_htlcs.len(), update_fail_malformed_htlcs.len(), if update_fee.is_some() { 1 } else { 0 });
 		msgs::CommitmentUpdate {
 			update_add_htlcs, update_fulfill_htlcs, update_fail_htlcs, update_fail_malformed_htlcs,
-			update_fee: None,
+			update_fee,
 			commitment_signed: self.send_commitment_no_state_update(logger).expect("It looks like we failed to re-generate a commitment_signed we had previously sent?").0,
 		}
 	}
@@ -2765,6 +4109,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			return Ok((Some(msgs::FundingLocked {
 				channel_id: self.channel_id(),
 				next_per_commitment_point,
+				short_channel_id_alias: if self.is_zero_conf() { Some(self.scid_alias) } else { None },
 			}), None, None, None, RAACommitmentOrder::CommitmentFirst, shutdown_msg));
 		}
 
@@ -2795,6 +4140,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			Some(msgs::FundingLocked {
 				channel_id: self.channel_id(),
 				next_per_commitment_point,
+				short_channel_id_alias: if self.is_zero_conf() { Some(self.scid_alias) } else { None },
 			})
 		} else { None };
 
@@ -2820,7 +4166,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				// now!
 				match self.free_holding_cell_htlcs(logger) {
 					Err(ChannelError::Close(msg)) => return Err(ChannelError::Close(msg)),
-					Err(ChannelError::Ignore(_)) | Err(ChannelError::CloseDelayBroadcast(_)) => panic!("Got non-channel-failing result from free_holding_cell_htlcs"),
+					Err(ChannelError::Ignore(_)) | Err(ChannelError::Warn(_)) | Err(ChannelError::CloseDelayBroadcast(_)) => panic!("Got non-channel-failing result from free_holding_cell_htlcs"),
 					Ok((Some((commitment_update, monitor_update)), htlcs_to_fail)) => {
 						// If in the future we no longer drop holding cell update_adds on peer
 						// disconnect, we may be handed some HTLCs to fail backwards here.
@@ -2869,7 +4215,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			proposed_feerate = self.feerate_per_kw;
 		}
 		let tx_weight = self.get_closing_transaction_weight(Some(&self.get_closing_scriptpubkey()), Some(self.counterparty_shutdown_scriptpubkey.as_ref().unwrap()));
-		let proposed_total_fee_satoshis = proposed_feerate as u64 * tx_weight / 1000;
+		let mut proposed_total_fee_satoshis = proposed_feerate as u64 * tx_weight / 1000;
+		if let (Some(target_feerate), Some(max_fee)) = (self.closing_target_feerate_per_kw, self.closing_max_fee_satoshis) {
+			proposed_total_fee_satoshis = cmp::min(target_feerate as u64 * tx_weight / 1000, max_fee);
+			proposed_feerate = (proposed_total_fee_satoshis * 1000 / tx_weight) as u32;
+		}
 
 		let (closing_tx, total_fee_satoshis) = self.build_closing_transaction(proposed_total_fee_satoshis, false);
 		let sig = self.holder_keys
@@ -2898,6 +4248,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			// can do that via error message without getting a connection fail anyway...
 			return Err(ChannelError::Close("Peer sent shutdown pre-funding generation".to_owned()));
 		}
+		if self.pending_splice.is_some() {
+			return Err(ChannelError::Close("Peer sent shutdown while splicing the channel".to_owned()));
+		}
 		for htlc in self.pending_inbound_htlcs.iter() {
 			if let InboundHTLCState::RemoteAnnounced(_) = htlc.state {
 				return Err(ChannelError::Close("Got shutdown with remote pending HTLCs".to_owned()));
@@ -2967,21 +4320,28 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if tx.input[0].witness.len() != 0 { panic!("Tried to re-sign closing transaction"); }
 		if tx.output.len() > 2 { panic!("Tried to sign bogus closing transaction"); }
 
-		tx.input[0].witness.push(Vec::new()); // First is the multisig dummy
+		tx.input[0].witness = self.build_funding_witness(counterparty_sig, sig);
+	}
+
+	/// Builds the witness spending our current funding output given both peers' signatures.
+	fn build_funding_witness(&self, counterparty_sig: &Signature, sig: &Signature) -> Vec<Vec<u8>> {
+		let mut witness = Vec::with_capacity(4);
+		witness.push(Vec::new()); // First is the multisig dummy
 
 		let funding_key = self.get_holder_pubkeys().funding_pubkey.serialize();
 		let counterparty_funding_key = self.counterparty_funding_pubkey().serialize();
 		if funding_key[..] < counterparty_funding_key[..] {
-			tx.input[0].witness.push(sig.serialize_der().to_vec());
-			tx.input[0].witness.push(counterparty_sig.serialize_der().to_vec());
+			witness.push(sig.serialize_der().to_vec());
+			witness.push(counterparty_sig.serialize_der().to_vec());
 		} else {
-			tx.input[0].witness.push(counterparty_sig.serialize_der().to_vec());
-			tx.input[0].witness.push(sig.serialize_der().to_vec());
+			witness.push(counterparty_sig.serialize_der().to_vec());
+			witness.push(sig.serialize_der().to_vec());
 		}
-		tx.input[0].witness[1].push(SigHashType::All as u8);
-		tx.input[0].witness[2].push(SigHashType::All as u8);
+		witness[1].push(SigHashType::All as u8);
+		witness[2].push(SigHashType::All as u8);
 
-		tx.input[0].witness.push(self.get_funding_redeemscript().into_bytes());
+		witness.push(self.get_funding_redeemscript().into_bytes());
+		witness
 	}
 
 	pub fn closing_signed<F: Deref>(&mut self, fee_estimator: &F, msg: &msgs::ClosingSigned) -> Result<(Option<msgs::ClosingSigned>, Option<Transaction>), ChannelError>
@@ -3032,10 +4392,10 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 			}
 		}
 
-		macro_rules! propose_new_feerate {
-			($new_feerate: expr) => {
-				let tx_weight = self.get_closing_transaction_weight(Some(&self.get_closing_scriptpubkey()), Some(self.counterparty_shutdown_scriptpubkey.as_ref().unwrap()));
-				let (closing_tx, used_total_fee) = self.build_closing_transaction($new_feerate as u64 * tx_weight / 1000, false);
+		let tx_weight = self.get_closing_transaction_weight(Some(&self.get_closing_scriptpubkey()), Some(self.counterparty_shutdown_scriptpubkey.as_ref().unwrap()));
+		macro_rules! propose_new_fee {
+			($new_feerate: expr, $new_fee: expr) => {
+				let (closing_tx, used_total_fee) = self.build_closing_transaction($new_fee, false);
 				let sig = self.holder_keys
 					.sign_closing_transaction(&closing_tx, &self.secp_ctx)
 					.map_err(|_| ChannelError::Close("External signer refused to sign closing transaction".to_owned()))?;
@@ -3048,28 +4408,56 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				}), None))
 			}
 		}
+		macro_rules! propose_new_feerate {
+			($new_feerate: expr) => {
+				propose_new_fee!($new_feerate, $new_feerate as u64 * tx_weight / 1000);
+			}
+		}
 
-		let mut min_feerate = 253;
-		if self.is_outbound() {
-			let max_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
-			if (msg.fee_satoshis as u64) > max_feerate as u64 * closing_tx_max_weight / 1000 {
-				if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
-					if max_feerate <= last_feerate {
-						return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something higher ({}) than our Normal feerate ({})", last_feerate, max_feerate)));
+		if let Some(max_fee) = self.closing_max_fee_satoshis {
+			// We were given limits when closing the channel. If we pay the fee any feerate which
+			// relays is fine, otherwise our counterparty must pay at least our target feerate.
+			let min_feerate = if self.is_outbound() { 253 } else { self.closing_target_feerate_per_kw.unwrap() };
+			let min_fee = cmp::min(min_feerate as u64 * closing_tx_max_weight / 1000, max_fee);
+			if msg.fee_satoshis > max_fee {
+				if let Some((_, last_fee, _)) = self.last_sent_closing_fee {
+					if last_fee >= max_fee {
+						return Err(ChannelError::Close(format!("Unable to come to consensus about closing fee, remote wanted something higher ({}) than our maximum fee ({})", msg.fee_satoshis, max_fee)));
 					}
 				}
-				propose_new_feerate!(max_feerate);
+				propose_new_fee!((max_fee * 1000 / tx_weight) as u32, max_fee);
+			}
+			if msg.fee_satoshis < min_fee {
+				if let Some((_, last_fee, _)) = self.last_sent_closing_fee {
+					if last_fee <= min_fee {
+						return Err(ChannelError::Close(format!("Unable to come to consensus about closing fee, remote wanted something lower ({}) than our minimum fee ({})", msg.fee_satoshis, min_fee)));
+					}
+				}
+				propose_new_fee!((min_fee * 1000 / tx_weight) as u32, min_fee);
 			}
 		} else {
-			min_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
-		}
-		if (msg.fee_satoshis as u64) < min_feerate as u64 * closing_tx_max_weight / 1000 {
-			if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
-				if min_feerate >= last_feerate {
-					return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something lower ({}) than our Background feerate ({}).", last_feerate, min_feerate)));
+			let mut min_feerate = 253;
+			if self.is_outbound() {
+				let max_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+				if (msg.fee_satoshis as u64) > max_feerate as u64 * closing_tx_max_weight / 1000 {
+					if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
+						if max_feerate <= last_feerate {
+							return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something higher ({}) than our Normal feerate ({})", last_feerate, max_feerate)));
+						}
+					}
+					propose_new_feerate!(max_feerate);
+				}
+			} else {
+				min_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Background);
+			}
+			if (msg.fee_satoshis as u64) < min_feerate as u64 * closing_tx_max_weight / 1000 {
+				if let Some((last_feerate, _, _)) = self.last_sent_closing_fee {
+					if min_feerate >= last_feerate {
+						return Err(ChannelError::Close(format!("Unable to come to consensus about closing feerate, remote wanted something lower ({}) than our Background feerate ({}).", last_feerate, min_feerate)));
+					}
 				}
+				propose_new_feerate!(min_feerate);
 			}
-			propose_new_feerate!(min_feerate);
 		}
 
 		let sig = self.holder_keys
@@ -3108,12 +4496,117 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.short_channel_id
 	}
 
+	/// Gets the short_channel_ids the channel had before being spliced which HTLCs we received
+	/// before the splice are still known by.
+	pub fn get_pre_splice_short_channel_ids(&self) -> Vec<u64> {
+		self.pre_splice_short_channel_ids.iter().map(|&(short_channel_id, _)| short_channel_id).collect()
+	}
+
+	/// Forgets the pre-splice short_channel_ids which no pending HTLC is known by anymore,
+	/// returning them.
+	pub fn prune_pre_splice_short_channel_ids(&mut self) -> Vec<u64> {
+		let pending_inbound_htlcs = &self.pending_inbound_htlcs;
+		let mut pruned = Vec::new();
+		self.pre_splice_short_channel_ids.retain(|&(short_channel_id, next_htlc_id)| {
+			if pending_inbound_htlcs.iter().any(|htlc| htlc.htlc_id < next_htlc_id) {
+				true
+			} else {
+				pruned.push(short_channel_id);
+				false
+			}
+		});
+		pruned
+	}
+
+	/// Gets the alias we sent our counterparty in funding_locked, once we've locked the funding
+	/// and thus accept HTLCs forwarded to it. Only zero-conf channels use an alias. It is still
+	/// returned after shutdown so that it can be forgotten along with the short_channel_id.
+	pub fn get_scid_alias(&self) -> Option<u64> {
+		if self.is_zero_conf() && self.channel_state & (ChannelState::OurFundingLocked as u32 | ChannelState::ChannelFunded as u32 | ChannelState::ShutdownComplete as u32) != 0 {
+			Some(self.scid_alias)
+		} else { None }
+	}
+
+	/// Gets the alias our counterparty sent in funding_locked, if any.
+	pub fn get_counterparty_scid_alias(&self) -> Option<u64> {
+		self.counterparty_scid_alias
+	}
+
+	/// Returns true if this is an inbound channel which is waiting for the user to accept it, see
+	/// UserConfig::manually_accept_inbound_channels.
+	pub fn is_awaiting_accept(&self) -> bool {
+		self.inbound_awaiting_accept
+	}
+
+	/// Marks an inbound channel as accepted by the user, after which its accept_channel (or
+	/// accept_channel2) message may be generated. If a config is given, it replaces the one the
+	/// channel was created with, except for announced_channel, which the opener decided.
+	pub fn set_accepted_by_user(&mut self, user_id: u64, config: Option<&ChannelConfig>) {
+		assert!(self.inbound_awaiting_accept);
+		self.inbound_awaiting_accept = false;
+		self.user_id = user_id;
+		if let Some(config) = config {
+			let announced_channel = self.config.announced_channel;
+			self.config = *config;
+			self.config.announced_channel = announced_channel;
+		}
+	}
+
+	/// Returns true if this channel may be used before its funding transaction confirms.
+	pub fn is_zero_conf(&self) -> bool {
+		self.minimum_depth == 0
+	}
+
+	/// Accepts this inbound channel without waiting for the funding transaction to confirm, as
+	/// we trust our counterparty not to double-spend it. Must be called before we send
+	/// accept_channel.
+	pub fn set_zero_conf(&mut self) {
+		assert!(!self.is_outbound());
+		self.minimum_depth = 0;
+	}
+
 	/// Returns the funding_txo we either got from our peer, or were given by
-	/// get_outbound_funding_created.
+	/// get_outbound_funding_created. This is the funding outpoint the channel was opened with even
+	/// once it has been spliced, as it identifies the channel's ChannelMonitor.
 	pub fn get_funding_txo(&self) -> Option<OutPoint> {
+		self.original_funding_txo.or(self.channel_transaction_parameters.funding_outpoint)
+	}
+
+	/// Returns the outpoint currently funding the channel, which differs from get_funding_txo once
+	/// the channel has been spliced.
+	pub fn get_current_funding_txo(&self) -> Option<OutPoint> {
 		self.channel_transaction_parameters.funding_outpoint
 	}
 
+	/// Returns the txid of the funding (or splice) transaction if it can't have been broadcast
+	/// yet, along with the transaction itself if we built it with our counterparty, for the
+	/// transaction to be discarded when the channel is dropped.
+	pub fn get_unbroadcast_funding(&self) -> Option<(Txid, Option<Transaction>)> {
+		if let Some(ref funding_tx_signing) = self.funding_tx_signing {
+			// Once we sent our signatures our counterparty may complete and broadcast it
+			if !funding_tx_signing.is_holder_signed() {
+				return Some((funding_tx_signing.tx.txid(), Some(funding_tx_signing.tx.clone())));
+			}
+			return None;
+		}
+		// Single-funded channels may only be broadcast once we've received funding_signed
+		if self.is_outbound() && self.cur_holder_commitment_transaction_number == INITIAL_COMMITMENT_NUMBER {
+			return self.get_funding_txo().map(|funding_txo| (funding_txo.txid, None));
+		}
+		None
+	}
+
+	/// Returns the funding output of the splice transaction we negotiated, until the splice
+	/// completes.
+	pub fn get_pending_splice_funding_txo(&self) -> Option<OutPoint> {
+		self.pending_splice.as_ref().and_then(|splice| splice.funding_txo())
+	}
+
+	/// Returns the txid of the splice transaction we negotiated, until the splice completes.
+	pub fn get_pending_splice_txid(&self) -> Option<Txid> {
+		self.get_pending_splice_funding_txo().map(|funding_txo| funding_txo.txid)
+	}
+
 	fn get_holder_selected_contest_delay(&self) -> u16 {
 		self.channel_transaction_parameters.holder_selected_contest_delay
 	}
@@ -3143,14 +4636,26 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Allowed in any state (including after shutdown)
 	pub fn get_announced_htlc_max_msat(&self) -> u64 {
-		return cmp::min(
+		let max_msat = cmp::min(
 			// Upper bound by capacity. We make it a bit less than full capacity to prevent attempts
 			// to use full capacity. This is an effort to reduce routing failures, because in many cases
 			// channel might have been used to route very small values (either by honest users or as DoS).
-			self.channel_value_satoshis * 9 / 10,
+			self.channel_value_satoshis * 1000 * 9 / 10,
 
 			Channel::<ChanSigner>::get_holder_max_htlc_value_in_flight_msat(self.channel_value_satoshis)
 		);
+		cmp::min(max_msat, self.config.htlc_maximum_msat.unwrap_or(max_msat))
+	}
+
+	/// Gets the smallest HTLC we'll forward over this channel, as announced in channel_update.
+	pub fn get_announced_htlc_min_msat(&self) -> u64 {
+		cmp::max(self.config.htlc_minimum_msat, self.counterparty_htlc_minimum_msat)
+	}
+
+	/// Gets the number of blocks we require between an inbound HTLC's CLTV and the CLTV of the
+	/// HTLC we forward over this channel.
+	pub fn get_cltv_expiry_delta(&self) -> u16 {
+		cmp::max(self.config.cltv_expiry_delta, MIN_CLTV_EXPIRY_DELTA)
 	}
 
 	/// Allowed in any state (including after shutdown)
@@ -3162,11 +4667,226 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_value_satoshis
 	}
 
+	/// Gets our balance, including outbound HTLCs which haven't been fulfilled yet.
+	pub fn get_value_to_self_msat(&self) -> u64 {
+		self.value_to_self_msat
+	}
+
+	/// Gets the reserve our counterparty requires us to keep, or None if we don't know it yet.
+	pub fn get_counterparty_selected_channel_reserve_satoshis(&self) -> Option<u64> {
+		if self.is_outbound() && !self.have_received_message() { None } else { Some(self.counterparty_selected_channel_reserve_satoshis) }
+	}
+
+	/// Gets the number of confirmations we wait for on the funding transaction, or None if we
+	/// don't know it yet.
+	pub fn get_confirmations_required(&self) -> Option<u32> {
+		if self.is_outbound() && !self.have_received_message() { None } else { Some(self.minimum_depth) }
+	}
+
+	pub fn get_funding_tx_confirmations(&self) -> u32 {
+		self.funding_tx_confirmations as u32
+	}
+
+	/// Gets the to_self_delay our counterparty imposes on our outputs, or None if we don't know
+	/// it yet.
+	pub fn get_holder_to_self_delay(&self) -> Option<u16> {
+		self.channel_transaction_parameters.counterparty_parameters.as_ref().map(|params| params.selected_contest_delay)
+	}
+
+	/// Gets the to_self_delay we impose on our counterparty's outputs.
+	pub fn get_counterparty_to_self_delay(&self) -> u16 {
+		self.get_holder_selected_contest_delay()
+	}
+
+	pub fn get_counterparty_forwarding_info(&self) -> Option<CounterpartyForwardingInfo> {
+		self.counterparty_forwarding_info.clone()
+	}
+
+	/// Stores the forwarding policy from a channel_update our counterparty sent for this channel.
+	/// Returns true if it differs from the one we had.
+	pub fn channel_update(&mut self, msg: &msgs::ChannelUpdate) -> bool {
+		let new_info = CounterpartyForwardingInfo {
+			fee_base_msat: msg.contents.fee_base_msat,
+			fee_proportional_millionths: msg.contents.fee_proportional_millionths,
+			cltv_expiry_delta: msg.contents.cltv_expiry_delta,
+			htlc_minimum_msat: msg.contents.htlc_minimum_msat,
+			htlc_maximum_msat: match msg.contents.htlc_maximum_msat {
+				OptionalField::Present(htlc_maximum_msat) => Some(htlc_maximum_msat),
+				OptionalField::Absent => None,
+			},
+		};
+		let changed = self.counterparty_forwarding_info.as_ref() != Some(&new_info);
+		self.counterparty_forwarding_info = Some(new_info);
+		changed
+	}
+
+	/// Gets the details of every HTLC pending in this channel, including outbound HTLCs still in
+	/// our holding cell.
+	pub fn get_pending_htlc_details(&self) -> Vec<PendingHTLCDetails> {
+		let mut res = Vec::with_capacity(self.pending_inbound_htlcs.len() + self.pending_outbound_htlcs.len());
+		for htlc in self.pending_inbound_htlcs.iter() {
+			let mut state = match htlc.state {
+				InboundHTLCState::RemoteAnnounced(_) => PendingHTLCState::Adding,
+				InboundHTLCState::AwaitingRemoteRevokeToAnnounce(_) => PendingHTLCState::Adding,
+				InboundHTLCState::AwaitingAnnouncedRemoteRevoke(_) => PendingHTLCState::Adding,
+				InboundHTLCState::Committed => PendingHTLCState::Committed,
+				InboundHTLCState::LocalRemoved(InboundHTLCRemovalReason::Fulfill(_)) => PendingHTLCState::Fulfilling,
+				InboundHTLCState::LocalRemoved(_) => PendingHTLCState::Failing,
+			};
+			// Removals we couldn't send yet sit in the holding cell until the next revoke_and_ack.
+			for update in self.holding_cell_htlc_updates.iter() {
+				match update {
+					&HTLCUpdateAwaitingACK::ClaimHTLC { htlc_id, .. } if htlc_id == htlc.htlc_id => state = PendingHTLCState::Fulfilling,
+					&HTLCUpdateAwaitingACK::FailHTLC { htlc_id, .. } if htlc_id == htlc.htlc_id => state = PendingHTLCState::Failing,
+					_ => {},
+				}
+			}
+			res.push(PendingHTLCDetails {
+				channel_id: self.channel_id,
+				htlc_id: Some(htlc.htlc_id),
+				direction: HTLCDirection::Inbound,
+				amount_msat: htlc.amount_msat,
+				payment_hash: htlc.payment_hash,
+				cltv_expiry: htlc.cltv_expiry,
+				state,
+			});
+		}
+		for htlc in self.pending_outbound_htlcs.iter() {
+			let state = match htlc.state {
+				OutboundHTLCState::LocalAnnounced(_) => PendingHTLCState::Adding,
+				OutboundHTLCState::Committed => PendingHTLCState::Committed,
+				OutboundHTLCState::RemoteRemoved(None) |
+				OutboundHTLCState::AwaitingRemoteRevokeToRemove(None) |
+				OutboundHTLCState::AwaitingRemovedRemoteRevoke(None) => PendingHTLCState::Fulfilling,
+				OutboundHTLCState::RemoteRemoved(Some(_)) |
+				OutboundHTLCState::AwaitingRemoteRevokeToRemove(Some(_)) |
+				OutboundHTLCState::AwaitingRemovedRemoteRevoke(Some(_)) => PendingHTLCState::Failing,
+			};
+			res.push(PendingHTLCDetails {
+				channel_id: self.channel_id,
+				htlc_id: Some(htlc.htlc_id),
+				direction: HTLCDirection::Outbound,
+				amount_msat: htlc.amount_msat,
+				payment_hash: htlc.payment_hash,
+				cltv_expiry: htlc.cltv_expiry,
+				state,
+			});
+		}
+		for update in self.holding_cell_htlc_updates.iter() {
+			if let &HTLCUpdateAwaitingACK::AddHTLC { amount_msat, cltv_expiry, payment_hash, .. } = update {
+				res.push(PendingHTLCDetails {
+					channel_id: self.channel_id,
+					htlc_id: None,
+					direction: HTLCDirection::Outbound,
+					amount_msat,
+					payment_hash,
+					cltv_expiry,
+					state: PendingHTLCState::HoldingCell,
+				});
+			}
+		}
+		res
+	}
+
+	/// Gets the sources of the HTLCs we sent over this channel whose outcome we haven't yet passed
+	/// upstream, including those still in our holding cell.
+	pub fn get_pending_outbound_htlc_sources(&self) -> Vec<(HTLCSource, PaymentHash)> {
+		let mut res = Vec::with_capacity(self.pending_outbound_htlcs.len());
+		for htlc in self.pending_outbound_htlcs.iter() {
+			match htlc.state {
+				// Claims are passed upstream as soon as we receive the preimage.
+				OutboundHTLCState::RemoteRemoved(None) |
+				OutboundHTLCState::AwaitingRemoteRevokeToRemove(None) |
+				OutboundHTLCState::AwaitingRemovedRemoteRevoke(None) => {},
+				_ => res.push((htlc.source.clone(), htlc.payment_hash)),
+			}
+		}
+		for update in self.holding_cell_htlc_updates.iter() {
+			if let &HTLCUpdateAwaitingACK::AddHTLC { ref source, payment_hash, .. } = update {
+				res.push((source.clone(), payment_hash));
+			}
+		}
+		res
+	}
+
 	pub fn get_fee_proportional_millionths(&self) -> u32 {
 		self.config.fee_proportional_millionths
 	}
 
-	#[cfg(test)]
+	/// Checks an HTLC we're asked to forward over this channel against our forwarding policy,
+	/// returning the failure message and code for the first violated rule. HTLCs paying our
+	/// previous policy are accepted until it expires, see maybe_expire_prev_config.
+	pub fn htlc_satisfies_config<F: Deref>(&self, htlc: &msgs::UpdateAddHTLC, amt_to_forward: u64, outgoing_cltv_value: u32, fee_estimator: &F) -> Result<(), (&'static str, u16)>
+		where F::Target: FeeEstimator
+	{
+		let res = self.htlc_satisfies_config_internal(&self.config, htlc, amt_to_forward, outgoing_cltv_value, fee_estimator);
+		if res.is_err() {
+			if let Some((ref prev_config, _)) = self.prev_config {
+				if self.htlc_satisfies_config_internal(prev_config, htlc, amt_to_forward, outgoing_cltv_value, fee_estimator).is_ok() {
+					return Ok(());
+				}
+			}
+		}
+		res
+	}
+
+	fn htlc_satisfies_config_internal<F: Deref>(&self, config: &ChannelConfig, htlc: &msgs::UpdateAddHTLC, amt_to_forward: u64, outgoing_cltv_value: u32, fee_estimator: &F) -> Result<(), (&'static str, u16)>
+		where F::Target: FeeEstimator
+	{
+		if amt_to_forward < config.htlc_minimum_msat { // amount_below_minimum
+			return Err(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11));
+		}
+		if let Some(htlc_maximum_msat) = config.htlc_maximum_msat {
+			if amt_to_forward > htlc_maximum_msat { // temporary_channel_failure
+				return Err(("HTLC amount was above the htlc_maximum_msat", 0x1000 | 7));
+			}
+		}
+		let fee = amt_to_forward.checked_mul(config.fee_proportional_millionths as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(self.get_fee_base_msat_for_config(config, fee_estimator) as u64) });
+		if fee.is_none() || htlc.amount_msat < fee.unwrap() || (htlc.amount_msat - fee.unwrap()) < amt_to_forward { // fee_insufficient
+			return Err(("Prior hop has deviated from specified fees parameters or origin node has obsolete ones", 0x1000 | 12));
+		}
+		if (htlc.cltv_expiry as u64) < outgoing_cltv_value as u64 + cmp::max(config.cltv_expiry_delta, MIN_CLTV_EXPIRY_DELTA) as u64 { // incorrect_cltv_expiry
+			return Err(("Forwarding node has tampered with the intended HTLC values or origin node has an obsolete cltv_expiry_delta", 0x1000 | 13));
+		}
+		Ok(())
+	}
+
+	/// Updates the forwarding policy of this channel, leaving the rest of its config untouched.
+	/// Returns true if the policy changed, in which case a new channel_update should be sent.
+	pub fn update_config(&mut self, config: &ChannelConfig) -> bool {
+		let prev_config = self.config;
+		self.config.fee_proportional_millionths = config.fee_proportional_millionths;
+		self.config.fee_base_msat = config.fee_base_msat;
+		self.config.cltv_expiry_delta = config.cltv_expiry_delta;
+		self.config.htlc_minimum_msat = config.htlc_minimum_msat;
+		self.config.htlc_maximum_msat = config.htlc_maximum_msat;
+		let changed = self.config.fee_proportional_millionths != prev_config.fee_proportional_millionths ||
+			self.config.fee_base_msat != prev_config.fee_base_msat ||
+			self.config.cltv_expiry_delta != prev_config.cltv_expiry_delta ||
+			self.config.htlc_minimum_msat != prev_config.htlc_minimum_msat ||
+			self.config.htlc_maximum_msat != prev_config.htlc_maximum_msat;
+		if changed {
+			self.prev_config = Some((prev_config, 0));
+			self.update_time_counter += 1;
+		}
+		changed
+	}
+
+	/// Called on each timer tick, forgets our previous forwarding policy once it has been replaced
+	/// for EXPIRE_PREV_CONFIG_TICKS ticks.
+	pub fn maybe_expire_prev_config(&mut self) {
+		let expired = match self.prev_config {
+			Some((_, ref mut ticks)) => {
+				*ticks += 1;
+				*ticks >= EXPIRE_PREV_CONFIG_TICKS
+			},
+			None => false,
+		};
+		if expired {
+			self.prev_config = None;
+		}
+	}
+
 	pub fn get_feerate(&self) -> u32 {
 		self.feerate_per_kw
 	}
@@ -3235,11 +4955,22 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	pub fn get_holder_fee_base_msat<F: Deref>(&self, fee_estimator: &F) -> u32
 		where F::Target: FeeEstimator
 	{
+		self.get_fee_base_msat_for_config(&self.config, fee_estimator)
+	}
+
+	fn get_fee_base_msat_for_config<F: Deref>(&self, config: &ChannelConfig, fee_estimator: &F) -> u32
+		where F::Target: FeeEstimator
+	{
+		if let Some(fee_base_msat) = config.fee_base_msat {
+			return fee_base_msat;
+		}
+
 		// For lack of a better metric, we calculate what it would cost to consolidate the new HTLC
 		// output value back into a transaction with the regular channel output:
 
 		// the fee cost of the HTLC-Success/HTLC-Timeout transaction:
-		let mut res = self.feerate_per_kw as u64 * cmp::max(HTLC_TIMEOUT_TX_WEIGHT, HTLC_SUCCESS_TX_WEIGHT) / 1000;
+		let opt_anchors = self.channel_transaction_parameters.opt_anchors;
+		let mut res = self.feerate_per_kw as u64 * cmp::max(chan_utils::htlc_tx_weight(true, opt_anchors), chan_utils::htlc_tx_weight(false, opt_anchors)) / 1000;
 
 		if self.is_outbound() {
 			// + the marginal fee increase cost to us in the commitment transaction:
@@ -3264,6 +4995,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		(self.channel_state & mask) == (ChannelState::ChannelFunded as u32)
 	}
 
+	/// Returns true if both sides have sent funding_locked, regardless of whether the channel is
+	/// closing or the peer is connected.
+	/// Allowed in any state (including after shutdown)
+	pub fn is_funding_locked(&self) -> bool {
+		(self.channel_state & ChannelState::ChannelFunded as u32) != 0
+	}
+
 	/// Returns true if this channel is currently available for use. This is a superset of
 	/// is_usable() and considers things like the channel being temporarily disabled.
 	/// Allowed in any state (including after shutdown)
@@ -3282,6 +5020,16 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		self.channel_state >= ChannelState::FundingSent as u32
 	}
 
+	/// Ages a channel which hasn't been funded yet by one timer tick. Returns true once it has
+	/// waited UNFUNDED_CHANNEL_AGE_LIMIT_TICKS ticks, at which point it should be forgotten.
+	pub fn unfunded_timer_tick(&mut self) -> bool {
+		if self.is_funding_initiated() {
+			return false;
+		}
+		self.unfunded_channel_age_ticks += 1;
+		self.unfunded_channel_age_ticks >= UNFUNDED_CHANNEL_AGE_LIMIT_TICKS
+	}
+
 	/// Returns true if this channel is fully shut down. True here implies that no further actions
 	/// may/will be taken on this channel, and thus this object should be freed. Any future changes
 	/// will be handled appropriately by the chain monitor.
@@ -3322,11 +5070,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	/// If we return Err, the channel may have been closed, at which point the standard
 	/// requirements apply - no calls may be made except those explicitly stated to be allowed
 	/// post-shutdown.
-	/// Only returns an ErrorAction of DisconnectPeer, if Err.
+	/// Only returns an ErrorAction of DisconnectPeer, if Err, along with the reason the channel was
+	/// closed.
 	///
 	/// May return some HTLCs (and their payment_hash) which have timed out and should be failed
 	/// back.
-	pub fn block_connected(&mut self, header: &BlockHeader, txdata: &TransactionData, height: u32) -> Result<(Option<msgs::FundingLocked>, Vec<(HTLCSource, PaymentHash)>), msgs::ErrorMessage> {
+	pub fn block_connected(&mut self, header: &BlockHeader, txdata: &TransactionData, height: u32) -> Result<(Option<msgs::FundingLocked>, Option<msgs::SpliceLocked>, Vec<(HTLCSource, PaymentHash)>), (msgs::ErrorMessage, ClosureReason)> {
 		let mut timed_out_htlcs = Vec::new();
 		self.holding_cell_htlc_updates.retain(|htlc_update| {
 			match htlc_update {
@@ -3345,7 +5094,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 				self.funding_tx_confirmations += 1;
 			}
 		}
-		if non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) == ChannelState::FundingSent as u32 {
+		// Zero-conf channels have locked their funding before it confirms, but still need to learn
+		// their short_channel_id.
+		let zero_conf_unconfirmed = self.minimum_depth == 0 && self.funding_tx_confirmations == 0 &&
+			non_shutdown_state >= ChannelState::FundingSent as u32 && self.channel_state < ChannelState::ShutdownComplete as u32;
+		if non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) == ChannelState::FundingSent as u32 || zero_conf_unconfirmed {
 			for &(index_in_block, tx) in txdata.iter() {
 				let funding_txo = self.get_funding_txo().unwrap();
 				if tx.txid() == funding_txo.txid {
@@ -3363,10 +5116,11 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 						}
 						self.channel_state = ChannelState::ShutdownComplete as u32;
 						self.update_time_counter += 1;
-						return Err(msgs::ErrorMessage {
+						let err = "funding tx had wrong script/value".to_owned();
+						return Err((msgs::ErrorMessage {
 							channel_id: self.channel_id(),
-							data: "funding tx had wrong script/value".to_owned()
-						});
+							data: err.clone(),
+						}, ClosureReason::ProcessingError { err }));
 					} else {
 						if self.is_outbound() {
 							for input in tx.input.iter() {
@@ -3386,69 +5140,189 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 						self.short_channel_id = Some(((height as u64)         << (5*8)) |
 						                             ((index_in_block as u64) << (2*8)) |
 						                             ((txo_idx as u64)        << (0*8)));
+						self.funding_transaction = Some((*tx).clone());
+					}
+				}
+			}
+		}
+		if !self.is_outbound() && self.minimum_depth > 0 && self.funding_tx_confirmations == 0 &&
+				non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) == ChannelState::FundingSent as u32 {
+			let funding_conf_deadline = *self.funding_conf_deadline.get_or_insert(height + FUNDING_CONF_DEADLINE_BLOCKS);
+			if height >= funding_conf_deadline {
+				self.channel_state = ChannelState::ShutdownComplete as u32;
+				self.update_time_counter += 1;
+				return Err((msgs::ErrorMessage {
+					channel_id: self.channel_id(),
+					data: "Funding transaction failed to confirm in time".to_owned(),
+				}, ClosureReason::FundingTimedOut));
+			}
+		}
+		let mut splice_locked = None;
+		if let Some(ref mut splice) = self.pending_splice {
+			if header.block_hash() != self.last_block_connected && splice.funding_tx_confirmations > 0 {
+				splice.funding_tx_confirmations += 1;
+			}
+			if let Some(funding_txo) = splice.funding_txo() {
+				for &(index_in_block, tx) in txdata.iter() {
+					if tx.txid() == funding_txo.txid {
+						// We built the splice transaction with our counterparty, so its funding
+						// output matches what we expect.
+						if height > 0xff_ff_ff || (index_in_block) > 0xff_ff_ff {
+							panic!("Block was bogus - either height 16 million or had > 16 million transactions");
+						}
+						splice.funding_tx_confirmations = 1;
+						splice.short_channel_id = Some(((height as u64)            << (5*8)) |
+						                               ((index_in_block as u64)    << (2*8)) |
+						                               ((funding_txo.index as u64) << (0*8)));
+						if splice.funding_transaction.is_none() {
+							// Our counterparty may broadcast it before we get its tx_signatures.
+							splice.funding_transaction = Some((*tx).clone());
+						}
 					}
 				}
 			}
+			if splice.funding_tx_confirmations > 0 && splice.funding_tx_confirmations >= self.minimum_depth as u64 && !splice.sent_splice_locked {
+				splice.sent_splice_locked = true;
+				splice.funding_tx_confirmed_in = Some(header.block_hash());
+				splice_locked = Some(msgs::SpliceLocked {
+					channel_id: self.channel_id,
+					splice_txid: splice.funding_txo().unwrap().txid,
+				});
+			}
 		}
+		self.maybe_complete_splice();
 		if header.block_hash() != self.last_block_connected {
 			self.last_block_connected = header.block_hash();
 			self.update_time_counter = cmp::max(self.update_time_counter, header.time);
 			if self.funding_tx_confirmations > 0 {
 				if self.funding_tx_confirmations == self.minimum_depth as u64 {
-					let need_commitment_update = if non_shutdown_state == ChannelState::FundingSent as u32 {
-						self.channel_state |= ChannelState::OurFundingLocked as u32;
-						true
-					} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::TheirFundingLocked as u32) {
-						self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & MULTI_STATE_FLAGS);
-						self.update_time_counter += 1;
-						true
-					} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32) {
-						// We got a reorg but not enough to trigger a force close, just update
-						// funding_tx_confirmed_in and return.
-						false
-					} else if self.channel_state < ChannelState::ChannelFunded as u32 {
-						panic!("Started confirming a channel in a state pre-FundingSent?: {}", self.channel_state);
-					} else {
-						// We got a reorg but not enough to trigger a force close, just update
-						// funding_tx_confirmed_in and return.
-						false
-					};
 					self.funding_tx_confirmed_in = Some(self.last_block_connected);
-
-					//TODO: Note that this must be a duplicate of the previous commitment point they sent us,
-					//as otherwise we will have a commitment transaction that they can't revoke (well, kinda,
-					//they can by sending two revoke_and_acks back-to-back, but not really). This appears to be
-					//a protocol oversight, but I assume I'm just missing something.
-					if need_commitment_update {
-						if self.channel_state & (ChannelState::MonitorUpdateFailed as u32) == 0 {
-							let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number, &self.secp_ctx);
-							return Ok((Some(msgs::FundingLocked {
-								channel_id: self.channel_id,
-								next_per_commitment_point,
-							}), timed_out_htlcs));
-						} else {
-							self.monitor_pending_funding_locked = true;
-							return Ok((None, timed_out_htlcs));
-						}
-					}
+					let funding_locked = self.lock_funding();
+					return Ok((funding_locked, splice_locked, timed_out_htlcs));
 				}
 			}
 		}
-		Ok((None, timed_out_htlcs))
+		Ok((None, splice_locked, timed_out_htlcs))
+	}
+
+	/// Marks the funding transaction as locked from our side, returning the funding_locked to send
+	/// unless we're waiting on a monitor update (in which case it is sent once the update
+	/// completes) or already sent it before a reorg.
+	fn lock_funding(&mut self) -> Option<msgs::FundingLocked> {
+		let non_shutdown_state = self.channel_state & (!MULTI_STATE_FLAGS);
+		let need_commitment_update = if non_shutdown_state == ChannelState::FundingSent as u32 {
+			self.channel_state |= ChannelState::OurFundingLocked as u32;
+			true
+		} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::TheirFundingLocked as u32) {
+			self.channel_state = ChannelState::ChannelFunded as u32 | (self.channel_state & MULTI_STATE_FLAGS);
+			self.update_time_counter += 1;
+			true
+		} else if non_shutdown_state == (ChannelState::FundingSent as u32 | ChannelState::OurFundingLocked as u32) {
+			// We got a reorg but not enough to trigger a force close, just update
+			// funding_tx_confirmed_in and return.
+			false
+		} else if self.channel_state < ChannelState::ChannelFunded as u32 {
+			panic!("Started confirming a channel in a state pre-FundingSent?: {}", self.channel_state);
+		} else {
+			// We got a reorg but not enough to trigger a force close, just update
+			// funding_tx_confirmed_in and return.
+			false
+		};
+
+		//TODO: Note that this must be a duplicate of the previous commitment point they sent us,
+		//as otherwise we will have a commitment transaction that they can't revoke (well, kinda,
+		//they can by sending two revoke_and_acks back-to-back, but not really). This appears to be
+		//a protocol oversight, but I assume I'm just missing something.
+		if need_commitment_update {
+			if self.channel_state & (ChannelState::MonitorUpdateFailed as u32) == 0 {
+				let next_per_commitment_point = self.holder_keys.get_per_commitment_point(self.cur_holder_commitment_transaction_number, &self.secp_ctx);
+				return Some(msgs::FundingLocked {
+					channel_id: self.channel_id,
+					next_per_commitment_point,
+					short_channel_id_alias: if self.is_zero_conf() { Some(self.scid_alias) } else { None },
+				});
+			} else {
+				self.monitor_pending_funding_locked = true;
+			}
+		}
+		None
+	}
+
+	/// Marks the funding of a zero-conf channel as locked as soon as the funding transaction is
+	/// fully signed, returning the funding_locked to send. Does nothing for other channels, which
+	/// lock once the funding transaction reaches minimum_depth in block_connected.
+	pub fn check_get_zero_conf_funding_locked(&mut self) -> Option<msgs::FundingLocked> {
+		if self.minimum_depth != 0 || self.funding_tx_signing.is_some() {
+			return None;
+		}
+		let non_shutdown_state = self.channel_state & (!MULTI_STATE_FLAGS);
+		if non_shutdown_state & !(ChannelState::TheirFundingLocked as u32) != ChannelState::FundingSent as u32 {
+			return None;
+		}
+		self.lock_funding()
+	}
+
+	/// Returns true if the given transaction double-spends our funding transaction, in which case
+	/// the channel can never be funded. Only possible for zero-conf channels or after a reorg,
+	/// and only detectable once we know the funding transaction, ie once we constructed it
+	/// interactively, it was provided via provide_funding_transaction, or we saw it in a block.
+	pub fn is_funding_double_spent_by(&self, tx: &Transaction) -> bool {
+		let funding_transaction = match (&self.funding_transaction, &self.funding_tx_signing, &self.pending_splice) {
+			(&Some(ref funding_transaction), _, _) => funding_transaction,
+			(&None, &Some(ref funding_tx_signing), &None) => &funding_tx_signing.tx,
+			_ => return false,
+		};
+		if funding_transaction.txid() != tx.txid() {
+			return tx.input.iter().any(|input| funding_transaction.input.iter().any(|funding_input| funding_input.previous_output == input.previous_output));
+		}
+		false
+	}
+
+	/// Records our funding transaction, which we otherwise only learn of once it confirms unless
+	/// we constructed it interactively, so that we can detect it being double-spent while it is
+	/// unconfirmed. Returns the ChannelMonitorUpdate providing its inputs to our ChannelMonitor.
+	pub fn provide_funding_transaction(&mut self, funding_transaction: Transaction) -> Result<ChannelMonitorUpdate, ChannelError> {
+		let funding_txo = match self.get_funding_txo() {
+			Some(funding_txo) => funding_txo,
+			None => return Err(ChannelError::Ignore("Channel isn't funded yet".to_owned())),
+		};
+		if funding_transaction.txid() != funding_txo.txid {
+			return Err(ChannelError::Ignore("Transaction isn't the funding transaction of the channel".to_owned()));
+		}
+		if self.funding_transaction.is_some() || self.funding_tx_signing.is_some() {
+			return Err(ChannelError::Ignore("The funding transaction of the channel is already known".to_owned()));
+		}
+		let inputs = funding_transaction.input.iter().map(|input| input.previous_output).collect();
+		self.funding_transaction = Some(funding_transaction);
+		self.latest_monitor_update_id += 1;
+		Ok(ChannelMonitorUpdate {
+			update_id: self.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::FundingTransactionInputs { inputs }],
+		})
 	}
 
 	/// Called by channelmanager based on chain blocks being disconnected.
 	/// Returns true if we need to close the channel now due to funding transaction
 	/// unconfirmation/reorg.
 	pub fn block_disconnected(&mut self, header: &BlockHeader) -> bool {
+		if let Some(ref mut splice) = self.pending_splice {
+			if splice.funding_tx_confirmations > 0 {
+				splice.funding_tx_confirmations -= 1;
+			}
+		}
 		if self.funding_tx_confirmations > 0 {
 			self.funding_tx_confirmations -= 1;
 			if self.funding_tx_confirmations == UNCONF_THRESHOLD as u64 {
 				return true;
 			}
+			if self.funding_tx_confirmations == 0 && self.minimum_depth == 0 {
+				// Zero-conf channels remain usable through their alias until the funding
+				// transaction confirms again.
+				self.short_channel_id = None;
+			}
 		}
 		self.last_block_connected = header.block_hash();
-		if Some(self.last_block_connected) == self.funding_tx_confirmed_in {
+		if self.minimum_depth > 0 && Some(self.last_block_connected) == self.funding_tx_confirmed_in {
 			self.funding_tx_confirmations = self.minimum_depth as u64 - 1;
 		}
 		false
@@ -3499,6 +5373,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.is_outbound() {
 			panic!("Tried to send accept_channel for an outbound channel?");
 		}
+		if self.inbound_awaiting_accept {
+			panic!("Tried to send accept_channel for a channel which the user has not accepted");
+		}
 		if self.channel_state != (ChannelState::OurInitSent as u32) | (ChannelState::TheirInitSent as u32) {
 			panic!("Tried to send accept_channel after channel had moved forward");
 		}
@@ -3528,7 +5405,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 	}
 
-	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created)
+	/// If an Err is returned, it is a ChannelError::Close (for get_outbound_funding_created and
+	/// get_initial_commitment_signed)
 	fn get_outbound_funding_created_signature<L: Deref>(&mut self, logger: &L) -> Result<Signature, ChannelError> where L::Target: Logger {
 		let counterparty_keys = self.build_remote_transaction_keys()?;
 		let counterparty_initial_commitment_tx = self.build_commitment_transaction(self.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, false, self.feerate_per_kw, logger).0;
@@ -3601,6 +5479,9 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if (self.channel_state & (ChannelState::LocalShutdownSent as u32 | ChannelState::ShutdownComplete as u32)) != 0 {
 			return Err(ChannelError::Ignore("Cannot get a ChannelAnnouncement once the channel is closing".to_owned()));
 		}
+		if self.short_channel_id.is_none() {
+			return Err(ChannelError::Ignore("Cannot get a ChannelAnnouncement until the funding transaction confirms".to_owned()));
+		}
 
 		let were_node_one = node_id.serialize()[..] < self.counterparty_node_id.serialize()[..];
 
@@ -3752,7 +5633,7 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		}
 
 		// Now update local state:
-		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) == (ChannelState::AwaitingRemoteRevoke as u32) {
+		if (self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32)) == (ChannelState::AwaitingRemoteRevoke as u32) || self.is_quiescing() {
 			self.holding_cell_htlc_updates.push(HTLCUpdateAwaitingACK::AddHTLC {
 				amount_msat,
 				payment_hash,
@@ -3899,8 +5780,8 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 			for (ref htlc_sig, ref htlc) in htlc_signatures.iter().zip(htlcs) {
 				log_trace!(logger, "Signed remote HTLC tx {} with redeemscript {} with pubkey {} -> {}",
-					encode::serialize_hex(&chan_utils::build_htlc_transaction(&counterparty_commitment_txid, feerate_per_kw, self.get_holder_selected_contest_delay(), htlc, &counterparty_keys.broadcaster_delayed_payment_key, &counterparty_keys.revocation_key)),
-					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, &counterparty_keys)),
+					encode::serialize_hex(&chan_utils::build_htlc_transaction(&counterparty_commitment_txid, feerate_per_kw, self.get_holder_selected_contest_delay(), htlc, self.channel_transaction_parameters.opt_anchors, &counterparty_keys.broadcaster_delayed_payment_key, &counterparty_keys.revocation_key)),
+					encode::serialize_hex(&chan_utils::get_htlc_redeemscript(&htlc, self.channel_transaction_parameters.opt_anchors, &counterparty_keys)),
 					log_bytes!(counterparty_keys.broadcaster_htlc_key.serialize()),
 					log_bytes!(htlc_sig.serialize_compact()[..]));
 			}
@@ -3929,7 +5810,13 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 
 	/// Begins the shutdown process, getting a message for the remote peer and returning all
 	/// holding cell HTLCs for payment failure.
-	pub fn get_shutdown(&mut self) -> Result<(msgs::Shutdown, Vec<(HTLCSource, PaymentHash)>), APIError> {
+	///
+	/// If given, our funds are paid to destination and the closing transaction fee is negotiated
+	/// within fee_limits, a (target feerate per kw, maximum total fee in satoshis) pair.
+	pub fn get_shutdown(&mut self, destination: Option<Script>, fee_limits: Option<(u32, u64)>) -> Result<(msgs::Shutdown, Vec<(HTLCSource, PaymentHash)>), APIError> {
+		if self.is_quiescing() {
+			return Err(APIError::ChannelUnavailable{err: "Cannot begin shutdown while splicing the channel".to_owned()});
+		}
 		for htlc in self.pending_outbound_htlcs.iter() {
 			if let OutboundHTLCState::LocalAnnounced(_) = htlc.state {
 				return Err(APIError::APIMisuseError{err: "Cannot begin shutdown with pending HTLCs. Process pending events first".to_owned()});
@@ -3947,10 +5834,32 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 		if self.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateFailed as u32) != 0 {
 			return Err(APIError::ChannelUnavailable{err: "Cannot begin shutdown while peer is disconnected or we're waiting on a monitor update, maybe force-close instead?".to_owned()});
 		}
+		if let Some(ref script) = destination {
+			// BOLT 2 only allows these forms, and if we committed to an upfront shutdown script our
+			// counterparty will refuse any other.
+			if !script.is_p2pkh() && !script.is_p2sh() && !script.is_v0_p2wpkh() && !script.is_v0_p2wsh() {
+				return Err(APIError::APIMisuseError{err: format!("Cannot close to a nonstandard scriptpubkey ({})", script.to_bytes().to_hex())});
+			}
+			if self.config.commit_upfront_shutdown_pubkey && *script != self.get_closing_scriptpubkey() {
+				return Err(APIError::APIMisuseError{err: format!("Cannot close to a scriptpubkey ({}) other than the upfront shutdown script we committed to", script.to_bytes().to_hex())});
+			}
+		}
+		if let Some((target_feerate, _)) = fee_limits {
+			if target_feerate < 253 {
+				return Err(APIError::APIMisuseError{err: format!("Target closing feerate {} is below the minimum relay feerate", target_feerate)});
+			}
+		}
 
+		// From here on out, we may not fail!
+		if destination.is_some() {
+			self.shutdown_scriptpubkey = destination;
+		}
+		if let Some((target_feerate, max_fee)) = fee_limits {
+			self.closing_target_feerate_per_kw = Some(target_feerate);
+			self.closing_max_fee_satoshis = Some(max_fee);
+		}
 		let closing_script = self.get_closing_scriptpubkey();
 
-		// From here on out, we may not fail!
 		if self.channel_state < ChannelState::FundingSent as u32 {
 			self.channel_state = ChannelState::ShutdownComplete as u32;
 		} else {
@@ -4020,8 +5929,12 @@ impl<ChanSigner: ChannelKeys> Channel<ChanSigner> {
 	}
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+// Version 2 appended the fields following commitment_secrets and extended the ChannelConfig and
+// ChannelTransactionParameters layouts, all of which we fill in with defaults when reading version
+// 1 data. As those layouts changed mid-record, version 1 readers can't skip the new fields and
+// must refuse version 2 data.
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 impl Writeable for InboundHTLCRemovalReason {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -4239,6 +6152,59 @@ impl<ChanSigner: ChannelKeys> Writeable for Channel<ChanSigner> {
 		self.counterparty_shutdown_scriptpubkey.write(writer)?;
 
 		self.commitment_secrets.write(writer)?;
+
+		// A splice is only persisted once we signed its transaction, see
+		// remove_uncommitted_htlcs_and_mark_paused.
+		let splice_signed = self.is_splice_signed();
+		if self.pending_splice.is_some() && !splice_signed {
+			None::<FundingTxSigning>.write(writer)?;
+		} else {
+			self.funding_tx_signing.write(writer)?;
+		}
+
+		self.funding_transaction.write(writer)?;
+		self.original_funding_txo.write(writer)?;
+		match self.pending_splice {
+			Some(ref splice) if splice_signed => {
+				1u8.write(writer)?;
+				splice.is_initiator.write(writer)?;
+				(splice.holder_relative_satoshis as u64).write(writer)?;
+				(splice.counterparty_relative_satoshis.unwrap() as u64).write(writer)?;
+				let funding = splice.funding.as_ref().unwrap();
+				funding.channel_value_satoshis.write(writer)?;
+				funding.value_to_self_msat.write(writer)?;
+				funding.channel_transaction_parameters.write(writer)?;
+				splice.received_commitment_signed.write(writer)?;
+				splice.funding_transaction.write(writer)?;
+				splice.funding_tx_confirmations.write(writer)?;
+				splice.funding_tx_confirmed_in.write(writer)?;
+				splice.short_channel_id.write(writer)?;
+				splice.sent_splice_locked.write(writer)?;
+				splice.received_splice_locked.write(writer)?;
+			},
+			_ => 0u8.write(writer)?,
+		}
+		(self.pre_splice_short_channel_ids.len() as u64).write(writer)?;
+		for &(short_channel_id, next_htlc_id) in self.pre_splice_short_channel_ids.iter() {
+			short_channel_id.write(writer)?;
+			next_htlc_id.write(writer)?;
+		}
+		self.max_funding_satoshis.write(writer)?;
+		self.scid_alias.write(writer)?;
+		self.counterparty_scid_alias.write(writer)?;
+		self.shutdown_scriptpubkey.write(writer)?;
+		self.closing_target_feerate_per_kw.write(writer)?;
+		self.closing_max_fee_satoshis.write(writer)?;
+		self.funding_conf_deadline.write(writer)?;
+		self.counterparty_forwarding_info.write(writer)?;
+		match self.prev_config {
+			Some((ref prev_config, ticks)) => {
+				1u8.write(writer)?;
+				prev_config.write(writer)?;
+				(ticks as u64).write(writer)?;
+			},
+			None => 0u8.write(writer)?,
+		}
 		Ok(())
 	}
 }
@@ -4247,14 +6213,14 @@ const MAX_ALLOC_SIZE: usize = 64*1024;
 impl<'a, ChanSigner: ChannelKeys, K: Deref> ReadableArgs<&'a K> for Channel<ChanSigner>
 		where K::Target: KeysInterface<ChanKeySigner = ChanSigner> {
 	fn read<R : ::std::io::Read>(reader: &mut R, keys_source: &'a K) -> Result<Self, DecodeError> {
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
 		}
 
 		let user_id = Readable::read(reader)?;
-		let config: ChannelConfig = Readable::read(reader)?;
+		let config = if ver >= 2 { Readable::read(reader)? } else { ChannelConfig::read_v1(reader)? };
 
 		let channel_id = Readable::read(reader)?;
 		let channel_state = Readable::read(reader)?;
@@ -4392,7 +6358,11 @@ impl<'a, ChanSigner: ChannelKeys, K: Deref> ReadableArgs<&'a K> for Channel<Chan
 		let counterparty_max_accepted_htlcs = Readable::read(reader)?;
 		let minimum_depth = Readable::read(reader)?;
 
-		let channel_parameters = Readable::read(reader)?;
+		let channel_parameters = if ver >= 2 { Readable::read(reader)? } else {
+			let mut channel_parameters = ChannelTransactionParameters::read_v1(reader)?;
+			channel_parameters.channel_value_satoshis = channel_value_satoshis;
+			channel_parameters
+		};
 		let counterparty_cur_commitment_point = Readable::read(reader)?;
 
 		let counterparty_prev_commitment_point = Readable::read(reader)?;
@@ -4401,10 +6371,85 @@ impl<'a, ChanSigner: ChannelKeys, K: Deref> ReadableArgs<&'a K> for Channel<Chan
 		let counterparty_shutdown_scriptpubkey = Readable::read(reader)?;
 		let commitment_secrets = Readable::read(reader)?;
 
+		let mut funding_tx_signing = None;
+		let mut funding_transaction = None;
+		let mut original_funding_txo = None;
+		let mut pending_splice = None;
+		let mut pre_splice_short_channel_ids = Vec::new();
+		let mut max_funding_satoshis = MAX_FUNDING_SATOSHIS_NO_WUMBO;
+		let mut scid_alias = byte_utils::slice_to_be64(&keys_source.get_secure_random_bytes()[24..32]);
+		let mut counterparty_scid_alias = None;
+		let mut shutdown_scriptpubkey = None;
+		let mut closing_target_feerate_per_kw = None;
+		let mut closing_max_fee_satoshis = None;
+		let mut funding_conf_deadline = None;
+		let mut counterparty_forwarding_info = None;
+		let mut prev_config = None;
+		if ver >= 2 {
+			funding_tx_signing = Readable::read(reader)?;
+
+			funding_transaction = Readable::read(reader)?;
+			original_funding_txo = Readable::read(reader)?;
+			pending_splice = match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => {
+					let is_initiator = Readable::read(reader)?;
+					let holder_relative_satoshis: u64 = Readable::read(reader)?;
+					let counterparty_relative_satoshis: u64 = Readable::read(reader)?;
+					let channel_value_satoshis = Readable::read(reader)?;
+					let value_to_self_msat = Readable::read(reader)?;
+					let channel_transaction_parameters: ChannelTransactionParameters = Readable::read(reader)?;
+					let mut splice_holder_keys = holder_keys.clone();
+					splice_holder_keys.splice_channel(&channel_transaction_parameters);
+					Some(PendingSplice {
+						is_initiator,
+						holder_relative_satoshis: holder_relative_satoshis as i64,
+						counterparty_relative_satoshis: Some(counterparty_relative_satoshis as i64),
+						funding: Some(SpliceFunding {
+							channel_value_satoshis,
+							value_to_self_msat,
+							channel_transaction_parameters,
+							holder_keys: splice_holder_keys,
+						}),
+						received_commitment_signed: Readable::read(reader)?,
+						funding_transaction: Readable::read(reader)?,
+						funding_tx_confirmations: Readable::read(reader)?,
+						funding_tx_confirmed_in: Readable::read(reader)?,
+						short_channel_id: Readable::read(reader)?,
+						sent_splice_locked: Readable::read(reader)?,
+						received_splice_locked: Readable::read(reader)?,
+					})
+				},
+				_ => return Err(DecodeError::InvalidValue),
+			};
+			let pre_splice_short_channel_id_count: u64 = Readable::read(reader)?;
+			for _ in 0..pre_splice_short_channel_id_count {
+				pre_splice_short_channel_ids.push((Readable::read(reader)?, Readable::read(reader)?));
+			}
+			max_funding_satoshis = Readable::read(reader)?;
+			scid_alias = Readable::read(reader)?;
+			counterparty_scid_alias = Readable::read(reader)?;
+			shutdown_scriptpubkey = Readable::read(reader)?;
+			closing_target_feerate_per_kw = Readable::read(reader)?;
+			closing_max_fee_satoshis = Readable::read(reader)?;
+			funding_conf_deadline = Readable::read(reader)?;
+			counterparty_forwarding_info = Readable::read(reader)?;
+			prev_config = match <u8 as Readable>::read(reader)? {
+				0 => None,
+				1 => {
+					let prev_config = Readable::read(reader)?;
+					let ticks: u64 = Readable::read(reader)?;
+					Some((prev_config, ticks as usize))
+				},
+				_ => return Err(DecodeError::InvalidValue),
+			};
+		}
+
 		Ok(Channel {
 			user_id,
 
 			config,
+			prev_config,
 			channel_id,
 			channel_state,
 			secp_ctx: Secp256k1::new(),
@@ -4471,6 +6516,28 @@ impl<'a, ChanSigner: ChannelKeys, K: Deref> ReadableArgs<&'a K> for Channel<Chan
 			commitment_secrets,
 
 			network_sync: UpdateStatus::Fresh,
+
+			dual_funding_contribution: None,
+			interactive_tx_constructor: None,
+			funding_tx_signing,
+			funding_transaction,
+			original_funding_txo,
+			splice_contribution: None,
+			sent_stfu: false,
+			received_stfu: false,
+			holder_is_quiescence_initiator: false,
+			pending_splice,
+			pre_splice_short_channel_ids,
+			max_funding_satoshis,
+			scid_alias,
+			counterparty_scid_alias,
+			shutdown_scriptpubkey,
+			closing_target_feerate_per_kw,
+			closing_max_fee_satoshis,
+			inbound_awaiting_accept: false,
+			funding_conf_deadline,
+			unfunded_channel_age_ticks: 0,
+			counterparty_forwarding_info,
 		})
 	}
 }
@@ -4488,7 +6555,7 @@ mod tests {
 	use hex;
 	use ln::channelmanager::{HTLCSource, PaymentPreimage, PaymentHash};
 	use ln::channel::{Channel,ChannelKeys,InboundHTLCOutput,OutboundHTLCOutput,InboundHTLCState,OutboundHTLCState,HTLCOutputInCommitment,TxCreationKeys};
-	use ln::channel::MAX_FUNDING_SATOSHIS;
+	use ln::channel::{MAX_FUNDING_SATOSHIS_NO_WUMBO, TOTAL_BITCOIN_SUPPLY_SATOSHIS};
 	use ln::features::InitFeatures;
 	use ln::msgs::{OptionalField, DataLossProtect, DecodeError};
 	use ln::chan_utils;
@@ -4500,6 +6567,7 @@ mod tests {
 	use util::enforcing_trait_impls::EnforcingChannelKeys;
 	use util::test_utils;
 	use util::logger::Logger;
+	use util::ser::{ReadableArgs, Writeable};
 	use bitcoin::secp256k1::{Secp256k1, Message, Signature, All};
 	use bitcoin::secp256k1::key::{SecretKey,PublicKey};
 	use bitcoin::hashes::sha256::Hash as Sha256;
@@ -4517,9 +6585,10 @@ mod tests {
 	}
 
 	#[test]
-	fn test_max_funding_satoshis() {
-		assert!(MAX_FUNDING_SATOSHIS <= 21_000_000 * 100_000_000,
-		        "MAX_FUNDING_SATOSHIS is greater than all satoshis in existence");
+	fn test_max_funding_satoshis_no_wumbo() {
+		assert_eq!(TOTAL_BITCOIN_SUPPLY_SATOSHIS, 21_000_000 * 100_000_000);
+		assert!(MAX_FUNDING_SATOSHIS_NO_WUMBO <= TOTAL_BITCOIN_SUPPLY_SATOSHIS,
+		        "MAX_FUNDING_SATOSHIS_NO_WUMBO is greater than all satoshis in existence");
 	}
 
 	struct Keys {
@@ -4566,7 +6635,7 @@ mod tests {
 
 		let node_a_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let config = UserConfig::default();
-		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_a_node_id, 10000000, 100000, 42, &config).unwrap();
+		let node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_a_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
 
 		// Now change the fee so we can check that the fee in the open_channel message is the
 		// same as the old fee.
@@ -4575,6 +6644,29 @@ mod tests {
 		assert_eq!(open_channel_msg.feerate_per_kw, original_fee);
 	}
 
+	#[test]
+	fn test_prev_config_serialization() {
+		// Check that the forwarding policy we replaced, and how long ago we did, survive a restart
+		// so HTLCs paying it are still accepted until it expires.
+		let fee_est = TestFeeEstimator{fee_est: 253 };
+		let secp_ctx = Secp256k1::new();
+		let keys_provider = test_utils::TestKeysInterface::new(&[42; 32], Network::Testnet);
+		let node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let config = UserConfig::default();
+		let mut chan = Channel::<EnforcingChannelKeys>::new_outbound(&&fee_est, &&keys_provider, node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
+
+		let mut new_config = config.channel_options;
+		new_config.fee_proportional_millionths += 1;
+		assert!(chan.update_config(&new_config));
+		chan.maybe_expire_prev_config();
+
+		let read_chan = Channel::<EnforcingChannelKeys>::read(&mut ::std::io::Cursor::new(chan.encode()), &&keys_provider).unwrap();
+		assert_eq!(read_chan.config.fee_proportional_millionths, new_config.fee_proportional_millionths);
+		let (prev_config, ticks) = read_chan.prev_config.unwrap();
+		assert_eq!(prev_config.fee_proportional_millionths, config.channel_options.fee_proportional_millionths);
+		assert_eq!(ticks, 1);
+	}
+
 	#[test]
 	fn channel_reestablish_no_updates() {
 		let feeest = TestFeeEstimator{fee_est: 15000};
@@ -4589,7 +6681,7 @@ mod tests {
 		// Create Node A's channel pointing to Node B's pubkey
 		let node_b_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let config = UserConfig::default();
-		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&feeest, &&keys_provider, node_b_node_id, 10000000, 100000, 42, &config).unwrap();
+		let mut node_a_chan = Channel::<EnforcingChannelKeys>::new_outbound(&&feeest, &&keys_provider, node_b_node_id, &InitFeatures::known(), 10000000, 100000, 42, &config).unwrap();
 
 		// Create Node B's channel by receiving Node A's open_channel message
 		let open_channel_msg = node_a_chan.get_open_channel(genesis_block(network).header.block_hash());
@@ -4667,7 +6759,7 @@ mod tests {
 		let counterparty_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
 		let mut config = UserConfig::default();
 		config.channel_options.announced_channel = false;
-		let mut chan = Channel::<InMemoryChannelKeys>::new_outbound(&&feeest, &&keys_provider, counterparty_node_id, 10_000_000, 100000, 42, &config).unwrap(); // Nothing uses their network key in this test
+		let mut chan = Channel::<InMemoryChannelKeys>::new_outbound(&&feeest, &&keys_provider, counterparty_node_id, &InitFeatures::known(), 10_000_000, 100000, 42, &config).unwrap(); // Nothing uses their network key in this test
 		chan.holder_dust_limit_satoshis = 546;
 
 		let funding_info = OutPoint{ txid: Txid::from_hex("8984484a580b825b9972d7adb15050b3ab624ccd731946b3eeddb92f4e7ef6be").unwrap(), index: 0 };
@@ -4759,7 +6851,7 @@ mod tests {
 
 					let ref htlc = htlcs[$htlc_idx];
 					let htlc_tx = chan.build_htlc_transaction(&unsigned_tx.txid, &htlc, true, &keys, chan.feerate_per_kw);
-					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, &keys);
+					let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&htlc, false, &keys);
 					let htlc_sighash = Message::from_slice(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, SigHashType::All)[..]).unwrap();
 					secp_ctx.verify(&htlc_sighash, &remote_signature, &keys.countersignatory_htlc_key).unwrap();
 
@@ -20,6 +20,8 @@
 
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::network::constants::Network;
 
 use bitcoin::hashes::{Hash, HashEngine};
@@ -40,15 +42,17 @@ use chain::chaininterface::{BroadcasterInterface, FeeEstimator};
 use chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, ChannelMonitorUpdateErr, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY, MonitorEvent, CLOSED_CHANNEL_UPDATE_ID};
 use chain::transaction::{OutPoint, TransactionData};
 use ln::channel::{Channel, ChannelError};
-use ln::features::{InitFeatures, NodeFeatures};
-use routing::router::{Route, RouteHop};
+use ln::interactivetx::InteractiveTxMessage;
+use ln::features::InitFeatures;
+use routing::network_graph::RoutingFees;
+use routing::router::{Route, RouteHint, RouteHop, MAX_PATH_LENGTH};
 use ln::msgs;
 use ln::msgs::NetAddress;
 use ln::onion_utils;
 use ln::msgs::{ChannelMessageHandler, DecodeError, LightningError, OptionalField};
 use chain::keysinterface::{ChannelKeys, KeysInterface, KeysManager, InMemoryChannelKeys};
-use util::config::UserConfig;
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::config::{UserConfig, ChannelConfig};
+use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider, ClosureReason};
 use util::{byte_utils, events};
 use util::ser::{Readable, ReadableArgs, MaybeReadable, Writeable, Writer};
 use util::chacha20::{ChaCha20, ChaChaReader};
@@ -90,6 +94,10 @@ enum PendingHTLCRouting {
 	},
 	Receive {
 		payment_data: Option<msgs::FinalOnionHopData>,
+		/// Set if this is a spontaneous (keysend) payment for which the sender provided the preimage
+		keysend_preimage: Option<PaymentPreimage>,
+		/// Application-specific TLV records the sender included in the final hop's onion payload
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 		incoming_cltv_expiry: u32, // Used to track when we should expire pending HTLCs that go unclaimed
 	},
 }
@@ -167,6 +175,7 @@ pub(crate) enum HTLCSource {
 		/// Technically we can recalculate this from the route, but we cache it here to avoid
 		/// doing a double-pass on route when we get a failure back
 		first_hop_htlc_msat: u64,
+		payment_id: PaymentId,
 	},
 }
 #[cfg(test)]
@@ -176,6 +185,7 @@ impl HTLCSource {
 			path: Vec::new(),
 			session_priv: SecretKey::from_slice(&[1; 32]).unwrap(),
 			first_hop_htlc_msat: 0,
+			payment_id: PaymentId([2; 32]),
 		}
 	}
 }
@@ -203,6 +213,10 @@ pub struct PaymentPreimage(pub [u8;32]);
 /// (C-not exported) as we just use [u8; 32] directly
 #[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
 pub struct PaymentSecret(pub [u8;32]);
+/// payment_id type, use to track an outbound payment across its paths and retries
+/// (C-not exported) as we just use [u8; 32] directly
+#[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PaymentId(pub [u8;32]);
 
 type ShutdownResult = (Option<OutPoint>, ChannelMonitorUpdate, Vec<(HTLCSource, PaymentHash)>);
 
@@ -269,6 +283,15 @@ impl MsgHandleErrInternal {
 					err: msg,
 					action: msgs::ErrorAction::IgnoreError,
 				},
+				ChannelError::Warn(msg) => LightningError {
+					err: msg.clone(),
+					action: msgs::ErrorAction::DisconnectPeerWithWarning {
+						msg: msgs::WarningMessage {
+							channel_id,
+							data: msg
+						},
+					},
+				},
 				ChannelError::Close(msg) => LightningError {
 					err: msg.clone(),
 					action: msgs::ErrorAction::SendErrorMessage {
@@ -337,6 +360,66 @@ struct PeerState {
 	latest_features: InitFeatures,
 }
 
+/// An outbound payment we've sent at least one path of, tracked so that failed paths can be
+/// retried without risking paying more than the recipient asked for.
+struct PendingOutboundPayment {
+	payment_hash: PaymentHash,
+	payment_secret: Option<PaymentSecret>,
+	keysend_preimage: Option<PaymentPreimage>,
+	custom_tlvs: Vec<(u64, Vec<u8>)>,
+	/// The total value of the payment, as given to the recipient in each path's onion.
+	total_msat: u64,
+	/// session_priv -> the value delivered to the recipient, for each path which may still be
+	/// claimed by the recipient.
+	pending_paths: HashMap<[u8; 32], u64>,
+	/// Set once any path is claimed. No more paths may be sent after this.
+	fulfilled: bool,
+	/// Set by ChannelManager::abandon_payment. No more paths may be sent after this.
+	abandoned: bool,
+	/// For circular payments to ourselves sent via ChannelManager::send_rebalance, the preimage
+	/// with which we claim the payment once all of it arrives back at us.
+	rebalance_preimage: Option<PaymentPreimage>,
+	/// The number of timer ticks this payment has spent with all of its paths failed, see
+	/// FAILED_PAYMENT_EXPIRY_TICKS.
+	timer_ticks_without_paths: u8,
+}
+
+impl PendingOutboundPayment {
+	fn pending_amt_msat(&self) -> u64 {
+		self.pending_paths.values().sum()
+	}
+
+	/// Whether we're done with this payment and can forget about it, ie whether all of its paths
+	/// are resolved and it can't be retried.
+	fn is_complete(&self) -> bool {
+		self.pending_paths.is_empty() && (self.fulfilled || self.abandoned)
+	}
+}
+
+impl_writeable!(PendingOutboundPayment, 0, {
+	payment_hash,
+	payment_secret,
+	keysend_preimage,
+	custom_tlvs,
+	total_msat,
+	pending_paths,
+	fulfilled,
+	abandoned,
+	rebalance_preimage,
+	timer_ticks_without_paths
+});
+
+/// The number of calls to ChannelManager::timer_chan_freshness_every_min after which we forget
+/// a payment whose paths all failed if it wasn't retried in the mean time.
+pub(crate) const FAILED_PAYMENT_EXPIRY_TICKS: u8 = 10;
+
+/// The key under which a path of a PendingOutboundPayment is tracked.
+fn session_priv_bytes(session_priv: &SecretKey) -> [u8; 32] {
+	let mut res = [0; 32];
+	res.copy_from_slice(&session_priv[..]);
+	res
+}
+
 #[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
 const ERR: () = "You need at least 32 bit pointers (well, usize, but we'll assume they're the same) for ChannelManager::latest_block_height";
 
@@ -433,6 +516,14 @@ pub struct ChannelManager<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref,
 	/// new channel.
 	per_peer_state: RwLock<HashMap<PublicKey, Mutex<PeerState>>>,
 
+	/// Peers whose inbound channels we accept as zero-conf, i.e. usable before the funding
+	/// transaction confirms. This is configuration rather than state and thus isn't persisted.
+	trusted_zero_conf_peers: Mutex<HashSet<PublicKey>>,
+
+	/// The outbound payments which may still be retried or claimed, by payment id.
+	/// Never locked while taking the channel_state lock.
+	pending_outbound_payments: Mutex<HashMap<PaymentId, PendingOutboundPayment>>,
+
 	pending_events: Mutex<Vec<events::Event>>,
 	/// Used when we have to take a BIG lock to make sure everything is self-consistent.
 	/// Essentially just when we're serializing ourselves out.
@@ -450,12 +541,15 @@ pub(crate) const BREAKDOWN_TIMEOUT: u16 = 6 * 24;
 /// The amount of time we're willing to wait to claim money back to us
 pub(crate) const MAX_LOCAL_BREAKDOWN_TIMEOUT: u16 = 6 * 24 * 7;
 
+/// The default number of blocks between an inbound HTLC's CLTV and the corresponding outbound
+/// HTLC's CLTV, see ChannelConfig::cltv_expiry_delta.
+pub(crate) const CLTV_EXPIRY_DELTA: u16 = 6 * 12; //TODO?
 /// The minimum number of blocks between an inbound HTLC's CLTV and the corresponding outbound
 /// HTLC's CLTV. This should always be a few blocks greater than channelmonitor::CLTV_CLAIM_BUFFER,
 /// ie the node we forwarded the payment on to should always have enough room to reliably time out
 /// the HTLC via a full update_fail_htlc/commitment_signed dance before we hit the
 /// CLTV_CLAIM_BUFFER point (we static assert that it's at least 3 blocks more).
-const CLTV_EXPIRY_DELTA: u16 = 6 * 12; //TODO?
+pub(crate) const MIN_CLTV_EXPIRY_DELTA: u16 = 6 * 6;
 pub(super) const CLTV_FAR_FAR_AWAY: u32 = 6 * 24 * 7; //TODO?
 
 // Check that our CLTV_EXPIRY is at least CLTV_CLAIM_BUFFER + ANTI_REORG_DELAY + LATENCY_GRACE_PERIOD_BLOCKS,
@@ -466,13 +560,13 @@ pub(super) const CLTV_FAR_FAR_AWAY: u32 = 6 * 24 * 7; //TODO?
 // LATENCY_GRACE_PERIOD_BLOCKS.
 #[deny(const_err)]
 #[allow(dead_code)]
-const CHECK_CLTV_EXPIRY_SANITY: u32 = CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - CLTV_CLAIM_BUFFER - ANTI_REORG_DELAY - LATENCY_GRACE_PERIOD_BLOCKS;
+const CHECK_CLTV_EXPIRY_SANITY: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - CLTV_CLAIM_BUFFER - ANTI_REORG_DELAY - LATENCY_GRACE_PERIOD_BLOCKS;
 
 // Check for ability of an attacker to make us fail on-chain by delaying inbound claim. See
 // ChannelMontior::would_broadcast_at_height for a description of why this is needed.
 #[deny(const_err)]
 #[allow(dead_code)]
-const CHECK_CLTV_EXPIRY_SANITY_2: u32 = CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - 2*CLTV_CLAIM_BUFFER;
+const CHECK_CLTV_EXPIRY_SANITY_2: u32 = MIN_CLTV_EXPIRY_DELTA as u32 - LATENCY_GRACE_PERIOD_BLOCKS - 2*CLTV_CLAIM_BUFFER;
 
 /// Details of a channel, as returned by ChannelManager::list_channels and ChannelManager::list_usable_channels
 #[derive(Clone)]
@@ -485,6 +579,15 @@ pub struct ChannelDetails {
 	/// The position of the funding transaction in the chain. None if the funding transaction has
 	/// not yet been confirmed and the channel fully opened.
 	pub short_channel_id: Option<u64>,
+	/// The alias we sent our counterparty in funding_locked, which we accept HTLCs for in place
+	/// of short_channel_id. Zero-conf channels are only known by this until their funding
+	/// transaction confirms. None until we've sent funding_locked, or if the channel isn't
+	/// zero-conf.
+	pub scid_alias: Option<u64>,
+	/// The alias our counterparty sent us in funding_locked, which should be used in route hints
+	/// for payments to us over this channel, in particular if it doesn't have a
+	/// short_channel_id yet.
+	pub counterparty_scid_alias: Option<u64>,
 	/// The node_id of our counterparty
 	pub remote_network_id: PublicKey,
 	/// The Features the channel counterparty provided upon last connection.
@@ -506,9 +609,116 @@ pub struct ChannelDetails {
 	/// Note that there are some corner cases not fully handled here, so the actual available
 	/// inbound capacity may be slightly higher than this.
 	pub inbound_capacity_msat: u64,
-	/// True if the channel is (a) confirmed and funding_locked messages have been exchanged, (b)
-	/// the peer is connected, and (c) no monitor update failure is pending resolution.
+	/// True if the channel is (a) confirmed (or zero-conf) and funding_locked messages have been
+	/// exchanged, (b) the peer is connected, and (c) no monitor update failure is pending
+	/// resolution.
 	pub is_live: bool,
+	/// Our balance in the channel, ie the value of our output in our latest commitment
+	/// transaction were all pending HTLCs to fail. This includes the channel reserve our
+	/// counterparty requires us to keep and the commitment transaction fee if we're the funder.
+	pub balance_msat: u64,
+	/// Our counterparty's balance in the channel, the counterpart of balance_msat.
+	pub counterparty_balance_msat: u64,
+	/// The reserve, in satoshis, our counterparty requires us to keep in the channel, which we
+	/// can't use to pay HTLCs. None if we opened the channel and haven't received accept_channel
+	/// yet.
+	pub holder_channel_reserve_satoshis: Option<u64>,
+	/// The reserve, in satoshis, we require our counterparty to keep in the channel.
+	pub counterparty_channel_reserve_satoshis: u64,
+	/// The number of confirmations the funding transaction has, which stays 0 until it's
+	/// included in a block.
+	pub confirmations: u32,
+	/// The number of confirmations the funding transaction needs before funding_locked is sent.
+	/// None if we opened the channel and haven't received accept_channel yet.
+	pub confirmations_required: Option<u32>,
+	/// True if the channel is (to be) announced to the rest of the network.
+	pub is_public: bool,
+	/// True once both we and our counterparty have sent funding_locked. Unlike is_live, this
+	/// stays true while the peer is disconnected or the channel is shutting down.
+	pub is_funding_locked: bool,
+	/// The feerate, in satoshis per 1000 weight, of our latest commitment transaction.
+	pub feerate_per_kw: u32,
+	/// The forwarding policy our counterparty set in its latest channel_update for this channel,
+	/// ie what it charges to forward HTLCs to us over it. None if we haven't received one yet.
+	pub counterparty_forwarding_info: Option<CounterpartyForwardingInfo>,
+	/// The number of blocks our funds are locked up for after we broadcast our own commitment
+	/// transaction, as selected by our counterparty. None if we opened the channel and haven't
+	/// received accept_channel yet.
+	pub holder_to_self_delay: Option<u16>,
+	/// The number of blocks our counterparty's funds are locked up for after it broadcasts its
+	/// commitment transaction, as selected by us.
+	pub counterparty_to_self_delay: u16,
+}
+
+impl ChannelDetails {
+	/// Gets the short_channel_id to use in the first hop of a route over this channel, which is
+	/// our scid_alias if the funding transaction hasn't confirmed yet.
+	pub fn get_outbound_payment_scid(&self) -> Option<u64> {
+		self.short_channel_id.or(self.scid_alias)
+	}
+}
+
+/// The forwarding policy our counterparty announced for one of our channels in its latest
+/// channel_update, see ChannelDetails::counterparty_forwarding_info.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CounterpartyForwardingInfo {
+	/// The base fee, in millisatoshis, charged to forward an HTLC over the channel.
+	pub fee_base_msat: u32,
+	/// The fee, in millionths of the forwarded amount, charged on top of fee_base_msat.
+	pub fee_proportional_millionths: u32,
+	/// The number of blocks by which the cltv_expiry of an HTLC forwarded over the channel must
+	/// exceed that of the HTLC it's forwarded from.
+	pub cltv_expiry_delta: u16,
+	/// The smallest HTLC, in millisatoshis, our counterparty accepts to forward.
+	pub htlc_minimum_msat: u64,
+	/// The largest HTLC, in millisatoshis, our counterparty accepts to forward, if it set one.
+	pub htlc_maximum_msat: Option<u64>,
+}
+
+/// Which side offered an HTLC, see PendingHTLCDetails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HTLCDirection {
+	/// Our counterparty offered us the HTLC, which either pays us or is to be forwarded.
+	Inbound,
+	/// We offered the HTLC to our counterparty, for a payment we sent or forwarded.
+	Outbound,
+}
+
+/// How far an HTLC is through the process of being added to and removed from the channel's
+/// commitment transactions, see PendingHTLCDetails.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PendingHTLCState {
+	/// The HTLC is queued in our holding cell, waiting for us to be able to offer it. Only
+	/// outbound HTLCs can be in this state.
+	HoldingCell,
+	/// The HTLC has been offered but isn't irrevocably committed to by both sides yet.
+	Adding,
+	/// The HTLC is irrevocably committed to and waiting to be fulfilled or failed.
+	Committed,
+	/// The HTLC has been fulfilled and is being removed from the commitment transactions.
+	Fulfilling,
+	/// The HTLC has been failed and is being removed from the commitment transactions.
+	Failing,
+}
+
+/// Details of an HTLC pending in one of our channels, as returned by
+/// ChannelManager::list_pending_htlcs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingHTLCDetails {
+	/// The channel the HTLC is pending in.
+	pub channel_id: [u8; 32],
+	/// The id of the HTLC within its channel, None if it's still in our holding cell.
+	pub htlc_id: Option<u64>,
+	/// Whether we or our counterparty offered the HTLC.
+	pub direction: HTLCDirection,
+	/// The value of the HTLC, in millisatoshis.
+	pub amount_msat: u64,
+	/// The payment_hash the HTLC is locked to.
+	pub payment_hash: PaymentHash,
+	/// The block height at which the HTLC times out.
+	pub cltv_expiry: u32,
+	/// How far the HTLC is through being added to or removed from the channel.
+	pub state: PendingHTLCState,
 }
 
 /// If a payment fails to send, it can be in one of several states. This enum is returned as the
@@ -538,13 +748,19 @@ pub enum PaymentSendFailure {
 	///
 	/// The results here are ordered the same as the paths in the route object which was passed to
 	/// send_payment, and any Errs which are not APIError::MonitorUpdateFailed can be safely
-	/// retried (though there is currently no API with which to do so).
+	/// retried via ChannelManager::retry_payment with the given payment_id.
 	///
 	/// Any entries which contain Err(APIError::MonitorUpdateFailed) or Ok(()) MUST NOT be retried
 	/// as they will result in over-/re-payment. These HTLCs all either successfully sent (in the
 	/// case of Ok(())) or will send once channel_monitor_updated is called on the next-hop channel
-	/// with the latest update_id.
-	PartialFailure(Vec<Result<(), APIError>>),
+	/// with the latest update_id. retry_payment refuses to send more than the value of the paths
+	/// which did not send.
+	PartialFailure {
+		/// The result of sending each path, in the order of the paths in the route.
+		results: Vec<Result<(), APIError>>,
+		/// The id of the payment, which may be used to retry the failed paths.
+		payment_id: PaymentId,
+	},
 }
 
 macro_rules! handle_error {
@@ -590,6 +806,38 @@ macro_rules! handle_error {
 	}
 }
 
+/// Forgets the short_channel_id, the alias and any pre-splice short_channel_id of a channel which
+/// is being removed.
+macro_rules! remove_short_ids {
+	($short_to_id: expr, $chan: expr) => {
+		if let Some(short_id) = $chan.get_short_channel_id() {
+			$short_to_id.remove(&short_id);
+		}
+		if let Some(alias) = $chan.get_scid_alias() {
+			$short_to_id.remove(&alias);
+		}
+		for short_id in $chan.get_pre_splice_short_channel_ids() {
+			$short_to_id.remove(&short_id);
+		}
+	}
+}
+
+/// Registers a channel's short_channel_id and alias, once known, for forwarding HTLCs to it, as
+/// well as any pre-splice short_channel_id HTLCs we received over it are still known by.
+macro_rules! insert_short_ids {
+	($short_to_id: expr, $chan: expr) => {
+		if let Some(short_id) = $chan.get_short_channel_id() {
+			$short_to_id.insert(short_id, $chan.channel_id());
+		}
+		if let Some(alias) = $chan.get_scid_alias() {
+			$short_to_id.insert(alias, $chan.channel_id());
+		}
+		for short_id in $chan.get_pre_splice_short_channel_ids() {
+			$short_to_id.insert(short_id, $chan.channel_id());
+		}
+	}
+}
+
 macro_rules! break_chan_entry {
 	($self: ident, $res: expr, $channel_state: expr, $entry: expr) => {
 		match $res {
@@ -597,12 +845,14 @@ macro_rules! break_chan_entry {
 			Err(ChannelError::Ignore(msg)) => {
 				break Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Ignore(msg), $entry.key().clone()))
 			},
+			Err(ChannelError::Warn(msg)) => {
+				break Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Warn(msg), $entry.key().clone()))
+			},
 			Err(ChannelError::Close(msg)) => {
 				log_trace!($self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
-					$channel_state.short_to_id.remove(&short_id);
-				}
+				remove_short_ids!($channel_state.short_to_id, chan);
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.clone() });
 				break Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
 			},
 			Err(ChannelError::CloseDelayBroadcast(_)) => { panic!("Wait is only generated on receipt of channel_reestablish, which is handled by try_chan_entry, we don't bother to support it here"); }
@@ -617,20 +867,21 @@ macro_rules! try_chan_entry {
 			Err(ChannelError::Ignore(msg)) => {
 				return Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Ignore(msg), $entry.key().clone()))
 			},
+			Err(ChannelError::Warn(msg)) => {
+				return Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Warn(msg), $entry.key().clone()))
+			},
 			Err(ChannelError::Close(msg)) => {
 				log_trace!($self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
-					$channel_state.short_to_id.remove(&short_id);
-				}
+				remove_short_ids!($channel_state.short_to_id, chan);
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.clone() });
 				return Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, chan.force_shutdown(true), $self.get_channel_update(&chan).ok()))
 			},
 			Err(ChannelError::CloseDelayBroadcast(msg)) => {
 				log_error!($self.logger, "Channel {} need to be shutdown but closing transactions not broadcast due to {}", log_bytes!($entry.key()[..]), msg);
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
-					$channel_state.short_to_id.remove(&short_id);
-				}
+				remove_short_ids!($channel_state.short_to_id, chan);
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.clone() });
 				let shutdown_res = chan.force_shutdown(false);
 				return Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, shutdown_res, $self.get_channel_update(&chan).ok()))
 			}
@@ -647,9 +898,8 @@ macro_rules! handle_monitor_err {
 			ChannelMonitorUpdateErr::PermanentFailure => {
 				log_error!($self.logger, "Closing channel {} due to monitor update PermanentFailure", log_bytes!($entry.key()[..]));
 				let (channel_id, mut chan) = $entry.remove_entry();
-				if let Some(short_id) = chan.get_short_channel_id() {
-					$channel_state.short_to_id.remove(&short_id);
-				}
+				remove_short_ids!($channel_state.short_to_id, chan);
+				$self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() });
 				// TODO: $failed_fails is dropped here, which will cause other channels to hit the
 				// chain in a confused state! We need to move them into the ChannelMonitor which
 				// will be responsible for failing backwards once things confirm on-chain.
@@ -723,7 +973,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// Non-proportional fees are fixed according to our risk using the provided fee estimator.
 	///
-	/// panics if channel_value_satoshis is >= `MAX_FUNDING_SATOSHIS`!
+	/// panics if channel_value_satoshis is > `MAX_FUNDING_SATOSHIS_NO_WUMBO`!
 	///
 	/// Users must provide the current blockchain height from which to track onchain channel
 	/// funding outpoints and send payments with reliable timelocks.
@@ -757,6 +1007,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 			per_peer_state: RwLock::new(HashMap::new()),
 
+			trusted_zero_conf_peers: Mutex::new(HashSet::new()),
+
+			pending_outbound_payments: Mutex::new(HashMap::new()),
+
 			pending_events: Mutex::new(Vec::new()),
 			total_consistency_lock: RwLock::new(()),
 
@@ -776,15 +1030,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// If successful, will generate a SendOpenChannel message event, so you should probably poll
 	/// PeerManager::process_events afterwards.
 	///
-	/// Raises APIError::APIMisuseError when channel_value_satoshis > 2**24 or push_msat is
-	/// greater than channel_value_satoshis * 1k or channel_value_satoshis is < 1000.
+	/// Raises APIError::APIMisuseError when channel_value_satoshis > 2**24 - 1 (unless the remote
+	/// node is connected and supports option_support_large_channel) or push_msat is greater than
+	/// channel_value_satoshis * 1k or channel_value_satoshis is < 1000.
 	pub fn create_channel(&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_id: u64, override_config: Option<UserConfig>) -> Result<(), APIError> {
 		if channel_value_satoshis < 1000 {
 			return Err(APIError::APIMisuseError { err: format!("Channel value must be at least 1000 satoshis. It was {}", channel_value_satoshis) });
 		}
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_network_key) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => InitFeatures::empty(),
+		};
 
 		let config = if override_config.is_some() { override_config.as_ref().unwrap() } else { &self.default_configuration };
-		let channel = Channel::new_outbound(&self.fee_estimator, &self.keys_manager, their_network_key, channel_value_satoshis, push_msat, user_id, config)?;
+		let channel = Channel::new_outbound(&self.fee_estimator, &self.keys_manager, their_network_key, &their_features, channel_value_satoshis, push_msat, user_id, config)?;
 		let res = channel.get_open_channel(self.genesis_hash.clone());
 
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
@@ -806,6 +1065,142 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	/// Creates a new outbound dual-funded channel to the given remote node, to which we contribute
+	/// channel_value_satoshis using inputs from KeysInterface::get_utxo_source. The remote node
+	/// may contribute funds to the channel as well when it accepts it.
+	///
+	/// Instead of an Event::FundingGenerationReady, the funding transaction is built with our
+	/// counterparty, signed using the funding wallet and broadcast once both sides have signed it.
+	///
+	/// user_id will be provided back as user_channel_id in events related to the channel, see
+	/// create_channel.
+	///
+	/// If successful, will generate a SendOpenChannelV2 message event, so you should probably poll
+	/// PeerManager::process_events afterwards.
+	///
+	/// Raises APIError::APIMisuseError when we have not enabled
+	/// ChannelHandshakeConfig::negotiate_dual_fund, when the remote node is not connected or does
+	/// not support option_dual_fund, when we have no funding wallet or it has insufficient funds,
+	/// or for the same reasons as create_channel.
+	pub fn create_dual_funded_channel(&self, their_network_key: PublicKey, channel_value_satoshis: u64, user_id: u64, override_config: Option<UserConfig>) -> Result<(), APIError> {
+		if channel_value_satoshis < 1000 {
+			return Err(APIError::APIMisuseError { err: format!("Channel value must be at least 1000 satoshis. It was {}", channel_value_satoshis) });
+		}
+		if !self.default_configuration.own_channel_config.negotiate_dual_fund {
+			return Err(APIError::APIMisuseError { err: "We have not enabled option_dual_fund".to_owned() });
+		}
+		let their_features = match self.per_peer_state.read().unwrap().get(&their_network_key) {
+			Some(peer_state) => peer_state.lock().unwrap().latest_features.clone(),
+			None => return Err(APIError::APIMisuseError { err: "Peer must be connected to open a dual-funded channel".to_owned() }),
+		};
+		if !their_features.supports_dual_fund() {
+			return Err(APIError::APIMisuseError { err: "Peer does not support option_dual_fund".to_owned() });
+		}
+
+		let config = if override_config.is_some() { override_config.as_ref().unwrap() } else { &self.default_configuration };
+		let channel = Channel::new_outbound_dual_funded(&self.fee_estimator, &self.keys_manager, their_network_key, &their_features, channel_value_satoshis, user_id, config)?;
+		let res = channel.get_open_channel_v2(self.genesis_hash.clone());
+
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let mut channel_state = self.channel_state.lock().unwrap();
+		match channel_state.by_id.entry(channel.channel_id()) {
+			hash_map::Entry::Occupied(_) => {
+				if cfg!(feature = "fuzztarget") {
+					return Err(APIError::APIMisuseError { err: "Fuzzy bad RNG".to_owned() });
+				} else {
+					panic!("RNG is bad???");
+				}
+			},
+			hash_map::Entry::Vacant(entry) => { entry.insert(channel); }
+		}
+		channel_state.pending_msg_events.push(events::MessageSendEvent::SendOpenChannelV2 {
+			node_id: their_network_key,
+			msg: res,
+		});
+		Ok(())
+	}
+
+	/// Accepts an inbound channel for which an Event::OpenChannelRequest was generated, sending
+	/// our accept_channel (or accept_channel2) message to the counterparty.
+	///
+	/// The user_channel_id is reported in ChannelDetails for this channel. If a config is given,
+	/// it is used instead of the default UserConfig::channel_options, though whether the channel
+	/// is announced is still decided by the counterparty's request.
+	pub fn accept_inbound_channel(&self, temporary_channel_id: &[u8; 32], user_channel_id: u64, config: Option<&ChannelConfig>) -> Result<(), APIError> {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		let chan = match channel_state.by_id.get_mut(temporary_channel_id) {
+			Some(chan) => chan,
+			None => return Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()}),
+		};
+		if !chan.is_awaiting_accept() {
+			return Err(APIError::APIMisuseError{err: "The channel is not awaiting to be accepted".to_owned()});
+		}
+		chan.set_accepted_by_user(user_channel_id, config);
+		if chan.is_dual_funded_pending_funding() {
+			channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannelV2 {
+				node_id: chan.get_counterparty_node_id(),
+				msg: chan.get_accept_channel_v2(),
+			});
+		} else {
+			channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
+				node_id: chan.get_counterparty_node_id(),
+				msg: chan.get_accept_channel(),
+			});
+		}
+		Ok(())
+	}
+
+	/// Rejects an inbound channel for which an Event::OpenChannelRequest was generated, forgetting
+	/// it and sending an error message to the counterparty.
+	pub fn reject_inbound_channel(&self, temporary_channel_id: &[u8; 32]) -> Result<(), APIError> {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(*temporary_channel_id) {
+			hash_map::Entry::Occupied(chan) => {
+				if !chan.get().is_awaiting_accept() {
+					return Err(APIError::APIMisuseError{err: "The channel is not awaiting to be accepted".to_owned()});
+				}
+				let counterparty_node_id = chan.get().get_counterparty_node_id();
+				chan.remove_entry();
+				channel_state.pending_msg_events.push(events::MessageSendEvent::HandleError {
+					node_id: counterparty_node_id,
+					action: msgs::ErrorAction::SendErrorMessage {
+						msg: msgs::ErrorMessage {
+							channel_id: *temporary_channel_id,
+							data: "Channel open was rejected".to_owned(),
+						},
+					},
+				});
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()}),
+		}
+	}
+
+	/// Sets whether we trust the given peer not to double-spend the funding transaction of the
+	/// channels it opens to us. Channels from trusted peers are accepted with a minimum_depth of 0
+	/// and can be used as soon as the funding transaction is signed, referring to them by their
+	/// scid_alias (see ChannelDetails::get_outbound_payment_scid) until it confirms.
+	///
+	/// Note that a double-spent funding transaction loses us any funds we received in the channel.
+	/// This is typically only used by users of an LSP which opens channels to them on demand.
+	///
+	/// The set of trusted peers is not persisted and must be set again after deserializing the
+	/// ChannelManager.
+	pub fn set_trusted_zero_conf_peer(&self, their_node_id: PublicKey, trusted: bool) {
+		let mut trusted_zero_conf_peers = self.trusted_zero_conf_peers.lock().unwrap();
+		if trusted {
+			trusted_zero_conf_peers.insert(their_node_id);
+		} else {
+			trusted_zero_conf_peers.remove(&their_node_id);
+		}
+	}
+
 	fn list_channels_with_filter<Fn: FnMut(&(&[u8; 32], &Channel<ChanSigner>)) -> bool>(&self, f: Fn) -> Vec<ChannelDetails> {
 		let mut res = Vec::new();
 		{
@@ -813,9 +1208,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			res.reserve(channel_state.by_id.len());
 			for (channel_id, channel) in channel_state.by_id.iter().filter(f) {
 				let (inbound_capacity_msat, outbound_capacity_msat) = channel.get_inbound_outbound_available_balance_msat();
+				let balance_msat = channel.get_value_to_self_msat();
 				res.push(ChannelDetails {
 					channel_id: (*channel_id).clone(),
 					short_channel_id: channel.get_short_channel_id(),
+					scid_alias: channel.get_scid_alias(),
+					counterparty_scid_alias: channel.get_counterparty_scid_alias(),
 					remote_network_id: channel.get_counterparty_node_id(),
 					counterparty_features: InitFeatures::empty(),
 					channel_value_satoshis: channel.get_value_satoshis(),
@@ -823,6 +1221,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					outbound_capacity_msat,
 					user_id: channel.get_user_id(),
 					is_live: channel.is_live(),
+					balance_msat,
+					counterparty_balance_msat: channel.get_value_satoshis() * 1000 - balance_msat,
+					holder_channel_reserve_satoshis: channel.get_counterparty_selected_channel_reserve_satoshis(),
+					counterparty_channel_reserve_satoshis: Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel.get_value_satoshis()),
+					confirmations: channel.get_funding_tx_confirmations(),
+					confirmations_required: channel.get_confirmations_required(),
+					is_public: channel.should_announce(),
+					is_funding_locked: channel.is_funding_locked(),
+					feerate_per_kw: channel.get_feerate(),
+					counterparty_forwarding_info: channel.get_counterparty_forwarding_info(),
+					holder_to_self_delay: channel.get_holder_to_self_delay(),
+					counterparty_to_self_delay: channel.get_counterparty_to_self_delay(),
 				});
 			}
 		}
@@ -853,12 +1263,94 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		self.list_channels_with_filter(|&(_, ref channel)| channel.is_live())
 	}
 
+	/// Gets route hints through our live private channels, to include in invoices so that payers
+	/// can reach us despite those channels not being announced.
+	///
+	/// Only channels whose counterparty sent us its channel_update are included, as its forwarding
+	/// policy is needed to build the hint. If amount_msat is set, only channels which can receive
+	/// that amount (considering the reserve our counterparty has to keep and its HTLC limits) are
+	/// included. Hints are sorted by decreasing inbound capacity.
+	pub fn get_route_hints(&self, amount_msat: Option<u64>) -> Vec<RouteHint> {
+		let channel_state = self.channel_state.lock().unwrap();
+		let mut hints = Vec::new();
+		for channel in channel_state.by_id.values() {
+			if channel.should_announce() || !channel.is_live() {
+				continue;
+			}
+			let forwarding_info = match channel.get_counterparty_forwarding_info() {
+				Some(info) => info,
+				None => continue,
+			};
+			// Our counterparty forwards HTLCs to us using the alias it gave the channel until the
+			// funding transaction confirms.
+			let short_channel_id = match channel.get_short_channel_id().or(channel.get_counterparty_scid_alias()) {
+				Some(id) => id,
+				None => continue,
+			};
+			let counterparty_reserve_msat = Channel::<ChanSigner>::get_holder_selected_channel_reserve_satoshis(channel.get_value_satoshis()) * 1000;
+			let inbound_capacity_msat = channel.get_inbound_outbound_available_balance_msat().0.saturating_sub(counterparty_reserve_msat);
+			if let Some(amount_msat) = amount_msat {
+				if amount_msat > inbound_capacity_msat || amount_msat < forwarding_info.htlc_minimum_msat ||
+						amount_msat > forwarding_info.htlc_maximum_msat.unwrap_or(u64::max_value()) {
+					continue;
+				}
+			}
+			hints.push((inbound_capacity_msat, RouteHint {
+				src_node_id: channel.get_counterparty_node_id(),
+				short_channel_id,
+				fees: RoutingFees {
+					base_msat: forwarding_info.fee_base_msat,
+					proportional_millionths: forwarding_info.fee_proportional_millionths,
+				},
+				cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+				htlc_minimum_msat: forwarding_info.htlc_minimum_msat,
+				htlc_maximum_msat: forwarding_info.htlc_maximum_msat,
+			}));
+		}
+		hints.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+		hints.into_iter().map(|(_, hint)| hint).collect()
+	}
+
+	/// Gets the list of HTLCs pending in any of our channels, in random order. See
+	/// PendingHTLCDetails field documentation for more information.
+	pub fn list_pending_htlcs(&self) -> Vec<PendingHTLCDetails> {
+		let channel_state = self.channel_state.lock().unwrap();
+		let mut res = Vec::new();
+		for channel in channel_state.by_id.values() {
+			res.append(&mut channel.get_pending_htlc_details());
+		}
+		res
+	}
+
 	/// Begins the process of closing a channel. After this call (plus some timeout), no new HTLCs
 	/// will be accepted on the given channel, and after additional timeout/the closing of all
 	/// pending HTLCs, the channel will be closed on chain.
 	///
 	/// May generate a SendShutdown message event on success, which should be relayed.
 	pub fn close_channel(&self, channel_id: &[u8; 32]) -> Result<(), APIError> {
+		self.close_channel_internal(channel_id, None, None)
+	}
+
+	/// Begins the process of closing a channel as in close_channel, paying our funds to
+	/// destination (if Some) instead of the script derived from KeysInterface::get_shutdown_pubkey
+	/// and negotiating the closing transaction fee without using our FeeEstimator.
+	///
+	/// destination must be a P2PKH, P2SH, P2WPKH or P2WSH script, and may not differ from the
+	/// upfront shutdown script we committed to if ChannelConfig::commit_upfront_shutdown_pubkey
+	/// was set when the channel was opened.
+	///
+	/// We propose a closing transaction paying target_feerate_sat_per_1000_weight if we pay its
+	/// fee, and will never agree to a total fee above max_fee_satoshis. If our counterparty pays
+	/// the fee, we also require it to pay at least target_feerate_sat_per_1000_weight (capped at
+	/// max_fee_satoshis). If no fee within these bounds can be agreed on, the channel is
+	/// force-closed.
+	///
+	/// May generate a SendShutdown message event on success, which should be relayed.
+	pub fn close_channel_with_options(&self, channel_id: &[u8; 32], destination: Option<Script>, target_feerate_sat_per_1000_weight: u32, max_fee_satoshis: u64) -> Result<(), APIError> {
+		self.close_channel_internal(channel_id, destination, Some((target_feerate_sat_per_1000_weight, max_fee_satoshis)))
+	}
+
+	fn close_channel_internal(&self, channel_id: &[u8; 32], destination: Option<Script>, fee_limits: Option<(u32, u64)>) -> Result<(), APIError> {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
 
 		let (mut failed_htlcs, chan_option) = {
@@ -866,15 +1358,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let channel_state = &mut *channel_state_lock;
 			match channel_state.by_id.entry(channel_id.clone()) {
 				hash_map::Entry::Occupied(mut chan_entry) => {
-					let (shutdown_msg, failed_htlcs) = chan_entry.get_mut().get_shutdown()?;
+					let (shutdown_msg, failed_htlcs) = chan_entry.get_mut().get_shutdown(destination, fee_limits)?;
 					channel_state.pending_msg_events.push(events::MessageSendEvent::SendShutdown {
 						node_id: chan_entry.get().get_counterparty_node_id(),
 						msg: shutdown_msg
 					});
 					if chan_entry.get().is_shutdown() {
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
-							channel_state.short_to_id.remove(&short_id);
-						}
+						remove_short_ids!(channel_state.short_to_id, chan_entry.get());
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(failed_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (failed_htlcs, None) }
 				},
@@ -900,6 +1391,133 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	/// Updates the forwarding policy of the given channels, ie their
+	/// ChannelConfig::fee_proportional_millionths, fee_base_msat, cltv_expiry_delta,
+	/// htlc_minimum_msat and htlc_maximum_msat, which are copied from config. The other fields of
+	/// config can't be changed on live channels and are ignored.
+	///
+	/// A new channel_update is signed for each channel whose policy changed. We keep accepting
+	/// HTLCs paying the previous policy for a few calls to timer_chan_freshness_every_min, as
+	/// nodes may not have seen the update yet when routing through us.
+	///
+	/// If any of the channels doesn't exist, none are updated.
+	///
+	/// May generate BroadcastChannelUpdate message events for announced channels, and
+	/// SendChannelUpdate message events for private ones, on success, which should be relayed.
+	pub fn update_channel_config(&self, channel_ids: &[[u8; 32]], config: &ChannelConfig) -> Result<(), APIError> {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		for channel_id in channel_ids.iter() {
+			if !channel_state.by_id.contains_key(channel_id) {
+				return Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()});
+			}
+		}
+		for channel_id in channel_ids.iter() {
+			let chan = channel_state.by_id.get_mut(channel_id).unwrap();
+			if !chan.update_config(config) {
+				continue;
+			}
+			if chan.should_announce() {
+				if let Ok(update) = self.get_channel_update(chan) {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+						msg: update
+					});
+				}
+			} else if let Some(update) = self.get_private_channel_update(chan) {
+				channel_state.pending_msg_events.push(update);
+			}
+		}
+		Ok(())
+	}
+
+	/// Begins splicing value_satoshis into a channel, using inputs from
+	/// KeysInterface::get_utxo_source.
+	///
+	/// The channel first stops accepting new HTLCs (which are kept in the holding cell until the
+	/// splice completes), then a splice transaction spending the current funding output is built
+	/// with our counterparty, signed and broadcast. The channel keeps using its current funding
+	/// output until the splice transaction reached the channel's minimum depth, after which its
+	/// short_channel_id changes.
+	///
+	/// May generate a SendStfu message event on success, which should be relayed.
+	///
+	/// Raises APIError::APIMisuseError when we have not enabled
+	/// ChannelHandshakeConfig::negotiate_splicing, when the remote node is not connected or does
+	/// not support splicing, when we have no funding wallet or it has insufficient funds, or if
+	/// the channel is already splicing.
+	pub fn splice_in(&self, channel_id: &[u8; 32], value_satoshis: u64) -> Result<(), APIError> {
+		self.start_splice(channel_id, |chan| chan.splice_in(value_satoshis, &self.fee_estimator, &self.keys_manager))
+	}
+
+	/// Begins splicing value_satoshis out of a channel to the given script_pubkey, with our
+	/// channel balance paying for the splice transaction fee. See splice_in for details on how
+	/// the splice proceeds.
+	///
+	/// Raises APIError::APIMisuseError when we have not enabled
+	/// ChannelHandshakeConfig::negotiate_splicing, when the remote node is not connected or does
+	/// not support splicing, when our channel balance is insufficient, or if the channel is
+	/// already splicing.
+	pub fn splice_out(&self, channel_id: &[u8; 32], value_satoshis: u64, script_pubkey: Script) -> Result<(), APIError> {
+		self.start_splice(channel_id, |chan| chan.splice_out(value_satoshis, script_pubkey, &self.fee_estimator))
+	}
+
+	fn start_splice<S>(&self, channel_id: &[u8; 32], start: S) -> Result<(), APIError>
+		where S: FnOnce(&mut Channel<ChanSigner>) -> Result<(), APIError>
+	{
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		if !self.default_configuration.own_channel_config.negotiate_splicing {
+			return Err(APIError::APIMisuseError { err: "We have not enabled splicing".to_owned() });
+		}
+		let counterparty_node_id = match self.channel_state.lock().unwrap().by_id.get(channel_id) {
+			Some(chan) => chan.get_counterparty_node_id(),
+			None => return Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()}),
+		};
+		{
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			let supports_splicing = match per_peer_state.get(&counterparty_node_id) {
+				Some(peer_state) => peer_state.lock().unwrap().latest_features.supports_splicing(),
+				None => return Err(APIError::APIMisuseError { err: "Peer must be connected to splice a channel".to_owned() }),
+			};
+			if !supports_splicing {
+				return Err(APIError::APIMisuseError { err: "Peer does not support splicing".to_owned() });
+			}
+		}
+
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.get_mut(channel_id) {
+			Some(chan) => {
+				start(chan)?;
+				if let Some(msg) = chan.maybe_send_splice_msg(&self.logger) {
+					channel_state.pending_msg_events.push(msg.into_msg_send_event(counterparty_node_id));
+				}
+				Ok(())
+			},
+			None => Err(APIError::ChannelUnavailable{err: "No such channel".to_owned()}),
+		}
+	}
+
+	/// Queues the events for a channel we're about to forget.
+	fn issue_channel_close_events(&self, channel: &Channel<ChanSigner>, reason: ClosureReason) {
+		if channel.is_awaiting_accept() { return; }
+		let mut pending_events = self.pending_events.lock().unwrap();
+		if let Some((funding_txid, transaction)) = channel.get_unbroadcast_funding() {
+			pending_events.push(events::Event::DiscardFunding {
+				channel_id: channel.channel_id(),
+				funding_txid,
+				transaction,
+			});
+		}
+		pending_events.push(events::Event::ChannelClosed {
+			channel_id: channel.channel_id(),
+			user_id: channel.get_user_id(),
+			reason,
+		});
+	}
+
 	#[inline]
 	fn finish_force_close_channel(&self, shutdown_res: ShutdownResult) {
 		let (funding_txo_option, monitor_update, mut failed_htlcs) = shutdown_res;
@@ -920,14 +1538,16 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// the chain and rejecting new HTLCs on the given channel.
 	pub fn force_close_channel(&self, channel_id: &[u8; 32]) {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		self.force_close_channel_with_reason(channel_id, ClosureReason::HolderForceClosed);
+	}
 
+	fn force_close_channel_with_reason(&self, channel_id: &[u8; 32], reason: ClosureReason) {
 		let mut chan = {
 			let mut channel_state_lock = self.channel_state.lock().unwrap();
 			let channel_state = &mut *channel_state_lock;
 			if let Some(chan) = channel_state.by_id.remove(channel_id) {
-				if let Some(short_id) = chan.get_short_channel_id() {
-					channel_state.short_to_id.remove(&short_id);
-				}
+				remove_short_ids!(channel_state.short_to_id, chan);
+				self.issue_channel_close_events(&chan, reason);
 				chan
 			} else {
 				return;
@@ -951,6 +1571,51 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Abandons an outbound channel whose funding transaction hasn't been broadcast yet, sending an
+	/// error message to our counterparty so that it forgets the channel as well.
+	///
+	/// A DiscardFunding event is generated if we were already given the funding transaction, so
+	/// that its inputs may be reused, followed by a ChannelClosed event.
+	///
+	/// Fails if the channel is inbound or if its funding transaction may already have been
+	/// broadcast, in which case force_close_channel should be used instead.
+	pub fn abandon_unfunded_channel(&self, channel_id: &[u8; 32]) -> Result<(), APIError> {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let shutdown_res = {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(*channel_id) {
+				hash_map::Entry::Occupied(mut chan_entry) => {
+					if !chan_entry.get().is_outbound() {
+						return Err(APIError::APIMisuseError { err: "Only outbound channels may be abandoned".to_owned() });
+					}
+					if chan_entry.get().is_funding_initiated() && chan_entry.get().get_unbroadcast_funding().is_none() {
+						return Err(APIError::APIMisuseError { err: "Channel funding transaction may already have been broadcast".to_owned() });
+					}
+					log_info!(self.logger, "Abandoning unfunded channel {}", log_bytes!(channel_id[..]));
+					self.issue_channel_close_events(chan_entry.get(), ClosureReason::HolderForceClosed);
+					channel_state.pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: chan_entry.get().get_counterparty_node_id(),
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id: *channel_id,
+								data: "Channel abandoned before funding".to_owned(),
+							},
+						},
+					});
+					let shutdown_res = chan_entry.get_mut().force_shutdown(false);
+					remove_short_ids!(channel_state.short_to_id, chan_entry.get());
+					chan_entry.remove_entry();
+					shutdown_res
+				},
+				hash_map::Entry::Vacant(_) => return Err(APIError::ChannelUnavailable { err: "No such channel".to_owned() }),
+			}
+		};
+		self.finish_force_close_channel(shutdown_res);
+		Ok(())
+	}
+
 	fn decode_update_add_htlc_onion(&self, msg: &msgs::UpdateAddHTLC) -> (PendingHTLCStatus, MutexGuard<ChannelHolder<ChanSigner>>) {
 		macro_rules! return_malformed_err {
 			($msg: expr, $err_code: expr) => {
@@ -1070,12 +1735,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					return_err!("Upstream node set CLTV to the wrong value", 18, &byte_utils::be32_to_array(msg.cltv_expiry));
 				}
 
-				let payment_data = match next_hop_data.format {
-					msgs::OnionHopDataFormat::Legacy { .. } => None,
+				let (payment_data, keysend_preimage, custom_tlvs) = match next_hop_data.format {
+					msgs::OnionHopDataFormat::Legacy { .. } => (None, None, Vec::new()),
 					msgs::OnionHopDataFormat::NonFinalNode { .. } => return_err!("Got non final data with an HMAC of 0", 0x4000 | 22, &[0;0]),
-					msgs::OnionHopDataFormat::FinalNode { payment_data } => payment_data,
+					msgs::OnionHopDataFormat::FinalNode { payment_data, keysend_preimage, custom_tlvs } => (payment_data, keysend_preimage, custom_tlvs),
 				};
 
+				// A spontaneous payment must carry the preimage to the payment_hash it was sent
+				// with, otherwise we'd report a payment we couldn't ever claim.
+				if let Some(ref preimage) = keysend_preimage {
+					if PaymentHash(Sha256::hash(&preimage.0).into_inner()) != msg.payment_hash {
+						return_err!("Payment preimage didn't match payment hash", 0x4000 | 22, &[0;0]);
+					}
+				}
+
 				// Note that we could obviously respond immediately with an update_fulfill_htlc
 				// message, however that would leak that we are the recipient of this payment, so
 				// instead we stay symmetric with the forwarding case, only responding (after a
@@ -1084,6 +1757,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				PendingHTLCStatus::Forward(PendingHTLCInfo {
 					routing: PendingHTLCRouting::Receive {
 						payment_data,
+						keysend_preimage,
+						custom_tlvs,
 						incoming_cltv_expiry: msg.cltv_expiry,
 					},
 					payment_hash: msg.payment_hash.clone(),
@@ -1169,23 +1844,19 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					// hopefully an attacker trying to path-trace payments cannot make this occur
 					// on a small/per-node/per-channel scale.
 					if !chan.is_live() { // channel_disabled
-						break Some(("Forwarding channel is not in a ready state.", 0x1000 | 20, Some(self.get_channel_update(chan).unwrap())));
+						break Some(("Forwarding channel is not in a ready state.", 0x1000 | 20, Some(self.get_channel_update_for_onion(chan).unwrap())));
 					}
 					if *amt_to_forward < chan.get_counterparty_htlc_minimum_msat() { // amount_below_minimum
-						break Some(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11, Some(self.get_channel_update(chan).unwrap())));
-					}
-					let fee = amt_to_forward.checked_mul(chan.get_fee_proportional_millionths() as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(chan.get_holder_fee_base_msat(&self.fee_estimator) as u64) });
-					if fee.is_none() || msg.amount_msat < fee.unwrap() || (msg.amount_msat - fee.unwrap()) < *amt_to_forward { // fee_insufficient
-						break Some(("Prior hop has deviated from specified fees parameters or origin node has obsolete ones", 0x1000 | 12, Some(self.get_channel_update(chan).unwrap())));
+						break Some(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11, Some(self.get_channel_update_for_onion(chan).unwrap())));
 					}
-					if (msg.cltv_expiry as u64) < (*outgoing_cltv_value) as u64 + CLTV_EXPIRY_DELTA as u64 { // incorrect_cltv_expiry
-						break Some(("Forwarding node has tampered with the intended HTLC values or origin node has an obsolete cltv_expiry_delta", 0x1000 | 13, Some(self.get_channel_update(chan).unwrap())));
+					if let Err((err, code)) = chan.htlc_satisfies_config(msg, *amt_to_forward, *outgoing_cltv_value, &self.fee_estimator) {
+						break Some((err, code, Some(self.get_channel_update_for_onion(chan).unwrap())));
 					}
 					let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 					// Theoretically, channel counterparty shouldn't send us a HTLC expiring now, but we want to be robust wrt to counterparty
 					// packet sanitization (see HTLC_FAIL_BACK_BUFFER rational)
 					if msg.cltv_expiry <= cur_height + HTLC_FAIL_BACK_BUFFER as u32 { // expiry_too_soon
-						break Some(("CLTV expiry is too close", 0x1000 | 14, Some(self.get_channel_update(chan).unwrap())));
+						break Some(("CLTV expiry is too close", 0x1000 | 14, Some(self.get_channel_update_for_onion(chan).unwrap())));
 					}
 					if msg.cltv_expiry > cur_height + CLTV_FAR_FAR_AWAY as u32 { // expiry_too_far
 						break Some(("CLTV expiry is too far in the future", 21, None));
@@ -1193,7 +1864,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					// In theory, we would be safe against unitentional channel-closure, if we only required a margin of LATENCY_GRACE_PERIOD_BLOCKS.
 					// But, to be safe against policy reception, we use a longuer delay.
 					if (*outgoing_cltv_value) as u64 <= (cur_height + HTLC_FAIL_BACK_BUFFER) as u64 {
-						break Some(("Outgoing CLTV value is too soon", 0x1000 | 14, Some(self.get_channel_update(chan).unwrap())));
+						break Some(("Outgoing CLTV value is too soon", 0x1000 | 14, Some(self.get_channel_update_for_onion(chan).unwrap())));
 					}
 
 					break None;
@@ -1228,7 +1899,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			None => return Err(LightningError{err: "Channel not yet established".to_owned(), action: msgs::ErrorAction::IgnoreError}),
 			Some(id) => id,
 		};
+		Ok(self.get_channel_update_for_short_id(chan, short_channel_id))
+	}
+
+	/// Gets the channel_update to include in onion failure messages, which refers to zero-conf
+	/// channels whose funding transaction hasn't confirmed yet by their alias.
+	fn get_channel_update_for_onion(&self, chan: &Channel<ChanSigner>) -> Result<msgs::ChannelUpdate, LightningError> {
+		let short_channel_id = match chan.get_short_channel_id().or(chan.get_scid_alias()) {
+			None => return Err(LightningError{err: "Channel not yet established".to_owned(), action: msgs::ErrorAction::IgnoreError}),
+			Some(id) => id,
+		};
+		Ok(self.get_channel_update_for_short_id(chan, short_channel_id))
+	}
 
+	fn get_channel_update_for_short_id(&self, chan: &Channel<ChanSigner>, short_channel_id: u64) -> msgs::ChannelUpdate {
 		let were_node_one = PublicKey::from_secret_key(&self.secp_ctx, &self.our_network_key).serialize()[..] < chan.get_counterparty_node_id().serialize()[..];
 
 		let unsigned = msgs::UnsignedChannelUpdate {
@@ -1236,8 +1920,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			short_channel_id,
 			timestamp: chan.get_update_time_counter(),
 			flags: (!were_node_one) as u8 | ((!chan.is_live() as u8) << 1),
-			cltv_expiry_delta: CLTV_EXPIRY_DELTA,
-			htlc_minimum_msat: chan.get_counterparty_htlc_minimum_msat(),
+			cltv_expiry_delta: chan.get_cltv_expiry_delta(),
+			htlc_minimum_msat: chan.get_announced_htlc_min_msat(),
 			htlc_maximum_msat: OptionalField::Present(chan.get_announced_htlc_max_msat()),
 			fee_base_msat: chan.get_holder_fee_base_msat(&self.fee_estimator),
 			fee_proportional_millionths: chan.get_fee_proportional_millionths(),
@@ -1247,21 +1931,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		let msg_hash = Sha256dHash::hash(&unsigned.encode()[..]);
 		let sig = self.secp_ctx.sign(&hash_to_message!(&msg_hash[..]), &self.our_network_key);
 
-		Ok(msgs::ChannelUpdate {
+		msgs::ChannelUpdate {
 			signature: sig,
 			contents: unsigned
-		})
+		}
 	}
 
 	// Only public for testing, this should otherwise never be called direcly
-	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, total_value: u64, cur_height: u32) -> Result<(), APIError> {
+	pub(crate) fn send_payment_along_path(&self, path: &Vec<RouteHop>, payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, total_value: u64, cur_height: u32, payment_id: PaymentId, session_priv: &SecretKey, keysend_preimage: &Option<PaymentPreimage>, custom_tlvs: &[(u64, Vec<u8>)]) -> Result<(), APIError> {
 		log_trace!(self.logger, "Attempting to send payment for path with next hop {}", path.first().unwrap().short_channel_id);
 		let prng_seed = self.keys_manager.get_secure_random_bytes();
-		let session_priv = SecretKey::from_slice(&self.keys_manager.get_secure_random_bytes()[..]).expect("RNG is busted");
 
 		let onion_keys = onion_utils::construct_onion_keys(&self.secp_ctx, &path, &session_priv)
 			.map_err(|_| APIError::RouteError{err: "Pubkey along hop was maliciously selected"})?;
-		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, cur_height)?;
+		let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(path, total_value, payment_secret, keysend_preimage, custom_tlvs, cur_height)?;
 		if onion_utils::route_size_insane(&onion_payloads) {
 			return Err(APIError::RouteError{err: "Route size too large considering onion data"});
 		}
@@ -1289,6 +1972,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						path: path.clone(),
 						session_priv: session_priv.clone(),
 						first_hop_htlc_msat: htlc_msat,
+						payment_id,
 					}, onion_packet, &self.logger), channel_state, chan)
 				} {
 					Some((update_add, commitment_signed, monitor_update)) => {
@@ -1340,6 +2024,11 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// payment_preimage tracking (which you should already be doing as they represent "proof of
 	/// payment") and prevent double-sends yourself.
 	///
+	/// On success, returns a PaymentId which uniquely identifies the payment in the PaymentSent
+	/// and PaymentFailed events for its paths. Failed paths may be retried via retry_payment,
+	/// which ensures the recipient is never paid more than the total value of route, even across
+	/// restarts. See ln::payment_retry::PaymentRetrier for a utility which does so automatically.
+	///
 	/// May generate SendHTLCs message(s) event on success, which should be relayed.
 	///
 	/// Each path may have a different return value, and PaymentSendValue may return a Vec with
@@ -1367,21 +2056,33 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// If a payment_secret *is* provided, we assume that the invoice had the payment_secret feature
 	/// bit set (either as required or as available). If multiple paths are present in the Route,
 	/// we assume the invoice had the basic_mpp feature set.
-	pub fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<(), PaymentSendFailure> {
+	///
+	/// custom_tlvs are application-specific (type, value) records to include in the final hop's
+	/// onion payload, which the recipient will see in its PaymentReceived event. Types must be
+	/// unique, odd unless you know the recipient understands them, and at least
+	/// msgs::CUSTOM_TLV_TYPE_MIN, otherwise an APIError::APIMisuseError is returned. Records may
+	/// only be included if the recipient supports variable-length onions.
+	pub fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>, custom_tlvs: &[(u64, Vec<u8>)]) -> Result<PaymentId, PaymentSendFailure> {
+		self.send_payment_internal(route, payment_hash, payment_secret, None, custom_tlvs, None)
+	}
+
+	/// Checks that the paths in the given route are usable, returning the total value they
+	/// deliver to the recipient.
+	fn check_route_paths(&self, route: &Route) -> Result<u64, PaymentSendFailure> {
 		if route.paths.len() < 1 {
 			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "There must be at least one path to send over"}));
 		}
 		if route.paths.len() > 10 {
 			// This limit is completely arbitrary - there aren't any real fundamental path-count
-			// limits. After we support retrying individual paths we should likely bump this, but
-			// for now more than 10 paths likely carries too much one-path failure.
+			// limits. Individual paths may be retried via retry_payment, but more than 10 paths
+			// still likely carries too much one-path failure.
 			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Sending over more than 10 paths is not currently supported"}));
 		}
 		let mut total_value = 0;
 		let our_node_id = self.get_our_node_id();
 		let mut path_errs = Vec::with_capacity(route.paths.len());
 		'path_check: for path in route.paths.iter() {
-			if path.len() < 1 || path.len() > 20 {
+			if path.len() < 1 || path.len() > MAX_PATH_LENGTH as usize {
 				path_errs.push(Err(APIError::RouteError{err: "Path didn't go anywhere/had bogus size"}));
 				continue 'path_check;
 			}
@@ -1397,27 +2098,95 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		if path_errs.iter().any(|e| e.is_err()) {
 			return Err(PaymentSendFailure::PathParameterError(path_errs));
 		}
+		Ok(total_value)
+	}
 
-		let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
-		let mut results = Vec::new();
+	/// Generates a session_priv for each path in route, tracking them as pending in payment
+	/// before any HTLC is sent so that concurrent retries account for them.
+	fn reserve_payment_paths(&self, payment: &mut PendingOutboundPayment, route: &Route) -> Vec<SecretKey> {
+		payment.timer_ticks_without_paths = 0;
+		let mut session_privs = Vec::with_capacity(route.paths.len());
 		for path in route.paths.iter() {
-			results.push(self.send_payment_along_path(&path, &payment_hash, payment_secret, total_value, cur_height));
+			let session_priv_bytes = self.keys_manager.get_secure_random_bytes();
+			session_privs.push(SecretKey::from_slice(&session_priv_bytes[..]).expect("RNG is busted"));
+			payment.pending_paths.insert(session_priv_bytes, path.last().unwrap().fee_msat);
 		}
-		let mut has_ok = false;
-		let mut has_err = false;
-		for res in results.iter() {
-			if res.is_ok() { has_ok = true; }
-			if res.is_err() { has_err = true; }
-			if let &Err(APIError::MonitorUpdateFailed) = res {
-				// MonitorUpdateFailed is inherently unsafe to retry, so we call it a
-				// PartialFailure.
-				has_err = true;
-				has_ok = true;
-				break;
+		session_privs
+	}
+
+	fn send_payment_internal(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>, keysend_preimage: Option<PaymentPreimage>, custom_tlvs: &[(u64, Vec<u8>)], rebalance_preimage: Option<PaymentPreimage>) -> Result<PaymentId, PaymentSendFailure> {
+		let mut custom_types = HashSet::with_capacity(custom_tlvs.len());
+		for &(typ, _) in custom_tlvs.iter() {
+			if typ < msgs::CUSTOM_TLV_TYPE_MIN || typ == msgs::KEYSEND_PREIMAGE_TLV_TYPE {
+				return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: format!("Custom TLV type {} is reserved", typ)}));
+			}
+			if !custom_types.insert(typ) {
+				return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: format!("Custom TLV type {} was provided more than once", typ)}));
+			}
+		}
+		let total_value = self.check_route_paths(route)?;
+
+		let payment_id = PaymentId(self.keys_manager.get_secure_random_bytes());
+		let session_privs = {
+			let mut payment = PendingOutboundPayment {
+				payment_hash,
+				payment_secret: *payment_secret,
+				keysend_preimage,
+				custom_tlvs: custom_tlvs.to_vec(),
+				total_msat: total_value,
+				pending_paths: HashMap::new(),
+				fulfilled: false,
+				abandoned: false,
+				rebalance_preimage,
+				timer_ticks_without_paths: 0,
+			};
+			let session_privs = self.reserve_payment_paths(&mut payment, route);
+			self.pending_outbound_payments.lock().unwrap().insert(payment_id, payment);
+			session_privs
+		};
+
+		match self.send_payment_paths(route, payment_id, &session_privs, &payment_hash, payment_secret, total_value, &keysend_preimage, custom_tlvs) {
+			Ok(()) => Ok(payment_id),
+			Err(PaymentSendFailure::AllFailedRetrySafe(errs)) => {
+				// Nothing was sent, so the payment may be freely sent again and needn't be tracked.
+				self.pending_outbound_payments.lock().unwrap().remove(&payment_id);
+				Err(PaymentSendFailure::AllFailedRetrySafe(errs))
+			},
+			Err(e) => Err(e),
+		}
+	}
+
+	fn send_payment_paths(&self, route: &Route, payment_id: PaymentId, session_privs: &[SecretKey], payment_hash: &PaymentHash, payment_secret: &Option<PaymentSecret>, total_value: u64, keysend_preimage: &Option<PaymentPreimage>, custom_tlvs: &[(u64, Vec<u8>)]) -> Result<(), PaymentSendFailure> {
+		let cur_height = self.latest_block_height.load(Ordering::Acquire) as u32 + 1;
+		let mut results = Vec::new();
+		for (path, session_priv) in route.paths.iter().zip(session_privs.iter()) {
+			let res = self.send_payment_along_path(&path, payment_hash, payment_secret, total_value, cur_height, payment_id, session_priv, keysend_preimage, custom_tlvs);
+			match res {
+				Ok(()) | Err(APIError::MonitorUpdateFailed) => {},
+				Err(_) => {
+					// The HTLC was never committed to, so it will never be claimed or failed.
+					if let Some(payment) = self.pending_outbound_payments.lock().unwrap().get_mut(&payment_id) {
+						payment.pending_paths.remove(&session_priv_bytes(session_priv));
+					}
+				},
+			}
+			results.push(res);
+		}
+		let mut has_ok = false;
+		let mut has_err = false;
+		for res in results.iter() {
+			if res.is_ok() { has_ok = true; }
+			if res.is_err() { has_err = true; }
+			if let &Err(APIError::MonitorUpdateFailed) = res {
+				// MonitorUpdateFailed is inherently unsafe to retry, so we call it a
+				// PartialFailure.
+				has_err = true;
+				has_ok = true;
+				break;
 			}
 		}
 		if has_err && has_ok {
-			Err(PaymentSendFailure::PartialFailure(results))
+			Err(PaymentSendFailure::PartialFailure { results, payment_id })
 		} else if has_err {
 			Err(PaymentSendFailure::AllFailedRetrySafe(results.drain(..).map(|r| r.unwrap_err()).collect()))
 		} else {
@@ -1425,12 +2194,183 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Retries a payment previously sent via send_payment or send_spontaneous_payment along the
+	/// given route, eg after some of its paths failed (see Event::PaymentFailed).
+	///
+	/// The paths in route must deliver, in addition to the paths of the payment which are still
+	/// pending, at most the total value of the original payment, so that a retry never results in
+	/// the recipient being paid more than once. Retrying is refused once any path of the payment
+	/// has been claimed, or after abandon_payment has been called for it. As the payment is
+	/// persisted with the ChannelManager, this also holds across restarts.
+	///
+	/// A payment whose paths have all failed may only be retried for FAILED_PAYMENT_EXPIRY_TICKS
+	/// (10) calls to timer_chan_freshness_every_min, after which it is forgotten.
+	///
+	/// Errors are as documented on send_payment, with a ParameterError additionally returned if
+	/// the payment is unknown or may not be retried.
+	pub fn retry_payment(&self, route: &Route, payment_id: PaymentId) -> Result<(), PaymentSendFailure> {
+		let retry_value = self.check_route_paths(route)?;
+		let (session_privs, payment_hash, payment_secret, keysend_preimage, custom_tlvs, total_msat) = {
+			let mut pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+			let payment = match pending_outbound_payments.get_mut(&payment_id) {
+				Some(payment) => payment,
+				None => return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: format!("Payment with id {} not found", log_bytes!(payment_id.0))})),
+			};
+			if payment.fulfilled {
+				return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: format!("Payment with id {} has already been claimed", log_bytes!(payment_id.0))}));
+			}
+			if payment.abandoned {
+				return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{err: format!("Payment with id {} has been abandoned", log_bytes!(payment_id.0))}));
+			}
+			if payment.keysend_preimage.is_some() && route.paths.len() > 1 {
+				return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Spontaneous payments cannot be sent over multiple paths"}));
+			}
+			let pending_amt_msat = payment.pending_amt_msat();
+			if pending_amt_msat + retry_value > payment.total_msat {
+				return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError{
+					err: format!("Retrying {} msat of payment with id {} would overpay it, as {} msat of its {} msat are still pending",
+						retry_value, log_bytes!(payment_id.0), pending_amt_msat, payment.total_msat)
+				}));
+			}
+			(self.reserve_payment_paths(payment, route), payment.payment_hash, payment.payment_secret,
+				payment.keysend_preimage, payment.custom_tlvs.clone(), payment.total_msat)
+		};
+		self.send_payment_paths(route, payment_id, &session_privs, &payment_hash, &payment_secret, total_msat, &keysend_preimage, &custom_tlvs)
+	}
+
+	/// Gives up on the given payment, causing any further retry_payment calls for it to fail.
+	///
+	/// Paths of the payment which are still pending may still be claimed or fail, generating the
+	/// usual events. The payment is forgotten once all of them are resolved.
+	///
+	/// Calling this isn't required to free the payment once all of its paths failed, as
+	/// timer_chan_freshness_every_min forgets such payments after a few ticks, but it ensures no
+	/// retry can be sent in the mean time.
+	pub fn abandon_payment(&self, payment_id: PaymentId) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let mut pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+		if let hash_map::Entry::Occupied(mut payment) = pending_outbound_payments.entry(payment_id) {
+			payment.get_mut().abandoned = true;
+			if payment.get().is_complete() {
+				payment.remove();
+			}
+		}
+	}
+
+	/// Stops tracking the given path of an outbound payment as it failed. Returns None if the path
+	/// was already resolved, in which case no event should be generated for it, and otherwise
+	/// whether the payment has no other pending paths and wasn't claimed.
+	fn outbound_path_failed(&self, payment_id: &PaymentId, session_priv: &SecretKey) -> Option<bool> {
+		let mut pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+		if let hash_map::Entry::Occupied(mut payment) = pending_outbound_payments.entry(*payment_id) {
+			if payment.get_mut().pending_paths.remove(&session_priv_bytes(session_priv)).is_none() {
+				log_trace!(self.logger, "Ignoring failure of already-resolved path of payment with id {}", log_bytes!(payment_id.0));
+				return None;
+			}
+			let all_paths_failed = payment.get().pending_paths.is_empty() && !payment.get().fulfilled;
+			if payment.get().is_complete() {
+				payment.remove();
+			}
+			Some(all_paths_failed)
+		} else {
+			// The path was sent without being tracked (or the payment was already forgotten), so
+			// as far as we know nothing else is pending.
+			Some(true)
+		}
+	}
+
+	/// Stops tracking the given path of an outbound payment as it was claimed, marking the payment
+	/// fulfilled. Returns false if the path was already resolved, in which case no event should
+	/// be generated for it.
+	fn outbound_path_claimed(&self, payment_id: &PaymentId, session_priv: &SecretKey) -> bool {
+		let mut pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+		if let hash_map::Entry::Occupied(mut payment) = pending_outbound_payments.entry(*payment_id) {
+			if payment.get_mut().pending_paths.remove(&session_priv_bytes(session_priv)).is_none() {
+				log_trace!(self.logger, "Ignoring claim of already-resolved path of payment with id {}", log_bytes!(payment_id.0));
+				return false;
+			}
+			payment.get_mut().fulfilled = true;
+			if payment.get().is_complete() {
+				payment.remove();
+			}
+		}
+		true
+	}
+
+	/// Sends a spontaneous (keysend) payment along a given route, without the recipient having
+	/// handed us a payment_hash first.
+	///
+	/// The payment preimage is included in the final hop's onion payload (TLV type 5482373484),
+	/// allowing the recipient to claim the payment without any prior interaction. If
+	/// payment_preimage is None, a random one is generated using our KeysInterface.
+	///
+	/// Returns the payment_hash (the SHA256 of the preimage) and the PaymentId identifying the
+	/// payment in PaymentSent/PaymentFailed events on success.
+	///
+	/// Because no payment_secret is provided, the route must consist of a single path and the
+	/// recipient must support variable-length onions. Otherwise, errors are as documented on
+	/// send_payment.
+	pub fn send_spontaneous_payment(&self, route: &Route, payment_preimage: Option<PaymentPreimage>) -> Result<(PaymentHash, PaymentId), PaymentSendFailure> {
+		if route.paths.len() > 1 {
+			return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Spontaneous payments cannot be sent over multiple paths"}));
+		}
+		let preimage = match payment_preimage {
+			Some(p) => p,
+			None => PaymentPreimage(self.keys_manager.get_secure_random_bytes()),
+		};
+		let payment_hash = PaymentHash(Sha256::hash(&preimage.0).into_inner());
+		match self.send_payment_internal(route, payment_hash, &None, Some(preimage), &[], None) {
+			Ok(payment_id) => Ok((payment_hash, payment_id)),
+			Err(e) => Err(e)
+		}
+	}
+
+	/// Sends a circular payment from ourselves back to ourselves along the given route, moving
+	/// liquidity from the channel each path leaves through to the channel it comes back in on.
+	/// Such routes may be built with router::get_rebalance_route.
+	///
+	/// A random payment preimage and payment_secret are generated for the payment. Once the full
+	/// amount arrives back at us we claim it automatically, without generating a PaymentReceived
+	/// event. The outcome is reported as for any other outbound payment, with the fee spent on
+	/// the rebalance available in PaymentSent::fee_paid_msat.
+	///
+	/// Every path in the route must end at us. Otherwise, errors are as documented on
+	/// send_payment.
+	pub fn send_rebalance(&self, route: &Route) -> Result<PaymentId, PaymentSendFailure> {
+		let our_node_id = self.get_our_node_id();
+		for path in route.paths.iter() {
+			if path.len() < 2 || path.last().unwrap().pubkey != our_node_id {
+				return Err(PaymentSendFailure::ParameterError(APIError::RouteError{err: "Rebalance paths must leave us and end at us"}));
+			}
+		}
+		let payment_preimage = PaymentPreimage(self.keys_manager.get_secure_random_bytes());
+		let payment_secret = PaymentSecret(self.keys_manager.get_secure_random_bytes());
+		let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
+		self.send_payment_internal(route, payment_hash, &Some(payment_secret), None, &[], Some(payment_preimage))
+	}
+
+	/// Returns the preimage of the rebalance with the given payment_hash and payment_secret, if
+	/// we're waiting for one to arrive back at us.
+	fn pending_rebalance_preimage(&self, payment_hash: &PaymentHash, payment_secret: &PaymentSecret) -> Option<PaymentPreimage> {
+		let pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+		for payment in pending_outbound_payments.values() {
+			if payment.payment_hash == *payment_hash && payment.payment_secret == Some(*payment_secret) {
+				if let Some(preimage) = payment.rebalance_preimage {
+					if !payment.abandoned { return Some(preimage); }
+				}
+			}
+		}
+		None
+	}
+
 	/// Call this upon creation of a funding transaction for the given channel.
 	///
 	/// Note that ALL inputs in the transaction pointed to by funding_txo MUST spend SegWit outputs
 	/// or your counterparty can steal your funds!
 	///
-	/// Panics if a funding transaction has already been provided for this channel.
+	/// Panics if a funding transaction has already been provided for this channel, or if the
+	/// channel is dual-funded.
 	///
 	/// May panic if the funding_txo is duplicative with some other channel (note that this should
 	/// be trivially prevented by using unique funding transaction keys per-channel).
@@ -1440,8 +2380,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		let (chan, msg) = {
 			let (res, chan) = match self.channel_state.lock().unwrap().by_id.remove(temporary_channel_id) {
 				Some(mut chan) => {
+					if chan.is_dual_funded_pending_funding() {
+						panic!("Tried to provide a funding transaction for a dual-funded channel");
+					}
 					(chan.get_outbound_funding_created(funding_txo, &self.logger)
 						.map_err(|e| if let ChannelError::Close(msg) = e {
+							self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: msg.clone() });
 							MsgHandleErrInternal::from_finish_shutdown(msg, chan.channel_id(), chan.force_shutdown(true), None)
 						} else { unreachable!(); })
 					, chan)
@@ -1471,6 +2415,43 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 	}
 
+	/// Provides the funding transaction of the given channel, which we otherwise only learn of
+	/// once it confirms as funding_transaction_generated and funding_created only carry its
+	/// outpoint (dual-funded channels construct it themselves).
+	///
+	/// Call this as soon as possible for zero-conf channels, both as the funder and as the
+	/// acceptor if the funder shares it, so that our ChannelMonitor can detect the funding
+	/// transaction being double-spent even if it never confirms, closing the channel and failing
+	/// back its HTLCs.
+	///
+	/// Returns an APIMisuseError if the channel isn't funded yet, the transaction isn't its funding
+	/// transaction or the latter is already known.
+	pub fn provide_funding_transaction(&self, channel_id: &[u8; 32], funding_transaction: Transaction) -> Result<(), APIError> {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+
+		let counterparty_node_id;
+		let err: Result<(), _> = loop {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(*channel_id) {
+				hash_map::Entry::Vacant(_) => return Err(APIError::APIMisuseError{err: format!("Failed to find corresponding channel for id {}", channel_id.to_hex())}),
+				hash_map::Entry::Occupied(mut chan) => {
+					counterparty_node_id = chan.get().get_counterparty_node_id();
+					let monitor_update = break_chan_entry!(self, chan.get_mut().provide_funding_transaction(funding_transaction), channel_state, chan);
+					if let Err(e) = self.chain_monitor.update_channel(chan.get().get_funding_txo().unwrap(), monitor_update) {
+						maybe_break_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, false);
+					}
+				},
+			}
+			return Ok(())
+		};
+
+		match handle_error!(self, err, counterparty_node_id) {
+			Ok(_) => unreachable!(),
+			Err(e) => { Err(APIError::APIMisuseError { err: e.err })}
+		}
+	}
+
 	fn get_announcement_sigs(&self, chan: &Channel<ChanSigner>) -> Option<msgs::AnnouncementSignatures> {
 		if !chan.should_announce() {
 			log_trace!(self.logger, "Can't send announcement_signatures for private channel {}", log_bytes!(chan.channel_id()));
@@ -1492,6 +2473,23 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		})
 	}
 
+	/// Gets the channel_update to send directly to our counterparty once a private channel is
+	/// usable, as it will never learn our forwarding policy for it through gossip but needs it to
+	/// give payers route hints through us.
+	///
+	/// Zero-conf channels whose funding transaction hasn't confirmed yet are referred to by the
+	/// alias our counterparty sent us, which is the one it knows the channel by.
+	fn get_private_channel_update(&self, chan: &Channel<ChanSigner>) -> Option<events::MessageSendEvent> {
+		if chan.should_announce() || !chan.is_usable() {
+			return None;
+		}
+		let short_channel_id = chan.get_short_channel_id().or(chan.get_counterparty_scid_alias())?;
+		Some(events::MessageSendEvent::SendChannelUpdate {
+			node_id: chan.get_counterparty_node_id(),
+			msg: self.get_channel_update_for_short_id(chan, short_channel_id),
+		})
+	}
+
 	#[allow(dead_code)]
 	// Messages of up to 64KB should never end up more than half full with addresses, as that would
 	// be absurd. We ensure this by checking that at least 500 (our stated public contract on when
@@ -1504,6 +2502,21 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	// smaller than 500:
 	const STATIC_ASSERT: u32 = Self::HALF_MESSAGE_IS_ADDRS - 500;
 
+	/// Gets the features we advertise, which depend on our default UserConfig.
+	fn our_init_features(&self) -> InitFeatures {
+		let mut features = InitFeatures::known();
+		if !self.default_configuration.own_channel_config.negotiate_anchor_outputs {
+			features = features.clear_anchors_zero_fee_htlc_tx();
+		}
+		if !self.default_configuration.own_channel_config.negotiate_dual_fund {
+			features = features.clear_dual_fund();
+		}
+		if !self.default_configuration.own_channel_config.negotiate_splicing {
+			features = features.clear_splicing();
+		}
+		features
+	}
+
 	/// Generates a signed node_announcement from the given arguments and creates a
 	/// BroadcastNodeAnnouncement event. Note that such messages will be ignored unless peers have
 	/// seen a channel_announcement from us (ie unless we have public channels open).
@@ -1525,7 +2538,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		}
 
 		let announcement = msgs::UnsignedNodeAnnouncement {
-			features: NodeFeatures::known(),
+			features: self.our_init_features().to_context(),
 			timestamp: self.last_node_announcement_serial.fetch_add(1, Ordering::AcqRel) as u32,
 			node_id: self.get_our_node_id(),
 			rgb, alias, addresses,
@@ -1552,6 +2565,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 		let mut new_events = Vec::new();
 		let mut failed_forwards = Vec::new();
+		let mut rebalances_to_claim = Vec::new();
 		let mut handle_errors = Vec::new();
 		{
 			let mut channel_state_lock = self.channel_state.lock().unwrap();
@@ -1612,7 +2626,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 											} else {
 												panic!("Stated return value requirements in send_htlc() were not met");
 											}
-											let chan_update = self.get_channel_update(chan.get()).unwrap();
+											let chan_update = self.get_channel_update_for_onion(chan.get()).unwrap();
 											failed_forwards.push((htlc_source, payment_hash,
 												HTLCFailReason::Reason { failure_code: 0x1000 | 7, data: chan_update.encode_with_len() }
 											));
@@ -1675,15 +2689,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									// close channel and then send error message to peer.
 									let counterparty_node_id = chan.get().get_counterparty_node_id();
 									let err: Result<(), _>  = match e {
-										ChannelError::Ignore(_) => {
+										ChannelError::Ignore(_) | ChannelError::Warn(_) => {
 											panic!("Stated return value requirements in send_commitment() were not met");
 										},
 										ChannelError::Close(msg) => {
 											log_trace!(self.logger, "Closing channel {} due to Close-required error: {}", log_bytes!(chan.key()[..]), msg);
 											let (channel_id, mut channel) = chan.remove_entry();
-											if let Some(short_id) = channel.get_short_channel_id() {
-												channel_state.short_to_id.remove(&short_id);
-											}
+											remove_short_ids!(channel_state.short_to_id, channel);
+											self.issue_channel_close_events(&channel, ClosureReason::ProcessingError { err: msg.clone() });
 											Err(MsgHandleErrInternal::from_finish_shutdown(msg, channel_id, channel.force_shutdown(true), self.get_channel_update(&channel).ok()))
 										},
 										ChannelError::CloseDelayBroadcast(_) => { panic!("Wait is only generated on receipt of channel_reestablish, which is handled by try_chan_entry, we don't bother to support it here"); }
@@ -1715,7 +2728,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					for forward_info in pending_forwards.drain(..) {
 						match forward_info {
 							HTLCForwardInfo::AddHTLC { prev_short_channel_id, prev_htlc_id, forward_info: PendingHTLCInfo {
-									routing: PendingHTLCRouting::Receive { payment_data, incoming_cltv_expiry },
+									routing: PendingHTLCRouting::Receive { payment_data, keysend_preimage, custom_tlvs, incoming_cltv_expiry },
 									incoming_shared_secret, payment_hash, amt_to_forward, .. },
 									prev_funding_outpoint } => {
 								let prev_hop = HTLCPreviousHopData {
@@ -1763,9 +2776,15 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 											));
 										}
 									} else if total_value == data.total_msat {
+										if let Some(preimage) = self.pending_rebalance_preimage(&payment_hash, &data.payment_secret) {
+											rebalances_to_claim.push((preimage, data.payment_secret, total_value));
+											continue;
+										}
 										new_events.push(events::Event::PaymentReceived {
 											payment_hash,
 											payment_secret: Some(data.payment_secret),
+											payment_preimage: keysend_preimage,
+											custom_tlvs,
 											amt: total_value,
 										});
 									}
@@ -1773,6 +2792,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 									new_events.push(events::Event::PaymentReceived {
 										payment_hash,
 										payment_secret: None,
+										payment_preimage: keysend_preimage,
+										custom_tlvs,
 										amt: amt_to_forward,
 									});
 								}
@@ -1797,6 +2818,12 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let _ = handle_error!(self, err, counterparty_node_id);
 		}
 
+		for (payment_preimage, payment_secret, amt) in rebalances_to_claim.drain(..) {
+			if !self.claim_payment_htlcs(payment_preimage, &Some(payment_secret), amt) {
+				log_warn!(self.logger, "Failed to claim rebalance payment which arrived back at us");
+			}
+		}
+
 		if new_events.is_empty() { return }
 		let mut events = self.pending_events.lock().unwrap();
 		events.append(&mut new_events);
@@ -1807,24 +2834,77 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	/// to inform the network about the uselessness of these channels.
 	///
 	/// This method handles all the details, and must be called roughly once per minute.
+	///
+	/// It also sends update_fee for outbound channels whose feerate drifted away from our
+	/// FeeEstimator's estimate, see ChannelConfig::auto_update_fee, and forgets channels which
+	/// haven't exchanged funding_created/funding_signed after an hour, generating a
+	/// ChannelClosed event with ClosureReason::FundingTimedOut for each. Outbound payments whose
+	/// paths all failed are forgotten after FAILED_PAYMENT_EXPIRY_TICKS calls, see retry_payment.
 	pub fn timer_chan_freshness_every_min(&self) {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
-		let mut channel_state_lock = self.channel_state.lock().unwrap();
-		let channel_state = &mut *channel_state_lock;
-		for (_, chan) in channel_state.by_id.iter_mut() {
-			if chan.is_disabled_staged() && !chan.is_live() {
-				if let Ok(update) = self.get_channel_update(&chan) {
-					channel_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
-						msg: update
+		let mut fee_updates = Vec::new();
+		let mut expired_channels = Vec::new();
+		{
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			let short_to_id = &mut channel_state.short_to_id;
+			let pending_msg_events = &mut channel_state.pending_msg_events;
+			channel_state.by_id.retain(|channel_id, chan| {
+				if chan.unfunded_timer_tick() {
+					log_info!(self.logger, "Timed out waiting for channel {} to be funded, forgetting it", log_bytes!(channel_id[..]));
+					remove_short_ids!(short_to_id, chan);
+					self.issue_channel_close_events(chan, ClosureReason::FundingTimedOut);
+					pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: chan.get_counterparty_node_id(),
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id: *channel_id,
+								data: "Timed out waiting for the channel to be funded".to_owned(),
+							},
+						},
 					});
+					expired_channels.push(chan.force_shutdown(false));
+					return false;
 				}
-				chan.to_fresh();
-			} else if chan.is_disabled_staged() && chan.is_live() {
-				chan.to_fresh();
-			} else if chan.is_disabled_marked() {
-				chan.to_disabled_staged();
-			}
+				chan.maybe_expire_prev_config();
+				if let Some(feerate_per_kw) = chan.get_auto_update_feerate(&self.fee_estimator) {
+					fee_updates.push((*channel_id, feerate_per_kw));
+				}
+				if chan.is_disabled_staged() && !chan.is_live() {
+					if let Ok(update) = self.get_channel_update(&chan) {
+						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
+							msg: update
+						});
+					}
+					chan.to_fresh();
+				} else if chan.is_disabled_staged() && chan.is_live() {
+					chan.to_fresh();
+				} else if chan.is_disabled_marked() {
+					chan.to_disabled_staged();
+				}
+				true
+			});
 		}
+		for shutdown_res in expired_channels.drain(..) {
+			self.finish_force_close_channel(shutdown_res);
+		}
+		for (channel_id, feerate_per_kw) in fee_updates.drain(..) {
+			log_debug!(self.logger, "Updating feerate of channel {} to {} sat/kw", log_bytes!(channel_id), feerate_per_kw);
+			let _ = self.update_fee_internal(channel_id, feerate_per_kw);
+		}
+		let logger = &self.logger;
+		self.pending_outbound_payments.lock().unwrap().retain(|payment_id, payment| {
+			if !payment.pending_paths.is_empty() || payment.fulfilled {
+				payment.timer_ticks_without_paths = 0;
+				return true;
+			}
+			payment.timer_ticks_without_paths += 1;
+			if payment.timer_ticks_without_paths >= FAILED_PAYMENT_EXPIRY_TICKS {
+				log_info!(logger, "Forgetting payment with id {} as all of its paths failed and it wasn't retried", log_bytes!(payment_id.0));
+				return false;
+			}
+			true
+		});
 	}
 
 	/// Indicates that the preimage for payment_hash is unknown or the received amount is incorrect
@@ -1874,15 +2954,24 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					self.fail_htlc_backwards_internal(channel_state,
 						htlc_src, &payment_hash, HTLCFailReason::Reason { failure_code, data: onion_failure_data});
 				},
-				HTLCSource::OutboundRoute { .. } => {
+				HTLCSource::OutboundRoute { path, session_priv, payment_id, .. } => {
+					let all_paths_failed = match self.outbound_path_failed(&payment_id, &session_priv) {
+						Some(all_paths_failed) => all_paths_failed,
+						None => continue,
+					};
 					self.pending_events.lock().unwrap().push(
 						events::Event::PaymentFailed {
 							payment_hash,
+							payment_id,
+							all_paths_failed,
 							rejected_by_dest: false,
-#[cfg(test)]
 							error_code: None,
-#[cfg(test)]
 							error_data: None,
+							failing_hop_index: None,
+							failing_node_id: None,
+							short_channel_id: path.first().map(|hop| hop.short_channel_id),
+							channel_update: None,
+							path,
 						}
 					)
 				},
@@ -1902,19 +2991,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		//between the branches here. We should make this async and move it into the forward HTLCs
 		//timer handling.
 		match source {
-			HTLCSource::OutboundRoute { ref path, .. } => {
+			HTLCSource::OutboundRoute { ref path, ref session_priv, ref payment_id, .. } => {
 				log_trace!(self.logger, "Failing outbound payment HTLC with payment_hash {}", log_bytes!(payment_hash.0));
 				mem::drop(channel_state_lock);
+				let all_paths_failed = match self.outbound_path_failed(payment_id, session_priv) {
+					Some(all_paths_failed) => all_paths_failed,
+					None => return,
+				};
 				match &onion_error {
 					&HTLCFailReason::LightningError { ref err } => {
-#[cfg(test)]
-						let (channel_update, payment_retryable, onion_error_code, onion_error_data) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
-#[cfg(not(test))]
-						let (channel_update, payment_retryable, _, _) = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
+						let failure = onion_utils::process_onion_failure(&self.secp_ctx, &self.logger, &source, err.data.clone());
 						// TODO: If we decided to blame ourselves (or one of our channels) in
 						// process_onion_failure we should close that channel as it implies our
 						// next-hop is needlessly blaming us!
-						if let Some(update) = channel_update {
+						if let Some(update) = failure.network_update {
 							self.channel_state.lock().unwrap().pending_msg_events.push(
 								events::MessageSendEvent::PaymentFailureNetworkUpdate {
 									update,
@@ -1924,20 +3014,20 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						self.pending_events.lock().unwrap().push(
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
-								rejected_by_dest: !payment_retryable,
-#[cfg(test)]
-								error_code: onion_error_code,
-#[cfg(test)]
-								error_data: onion_error_data
+								payment_id: *payment_id,
+								all_paths_failed,
+								rejected_by_dest: !failure.payment_retryable,
+								error_code: failure.error_code,
+								error_data: failure.error_data,
+								failing_hop_index: failure.failing_hop_index,
+								failing_node_id: failure.failing_hop_index.map(|idx| path[idx].pubkey),
+								short_channel_id: failure.short_channel_id,
+								channel_update: failure.channel_update,
+								path: path.clone(),
 							}
 						);
 					},
-					&HTLCFailReason::Reason {
-#[cfg(test)]
-							ref failure_code,
-#[cfg(test)]
-							ref data,
-							.. } => {
+					&HTLCFailReason::Reason { ref failure_code, ref data } => {
 						// we get a fail_malformed_htlc from the first hop
 						// TODO: We'd like to generate a PaymentFailureNetworkUpdate for temporary
 						// failures here, but that would be insufficient as get_route
@@ -1948,11 +3038,16 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						self.pending_events.lock().unwrap().push(
 							events::Event::PaymentFailed {
 								payment_hash: payment_hash.clone(),
+								payment_id: *payment_id,
+								all_paths_failed,
 								rejected_by_dest: path.len() == 1,
-#[cfg(test)]
 								error_code: Some(*failure_code),
-#[cfg(test)]
 								error_data: Some(data.clone()),
+								failing_hop_index: None,
+								failing_node_id: None,
+								short_channel_id: Some(path[0].short_channel_id),
+								channel_update: onion_utils::decode_failure_channel_update(*failure_code, &data[..]),
+								path: path.clone(),
 							}
 						);
 					}
@@ -2010,9 +3105,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	///
 	/// May panic if called except in response to a PaymentReceived event.
 	pub fn claim_funds(&self, payment_preimage: PaymentPreimage, payment_secret: &Option<PaymentSecret>, expected_amount: u64) -> bool {
-		let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
-
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		self.claim_payment_htlcs(payment_preimage, payment_secret, expected_amount)
+	}
+
+	/// Claims the claimable HTLCs for the given payment, see claim_funds. The caller must hold
+	/// the total_consistency_lock.
+	fn claim_payment_htlcs(&self, payment_preimage: PaymentPreimage, payment_secret: &Option<PaymentSecret>, expected_amount: u64) -> bool {
+		let payment_hash = PaymentHash(Sha256::hash(&payment_preimage.0).into_inner());
 
 		let mut channel_state = Some(self.channel_state.lock().unwrap());
 		let removed_source = channel_state.as_mut().unwrap().claimable_htlcs.remove(&(payment_hash, *payment_secret));
@@ -2145,12 +3245,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 	fn claim_funds_internal(&self, mut channel_state_lock: MutexGuard<ChannelHolder<ChanSigner>>, source: HTLCSource, payment_preimage: PaymentPreimage) {
 		match source {
-			HTLCSource::OutboundRoute { .. } => {
+			HTLCSource::OutboundRoute { path, session_priv, payment_id, .. } => {
 				mem::drop(channel_state_lock);
-				let mut pending_events = self.pending_events.lock().unwrap();
-				pending_events.push(events::Event::PaymentSent {
-					payment_preimage
-				});
+				if self.outbound_path_claimed(&payment_id, &session_priv) {
+					let fee_paid_msat = path.split_last().map(|(_, hops)| hops.iter().map(|hop| hop.fee_msat).sum()).unwrap_or(0);
+					let mut pending_events = self.pending_events.lock().unwrap();
+					pending_events.push(events::Event::PaymentSent {
+						payment_preimage,
+						payment_id,
+						path,
+						fee_paid_msat,
+					});
+				}
 			},
 			HTLCSource::PreviousHopData(hop_data) => {
 				let prev_outpoint = hop_data.outpoint;
@@ -2214,6 +3320,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		let mut htlc_forwards = Vec::new();
 		let mut htlc_failures = Vec::new();
 		let mut pending_events = Vec::new();
+		let mut funding_tx = None;
 
 		{
 			let mut channel_lock = self.channel_state.lock().unwrap();
@@ -2230,7 +3337,7 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 			let (raa, commitment_update, order, pending_forwards, mut pending_failures, needs_broadcast_safe, funding_locked) = channel.monitor_updating_restored(&self.logger);
 			if !pending_forwards.is_empty() {
-				htlc_forwards.push((channel.get_short_channel_id().expect("We can't have pending forwards before funding confirmation"), funding_txo.clone(), pending_forwards));
+				htlc_forwards.push((channel.get_short_channel_id().or(channel.get_scid_alias()).expect("We can't have pending forwards before funding_locked"), funding_txo.clone(), pending_forwards));
 			}
 			htlc_failures.append(&mut pending_failures);
 
@@ -2277,8 +3384,35 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg: announcement_sigs,
 					});
 				}
-				short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
+				if let Some(update) = self.get_private_channel_update(channel) {
+					pending_msg_events.push(update);
+				}
+				insert_short_ids!(short_to_id, channel);
 			}
+			match channel.maybe_sign_funding_tx(&self.keys_manager) {
+				Ok((tx_signatures, tx)) => {
+					if let Some(msg) = tx_signatures {
+						pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: channel.get_counterparty_node_id(),
+							msg,
+						});
+					}
+					if tx.is_some() {
+						self.maybe_send_zero_conf_funding_locked(channel, short_to_id, pending_msg_events);
+					}
+					funding_tx = tx;
+				},
+				Err(e) => {
+					log_error!(self.logger, "Failed to sign funding transaction for channel {}: {:?}", log_bytes!(channel.channel_id()), e);
+				},
+			}
+			if let Some(msg) = channel.maybe_send_splice_msg(&self.logger) {
+				pending_msg_events.push(msg.into_msg_send_event(channel.get_counterparty_node_id()));
+			}
+		}
+
+		if let Some(tx) = funding_tx {
+			self.tx_broadcaster.broadcast_transaction(&tx);
 		}
 
 		self.pending_events.lock().unwrap().append(&mut pending_events);
@@ -2298,19 +3432,37 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			return Err(MsgHandleErrInternal::send_err_msg_no_close("Unknown genesis block hash".to_owned(), msg.temporary_channel_id.clone()));
 		}
 
-		let channel = Channel::new_from_req(&self.fee_estimator, &self.keys_manager, counterparty_node_id.clone(), their_features, msg, 0, &self.default_configuration)
+		let mut channel = Channel::new_from_req(&self.fee_estimator, &self.keys_manager, counterparty_node_id.clone(), their_features.clone(), msg, 0, &self.default_configuration)
 			.map_err(|e| MsgHandleErrInternal::from_chan_no_close(e, msg.temporary_channel_id))?;
-		let mut channel_state_lock = self.channel_state.lock().unwrap();
-		let channel_state = &mut *channel_state_lock;
-		match channel_state.by_id.entry(channel.channel_id()) {
-			hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!".to_owned(), msg.temporary_channel_id.clone())),
-			hash_map::Entry::Vacant(entry) => {
-				channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
-					node_id: counterparty_node_id.clone(),
-					msg: channel.get_accept_channel(),
-				});
-				entry.insert(channel);
+		if self.trusted_zero_conf_peers.lock().unwrap().contains(counterparty_node_id) {
+			channel.set_zero_conf();
+		}
+		let awaiting_accept = {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(channel.channel_id()) {
+				hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!".to_owned(), msg.temporary_channel_id.clone())),
+				hash_map::Entry::Vacant(entry) => {
+					let awaiting_accept = channel.is_awaiting_accept();
+					if !awaiting_accept {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
+							node_id: counterparty_node_id.clone(),
+							msg: channel.get_accept_channel(),
+						});
+					}
+					entry.insert(channel);
+					awaiting_accept
+				}
 			}
+		};
+		if awaiting_accept {
+			self.pending_events.lock().unwrap().push(events::Event::OpenChannelRequest {
+				temporary_channel_id: msg.temporary_channel_id,
+				counterparty_node_id: *counterparty_node_id,
+				funding_satoshis: msg.funding_satoshis,
+				push_msat: msg.push_msat,
+				counterparty_features: their_features,
+			});
 		}
 		Ok(())
 	}
@@ -2329,100 +3481,421 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				},
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
 			}
-		};
-		let mut pending_events = self.pending_events.lock().unwrap();
-		pending_events.push(events::Event::FundingGenerationReady {
-			temporary_channel_id: msg.temporary_channel_id,
-			channel_value_satoshis: value,
-			output_script,
-			user_channel_id: user_id,
-		});
+		};
+		let mut pending_events = self.pending_events.lock().unwrap();
+		pending_events.push(events::Event::FundingGenerationReady {
+			temporary_channel_id: msg.temporary_channel_id,
+			channel_value_satoshis: value,
+			output_script,
+			user_channel_id: user_id,
+		});
+		Ok(())
+	}
+
+	fn internal_open_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) -> Result<(), MsgHandleErrInternal> {
+		if msg.chain_hash != self.genesis_hash {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("Unknown genesis block hash".to_owned(), msg.temporary_channel_id.clone()));
+		}
+		if !self.default_configuration.own_channel_config.negotiate_dual_fund {
+			return Err(MsgHandleErrInternal::send_err_msg_no_close("We don't support option_dual_fund".to_owned(), msg.temporary_channel_id.clone()));
+		}
+
+		let mut channel = Channel::new_from_req_v2(&self.fee_estimator, &self.keys_manager, counterparty_node_id.clone(), their_features.clone(), msg, 0, &self.default_configuration)
+			.map_err(|e| MsgHandleErrInternal::from_chan_no_close(e, msg.temporary_channel_id))?;
+		if self.trusted_zero_conf_peers.lock().unwrap().contains(counterparty_node_id) {
+			channel.set_zero_conf();
+		}
+		let awaiting_accept = {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(channel.channel_id()) {
+				hash_map::Entry::Occupied(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("temporary_channel_id collision!".to_owned(), msg.temporary_channel_id.clone())),
+				hash_map::Entry::Vacant(entry) => {
+					let awaiting_accept = channel.is_awaiting_accept();
+					if !awaiting_accept {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannelV2 {
+							node_id: counterparty_node_id.clone(),
+							msg: channel.get_accept_channel_v2(),
+						});
+					}
+					entry.insert(channel);
+					awaiting_accept
+				}
+			}
+		};
+		if awaiting_accept {
+			self.pending_events.lock().unwrap().push(events::Event::OpenChannelRequest {
+				temporary_channel_id: msg.temporary_channel_id,
+				counterparty_node_id: *counterparty_node_id,
+				funding_satoshis: msg.funding_satoshis,
+				push_msat: 0,
+				counterparty_features: their_features,
+			});
+		}
+		Ok(())
+	}
+
+	fn internal_accept_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_lock;
+		match channel_state.by_id.entry(msg.temporary_channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.temporary_channel_id));
+				}
+				let tx_msg = try_chan_entry!(self, chan.get_mut().accept_channel_v2(&msg, &self.default_configuration, their_features), channel_state, chan);
+				channel_state.pending_msg_events.push(tx_msg.into_msg_send_event(counterparty_node_id.clone()));
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
+		}
+	}
+
+	/// Handles a tx_add_input, tx_add_output or tx_complete message for the given channel, sending
+	/// our reply and, once the funding or splice transaction is constructed, our commitment_signed
+	/// for the counterparty's commitment transaction spending its funding output.
+	fn internal_interactive_tx<H>(&self, counterparty_node_id: &PublicKey, channel_id: [u8; 32], handle: H) -> Result<(), MsgHandleErrInternal>
+		where H: FnOnce(&mut Channel<ChanSigner>) -> Result<(Option<InteractiveTxMessage>, Option<msgs::CommitmentSigned>), ChannelError>
+	{
+		let mut channel_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_lock;
+		let funded_chan = match channel_state.by_id.entry(channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), channel_id));
+				}
+				let (tx_msg, commitment_signed) = try_chan_entry!(self, handle(chan.get_mut()), channel_state, chan);
+				if let Some(tx_msg) = tx_msg {
+					channel_state.pending_msg_events.push(tx_msg.into_msg_send_event(counterparty_node_id.clone()));
+				}
+				if let Some(msg) = commitment_signed {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
+						node_id: counterparty_node_id.clone(),
+						updates: msgs::CommitmentUpdate {
+							update_add_htlcs: Vec::new(),
+							update_fulfill_htlcs: Vec::new(),
+							update_fail_htlcs: Vec::new(),
+							update_fail_malformed_htlcs: Vec::new(),
+							update_fee: None,
+							commitment_signed: msg,
+						},
+					});
+				}
+				// Signing the initial commitment transactions of a dual-funded channel changed its
+				// channel_id
+				if chan.get().channel_id() != channel_id { Some(chan.remove()) } else { None }
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), channel_id))
+		};
+		if let Some(chan) = funded_chan {
+			match channel_state.by_id.entry(chan.channel_id()) {
+				hash_map::Entry::Occupied(_) => {
+					self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: "Already had channel with the new channel_id".to_owned() });
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Already had channel with the new channel_id".to_owned(), chan.channel_id()))
+				},
+				hash_map::Entry::Vacant(e) => {
+					e.insert(chan);
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn internal_funding_created(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingCreated) -> Result<(), MsgHandleErrInternal> {
+		let ((funding_msg, monitor), mut chan) = {
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			match channel_state.by_id.entry(msg.temporary_channel_id.clone()) {
+				hash_map::Entry::Occupied(mut chan) => {
+					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.temporary_channel_id));
+					}
+					(try_chan_entry!(self, chan.get_mut().funding_created(msg, &self.logger), channel_state, chan), chan.remove())
+				},
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
+			}
+		};
+		// Because we have exclusive ownership of the channel here we can release the channel_state
+		// lock before watch_channel
+		if let Err(e) = self.chain_monitor.watch_channel(monitor.get_funding_txo().0, monitor) {
+			match e {
+				ChannelMonitorUpdateErr::PermanentFailure => {
+					// Note that we reply with the new channel_id in error messages if we gave up on the
+					// channel, not the temporary_channel_id. This is compatible with ourselves, but the
+					// spec is somewhat ambiguous here. Not a huge deal since we'll send error messages for
+					// any messages referencing a previously-closed channel anyway.
+					// We do not do a force-close here as that would generate a monitor update for
+					// a monitor that we didn't manage to store (and that we don't care about - we
+					// don't respond with the funding_signed so the channel can never go on chain).
+					self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() });
+					let (_funding_txo_option, _monitor_update, failed_htlcs) = chan.force_shutdown(true);
+					assert!(failed_htlcs.is_empty());
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("ChannelMonitor storage failure".to_owned(), funding_msg.channel_id));
+				},
+				ChannelMonitorUpdateErr::TemporaryFailure => {
+					// There's no problem signing a counterparty's funding transaction if our monitor
+					// hasn't persisted to disk yet - we can't lose money on a transaction that we haven't
+					// accepted payment from yet. We do, however, need to wait to send our funding_locked
+					// until we have persisted our monitor.
+					chan.monitor_update_failed(false, false, Vec::new(), Vec::new());
+				},
+			}
+		}
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(funding_msg.channel_id) {
+			hash_map::Entry::Occupied(_) => {
+				self.issue_channel_close_events(&chan, ClosureReason::ProcessingError { err: "Already had channel with the new channel_id".to_owned() });
+				return Err(MsgHandleErrInternal::send_err_msg_no_close("Already had channel with the new channel_id".to_owned(), funding_msg.channel_id))
+			},
+			hash_map::Entry::Vacant(e) => {
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendFundingSigned {
+					node_id: counterparty_node_id.clone(),
+					msg: funding_msg,
+				});
+				self.maybe_send_zero_conf_funding_locked(&mut chan, &mut channel_state.short_to_id, &mut channel_state.pending_msg_events);
+				e.insert(chan);
+			}
+		}
+		Ok(())
+	}
+
+	fn internal_funding_signed(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingSigned) -> Result<(), MsgHandleErrInternal> {
+		let (funding_txo, user_id) = {
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			match channel_state.by_id.entry(msg.channel_id) {
+				hash_map::Entry::Occupied(mut chan) => {
+					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+					}
+					let monitor = match chan.get_mut().funding_signed(&msg, &self.logger) {
+						Ok(update) => update,
+						Err(e) => try_chan_entry!(self, Err(e), channel_state, chan),
+					};
+					if let Err(e) = self.chain_monitor.watch_channel(chan.get().get_funding_txo().unwrap(), monitor) {
+						return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+					}
+					self.maybe_send_zero_conf_funding_locked(chan.get_mut(), &mut channel_state.short_to_id, &mut channel_state.pending_msg_events);
+					(chan.get().get_funding_txo().unwrap(), chan.get().get_user_id())
+				},
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+			}
+		};
+		let mut pending_events = self.pending_events.lock().unwrap();
+		pending_events.push(events::Event::FundingBroadcastSafe {
+			funding_txo,
+			user_channel_id: user_id,
+		});
+		Ok(())
+	}
+
+	fn internal_tx_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxSignatures) -> Result<(), MsgHandleErrInternal> {
+		let funding_tx = {
+			let mut channel_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_lock;
+			match channel_state.by_id.entry(msg.channel_id) {
+				hash_map::Entry::Occupied(mut chan) => {
+					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+					}
+					try_chan_entry!(self, chan.get_mut().tx_signatures(&msg), channel_state, chan);
+					let (tx_signatures, funding_tx) = try_chan_entry!(self, chan.get_mut().maybe_sign_funding_tx(&self.keys_manager), channel_state, chan);
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: counterparty_node_id.clone(),
+							msg,
+						});
+					}
+					if funding_tx.is_some() {
+						self.maybe_send_zero_conf_funding_locked(chan.get_mut(), &mut channel_state.short_to_id, &mut channel_state.pending_msg_events);
+					}
+					funding_tx
+				},
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+			}
+		};
+		if let Some(tx) = funding_tx {
+			log_info!(self.logger, "Broadcasting funding transaction {} for channel {}", tx.txid(), log_bytes!(msg.channel_id));
+			self.tx_broadcaster.broadcast_transaction(&tx);
+		}
+		Ok(())
+	}
+
+	fn internal_stfu(&self, counterparty_node_id: &PublicKey, msg: &msgs::Stfu) -> Result<(), MsgHandleErrInternal> {
+		if !self.default_configuration.own_channel_config.negotiate_splicing {
+			return Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Warn("We don't support splicing".to_owned()), msg.channel_id));
+		}
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				try_chan_entry!(self, chan.get_mut().stfu(&msg), channel_state, chan);
+				if let Some(splice_msg) = chan.get_mut().maybe_send_splice_msg(&self.logger) {
+					channel_state.pending_msg_events.push(splice_msg.into_msg_send_event(counterparty_node_id.clone()));
+				}
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_splice(&self, counterparty_node_id: &PublicKey, msg: &msgs::Splice) -> Result<(), MsgHandleErrInternal> {
+		if !self.default_configuration.own_channel_config.negotiate_splicing {
+			return Err(MsgHandleErrInternal::from_chan_no_close(ChannelError::Warn("We don't support splicing".to_owned()), msg.channel_id));
+		}
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let splice_ack = try_chan_entry!(self, chan.get_mut().splice(&msg, &self.fee_estimator), channel_state, chan);
+				channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceAck {
+					node_id: counterparty_node_id.clone(),
+					msg: splice_ack,
+				});
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_splice_ack(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceAck) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		match channel_state.by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+				}
+				let tx_msg = try_chan_entry!(self, chan.get_mut().splice_ack(&msg), channel_state, chan);
+				channel_state.pending_msg_events.push(tx_msg.into_msg_send_event(counterparty_node_id.clone()));
+				Ok(())
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+		}
+	}
+
+	fn internal_tx_abort(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAbort) -> Result<(), MsgHandleErrInternal> {
+		{
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(msg.channel_id) {
+				hash_map::Entry::Occupied(mut chan) => {
+					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+					}
+					if let Some(tx_abort) = try_chan_entry!(self, chan.get_mut().tx_abort(&msg, &self.logger), channel_state, chan) {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxAbort {
+							node_id: counterparty_node_id.clone(),
+							msg: tx_abort,
+						});
+					}
+				},
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+			}
+		}
+		// The channel is no longer quiescent, send any update we held back during the splice.
+		self.free_holding_cell_htlcs_after_splice(&msg.channel_id);
 		Ok(())
 	}
 
-	fn internal_funding_created(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingCreated) -> Result<(), MsgHandleErrInternal> {
-		let ((funding_msg, monitor), mut chan) = {
-			let mut channel_lock = self.channel_state.lock().unwrap();
-			let channel_state = &mut *channel_lock;
-			match channel_state.by_id.entry(msg.temporary_channel_id.clone()) {
+	fn internal_splice_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceLocked) -> Result<(), MsgHandleErrInternal> {
+		{
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(msg.channel_id) {
 				hash_map::Entry::Occupied(mut chan) => {
 					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
-						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.temporary_channel_id));
+						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
 					}
-					(try_chan_entry!(self, chan.get_mut().funding_created(msg, &self.logger), channel_state, chan), chan.remove())
+					let prev_short_channel_id = chan.get().get_short_channel_id();
+					try_chan_entry!(self, chan.get_mut().splice_locked(&msg), channel_state, chan);
+					self.update_short_channel_id(chan.get(), prev_short_channel_id, &mut channel_state.short_to_id, &mut channel_state.pending_msg_events);
 				},
-				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.temporary_channel_id))
+				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
 			}
-		};
-		// Because we have exclusive ownership of the channel here we can release the channel_state
-		// lock before watch_channel
-		if let Err(e) = self.chain_monitor.watch_channel(monitor.get_funding_txo().0, monitor) {
-			match e {
-				ChannelMonitorUpdateErr::PermanentFailure => {
-					// Note that we reply with the new channel_id in error messages if we gave up on the
-					// channel, not the temporary_channel_id. This is compatible with ourselves, but the
-					// spec is somewhat ambiguous here. Not a huge deal since we'll send error messages for
-					// any messages referencing a previously-closed channel anyway.
-					// We do not do a force-close here as that would generate a monitor update for
-					// a monitor that we didn't manage to store (and that we don't care about - we
-					// don't respond with the funding_signed so the channel can never go on chain).
-					let (_funding_txo_option, _monitor_update, failed_htlcs) = chan.force_shutdown(true);
-					assert!(failed_htlcs.is_empty());
-					return Err(MsgHandleErrInternal::send_err_msg_no_close("ChannelMonitor storage failure".to_owned(), funding_msg.channel_id));
-				},
-				ChannelMonitorUpdateErr::TemporaryFailure => {
-					// There's no problem signing a counterparty's funding transaction if our monitor
-					// hasn't persisted to disk yet - we can't lose money on a transaction that we haven't
-					// accepted payment from yet. We do, however, need to wait to send our funding_locked
-					// until we have persisted our monitor.
-					chan.monitor_update_failed(false, false, Vec::new(), Vec::new());
-				},
+		}
+		self.free_holding_cell_htlcs_after_splice(&msg.channel_id);
+		Ok(())
+	}
+
+	/// Sends our funding_locked for a zero-conf channel as soon as its funding transaction is
+	/// signed, registering its alias so that HTLCs can be forwarded to it right away.
+	fn maybe_send_zero_conf_funding_locked(&self, chan: &mut Channel<ChanSigner>, short_to_id: &mut HashMap<u64, [u8; 32]>, pending_msg_events: &mut Vec<events::MessageSendEvent>) {
+		if let Some(msg) = chan.check_get_zero_conf_funding_locked() {
+			log_trace!(self.logger, "Sending funding_locked for zero-conf channel {}", log_bytes!(chan.channel_id()));
+			pending_msg_events.push(events::MessageSendEvent::SendFundingLocked {
+				node_id: chan.get_counterparty_node_id(),
+				msg,
+			});
+			if let Some(update) = self.get_private_channel_update(chan) {
+				pending_msg_events.push(update);
 			}
+			insert_short_ids!(short_to_id, chan);
 		}
-		let mut channel_state_lock = self.channel_state.lock().unwrap();
-		let channel_state = &mut *channel_state_lock;
-		match channel_state.by_id.entry(funding_msg.channel_id) {
-			hash_map::Entry::Occupied(_) => {
-				return Err(MsgHandleErrInternal::send_err_msg_no_close("Already had channel with the new channel_id".to_owned(), funding_msg.channel_id))
-			},
-			hash_map::Entry::Vacant(e) => {
-				channel_state.pending_msg_events.push(events::MessageSendEvent::SendFundingSigned {
-					node_id: counterparty_node_id.clone(),
-					msg: funding_msg,
-				});
-				e.insert(chan);
+	}
+
+	/// Moves a channel to its new short_channel_id once a splice completed or the funding
+	/// transaction of a zero-conf channel confirmed, sending our announcement_signatures for it.
+	/// The previous short_channel_id is kept until the HTLCs we received over it are resolved.
+	fn update_short_channel_id(&self, chan: &Channel<ChanSigner>, prev_short_channel_id: Option<u64>, short_to_id: &mut HashMap<u64, [u8; 32]>, pending_msg_events: &mut Vec<events::MessageSendEvent>) {
+		let short_channel_id = chan.get_short_channel_id();
+		if short_channel_id == prev_short_channel_id {
+			return;
+		}
+		log_info!(self.logger, "Channel {} moving to short_channel_id {}", log_bytes!(chan.channel_id()), short_channel_id.unwrap());
+		if let Some(short_id) = prev_short_channel_id {
+			if !chan.get_pre_splice_short_channel_ids().contains(&short_id) {
+				short_to_id.remove(&short_id);
 			}
 		}
-		Ok(())
+		short_to_id.insert(short_channel_id.unwrap(), chan.channel_id());
+		if let Some(announcement_sigs) = self.get_announcement_sigs(chan) {
+			pending_msg_events.push(events::MessageSendEvent::SendAnnouncementSignatures {
+				node_id: chan.get_counterparty_node_id(),
+				msg: announcement_sigs,
+			});
+		}
 	}
 
-	fn internal_funding_signed(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingSigned) -> Result<(), MsgHandleErrInternal> {
-		let (funding_txo, user_id) = {
-			let mut channel_lock = self.channel_state.lock().unwrap();
-			let channel_state = &mut *channel_lock;
-			match channel_state.by_id.entry(msg.channel_id) {
+	/// Sends the HTLC and fee updates which were kept in the holding cell while a channel was
+	/// quiescent for a splice, then starts our own splice if our counterparty's went first.
+	fn free_holding_cell_htlcs_after_splice(&self, channel_id: &[u8; 32]) {
+		let mut htlcs_to_fail = Vec::new();
+		let counterparty_node_id;
+		let res = loop {
+			let mut channel_state_lock = self.channel_state.lock().unwrap();
+			let channel_state = &mut *channel_state_lock;
+			match channel_state.by_id.entry(*channel_id) {
 				hash_map::Entry::Occupied(mut chan) => {
-					if chan.get().get_counterparty_node_id() != *counterparty_node_id {
-						return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
+					counterparty_node_id = chan.get().get_counterparty_node_id();
+					let (commitment_update, htlcs_to_fail_in) = break_chan_entry!(self, chan.get_mut().maybe_free_holding_cell_htlcs(&self.logger), channel_state, chan);
+					htlcs_to_fail = htlcs_to_fail_in;
+					if let Some((updates, monitor_update)) = commitment_update {
+						if let Err(e) = self.chain_monitor.update_channel(chan.get().get_funding_txo().unwrap(), monitor_update) {
+							break handle_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, true);
+						}
+						channel_state.pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
+							node_id: counterparty_node_id.clone(),
+							updates,
+						});
 					}
-					let monitor = match chan.get_mut().funding_signed(&msg, &self.logger) {
-						Ok(update) => update,
-						Err(e) => try_chan_entry!(self, Err(e), channel_state, chan),
-					};
-					if let Err(e) = self.chain_monitor.watch_channel(chan.get().get_funding_txo().unwrap(), monitor) {
-						return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+					if let Some(msg) = chan.get_mut().maybe_send_splice_msg(&self.logger) {
+						channel_state.pending_msg_events.push(msg.into_msg_send_event(counterparty_node_id.clone()));
 					}
-					(chan.get().get_funding_txo().unwrap(), chan.get().get_user_id())
+					break Ok(());
 				},
-				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
+				hash_map::Entry::Vacant(_) => return,
 			}
 		};
-		let mut pending_events = self.pending_events.lock().unwrap();
-		pending_events.push(events::Event::FundingBroadcastSafe {
-			funding_txo,
-			user_channel_id: user_id,
-		});
-		Ok(())
+		self.fail_holding_cell_htlcs(htlcs_to_fail, *channel_id);
+		let _ = handle_error!(self, res, counterparty_node_id);
 	}
 
 	fn internal_funding_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingLocked) -> Result<(), MsgHandleErrInternal> {
@@ -2450,6 +3923,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg: announcement_sigs,
 					});
 				}
+				if let Some(update) = self.get_private_channel_update(chan.get()) {
+					channel_state.pending_msg_events.push(update);
+				}
 				Ok(())
 			},
 			hash_map::Entry::Vacant(_) => Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
@@ -2480,9 +3956,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						});
 					}
 					if chan_entry.get().is_shutdown() {
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
-							channel_state.short_to_id.remove(&short_id);
-						}
+						remove_short_ids!(channel_state.short_to_id, chan_entry.get());
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(dropped_htlcs, Some(chan_entry.remove_entry().1))
 					} else { (dropped_htlcs, None) }
 				},
@@ -2525,9 +4000,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						// also implies there are no pending HTLCs left on the channel, so we can
 						// fully delete it from tracking (the channel monitor is still around to
 						// watch for old state broadcasts)!
-						if let Some(short_id) = chan_entry.get().get_short_channel_id() {
-							channel_state.short_to_id.remove(&short_id);
-						}
+						remove_short_ids!(channel_state.short_to_id, chan_entry.get());
+						self.issue_channel_close_events(chan_entry.get(), ClosureReason::CooperativeClosure);
 						(tx, Some(chan_entry.remove_entry().1))
 					} else { (tx, None) }
 				},
@@ -2674,6 +4148,38 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 				if chan.get().get_counterparty_node_id() != *counterparty_node_id {
 					return Err(MsgHandleErrInternal::send_err_msg_no_close("Got a message for a channel from the wrong node!".to_owned(), msg.channel_id));
 				}
+				if chan.get().is_awaiting_initial_commitment_signed() {
+					let monitor = try_chan_entry!(self, chan.get_mut().initial_commitment_signed(&msg, &self.logger), channel_state, chan);
+					if let Err(e) = self.chain_monitor.watch_channel(chan.get().get_funding_txo().unwrap(), monitor) {
+						return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+					}
+					// If we contribute less to the channel we sign the funding transaction first, now
+					// that our ChannelMonitor can claim our balance.
+					let (tx_signatures, _) = try_chan_entry!(self, chan.get_mut().maybe_sign_funding_tx(&self.keys_manager), channel_state, chan);
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: counterparty_node_id.clone(),
+							msg,
+						});
+					}
+					return Ok(());
+				}
+				if chan.get().is_awaiting_splice_commitment_signed() {
+					let monitor_update = try_chan_entry!(self, chan.get_mut().splice_commitment_signed(&msg, &self.logger), channel_state, chan);
+					if let Err(e) = self.chain_monitor.update_channel(chan.get().get_funding_txo().unwrap(), monitor_update) {
+						return_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::RevokeAndACKFirst, false, false);
+					}
+					// Our counterparty only sends its tx_signatures after our commitment_signed, so
+					// the splice transaction can't be fully signed yet.
+					let (tx_signatures, _) = try_chan_entry!(self, chan.get_mut().maybe_sign_funding_tx(&self.keys_manager), channel_state, chan);
+					if let Some(msg) = tx_signatures {
+						channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+							node_id: counterparty_node_id.clone(),
+							msg,
+						});
+					}
+					return Ok(());
+				}
 				let (revoke_and_ack, commitment_signed, closing_signed, monitor_update) =
 					match chan.get_mut().commitment_signed(&msg, &self.fee_estimator, &self.logger) {
 						Err((None, e)) => try_chan_entry!(self, Err(e), channel_state, chan),
@@ -2712,6 +4218,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg,
 					});
 				}
+				if let Some(msg) = chan.get_mut().maybe_send_splice_msg(&self.logger) {
+					channel_state.pending_msg_events.push(msg.into_msg_send_event(counterparty_node_id.clone()));
+				}
 				Ok(())
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
@@ -2791,7 +4300,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 							msg,
 						});
 					}
-					break Ok((pending_forwards, pending_failures, chan.get().get_short_channel_id().expect("RAA should only work on a short-id-available channel"), chan.get().get_funding_txo().unwrap()))
+					if let Some(msg) = chan.get_mut().maybe_send_splice_msg(&self.logger) {
+						channel_state.pending_msg_events.push(msg.into_msg_send_event(counterparty_node_id.clone()));
+					}
+					for short_id in chan.get_mut().prune_pre_splice_short_channel_ids() {
+						log_trace!(self.logger, "Forgetting pre-splice short_channel_id {} of channel {}", short_id, log_bytes!(msg.channel_id));
+						channel_state.short_to_id.remove(&short_id);
+					}
+					break Ok((pending_forwards, pending_failures, chan.get().get_short_channel_id().or(chan.get().get_scid_alias()).expect("RAA should only work on a short-id-available channel"), chan.get().get_funding_txo().unwrap()))
 				},
 				hash_map::Entry::Vacant(_) => break Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
 			}
@@ -2878,6 +4394,34 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		Ok(())
 	}
 
+	fn internal_channel_update(&self, msg: &msgs::ChannelUpdate) -> Result<(), MsgHandleErrInternal> {
+		let mut channel_state_lock = self.channel_state.lock().unwrap();
+		let channel_state = &mut *channel_state_lock;
+		// Our counterparty refers to zero-conf channels by the alias we gave it, which is indexed
+		// along with the short_channel_ids of all of our other channels. Most updates are gossip
+		// for channels which aren't ours at all.
+		let chan_id = match channel_state.short_to_id.get(&msg.contents.short_channel_id) {
+			Some(chan_id) => chan_id.clone(),
+			None => return Ok(()),
+		};
+		let chan = channel_state.by_id.get_mut(&chan_id).unwrap();
+		let counterparty_node_id = chan.get_counterparty_node_id();
+		// Updates for our direction of the channel, or relayed through some other peer for the
+		// counterparty's, are simply none of our business here.
+		let were_node_one = self.get_our_node_id().serialize()[..] < counterparty_node_id.serialize()[..];
+		if (msg.contents.flags & 1 == 1) != were_node_one {
+			return Ok(());
+		}
+		let msg_hash = hash_to_message!(&Sha256dHash::hash(&msg.contents.encode()[..])[..]);
+		if self.secp_ctx.verify(&msg_hash, &msg.signature, &counterparty_node_id).is_err() {
+			return Err(MsgHandleErrInternal::from_no_close(LightningError{err: "Invalid signature on channel_update for one of our channels".to_owned(), action: msgs::ErrorAction::IgnoreError}));
+		}
+		if chan.channel_update(msg) {
+			log_trace!(self.logger, "Counterparty updated its forwarding policy for channel {}", log_bytes!(chan_id[..]));
+		}
+		Ok(())
+	}
+
 	fn internal_channel_reestablish(&self, counterparty_node_id: &PublicKey, msg: &msgs::ChannelReestablish) -> Result<(), MsgHandleErrInternal> {
 		let mut channel_state_lock = self.channel_state.lock().unwrap();
 		let channel_state = &mut *channel_state_lock;
@@ -2908,6 +4452,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						//TODO: Resend the funding_locked if needed once we get the monitor running again
 					}
 				}
+				if let Some(msg) = chan.get().get_funding_tx_signatures_to_resend() {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendTxSignatures {
+						node_id: counterparty_node_id.clone(),
+						msg
+					});
+				}
+				if let Some(msg) = chan.get().get_splice_locked_to_resend() {
+					channel_state.pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+						node_id: counterparty_node_id.clone(),
+						msg
+					});
+				}
 				if let Some(msg) = funding_locked {
 					channel_state.pending_msg_events.push(events::MessageSendEvent::SendFundingLocked {
 						node_id: counterparty_node_id.clone(),
@@ -2946,6 +4502,14 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						msg,
 					});
 				}
+				if let Some(msg) = chan.get_mut().maybe_send_splice_msg(&self.logger) {
+					channel_state.pending_msg_events.push(msg.into_msg_send_event(counterparty_node_id.clone()));
+				}
+				// Our counterparty may have missed our channel_update if we disconnected right after
+				// sending it, so resend it for private channels.
+				if let Some(update) = self.get_private_channel_update(chan.get()) {
+					channel_state.pending_msg_events.push(update);
+				}
 				Ok(())
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close("Failed to find corresponding channel".to_owned(), msg.channel_id))
@@ -2960,6 +4524,11 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 	#[doc(hidden)]
 	pub fn update_fee(&self, channel_id: [u8;32], feerate_per_kw: u32) -> Result<(), APIError> {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		self.update_fee_internal(channel_id, feerate_per_kw)
+	}
+
+	/// update_fee without taking the total_consistency_lock, which callers must hold.
+	fn update_fee_internal(&self, channel_id: [u8;32], feerate_per_kw: u32) -> Result<(), APIError> {
 		let counterparty_node_id;
 		let err: Result<(), _> = loop {
 			let mut channel_state_lock = self.channel_state.lock().unwrap();
@@ -2981,8 +4550,11 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					if let Some((update_fee, commitment_signed, monitor_update)) =
 							break_chan_entry!(self, chan.get_mut().send_update_fee_and_commit(feerate_per_kw, &self.logger), channel_state, chan)
 					{
-						if let Err(_e) = self.chain_monitor.update_channel(chan.get().get_funding_txo().unwrap(), monitor_update) {
-							unimplemented!();
+						if let Err(e) = self.chain_monitor.update_channel(chan.get().get_funding_txo().unwrap(), monitor_update) {
+							maybe_break_monitor_err!(self, e, channel_state, chan, RAACommitmentOrder::CommitmentFirst, false, true);
+							// The update_fee and commitment_signed are resent once monitor updating
+							// is restored.
+							return Err(APIError::MonitorUpdateFailed);
 						}
 						channel_state.pending_msg_events.push(events::MessageSendEvent::UpdateHTLCs {
 							node_id: chan.get().get_counterparty_node_id(),
@@ -3029,9 +4601,8 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						let short_to_id = &mut channel_state.short_to_id;
 						let pending_msg_events = &mut channel_state.pending_msg_events;
 						if let Some(mut chan) = by_id.remove(&funding_outpoint.to_channel_id()) {
-							if let Some(short_id) = chan.get_short_channel_id() {
-								short_to_id.remove(&short_id);
-							}
+							remove_short_ids!(short_to_id, chan);
+							self.issue_channel_close_events(&chan, ClosureReason::HolderForceClosed);
 							failed_channels.push(chan.force_shutdown(false));
 							if let Ok(update) = self.get_channel_update(&chan) {
 								pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
@@ -3102,16 +4673,18 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
 		let mut failed_channels = Vec::new();
 		let mut timed_out_htlcs = Vec::new();
+		let mut spliced_channels = Vec::new();
 		{
 			let mut channel_lock = self.channel_state.lock().unwrap();
 			let channel_state = &mut *channel_lock;
 			let short_to_id = &mut channel_state.short_to_id;
 			let pending_msg_events = &mut channel_state.pending_msg_events;
 			channel_state.by_id.retain(|_, channel| {
+				let prev_short_channel_id = channel.get_short_channel_id();
 				let res = channel.block_connected(header, txdata, height);
-				if let Ok((chan_res, mut timed_out_pending_htlcs)) = res {
+				if let Ok((chan_res, splice_locked, mut timed_out_pending_htlcs)) = res {
 					for (source, payment_hash) in timed_out_pending_htlcs.drain(..) {
-						let chan_update = self.get_channel_update(&channel).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id so unwrap is safe
+						let chan_update = self.get_channel_update_for_onion(&channel).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id or alias so unwrap is safe
 						timed_out_htlcs.push((source, payment_hash,  HTLCFailReason::Reason {
 							failure_code: 0x1000 | 14, // expiry_too_soon, or at least it is now
 							data: chan_update,
@@ -3131,23 +4704,63 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 						} else {
 							log_trace!(self.logger, "Sending funding_locked WITHOUT announcement_signatures for {}", log_bytes!(channel.channel_id()));
 						}
-						short_to_id.insert(channel.get_short_channel_id().unwrap(), channel.channel_id());
+						if let Some(update) = self.get_private_channel_update(channel) {
+							pending_msg_events.push(update);
+						}
+						insert_short_ids!(short_to_id, channel);
+					}
+					if let Some(msg) = splice_locked {
+						pending_msg_events.push(events::MessageSendEvent::SendSpliceLocked {
+							node_id: channel.get_counterparty_node_id(),
+							msg,
+						});
+						if prev_short_channel_id != channel.get_short_channel_id() {
+							self.update_short_channel_id(channel, prev_short_channel_id, short_to_id, pending_msg_events);
+							spliced_channels.push(channel.channel_id());
+						}
+					} else if channel.get_scid_alias().is_some() && prev_short_channel_id != channel.get_short_channel_id() {
+						// Zero-conf channels only learn their short_channel_id once the funding
+						// transaction confirms, long after funding_locked.
+						self.update_short_channel_id(channel, prev_short_channel_id, short_to_id, pending_msg_events);
 					}
-				} else if let Err(e) = res {
+				} else if let Err((e, reason)) = res {
+					self.issue_channel_close_events(channel, reason);
 					pending_msg_events.push(events::MessageSendEvent::HandleError {
 						node_id: channel.get_counterparty_node_id(),
 						action: msgs::ErrorAction::SendErrorMessage { msg: e },
 					});
 					return false;
 				}
-				if let Some(funding_txo) = channel.get_funding_txo() {
+				if let Some(&(_, tx)) = txdata.iter().find(|&&(_, tx)| channel.is_funding_double_spent_by(tx)) {
+					log_error!(self.logger, "Funding transaction of channel {} was double-spent by {}, closing channel", log_bytes!(channel.channel_id()), tx.txid());
+					remove_short_ids!(short_to_id, channel);
+					self.issue_channel_close_events(channel, ClosureReason::ProcessingError { err: "Funding transaction was double-spent".to_owned() });
+					// There is no commitment transaction to broadcast anymore, our ChannelMonitor
+					// fails back any HTLCs once the double-spend is buried deep enough.
+					failed_channels.push(channel.force_shutdown(false));
+					pending_msg_events.push(events::MessageSendEvent::HandleError {
+						node_id: channel.get_counterparty_node_id(),
+						action: msgs::ErrorAction::SendErrorMessage {
+							msg: msgs::ErrorMessage {
+								channel_id: channel.channel_id(),
+								data: "Funding transaction was double-spent".to_owned(),
+							},
+						},
+					});
+					return false;
+				}
+				if let Some(funding_txo) = channel.get_current_funding_txo() {
+					// Once the splice transaction confirmed, our counterparty may go on-chain
+					// spending its funding output before the splice is locked.
+					let splice_funding_txo = channel.get_pending_splice_funding_txo().map(|txo| txo.into_bitcoin_outpoint());
 					for &(_, tx) in txdata.iter() {
+						// Splice transactions spend the current funding output too
+						if channel.get_pending_splice_txid() == Some(tx.txid()) { continue; }
 						for inp in tx.input.iter() {
-							if inp.previous_output == funding_txo.into_bitcoin_outpoint() {
+							if inp.previous_output == funding_txo.into_bitcoin_outpoint() || Some(inp.previous_output) == splice_funding_txo {
 								log_trace!(self.logger, "Detected channel-closing tx {} spending {}:{}, closing channel {}", tx.txid(), inp.previous_output.txid, inp.previous_output.vout, log_bytes!(channel.channel_id()));
-								if let Some(short_id) = channel.get_short_channel_id() {
-									short_to_id.remove(&short_id);
-								}
+								remove_short_ids!(short_to_id, channel);
+								self.issue_channel_close_events(channel, ClosureReason::CommitmentTxConfirmed);
 								// It looks like our counterparty went on-chain. We go ahead and
 								// broadcast our latest local state as well here, just in case its
 								// some kind of SPV attack, though we expect these to be dropped.
@@ -3187,6 +4800,9 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 		for failure in failed_channels.drain(..) {
 			self.finish_force_close_channel(failure);
 		}
+		for channel_id in spliced_channels.drain(..) {
+			self.free_holding_cell_htlcs_after_splice(&channel_id);
+		}
 
 		for (source, payment_hash, reason) in timed_out_htlcs.drain(..) {
 			self.fail_htlc_backwards_internal(self.channel_state.lock().unwrap(), source, &payment_hash, reason);
@@ -3220,10 +4836,10 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 			let short_to_id = &mut channel_state.short_to_id;
 			let pending_msg_events = &mut channel_state.pending_msg_events;
 			channel_state.by_id.retain(|_,  v| {
+				let prev_short_channel_id = v.get_short_channel_id();
 				if v.block_disconnected(header) {
-					if let Some(short_id) = v.get_short_channel_id() {
-						short_to_id.remove(&short_id);
-					}
+					remove_short_ids!(short_to_id, v);
+					self.issue_channel_close_events(v, ClosureReason::ProcessingError { err: "Funding transaction was reorged out".to_owned() });
 					failed_channels.push(v.force_shutdown(true));
 					if let Ok(update) = self.get_channel_update(&v) {
 						pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
@@ -3232,6 +4848,13 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 					}
 					false
 				} else {
+					// Zero-conf channels forget their short_channel_id if the funding transaction
+					// is reorged out, remaining usable through their alias.
+					if let Some(short_id) = prev_short_channel_id {
+						if v.get_short_channel_id() != prev_short_channel_id {
+							short_to_id.remove(&short_id);
+						}
+					}
 					true
 				}
 			});
@@ -3262,6 +4885,71 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		let _ = handle_error!(self, self.internal_accept_channel(counterparty_node_id, their_features, msg), *counterparty_node_id);
 	}
 
+	fn handle_open_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::OpenChannelV2) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_open_channel_v2(counterparty_node_id, their_features, msg), *counterparty_node_id);
+	}
+
+	fn handle_accept_channel_v2(&self, counterparty_node_id: &PublicKey, their_features: InitFeatures, msg: &msgs::AcceptChannelV2) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_accept_channel_v2(counterparty_node_id, their_features, msg), *counterparty_node_id);
+	}
+
+	fn handle_tx_add_input(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAddInput) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_interactive_tx(counterparty_node_id, msg.channel_id, |chan| chan.tx_add_input(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_add_output(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAddOutput) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_interactive_tx(counterparty_node_id, msg.channel_id, |chan| chan.tx_add_output(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_remove_input(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxRemoveInput) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_interactive_tx(counterparty_node_id, msg.channel_id, |chan| chan.tx_remove_input(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_remove_output(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxRemoveOutput) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_interactive_tx(counterparty_node_id, msg.channel_id, |chan| chan.tx_remove_output(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_complete(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxComplete) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_interactive_tx(counterparty_node_id, msg.channel_id, |chan| chan.tx_complete(msg, &self.logger)), *counterparty_node_id);
+	}
+
+	fn handle_tx_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxSignatures) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_signatures(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_tx_abort(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAbort) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_tx_abort(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_stfu(&self, counterparty_node_id: &PublicKey, msg: &msgs::Stfu) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_stfu(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice(&self, counterparty_node_id: &PublicKey, msg: &msgs::Splice) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice_ack(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceAck) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice_ack(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
+	fn handle_splice_locked(&self, counterparty_node_id: &PublicKey, msg: &msgs::SpliceLocked) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_splice_locked(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
 	fn handle_funding_created(&self, counterparty_node_id: &PublicKey, msg: &msgs::FundingCreated) {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
 		let _ = handle_error!(self, self.internal_funding_created(counterparty_node_id, msg), *counterparty_node_id);
@@ -3327,6 +5015,11 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		let _ = handle_error!(self, self.internal_announcement_signatures(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
+	fn handle_channel_update(&self, counterparty_node_id: &PublicKey, msg: &msgs::ChannelUpdate) {
+		let _consistency_lock = self.total_consistency_lock.read().unwrap();
+		let _ = handle_error!(self, self.internal_channel_update(msg), *counterparty_node_id);
+	}
+
 	fn handle_channel_reestablish(&self, counterparty_node_id: &PublicKey, msg: &msgs::ChannelReestablish) {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
 		let _ = handle_error!(self, self.internal_channel_reestablish(counterparty_node_id, msg), *counterparty_node_id);
@@ -3346,9 +5039,8 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				log_debug!(self.logger, "Failing all channels with {} due to no_connection_possible", log_pubkey!(counterparty_node_id));
 				channel_state.by_id.retain(|_, chan| {
 					if chan.get_counterparty_node_id() == *counterparty_node_id {
-						if let Some(short_id) = chan.get_short_channel_id() {
-							short_to_id.remove(&short_id);
-						}
+						remove_short_ids!(short_to_id, chan);
+						self.issue_channel_close_events(chan, ClosureReason::DisconnectedPeer);
 						failed_channels.push(chan.force_shutdown(true));
 						if let Ok(update) = self.get_channel_update(&chan) {
 							pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate {
@@ -3371,13 +5063,12 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 						let failed_adds = chan.remove_uncommitted_htlcs_and_mark_paused(&self.logger);
 						chan.to_disabled_marked();
 						if !failed_adds.is_empty() {
-							let chan_update = self.get_channel_update(&chan).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id so unwrap is safe
+							let chan_update = self.get_channel_update_for_onion(&chan).map(|u| u.encode_with_len()).unwrap(); // Cannot add/recv HTLCs before we have a short_id or alias so unwrap is safe
 							failed_payments.push((chan_update, failed_adds));
 						}
 						if chan.is_shutdown() {
-							if let Some(short_id) = chan.get_short_channel_id() {
-								short_to_id.remove(&short_id);
-							}
+							remove_short_ids!(short_to_id, chan);
+							self.issue_channel_close_events(chan, ClosureReason::DisconnectedPeer);
 							return false;
 						} else {
 							no_channels_remain = false;
@@ -3390,6 +5081,17 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 				match msg {
 					&events::MessageSendEvent::SendAcceptChannel { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendOpenChannel { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendAcceptChannelV2 { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendOpenChannelV2 { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAddInput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAddOutput { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxComplete { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxSignatures { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendTxAbort { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendStfu { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSplice { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSpliceAck { ref node_id, .. } => node_id != counterparty_node_id,
+					&events::MessageSendEvent::SendSpliceLocked { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingCreated { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingSigned { ref node_id, .. } => node_id != counterparty_node_id,
 					&events::MessageSendEvent::SendFundingLocked { ref node_id, .. } => node_id != counterparty_node_id,
@@ -3406,6 +5108,12 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 					&events::MessageSendEvent::PaymentFailureNetworkUpdate { .. } => true,
 					&events::MessageSendEvent::SendChannelRangeQuery { .. } => false,
 					&events::MessageSendEvent::SendShortIdsQuery { .. } => false,
+					&events::MessageSendEvent::SendReplyChannelRange { .. } => false,
+					&events::MessageSendEvent::SendChannelAnnouncement { .. } => false,
+					&events::MessageSendEvent::SendChannelUpdate { .. } => false,
+					&events::MessageSendEvent::SendNodeAnnouncement { .. } => false,
+					&events::MessageSendEvent::SendReplyShortChannelIdsEnd { .. } => false,
+					&events::MessageSendEvent::SendGossipTimestampFilter { .. } => false,
 				}
 			});
 		}
@@ -3465,23 +5173,30 @@ impl<ChanSigner: ChannelKeys, M: Deref + Sync + Send, T: Deref + Sync + Send, K:
 		//TODO: Also re-broadcast announcement_signatures
 	}
 
+	fn provided_init_features(&self) -> InitFeatures {
+		self.our_init_features()
+	}
+
 	fn handle_error(&self, counterparty_node_id: &PublicKey, msg: &msgs::ErrorMessage) {
 		let _consistency_lock = self.total_consistency_lock.read().unwrap();
 
+		let reason = ClosureReason::CounterpartyForceClosed { peer_msg: msg.data.clone() };
 		if msg.channel_id == [0; 32] {
 			for chan in self.list_channels() {
 				if chan.remote_network_id == *counterparty_node_id {
-					self.force_close_channel(&chan.channel_id);
+					self.force_close_channel_with_reason(&chan.channel_id, reason.clone());
 				}
 			}
 		} else {
-			self.force_close_channel(&msg.channel_id);
+			self.force_close_channel_with_reason(&msg.channel_id, reason);
 		}
 	}
 }
 
-const SERIALIZATION_VERSION: u8 = 1;
-const MIN_SERIALIZATION_VERSION: u8 = 1;
+// Version 2 appended pending_outbound_payments, which we leave empty when reading version 1 data.
+// The Channels it contains changed layout in version 2, so version 1 readers must refuse it.
+const SERIALIZATION_VERSION: u8 = 2;
+const MIN_SERIALIZATION_VERSION: u8 = 2;
 
 impl Writeable for PendingHTLCInfo {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
@@ -3491,10 +5206,19 @@ impl Writeable for PendingHTLCInfo {
 				onion_packet.write(writer)?;
 				short_channel_id.write(writer)?;
 			},
-			&PendingHTLCRouting::Receive { ref payment_data, ref incoming_cltv_expiry } => {
-				1u8.write(writer)?;
+			&PendingHTLCRouting::Receive { ref payment_data, ref keysend_preimage, ref custom_tlvs, ref incoming_cltv_expiry } => {
+				// Keep writing the original format when we can so that older versions can read us
+				if keysend_preimage.is_none() && custom_tlvs.is_empty() {
+					1u8.write(writer)?;
+				} else {
+					2u8.write(writer)?;
+				}
 				payment_data.write(writer)?;
 				incoming_cltv_expiry.write(writer)?;
+				if keysend_preimage.is_some() || !custom_tlvs.is_empty() {
+					keysend_preimage.write(writer)?;
+					custom_tlvs.write(writer)?;
+				}
 			},
 		}
 		self.incoming_shared_secret.write(writer)?;
@@ -3514,8 +5238,16 @@ impl Readable for PendingHTLCInfo {
 					short_channel_id: Readable::read(reader)?,
 				},
 				1u8 => PendingHTLCRouting::Receive {
+					payment_data: Readable::read(reader)?,
+					keysend_preimage: None,
+					custom_tlvs: Vec::new(),
+					incoming_cltv_expiry: Readable::read(reader)?,
+				},
+				2u8 => PendingHTLCRouting::Receive {
 					payment_data: Readable::read(reader)?,
 					incoming_cltv_expiry: Readable::read(reader)?,
+					keysend_preimage: Readable::read(reader)?,
+					custom_tlvs: Readable::read(reader)?,
 				},
 				_ => return Err(DecodeError::InvalidValue),
 			},
@@ -3579,6 +5311,14 @@ impl Readable for PendingHTLCStatus {
 	}
 }
 
+impl_writeable!(CounterpartyForwardingInfo, 0, {
+	fee_base_msat,
+	fee_proportional_millionths,
+	cltv_expiry_delta,
+	htlc_minimum_msat,
+	htlc_maximum_msat
+});
+
 impl_writeable!(HTLCPreviousHopData, 0, {
 	short_channel_id,
 	outpoint,
@@ -3600,11 +5340,13 @@ impl Writeable for HTLCSource {
 				0u8.write(writer)?;
 				hop_data.write(writer)?;
 			},
-			&HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat } => {
-				1u8.write(writer)?;
+			&HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat, ref payment_id } => {
+				// Type 1 is read for sources written before payment ids were added
+				2u8.write(writer)?;
 				path.write(writer)?;
 				session_priv.write(writer)?;
 				first_hop_htlc_msat.write(writer)?;
+				payment_id.write(writer)?;
 			}
 		}
 		Ok(())
@@ -3615,10 +5357,26 @@ impl Readable for HTLCSource {
 	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<HTLCSource, DecodeError> {
 		match <u8 as Readable>::read(reader)? {
 			0 => Ok(HTLCSource::PreviousHopData(Readable::read(reader)?)),
-			1 => Ok(HTLCSource::OutboundRoute {
+			1 => {
+				let path = Readable::read(reader)?;
+				let session_priv: SecretKey = Readable::read(reader)?;
+				let first_hop_htlc_msat = Readable::read(reader)?;
+				// Sources written before payment ids were added are tracked as a payment of their
+				// own, identified by their session key, which is unique to each path.
+				let mut payment_id = [0; 32];
+				payment_id.copy_from_slice(&session_priv[..]);
+				Ok(HTLCSource::OutboundRoute {
+					path,
+					session_priv,
+					first_hop_htlc_msat,
+					payment_id: PaymentId(payment_id),
+				})
+			},
+			2 => Ok(HTLCSource::OutboundRoute {
 				path: Readable::read(reader)?,
 				session_priv: Readable::read(reader)?,
 				first_hop_htlc_msat: Readable::read(reader)?,
+				payment_id: Readable::read(reader)?,
 			}),
 			_ => Err(DecodeError::InvalidValue),
 		}
@@ -3758,6 +5516,13 @@ impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref>
 
 		(self.last_node_announcement_serial.load(Ordering::Acquire) as u32).write(writer)?;
 
+		let pending_outbound_payments = self.pending_outbound_payments.lock().unwrap();
+		(pending_outbound_payments.len() as u64).write(writer)?;
+		for (payment_id, payment) in pending_outbound_payments.iter() {
+			payment_id.write(writer)?;
+			payment.write(writer)?;
+		}
+
 		Ok(())
 	}
 }
@@ -3870,7 +5635,7 @@ impl<'a, ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Der
         L::Target: Logger,
 {
 	fn read<R: ::std::io::Read>(reader: &mut R, mut args: ChannelManagerReadArgs<'a, ChanSigner, M, T, K, F, L>) -> Result<Self, DecodeError> {
-		let _ver: u8 = Readable::read(reader)?;
+		let ver: u8 = Readable::read(reader)?;
 		let min_ver: u8 = Readable::read(reader)?;
 		if min_ver > SERIALIZATION_VERSION {
 			return Err(DecodeError::UnknownVersion);
@@ -3881,6 +5646,7 @@ impl<'a, ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Der
 		let last_block_hash: BlockHash = Readable::read(reader)?;
 
 		let mut failed_htlcs = Vec::new();
+		let mut closed_channel_events = Vec::new();
 
 		let channel_count: u64 = Readable::read(reader)?;
 		let mut funding_txo_set = HashSet::with_capacity(cmp::min(channel_count as usize, 128));
@@ -3906,13 +5672,16 @@ impl<'a, ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Der
 						channel.get_cur_counterparty_commitment_transaction_number() > monitor.get_cur_counterparty_commitment_number() ||
 						channel.get_latest_monitor_update_id() < monitor.get_latest_update_id() {
 					// But if the channel is behind of the monitor, close the channel:
+					closed_channel_events.push(events::Event::ChannelClosed {
+						channel_id: channel.channel_id(),
+						user_id: channel.get_user_id(),
+						reason: ClosureReason::ProcessingError { err: "ChannelManager is stale compared to its ChannelMonitor".to_owned() },
+					});
 					let (_, _, mut new_failed_htlcs) = channel.force_shutdown(true);
 					failed_htlcs.append(&mut new_failed_htlcs);
 					monitor.broadcast_latest_holder_commitment_txn(&args.tx_broadcaster, &args.logger);
 				} else {
-					if let Some(short_channel_id) = channel.get_short_channel_id() {
-						short_to_id.insert(short_channel_id, channel.channel_id());
-					}
+					insert_short_ids!(short_to_id, channel);
 					by_id.insert(channel.channel_id(), channel);
 				}
 			} else {
@@ -3969,9 +5738,80 @@ impl<'a, ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Der
 				None => continue,
 			}
 		}
+		pending_events_read.append(&mut closed_channel_events);
 
 		let last_node_announcement_serial: u32 = Readable::read(reader)?;
 
+		let mut pending_outbound_payments = HashMap::new();
+		if ver >= 2 {
+			let pending_outbound_payments_count: u64 = Readable::read(reader)?;
+			pending_outbound_payments.reserve(cmp::min(pending_outbound_payments_count as usize, MAX_ALLOC_SIZE/64));
+			for _ in 0..pending_outbound_payments_count {
+				let payment_id: PaymentId = Readable::read(reader)?;
+				let payment: PendingOutboundPayment = Readable::read(reader)?;
+				pending_outbound_payments.insert(payment_id, payment);
+			}
+		}
+
+		// We may have been persisted before HTLCs of our outbound payments were added to or
+		// resolved in the ChannelMonitors, so make sure we track exactly the paths which are still
+		// pending in our channels or in the monitors of the channels we no longer have.
+		let mut pending_outbound_htlcs = Vec::new();
+		for channel in by_id.values() {
+			pending_outbound_htlcs.append(&mut channel.get_pending_outbound_htlc_sources());
+		}
+		for (funding_txo, monitor) in args.channel_monitors.iter() {
+			if !by_id.contains_key(&funding_txo.to_channel_id()) {
+				pending_outbound_htlcs.append(&mut monitor.get_pending_outbound_htlcs());
+			}
+		}
+		for &(ref source, ref payment_hash) in failed_htlcs.iter() {
+			pending_outbound_htlcs.push((source.clone(), *payment_hash));
+		}
+		let mut pending_session_privs = HashSet::with_capacity(pending_outbound_htlcs.len());
+		for (source, payment_hash) in pending_outbound_htlcs.drain(..) {
+			if let HTLCSource::OutboundRoute { path, session_priv, payment_id, .. } = source {
+				let path_amt_msat = path.last().map(|hop| hop.fee_msat).unwrap_or(0);
+				let session_priv_bytes = session_priv_bytes(&session_priv);
+				pending_session_privs.insert(session_priv_bytes);
+				match pending_outbound_payments.entry(payment_id) {
+					hash_map::Entry::Occupied(mut payment) => {
+						if payment.get_mut().pending_paths.insert(session_priv_bytes, path_amt_msat).is_none() {
+							log_info!(args.logger, "Tracking a path of payment with id {} which was sent after we were last persisted", log_bytes!(payment_id.0));
+						}
+					},
+					hash_map::Entry::Vacant(entry) => {
+						// We don't know the payment_secret or total value of the payment, so
+						// track it only until its paths are resolved, refusing any retry.
+						log_info!(args.logger, "Tracking payment with id {} which was sent after we were last persisted, it may not be retried", log_bytes!(payment_id.0));
+						let mut pending_paths = HashMap::new();
+						pending_paths.insert(session_priv_bytes, path_amt_msat);
+						entry.insert(PendingOutboundPayment {
+							payment_hash,
+							payment_secret: None,
+							keysend_preimage: None,
+							custom_tlvs: Vec::new(),
+							total_msat: path_amt_msat,
+							pending_paths,
+							fulfilled: false,
+							abandoned: true,
+							rebalance_preimage: None,
+							timer_ticks_without_paths: 0,
+						});
+					},
+				}
+			}
+		}
+		for (payment_id, payment) in pending_outbound_payments.iter_mut() {
+			let pending_paths_count = payment.pending_paths.len();
+			payment.pending_paths.retain(|session_priv, _| pending_session_privs.contains(session_priv));
+			if payment.pending_paths.len() != pending_paths_count {
+				log_info!(args.logger, "Forgetting {} path(s) of payment with id {} which were resolved after we were last persisted",
+					pending_paths_count - payment.pending_paths.len(), log_bytes!(payment_id.0));
+			}
+		}
+		pending_outbound_payments.retain(|_, payment| !payment.is_complete());
+
 		let channel_manager = ChannelManager {
 			genesis_hash,
 			fee_estimator: args.fee_estimator,
@@ -3995,6 +5835,10 @@ impl<'a, ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Der
 
 			per_peer_state: RwLock::new(per_peer_state),
 
+			trusted_zero_conf_peers: Mutex::new(HashSet::new()),
+
+			pending_outbound_payments: Mutex::new(pending_outbound_payments),
+
 			pending_events: Mutex::new(pending_events_read),
 			total_consistency_lock: RwLock::new(()),
 			keys_manager: args.keys_manager,
@@ -101,6 +101,16 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
@@ -108,7 +118,17 @@ mod sealed {
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | Wumbo | AnchorsZeroFeeHtlcTx,
+			// Byte 3
+			DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(NodeContext {
@@ -119,6 +139,16 @@ mod sealed {
 			StaticRemoteKey,
 			// Byte 2
 			,
+			// Byte 3
+			,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			,
 		],
 		optional_features: [
 			// Byte 0
@@ -126,7 +156,17 @@ mod sealed {
 			// Byte 1
 			VariableLengthOnion | PaymentSecret,
 			// Byte 2
-			BasicMPP,
+			BasicMPP | Wumbo | AnchorsZeroFeeHtlcTx,
+			// Byte 3
+			DualFund,
+			// Byte 4
+			,
+			// Byte 5
+			,
+			// Byte 6
+			,
+			// Byte 7
+			Splicing,
 		],
 	});
 	define_context!(ChannelContext {
@@ -253,6 +293,14 @@ mod sealed {
 		"Feature flags for `payment_secret`.");
 	define_feature!(17, BasicMPP, [InitContext, NodeContext],
 		"Feature flags for `basic_mpp`.");
+	define_feature!(19, Wumbo, [InitContext, NodeContext],
+		"Feature flags for `option_support_large_channel` (aka wumbo channels).");
+	define_feature!(23, AnchorsZeroFeeHtlcTx, [InitContext, NodeContext],
+		"Feature flags for `option_anchors_zero_fee_htlc_tx`.");
+	define_feature!(29, DualFund, [InitContext, NodeContext],
+		"Feature flags for `option_dual_fund`.");
+	define_feature!(63, Splicing, [InitContext, NodeContext],
+		"Feature flags for `option_splice`.");
 
 	#[cfg(test)]
 	define_context!(TestingContext {
@@ -262,6 +310,8 @@ mod sealed {
 			// Byte 1
 			,
 			// Byte 2
+			,
+			// Byte 3
 			UnknownFeature,
 		],
 		optional_features: [
@@ -271,11 +321,13 @@ mod sealed {
 			,
 			// Byte 2
 			,
+			// Byte 3
+			,
 		],
 	});
 
 	#[cfg(test)]
-	define_feature!(23, UnknownFeature, [TestingContext],
+	define_feature!(25, UnknownFeature, [TestingContext],
 		"Feature flags for an unknown feature used in testing.");
 }
 
@@ -550,6 +602,63 @@ impl<T: sealed::BasicMPP> Features<T> {
 	}
 }
 
+impl<T: sealed::Wumbo> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_wumbo(&self) -> bool {
+		<T as sealed::Wumbo>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_wumbo(&self) -> bool {
+		<T as sealed::Wumbo>::supports_feature(&self.flags)
+	}
+}
+
+impl<T: sealed::AnchorsZeroFeeHtlcTx> Features<T> {
+	pub(crate) fn requires_anchors_zero_fee_htlc_tx(&self) -> bool {
+		<T as sealed::AnchorsZeroFeeHtlcTx>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_anchors_zero_fee_htlc_tx(&self) -> bool {
+		<T as sealed::AnchorsZeroFeeHtlcTx>::supports_feature(&self.flags)
+	}
+	// Anchor outputs are only advertised if the user opted into them, see
+	// ChannelHandshakeConfig::negotiate_anchor_outputs.
+	pub(crate) fn clear_anchors_zero_fee_htlc_tx(mut self) -> Self {
+		<T as sealed::AnchorsZeroFeeHtlcTx>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::DualFund> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_dual_fund(&self) -> bool {
+		<T as sealed::DualFund>::supports_feature(&self.flags)
+	}
+	// Dual funding is only advertised if the user opted into it, see
+	// ChannelHandshakeConfig::negotiate_dual_fund.
+	pub(crate) fn clear_dual_fund(mut self) -> Self {
+		<T as sealed::DualFund>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
+impl<T: sealed::Splicing> Features<T> {
+	#[cfg(test)]
+	pub(crate) fn requires_splicing(&self) -> bool {
+		<T as sealed::Splicing>::requires_feature(&self.flags)
+	}
+	pub(crate) fn supports_splicing(&self) -> bool {
+		<T as sealed::Splicing>::supports_feature(&self.flags)
+	}
+	// Splicing is only advertised if the user opted into it, see
+	// ChannelHandshakeConfig::negotiate_splicing.
+	pub(crate) fn clear_splicing(mut self) -> Self {
+		<T as sealed::Splicing>::clear_bits(&mut self.flags);
+		self
+	}
+}
+
 impl<T: sealed::Context> Writeable for Features<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.size_hint(self.flags.len() + 2);
@@ -620,6 +729,29 @@ mod tests {
 		assert!(!InitFeatures::known().requires_basic_mpp());
 		assert!(!NodeFeatures::known().requires_basic_mpp());
 
+		assert!(InitFeatures::known().supports_wumbo());
+		assert!(NodeFeatures::known().supports_wumbo());
+		assert!(!InitFeatures::known().requires_wumbo());
+		assert!(!NodeFeatures::known().requires_wumbo());
+
+		assert!(InitFeatures::known().supports_anchors_zero_fee_htlc_tx());
+		assert!(NodeFeatures::known().supports_anchors_zero_fee_htlc_tx());
+		assert!(!InitFeatures::known().requires_anchors_zero_fee_htlc_tx());
+		assert!(!NodeFeatures::known().requires_anchors_zero_fee_htlc_tx());
+		assert!(!InitFeatures::known().clear_anchors_zero_fee_htlc_tx().supports_anchors_zero_fee_htlc_tx());
+
+		assert!(InitFeatures::known().supports_dual_fund());
+		assert!(NodeFeatures::known().supports_dual_fund());
+		assert!(!InitFeatures::known().requires_dual_fund());
+		assert!(!NodeFeatures::known().requires_dual_fund());
+		assert!(!InitFeatures::known().clear_dual_fund().supports_dual_fund());
+
+		assert!(InitFeatures::known().supports_splicing());
+		assert!(NodeFeatures::known().supports_splicing());
+		assert!(!InitFeatures::known().requires_splicing());
+		assert!(!NodeFeatures::known().requires_splicing());
+		assert!(!InitFeatures::known().clear_splicing().supports_splicing());
+
 		let mut init_features = InitFeatures::known();
 		assert!(init_features.initial_routing_sync());
 		init_features.clear_initial_routing_sync();
@@ -657,11 +789,18 @@ mod tests {
 			// Check that the flags are as expected:
 			// - option_data_loss_protect
 			// - var_onion_optin | static_remote_key (req) | payment_secret
-			// - basic_mpp
-			assert_eq!(node_features.flags.len(), 3);
+			// - basic_mpp | option_support_large_channel | option_anchors_zero_fee_htlc_tx
+			// - option_dual_fund
+			// - option_splice
+			assert_eq!(node_features.flags.len(), 8);
 			assert_eq!(node_features.flags[0], 0b00000010);
 			assert_eq!(node_features.flags[1], 0b10010010);
-			assert_eq!(node_features.flags[2], 0b00000010);
+			assert_eq!(node_features.flags[2], 0b10001010);
+			assert_eq!(node_features.flags[3], 0b00100000);
+			assert_eq!(node_features.flags[4], 0);
+			assert_eq!(node_features.flags[5], 0);
+			assert_eq!(node_features.flags[6], 0);
+			assert_eq!(node_features.flags[7], 0b10000000);
 		}
 
 		// Check that cleared flags are kept blank when converting back:
@@ -16,20 +16,23 @@ use chain::channelmonitor;
 use chain::channelmonitor::{ChannelMonitor, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY};
 use chain::transaction::OutPoint;
 use chain::keysinterface::{ChannelKeys, KeysInterface, SpendableOutputDescriptor};
-use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC};
-use ln::channelmanager::{ChannelManager, ChannelManagerReadArgs, RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentSendFailure, BREAKDOWN_TIMEOUT};
+use ln::channel::{COMMITMENT_TX_BASE_WEIGHT, COMMITMENT_TX_WEIGHT_PER_HTLC, EXPIRE_PREV_CONFIG_TICKS, FUNDING_CONF_DEADLINE_BLOCKS, UNFUNDED_CHANNEL_AGE_LIMIT_TICKS};
+use ln::channelmanager::{ChannelManager, ChannelManagerReadArgs, RAACommitmentOrder, PaymentPreimage, PaymentHash, PaymentSecret, PaymentId, PaymentSendFailure, BREAKDOWN_TIMEOUT, FAILED_PAYMENT_EXPIRY_TICKS, CounterpartyForwardingInfo, HTLCDirection, PendingHTLCState, PendingHTLCDetails};
 use ln::channel::{Channel, ChannelError};
+use ln::payment_retry::{PaymentRetrier, RouteParameters};
 use ln::{chan_utils, onion_utils};
-use routing::router::{Route, RouteHop, get_route};
+use routing::router::{Route, RouteConstraints, RouteHop, RouteHint, get_route, get_rebalance_route};
+use routing::network_graph::RoutingFees;
+use routing::scorer::Scorer;
 use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 use ln::msgs;
-use ln::msgs::{ChannelMessageHandler,RoutingMessageHandler,HTLCFailChannelUpdate, ErrorAction};
+use ln::msgs::{ChannelMessageHandler,RoutingMessageHandler,HTLCFailChannelUpdate, ErrorAction, OptionalField};
 use util::enforcing_trait_impls::EnforcingChannelKeys;
 use util::{byte_utils, test_utils};
-use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider};
+use util::events::{Event, EventsProvider, MessageSendEvent, MessageSendEventsProvider, ClosureReason};
 use util::errors::APIError;
 use util::ser::{Writeable, ReadableArgs};
-use util::config::UserConfig;
+use util::config::{UserConfig, ChannelConfig};
 
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hash_types::{Txid, BlockHash};
@@ -37,7 +40,7 @@ use bitcoin::util::bip143;
 use bitcoin::util::address::Address;
 use bitcoin::util::bip32::{ChildNumber, ExtendedPubKey, ExtendedPrivKey};
 use bitcoin::blockdata::block::{Block, BlockHeader};
-use bitcoin::blockdata::transaction::{Transaction, TxOut, TxIn, SigHashType};
+use bitcoin::blockdata::transaction::{Transaction, TxOut, TxIn, SigHashType, OutPoint as BitcoinOutPoint};
 use bitcoin::blockdata::script::{Builder, Script};
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::constants::genesis_block;
@@ -50,10 +53,12 @@ use bitcoin::secp256k1::{Secp256k1, Message};
 use bitcoin::secp256k1::key::{PublicKey,SecretKey};
 
 use regex;
+use hex;
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::default::Default;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::sync::atomic::Ordering;
 use std::mem;
 
@@ -97,11 +102,12 @@ fn test_insane_channel_opens() {
 		} else { assert!(false); }
 	};
 
-	use ln::channel::MAX_FUNDING_SATOSHIS;
+	use ln::channel::{MAX_FUNDING_SATOSHIS_NO_WUMBO, TOTAL_BITCOIN_SUPPLY_SATOSHIS};
 	use ln::channelmanager::MAX_LOCAL_BREAKDOWN_TIMEOUT;
 
 	// Test all mutations that would make the channel open message insane
-	insane_open_helper(format!("Funding must be smaller than {}. It was {}", MAX_FUNDING_SATOSHIS, MAX_FUNDING_SATOSHIS).as_str(), |mut msg| { msg.funding_satoshis = MAX_FUNDING_SATOSHIS; msg });
+	insane_open_helper(format!("Per our config, funding must not exceed {}. It was {}", MAX_FUNDING_SATOSHIS_NO_WUMBO, MAX_FUNDING_SATOSHIS_NO_WUMBO + 1).as_str(), |mut msg| { msg.funding_satoshis = MAX_FUNDING_SATOSHIS_NO_WUMBO + 1; msg });
+	insane_open_helper(format!("Funding must not exceed the total bitcoin supply. It was {}", TOTAL_BITCOIN_SUPPLY_SATOSHIS + 1).as_str(), |mut msg| { msg.funding_satoshis = TOTAL_BITCOIN_SUPPLY_SATOSHIS + 1; msg });
 
 	insane_open_helper("Bogus channel_reserve_satoshis", |mut msg| { msg.channel_reserve_satoshis = msg.funding_satoshis + 1; msg });
 
@@ -128,6 +134,7 @@ fn test_async_inbound_update_fee() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 	let channel_id = chan.2;
 
 	// balancing
@@ -168,7 +175,7 @@ fn test_async_inbound_update_fee() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &scorer).unwrap(), our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -244,6 +251,7 @@ fn test_update_fee_unordered_raa() {
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let channel_id = chan.2;
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -266,7 +274,7 @@ fn test_update_fee_unordered_raa() {
 	// ...but before it's delivered, nodes[1] starts to send a payment back to nodes[0]...
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger).unwrap(), our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 40000, TEST_FINAL_CLTV, &logger, &scorer).unwrap(), our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[1], 1);
 
 	let payment_event = {
@@ -619,6 +627,7 @@ fn test_update_fee_that_funder_cannot_afford() {
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Funding remote cannot afford proposed new fee".to_string(), 1);
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], true);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Funding remote cannot afford proposed new fee".to_string() });
 }
 
 #[test]
@@ -630,6 +639,7 @@ fn test_update_fee_with_fundee_update_add_htlc() {
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let channel_id = chan.2;
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -653,10 +663,10 @@ fn test_update_fee_with_fundee_update_add_htlc() {
 
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[1]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	// nothing happens since node[1] is in AwaitingRemoteRevoke
-	nodes[1].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	nodes[1].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	{
 		let mut added_monitors = nodes[0].chain_monitor.added_monitors.lock().unwrap();
 		assert_eq!(added_monitors.len(), 0);
@@ -846,6 +856,8 @@ fn pre_funding_lock_shutdown_test() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	assert!(nodes[1].node.list_channels().is_empty());
@@ -861,6 +873,7 @@ fn updates_shutdown_wait() {
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (our_payment_preimage, _) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 100000);
 
@@ -877,10 +890,10 @@ fn updates_shutdown_wait() {
 
 	let net_graph_msg_handler0 = &nodes[0].net_graph_msg_handler;
 	let net_graph_msg_handler1 = &nodes[1].net_graph_msg_handler;
-	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	unwrap_send_err!(nodes[0].node.send_payment(&route_1, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
-	unwrap_send_err!(nodes[1].node.send_payment(&route_2, payment_hash, &None), true, APIError::ChannelUnavailable {..}, {});
+	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler0.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let route_2 = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler1.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	unwrap_send_err!(nodes[0].node.send_payment(&route_1, payment_hash, &None, &[]), true, APIError::ChannelUnavailable {..}, {});
+	unwrap_send_err!(nodes[1].node.send_payment(&route_2, payment_hash, &None, &[]), true, APIError::ChannelUnavailable {..}, {});
 
 	assert!(nodes[2].node.claim_funds(our_payment_preimage, &None, 100_000));
 	check_added_monitors!(nodes[2], 1);
@@ -906,7 +919,7 @@ fn updates_shutdown_wait() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(our_payment_preimage, *payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -918,6 +931,8 @@ fn updates_shutdown_wait() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -938,11 +953,12 @@ fn htlc_fail_async_shutdown() {
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	assert_eq!(updates.update_add_htlcs.len(), 1);
@@ -996,6 +1012,8 @@ fn htlc_fail_async_shutdown() {
 	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 
 	assert!(nodes[0].node.list_channels().is_empty());
 
@@ -1079,7 +1097,7 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(our_payment_preimage, *payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -1122,6 +1140,8 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 		nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
 		let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
 		assert!(node_0_none.is_none());
+		check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+		check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 	} else {
 		// If one node, however, received + responded with an identical closing_signed we end
 		// up erroring and node[0] will try to broadcast its own latest commitment transaction.
@@ -1150,6 +1170,8 @@ fn do_test_shutdown_rebroadcast(recv_count: u8) {
 		// closing_signed so we do it ourselves
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Failed to find corresponding channel".to_string() });
+		check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 	}
 
 	assert!(nodes[0].node.list_channels().is_empty());
@@ -1311,13 +1333,14 @@ fn holding_cell_htlc_counting() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let mut payments = Vec::new();
 	for _ in 0..::ln::channel::OUR_MAX_HTLCS {
 		let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[1].node.send_payment(&route, payment_hash, &None).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[1].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
 		payments.push((payment_preimage, payment_hash));
 	}
 	check_added_monitors!(nodes[1], 1);
@@ -1333,8 +1356,8 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_1) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		unwrap_send_err!(nodes[1].node.send_payment(&route, payment_hash_1, &None), true, APIError::ChannelUnavailable { ref err },
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		unwrap_send_err!(nodes[1].node.send_payment(&route, payment_hash_1, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot push more than their max accepted HTLCs \(\d+\)").unwrap().is_match(err)));
 		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
 		nodes[1].logger.assert_log_contains("lightning::ln::channelmanager".to_string(), "Cannot push more than their max accepted HTLCs".to_string(), 1);
@@ -1344,8 +1367,8 @@ fn holding_cell_htlc_counting() {
 	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, payment_hash_2, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 	}
 
@@ -1470,6 +1493,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// balancing
 	send_payment(&nodes[0], &vec!(&nodes[1])[..], 8000000, 8_000_000);
@@ -1477,7 +1501,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let (payment_preimage, payment_hash) = route_payment(&nodes[0], &vec!(&nodes[1])[..], 900_000);
 
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 800_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &vec!(&nodes[0])[..], 800_000, payment_hash);
 
 	// Provide preimage to node 0 by claiming payment
@@ -1501,6 +1525,7 @@ fn test_duplicate_htlc_different_direction_onchain() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header, txdata: vec![remote_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 
 	// Check we only broadcast 1 timeout tx
 	let claim_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
@@ -1540,6 +1565,7 @@ fn test_basic_channel_reserve() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	let channel_reserve = chan_stat.channel_reserve_msat;
@@ -1549,8 +1575,8 @@ fn test_basic_channel_reserve() {
 	let commit_tx_fee = 2 * commit_tx_fee_msat(get_feerate!(nodes[0], chan.2), 1 + 1);
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee;
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger).unwrap();
-	let err = nodes[0].node.send_payment(&route, our_payment_hash, &None).err().unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), max_can_send + 1, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let err = nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).err().unwrap();
 	match err {
 		PaymentSendFailure::AllFailedRetrySafe(ref fails) => {
 			match &fails[0] {
@@ -1575,12 +1601,13 @@ fn test_fee_spike_violation_fails_htlc() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler.network_graph.read().unwrap();
-			let route = get_route(&nodes[0].node.get_our_node_id(), net_graph_msg_handler, &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), net_graph_msg_handler, &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1593,7 +1620,7 @@ fn test_fee_spike_violation_fails_htlc() {
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3460001, &None, &None, &[], cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1707,25 +1734,26 @@ fn test_chan_reserve_violation_outbound_htlc_inbound_chan() {
 	// Set the fee rate for the channel very high, to the point where the fundee
 	// sending any amount would result in a channel reserve violation. In this test
 	// we check that we would be prevented from sending an HTLC in this situation.
-	chanmon_cfgs[0].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 6000 };
-	chanmon_cfgs[1].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 6000 };
+	chanmon_cfgs[0].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(6000) };
+	chanmon_cfgs[1].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(6000) };
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let _ = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
 
 	let (route, our_payment_hash, _) = get_route_and_payment_hash!(1000);
-	unwrap_send_err!(nodes[1].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+	unwrap_send_err!(nodes[1].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 		assert_eq!(err, "Cannot send value that would put counterparty balance under holder-announced channel reserve value"));
 	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Cannot send value that would put counterparty balance under holder-announced channel reserve value".to_string(), 1);
@@ -1739,19 +1767,20 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	// to channel reserve violation. This close could also happen if the fee went
 	// up a more realistic amount, but many HTLCs were outstanding at the time of
 	// the update_add_htlc.
-	chanmon_cfgs[0].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 6000 };
-	chanmon_cfgs[1].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 6000 };
+	chanmon_cfgs[0].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(6000) };
+	chanmon_cfgs[1].fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(6000) };
 	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[1]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.first().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1762,7 +1791,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 1000, &None, &None, &[], cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1778,6 +1807,7 @@ fn test_chan_reserve_violation_inbound_htlc_outbound_channel() {
 	nodes[0].logger.assert_log("lightning::ln::channelmanager".to_string(), "Cannot accept HTLC that would put our balance under counterparty-announced channel reserve value".to_string(), 1);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Cannot accept HTLC that would put our balance under counterparty-announced channel reserve value".to_string() });
 	assert_eq!(err_msg.data, "Cannot accept HTLC that would put our balance under counterparty-announced channel reserve value");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -1791,12 +1821,13 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let _ = create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	macro_rules! get_route_and_payment_hash {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1814,7 +1845,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	// Add a pending HTLC.
 	let (route_1, our_payment_hash_1, _) = get_route_and_payment_hash!(amt_msat_1);
 	let payment_event_1 = {
-		nodes[0].node.send_payment(&route_1, our_payment_hash_1, &None).unwrap();
+		nodes[0].node.send_payment(&route_1, our_payment_hash_1, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -1834,7 +1865,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route_2.paths[0], &session_priv).unwrap();
-	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, cur_height).unwrap();
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route_2.paths[0], recv_value_2, &None, &None, &[], cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash_1);
 	let msg = msgs::UpdateAddHTLC {
 		channel_id: chan.2,
@@ -1850,6 +1881,7 @@ fn test_chan_reserve_violation_inbound_htlc_inbound_chan() {
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote HTLC add would put them under remote reserve value".to_string(), 1);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them under remote reserve value".to_string() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -1886,6 +1918,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let chan_1 = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 190000, 1001, InitFeatures::known(), InitFeatures::known());
 	let chan_2 = create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 190000, 1001, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let mut stat01 = get_channel_value_stat!(nodes[0], chan_1.2);
 	let mut stat11 = get_channel_value_stat!(nodes[1], chan_1.2);
@@ -1897,7 +1930,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		($recv_value: expr) => {{
 			let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes.last().unwrap().node.get_our_node_id(), None, &Vec::new(), $recv_value, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 			(route, payment_hash, payment_preimage)
 		}}
 	};
@@ -1920,9 +1953,12 @@ fn test_channel_reserve_holding_cell_htlcs() {
 
 	// attempt to send amt_msat > their_max_htlc_value_in_flight_msat
 	{
-		let (route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_0 + 1);
+		// The router won't give us a route which exceeds the channel's htlc_maximum_msat, so bump
+		// the value of a valid route instead.
+		let (mut route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_0);
+		route.paths[0].last_mut().unwrap().fee_msat += 1;
 		assert!(route.paths[0].iter().rev().skip(1).all(|h| h.fee_msat == feemsat));
-		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot send value that would put us over the max HTLC value in flight our peer will accept \(\d+\)").unwrap().is_match(err)));
 		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 		nodes[0].logger.assert_log_contains("lightning::ln::channelmanager".to_string(), "Cannot send value that would put us over the max HTLC value in flight our peer will accept".to_string(), 1);
@@ -1973,7 +2009,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 
 	let (route_1, our_payment_hash_1, our_payment_preimage_1) = get_route_and_payment_hash!(recv_value_1);
 	let payment_event_1 = {
-		nodes[0].node.send_payment(&route_1, our_payment_hash_1, &None).unwrap();
+		nodes[0].node.send_payment(&route_1, our_payment_hash_1, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -1986,7 +2022,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let recv_value_2 = stat01.value_to_self_msat - amt_msat_1 - stat01.channel_reserve_msat - total_fee_msat - commit_tx_fee_2_htlcs;
 	{
 		let (route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_2 + 1);
-		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot send value that would put our balance under counterparty-announced channel reserve value \(\d+\)").unwrap().is_match(err)));
 		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 	}
@@ -2004,7 +2040,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	// now see if they go through on both sides
 	let (route_21, our_payment_hash_21, our_payment_preimage_21) = get_route_and_payment_hash!(recv_value_21);
 	// but this will stuck in the holding cell
-	nodes[0].node.send_payment(&route_21, our_payment_hash_21, &None).unwrap();
+	nodes[0].node.send_payment(&route_21, our_payment_hash_21, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 0);
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 0);
@@ -2012,7 +2048,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	// test with outbound holding cell amount > 0
 	{
 		let (route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_22+1);
-		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+		unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 			assert!(regex::Regex::new(r"Cannot send value that would put our balance under counterparty-announced channel reserve value \(\d+\)").unwrap().is_match(err)));
 		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 		nodes[0].logger.assert_log_contains("lightning::ln::channelmanager".to_string(), "Cannot send value that would put our balance under counterparty-announced channel reserve value".to_string(), 2);
@@ -2020,7 +2056,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 
 	let (route_22, our_payment_hash_22, our_payment_preimage_22) = get_route_and_payment_hash!(recv_value_22);
 	// this will also stuck in the holding cell
-	nodes[0].node.send_payment(&route_22, our_payment_hash_22, &None).unwrap();
+	nodes[0].node.send_payment(&route_22, our_payment_hash_22, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 0);
 	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
 	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
@@ -2071,7 +2107,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let events = nodes[2].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 2);
 	match events[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(our_payment_hash_21, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(recv_value_21, amt);
@@ -2079,7 +2115,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 		_ => panic!("Unexpected event"),
 	}
 	match events[1] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(our_payment_hash_22, *payment_hash);
 			assert_eq!(None, *payment_secret);
 			assert_eq!(recv_value_22, amt);
@@ -2095,7 +2131,7 @@ fn test_channel_reserve_holding_cell_htlcs() {
 	let recv_value_3 = commit_tx_fee_2_htlcs - commit_tx_fee_0_htlcs - total_fee_msat;
 	{
 		let (route, our_payment_hash, _) = get_route_and_payment_hash!(recv_value_3 + 1);
-		let err = nodes[0].node.send_payment(&route, our_payment_hash, &None).err().unwrap();
+		let err = nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).err().unwrap();
 		match err {
 			PaymentSendFailure::AllFailedRetrySafe(ref fails) => {
 				match &fails[0] {
@@ -2152,6 +2188,7 @@ fn channel_reserve_in_flight_removes() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let b_chan_values = get_channel_value_stat!(nodes[1], chan_1.2);
 	// Route the first two HTLCs.
@@ -2162,8 +2199,8 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_3, payment_hash_3) = get_payment_preimage_hash!(nodes[0]);
 	let send_1 = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, payment_hash_3, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, payment_hash_3, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -2235,8 +2272,8 @@ fn channel_reserve_in_flight_removes() {
 	let (payment_preimage_4, payment_hash_4) = get_payment_preimage_hash!(nodes[1]);
 	let send_2 = {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 10000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[1].node.send_payment(&route, payment_hash_4, &None).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &[], 10000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[1].node.send_payment(&route, payment_hash_4, &None, &[]).unwrap();
 		check_added_monitors!(nodes[1], 1);
 		let mut events = nodes[1].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -2309,6 +2346,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 0, 1);
 	assert_eq!(nodes[0].node.list_channels().len(), 0);
 	assert_eq!(nodes[1].node.list_channels().len(), 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
 
 	// One pending HTLC is discarded by the force-close:
 	let payment_preimage_1 = route_payment(&nodes[1], &vec!(&nodes[2], &nodes[3])[..], 3000000).0;
@@ -2326,6 +2365,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 1, 2);
 	assert_eq!(nodes[1].node.list_channels().len(), 0);
 	assert_eq!(nodes[2].node.list_channels().len(), 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 
 	macro_rules! claim_funds {
 		($node: expr, $prev_node: expr, $preimage: expr, $amount: expr) => {
@@ -2368,6 +2409,8 @@ fn channel_monitor_network_test() {
 	get_announce_close_broadcast_events(&nodes, 2, 3);
 	assert_eq!(nodes[2].node.list_channels().len(), 0);
 	assert_eq!(nodes[3].node.list_channels().len(), 1);
+	check_closed_event!(nodes[2], 1, ClosureReason::DisconnectedPeer);
+	check_closed_event!(nodes[3], 1, ClosureReason::CommitmentTxConfirmed);
 
 	{ // Cheat and reset nodes[4]'s height to 1
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
@@ -2456,6 +2499,8 @@ fn channel_monitor_network_test() {
 	nodes[4].net_graph_msg_handler.handle_channel_update(&close_chan_update_1).unwrap();
 	assert_eq!(nodes[3].node.list_channels().len(), 0);
 	assert_eq!(nodes[4].node.list_channels().len(), 0);
+	check_closed_event!(nodes[3], 1, ClosureReason::HolderForceClosed);
+	check_closed_event!(nodes[4], 1, ClosureReason::HolderForceClosed);
 }
 
 #[test]
@@ -2504,6 +2549,7 @@ fn test_justice_tx() {
 			node_txn.truncate(1);
 		}
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[1], &chan_5, None, HTLCType::NONE);
 
 		connect_block(&nodes[0], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
@@ -2511,6 +2557,7 @@ fn test_justice_tx() {
 		let node_txn = test_txn_broadcast(&nodes[0], &chan_5, Some(revoked_local_txn[0].clone()), HTLCType::TIMEOUT);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		// Broadcast revoked HTLC-timeout on node 1
 		connect_block(&nodes[1], &Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[1], node_txn[1].clone(), revoked_local_txn[0].clone());
@@ -2550,12 +2597,14 @@ fn test_justice_tx() {
 			node_txn.swap_remove(0);
 		}
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		test_txn_broadcast(&nodes[0], &chan_6, None, HTLCType::NONE);
 
 		connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		let node_txn = test_txn_broadcast(&nodes[1], &chan_6, Some(revoked_local_txn[0].clone()), HTLCType::SUCCESS);
 		header = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		connect_block(&nodes[0], &Block { header, txdata: vec![node_txn[1].clone()] }, 1);
 		test_revoked_htlc_claim_txn_broadcast(&nodes[0], node_txn[1].clone(), revoked_local_txn[0].clone());
 	}
@@ -2594,7 +2643,9 @@ fn revoked_output_claim() {
 	// Inform nodes[0] that a watchtower cheated on its behalf, so it will force-close the chan
 	connect_block(&nodes[0], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	get_announce_close_broadcast_events(&nodes, 0, 1);
-	check_added_monitors!(nodes[0], 1)
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 }
 
 #[test]
@@ -2631,8 +2682,10 @@ fn claim_htlc_outputs_shared_tx() {
 		let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 		connect_block(&nodes[0], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 		check_added_monitors!(nodes[1], 1);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 		connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
 
@@ -2696,7 +2749,17 @@ fn claim_htlc_outputs_single_tx() {
 		check_added_monitors!(nodes[0], 1);
 		connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 200);
 		check_added_monitors!(nodes[1], 1);
-		expect_pending_htlcs_forwardable_ignore!(nodes[0]);
+		check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+		match events[1] {
+			Event::PendingHTLCsForwardable { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
 
 		connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 200, true, header.block_hash());
 		expect_payment_failed!(nodes[1], payment_hash_2, true);
@@ -2788,6 +2851,7 @@ fn test_htlc_on_chain_success() {
 
 	connect_block(&nodes[2], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[2], 1);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx, 2*htlc-success tx), ChannelMonitor : 2 (2 * HTLC-Success tx)
 	assert_eq!(node_txn.len(), 5);
@@ -2879,6 +2943,7 @@ fn test_htlc_on_chain_success() {
 	check_spends!(commitment_tx[0], chan_1.3);
 	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 2, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 3 (commitment tx + HTLC-Sucess * 2), ChannelMonitor : 1 (HTLC-Success)
 	assert_eq!(node_txn.len(), 4);
@@ -2900,11 +2965,12 @@ fn test_htlc_on_chain_success() {
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
 	let events = nodes[0].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), 2);
+	assert_eq!(events.len(), 3);
 	let mut first_claimed = false;
 	for event in events {
 		match event {
-			Event::PaymentSent { payment_preimage } => {
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+			Event::PaymentSent { payment_preimage, .. } => {
 				if payment_preimage == our_payment_preimage {
 					assert!(!first_claimed);
 					first_claimed = true;
@@ -2966,6 +3032,7 @@ fn test_htlc_on_chain_timeout() {
 	};
 	connect_block(&nodes[2], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[2], 1);
 	let node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 1 (commitment tx)
 	assert_eq!(node_txn.len(), 1);
@@ -2975,6 +3042,7 @@ fn test_htlc_on_chain_timeout() {
 	// Broadcast timeout transaction by B on received output from C's commitment tx on B's chain
 	// Verify that B's ChannelManager is able to detect that HTLC is timeout by its own tx and react backward in consequence
 	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 200);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let timeout_tx;
 	{
 		let mut node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -3023,6 +3091,7 @@ fn test_htlc_on_chain_timeout() {
 	connect_block(&nodes[0], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 200);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Timeout tx), ChannelMonitor : 1 timeout tx
 	assert_eq!(node_txn.len(), 3);
 	check_spends!(node_txn[0], commitment_tx[0]);
@@ -3057,6 +3126,7 @@ fn test_simple_commitment_revoked_fail_backward() {
 
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 	check_added_monitors!(nodes[1], 1);
 	check_closed_broadcast!(nodes[1], false);
@@ -3184,8 +3254,9 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	let (_, fourth_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[1].node.send_payment(&route, fourth_payment_hash, &None).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[1].node.send_payment(&route, fourth_payment_hash, &None, &[]).unwrap();
 	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
 	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
 	check_added_monitors!(nodes[1], 0);
@@ -3214,15 +3285,19 @@ fn do_test_commitment_revoked_fail_backward_exhaustive(deliver_bs_raa: bool, use
 	connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 
 	let events = nodes[1].node.get_and_clear_pending_events();
-	assert_eq!(events.len(), if deliver_bs_raa { 1 } else { 2 });
+	assert_eq!(events.len(), if deliver_bs_raa { 2 } else { 3 });
 	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => { },
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
 		Event::PaymentFailed { ref payment_hash, .. } => {
 			assert_eq!(*payment_hash, fourth_payment_hash);
 		},
 		_ => panic!("Unexpected event"),
 	}
 	if !deliver_bs_raa {
-		match events[1] {
+		match events[2] {
 			Event::PendingHTLCsForwardable { .. } => { },
 			_ => panic!("Unexpected event"),
 		};
@@ -3325,13 +3400,14 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 500_000_000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// Alice -> Bob: Route a payment but without Bob sending revoke_and_ack.
 	{
 		let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let payment_event = {
@@ -3347,8 +3423,8 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	let (_, failed_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, failed_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, failed_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 0);
 
 		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
@@ -3362,8 +3438,8 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 		let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 		let current_height = nodes[1].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger).unwrap();
-		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, current_height).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 50_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		let (onion_payloads, _amount_msat, cltv_expiry) = onion_utils::build_onion_payloads(&route.paths[0], 50_000, &None, &None, &[], current_height).unwrap();
 		let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &route.paths[0], &session_priv).unwrap();
 		let onion_routing_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &payment_hash);
 
@@ -3380,7 +3456,21 @@ fn fail_backward_pending_htlc_upon_channel_failure() {
 	}
 
 	// Check that Alice fails backward the pending HTLC from the second payment.
-	expect_payment_failed!(nodes[0], failed_payment_hash, true);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { ref reason, .. } => {
+			assert_eq!(*reason, ClosureReason::ProcessingError { err: "Remote side tried to send a 0-msat HTLC".to_string() });
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentFailed { ref payment_hash, rejected_by_dest, .. } => {
+			assert_eq!(*payment_hash, failed_payment_hash);
+			assert!(rejected_by_dest);
+		},
+		_ => panic!("Unexpected event"),
+	}
 	check_closed_broadcast!(nodes[0], true);
 	check_added_monitors!(nodes[0], 1);
 }
@@ -3398,6 +3488,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	route_payment(&nodes[0], &[&nodes[1]], 10000000);
 	nodes[0].node.force_close_channel(&nodes[0].node.list_channels()[0].channel_id);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -3406,6 +3497,7 @@ fn test_htlc_ignore_latest_remote_commitment() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![node_txn[0].clone(), node_txn[1].clone()]}, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	// Duplicate the connect_block call since this may happen due to other listeners
@@ -3423,13 +3515,14 @@ fn test_force_close_fail_back() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, 42, &logger).unwrap();
-		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 1000000, 42, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -3459,6 +3552,7 @@ fn test_force_close_fail_back() {
 
 	nodes[2].node.force_close_channel(&payment_event.commitment_msg.channel_id);
 	check_closed_broadcast!(nodes[2], false);
+	check_closed_event!(nodes[2], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[2], 1);
 	let tx = {
 		let mut node_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -3477,6 +3571,7 @@ fn test_force_close_fail_back() {
 
 	// Note no UpdateHTLCs event here from nodes[1] to nodes[0]!
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	// Now check that if we add the preimage to ChannelMonitor it broadcasts our HTLC-Success..
@@ -3521,6 +3616,7 @@ fn test_unconf_chan() {
 		nodes[0].node.block_disconnected(&headers.pop().unwrap());
 	}
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Funding transaction was reorged out".to_string() });
 	check_added_monitors!(nodes[0], 1);
 	let channel_state = nodes[0].node.channel_state.lock().unwrap();
 	assert_eq!(channel_state.by_id.len(), 0);
@@ -3566,7 +3662,7 @@ fn test_simple_peer_disconnect() {
 		let events = nodes[0].node.get_and_clear_pending_events();
 		assert_eq!(events.len(), 2);
 		match events[0] {
-			Event::PaymentSent { payment_preimage } => {
+			Event::PaymentSent { payment_preimage, .. } => {
 				assert_eq!(payment_preimage, payment_preimage_3);
 			},
 			_ => panic!("Unexpected event"),
@@ -3600,12 +3696,13 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let (payment_preimage_1, payment_hash_1) = get_payment_preimage_hash!(nodes[0]);
 
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 	let payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(),
 			&nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
-			&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, payment_hash_1, &None).unwrap();
+			&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, payment_hash_1, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -3680,7 +3777,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let events_2 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_2.len(), 1);
 	match events_2[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt, .. } => {
 			assert_eq!(payment_hash_1, *payment_hash);
 			assert_eq!(*payment_secret, None);
 			assert_eq!(amt, 1000000);
@@ -3712,7 +3809,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 		let events_4 = nodes[0].node.get_and_clear_pending_events();
 		assert_eq!(events_4.len(), 1);
 		match events_4[0] {
-			Event::PaymentSent { ref payment_preimage } => {
+			Event::PaymentSent { ref payment_preimage, .. } => {
 				assert_eq!(payment_preimage_1, *payment_preimage);
 			},
 			_ => panic!("Unexpected event"),
@@ -3753,7 +3850,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 			let events_4 = nodes[0].node.get_and_clear_pending_events();
 			assert_eq!(events_4.len(), 1);
 			match events_4[0] {
-				Event::PaymentSent { ref payment_preimage } => {
+				Event::PaymentSent { ref payment_preimage, .. } => {
 					assert_eq!(payment_preimage_1, *payment_preimage);
 				},
 				_ => panic!("Unexpected event"),
@@ -3781,7 +3878,7 @@ fn do_test_drop_messages_peer_disconnect(messages_delivered: u8) {
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(),
 		&nodes[1].node.get_our_node_id(), Some(&nodes[0].node.list_usable_channels().iter().collect::<Vec<_>>()),
-		&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+		&Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let payment_preimage_2 = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000).0;
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage_2, 1_000_000);
 }
@@ -3882,7 +3979,8 @@ fn test_funding_peer_disconnect() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1]], 1000000);
 	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 1_000_000);
 }
@@ -3897,14 +3995,15 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (payment_preimage_1, _) = route_payment(&nodes[0], &[&nodes[1]], 1000000);
 
 	// Now try to send a second payment which will fail to send
 	let (payment_preimage_2, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, payment_hash_2, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, payment_hash_2, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
 	let events_1 = nodes[0].node.get_and_clear_pending_msg_events();
@@ -3932,7 +4031,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 			let events_3 = nodes[0].node.get_and_clear_pending_events();
 			assert_eq!(events_3.len(), 1);
 			match events_3[0] {
-				Event::PaymentSent { ref payment_preimage } => {
+				Event::PaymentSent { ref payment_preimage, .. } => {
 					assert_eq!(*payment_preimage, payment_preimage_1);
 				},
 				_ => panic!("Unexpected event"),
@@ -4017,7 +4116,7 @@ fn test_drop_messages_peer_disconnect_dual_htlc() {
 	let events_5 = nodes[1].node.get_and_clear_pending_events();
 	assert_eq!(events_5.len(), 1);
 	match events_5[0] {
-		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt: _ } => {
+		Event::PaymentReceived { ref payment_hash, ref payment_secret, amt: _, .. } => {
 			assert_eq!(payment_hash_2, *payment_hash);
 			assert_eq!(*payment_secret, None);
 		},
@@ -4041,15 +4140,16 @@ fn do_test_htlc_timeout(send_partial_mpp: bool) {
 
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let our_payment_hash = if send_partial_mpp {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 		let (_, our_payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 		let payment_secret = PaymentSecret([0xdb; 32]);
 		// Use the utility function send_payment_along_path to send the payment with MPP data which
 		// indicates there are more HTLCs coming.
-		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), 200000, CHAN_CONFIRM_DEPTH).unwrap();
+		nodes[0].node.send_payment_along_path(&route.paths[0], &our_payment_hash, &Some(payment_secret), 200000, CHAN_CONFIRM_DEPTH, PaymentId([42; 32]), &SecretKey::from_slice(&[42; 32]).unwrap(), &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -4105,13 +4205,14 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// Route a first payment to get the 1 -> 2 channel in awaiting_raa...
 	let (_, first_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[1].node.send_payment(&route, first_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[1].node.send_payment(&route, first_payment_hash, &None, &[]).unwrap();
 	}
 	assert_eq!(nodes[1].node.get_and_clear_pending_msg_events().len(), 1);
 	check_added_monitors!(nodes[1], 1);
@@ -4120,8 +4221,8 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 	let (_, second_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	if forwarded_htlc {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, second_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, second_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let payment_event = SendEvent::from_event(nodes[0].node.get_and_clear_pending_msg_events().remove(0));
 		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
@@ -4130,8 +4231,8 @@ fn do_test_holding_cell_htlc_add_timeouts(forwarded_htlc: bool) {
 		check_added_monitors!(nodes[1], 0);
 	} else {
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[1].node.send_payment(&route, second_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[1].node.send_payment(&route, second_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[1], 0);
 	}
 
@@ -4282,9 +4383,9 @@ fn test_no_txn_manager_serialize_deserialize() {
 	nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write(&mut chan_0_monitor_serialized).unwrap();
 
 	logger = test_utils::TestLogger::new();
-	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
 	persister = test_utils::TestPersister::new();
-	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister);
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
 	nodes[0].chain_monitor = &new_chain_monitor;
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	let mut chan_0_monitor_read = &chan_0_monitor_serialized.0[..];
@@ -4391,10 +4492,10 @@ fn test_manager_serialize_deserialize_events() {
 	let mut chan_0_monitor_serialized = test_utils::TestVecWriter(Vec::new());
 	nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write(&mut chan_0_monitor_serialized).unwrap();
 
-	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
 	logger = test_utils::TestLogger::new();
 	persister = test_utils::TestPersister::new();
-	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister);
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	nodes[0].chain_monitor = &new_chain_monitor;
 	let mut chan_0_monitor_read = &chan_0_monitor_serialized.0[..];
@@ -4461,6 +4562,101 @@ fn test_manager_serialize_deserialize_events() {
 	send_payment(&nodes[0], &[&nodes[1]], 1000000, 1_000_000);
 }
 
+#[test]
+fn test_read_v1_manager_and_monitor() {
+	// Reads a ChannelManager and ChannelMonitor written before fields were appended to Channel,
+	// ChannelConfig, ChannelMonitor and OnchainTxHandler and payment ids were added to
+	// HTLCSource, ie at SERIALIZATION_VERSION 1. The channel has an outbound HTLC pending, which
+	// we fail back after closing the channel on chain.
+	const NODE_0_MANAGER: &str = "010143497fd7f826957108f4a30fd9cec3aeba79972084e90ead01ea330900000000000000634b0f6a493e4c232f7b85413066beda34d887c45cf0eb393cce58f5acbe37023600000000000000010101000000000000002a0000000001011901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000000c000000000000186a0000000000000000300000257ee311bcc907eca23e00ed8abda72bbc5bbc5bf0d7bb7e4c23aede0e422d38fb539cb9f6be9a843771a57af6e856cb0d987ca08f285f33b45477b35823765404c28bd7cdeff77f3eebb8bd07b1548264597275908a3c214b04583cf72eadef5e8ff5c59b1fc6eaa17731518a0701021887f75336ed9a9c2a126d68c76ce688998aa56f949ad008d4a82d732e2dfaead9c3ec706b167b9e44609c0889bd363020eb6bd54eb0163d8c3412904b60fd890df158b0a131144fea789b358bb9b73a75afd017403f1fe6aa53465a0c599efbe1e683987654348020170cb838e028925197171a6f4024c8587d22ee3fe032c07c7d28113121a8b8087741930c991ed34e11085678d0f02fc44f803b20769e7866d129af45182663b2fc62414f838d1857697ff16ba779902f9e5e6f73e721385d6b788febb3a6958c7415afa23f6f8732887451634cd90d8022775e8213d8dcac449d9c09166ee66ac4711062e5a45eebeeac80e03f6fcd826009001a802306ffa5622b4a913bd0ba871c6547d1bf9ddfa41dacceb5897421945fa7ed865028a9179e9a5db509754e6254e5dc8a67e1eac58cb9fc20cdd42e1662f3532db2402b882a9779490586f622a22403ec2fd5c4c130dd58daca3b73fe9ca9a3e41fb9302f9078c16df40a2306b585558e145d2ec4d402746261354cb7959d54f982c13d302bc0fd4a19d985100840202f528e595515e4c2a7cb159008ec7c958ac12bbb0140090231901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000000000000000186a00000000000000000000000004d49e5da090000fffffffffffe0365b6760e073eaa46c1a0e558c96d72f254ec696c595b4088303273e6855357ea00160014d5a9aa98b89acc215fc3d23d6fec0ad59ca3665f0000fffffffffffd0000fffffffffffd0000000005f5b9ef00000000000000000000000000000001000000000000000000000000001e84800000008466687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f292501010355f8d2238a322d16b602bd0ceaad5b01019fb055971eaadcc9b29226a4da6c2300030292a20000010000000000000000000000001e848000000020c16ec490297243c619f9a3c135d3b1b6d38ca8d56b79f35a6f562b856b08258e00000000001e848001000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000002b000000fd0021a9d35fbb95c42c116a3831018a02415e4504d837f322a4089da42d220298072f0900000100000000004b0f6a493e4c232f7b85413066beda34d887c45cf0eb393cce58f5acbe370236000000000000006300000000000002220000000000000222000000000098968000000000000003e800000000000003e800000000000003e800320000000603f1fe6aa53465a0c599efbe1e683987654348020170cb838e028925197171a6f4024c8587d22ee3fe032c07c7d28113121a8b8087741930c991ed34e11085678d0f02fc44f803b20769e7866d129af45182663b2fc62414f838d1857697ff16ba779902f9e5e6f73e721385d6b788febb3a6958c7415afa23f6f8732887451634cd90d8022775e8213d8dcac449d9c09166ee66ac4711062e5a45eebeeac80e03f6fcd826009001a802306ffa5622b4a913bd0ba871c6547d1bf9ddfa41dacceb5897421945fa7ed865028a9179e9a5db509754e6254e5dc8a67e1eac58cb9fc20cdd42e1662f3532db2402b882a9779490586f622a22403ec2fd5c4c130dd58daca3b73fe9ca9a3e41fb9302f9078c16df40a2306b585558e145d2ec4d402746261354cb7959d54f982c13d302bc0fd4a19d985100840202f528e595515e4c2a7cb159008ec7c958ac12bbb0140090231901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000022035a4e5c2ed6418b5a2dd02a98af98fd05dd6b0dff3db90e52c605d14beb8564222202ab75faeba4adbbe47d74a808786821f5df2ccbc7ec475d8f77779867e87dc56f0355f8d2238a322d16b602bd0ceaad5b01019fb055971eaadcc9b29226a4da6c231900160014b4c4a103d72045631c3f3c86a7fea454fee696b5d73cb4a649d52aac4655a11af0315f7bbee39c0ef9fba45a3495e6ccf4b0028b0000ffffffffffff00000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000000002b";
+	const NODE_0_MONITOR: &str = "01010000000000000003d7c2cbb6c42a00160014d5a9aa98b89acc215fc3d23d6fec0ad59ca3665f01001600148b6c177a71687eea15db7d08d6484e737ff623c700160014caf6d80fe2bab80473b021f57588a9c384bf23170000000000000000000000004d49e5da024c8587d22ee3fe032c07c7d28113121a8b8087741930c991ed34e11085678d0f1901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000000220020b0ed8dd3b9ef27d8e9f73a3bd213e82f5cc9359c19942e054c7679a2e5a155dd218b0c77c6ea4e2d40738de8e1027bae5bdc10a68655a45445efe2e7a5553e74140002f9078c16df40a2306b585558e145d2ec4d402746261354cb7959d54f982c13d302bc0fd4a19d985100840202f528e595515e4c2a7cb159008ec7c958ac12bbb014009000000000000000028b0c77c6ea4e2d40738de8e1027bae5bdc10a68655a45445efe2e7a5553e741400000000000000010000000000001e84800000008466687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f29250500000000d26bb2bb57a0b2c051964bb4920fe3d7eaa06e61a8754b9897bdf34dc091667400000000000000000047522102306ffa5622b4a913bd0ba871c6547d1bf9ddfa41dacceb5897421945fa7ed8652103f1fe6aa53465a0c599efbe1e683987654348020170cb838e028925197171a6f452ae00000000000186a0ffffffffffff0293f0b0962fa3a412a8776c5b0d029e23a3d1ddf577f8d273c29d7e2b78eefdaf02ab75faeba4adbbe47d74a808786821f5df2ccbc7ec475d8f77779867e87dc56f0090d73cb4a649d52aac4655a11af0315f7bbee39c0ef9fba45a3495e6ccf4b0028b0000ffffffffffff00000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000000000000000000000000000000000000000000000000000000001000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000000000000000000100000000000000000000000000028b0c77c6ea4e2d40738de8e1027bae5bdc10a68655a45445efe2e7a5553e741400000000000000010000000000001e84800000008466687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f292505000000006701010355f8d2238a322d16b602bd0ceaad5b01019fb055971eaadcc9b29226a4da6c2300030292a20000010000000000000000000000001e848000000020c16ec490297243c619f9a3c135d3b1b6d38ca8d56b79f35a6f562b856b08258e00000000001e8480d26bb2bb57a0b2c051964bb4920fe3d7eaa06e61a8754b9897bdf34dc091667400000000000000000000000000000000000000000000000166687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925fffffffffffe014dea93949bc5188c00c10b500adaa7d351fcd95df75b0746eb92edd26ccdc766034bfcc0be233e37cd910ee19f90ac9fd119abbce0912c8919630fd064201fa43e036c5f7ff73481af1ddf6bffbeb55991da4b47dbfeef9d64a38640f9fe33fff4a30399152239c128af2f55fa151531373d439d697f10f37420c5ede0a6a2d58331110387ad5898bd0ef33bee962ca7f3ea3825d3de733e4cc2c28ccf95a9139df89c5602435ea1d389088f9ff11d3704b0fd30b3bc90e5ca91c3b24495039eb2796721f3000000fd0000000000000000ef26030c7d13b5c932ba5f74f0e0643653a91ceb9e7b9cc9a2aa31745b5bb3c502a47d6a77499f8ba4275cc4028490dcebe1a28dfed3ea21143db5332bfad06bfa024ad350e7026a3e53bf2e1074274f34310f679739a528caadd1fe0e730875160d03a7c95e44d31793c5cba9cc049e95353e7a13dd188d8773d62359fa73992741e902d63544ffa1e362e9991773644231d5a4754fb13677be1576613ae5ee687e71b003ab835b9883f0e46e7165d083109a61994291f0c40217724ef6729d27c9310775000000fd00000000000000010100000000001e84800000008466687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925050000000001bf2ea13b5c1334581af34014734086ab36320daa52643886a4dd950880ac1be632958ed5a7e62cade90a21b779285b88584d21d049464ecee5ff3f0b149797b76701010355f8d2238a322d16b602bd0ceaad5b01019fb055971eaadcc9b29226a4da6c2300030292a20000010000000000000000000000001e848000000020c16ec490297243c619f9a3c135d3b1b6d38ca8d56b79f35a6f562b856b08258e00000000001e8480fffffffffffefffffffffffe0000000000000000000000000000000000000000000000004b0f6a493e4c232f7b85413066beda34d887c45cf0eb393cce58f5acbe370236000000000000000000000000000000011901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b00000000000000010000000000220020b0ed8dd3b9ef27d8e9f73a3bd213e82f5cc9359c19942e054c7679a2e5a155dd00160014d5a9aa98b89acc215fc3d23d6fec0ad59ca3665ffd02220000fffffffffffe0000000000017de30000000000000000000000fd00010100000000001e84800000008466687aadf862bd776c8fc18b8e9f8e20089714856ee233b3902a591d0d5f2925050000000003ab835b9883f0e46e7165d083109a61994291f0c40217724ef6729d27c931077502a47d6a77499f8ba4275cc4028490dcebe1a28dfed3ea21143db5332bfad06bfa024ad350e7026a3e53bf2e1074274f34310f679739a528caadd1fe0e730875160d03a7c95e44d31793c5cba9cc049e95353e7a13dd188d8773d62359fa73992741e902d63544ffa1e362e9991773644231d5a4754fb13677be1576613ae5ee687e71b002000000011901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b0000000000cbc2d78002d007000000000000220020bacf5b9de4ac68401cc38719a7d2f0bf7638546bd593d9d26fb558c220dd8f82e37d010000000000220020eec5af7fd969a6ff4ec2525ac3792db411250c7ecbc37d11a9faa76b3c7376ab2bc4b620ef26030c7d13b5c932ba5f74f0e0643653a91ceb9e7b9cc9a2aa31745b5bb3c56a54d2e17c5d17399119f7caadd1354c8952635fea92f61c3fd8decfba4a16cf31fc52e4119b4d236e4d4aeadb0839a90d28ca6fde70065b7a47e0fcea50c6490001bf2ea13b5c1334581af34014734086ab36320daa52643886a4dd950880ac1be632958ed5a7e62cade90a21b779285b88584d21d049464ecee5ff3f0b149797b70000fd01850000ffffffffffff00000000000185de0000000000000000000000fd000002435ea1d389088f9ff11d3704b0fd30b3bc90e5ca91c3b24495039eb2796721f3034bfcc0be233e37cd910ee19f90ac9fd119abbce0912c8919630fd064201fa43e036c5f7ff73481af1ddf6bffbeb55991da4b47dbfeef9d64a38640f9fe33fff4a30399152239c128af2f55fa151531373d439d697f10f37420c5ede0a6a2d58331110387ad5898bd0ef33bee962ca7f3ea3825d3de733e4cc2c28ccf95a9139df89c5602000000011901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b0000000000cbc2d78001de850100000000002200202fba642d05a5e47e7a4377526ee45fdae6d72ab795fb1d494f89336fd8958ad32ac4b6204dea93949bc5188c00c10b500adaa7d351fcd95df75b0746eb92edd26ccdc7662e2f6ab86c70ae8d902cc3379a223984796061fe8624c6ffa3a7da72da59ac4000ccf683539d7e776ecb9a9c38135a2589aecd0cb200c671834e5317f4f180fb0000000003f1fe6aa53465a0c599efbe1e683987654348020170cb838e028925197171a6f4024c8587d22ee3fe032c07c7d28113121a8b8087741930c991ed34e11085678d0f02fc44f803b20769e7866d129af45182663b2fc62414f838d1857697ff16ba779902f9e5e6f73e721385d6b788febb3a6958c7415afa23f6f8732887451634cd90d8022775e8213d8dcac449d9c09166ee66ac4711062e5a45eebeeac80e03f6fcd826009001a802306ffa5622b4a913bd0ba871c6547d1bf9ddfa41dacceb5897421945fa7ed865028a9179e9a5db509754e6254e5dc8a67e1eac58cb9fc20cdd42e1662f3532db2402b882a9779490586f622a22403ec2fd5c4c130dd58daca3b73fe9ca9a3e41fb9302f9078c16df40a2306b585558e145d2ec4d402746261354cb7959d54f982c13d302bc0fd4a19d985100840202f528e595515e4c2a7cb159008ec7c958ac12bbb0140090231901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000000000257ee311bcc907eca23e00ed8abda72bbc5bbc5bf0d7bb7e4c23aede0e422d38fb539cb9f6be9a843771a57af6e856cb0d987ca08f285f33b45477b35823765404c28bd7cdeff77f3eebb8bd07b1548264597275908a3c214b04583cf72eadef5e8ff5c59b1fc6eaa17731518a0701021887f75336ed9a9c2a126d68c76ce688998aa56f949ad008d4a82d732e2dfaead9c3ec706b167b9e44609c0889bd363020eb6bd54eb0163d8c3412904b60fd890df158b0a131144fea789b358bb9b73a75afd017403f1fe6aa53465a0c599efbe1e683987654348020170cb838e028925197171a6f4024c8587d22ee3fe032c07c7d28113121a8b8087741930c991ed34e11085678d0f02fc44f803b20769e7866d129af45182663b2fc62414f838d1857697ff16ba779902f9e5e6f73e721385d6b788febb3a6958c7415afa23f6f8732887451634cd90d8022775e8213d8dcac449d9c09166ee66ac4711062e5a45eebeeac80e03f6fcd826009001a802306ffa5622b4a913bd0ba871c6547d1bf9ddfa41dacceb5897421945fa7ed865028a9179e9a5db509754e6254e5dc8a67e1eac58cb9fc20cdd42e1662f3532db2402b882a9779490586f622a22403ec2fd5c4c130dd58daca3b73fe9ca9a3e41fb9302f9078c16df40a2306b585558e145d2ec4d402746261354cb7959d54f982c13d302bc0fd4a19d985100840202f528e595515e4c2a7cb159008ec7c958ac12bbb0140090231901b6f0ff14c233fc709c5637e93a6b612bc0079c32cc9557476c83640f372b000000000000000186a00000000000000000000000004d49e5da090000ffffffffffff000000000000000000000000000000000000000000000000000000000000";
+
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let fee_estimator: test_utils::TestFeeEstimator;
+	let persister: test_utils::TestPersister;
+	let logger: test_utils::TestLogger;
+	let new_chain_monitor: test_utils::TestChainMonitor;
+	let keys_manager: test_utils::TestKeysInterface;
+	let nodes_0_deserialized: ChannelManager<EnforcingChannelKeys, &test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>;
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+	logger = test_utils::TestLogger::new();
+	persister = test_utils::TestPersister::new();
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
+	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
+	nodes[0].chain_monitor = &new_chain_monitor;
+
+	let chan_0_monitor_serialized = hex::decode(NODE_0_MONITOR).unwrap();
+	let mut chan_0_monitor_read = &chan_0_monitor_serialized[..];
+	let (_, mut chan_0_monitor) = <(BlockHash, ChannelMonitor<EnforcingChannelKeys>)>::read(
+		&mut chan_0_monitor_read, &keys_manager).unwrap();
+	assert!(chan_0_monitor_read.is_empty());
+
+	let nodes_0_serialized = hex::decode(NODE_0_MANAGER).unwrap();
+	let mut nodes_0_read = &nodes_0_serialized[..];
+	let (_, nodes_0_deserialized_tmp) = {
+		let mut channel_monitors = HashMap::new();
+		channel_monitors.insert(chan_0_monitor.get_funding_txo().0, &mut chan_0_monitor);
+		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_0_read, ChannelManagerReadArgs {
+			default_config: UserConfig::default(),
+			keys_manager: &keys_manager,
+			fee_estimator: &fee_estimator,
+			chain_monitor: nodes[0].chain_monitor,
+			tx_broadcaster: nodes[0].tx_broadcaster.clone(),
+			logger: &logger,
+			channel_monitors,
+		}).unwrap()
+	};
+	nodes_0_deserialized = nodes_0_deserialized_tmp;
+	assert!(nodes_0_read.is_empty());
+
+	let funding_txo = chan_0_monitor.get_funding_txo().0;
+	assert!(nodes[0].chain_monitor.watch_channel(funding_txo, chan_0_monitor).is_ok());
+	check_added_monitors!(nodes[0], 1);
+	nodes[0].node = &nodes_0_deserialized;
+
+	let channels = nodes[0].node.list_channels();
+	assert_eq!(channels.len(), 1);
+	assert_eq!(channels[0].channel_id, funding_txo.to_channel_id());
+	assert_eq!(channels[0].remote_network_id, nodes[1].node.get_our_node_id());
+	assert_eq!(channels[0].channel_value_satoshis, 100000);
+
+	// The funding transaction of create_announced_chan_between_nodes, which pays to the 2-of-2
+	// funding script of the channel.
+	let funding_tx = Transaction { version: 0, lock_time: 0, input: Vec::new(), output: vec![TxOut {
+		value: 100000, script_pubkey: Script::from(hex::decode("0020b0ed8dd3b9ef27d8e9f73a3bd213e82f5cc9359c19942e054c7679a2e5a155dd").unwrap()),
+	}]};
+	assert_eq!(funding_tx.txid(), funding_txo.txid);
+
+	nodes[0].node.force_close_channel(&channels[0].channel_id);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	assert_eq!(node_txn.len(), 2);
+	check_spends!(node_txn[0], funding_tx);
+	check_spends!(node_txn[1], node_txn[0]);
+	assert_eq!(node_txn[0].output.len(), 2);
+	assert_eq!(node_txn[1].lock_time, 132);
+
+	// Once the HTLC-timeout transaction reaches ANTI_REORG_DELAY, the payment which was pending
+	// when the ChannelManager was written fails back.
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header, txdata: vec![node_txn[0].clone(), node_txn[1].clone()] }, 132);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1, 132, true, header.block_hash());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { ref payment_hash, .. } => {
+			assert_eq!(*payment_hash, PaymentHash(Sha256::hash(&[0; 32]).into_inner()));
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
 #[test]
 fn test_simple_manager_serialize_deserialize() {
 	let chanmon_cfgs = create_chanmon_cfgs(2);
@@ -4485,9 +4681,9 @@ fn test_simple_manager_serialize_deserialize() {
 	nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write(&mut chan_0_monitor_serialized).unwrap();
 
 	logger = test_utils::TestLogger::new();
-	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
 	persister = test_utils::TestPersister::new();
-	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister);
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
 	nodes[0].chain_monitor = &new_chain_monitor;
 	let mut chan_0_monitor_read = &chan_0_monitor_serialized.0[..];
@@ -4566,9 +4762,9 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 	}
 
 	logger = test_utils::TestLogger::new();
-	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
 	persister = test_utils::TestPersister::new();
-	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister);
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
 	nodes[0].chain_monitor = &new_chain_monitor;
 
 	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
@@ -4629,6 +4825,7 @@ fn test_manager_serialize_deserialize_inconsistent_monitor() {
 		check_added_monitors!(nodes[0], 1);
 	}
 	nodes[0].node = &nodes_0_deserialized;
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelManager is stale compared to its ChannelMonitor".to_owned() });
 
 	// nodes[1] and nodes[2] have no lost state with nodes[0]...
 	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
@@ -4785,6 +4982,7 @@ fn test_claim_sizeable_push_msat() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
 	nodes[1].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[1], 1);
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 	assert_eq!(node_txn.len(), 1);
@@ -4812,6 +5010,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 99000000, InitFeatures::known(), InitFeatures::known());
 	nodes[0].node.force_close_channel(&chan.2);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4822,6 +5021,7 @@ fn test_claim_on_remote_sizeable_push_msat() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![node_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 	connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
 
@@ -4850,6 +5050,7 @@ fn test_claim_on_remote_revoked_sizeable_push_msat() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -4885,6 +5086,7 @@ fn test_static_spendable_outputs_preimage_tx() {
 	check_added_monitors!(nodes[1], 1);
 	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_tx[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -4935,6 +5137,7 @@ fn test_static_spendable_outputs_timeout_tx() {
 	let header = BlockHeader { version: 0x2000_0000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42};
 	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_tx[0].clone()] }, 0);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::BroadcastChannelUpdate { .. } => {},
@@ -4979,6 +5182,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_commitment_tx() {
 	let  header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5016,6 +5220,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	// A will generate HTLC-Timeout from revoked commitment tx
 	connect_block(&nodes[0], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let revoked_htlc_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5028,6 +5233,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_timeout_tx() {
 	// B will generate justice tx from A's revoked commitment/HTLC tx
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 0);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5085,6 +5291,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	// B will generate HTLC-Success from revoked commitment tx
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 
@@ -5100,6 +5307,7 @@ fn test_static_spendable_outputs_justice_tx_revoked_htlc_success_tx() {
 	// A will generate justice tx from B's revoked commitment/HTLC tx
 	connect_block(&nodes[0], &Block { header, txdata: vec![revoked_local_txn[0].clone(), revoked_htlc_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -5175,6 +5383,7 @@ fn test_onchain_to_onchain_claim() {
 
 	connect_block(&nodes[2], &Block { header, txdata: vec![commitment_tx[0].clone()]}, 1);
 	check_closed_broadcast!(nodes[2], false);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[2], 1);
 
 	let c_txn = nodes[2].tx_broadcaster.txn_broadcasted.lock().unwrap().clone(); // ChannelManager : 2 (commitment tx, HTLC-Success tx), ChannelMonitor : 1 (HTLC-Success tx)
@@ -5236,6 +5445,7 @@ fn test_onchain_to_onchain_claim() {
 	assert_eq!(b_txn[0].lock_time, 0); // Success tx
 
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 2, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -5262,6 +5472,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let htlc_timeout_tx;
@@ -5285,6 +5496,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 	nodes[2].node.claim_funds(our_payment_preimage, &None, 900_000);
 	connect_block(&nodes[2], &Block { header, txdata: vec![commitment_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[2], 3);
+	check_closed_event!(nodes[2], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[2].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5349,7 +5561,7 @@ fn test_duplicate_payment_hash_one_failure_one_success() {
 
 	let events = nodes[0].node.get_and_clear_pending_events();
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(*payment_preimage, our_payment_preimage);
 		}
 		_ => panic!("Unexpected event"),
@@ -5377,6 +5589,7 @@ fn test_dynamic_spendable_outputs_local_htlc_success_tx() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[1], &Block { header, txdata: vec![local_txn[0].clone()] }, 1);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	let events = nodes[1].node.get_and_clear_pending_msg_events();
 	match events[0] {
 		MessageSendEvent::UpdateHTLCs { .. } => {},
@@ -5423,6 +5636,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let node_chanmgrs = create_node_chanmgrs(6, &node_cfgs, &[None, None, None, None, None, None]);
 	let nodes = create_network(6, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
@@ -5442,7 +5656,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_2) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
 	let our_node_id = &nodes[1].node.get_our_node_id();
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	// 2nd HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_1); // not added < dust limit + HTLC tx fee
 	// 3rd HTLC:
@@ -5451,7 +5665,7 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	let (_, payment_hash_3) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 5th HTLC:
 	let (_, payment_hash_4) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	// 6th HTLC:
 	send_along_route_with_hash(&nodes[1], route.clone(), &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_3);
 	// 7th HTLC:
@@ -5460,13 +5674,13 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	// 8th HTLC:
 	let (_, payment_hash_5) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], 1000000);
 	// 9th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), ds_dust_limit*1000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], ds_dust_limit*1000, payment_hash_5); // not added < dust limit + HTLC tx fee
 
 	// 10th HTLC:
 	let (_, payment_hash_6) = route_payment(&nodes[0], &[&nodes[2], &nodes[3], &nodes[4]], ds_dust_limit*1000); // not added < dust limit + HTLC tx fee
 	// 11th HTLC:
-	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(our_node_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[5].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_hash(&nodes[1], route, &[&nodes[2], &nodes[3], &nodes[5]], 1000000, payment_hash_6);
 
 	// Double-check that six of the new HTLC were added
@@ -5542,7 +5756,16 @@ fn do_test_fail_backwards_unrevoked_remote_announce(deliver_last_raa: bool, anno
 	}
 	connect_blocks(&nodes[2], ANTI_REORG_DELAY - 1, 1, true,  header.block_hash());
 	check_closed_broadcast!(nodes[2], false);
-	expect_pending_htlcs_forwardable!(nodes[2]);
+	let events = nodes[2].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	for event in events {
+		match event {
+			Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+			Event::PendingHTLCsForwardable { .. } => {},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	nodes[2].node.process_pending_htlc_forwards();
 	check_added_monitors!(nodes[2], 3);
 
 	let cs_msgs = nodes[2].node.get_and_clear_pending_msg_events();
@@ -5680,6 +5903,7 @@ fn test_dynamic_spendable_outputs_local_htlc_timeout_tx() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header, txdata: vec![local_txn[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let htlc_timeout = {
@@ -5713,7 +5937,7 @@ fn test_key_derivation_params() {
 	// We manually create the node configuration to backup the seed.
 	let seed = [42; 32];
 	let keys_manager = test_utils::TestKeysInterface::new(&seed, Network::Testnet);
-	let chain_monitor = test_utils::TestChainMonitor::new(Some(&chanmon_cfgs[0].chain_source), &chanmon_cfgs[0].tx_broadcaster, &chanmon_cfgs[0].logger, &chanmon_cfgs[0].fee_estimator, &chanmon_cfgs[0].persister);
+	let chain_monitor = test_utils::TestChainMonitor::new(Some(&chanmon_cfgs[0].chain_source), &chanmon_cfgs[0].tx_broadcaster, &chanmon_cfgs[0].logger, &chanmon_cfgs[0].fee_estimator, &chanmon_cfgs[0].persister, None);
 	let node = NodeCfg { chain_source: &chanmon_cfgs[0].chain_source, logger: &chanmon_cfgs[0].logger, tx_broadcaster: &chanmon_cfgs[0].tx_broadcaster, fee_estimator: &chanmon_cfgs[0].fee_estimator, chain_monitor, keys_manager, node_seed: seed };
 	let mut node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
 	node_cfgs.remove(0);
@@ -5749,6 +5973,7 @@ fn test_key_derivation_params() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header, txdata: vec![local_txn_1[0].clone()] }, 200);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	let htlc_timeout = {
@@ -5819,7 +6044,7 @@ fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { payment_preimage } => {
+		Event::PaymentSent { payment_preimage, .. } => {
 			assert_eq!(payment_preimage, our_payment_preimage);
 		},
 		_ => panic!("Unexpected event"),
@@ -5842,6 +6067,7 @@ fn do_htlc_claim_local_commitment_only(use_dust: bool) {
 	test_txn_broadcast(&nodes[1], &chan, None, if use_dust { HTLCType::NONE } else { HTLCType::SUCCESS });
 	check_closed_broadcast!(nodes[1], false);
 	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 }
 
 fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
@@ -5851,11 +6077,12 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), if use_dust { 50000 } else { 3000000 }, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
 	let _as_update = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -5873,6 +6100,7 @@ fn do_htlc_claim_current_remote_commitment_only(use_dust: bool) {
 	test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 	check_closed_broadcast!(nodes[0], false);
 	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 }
 
 fn do_htlc_claim_previous_remote_commitment_only(use_dust: bool, check_revoke_no_close: bool) {
@@ -5920,6 +6148,7 @@ fn do_htlc_claim_previous_remote_commitment_only(use_dust: bool, check_revoke_no
 		test_txn_broadcast(&nodes[0], &chan, None, HTLCType::NONE);
 		check_closed_broadcast!(nodes[0], false);
 		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
 	} else {
 		expect_payment_failed!(nodes[0], our_payment_hash, true);
 	}
@@ -6033,6 +6262,7 @@ fn test_fail_holding_cell_htlc_upon_free() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// First nodes[0] generates an update_fee, setting the channel's
 	// pending_update_fee.
@@ -6058,10 +6288,10 @@ fn test_fail_holding_cell_htlc_upon_free() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let max_can_send = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 1 + 1);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	// Send a payment which passes reserve checks but gets stuck in the holding cell.
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	assert_eq!(chan_stat.holding_cell_outbound_amount_msat, max_can_send);
 
@@ -6085,7 +6315,7 @@ fn test_fail_holding_cell_htlc_upon_free() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match &events[0] {
-		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data } => {
+		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data, .. } => {
 			assert_eq!(our_payment_hash.clone(), *payment_hash);
 			assert_eq!(*rejected_by_dest, false);
 			assert_eq!(*error_code, None);
@@ -6106,6 +6336,7 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// First nodes[0] generates an update_fee, setting the channel's
 	// pending_update_fee.
@@ -6133,14 +6364,14 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
 	let amt_2 = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 2 + 1) - amt_1;
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_1, TEST_FINAL_CLTV, &logger).unwrap();
-	let route_2 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_2, TEST_FINAL_CLTV, &logger).unwrap();
+	let route_1 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_1, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let route_2 = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], amt_2, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	// Send 2 payments which pass reserve checks but get stuck in the holding cell.
-	nodes[0].node.send_payment(&route_1, payment_hash_1, &None).unwrap();
+	nodes[0].node.send_payment(&route_1, payment_hash_1, &None, &[]).unwrap();
 	chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	assert_eq!(chan_stat.holding_cell_outbound_amount_msat, amt_1);
-	nodes[0].node.send_payment(&route_2, payment_hash_2, &None).unwrap();
+	nodes[0].node.send_payment(&route_2, payment_hash_2, &None, &[]).unwrap();
 	chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	assert_eq!(chan_stat.holding_cell_outbound_amount_msat, amt_1 + amt_2);
 
@@ -6165,7 +6396,7 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match &events[0] {
-		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data } => {
+		&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest, ref error_code, ref error_data, .. } => {
 			assert_eq!(payment_hash_2.clone(), *payment_hash);
 			assert_eq!(*rejected_by_dest, false);
 			assert_eq!(*error_code, None);
@@ -6209,7 +6440,7 @@ fn test_free_and_fail_holding_cell_htlcs() {
 	let events = nodes[0].node.get_and_clear_pending_events();
 	assert_eq!(events.len(), 1);
 	match events[0] {
-		Event::PaymentSent { ref payment_preimage } => {
+		Event::PaymentSent { ref payment_preimage, .. } => {
 			assert_eq!(*payment_preimage, payment_preimage_1);
 		}
 		_ => panic!("Unexpected event"),
@@ -6229,6 +6460,7 @@ fn test_fail_holding_cell_htlc_upon_free_multihop() {
 	let chan_0_1 = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let chan_1_2 = create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	// First nodes[1] generates an update_fee, setting the channel's
 	// pending_update_fee.
@@ -6257,8 +6489,8 @@ fn test_fail_holding_cell_htlc_upon_free_multihop() {
 	let max_can_send = 5000000 - channel_reserve - 2*commit_tx_fee_msat(feerate, 1 + 1) - total_routing_fee_msat;
 	let payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -6373,10 +6605,11 @@ fn test_update_add_htlc_bolt2_sender_value_below_minimum_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	route.paths[0][0].fee_msat = 100;
 
-	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 		assert!(regex::Regex::new(r"Cannot send less than their minimum HTLC value \(\d+\)").unwrap().is_match(err)));
 	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
 	nodes[0].logger.assert_log_contains("lightning::ln::channelmanager".to_string(), "Cannot send less than their minimum HTLC value".to_string(), 1);
@@ -6394,9 +6627,10 @@ fn test_update_add_htlc_bolt2_sender_zero_value_msat() {
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	route.paths[0][0].fee_msat = 0;
-	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 		assert_eq!(err, "Cannot send 0-msat HTLC"));
 
 	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
@@ -6415,8 +6649,9 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	updates.update_add_htlcs[0].amount_msat = 0;
@@ -6424,6 +6659,7 @@ fn test_update_add_htlc_bolt2_receiver_zero_value_msat() {
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), "Remote side tried to send a 0-msat HTLC".to_string(), 1);
 	check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote side tried to send a 0-msat HTLC".to_string() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -6437,12 +6673,13 @@ fn test_update_add_htlc_bolt2_sender_cltv_expiry_too_high() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let _chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 0, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000000, 500000001, &logger).unwrap();
-	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::RouteError { ref err },
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, 500000001, &logger, &scorer).unwrap();
+	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::RouteError { ref err },
 		assert_eq!(err, &"Channel CLTV overflowed?"));
 }
 
@@ -6459,12 +6696,13 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 	let max_accepted_htlcs = nodes[1].node.channel_state.lock().unwrap().by_id.get(&chan.2).unwrap().counterparty_max_accepted_htlcs as u64;
 
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 	for i in 0..max_accepted_htlcs {
 		let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 		let payment_event = {
 			let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-			nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+			let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+			nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 			check_added_monitors!(nodes[0], 1);
 
 			let mut events = nodes[0].node.get_and_clear_pending_msg_events();
@@ -6485,8 +6723,8 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_num_and_htlc_id_increment()
 	}
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
-	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 		assert!(regex::Regex::new(r"Cannot push more than their max accepted HTLCs \(\d+\)").unwrap().is_match(err)));
 
 	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
@@ -6509,8 +6747,11 @@ fn test_update_add_htlc_bolt2_sender_exceed_max_htlc_value_in_flight() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_in_flight+1, TEST_FINAL_CLTV, &logger).unwrap();
-	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None), true, APIError::ChannelUnavailable { ref err },
+	let scorer = Scorer::with_fixed_penalty(0);
+	// The router won't exceed the channel's htlc_maximum_msat, so bump the value of a valid route
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_in_flight, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	route.paths[0][0].fee_msat = max_in_flight + 1;
+	unwrap_send_err!(nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]), true, APIError::ChannelUnavailable { ref err },
 		assert!(regex::Regex::new(r"Cannot send value that would put us over the max HTLC value in flight our peer will accept \(\d+\)").unwrap().is_match(err)));
 
 	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
@@ -6538,14 +6779,16 @@ fn test_update_add_htlc_bolt2_receiver_check_amount_received_more_than_min() {
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
 	let logger = test_utils::TestLogger::new();
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], htlc_minimum_msat, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	updates.update_add_htlcs[0].amount_msat = htlc_minimum_msat-1;
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote side tried to send less than our minimum HTLC value. Lower limit: (1000). Actual: (999)".to_string() });
 	assert!(regex::Regex::new(r"Remote side tried to send less than our minimum HTLC value\. Lower limit: \(\d+\)\. Actual: \(\d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6559,6 +6802,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let chan_stat = get_channel_value_stat!(nodes[0], chan.2);
 	let channel_reserve = chan_stat.channel_reserve_msat;
@@ -6569,8 +6813,8 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 	let max_can_send = 5000000 - channel_reserve - commit_tx_fee_outbound;
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], max_can_send, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 
@@ -6582,6 +6826,7 @@ fn test_update_add_htlc_bolt2_receiver_sender_can_afford_amount_sent() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them under remote reserve value".to_string() });
 	assert_eq!(err_msg.data, "Remote HTLC add would put them under remote reserve value");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6596,16 +6841,17 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let session_priv = SecretKey::from_slice(&[42; 32]).unwrap();
 
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 3999999, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 3999999, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 
 	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
 	let onion_keys = onion_utils::construct_onion_keys(&Secp256k1::signing_only(), &route.paths[0], &session_priv).unwrap();
-	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, cur_height).unwrap();
+	let (onion_payloads, _htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(&route.paths[0], 3999999, &None, &None, &[], cur_height).unwrap();
 	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, [0; 32], &our_payment_hash);
 
 	let mut msg = msgs::UpdateAddHTLC {
@@ -6626,6 +6872,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_htlc_limit() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote tried to push more than our max accepted HTLCs (50)".to_string() });
 	assert!(regex::Regex::new(r"Remote tried to push more than our max accepted HTLCs \(\d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6639,11 +6886,12 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	updates.update_add_htlcs[0].amount_msat = get_channel_value_stat!(nodes[1], chan.2).counterparty_max_htlc_value_in_flight_msat + 1;
@@ -6651,6 +6899,7 @@ fn test_update_add_htlc_bolt2_receiver_check_max_in_flight_msat() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote HTLC add would put them over our max HTLC value (100000000)".to_string() });
 	assert!(regex::Regex::new("Remote HTLC add would put them over our max HTLC value").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6663,12 +6912,13 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 95000000, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	updates.update_add_htlcs[0].cltv_expiry = 500000000;
@@ -6676,6 +6926,7 @@ fn test_update_add_htlc_bolt2_receiver_check_cltv_expiry() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote provided CLTV expiry in seconds instead of block height".to_string() });
 	assert_eq!(err_msg.data,"Remote provided CLTV expiry in seconds instead of block height");
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6690,12 +6941,13 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
@@ -6725,6 +6977,7 @@ fn test_update_add_htlc_bolt2_receiver_check_repeated_id_ignore() {
 
 	assert!(nodes[1].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[1], true).unwrap();
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Remote skipped HTLC ID (skipped ID: 1)".to_string() });
 	assert!(regex::Regex::new(r"Remote skipped HTLC ID \(skipped ID: \d+\)").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[1], 1);
 }
@@ -6738,11 +6991,12 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let (our_payment_preimage, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6758,6 +7012,7 @@ fn test_update_fulfill_htlc_bolt2_update_fulfill_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC (0) before it had been committed".to_string() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6772,11 +7027,12 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
@@ -6791,6 +7047,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_htlc_before_commitment() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC (0) before it had been committed".to_string() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6805,11 +7062,12 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
 	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
@@ -6825,6 +7083,7 @@ fn test_update_fulfill_htlc_bolt2_update_fail_malformed_htlc_before_commitment()
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail HTLC (0) before it had been committed".to_string() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill/fail HTLC \(\d+\) before it had been committed").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6866,6 +7125,7 @@ fn test_update_fulfill_htlc_bolt2_incorrect_htlc_id() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill/fail an HTLC we couldn't find".to_string() });
 	assert_eq!(err_msg.data, "Remote tried to fulfill/fail an HTLC we couldn't find");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6907,6 +7167,7 @@ fn test_update_fulfill_htlc_bolt2_wrong_preimage() {
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Remote tried to fulfill HTLC (0) with an incorrect preimage".to_string() });
 	assert!(regex::Regex::new(r"Remote tried to fulfill HTLC \(\d+\) with an incorrect preimage").unwrap().is_match(err_msg.data.as_str()));
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6921,11 +7182,12 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger).unwrap();
-	nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &[], 1000000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 	check_added_monitors!(nodes[0], 1);
 
 	let mut updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
@@ -6955,6 +7217,7 @@ fn test_update_fulfill_htlc_bolt2_missing_badonion_bit_for_malformed_htlc_messag
 
 	assert!(nodes[0].node.list_channels().is_empty());
 	let err_msg = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Got update_fail_malformed_htlc with BADONION not set".to_string() });
 	assert_eq!(err_msg.data, "Got update_fail_malformed_htlc with BADONION not set");
 	check_added_monitors!(nodes[0], 1);
 }
@@ -6971,14 +7234,15 @@ fn test_update_fulfill_htlc_bolt2_after_malformed_htlc_message_must_forward_upda
 	create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes_with_value(&nodes, 1, 2, 1000000, 1000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (_, our_payment_hash) = get_payment_preimage_hash!(nodes[0]);
 
 	//First hop
 	let mut payment_event = {
 		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[0].node.send_payment(&route, our_payment_hash, &None).unwrap();
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[0].node.send_payment(&route, our_payment_hash, &None, &[]).unwrap();
 		check_added_monitors!(nodes[0], 1);
 		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 1);
@@ -7099,6 +7363,7 @@ fn do_test_failure_delay_dust_htlc_local_commitment(announce_latest: bool) {
 	}
 
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 
 	assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
@@ -7162,6 +7427,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		// We fail dust-HTLC 1 by broadcast of local commitment tx
 		connect_block(&nodes[0], &Block { header, txdata: vec![as_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		check_added_monitors!(nodes[0], 1);
 		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
@@ -7179,6 +7445,7 @@ fn do_test_sweep_outbound_htlc_failure_update(revoked: bool, local: bool) {
 		// We fail dust-HTLC 1 by broadcast of remote commitment tx. If revoked, fail also non-dust HTLC
 		connect_block(&nodes[0], &Block { header, txdata: vec![bs_commitment_tx[0].clone()]}, 1);
 		check_closed_broadcast!(nodes[0], false);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 		check_added_monitors!(nodes[0], 1);
 		assert_eq!(nodes[0].node.get_and_clear_pending_events().len(), 0);
 		timeout_tx.push(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap()[0].clone());
@@ -7248,6 +7515,7 @@ fn test_upfront_shutdown_script() {
 	// Test we enforce upfront_scriptpbukey if by providing a diffrent one at closing that  we disconnect peer
 	nodes[2].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
     assert!(regex::Regex::new(r"Got shutdown request with a scriptpubkey \([A-Fa-f0-9]+\) which did not match their previous scriptpubkey.").unwrap().is_match(check_closed_broadcast!(nodes[2], true).unwrap().data.as_str()));
+    check_closed_event!(nodes[2], 1, ClosureReason::ProcessingError { err: "Got shutdown request with a scriptpubkey (a91441c98a140039816273e50db317422c11c2bfcc8887) which did not match their previous scriptpubkey.".to_string() });
 	check_added_monitors!(nodes[2], 1);
 
 	// We test that in case of peer committing upfront to a script, if it doesn't change at closing, we sign
@@ -7326,7 +7594,7 @@ fn test_user_configurable_csv_delay() {
 
 	// We test config.our_to_self > BREAKDOWN_TIMEOUT is enforced in Channel::new_outbound()
 	let keys_manager = Arc::new(test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet));
-	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), 1000000, 1000000, 0, &low_our_to_self_config) {
+	if let Err(error) = Channel::new_outbound(&&test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) }, &keys_manager, nodes[1].node.get_our_node_id(), &InitFeatures::known(), 1000000, 1000000, 0, &low_our_to_self_config) {
 		match error {
 			APIError::APIMisuseError { err } => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str())); },
 			_ => panic!("Unexpected event"),
@@ -7337,7 +7605,7 @@ fn test_user_configurable_csv_delay() {
 	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
 	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
 	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &low_our_to_self_config) {
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &low_our_to_self_config) {
 		match error {
 			ChannelError::Close(err) => { assert!(regex::Regex::new(r"Configured with an unreasonable our_to_self_delay \(\d+\) putting user funds at risks").unwrap().is_match(err.as_str()));  },
 			_ => panic!("Unexpected event"),
@@ -7358,12 +7626,13 @@ fn test_user_configurable_csv_delay() {
 			_ => { assert!(false); }
 		}
 	} else { assert!(false); }
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "They wanted our payments to be delayed by a needlessly long period. Upper limit: 100. Actual: 200".to_owned() });
 
 	// We test msg.to_self_delay <= config.their_to_self_delay is enforced in Channel::new_from_req()
 	nodes[1].node.create_channel(nodes[0].node.get_our_node_id(), 1000000, 1000000, 42, None).unwrap();
 	let mut open_channel = get_event_msg!(nodes[1], MessageSendEvent::SendOpenChannel, nodes[0].node.get_our_node_id());
 	open_channel.to_self_delay = 200;
-	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: 253 }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &high_their_to_self_config) {
+	if let Err(error) = Channel::new_from_req(&&test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) }, &keys_manager, nodes[1].node.get_our_node_id(), InitFeatures::known(), &open_channel, 0, &high_their_to_self_config) {
 		match error {
 			ChannelError::Close(err) => { assert!(regex::Regex::new(r"They wanted our payments to be delayed by a needlessly long period\. Upper limit: \d+\. Actual: \d+").unwrap().is_match(err.as_str())); },
 			_ => panic!("Unexpected event"),
@@ -7409,9 +7678,9 @@ fn test_data_loss_protect() {
 	let mut chain_monitor = <(BlockHash, ChannelMonitor<EnforcingChannelKeys>)>::read(&mut ::std::io::Cursor::new(previous_chain_monitor_state.0), &keys_manager).unwrap().1;
 	chain_source = test_utils::TestChainSource::new(Network::Testnet);
 	tx_broadcaster = test_utils::TestBroadcaster{txn_broadcasted: Mutex::new(Vec::new())};
-	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: 253 };
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
 	persister = test_utils::TestPersister::new();
-	monitor = test_utils::TestChainMonitor::new(Some(&chain_source), &tx_broadcaster, &logger, &fee_estimator, &persister);
+	monitor = test_utils::TestChainMonitor::new(Some(&chain_source), &tx_broadcaster, &logger, &fee_estimator, &persister, None);
 	node_state_0 = {
 		let mut channel_monitors = HashMap::new();
 		channel_monitors.insert(OutPoint { txid: chan.3.txid(), index: 0 }, &mut chain_monitor);
@@ -7463,10 +7732,12 @@ fn test_data_loss_protect() {
 			panic!("Unexpected event")
 		}
 	}
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "We have fallen behind - we have received proof that if we broadcast remote is going to claim our funds - we can't do any automated broadcasting".to_string() });
 
 	// Check we close channel detecting A is fallen-behind
 	nodes[1].node.handle_channel_reestablish(&nodes[0].node.get_our_node_id(), &reestablish_1[0]);
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Peer attempted to reestablish channel with a very old local commitment transaction");
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer attempted to reestablish channel with a very old local commitment transaction".to_string() });
 	check_added_monitors!(nodes[1], 1);
 
 
@@ -7603,11 +7874,12 @@ fn test_bump_penalty_txn_on_revoked_commitment() {
 
 	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1000000, 59000000, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 
 	let payment_preimage = route_payment(&nodes[0], &vec!(&nodes[1])[..], 3000000).0;
 	let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000, 30, &logger).unwrap();
+	let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000, 30, &logger, &scorer).unwrap();
 	send_along_route(&nodes[1], route, &vec!(&nodes[0])[..], 3000000);
 
 	let revoked_txn = get_local_commitment_txn!(nodes[0], chan.2);
@@ -7724,6 +7996,7 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	// B will generate both revoked HTLC-timeout/HTLC-preimage txn from revoked commitment tx
 	connect_block(&nodes[1], &Block { header, txdata: vec![revoked_local_txn[0].clone()] }, 1);
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[1], 1);
 
 	let revoked_htlc_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -7747,7 +8020,16 @@ fn test_bump_penalty_txn_on_revoked_htlcs() {
 	// Broadcast set of revoked txn on A
 	let header_128 = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header: header_128, txdata: vec![revoked_local_txn[0].clone()] }, 128);
-	expect_pending_htlcs_forwardable_ignore!(nodes[0]);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::ChannelClosed { reason: ClosureReason::CommitmentTxConfirmed, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PendingHTLCsForwardable { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
 	let header_129 = BlockHeader { version: 0x20000000, prev_blockhash: header_128.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header: header_129, txdata: vec![revoked_htlc_txn[0].clone(), revoked_htlc_txn[1].clone()] }, 129);
 	let first;
@@ -8006,6 +8288,7 @@ fn test_set_outpoints_partial_claiming() {
 	let header = BlockHeader { version: 0x20000000, prev_blockhash: prev_header_100, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header, txdata: vec![remote_txn[0].clone()] }, 101);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 	// Verify node A broadcast tx claiming both HTLCs
 	{
@@ -8023,6 +8306,7 @@ fn test_set_outpoints_partial_claiming() {
 	// Connect blocks on node B
 	connect_blocks(&nodes[1], 135, 0, false, Default::default());
 	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[1], 1);
 	// Verify node B broadcast 2 HTLC-timeout txn
 	let partial_claim_tx = {
@@ -8093,6 +8377,7 @@ fn test_counterparty_raa_skip_no_crash() {
 	nodes[1].node.handle_revoke_and_ack(&nodes[0].node.get_our_node_id(),
 		&msgs::RevokeAndACK { channel_id, per_commitment_secret, next_per_commitment_point });
 	assert_eq!(check_closed_broadcast!(nodes[1], true).unwrap().data, "Received an unexpected revoke_and_ack");
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Received an unexpected revoke_and_ack".to_string() });
 	check_added_monitors!(nodes[1], 1);
 }
 
@@ -8125,6 +8410,7 @@ fn test_bump_txn_sanitize_tracking_maps() {
 	let header_129 = BlockHeader { version: 0x20000000, prev_blockhash: header_128, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
 	connect_block(&nodes[0], &Block { header: header_129, txdata: vec![revoked_local_txn[0].clone()] }, 129);
 	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
 	check_added_monitors!(nodes[0], 1);
 	let penalty_txn = {
 		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -8197,11 +8483,12 @@ fn test_simple_payment_secret() {
 	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
 	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 	let payment_secret = PaymentSecret([0xdb; 32]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 100000, payment_hash, Some(payment_secret.clone()));
 	// Claiming with all the correct values but the wrong secret should result in nothing...
 	assert_eq!(nodes[2].node.claim_funds(payment_preimage, &None, 100_000), false);
@@ -8223,11 +8510,12 @@ fn test_simple_mpp() {
 	let chan_3_id = create_announced_chan_between_nodes(&nodes, 1, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 	let chan_4_id = create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known()).0.contents.short_channel_id;
 	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
 
 	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(&nodes[0]);
 	let payment_secret = PaymentSecret([0xdb; 32]);
 	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
-	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger).unwrap();
+	let mut route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[3].node.get_our_node_id(), None, &[], 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
 	let path = route.paths[0].clone();
 	route.paths.push(path);
 	route.paths[0][0].pubkey = nodes[1].node.get_our_node_id();
@@ -8281,7 +8569,7 @@ fn test_update_err_monitor_lockdown() {
 		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
 				&mut ::std::io::Cursor::new(&w.0), &test_utils::OnlyReadsKeysInterface {}).unwrap().1;
 		assert!(new_monitor == *monitor);
-		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister);
+		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister, None);
 		assert!(watchtower.watch_channel(outpoint, new_monitor).is_ok());
 		watchtower
 	};
@@ -8340,7 +8628,7 @@ fn test_concurrent_monitor_claim() {
 		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
 				&mut ::std::io::Cursor::new(&w.0), &test_utils::OnlyReadsKeysInterface {}).unwrap().1;
 		assert!(new_monitor == *monitor);
-		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister);
+		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister, None);
 		assert!(watchtower.watch_channel(outpoint, new_monitor).is_ok());
 		watchtower
 	};
@@ -8357,6 +8645,7 @@ fn test_concurrent_monitor_claim() {
 	// Copy ChainMonitor to simulate watchtower Bob and make it receive a commitment update first.
 	let chain_source = test_utils::TestChainSource::new(Network::Testnet);
 	let logger = test_utils::TestLogger::with_id(format!("node {}", "Bob"));
+	let scorer = Scorer::with_fixed_penalty(0);
 	let persister = test_utils::TestPersister::new();
 	let watchtower_bob = {
 		let monitors = nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap();
@@ -8366,7 +8655,7 @@ fn test_concurrent_monitor_claim() {
 		let new_monitor = <(BlockHash, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>::read(
 				&mut ::std::io::Cursor::new(&w.0), &test_utils::OnlyReadsKeysInterface {}).unwrap().1;
 		assert!(new_monitor == *monitor);
-		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister);
+		let watchtower = test_utils::TestChainMonitor::new(Some(&chain_source), &chanmon_cfgs[0].tx_broadcaster, &logger, &chanmon_cfgs[0].fee_estimator, &persister, None);
 		assert!(watchtower.watch_channel(outpoint, new_monitor).is_ok());
 		watchtower
 	};
@@ -8377,8 +8666,8 @@ fn test_concurrent_monitor_claim() {
 	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
 	{
 		let net_graph_msg_handler = &nodes[1].net_graph_msg_handler;
-		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000 , TEST_FINAL_CLTV, &logger).unwrap();
-		nodes[1].node.send_payment(&route, payment_hash, &None).unwrap();
+		let route = get_route(&nodes[1].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0].node.get_our_node_id(), None, &Vec::new(), 3000000 , TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		nodes[1].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
 	}
 	check_added_monitors!(nodes[1], 1);
 
@@ -8455,6 +8744,154 @@ fn test_pre_lockin_no_chan_closed_update() {
 	let channel_id = ::chain::transaction::OutPoint { txid: funding_created_msg.funding_txid, index: funding_created_msg.funding_output_index }.to_channel_id();
 	nodes[0].node.handle_error(&nodes[1].node.get_our_node_id(), &msgs::ErrorMessage { channel_id, data: "Hi".to_owned() });
 	assert!(nodes[0].chain_monitor.added_monitors.lock().unwrap().is_empty());
+
+	// The funding transaction was never broadcast, so the wallet is told it may reuse its inputs.
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::DiscardFunding { channel_id: ref discarded_channel_id, ref funding_txid, ref transaction } => {
+			assert_eq!(*discarded_channel_id, channel_id);
+			assert_eq!(*funding_txid, funding_output.txid);
+			assert!(transaction.is_none());
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::ChannelClosed { channel_id: ref closed_channel_id, user_id, ref reason } => {
+			assert_eq!(*closed_channel_id, channel_id);
+			assert_eq!(user_id, 42);
+			assert_eq!(*reason, ClosureReason::CounterpartyForceClosed { peer_msg: "Hi".to_owned() });
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_inbound_funding_timeout() {
+	// Test that if the funding transaction of an inbound channel never confirms, we give up on the
+	// channel once FUNDING_CONF_DEADLINE_BLOCKS have passed and tell our counterparty about it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	create_chan_between_nodes_with_value_init(&nodes[0], &nodes[1], 100000, 10001, InitFeatures::known(), InitFeatures::known());
+	let channel_id = nodes[1].node.list_channels()[0].channel_id;
+
+	connect_blocks(&nodes[1], FUNDING_CONF_DEADLINE_BLOCKS, 0, false, Default::default());
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	connect_blocks(&nodes[1], 1, FUNDING_CONF_DEADLINE_BLOCKS, false, Default::default());
+	assert!(nodes[1].node.list_channels().is_empty());
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, node_id } => {
+			assert_eq!(node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(msg.channel_id, channel_id);
+			assert_eq!(msg.data, "Funding transaction failed to confirm in time");
+		},
+		_ => panic!("Unexpected event"),
+	}
+	check_closed_event!(nodes[1], 1, ClosureReason::FundingTimedOut);
+}
+
+#[test]
+fn test_unfunded_channel_timeout() {
+	// Test that a channel whose funding flow never completes is forgotten by both sides after
+	// UNFUNDED_CHANNEL_AGE_LIMIT_TICKS timer ticks.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id()));
+	// nodes[0] never hands us the funding transaction
+	let (temporary_channel_id, _, _) = create_funding_transaction(&nodes[0], 100000, 42);
+
+	for _ in 0..UNFUNDED_CHANNEL_AGE_LIMIT_TICKS - 1 {
+		nodes[0].node.timer_chan_freshness_every_min();
+		nodes[1].node.timer_chan_freshness_every_min();
+	}
+	assert_eq!(nodes[0].node.list_channels().len(), 1);
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+
+	for node in nodes.iter() {
+		node.node.timer_chan_freshness_every_min();
+		assert!(node.node.list_channels().is_empty());
+		let events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, .. } => {
+				assert_eq!(msg.channel_id, temporary_channel_id);
+				assert_eq!(msg.data, "Timed out waiting for the channel to be funded");
+			},
+			_ => panic!("Unexpected event"),
+		}
+		check_closed_event!(node, 1, ClosureReason::FundingTimedOut);
+	}
+}
+
+#[test]
+fn test_abandon_unfunded_channel() {
+	// Test that an outbound channel may be abandoned until we receive funding_signed, releasing
+	// its funding transaction and telling our counterparty.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id()));
+	let (temporary_channel_id, _, funding_output) = create_funding_transaction(&nodes[0], 100000, 42);
+	nodes[0].node.funding_transaction_generated(&temporary_channel_id, funding_output);
+	nodes[1].node.handle_funding_created(&nodes[0].node.get_our_node_id(), &get_event_msg!(nodes[0], MessageSendEvent::SendFundingCreated, nodes[1].node.get_our_node_id()));
+	check_added_monitors!(nodes[1], 1);
+	// The funding_signed never makes it to nodes[0]
+	get_event_msg!(nodes[1], MessageSendEvent::SendFundingSigned, nodes[0].node.get_our_node_id());
+	let channel_id = funding_output.to_channel_id();
+
+	// Only the funder may abandon the channel
+	assert!(nodes[1].node.abandon_unfunded_channel(&channel_id).is_err());
+
+	nodes[0].node.abandon_unfunded_channel(&channel_id).unwrap();
+	assert!(nodes[0].node.list_channels().is_empty());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::DiscardFunding { channel_id: ref discarded_channel_id, ref funding_txid, ref transaction } => {
+			assert_eq!(*discarded_channel_id, channel_id);
+			assert_eq!(*funding_txid, funding_output.txid);
+			assert!(transaction.is_none());
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::ChannelClosed { reason: ClosureReason::HolderForceClosed, user_id, .. } => assert_eq!(user_id, 42),
+		_ => panic!("Unexpected event"),
+	}
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, node_id } => {
+			assert_eq!(node_id, nodes[1].node.get_our_node_id());
+			assert_eq!(msg.channel_id, channel_id);
+			nodes[1].node.handle_error(&nodes[0].node.get_our_node_id(), msg);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Channel abandoned before funding".to_owned() });
+	assert!(nodes[1].node.list_channels().is_empty());
+
+	// Once funding_signed has been received the funding transaction may have been broadcast
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	assert!(nodes[0].node.abandon_unfunded_channel(&chan.2).is_err());
 }
 
 #[test]
@@ -8488,6 +8925,7 @@ fn test_htlc_no_detection() {
 	// this test before #653 fix.
         connect_block(&nodes[0], &Block { header, txdata: vec![local_txn[0].clone()] }, 200);
         check_closed_broadcast!(nodes[0], false);
+        check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
         check_added_monitors!(nodes[0], 1);
 
         let htlc_timeout = {
@@ -8546,6 +8984,7 @@ fn do_test_onchain_htlc_settlement_after_close(broadcast_alice: bool, go_onchain
 	if !broadcast_alice { force_closing_node = 1; } // Bob force-closes
 	nodes[force_closing_node].node.force_close_channel(&chan_ab.2);
 	check_closed_broadcast!(nodes[force_closing_node], false);
+	check_closed_event!(nodes[force_closing_node], 1, ClosureReason::HolderForceClosed);
 	check_added_monitors!(nodes[force_closing_node], 1);
 	if go_onchain_before_fulfill {
 		let txn_to_broadcast = match broadcast_alice {
@@ -8557,6 +8996,7 @@ fn do_test_onchain_htlc_settlement_after_close(broadcast_alice: bool, go_onchain
 		let mut bob_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
 		if broadcast_alice {
 			check_closed_broadcast!(nodes[1], false);
+			check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 			check_added_monitors!(nodes[1], 1);
 		}
 		assert_eq!(bob_txn.len(), 1);
@@ -8636,6 +9076,7 @@ fn do_test_onchain_htlc_settlement_after_close(broadcast_alice: bool, go_onchain
 		// If Bob was the one to force-close, he will have already passed these checks earlier.
 		if broadcast_alice {
 			check_closed_broadcast!(nodes[1], false);
+			check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
 			check_added_monitors!(nodes[1], 1);
 		}
 		let mut bob_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap();
@@ -8801,6 +9242,7 @@ fn test_duplicate_chan_id() {
 			_ => panic!("Unexpected event"),
 		}
 	}
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "ChannelMonitor storage failure".to_owned() });
 
 	// finally, finish creating the original channel and send a payment over it to make sure
 	// everything is functional.
@@ -8827,3 +9269,2551 @@ fn test_duplicate_chan_id() {
 	update_nodes_with_chan_announce(&nodes, 0, 1, &announcement, &as_update, &bs_update);
 	send_payment(&nodes[0], &[&nodes[1]], 8000000, 8_000_000);
 }
+
+#[test]
+fn test_spontaneous_payment() {
+	// Test that a keysend payment carries its preimage to the recipient, who gets it handed back
+	// in the PaymentReceived event and can claim the HTLC with it directly.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	for &provide_preimage in [true, false].iter() {
+		let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+		let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+		let expected_preimage = if provide_preimage { Some(PaymentPreimage([42; 32])) } else { None };
+		let (payment_hash, _) = nodes[0].node.send_spontaneous_payment(&route, expected_preimage).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		if let Some(preimage) = expected_preimage {
+			assert_eq!(payment_hash, PaymentHash(Sha256::hash(&preimage.0).into_inner()));
+		}
+
+		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		let payment_event = SendEvent::from_event(events.remove(0));
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+		expect_pending_htlcs_forwardable!(nodes[1]);
+
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		let payment_preimage = match events[0] {
+			Event::PaymentReceived { payment_hash: ref received_hash, ref payment_secret, ref payment_preimage, ref custom_tlvs, amt } => {
+				assert_eq!(*received_hash, payment_hash);
+				assert!(payment_secret.is_none());
+				assert!(custom_tlvs.is_empty());
+				assert_eq!(amt, 100000);
+				let preimage = payment_preimage.unwrap();
+				assert_eq!(PaymentHash(Sha256::hash(&preimage.0).into_inner()), payment_hash);
+				preimage
+			},
+			_ => panic!("Unexpected event"),
+		};
+		claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 100000);
+	}
+}
+
+#[test]
+fn test_spontaneous_payment_preimage_mismatch() {
+	// Test that a keysend payment whose preimage doesn't hash to the HTLC's payment_hash is
+	// rejected by the recipient rather than being reported as received.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let cur_height = nodes[0].node.latest_block_height.load(Ordering::Acquire) as u32 + 1;
+	nodes[0].node.send_payment_along_path(&route.paths[0], &payment_hash, &None, 100000, cur_height, PaymentId([42; 32]), &SecretKey::from_slice(&[42; 32]).unwrap(), &Some(PaymentPreimage([42; 32])), &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false, true);
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	expect_payment_failed!(nodes[0], payment_hash, true, 0x4000 | 22, &[0; 0][..]);
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::PaymentFailureNetworkUpdate { update: HTLCFailChannelUpdate::ChannelClosed { short_channel_id, is_permanent: true } } => {
+			assert_eq!(short_channel_id, route.paths[0][0].short_channel_id);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_payment_custom_tlvs() {
+	// Test that custom TLV records provided to send_payment reach the recipient's PaymentReceived
+	// event, and that records with unknown even types are rejected by the recipient.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let net_graph_msg_handler = &nodes[0].net_graph_msg_handler;
+	let route = get_route(&nodes[0].node.get_our_node_id(), &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+
+	// Reserved and duplicate types are refused outright
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	match nodes[0].node.send_payment(&route, payment_hash, &None, &[(65535, vec![])]) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert_eq!(err, "Custom TLV type 65535 is reserved"),
+		_ => panic!(),
+	}
+	match nodes[0].node.send_payment(&route, payment_hash, &None, &[(65537, vec![]), (65537, vec![42])]) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert_eq!(err, "Custom TLV type 65537 was provided more than once"),
+		_ => panic!(),
+	}
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.send_payment(&route, payment_hash, &None, &[(70001, vec![]), (65537, vec![1, 2, 3])]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentReceived { payment_hash: ref received_hash, ref custom_tlvs, amt, .. } => {
+			assert_eq!(*received_hash, payment_hash);
+			assert_eq!(*custom_tlvs, vec![(65537, vec![1, 2, 3]), (70001, vec![])]);
+			assert_eq!(amt, 100000);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 100000);
+
+	// An even type we don't understand must cause the HTLC to be failed back
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	nodes[0].node.send_payment(&route, payment_hash, &None, &[(65536, vec![])]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &updates.update_add_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false, true);
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false, true);
+	expect_payment_failed!(nodes[0], payment_hash, true, 0x4000 | 22, &[0; 0][..]);
+	let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+}
+
+#[test]
+fn test_anchor_outputs_channel() {
+	// Open a channel using option_anchors_zero_fee_htlc_tx and check the transactions built for it:
+	// the commitment transaction carries two anchors, the HTLC-Timeout transaction is zero-fee and
+	// signed by our counterparty with SIGHASH_SINGLE|SIGHASH_ANYONECANPAY, and claims of the 1
+	// OP_CSV-encumbered outputs on a counterparty commitment transaction are valid.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	assert!(nodes[0].node.provided_init_features().supports_anchors_zero_fee_htlc_tx());
+
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10_000_000, InitFeatures::known(), InitFeatures::known());
+	let (payment_preimage, _) = route_payment(&nodes[0], &[&nodes[1]], 3_000_000);
+
+	let node_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	assert_eq!(node_txn.len(), 2);
+	let commitment_tx = node_txn[0].clone();
+	check_spends!(commitment_tx, chan.3);
+	// to_local, to_remote, the HTLC and both anchors
+	assert_eq!(commitment_tx.output.len(), 5);
+	assert_eq!(commitment_tx.output.iter().filter(|o| o.value == chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI).count(), 2);
+	assert!(commitment_tx.output.iter().all(|o| o.script_pubkey.is_v0_p2wsh()));
+
+	let htlc_timeout_tx = &node_txn[1];
+	assert_eq!(htlc_timeout_tx.input[0].sequence, 1);
+	assert_eq!(htlc_timeout_tx.input[0].witness.last().unwrap().len(), OFFERED_HTLC_SCRIPT_WEIGHT + 3);
+	assert_eq!(htlc_timeout_tx.output[0].value, 3_000);
+	htlc_timeout_tx.verify(|outp| commitment_tx.output.get(outp.vout as usize).cloned()).unwrap();
+
+	// Claim the HTLC on nodes[0]'s commitment transaction with the preimage
+	assert!(nodes[1].node.claim_funds(payment_preimage, &None, 3_000_000));
+	check_added_monitors!(nodes[1], 1);
+	get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[1], &Block { header, txdata: vec![commitment_tx.clone()] }, 1);
+	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::CommitmentTxConfirmed);
+	check_added_monitors!(nodes[1], 1);
+	let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clone();
+	let claim_txn: Vec<_> = node_txn.iter().filter(|tx| tx.input[0].previous_output.txid == commitment_tx.txid()).collect();
+	assert_eq!(claim_txn.len(), 1);
+	assert_eq!(claim_txn[0].input[0].sequence, 1);
+	check_spends!(claim_txn[0], commitment_tx);
+
+	// Our to_remote output is spendable once the commitment transaction is deep enough
+	connect_blocks(&nodes[1], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
+	let events = nodes[1].chain_monitor.chain_monitor.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::SpendableOutputs { ref outputs } => {
+			assert_eq!(outputs.len(), 1);
+			match outputs[0] {
+				SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref output, ref key_derivation_params, .. } => {
+					let payment_point = nodes[1].keys_manager.derive_channel_keys(100000, key_derivation_params.0, key_derivation_params.1).pubkeys().payment_point;
+					let to_remote_script = chan_utils::get_to_countersignatory_with_anchors_redeemscript(&payment_point);
+					assert_eq!(output.script_pubkey, to_remote_script.to_v0_p2wsh());
+
+					// The output is spent with an nSequence of 1 and the witness script
+					let change_script = Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script();
+					let spend_tx = nodes[1].keys_manager.spend_spendable_outputs(&[&outputs[0]], Vec::new(), change_script, 253).unwrap();
+					assert_eq!(spend_tx.input.len(), 1);
+					assert_eq!(spend_tx.input[0].sequence, 1);
+					assert_eq!(spend_tx.input[0].witness.len(), 2);
+					assert_eq!(spend_tx.input[0].witness[1], to_remote_script.into_bytes());
+					assert_eq!(spend_tx.output.len(), 1);
+					check_spends!(spend_tx, commitment_tx);
+					// The fee covers the transaction's weight at the requested feerate
+					assert!((output.value - spend_tx.output[0].value) * 1000 >= spend_tx.get_weight() as u64 * 253);
+				},
+				_ => panic!("Unexpected descriptor"),
+			}
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_anchor_outputs_requires_both_peers() {
+	// A node which opted into option_anchors_zero_fee_htlc_tx still opens legacy channels with
+	// peers which don't advertise it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	assert!(!nodes[1].node.provided_init_features().supports_anchors_zero_fee_htlc_tx());
+
+	let their_features = nodes[1].node.provided_init_features();
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10_000_000, InitFeatures::known(), their_features);
+	let node_txn = get_local_commitment_txn!(nodes[0], chan.2);
+	assert_eq!(node_txn[0].output.len(), 2);
+	assert!(node_txn[0].output.iter().all(|o| o.value != chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI));
+}
+
+#[test]
+fn test_anchor_outputs_cpfp_bump() {
+	// Force-close an option_anchors_zero_fee_htlc_tx channel while feerates spike and check that we
+	// bump our commitment transaction with a child spending our anchor output and a wallet UTXO,
+	// replacing it on height timer expiration, until the commitment transaction confirms.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10_000_000, InitFeatures::known(), InitFeatures::known());
+	route_payment(&nodes[0], &[&nodes[1]], 3_000_000);
+	let wallet_utxo = chanmon_cfgs[0].utxo_source.add_utxo(100_000);
+
+	nodes[0].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+	let commitment_tx = {
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		// The zero-fee HTLC-Timeout is only broadcast with wallet inputs, once it can confirm
+		assert_eq!(node_txn.len(), 1);
+		check_spends!(node_txn[0], chan.3);
+		let commitment_tx = node_txn[0].clone();
+		node_txn.clear();
+		commitment_tx
+	};
+	let anchor_vout = commitment_tx.output.iter().position(|o| o.value == chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI &&
+		o.script_pubkey == chan_utils::get_anchor_redeemscript(&nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap()
+			.get(&OutPoint { txid: chan.3.txid(), index: 0 }).unwrap().onchain_tx_handler.channel_transaction_parameters.holder_pubkeys.funding_pubkey).to_v0_p2wsh()).unwrap();
+
+	let high_fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(2000) };
+	macro_rules! bump_at_height {
+		($height: expr) => {
+			nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap().get_mut(&OutPoint { txid: chan.3.txid(), index: 0 }).unwrap()
+				.bump_holder_commitment_with_cpfp($height, &chanmon_cfgs[0].utxo_source, nodes[0].tx_broadcaster, &high_fee_estimator, nodes[0].logger);
+		}
+	}
+	macro_rules! check_child {
+		() => { {
+			let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+			assert_eq!(node_txn.len(), 2);
+			assert_eq!(node_txn[0], commitment_tx);
+			let child_tx = node_txn[1].clone();
+			node_txn.clear();
+			assert_eq!(child_tx.input.len(), 2);
+			assert_eq!(child_tx.input[0].previous_output, BitcoinOutPoint { txid: commitment_tx.txid(), vout: anchor_vout as u32 });
+			assert_eq!(child_tx.input[1].previous_output, wallet_utxo.outpoint());
+			assert_eq!(child_tx.output.len(), 1);
+			child_tx.verify(|outp| if outp.txid == commitment_tx.txid() { commitment_tx.output.get(outp.vout as usize).cloned() } else { Some(wallet_utxo.output().clone()) }).unwrap();
+			let child_fee = chan_utils::ANCHOR_OUTPUT_VALUE_SATOSHI + wallet_utxo.output().value - child_tx.output[0].value;
+			let commitment_fee = 253 * commitment_tx.get_weight() as u64 / 1000;
+			assert!((commitment_fee + child_fee) * 1000 / (commitment_tx.get_weight() + child_tx.get_weight()) as u64 >= 2000);
+			child_fee
+		} }
+	}
+
+	bump_at_height!(1);
+	let first_child_fee = check_child!();
+
+	// Nothing happens until the height timer expires, then the child is replaced at a higher feerate
+	bump_at_height!(1);
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	let mut height = 2;
+	while nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty() {
+		assert!(height <= 16);
+		bump_at_height!(height);
+		height += 1;
+	}
+	let second_child_fee = check_child!();
+	assert!(second_child_fee > first_child_fee);
+
+	// Once the commitment transaction confirms, we stop bumping it
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header, txdata: vec![commitment_tx.clone()] }, height);
+	nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+	bump_at_height!(height + 100);
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_anchor_outputs_htlc_tx_bump() {
+	// Force-close an option_anchors_zero_fee_htlc_tx channel with an outbound HTLC and check that,
+	// once it expires, we broadcast the zero-fee HTLC-Timeout transaction with a wallet UTXO and a
+	// change output paying its fee, replacing it on height timer expiration until it confirms.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut anchors_config = UserConfig::default();
+	anchors_config.channel_options.announced_channel = true;
+	anchors_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	anchors_config.own_channel_config.negotiate_anchor_outputs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(anchors_config), Some(anchors_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100000, 10_000_000, InitFeatures::known(), InitFeatures::known());
+	let (_, payment_hash) = route_payment(&nodes[0], &[&nodes[1]], 3_000_000);
+	let wallet_utxo = chanmon_cfgs[0].utxo_source.add_utxo(100_000);
+	let cltv_expiry = get_local_commitment_txn!(nodes[0], chan.2)[1].lock_time;
+
+	nodes[0].node.force_close_channel(&chan.2);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+	let commitment_tx = {
+		let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+		assert_eq!(node_txn.len(), 1);
+		check_spends!(node_txn[0], chan.3);
+		node_txn.remove(0)
+	};
+	let htlc_vout = commitment_tx.output.iter().position(|o| o.value == 3_000).unwrap();
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header, txdata: vec![commitment_tx.clone()] }, 1);
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+
+	// Nothing is broadcast until the HTLC expires
+	let mut prev_blockhash = connect_blocks(&nodes[0], cltv_expiry - 2, 1, true, header.block_hash());
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	prev_blockhash = connect_blocks(&nodes[0], 1, cltv_expiry - 1, true, prev_blockhash);
+	let mut height = cltv_expiry;
+	macro_rules! check_htlc_tx {
+		() => { {
+			let mut node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap();
+			assert_eq!(node_txn.len(), 1);
+			let htlc_tx = node_txn.remove(0);
+			assert_eq!(htlc_tx.lock_time, cltv_expiry);
+			assert_eq!(htlc_tx.input.len(), 2);
+			assert_eq!(htlc_tx.input[0].previous_output, BitcoinOutPoint { txid: commitment_tx.txid(), vout: htlc_vout as u32 });
+			assert_eq!(htlc_tx.input[0].sequence, 1);
+			assert_eq!(htlc_tx.input[1].previous_output, wallet_utxo.outpoint());
+			assert_eq!(htlc_tx.output.len(), 2);
+			assert_eq!(htlc_tx.output[0].value, 3_000);
+			assert_eq!(htlc_tx.output[1].script_pubkey, chanmon_cfgs[0].utxo_source.wallet_script());
+			htlc_tx.verify(|outp| if outp.txid == commitment_tx.txid() { commitment_tx.output.get(outp.vout as usize).cloned() } else { Some(wallet_utxo.output().clone()) }).unwrap();
+			let htlc_fee = wallet_utxo.output().value - htlc_tx.output[1].value;
+			assert!(htlc_fee * 1000 / htlc_tx.get_weight() as u64 >= 253);
+			(htlc_tx, htlc_fee)
+		} }
+	}
+	let (_, first_htlc_fee) = check_htlc_tx!();
+
+	// The HTLC has expired, so the transaction is replaced at a higher feerate on the next block
+	prev_blockhash = connect_blocks(&nodes[0], 1, height, true, prev_blockhash);
+	height += 1;
+	let (htlc_tx, second_htlc_fee) = check_htlc_tx!();
+	assert!(second_htlc_fee > first_htlc_fee);
+
+	// Once the HTLC transaction confirms, we stop bumping it and fail the HTLC back
+	let header = BlockHeader { version: 0x20000000, prev_blockhash, merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header, txdata: vec![htlc_tx] }, height + 1);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1, height + 1, true, header.block_hash());
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().iter().all(|tx| tx.input[0].previous_output.txid != commitment_tx.txid()));
+	expect_payment_failed!(nodes[0], payment_hash, true);
+}
+
+#[test]
+fn test_dual_funded_channel() {
+	// Both peers contribute to the funding transaction of a dual-funded channel, so that both can
+	// send payments as soon as it's open.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut initiator_config = UserConfig::default();
+	initiator_config.channel_options.announced_channel = true;
+	initiator_config.own_channel_config.negotiate_dual_fund = true;
+	let mut acceptor_config = initiator_config;
+	acceptor_config.own_channel_config.dual_funding_contribution_satoshis = 50_000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(initiator_config), Some(acceptor_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// We only open dual-funded channels to peers we know support them
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let initiator_input = nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+	let acceptor_input = nodes[1].keys_manager.utxo_source.add_utxo(80_000);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	assert_eq!(open_channel.funding_satoshis, 100_000);
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannelV2, nodes[0].node.get_our_node_id());
+	assert_eq!(accept_channel.funding_satoshis, 50_000);
+	nodes[0].node.handle_accept_channel_v2(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+
+	// Relay the funding transaction construction messages until both sides are done, at which
+	// point both send commitment_signed for their counterparty's initial commitment transaction.
+	let mut commitment_signed = [None, None];
+	let mut sender = 0;
+	loop {
+		let events = nodes[sender].node.get_and_clear_pending_msg_events();
+		if events.is_empty() { break; }
+		let receiver = 1 - sender;
+		let sender_id = nodes[sender].node.get_our_node_id();
+		for event in events {
+			match event {
+				MessageSendEvent::SendTxAddInput { ref msg, .. } => nodes[receiver].node.handle_tx_add_input(&sender_id, msg),
+				MessageSendEvent::SendTxAddOutput { ref msg, .. } => nodes[receiver].node.handle_tx_add_output(&sender_id, msg),
+				MessageSendEvent::SendTxComplete { ref msg, .. } => nodes[receiver].node.handle_tx_complete(&sender_id, msg),
+				MessageSendEvent::UpdateHTLCs { updates, .. } => {
+					assert!(updates.update_add_htlcs.is_empty());
+					assert!(updates.commitment_signed.htlc_signatures.is_empty());
+					commitment_signed[sender] = Some(updates.commitment_signed);
+				},
+				_ => panic!("Unexpected event"),
+			}
+		}
+		sender = receiver;
+	}
+	let initiator_commitment_signed = commitment_signed[0].take().unwrap();
+	let acceptor_commitment_signed = commitment_signed[1].take().unwrap();
+	assert_eq!(initiator_commitment_signed.channel_id, acceptor_commitment_signed.channel_id);
+
+	// The acceptor put in less, so it signs the funding transaction first, once it has a
+	// ChannelMonitor for its initial commitment transaction.
+	nodes[1].node.handle_commitment_signed(&nodes[0].node.get_our_node_id(), &initiator_commitment_signed);
+	check_added_monitors!(nodes[1], 1);
+	let acceptor_tx_signatures = get_event_msg!(nodes[1], MessageSendEvent::SendTxSignatures, nodes[0].node.get_our_node_id());
+	assert_eq!(acceptor_tx_signatures.witnesses.len(), 1);
+
+	nodes[0].node.handle_commitment_signed(&nodes[1].node.get_our_node_id(), &acceptor_commitment_signed);
+	check_added_monitors!(nodes[0], 1);
+	// We don't ask the user to broadcast the funding transaction of dual-funded channels
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[0].node.handle_tx_signatures(&nodes[1].node.get_our_node_id(), &acceptor_tx_signatures);
+	let initiator_tx_signatures = get_event_msg!(nodes[0], MessageSendEvent::SendTxSignatures, nodes[1].node.get_our_node_id());
+	let funding_tx = {
+		let txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(txn.len(), 1);
+		txn[0].clone()
+	};
+	nodes[1].node.handle_tx_signatures(&nodes[0].node.get_our_node_id(), &initiator_tx_signatures);
+	assert_eq!(*nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap(), vec![funding_tx.clone()]);
+	nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+	// The funding output, and each side's change
+	assert_eq!(funding_tx.input.len(), 2);
+	assert_eq!(funding_tx.output.len(), 3);
+	assert!(funding_tx.output.iter().any(|output| output.value == 150_000));
+	check_spends!(funding_tx, initiator_input.prev_tx, acceptor_input.prev_tx);
+
+	let (funding_locked, _) = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &funding_tx);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_locked);
+	update_nodes_with_chan_announce(&nodes, 0, 1, &announcement, &as_update, &bs_update);
+
+	let channel = &nodes[1].node.list_channels()[0];
+	assert_eq!(channel.channel_value_satoshis, 150_000);
+	assert_eq!(channel.user_id, 0);
+	assert_eq!(nodes[0].node.list_channels()[0].user_id, 42);
+
+	send_payment(&nodes[1], &[&nodes[0]], 10_000_000, 10_000_000);
+	send_payment(&nodes[0], &[&nodes[1]], 12_000_000, 12_000_000);
+}
+
+#[test]
+fn test_dual_funded_channel_insufficient_wallet_funds() {
+	// If our funding wallet can't cover the contribution we're configured to make to dual-funded
+	// channels, we reject the channel rather than silently accepting it without funding it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut initiator_config = UserConfig::default();
+	initiator_config.own_channel_config.negotiate_dual_fund = true;
+	let mut acceptor_config = initiator_config;
+	acceptor_config.own_channel_config.dual_funding_contribution_satoshis = 50_000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(initiator_config), Some(acceptor_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+	nodes[1].keys_manager.utxo_source.add_utxo(20_000);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	if let MessageSendEvent::HandleError { ref action, .. } = msg_events[0] {
+		match action {
+			&ErrorAction::SendErrorMessage { ref msg } => {
+				assert_eq!(msg.channel_id, open_channel.temporary_channel_id);
+				assert!(msg.data.starts_with("Failed to contribute 50000 sat to the channel: Funding wallet provided 20000 sat"));
+			},
+			_ => panic!("Unexpected event"),
+		}
+	} else { panic!("Unexpected event"); }
+	assert!(nodes[1].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_dual_funded_channel_contribution_above_max_funding() {
+	// Like when our funding wallet is short, we reject a dual-funded channel our contribution
+	// would take above the largest channel we accept, rather than accepting it without funding it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut initiator_config = UserConfig::default();
+	initiator_config.own_channel_config.negotiate_dual_fund = true;
+	let mut acceptor_config = initiator_config;
+	acceptor_config.own_channel_config.dual_funding_contribution_satoshis = 50_000;
+	acceptor_config.peer_channel_config_limits.max_funding_satoshis = 120_000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(initiator_config), Some(acceptor_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+	nodes[1].keys_manager.utxo_source.add_utxo(80_000);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	if let MessageSendEvent::HandleError { ref action, .. } = msg_events[0] {
+		match action {
+			&ErrorAction::SendErrorMessage { ref msg } => {
+				assert_eq!(msg.channel_id, open_channel.temporary_channel_id);
+				assert_eq!(msg.data, "Failed to contribute 50000 sat to the channel: the channel value would exceed 120000 sat");
+			},
+			_ => panic!("Unexpected event"),
+		}
+	} else { panic!("Unexpected event"); }
+	assert!(nodes[1].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_dual_funded_channel_rejects_non_p2wpkh_input() {
+	// We can't tell how much weight the witness of an arbitrary input adds to the funding
+	// transaction, and thus whether our counterparty pays its share of the fee, so we only accept
+	// P2WPKH inputs.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = UserConfig::default();
+	config.own_channel_config.negotiate_dual_fund = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannelV2, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_accept_channel_v2(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+	let mut tx_add_input = get_event_msg!(nodes[0], MessageSendEvent::SendTxAddInput, nodes[1].node.get_our_node_id());
+
+	let prevtx_out = tx_add_input.prevtx_out as usize;
+	tx_add_input.prevtx.output[prevtx_out].script_pubkey = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 32]).into_script();
+	nodes[1].node.handle_tx_add_input(&nodes[0].node.get_our_node_id(), &tx_add_input);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, .. } => {
+			assert_eq!(msg.data, "Peer added an input spending a missing, non-P2WPKH or invalid output");
+		},
+		_ => panic!("Unexpected event"),
+	}
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err: "Peer added an input spending a missing, non-P2WPKH or invalid output".to_string() });
+	assert!(nodes[1].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_dual_funded_channel_tx_remove_input() {
+	// Our counterparty may remove inputs it added to the funding transaction, but only those.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = UserConfig::default();
+	config.own_channel_config.negotiate_dual_fund = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannelV2, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_accept_channel_v2(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+	let tx_add_input = get_event_msg!(nodes[0], MessageSendEvent::SendTxAddInput, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_tx_add_input(&nodes[0].node.get_our_node_id(), &tx_add_input);
+	get_event_msg!(nodes[1], MessageSendEvent::SendTxComplete, nodes[0].node.get_our_node_id());
+
+	let tx_remove_input = msgs::TxRemoveInput { channel_id: tx_add_input.channel_id, serial_id: tx_add_input.serial_id };
+	nodes[1].node.handle_tx_remove_input(&nodes[0].node.get_our_node_id(), &tx_remove_input);
+	get_event_msg!(nodes[1], MessageSendEvent::SendTxComplete, nodes[0].node.get_our_node_id());
+
+	// The input is gone, so removing it again fails the channel
+	nodes[1].node.handle_tx_remove_input(&nodes[0].node.get_our_node_id(), &tx_remove_input);
+	let err = format!("Peer removed input {} which it didn't add", tx_add_input.serial_id);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, .. } => assert_eq!(msg.data, err),
+		_ => panic!("Unexpected event"),
+	}
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError { err });
+	assert!(nodes[1].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_dual_funded_channel_requires_opt_in() {
+	// option_dual_fund is only advertised, and dual-funded channels only opened or accepted, if we
+	// opted into it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut dual_fund_config = UserConfig::default();
+	dual_fund_config.own_channel_config.negotiate_dual_fund = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(dual_fund_config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	assert!(nodes[0].node.provided_init_features().supports_dual_fund());
+	assert!(!nodes[1].node.provided_init_features().supports_dual_fund());
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].keys_manager.utxo_source.add_utxo(150_000);
+	nodes[1].keys_manager.utxo_source.add_utxo(150_000);
+
+	match nodes[1].node.create_dual_funded_channel(nodes[0].node.get_our_node_id(), 100_000, 42, None) {
+		Err(APIError::APIMisuseError { ref err }) => assert_eq!(err, "We have not enabled option_dual_fund"),
+		_ => panic!("Unexpected result"),
+	}
+
+	nodes[0].node.create_dual_funded_channel(nodes[1].node.get_our_node_id(), 100_000, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannelV2, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel_v2(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	if let MessageSendEvent::HandleError { ref action, .. } = msg_events[0] {
+		match action {
+			&ErrorAction::SendErrorMessage { ref msg } => {
+				assert_eq!(msg.channel_id, open_channel.temporary_channel_id);
+				assert_eq!(msg.data, "We don't support option_dual_fund");
+			},
+			_ => panic!("Unexpected event"),
+		}
+	} else { panic!("Unexpected event"); }
+	assert!(nodes[1].node.list_channels().is_empty());
+}
+
+fn splicing_config() -> UserConfig {
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.peer_channel_config_limits.force_announced_channel_preference = false;
+	config.own_channel_config.negotiate_splicing = true;
+	config
+}
+
+fn relay_splice_negotiation<'a, 'b, 'c>(nodes: &Vec<Node<'a, 'b, 'c>>) -> Transaction {
+	// Relay the splice transaction construction, followed by the commitment_signed for the new
+	// funding output and the splice transaction signatures.
+	let mut sender = 0;
+	let mut idle_nodes = 0;
+	while idle_nodes < 2 {
+		let events = nodes[sender].node.get_and_clear_pending_msg_events();
+		idle_nodes = if events.is_empty() { idle_nodes + 1 } else { 0 };
+		let receiver = 1 - sender;
+		let sender_id = nodes[sender].node.get_our_node_id();
+		for event in events {
+			match event {
+				MessageSendEvent::SendTxAddInput { ref msg, .. } => nodes[receiver].node.handle_tx_add_input(&sender_id, msg),
+				MessageSendEvent::SendTxAddOutput { ref msg, .. } => nodes[receiver].node.handle_tx_add_output(&sender_id, msg),
+				MessageSendEvent::SendTxComplete { ref msg, .. } => nodes[receiver].node.handle_tx_complete(&sender_id, msg),
+				MessageSendEvent::UpdateHTLCs { ref updates, .. } => {
+					assert!(updates.update_add_htlcs.is_empty());
+					nodes[receiver].node.handle_commitment_signed(&sender_id, &updates.commitment_signed);
+					check_added_monitors!(nodes[receiver], 1);
+				},
+				MessageSendEvent::SendTxSignatures { ref msg, .. } => nodes[receiver].node.handle_tx_signatures(&sender_id, msg),
+				_ => panic!("Unexpected event"),
+			}
+		}
+		sender = receiver;
+	}
+
+	// Both sides broadcast the splice transaction once it's fully signed
+	let splice_tx = {
+		let txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(txn.len(), 1);
+		txn[0].clone()
+	};
+	assert_eq!(*nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap(), vec![splice_tx.clone()]);
+	nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+	splice_tx
+}
+
+fn splice_out_between_nodes<'a, 'b, 'c>(nodes: &Vec<Node<'a, 'b, 'c>>, channel_id: &[u8; 32]) -> (msgs::Splice, Transaction) {
+	// Splice 20_000 sat out of the channel from node 0, returning its splice message and the
+	// signed splice transaction.
+	let splice_out_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script();
+	nodes[0].node.splice_out(channel_id, 20_000, splice_out_script).unwrap();
+	let stfu = get_event_msg!(nodes[0], MessageSendEvent::SendStfu, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_stfu(&nodes[0].node.get_our_node_id(), &stfu);
+	let stfu = get_event_msg!(nodes[1], MessageSendEvent::SendStfu, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_stfu(&nodes[1].node.get_our_node_id(), &stfu);
+	let splice = get_event_msg!(nodes[0], MessageSendEvent::SendSplice, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_splice(&nodes[0].node.get_our_node_id(), &splice);
+	let splice_ack = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceAck, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_splice_ack(&nodes[1].node.get_our_node_id(), &splice_ack);
+	let splice_tx = relay_splice_negotiation(nodes);
+	(splice, splice_tx)
+}
+
+#[test]
+fn test_splice_in() {
+	// Splice funds from our wallet into an existing channel, checking that HTLC updates are held
+	// back until the splice completes and that the channel moves to the splice transaction's
+	// funding output once it confirmed.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// We only splice channels with peers we know support it
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (_, _, channel_id, funding_tx) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let prev_short_channel_id = nodes[0].node.list_channels()[0].short_channel_id.unwrap();
+	let wallet_input = nodes[0].keys_manager.utxo_source.add_utxo(50_000);
+
+	nodes[0].node.splice_in(&channel_id, 40_000).unwrap();
+	let stfu = get_event_msg!(nodes[0], MessageSendEvent::SendStfu, nodes[1].node.get_our_node_id());
+	assert_eq!(stfu.initiator, 1);
+	match nodes[0].node.splice_in(&channel_id, 40_000) {
+		Err(APIError::ChannelUnavailable { err }) => assert_eq!(err, "A splice is already in progress on this channel"),
+		_ => panic!("Unexpected result"),
+	}
+
+	// A payment sent while the channel is quiescent stays in the holding cell
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1_000_000, TEST_FINAL_CLTV, nodes[0].logger, &scorer).unwrap();
+	nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 0);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[1].node.handle_stfu(&nodes[0].node.get_our_node_id(), &stfu);
+	let stfu = get_event_msg!(nodes[1], MessageSendEvent::SendStfu, nodes[0].node.get_our_node_id());
+	assert_eq!(stfu.initiator, 0);
+	nodes[0].node.handle_stfu(&nodes[1].node.get_our_node_id(), &stfu);
+	let splice = get_event_msg!(nodes[0], MessageSendEvent::SendSplice, nodes[1].node.get_our_node_id());
+	assert_eq!(splice.relative_satoshis, 40_000);
+	nodes[1].node.handle_splice(&nodes[0].node.get_our_node_id(), &splice);
+	let splice_ack = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceAck, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_splice_ack(&nodes[1].node.get_our_node_id(), &splice_ack);
+
+	let splice_tx = relay_splice_negotiation(&nodes);
+	assert!(splice_tx.output.iter().any(|output| output.value == 140_000));
+	check_spends!(splice_tx, funding_tx, wallet_input.prev_tx);
+
+	// Until the splice transaction confirms, the channel keeps its current funding output
+	assert_eq!(nodes[0].node.list_channels()[0].channel_value_satoshis, 100_000);
+	assert_eq!(nodes[0].node.list_channels()[0].short_channel_id, Some(prev_short_channel_id));
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx.clone()] };
+	connect_block(&nodes[0], &block, CHAN_CONFIRM_DEPTH + 1);
+	connect_blocks(&nodes[0], 5, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	let as_splice_locked = get_event_msg!(nodes[0], MessageSendEvent::SendSpliceLocked, nodes[1].node.get_our_node_id());
+	assert_eq!(as_splice_locked.splice_txid, splice_tx.txid());
+	nodes[1].node.handle_splice_locked(&nodes[0].node.get_our_node_id(), &as_splice_locked);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	connect_block(&nodes[1], &block, CHAN_CONFIRM_DEPTH + 1);
+	connect_blocks(&nodes[1], 5, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		MessageSendEvent::SendSpliceLocked { ref msg, .. } => nodes[0].node.handle_splice_locked(&nodes[1].node.get_our_node_id(), msg),
+		_ => panic!("Unexpected event"),
+	}
+	let bs_announcement_sigs = match events[1] {
+		MessageSendEvent::SendAnnouncementSignatures { ref msg, .. } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+
+	// Once both sides sent splice_locked, node 0 announces the channel under its new
+	// short_channel_id and sends the held back payment
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	let as_announcement_sigs = match events[0] {
+		MessageSendEvent::SendAnnouncementSignatures { ref msg, .. } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	assert_ne!(as_announcement_sigs.short_channel_id, prev_short_channel_id);
+	assert_eq!(as_announcement_sigs.short_channel_id, bs_announcement_sigs.short_channel_id);
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_event(events[1].clone());
+	nodes[0].node.handle_announcement_signatures(&nodes[1].node.get_our_node_id(), &bs_announcement_sigs);
+	nodes[1].node.handle_announcement_signatures(&nodes[0].node.get_our_node_id(), &as_announcement_sigs);
+	for node in nodes.iter() {
+		let events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::BroadcastChannelAnnouncement { ref msg, .. } => assert_eq!(msg.contents.short_channel_id, as_announcement_sigs.short_channel_id),
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	for node in nodes.iter() {
+		let channel = &node.node.list_channels()[0];
+		assert_eq!(channel.channel_value_satoshis, 140_000);
+		assert_eq!(channel.short_channel_id, Some(as_announcement_sigs.short_channel_id));
+		// The ChannelMonitor watches the splice transaction's funding output
+		let monitors = node.chain_monitor.chain_monitor.monitors.lock().unwrap();
+		let monitor = monitors.get(&OutPoint { txid: funding_tx.txid(), index: 0 }).unwrap();
+		assert!(monitor.get_outputs_to_watch().contains_key(&splice_tx.txid()));
+	}
+
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	expect_payment_received!(nodes[1], payment_hash, 1_000_000);
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 1_000_000);
+
+	// The spliced in funds are available to send payments
+	let first_hops = nodes[0].node.list_usable_channels();
+	assert!(first_hops[0].outbound_capacity_msat > 100_000_000);
+	let scorer = Scorer::with_fixed_penalty(0);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&first_hops.iter().collect::<Vec<_>>()), &Vec::new(), 5_000_000, TEST_FINAL_CLTV, nodes[0].logger, &scorer).unwrap();
+	let payment_preimage = send_along_route(&nodes[0], route, &[&nodes[1]], 5_000_000).0;
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 5_000_000);
+}
+
+#[test]
+fn test_splicing_requires_opt_in() {
+	// Splicing is only advertised, and splices only started or accepted, if we opted into it.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	assert!(nodes[0].node.provided_init_features().supports_splicing());
+	assert!(!nodes[1].node.provided_init_features().supports_splicing());
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	let (_, _, channel_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	nodes[0].keys_manager.utxo_source.add_utxo(50_000);
+	nodes[1].keys_manager.utxo_source.add_utxo(50_000);
+
+	match nodes[1].node.splice_in(&channel_id, 40_000) {
+		Err(APIError::APIMisuseError { ref err }) => assert_eq!(err, "We have not enabled splicing"),
+		_ => panic!("Unexpected result"),
+	}
+
+	nodes[0].node.splice_in(&channel_id, 40_000).unwrap();
+	let stfu = get_event_msg!(nodes[0], MessageSendEvent::SendStfu, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_stfu(&nodes[0].node.get_our_node_id(), &stfu);
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::DisconnectPeerWithWarning { ref msg }, .. } => {
+			assert_eq!(msg.channel_id, channel_id);
+			assert_eq!(msg.data, "We don't support splicing");
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert_eq!(nodes[1].node.list_channels().len(), 1);
+}
+
+#[test]
+fn test_splice_out() {
+	// Splice funds out of a channel to an on-chain address, paying the splice transaction fee
+	// from our channel balance. Our counterparty initiates quiescence concurrently, but as the
+	// channel funder we get to splice first.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (_, _, channel_id, funding_tx) = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 50_000_000, InitFeatures::known(), InitFeatures::known());
+	let splice_out_script = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script();
+
+	nodes[0].node.splice_out(&channel_id, 20_000, splice_out_script.clone()).unwrap();
+	nodes[1].node.splice_out(&channel_id, 10_000, splice_out_script.clone()).unwrap();
+	let as_stfu = get_event_msg!(nodes[0], MessageSendEvent::SendStfu, nodes[1].node.get_our_node_id());
+	let bs_stfu = get_event_msg!(nodes[1], MessageSendEvent::SendStfu, nodes[0].node.get_our_node_id());
+	assert_eq!(as_stfu.initiator, 1);
+	assert_eq!(bs_stfu.initiator, 1);
+	nodes[1].node.handle_stfu(&nodes[0].node.get_our_node_id(), &as_stfu);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	nodes[0].node.handle_stfu(&nodes[1].node.get_our_node_id(), &bs_stfu);
+	let splice = get_event_msg!(nodes[0], MessageSendEvent::SendSplice, nodes[1].node.get_our_node_id());
+	assert!(splice.relative_satoshis < -20_000);
+	nodes[1].node.handle_splice(&nodes[0].node.get_our_node_id(), &splice);
+	let splice_ack = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceAck, nodes[0].node.get_our_node_id());
+	assert_eq!(splice_ack.relative_satoshis, 0);
+	nodes[0].node.handle_splice_ack(&nodes[1].node.get_our_node_id(), &splice_ack);
+
+	let splice_tx = relay_splice_negotiation(&nodes);
+	check_spends!(splice_tx, funding_tx);
+	assert!(splice_tx.output.iter().any(|output| output.script_pubkey == splice_out_script && output.value == 20_000));
+	let new_value_satoshis = (100_000 + splice.relative_satoshis) as u64;
+	assert!(splice_tx.output.iter().any(|output| output.value == new_value_satoshis));
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx.clone()] };
+	for node in nodes.iter() {
+		connect_block(node, &block, CHAN_CONFIRM_DEPTH + 1);
+		connect_blocks(node, 5, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	}
+	let as_splice_locked = get_event_msg!(nodes[0], MessageSendEvent::SendSpliceLocked, nodes[1].node.get_our_node_id());
+	let bs_splice_locked = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceLocked, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_splice_locked(&nodes[1].node.get_our_node_id(), &bs_splice_locked);
+	nodes[1].node.handle_splice_locked(&nodes[0].node.get_our_node_id(), &as_splice_locked);
+	get_event_msg!(nodes[0], MessageSendEvent::SendAnnouncementSignatures, nodes[1].node.get_our_node_id());
+	assert_eq!(nodes[0].node.list_channels()[0].channel_value_satoshis, new_value_satoshis);
+	assert_eq!(nodes[1].node.list_channels()[0].channel_value_satoshis, new_value_satoshis);
+
+	// Once the first splice completed, our counterparty starts its own
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		MessageSendEvent::SendAnnouncementSignatures { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		MessageSendEvent::SendStfu { ref msg, .. } => assert_eq!(msg.initiator, 1),
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_update_during_splice_warns_peer() {
+	// A peer that sends an update while a splice is pending gets a warning and is disconnected,
+	// without the channel being closed. The update is then delivered once the splice completes.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (_, _, channel_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 1_000_000).0;
+	let (splice, splice_tx) = splice_out_between_nodes(&nodes, &channel_id);
+
+	// Node 1 holds its fulfill back until the splice completes, but a peer may not do so
+	assert!(nodes[1].node.claim_funds(payment_preimage, &None, 1_000_000));
+	check_added_monitors!(nodes[1], 1);
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	let update_fulfill = msgs::UpdateFulfillHTLC { channel_id, htlc_id: 0, payment_preimage };
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &update_fulfill);
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::DisconnectPeerWithWarning { ref msg }, .. } => {
+			assert_eq!(msg.channel_id, channel_id);
+			assert_eq!(msg.data, "Peer sent update_fulfill_htlc while the channel was quiescent");
+		},
+		_ => panic!("Unexpected event"),
+	}
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	assert_eq!(nodes[0].node.list_channels().len(), 1);
+
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
+	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+
+	// Once the splice completed, node 1 sends its fulfill
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx] };
+	for node in nodes.iter() {
+		connect_block(node, &block, CHAN_CONFIRM_DEPTH + 1);
+		connect_blocks(node, 5, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	}
+	let as_splice_locked = get_event_msg!(nodes[0], MessageSendEvent::SendSpliceLocked, nodes[1].node.get_our_node_id());
+	let bs_splice_locked = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceLocked, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_splice_locked(&nodes[1].node.get_our_node_id(), &bs_splice_locked);
+	nodes[1].node.handle_splice_locked(&nodes[0].node.get_our_node_id(), &as_splice_locked);
+	get_event_msg!(nodes[0], MessageSendEvent::SendAnnouncementSignatures, nodes[1].node.get_our_node_id());
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		MessageSendEvent::SendAnnouncementSignatures { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	check_added_monitors!(nodes[1], 1);
+	let updates = match events[1] {
+		MessageSendEvent::UpdateHTLCs { ref updates, .. } => updates.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	expect_payment_sent!(nodes[0], payment_preimage);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	assert_eq!(nodes[0].node.list_channels()[0].channel_value_satoshis, (100_000 + splice.relative_satoshis) as u64);
+}
+
+#[test]
+fn test_claim_forwarded_htlc_after_splice() {
+	// An HTLC we forwarded before the channel it came in over was spliced is known by that
+	// channel's previous short_channel_id, which we need to keep recognizing to claim the HTLC
+	// once the splice completed.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(splicing_config()), Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (_, _, channel_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let prev_short_channel_id = nodes[0].node.list_channels()[0].short_channel_id.unwrap();
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 1_000_000).0;
+
+	let (_, splice_tx) = splice_out_between_nodes(&nodes, &channel_id);
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx] };
+	for node in nodes.iter() {
+		connect_block(node, &block, CHAN_CONFIRM_DEPTH + 1);
+		connect_blocks(node, 5, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	}
+	let as_splice_locked = get_event_msg!(nodes[0], MessageSendEvent::SendSpliceLocked, nodes[1].node.get_our_node_id());
+	let bs_splice_locked = get_event_msg!(nodes[1], MessageSendEvent::SendSpliceLocked, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_splice_locked(&nodes[1].node.get_our_node_id(), &bs_splice_locked);
+	nodes[1].node.handle_splice_locked(&nodes[0].node.get_our_node_id(), &as_splice_locked);
+	get_event_msg!(nodes[0], MessageSendEvent::SendAnnouncementSignatures, nodes[1].node.get_our_node_id());
+	get_event_msg!(nodes[1], MessageSendEvent::SendAnnouncementSignatures, nodes[0].node.get_our_node_id());
+	assert_ne!(nodes[0].node.list_channels()[0].short_channel_id.unwrap(), prev_short_channel_id);
+	assert!(nodes[1].node.channel_state.lock().unwrap().short_to_id.contains_key(&prev_short_channel_id));
+
+	// Node 1 claims the HTLC from node 0 by the short_channel_id it received it over, which it
+	// forgets once the HTLC is resolved.
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 1_000_000);
+	assert!(!nodes[1].node.channel_state.lock().unwrap().short_to_id.contains_key(&prev_short_channel_id));
+	assert!(!nodes[0].node.channel_state.lock().unwrap().short_to_id.contains_key(&prev_short_channel_id));
+}
+
+#[test]
+fn test_splice_reorged_out() {
+	// If the splice transaction is reorged out before the splice is locked, we keep using the
+	// previous funding output to close the channel.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let (_, _, channel_id, funding_tx) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let (_, splice_tx) = splice_out_between_nodes(&nodes, &channel_id);
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx.clone()] };
+	connect_block(&nodes[0], &block, CHAN_CONFIRM_DEPTH + 1);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 2, CHAN_CONFIRM_DEPTH + 1, true, header.block_hash());
+	let monitor_watches_splice_tx = || {
+		let monitors = nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap();
+		let monitor = monitors.get(&OutPoint { txid: funding_tx.txid(), index: 0 }).unwrap();
+		monitor.get_outputs_to_watch().contains_key(&splice_tx.txid())
+	};
+	assert!(monitor_watches_splice_tx());
+
+	// The splice transaction is reorged out one block before it could be locked
+	for height in (CHAN_CONFIRM_DEPTH + 1..CHAN_CONFIRM_DEPTH + ANTI_REORG_DELAY).rev() {
+		disconnect_block(&nodes[0], &header, height);
+	}
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY, CHAN_CONFIRM_DEPTH, true, header.block_hash());
+
+	nodes[0].node.force_close_channel(&channel_id);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[0], 1);
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(node_txn.len(), 1);
+	check_spends!(node_txn[0], funding_tx);
+}
+
+#[test]
+fn test_splice_commitment_tx_before_lock_in() {
+	// Once the splice transaction confirmed, our counterparty may broadcast a commitment
+	// transaction spending its funding output before the splice transaction has ANTI_REORG_DELAY
+	// confirmations, which we need to claim our outputs from.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(splicing_config()), Some(splicing_config())]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let channel_id = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known()).2;
+	let (_, splice_tx) = splice_out_between_nodes(&nodes, &channel_id);
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let block = Block { header, txdata: vec![splice_tx.clone()] };
+	for node in nodes.iter() {
+		connect_block(node, &block, CHAN_CONFIRM_DEPTH + 1);
+	}
+
+	// The previous funding output was spent by the splice transaction, so node 1 broadcasts its
+	// commitment transaction spending the splice transaction's funding output
+	nodes[1].node.force_close_channel(&channel_id);
+	check_closed_broadcast!(nodes[1], false);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
+	check_added_monitors!(nodes[1], 1);
+	let commitment_tx = {
+		let node_txn = nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(node_txn.len(), 1);
+		node_txn[0].clone()
+	};
+	check_spends!(commitment_tx, splice_tx);
+
+	let header_2 = BlockHeader { version: 0x20000000, prev_blockhash: header.block_hash(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header: header_2, txdata: vec![commitment_tx.clone()] }, CHAN_CONFIRM_DEPTH + 2);
+	check_closed_broadcast!(nodes[0], false);
+	check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+	check_added_monitors!(nodes[0], 1);
+	// Node 0 broadcasts its own commitment transaction spending the splice transaction in case
+	// the one of node 1 is reorged out
+	{
+		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		assert_eq!(node_txn.len(), 1);
+		check_spends!(node_txn[0], splice_tx);
+	}
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1, CHAN_CONFIRM_DEPTH + 2, true, header_2.block_hash());
+	let events = nodes[0].chain_monitor.chain_monitor.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::SpendableOutputs { ref outputs } => {
+			assert_eq!(outputs.len(), 1);
+			match outputs[0] {
+				SpendableOutputDescriptor::StaticOutputCounterpartyPayment { ref outpoint, .. } => assert_eq!(outpoint.txid, commitment_tx.txid()),
+				_ => panic!("Unexpected descriptor"),
+			}
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_large_channel() {
+	// Open a channel above 2^24 sat between two peers supporting option_support_large_channel. The
+	// acceptor only takes channels up to its configured max_funding_satoshis, and requires more
+	// confirmations of the funding transaction than its configured minimum_depth.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut wumbo_config = UserConfig::default();
+	wumbo_config.channel_options.announced_channel = true;
+	wumbo_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	wumbo_config.peer_channel_config_limits.max_funding_satoshis = 20 * 100_000_000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(wumbo_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	assert!(nodes[0].node.provided_init_features().supports_wumbo());
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+
+	let channel_value = 20 * 100_000_000;
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value + 1, 0, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { .. }, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), format!("Per our config, funding must not exceed {}. It was {}", channel_value, channel_value + 1), 1);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value, 0, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	// 20 BTC is 3.2 block subsidies, times six
+	assert_eq!(accept_channel.minimum_depth, 20);
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+
+	let (temporary_channel_id, tx, funding_output) = create_funding_transaction(&nodes[0], channel_value, 42);
+	nodes[0].node.funding_transaction_generated(&temporary_channel_id, funding_output);
+	nodes[1].node.handle_funding_created(&nodes[0].node.get_our_node_id(), &get_event_msg!(nodes[0], MessageSendEvent::SendFundingCreated, nodes[1].node.get_our_node_id()));
+	check_added_monitors!(nodes[1], 1);
+	nodes[0].node.handle_funding_signed(&nodes[1].node.get_our_node_id(), &get_event_msg!(nodes[1], MessageSendEvent::SendFundingSigned, nodes[0].node.get_our_node_id()));
+	check_added_monitors!(nodes[0], 1);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::FundingBroadcastSafe { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+
+	let (funding_locked, _) = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &tx);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_locked);
+	for node in nodes.iter() {
+		assert!(node.net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		node.net_graph_msg_handler.handle_channel_update(&as_update).unwrap();
+		node.net_graph_msg_handler.handle_channel_update(&bs_update).unwrap();
+	}
+	assert_eq!(nodes[0].node.list_usable_channels()[0].channel_value_satoshis, channel_value);
+
+	// A single payment may now be larger than any channel without option_support_large_channel
+	send_payment(&nodes[0], &[&nodes[1]], 100_000_000_000, 100_000_000_000);
+}
+
+#[test]
+fn test_large_channel_requires_wumbo() {
+	// Without option_support_large_channel, channels above 2^24 - 1 sat are neither opened nor
+	// accepted, regardless of our configured max_funding_satoshis.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut wumbo_config = UserConfig::default();
+	wumbo_config.peer_channel_config_limits.max_funding_satoshis = 100_000_000;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(wumbo_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let channel_value = 1 << 24;
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::empty() });
+	match nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value, 0, 42, None) {
+		Err(APIError::APIMisuseError { err }) => assert_eq!(err, format!("funding_value must not exceed {}, it was {}", channel_value - 1, channel_value)),
+		_ => panic!("Unexpected result"),
+	}
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+
+	nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::known() });
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), channel_value, 0, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::empty(), &open_channel);
+	let msg_events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(msg_events.len(), 1);
+	match msg_events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { .. }, .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	nodes[1].logger.assert_log("lightning::ln::channelmanager".to_string(), format!("Funding must not exceed {} without option_support_large_channel. It was {}", channel_value - 1, channel_value), 1);
+}
+
+fn open_zero_conf_channel<'a, 'b, 'c>(lsp: &Node<'a, 'b, 'c>, user: &Node<'a, 'b, 'c>, funding_input: Option<TxIn>) -> Transaction {
+	// Opens a channel from lsp to user, which trusts lsp, returning the (unbroadcast) funding
+	// transaction once both sides exchanged funding_locked.
+	lsp.node.create_channel(user.node.get_our_node_id(), 100_000, 0, 42, None).unwrap();
+	user.node.handle_open_channel(&lsp.node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(lsp, MessageSendEvent::SendOpenChannel, user.node.get_our_node_id()));
+	let accept_channel = get_event_msg!(user, MessageSendEvent::SendAcceptChannel, lsp.node.get_our_node_id());
+	assert_eq!(accept_channel.minimum_depth, 0);
+	lsp.node.handle_accept_channel(&user.node.get_our_node_id(), InitFeatures::known(), &accept_channel);
+
+	let (temporary_channel_id, mut tx, _) = create_funding_transaction(lsp, 100_000, 42);
+	if let Some(input) = funding_input {
+		tx.input.push(input);
+	}
+	lsp.node.funding_transaction_generated(&temporary_channel_id, OutPoint { txid: tx.txid(), index: 0 });
+	user.node.handle_funding_created(&lsp.node.get_our_node_id(), &get_event_msg!(lsp, MessageSendEvent::SendFundingCreated, user.node.get_our_node_id()));
+	check_added_monitors!(user, 1);
+	let events = user.node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	let user_funding_locked = match (&events[0], &events[1]) {
+		(&MessageSendEvent::SendFundingSigned { ref msg, .. }, &MessageSendEvent::SendFundingLocked { msg: ref funding_locked, .. }) => {
+			lsp.node.handle_funding_signed(&user.node.get_our_node_id(), msg);
+			funding_locked.clone()
+		},
+		_ => panic!("Unexpected event"),
+	};
+	check_added_monitors!(lsp, 1);
+	let events = lsp.node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::FundingBroadcastSafe { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	let lsp_funding_locked = get_event_msg!(lsp, MessageSendEvent::SendFundingLocked, user.node.get_our_node_id());
+	assert!(lsp_funding_locked.short_channel_id_alias.is_some());
+
+	user.node.handle_funding_locked(&lsp.node.get_our_node_id(), &lsp_funding_locked);
+	lsp.node.handle_funding_locked(&user.node.get_our_node_id(), &user_funding_locked);
+	// We can't announce the channel before it has a short_channel_id
+	assert!(user.node.get_and_clear_pending_msg_events().is_empty());
+	assert!(lsp.node.get_and_clear_pending_msg_events().is_empty());
+	tx
+}
+
+#[test]
+fn test_zero_conf_channel() {
+	// An LSP (nodes[1]) opens a channel to a user (nodes[2]) which trusts it not to double-spend the
+	// funding transaction. The channel is usable right away through its aliases, both for direct
+	// payments and for payments forwarded by the LSP using a route hint, and gets a
+	// short_channel_id once the funding transaction confirms.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	connect_blocks(&nodes[2], CHAN_CONFIRM_DEPTH - 1, 0, false, Default::default());
+
+	nodes[2].node.set_trusted_zero_conf_peer(nodes[1].node.get_our_node_id(), true);
+	let funding_tx = open_zero_conf_channel(&nodes[1], &nodes[2], None);
+
+	let lsp_chan = nodes[1].node.list_usable_channels().into_iter().find(|chan| chan.remote_network_id == nodes[2].node.get_our_node_id()).unwrap();
+	let user_chan = nodes[2].node.list_usable_channels().pop().unwrap();
+	assert!(lsp_chan.short_channel_id.is_none() && user_chan.short_channel_id.is_none());
+	assert!(lsp_chan.scid_alias.is_some() && user_chan.scid_alias.is_some());
+	assert_eq!(lsp_chan.counterparty_scid_alias, user_chan.scid_alias);
+	assert_eq!(user_chan.counterparty_scid_alias, lsp_chan.scid_alias);
+	assert_eq!(lsp_chan.get_outbound_payment_scid(), lsp_chan.scid_alias);
+
+	// Pay in both directions before the funding transaction confirms
+	let route = get_route(&nodes[1].node.get_our_node_id(), &nodes[1].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), Some(&[&lsp_chan]), &[], 10_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	assert_eq!(route.paths[0][0].short_channel_id, lsp_chan.scid_alias.unwrap());
+	let (payment_preimage, _) = send_along_route(&nodes[1], route, &[&nodes[2]], 10_000_000);
+	claim_payment(&nodes[1], &[&nodes[2]], payment_preimage, 10_000_000);
+	let user_chan = nodes[2].node.list_usable_channels().pop().unwrap();
+	let route = get_route(&nodes[2].node.get_our_node_id(), &nodes[2].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&[&user_chan]), &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let (payment_preimage, _) = send_along_route(&nodes[2], route, &[&nodes[1]], 1_000_000);
+	claim_payment(&nodes[2], &[&nodes[1]], payment_preimage, 1_000_000);
+
+	// The user can be paid through the LSP with a route hint using the alias the LSP gave it
+	let route_hint = RouteHint {
+		src_node_id: nodes[1].node.get_our_node_id(),
+		short_channel_id: user_chan.counterparty_scid_alias.unwrap(),
+		fees: RoutingFees { base_msat: 1000, proportional_millionths: 0 },
+		cltv_expiry_delta: 72,
+		htlc_minimum_msat: 0,
+		htlc_maximum_msat: None,
+	};
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[&route_hint], 5_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	assert_eq!(route.paths[0].len(), 2);
+	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 5_000_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 5_000_000);
+
+	// Once the funding transaction confirms the channel gets its short_channel_id and is announced
+	confirm_transaction(&nodes[1], &funding_tx);
+	get_event_msg!(nodes[1], MessageSendEvent::SendAnnouncementSignatures, nodes[2].node.get_our_node_id());
+	confirm_transaction(&nodes[2], &funding_tx);
+	get_event_msg!(nodes[2], MessageSendEvent::SendAnnouncementSignatures, nodes[1].node.get_our_node_id());
+	let lsp_chan = nodes[1].node.list_usable_channels().into_iter().find(|chan| chan.remote_network_id == nodes[2].node.get_our_node_id()).unwrap();
+	assert!(lsp_chan.short_channel_id.is_some());
+	assert_eq!(lsp_chan.short_channel_id, nodes[2].node.list_usable_channels()[0].short_channel_id);
+	assert_eq!(lsp_chan.get_outbound_payment_scid(), lsp_chan.short_channel_id);
+
+	// Route hints using the alias keep working
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[&route_hint], 5_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 5_000_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 5_000_000);
+}
+
+#[test]
+fn test_zero_conf_channel_requires_trust() {
+	// Inbound channels from peers we don't trust wait for our configured minimum_depth.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[1].node.set_trusted_zero_conf_peer(nodes[0].node.get_our_node_id(), true);
+	nodes[1].node.set_trusted_zero_conf_peer(nodes[0].node.get_our_node_id(), false);
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100_000, 0, 42, None).unwrap();
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id()));
+	let accept_channel = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+	assert_eq!(accept_channel.minimum_depth, UserConfig::default().own_channel_config.minimum_depth);
+}
+
+#[test]
+fn test_zero_conf_funding_double_spent() {
+	// If the funding transaction of a zero-conf channel is double-spent, both sides close the
+	// channel without broadcasting anything and the ChannelMonitor fails back pending HTLCs once
+	// the double-spend is buried ANTI_REORG_DELAY deep.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	nodes[1].node.set_trusted_zero_conf_peer(nodes[0].node.get_our_node_id(), true);
+	let funding_input = TxIn {
+		previous_output: BitcoinOutPoint { txid: Default::default(), vout: 0 },
+		script_sig: Script::new(),
+		sequence: 0xffffffff,
+		witness: vec![vec![1]],
+	};
+	let funding_tx = open_zero_conf_channel(&nodes[0], &nodes[1], Some(funding_input.clone()));
+
+	// Our ChannelMonitors learn the funding transaction's inputs once it confirms...
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	let funding_block = Block { header, txdata: vec![funding_tx] };
+	for node in nodes.iter() {
+		connect_block(node, &funding_block, 1);
+		node.node.get_and_clear_pending_msg_events();
+		assert!(node.node.list_usable_channels()[0].short_channel_id.is_some());
+	}
+
+	let chan = nodes[0].node.list_usable_channels().pop().unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&[&chan]), &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let (_, payment_hash) = send_along_route(&nodes[0], route, &[&nodes[1]], 1_000_000);
+
+	// ...but it is then reorged out, leaving the channel usable through its aliases only...
+	for node in nodes.iter() {
+		disconnect_block(node, &header, 1);
+		assert!(node.node.list_usable_channels()[0].short_channel_id.is_none());
+	}
+
+	// ...until a conflicting transaction confirms.
+	let double_spend = Transaction { version: 2, lock_time: 0, input: vec![funding_input], output: vec![TxOut { value: 1000, script_pubkey: Script::new() }] };
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 43, bits: 42, nonce: 42 };
+	for node in nodes.iter() {
+		connect_block(node, &Block { header, txdata: vec![double_spend.clone()] }, 1);
+		check_added_monitors!(node, 1);
+		check_closed_event!(node, 1, ClosureReason::ProcessingError { err: "Funding transaction was double-spent".to_owned() });
+		let events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, .. } => {
+				assert_eq!(msg.data, "Funding transaction was double-spent");
+			},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(node.node.list_channels().is_empty());
+	}
+
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 2, 1, true, header.block_hash());
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	connect_blocks(&nodes[0], 1, ANTI_REORG_DELAY - 1, false, Default::default());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_payment_hash, .. } => assert_eq!(*failed_payment_hash, payment_hash),
+		_ => panic!("Unexpected event"),
+	}
+	// Nothing is ever broadcast for a channel whose funding transaction was double-spent
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_zero_conf_unconfirmed_funding_double_spent() {
+	// Once both sides are given the funding transaction of a zero-conf channel, they detect it
+	// being double-spent even though it never confirmed.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	nodes[1].node.set_trusted_zero_conf_peer(nodes[0].node.get_our_node_id(), true);
+	let funding_input = TxIn {
+		previous_output: BitcoinOutPoint { txid: Default::default(), vout: 0 },
+		script_sig: Script::new(),
+		sequence: 0xffffffff,
+		witness: vec![vec![1]],
+	};
+	let funding_tx = open_zero_conf_channel(&nodes[0], &nodes[1], Some(funding_input.clone()));
+	let channel_id = OutPoint { txid: funding_tx.txid(), index: 0 }.to_channel_id();
+	let double_spend = Transaction { version: 2, lock_time: 0, input: vec![funding_input], output: vec![TxOut { value: 1000, script_pubkey: Script::new() }] };
+
+	match nodes[1].node.provide_funding_transaction(&channel_id, double_spend.clone()) {
+		Err(APIError::APIMisuseError { ref err }) => assert_eq!(err, "Transaction isn't the funding transaction of the channel"),
+		_ => panic!("Unexpected result"),
+	}
+	for node in nodes.iter() {
+		node.node.provide_funding_transaction(&channel_id, funding_tx.clone()).unwrap();
+		check_added_monitors!(node, 1);
+	}
+	match nodes[1].node.provide_funding_transaction(&channel_id, funding_tx.clone()) {
+		Err(APIError::APIMisuseError { ref err }) => assert_eq!(err, "The funding transaction of the channel is already known"),
+		_ => panic!("Unexpected result"),
+	}
+
+	let chan = nodes[0].node.list_usable_channels().pop().unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), Some(&[&chan]), &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let (_, payment_hash) = send_along_route(&nodes[0], route, &[&nodes[1]], 1_000_000);
+
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	for node in nodes.iter() {
+		connect_block(node, &Block { header, txdata: vec![double_spend.clone()] }, 1);
+		check_added_monitors!(node, 1);
+		check_closed_event!(node, 1, ClosureReason::ProcessingError { err: "Funding transaction was double-spent".to_owned() });
+		let events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, .. } => {
+				assert_eq!(msg.data, "Funding transaction was double-spent");
+			},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(node.node.list_channels().is_empty());
+	}
+
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1, 1, true, header.block_hash());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_payment_hash, .. } => assert_eq!(*failed_payment_hash, payment_hash),
+		_ => panic!("Unexpected event"),
+	}
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_close_channel_with_options() {
+	// Test closing a channel to a destination of our choosing, at our target feerate.
+	let mut config = UserConfig::default();
+	config.channel_options.announced_channel = true;
+	config.channel_options.commit_upfront_shutdown_pubkey = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 100_000, 50_000_000, InitFeatures::known(), InitFeatures::known());
+
+	let destination = Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&[42; 20]).into_script();
+	let nonstandard = Builder::new().push_opcode(opcodes::all::OP_RETURN).into_script();
+	match nodes[0].node.close_channel_with_options(&chan.2, Some(nonstandard), 1000, 10_000) {
+		Err(APIError::APIMisuseError { ref err }) => assert!(err.starts_with("Cannot close to a nonstandard scriptpubkey")),
+		_ => panic!("Unexpected result"),
+	}
+	match nodes[0].node.close_channel_with_options(&chan.2, Some(destination.clone()), 100, 10_000) {
+		Err(APIError::APIMisuseError { ref err }) => assert_eq!(err, "Target closing feerate 100 is below the minimum relay feerate"),
+		_ => panic!("Unexpected result"),
+	}
+	// nodes[1] committed to its upfront shutdown script, so may not close anywhere else
+	match nodes[1].node.close_channel_with_options(&chan.2, Some(destination.clone()), 1000, 10_000) {
+		Err(APIError::APIMisuseError { ref err }) => assert!(err.ends_with("other than the upfront shutdown script we committed to")),
+		_ => panic!("Unexpected result"),
+	}
+
+	nodes[0].node.close_channel_with_options(&chan.2, Some(destination.clone()), 1000, 10_000).unwrap();
+	let node_0_shutdown = get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id());
+	assert_eq!(node_0_shutdown.scriptpubkey, destination);
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+	let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &node_1_shutdown);
+	let node_0_closing_signed = get_event_msg!(nodes[0], MessageSendEvent::SendClosingSigned, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let (_, node_1_closing_signed) = get_closing_signed_broadcast!(nodes[1].node, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed.unwrap());
+	let (_, node_0_none) = get_closing_signed_broadcast!(nodes[0].node, nodes[1].node.get_our_node_id());
+	assert!(node_0_none.is_none());
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
+
+	let closing_tx = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().pop().unwrap();
+	assert_eq!(closing_tx, nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().pop().unwrap());
+	assert!(closing_tx.output.iter().any(|output| output.script_pubkey == destination));
+	// At 1000 sat per kw the fee is the closing transaction's weight (give or take the
+	// signatures' sizes).
+	let fee = 100_000 - closing_tx.output.iter().map(|output| output.value).sum::<u64>();
+	assert_eq!(fee, node_0_closing_signed.fee_satoshis);
+	assert!(fee >= closing_tx.get_weight() as u64 && fee <= closing_tx.get_weight() as u64 + 2);
+}
+
+#[test]
+fn test_close_channel_fee_limits_unmet() {
+	// If our counterparty insists on a closing fee above the maximum we gave, we give up and
+	// force-close the channel.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+
+	nodes[0].node.close_channel_with_options(&chan.2, None, 253, 100).unwrap();
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id()));
+	nodes[0].node.handle_shutdown(&nodes[1].node.get_our_node_id(), &get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, nodes[0].node.get_our_node_id()));
+	let node_0_closing_signed = get_event_msg!(nodes[0], MessageSendEvent::SendClosingSigned, nodes[1].node.get_our_node_id());
+	assert_eq!(node_0_closing_signed.fee_satoshis, 100);
+
+	// nodes[1] wants at least its Background feerate...
+	nodes[1].node.handle_closing_signed(&nodes[0].node.get_our_node_id(), &node_0_closing_signed);
+	let node_1_closing_signed = get_event_msg!(nodes[1], MessageSendEvent::SendClosingSigned, nodes[0].node.get_our_node_id());
+	assert!(node_1_closing_signed.fee_satoshis > 100);
+	assert!(nodes[1].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+
+	// ...which nodes[0] won't pay.
+	nodes[0].node.handle_closing_signed(&nodes[1].node.get_our_node_id(), &node_1_closing_signed);
+	let error = check_closed_broadcast!(nodes[0], true).unwrap();
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Unable to come to consensus about closing fee, remote wanted something higher (170) than our maximum fee (100)".to_string() });
+	assert_eq!(error.data, format!("Unable to come to consensus about closing fee, remote wanted something higher ({}) than our maximum fee (100)", node_1_closing_signed.fee_satoshis));
+	check_added_monitors!(nodes[0], 1);
+	assert!(nodes[0].node.list_channels().is_empty());
+}
+
+#[test]
+fn test_update_channel_config() {
+	// Test updating the forwarding policy of a live channel: a new channel_update is broadcast and
+	// HTLCs paying the previous policy are accepted until it expires.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+
+	let old_route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+
+	let mut config = UserConfig::default().channel_options;
+	config.fee_base_msat = Some(5_000);
+	config.fee_proportional_millionths = 1_000;
+	config.cltv_expiry_delta = 100;
+	config.htlc_minimum_msat = 10_000;
+	match nodes[1].node.update_channel_config(&[chan.2, [42; 32]], &config) {
+		Err(APIError::ChannelUnavailable { ref err }) => assert_eq!(err, "No such channel"),
+		_ => panic!("Unexpected result"),
+	}
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[1].node.update_channel_config(&[chan.2], &config).unwrap();
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let channel_update = match events[0] {
+		MessageSendEvent::BroadcastChannelUpdate { ref msg } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	assert_eq!(channel_update.contents.fee_base_msat, 5_000);
+	assert_eq!(channel_update.contents.fee_proportional_millionths, 1_000);
+	assert_eq!(channel_update.contents.cltv_expiry_delta, 100);
+	assert_eq!(channel_update.contents.htlc_minimum_msat, 10_000);
+	// Setting the same policy again is a no-op
+	nodes[1].node.update_channel_config(&[chan.2], &config).unwrap();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+	// Routes using the new policy work...
+	nodes[0].net_graph_msg_handler.handle_channel_update(&channel_update).unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	assert_eq!(route.paths[0][0].fee_msat, 5_000 + 1_000);
+	assert_eq!(route.paths[0][0].cltv_expiry_delta, 100);
+	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 1_000_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 1_000_000);
+
+	// ...and so do routes built before the update, for a few timer ticks...
+	for _ in 0..EXPIRE_PREV_CONFIG_TICKS - 1 {
+		nodes[1].node.timer_chan_freshness_every_min();
+	}
+	let (payment_preimage, _) = send_along_route(&nodes[0], old_route.clone(), &[&nodes[1], &nodes[2]], 1_000_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 1_000_000);
+
+	// ...after which they fail with fee_insufficient.
+	nodes[1].node.timer_chan_freshness_every_min();
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	nodes[0].node.send_payment(&old_route, payment_hash, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let update_0 = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &update_0.update_add_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], &update_0.commitment_signed, false, true);
+	let update_1_0 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(update_1_0.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &update_1_0.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], update_1_0.commitment_signed, false, true);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_hash: ref failed_payment_hash, rejected_by_dest, ref error_code, failing_hop_index, failing_node_id, short_channel_id, ref channel_update, ref path, .. } => {
+			assert_eq!(*failed_payment_hash, payment_hash);
+			assert!(!rejected_by_dest);
+			assert_eq!(error_code.unwrap(), 0x1000 | 12);
+			assert_eq!(failing_hop_index, Some(0));
+			assert_eq!(failing_node_id, Some(nodes[1].node.get_our_node_id()));
+			assert_eq!(short_channel_id, Some(chan.0.contents.short_channel_id));
+			assert_eq!(channel_update.as_ref().unwrap().contents.fee_base_msat, 5_000);
+			assert!(*path == old_route.paths[0]);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::PaymentFailureNetworkUpdate { update: HTLCFailChannelUpdate::ChannelUpdateMessage { ref msg } } => {
+			assert_eq!(msg.contents.fee_base_msat, 5_000);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_auto_update_fee() {
+	// Test that outbound channels follow our feerate estimate from timer_chan_freshness_every_min,
+	// within the thresholds and ceiling set in their config.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut auto_config = UserConfig::default();
+	auto_config.channel_options.announced_channel = true;
+	auto_config.channel_options.auto_update_fee = true;
+	let mut capped_config = auto_config;
+	capped_config.channel_options.max_update_feerate_per_kw = Some(1000);
+	let mut manual_config = UserConfig::default();
+	manual_config.channel_options.announced_channel = true;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[Some(capped_config), Some(auto_config), Some(manual_config)]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 2, 1, InitFeatures::known(), InitFeatures::known());
+
+	macro_rules! expect_update_fee {
+		($feerate: expr) => {{
+			// Keep nodes[1]'s estimate close enough that it accepts the new feerate
+			*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = $feerate;
+			nodes[0].node.timer_chan_freshness_every_min();
+			check_added_monitors!(nodes[0], 1);
+			let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+			assert_eq!(updates.update_fee.as_ref().unwrap().feerate_per_kw, $feerate);
+			nodes[1].node.handle_update_fee(&nodes[0].node.get_our_node_id(), updates.update_fee.as_ref().unwrap());
+			nodes[1].node.handle_commitment_signed(&nodes[0].node.get_our_node_id(), &updates.commitment_signed);
+			let (revoke_msg, commitment_signed) = get_revoke_commit_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+			check_added_monitors!(nodes[1], 1);
+			nodes[0].node.handle_revoke_and_ack(&nodes[1].node.get_our_node_id(), &revoke_msg);
+			check_added_monitors!(nodes[0], 1);
+			nodes[0].node.handle_commitment_signed(&nodes[1].node.get_our_node_id(), &commitment_signed);
+			let revoke_msg = get_event_msg!(nodes[0], MessageSendEvent::SendRevokeAndACK, nodes[1].node.get_our_node_id());
+			check_added_monitors!(nodes[0], 1);
+			nodes[1].node.handle_revoke_and_ack(&nodes[0].node.get_our_node_id(), &revoke_msg);
+			check_added_monitors!(nodes[1], 1);
+			assert_eq!(get_feerate!(nodes[0], chan.2), $feerate);
+			assert_eq!(get_feerate!(nodes[1], chan.2), $feerate);
+		}}
+	}
+
+	// Small increases of our estimate are ignored...
+	*chanmon_cfgs[0].fee_estimator.sat_per_kw.lock().unwrap() = 270;
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+	// ...larger ones are followed, up to our ceiling...
+	*chanmon_cfgs[0].fee_estimator.sat_per_kw.lock().unwrap() = 2000;
+	expect_update_fee!(1000);
+
+	// ...and we only lower the feerate once our estimate is less than half of it.
+	*chanmon_cfgs[0].fee_estimator.sat_per_kw.lock().unwrap() = 600;
+	nodes[0].node.timer_chan_freshness_every_min();
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	*chanmon_cfgs[0].fee_estimator.sat_per_kw.lock().unwrap() = 400;
+	expect_update_fee!(400);
+
+	// Inbound channels and channels with auto_update_fee disabled are left alone.
+	*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 2000;
+	*chanmon_cfgs[2].fee_estimator.sat_per_kw.lock().unwrap() = 2000;
+	nodes[1].node.timer_chan_freshness_every_min();
+	nodes[2].node.timer_chan_freshness_every_min();
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	assert!(nodes[2].node.get_and_clear_pending_msg_events().is_empty());
+}
+
+#[test]
+fn test_auto_update_fee_funder_cannot_afford() {
+	// Test that we don't follow our feerate estimate to a feerate our balance can't pay for, as
+	// our counterparty would close the channel on receiving such an update_fee.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut auto_config = UserConfig::default();
+	auto_config.channel_options.announced_channel = true;
+	auto_config.channel_options.auto_update_fee = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(auto_config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	// As in test_update_fee_that_funder_cannot_afford, a feerate of 260 is the most nodes[0] can
+	// afford on this channel.
+	let chan = create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1888, 700000, InitFeatures::known(), InitFeatures::known());
+
+	*chanmon_cfgs[0].fee_estimator.sat_per_kw.lock().unwrap() = 2000;
+	nodes[0].node.timer_chan_freshness_every_min();
+	check_added_monitors!(nodes[0], 0);
+	assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	assert_eq!(get_feerate!(nodes[0], chan.2), 253);
+	assert_eq!(nodes[0].node.list_channels().len(), 1);
+}
+
+#[test]
+fn test_manually_accept_inbound_channel() {
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut manual_config = UserConfig::default();
+	manual_config.channel_options.announced_channel = true;
+	manual_config.manually_accept_inbound_channels = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manual_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+
+	// The channel isn't accepted until we're told to.
+	assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::OpenChannelRequest { temporary_channel_id, counterparty_node_id, funding_satoshis, push_msat, ref counterparty_features } => {
+			assert_eq!(temporary_channel_id, open_channel.temporary_channel_id);
+			assert_eq!(counterparty_node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(funding_satoshis, 100000);
+			assert_eq!(push_msat, 10001);
+			assert_eq!(*counterparty_features, InitFeatures::known());
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	let mut chan_config = ChannelConfig::default();
+	chan_config.fee_base_msat = Some(1234);
+	assert!(nodes[1].node.accept_inbound_channel(&[42; 32], 23, None).is_err());
+	nodes[1].node.accept_inbound_channel(&open_channel.temporary_channel_id, 23, Some(&chan_config)).unwrap();
+	if let Err(APIError::APIMisuseError { err }) = nodes[1].node.accept_inbound_channel(&open_channel.temporary_channel_id, 23, None) {
+		assert_eq!(err, "The channel is not awaiting to be accepted");
+	} else { panic!(); }
+	nodes[0].node.handle_accept_channel(&nodes[1].node.get_our_node_id(), InitFeatures::known(), &get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id()));
+
+	let (temporary_channel_id, tx, funding_output) = create_funding_transaction(&nodes[0], 100000, 42);
+	nodes[0].node.funding_transaction_generated(&temporary_channel_id, funding_output);
+	nodes[1].node.handle_funding_created(&nodes[0].node.get_our_node_id(), &get_event_msg!(nodes[0], MessageSendEvent::SendFundingCreated, nodes[1].node.get_our_node_id()));
+	check_added_monitors!(nodes[1], 1);
+	nodes[0].node.handle_funding_signed(&nodes[1].node.get_our_node_id(), &get_event_msg!(nodes[1], MessageSendEvent::SendFundingSigned, nodes[0].node.get_our_node_id()));
+	check_added_monitors!(nodes[0], 1);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::FundingBroadcastSafe { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+
+	let (funding_locked, channel_id) = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &tx);
+	let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &funding_locked);
+	update_nodes_with_chan_announce(&nodes, 0, 1, &announcement, &as_update, &bs_update);
+
+	// The channel uses the user_channel_id and config given when accepting it.
+	let chan = nodes[1].node.list_channels().into_iter().find(|c| c.channel_id == channel_id).unwrap();
+	assert_eq!(chan.user_id, 23);
+	assert_eq!(bs_update.contents.fee_base_msat, 1234);
+	assert_eq!(bs_update.contents.flags & 1, 1);
+}
+
+#[test]
+fn test_manually_reject_inbound_channel() {
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut manual_config = UserConfig::default();
+	manual_config.channel_options.announced_channel = true;
+	manual_config.manually_accept_inbound_channels = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manual_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 0, 42, None).unwrap();
+	let open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), InitFeatures::known(), &open_channel);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::OpenChannelRequest { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+
+	nodes[1].node.reject_inbound_channel(&open_channel.temporary_channel_id).unwrap();
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { ref node_id, action: ErrorAction::SendErrorMessage { ref msg } } => {
+			assert_eq!(*node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(msg.channel_id, open_channel.temporary_channel_id);
+			nodes[0].node.handle_error(&nodes[1].node.get_our_node_id(), msg);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	check_closed_event!(nodes[0], 1, ClosureReason::CounterpartyForceClosed { peer_msg: "Channel open was rejected".to_owned() });
+	assert!(nodes[0].node.list_channels().is_empty());
+	assert!(nodes[1].node.list_channels().is_empty());
+	assert!(nodes[1].node.reject_inbound_channel(&open_channel.temporary_channel_id).is_err());
+}
+
+#[test]
+fn test_channel_details_and_pending_htlcs() {
+	// Test the balances, reserves and counterparty forwarding policy reported in ChannelDetails,
+	// and the HTLCs reported by list_pending_htlcs as a payment goes through.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+
+	let reserve = Channel::<EnforcingChannelKeys>::get_holder_selected_channel_reserve_satoshis(100_000);
+	let details = &nodes[0].node.list_channels()[0];
+	assert_eq!(details.balance_msat, 100_000_000 - 10_001);
+	assert_eq!(details.counterparty_balance_msat, 10_001);
+	assert_eq!(details.holder_channel_reserve_satoshis, Some(reserve));
+	assert_eq!(details.counterparty_channel_reserve_satoshis, reserve);
+	assert!(details.confirmations >= details.confirmations_required.unwrap());
+	assert!(details.is_public);
+	assert!(details.is_funding_locked);
+	assert_eq!(details.feerate_per_kw, 253);
+	assert_eq!(details.counterparty_forwarding_info, None);
+	assert_eq!(details.holder_to_self_delay, Some(BREAKDOWN_TIMEOUT));
+	assert_eq!(details.counterparty_to_self_delay, BREAKDOWN_TIMEOUT);
+
+	// Only the counterparty's channel_update for the channel is taken as its forwarding policy.
+	let mut config = UserConfig::default().channel_options;
+	config.fee_base_msat = Some(5_000);
+	config.fee_proportional_millionths = 1_000;
+	config.cltv_expiry_delta = 100;
+	config.htlc_minimum_msat = 10_000;
+	nodes[1].node.update_channel_config(&[chan.2], &config).unwrap();
+	let bs_update = match nodes[1].node.get_and_clear_pending_msg_events()[0] {
+		MessageSendEvent::BroadcastChannelUpdate { ref msg } => msg.clone(),
+		_ => panic!("Unexpected event"),
+	};
+	nodes[1].node.handle_channel_update(&nodes[0].node.get_our_node_id(), &bs_update);
+	assert_eq!(nodes[1].node.list_channels()[0].counterparty_forwarding_info, None);
+	nodes[0].node.handle_channel_update(&nodes[1].node.get_our_node_id(), &bs_update);
+	assert_eq!(nodes[0].node.list_channels()[0].counterparty_forwarding_info, Some(CounterpartyForwardingInfo {
+		fee_base_msat: 5_000,
+		fee_proportional_millionths: 1_000,
+		cltv_expiry_delta: 100,
+		htlc_minimum_msat: 10_000,
+		htlc_maximum_msat: match bs_update.contents.htlc_maximum_msat { OptionalField::Present(max) => Some(max), OptionalField::Absent => None },
+	}));
+
+	let (payment_preimage, payment_hash) = route_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+	let htlcs = nodes[0].node.list_pending_htlcs();
+	assert_eq!(htlcs.len(), 1);
+	let htlc = htlcs[0].clone();
+	assert_eq!(htlc, PendingHTLCDetails {
+		channel_id: chan.2,
+		htlc_id: Some(0),
+		direction: HTLCDirection::Outbound,
+		amount_msat: 1_000_000,
+		payment_hash,
+		cltv_expiry: htlc.cltv_expiry,
+		state: PendingHTLCState::Committed,
+	});
+	assert_eq!(nodes[1].node.list_pending_htlcs(), vec![PendingHTLCDetails { direction: HTLCDirection::Inbound, ..htlc.clone() }]);
+	assert_eq!(nodes[0].node.list_channels()[0].balance_msat, 100_000_000 - 10_001);
+
+	assert!(nodes[1].node.claim_funds(payment_preimage, &None, 1_000_000));
+	check_added_monitors!(nodes[1], 1);
+	assert_eq!(nodes[1].node.list_pending_htlcs(), vec![PendingHTLCDetails { direction: HTLCDirection::Inbound, state: PendingHTLCState::Fulfilling, ..htlc.clone() }]);
+	let bs_updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &bs_updates.update_fulfill_htlcs[0]);
+	expect_payment_sent!(nodes[0], payment_preimage);
+	assert_eq!(nodes[0].node.list_pending_htlcs(), vec![PendingHTLCDetails { state: PendingHTLCState::Fulfilling, ..htlc }]);
+	commitment_signed_dance!(nodes[0], nodes[1], bs_updates.commitment_signed, false);
+	assert!(nodes[0].node.list_pending_htlcs().is_empty());
+	assert!(nodes[1].node.list_pending_htlcs().is_empty());
+	assert_eq!(nodes[0].node.list_channels()[0].balance_msat, 100_000_000 - 10_001 - 1_000_000);
+	assert_eq!(nodes[1].node.list_channels()[0].balance_msat, 10_001 + 1_000_000);
+}
+
+#[test]
+fn test_private_channel_route_hints() {
+	// A user (nodes[2]) with private channels from an LSP (nodes[1]) gets route hints through them,
+	// built from the channel_update the LSP sent it directly, which payers can use to reach it.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut lsp_config = UserConfig::default();
+	lsp_config.channel_options.announced_channel = false;
+	lsp_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	lsp_config.channel_options.fee_base_msat = Some(2_000);
+	lsp_config.channel_options.fee_proportional_millionths = 100;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, Some(lsp_config), None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let (lsp_update, _, _, _) = create_unannounced_chan_between_nodes_with_value(&nodes, 1, 2, 100_000, 0, InitFeatures::known(), InitFeatures::known());
+	let (small_lsp_update, _, _, _) = create_unannounced_chan_between_nodes_with_value(&nodes, 1, 2, 50_000, 48_000_000, InitFeatures::known(), InitFeatures::known());
+
+	// Hints are sorted by inbound capacity and only include channels which can take the amount,
+	// both in terms of liquidity and of the LSP's HTLC limits
+	let hints = nodes[2].node.get_route_hints(None);
+	assert_eq!(hints.len(), 2);
+	assert_eq!(hints[0].short_channel_id, lsp_update.contents.short_channel_id);
+	assert_eq!(hints[1].short_channel_id, small_lsp_update.contents.short_channel_id);
+	assert_eq!(nodes[2].node.get_route_hints(Some(500_000)).len(), 2);
+	let hints = nodes[2].node.get_route_hints(Some(3_000_000));
+	assert_eq!(hints.len(), 1);
+	let hint = &hints[0];
+	assert_eq!(hint.src_node_id, nodes[1].node.get_our_node_id());
+	assert_eq!(hint.short_channel_id, lsp_update.contents.short_channel_id);
+	assert_eq!(hint.fees.base_msat, 2_000);
+	assert_eq!(hint.fees.proportional_millionths, 100);
+	assert_eq!(hint.cltv_expiry_delta, lsp_update.contents.cltv_expiry_delta);
+	assert_eq!(hint.htlc_minimum_msat, lsp_update.contents.htlc_minimum_msat);
+	match lsp_update.contents.htlc_maximum_msat {
+		OptionalField::Present(max) => assert_eq!(hint.htlc_maximum_msat, Some(max)),
+		OptionalField::Absent => panic!("Expected an htlc_maximum_msat"),
+	}
+	assert!(nodes[2].node.get_route_hints(Some(20_000_000)).is_empty());
+	// The LSP only has inbound capacity on the channel where it pushed funds to the user
+	let hints = nodes[1].node.get_route_hints(Some(1_000_000));
+	assert_eq!(hints.len(), 1);
+	assert_eq!(hints[0].src_node_id, nodes[2].node.get_our_node_id());
+	assert_eq!(hints[0].short_channel_id, small_lsp_update.contents.short_channel_id);
+
+	// The private channels aren't in the network graph, but a payer can route over the hint
+	assert!(get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).is_err());
+	let hint = nodes[2].node.get_route_hints(Some(3_000_000)).pop().unwrap();
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2].node.get_our_node_id(), None, &[&hint], 1_000_000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	assert_eq!(route.paths[0][0].fee_msat, 2_000 + 100);
+	let (payment_preimage, _) = send_along_route(&nodes[0], route, &[&nodes[1], &nodes[2]], 1_000_000);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage, 1_000_000);
+
+	// The LSP's forwarding policy is still known while it's offline, but the channels aren't live
+	nodes[1].node.peer_disconnected(&nodes[2].node.get_our_node_id(), false);
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id(), false);
+	assert!(nodes[2].node.get_route_hints(None).is_empty());
+	assert_eq!(nodes[2].node.list_channels()[0].counterparty_forwarding_info.as_ref().unwrap().fee_base_msat, 2_000);
+
+	// Reconnecting resends the channel_update of private channels, in case it was lost
+	nodes[1].node.peer_connected(&nodes[2].node.get_our_node_id(), &msgs::Init { features: InitFeatures::empty() });
+	nodes[2].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init { features: InitFeatures::empty() });
+	let lsp_reestablish = get_chan_reestablish_msgs!(nodes[1], nodes[2]);
+	let user_reestablish = get_chan_reestablish_msgs!(nodes[2], nodes[1]);
+	assert_eq!(lsp_reestablish.len(), 2);
+	for msg in lsp_reestablish.iter() {
+		nodes[2].node.handle_channel_reestablish(&nodes[1].node.get_our_node_id(), msg);
+	}
+	for msg in user_reestablish.iter() {
+		nodes[1].node.handle_channel_reestablish(&nodes[2].node.get_our_node_id(), msg);
+	}
+	for (node, counterparty) in [(&nodes[1], &nodes[2]), (&nodes[2], &nodes[1])].iter() {
+		// funding_locked is resent for the channel which has no commitment update yet
+		let events = node.node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 3);
+		let mut channel_updates = 0;
+		for event in events.iter() {
+			match event {
+				&MessageSendEvent::SendChannelUpdate { ref node_id, ref msg } => {
+					assert_eq!(*node_id, counterparty.node.get_our_node_id());
+					counterparty.node.handle_channel_update(&node.node.get_our_node_id(), msg);
+					channel_updates += 1;
+				},
+				&MessageSendEvent::SendFundingLocked { .. } => {},
+				_ => panic!("Unexpected event"),
+			}
+		}
+		assert_eq!(channel_updates, 2);
+	}
+	assert_eq!(nodes[2].node.get_route_hints(None).len(), 2);
+
+	// Updating the forwarding policy of a private channel sends the new channel_update to our
+	// counterparty only, rather than broadcasting it.
+	let lsp_chan_id = nodes[1].node.list_channels().iter().find(|c| c.short_channel_id == Some(lsp_update.contents.short_channel_id)).unwrap().channel_id;
+	let mut config = UserConfig::default().channel_options;
+	config.fee_base_msat = Some(3_000);
+	config.fee_proportional_millionths = 100;
+	nodes[1].node.update_channel_config(&[lsp_chan_id], &config).unwrap();
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::SendChannelUpdate { ref node_id, ref msg } => {
+			assert_eq!(*node_id, nodes[2].node.get_our_node_id());
+			nodes[2].node.handle_channel_update(&nodes[1].node.get_our_node_id(), msg);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	let hint = nodes[2].node.get_route_hints(Some(3_000_000)).pop().unwrap();
+	assert_eq!(hint.short_channel_id, lsp_update.contents.short_channel_id);
+	assert_eq!(hint.fees.base_msat, 3_000);
+}
+
+#[test]
+fn test_retry_payment() {
+	// Test that a failed path can be retried via retry_payment, but only up to the value of the
+	// payment, and not once the payment has been claimed or abandoned.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let payment_id = nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	// The path is still pending, so retrying it would overpay the recipient.
+	match nodes[0].node.retry_payment(&route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("would overpay it")),
+		_ => panic!("Unexpected result"),
+	}
+
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 100000, payment_hash, None);
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash, &None));
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentFailed { payment_id: ref failed_id, all_paths_failed, .. } => {
+			assert_eq!(*failed_id, payment_id);
+			assert!(all_paths_failed);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// Now that it failed, the path may be retried, but the retry may not exceed the value of the
+	// payment.
+	let big_route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100001, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	match nodes[0].node.retry_payment(&big_route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("would overpay it")),
+		_ => panic!("Unexpected result"),
+	}
+	nodes[0].node.retry_payment(&route, payment_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 100000, payment_hash, None);
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage, 100000);
+
+	// Once claimed, the payment is forgotten and may not be retried.
+	match nodes[0].node.retry_payment(&route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("not found")),
+		_ => panic!("Unexpected result"),
+	}
+
+	// An abandoned payment may not be retried either, even once its path failed.
+	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
+	let payment_id_2 = nodes[0].node.send_payment(&route, payment_hash_2, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	nodes[0].node.abandon_payment(payment_id_2);
+	match nodes[0].node.retry_payment(&route, payment_id_2) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("abandoned")),
+		_ => panic!("Unexpected result"),
+	}
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 100000, payment_hash_2, None);
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash_2, &None));
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed!(nodes[0], payment_hash_2, true);
+	match nodes[0].node.retry_payment(&route, payment_id_2) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("not found")),
+		_ => panic!("Unexpected result"),
+	}
+}
+
+#[test]
+fn test_failed_payment_expires() {
+	// Test that a payment whose paths all failed is forgotten after FAILED_PAYMENT_EXPIRY_TICKS
+	// timer ticks without being retried, but not while any of its paths are pending.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	// Retrying along big_route always overpays the payment, but tells us whether it's still known.
+	let big_route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 100001, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	let payment_id = nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 100000, payment_hash, None);
+
+	// While the path is pending the payment is never forgotten.
+	for _ in 0..FAILED_PAYMENT_EXPIRY_TICKS {
+		nodes[0].node.timer_chan_freshness_every_min();
+	}
+	match nodes[0].node.retry_payment(&big_route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("would overpay it")),
+		_ => panic!("Unexpected result"),
+	}
+
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash, &None));
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed!(nodes[0], payment_hash, true);
+
+	// Retrying the failed payment resets its expiry.
+	for _ in 0..FAILED_PAYMENT_EXPIRY_TICKS - 1 {
+		nodes[0].node.timer_chan_freshness_every_min();
+	}
+	nodes[0].node.retry_payment(&route, payment_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 100000, payment_hash, None);
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash, &None));
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed!(nodes[0], payment_hash, true);
+
+	for _ in 0..FAILED_PAYMENT_EXPIRY_TICKS - 1 {
+		nodes[0].node.timer_chan_freshness_every_min();
+	}
+	match nodes[0].node.retry_payment(&big_route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("would overpay it")),
+		_ => panic!("Unexpected result"),
+	}
+	nodes[0].node.timer_chan_freshness_every_min();
+	match nodes[0].node.retry_payment(&route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("not found")),
+		_ => panic!("Unexpected result"),
+	}
+}
+
+#[test]
+fn test_stale_manager_tracks_monitor_payment_paths() {
+	// Test that a ChannelManager which was persisted before paths of its outbound payments were
+	// sent learns of them from the ChannelMonitors when it is loaded, so that their failure is
+	// reported and they can't be retried while they may still be claimed.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let logger: test_utils::TestLogger;
+	let fee_estimator: test_utils::TestFeeEstimator;
+	let persister: test_utils::TestPersister;
+	let new_chain_monitor: test_utils::TestChainMonitor;
+	let keys_manager: test_utils::TestKeysInterface;
+	let nodes_0_deserialized: ChannelManager<EnforcingChannelKeys, &test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>;
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (_, _, _, funding_tx) = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let route_logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let (_, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let route = get_route(&nodes[0].node.get_our_node_id(), &nodes[0].net_graph_msg_handler.network_graph.read().unwrap(), &nodes[1].node.get_our_node_id(), None, &Vec::new(), 1000000, TEST_FINAL_CLTV, &route_logger, &scorer).unwrap();
+	let payment_id = nodes[0].node.send_payment(&route, payment_hash, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 1000000, payment_hash, None);
+	assert!(nodes[1].node.fail_htlc_backwards(&payment_hash, &None));
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed!(nodes[0], payment_hash, true);
+
+	// Persist the ChannelManager while the payment has no pending path, then retry it and send
+	// a second payment it never learns of, keeping only the ChannelMonitor up-to-date.
+	let nodes_0_serialized = nodes[0].node.encode();
+	nodes[0].node.retry_payment(&route, payment_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 1000000, payment_hash, None);
+	let (_, payment_hash_2) = get_payment_preimage_hash!(nodes[0]);
+	let payment_id_2 = nodes[0].node.send_payment(&route, payment_hash_2, &None, &[]).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[1]]], 1000000, payment_hash_2, None);
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id(), false);
+
+	let mut chan_0_monitor_serialized = test_utils::TestVecWriter(Vec::new());
+	nodes[0].chain_monitor.chain_monitor.monitors.lock().unwrap().iter().next().unwrap().1.write(&mut chan_0_monitor_serialized).unwrap();
+
+	logger = test_utils::TestLogger::new();
+	fee_estimator = test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+	persister = test_utils::TestPersister::new();
+	new_chain_monitor = test_utils::TestChainMonitor::new(Some(nodes[0].chain_source), nodes[0].tx_broadcaster.clone(), &logger, &fee_estimator, &persister, None);
+	keys_manager = test_utils::TestKeysInterface::new(&nodes[0].node_seed, Network::Testnet);
+	nodes[0].chain_monitor = &new_chain_monitor;
+	let mut chan_0_monitor_read = &chan_0_monitor_serialized.0[..];
+	let (_, mut chan_0_monitor) = <(BlockHash, ChannelMonitor<EnforcingChannelKeys>)>::read(
+		&mut chan_0_monitor_read, &keys_manager).unwrap();
+	assert!(chan_0_monitor_read.is_empty());
+
+	let mut nodes_0_read = &nodes_0_serialized[..];
+	let (_, nodes_0_deserialized_tmp) = {
+		let mut channel_monitors = HashMap::new();
+		channel_monitors.insert(chan_0_monitor.get_funding_txo().0, &mut chan_0_monitor);
+		<(BlockHash, ChannelManager<EnforcingChannelKeys, &test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestLogger>)>::read(&mut nodes_0_read, ChannelManagerReadArgs {
+			default_config: UserConfig::default(),
+			keys_manager: &keys_manager,
+			fee_estimator: &fee_estimator,
+			chain_monitor: nodes[0].chain_monitor,
+			tx_broadcaster: nodes[0].tx_broadcaster.clone(),
+			logger: &logger,
+			channel_monitors,
+		}).unwrap()
+	};
+	nodes_0_deserialized = nodes_0_deserialized_tmp;
+	assert!(nodes_0_read.is_empty());
+
+	assert!(nodes[0].chain_monitor.watch_channel(chan_0_monitor.get_funding_txo().0, chan_0_monitor).is_ok());
+	nodes[0].node = &nodes_0_deserialized;
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "ChannelManager is stale compared to its ChannelMonitor".to_owned() });
+
+	// The retried path is pending again, and the unknown payment is tracked but may not be retried.
+	match nodes[0].node.retry_payment(&route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("would overpay it")),
+		_ => panic!("Unexpected result"),
+	}
+	match nodes[0].node.retry_payment(&route, payment_id_2) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("abandoned")),
+		_ => panic!("Unexpected result"),
+	}
+
+	// Our commitment transaction and both HTLC-timeout transactions were broadcast on load. Once
+	// the latter reach ANTI_REORG_DELAY, both paths fail back.
+	let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+	assert_eq!(node_txn.len(), 3);
+	check_spends!(node_txn[0], funding_tx);
+	check_spends!(node_txn[1], node_txn[0]);
+	check_spends!(node_txn[2], node_txn[0]);
+	let height = node_txn[1].lock_time;
+	let header = BlockHeader { version: 0x20000000, prev_blockhash: Default::default(), merkle_root: Default::default(), time: 42, bits: 42, nonce: 42 };
+	connect_block(&nodes[0], &Block { header, txdata: node_txn.clone() }, height);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 1, height, true, header.block_hash());
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	let mut failed_ids = HashSet::new();
+	for event in events.iter() {
+		match event {
+			&Event::PaymentFailed { ref payment_id, all_paths_failed, .. } => {
+				assert!(all_paths_failed);
+				failed_ids.insert(*payment_id);
+			},
+			_ => panic!("Unexpected event"),
+		}
+	}
+	assert!(failed_ids.contains(&payment_id));
+	assert!(failed_ids.contains(&payment_id_2));
+
+	// The failed payment may be retried (though not over the closed channel) while the unknown
+	// one is forgotten.
+	match nodes[0].node.retry_payment(&route, payment_id) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(!err.contains("not found")),
+		Err(_) => {},
+		Ok(()) => panic!("Unexpected result"),
+	}
+	match nodes[0].node.retry_payment(&route, payment_id_2) {
+		Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError { ref err })) => assert!(err.contains("not found")),
+		_ => panic!("Unexpected result"),
+	}
+}
+
+#[test]
+fn test_payment_retrier_retries_failed_path() {
+	// Test that PaymentRetrier retries a path which failed at an intermediate node over a route
+	// avoiding the failed channel, and only surfaces the final PaymentSent.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	// Make the path through nodes[2] more expensive, so that the payment is first attempted through
+	// nodes[1].
+	let mut expensive_config = UserConfig::default();
+	expensive_config.channel_options.announced_channel = true;
+	expensive_config.peer_channel_config_limits.force_announced_channel_preference = false;
+	expensive_config.channel_options.fee_proportional_millionths = 100_000;
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, Some(expensive_config), None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 0, 2, InitFeatures::known(), InitFeatures::known());
+	// nodes[1] pushes nearly all of its balance to nodes[3], so it can't forward the payment.
+	let chan_3 = create_announced_chan_between_nodes_with_value(&nodes, 1, 3, 100000, 99_000_000, InitFeatures::known(), InitFeatures::known());
+	create_announced_chan_between_nodes(&nodes, 2, 3, InitFeatures::known(), InitFeatures::known());
+
+	let retrier = PaymentRetrier::new(nodes[0].node, &nodes[0].net_graph_msg_handler, Scorer::default(), nodes[0].logger, 3, Duration::from_secs(60));
+	let (payment_preimage, payment_hash) = get_payment_preimage_hash!(nodes[0]);
+	let payment_id = retrier.pay(payment_hash, None, RouteParameters {
+		payee: nodes[3].node.get_our_node_id(),
+		last_hops: Vec::new(),
+		final_value_msat: 2_000_000,
+		final_cltv: TEST_FINAL_CLTV,
+		constraints: RouteConstraints::default(),
+	}).unwrap();
+	check_added_monitors!(nodes[0], 1);
+
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.pop().unwrap());
+	assert_eq!(payment_event.node_id, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	check_added_monitors!(nodes[1], 1);
+	let bs_fail_updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &bs_fail_updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], bs_fail_updates.commitment_signed, false, true);
+	let events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::PaymentFailureNetworkUpdate { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+
+	// The failure is swallowed by the retrier, which retries the payment through nodes[2].
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	assert!(retrier.handle_event(events[0].clone()).is_none());
+	assert!(retrier.scorer().channel_failure_penalty_msat(chan_3.0.contents.short_channel_id) > 0);
+	check_added_monitors!(nodes[0], 1);
+	pass_along_route(&nodes[0], &[&[&nodes[2], &nodes[3]]], 2_000_000, payment_hash, None);
+
+	assert!(nodes[3].node.claim_funds(payment_preimage, &None, 2_000_000));
+	check_added_monitors!(nodes[3], 1);
+	let ds_updates = get_htlc_update_msgs!(nodes[3], nodes[2].node.get_our_node_id());
+	nodes[2].node.handle_update_fulfill_htlc(&nodes[3].node.get_our_node_id(), &ds_updates.update_fulfill_htlcs[0]);
+	check_added_monitors!(nodes[2], 1);
+	let cs_updates = get_htlc_update_msgs!(nodes[2], nodes[0].node.get_our_node_id());
+	commitment_signed_dance!(nodes[2], nodes[3], ds_updates.commitment_signed, false);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &cs_updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[2], cs_updates.commitment_signed, false);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match retrier.handle_event(events[0].clone()) {
+		Some(Event::PaymentSent { payment_preimage: preimage, payment_id: sent_id, .. }) => {
+			assert_eq!(preimage, payment_preimage);
+			assert_eq!(sent_id, payment_id);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_rebalance_payment() {
+	// Test that a rebalance moves liquidity from one of our channels to another along a cycle,
+	// settling without a PaymentReceived event and reporting the fee paid.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan_1 = create_announced_chan_between_nodes(&nodes, 0, 1, InitFeatures::known(), InitFeatures::known());
+	let chan_2 = create_announced_chan_between_nodes(&nodes, 1, 2, InitFeatures::known(), InitFeatures::known());
+	let chan_3 = create_announced_chan_between_nodes(&nodes, 2, 0, InitFeatures::known(), InitFeatures::known());
+	nodes[0].node.handle_channel_update(&nodes[2].node.get_our_node_id(), &chan_3.0);
+	let outbound_scid = chan_1.0.contents.short_channel_id;
+	let inbound_scid = chan_3.0.contents.short_channel_id;
+	let logger = test_utils::TestLogger::new();
+	let scorer = Scorer::with_fixed_penalty(0);
+
+	let channel_by_scid = |scid: u64| nodes[0].node.list_channels().into_iter().find(|chan| chan.short_channel_id == Some(scid)).unwrap();
+	let outbound_chan_capacity_msat = channel_by_scid(outbound_scid).outbound_capacity_msat;
+	let inbound_chan_capacity_msat = channel_by_scid(inbound_scid).outbound_capacity_msat;
+
+	let usable_channels = nodes[0].node.list_usable_channels();
+	let first_hops: Vec<_> = usable_channels.iter().collect();
+	let network_graph = nodes[0].net_graph_msg_handler.network_graph.read().unwrap();
+	match get_rebalance_route(&nodes[0].node.get_our_node_id(), &network_graph, &first_hops, outbound_scid, outbound_scid, 100000, TEST_FINAL_CLTV, &RouteConstraints::default(), &logger, &scorer) {
+		Err(msgs::LightningError { ref err, .. }) => assert_eq!(err, "Cannot rebalance a channel into itself"),
+		Ok(_) => panic!("Expected error"),
+	}
+	let route = get_rebalance_route(&nodes[0].node.get_our_node_id(), &network_graph, &first_hops, outbound_scid, inbound_scid, 100000, TEST_FINAL_CLTV, &RouteConstraints::default(), &logger, &scorer).unwrap();
+	let normal_route = get_route(&nodes[0].node.get_our_node_id(), &network_graph, &nodes[2].node.get_our_node_id(), Some(&first_hops), &Vec::new(), 100000, TEST_FINAL_CLTV, &logger, &scorer).unwrap();
+	mem::drop(network_graph);
+
+	assert_eq!(route.paths.len(), 1);
+	let path = &route.paths[0];
+	assert_eq!(path.len(), 3);
+	assert_eq!(path[0].short_channel_id, outbound_scid);
+	assert_eq!(path[2].pubkey, nodes[0].node.get_our_node_id());
+	assert_eq!(path[2].short_channel_id, inbound_scid);
+	assert_eq!(path[2].fee_msat, 100000);
+	assert_eq!(path[1].fee_msat, chan_3.0.contents.fee_base_msat as u64 + chan_3.0.contents.fee_proportional_millionths as u64 * 100000 / 1000000);
+	assert_eq!(path[0].fee_msat, chan_2.0.contents.fee_base_msat as u64 + chan_2.0.contents.fee_proportional_millionths as u64 * (100000 + path[1].fee_msat) / 1000000);
+	let expected_fee_msat = path[0].fee_msat + path[1].fee_msat;
+
+	// Routes which don't come back to us can't be used to rebalance.
+	match nodes[0].node.send_rebalance(&normal_route) {
+		Err(PaymentSendFailure::ParameterError(APIError::RouteError { err })) => assert_eq!(err, "Rebalance paths must leave us and end at us"),
+		_ => panic!("Unexpected result"),
+	}
+
+	let payment_id = nodes[0].node.send_rebalance(&route).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	// The payment hash is only used by pass_along_path when a PaymentReceived is expected.
+	pass_along_path(&nodes[0], &[&nodes[1], &nodes[2], &nodes[0]], 100000, PaymentHash([0; 32]), None, events.remove(0), false);
+	// Once the payment arrives back at us it is claimed without any user involvement.
+	check_added_monitors!(nodes[0], 1);
+
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[2].node.get_our_node_id());
+	nodes[2].node.handle_update_fulfill_htlc(&nodes[0].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	check_added_monitors!(nodes[2], 1);
+	let updates_2 = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+	commitment_signed_dance!(nodes[2], nodes[0], updates.commitment_signed, false);
+	nodes[1].node.handle_update_fulfill_htlc(&nodes[2].node.get_our_node_id(), &updates_2.update_fulfill_htlcs[0]);
+	check_added_monitors!(nodes[1], 1);
+	let updates_3 = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	commitment_signed_dance!(nodes[1], nodes[2], updates_2.commitment_signed, false);
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates_3.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates_3.commitment_signed, false);
+
+	let events = nodes[0].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentSent { payment_id: sent_id, fee_paid_msat, .. } => {
+			assert_eq!(sent_id, payment_id);
+			assert_eq!(fee_paid_msat, expected_fee_msat);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	assert_eq!(channel_by_scid(outbound_scid).outbound_capacity_msat, outbound_chan_capacity_msat - 100000 - expected_fee_msat);
+	assert_eq!(channel_by_scid(inbound_scid).outbound_capacity_msat, inbound_chan_capacity_msat + 100000);
+}
@@ -0,0 +1,689 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Interactive construction of the funding transaction of dual-funded channels and of splice
+//! transactions. Both peers take turns adding inputs and outputs with tx_add_input and
+//! tx_add_output until both have sent tx_complete, after which each side signs its own inputs and
+//! sends them in tx_signatures.
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{OutPoint as BitcoinOutPoint, Transaction, TxIn, TxOut};
+use bitcoin::secp256k1::key::PublicKey;
+use bitcoin::secp256k1::Signature;
+
+use chain::chaininterface::{UtxoSource, WalletUtxo};
+use ln::msgs;
+use ln::msgs::DecodeError;
+use util::events::MessageSendEvent;
+use util::ser::{Readable, Writeable, Writer};
+
+use std::cmp;
+use std::collections::VecDeque;
+
+/// The maximum number of inputs or outputs each peer may add to a funding transaction.
+const MAX_INPUTS_OUTPUTS_COUNT: usize = 252;
+/// The maximum number of tx_add_input, and separately tx_add_output, messages we accept from our
+/// counterparty while constructing a funding transaction. As inputs and outputs may be removed,
+/// this bounds the length of the negotiation rather than the size of the transaction.
+const MAX_RECEIVED_TX_ADD_INPUT_OUTPUT_COUNT: u16 = 4096;
+/// The minimum value of the outputs either peer may add, other than the funding output.
+const MIN_OUTPUT_VALUE_SATOSHIS: u64 = 546;
+/// The total supply of bitcoin, which no single output may exceed.
+const MAX_VALUE_SATOSHIS: u64 = 21_000_000_0000_0000;
+/// The nSequence of the inputs we contribute, which signals replaceability.
+const FUNDING_INPUT_SEQUENCE: u32 = 0xfffffffd;
+
+/// The weight of the version, locktime, input and output counts and segwit marker and flag of a
+/// funding transaction, which are paid for by the channel initiator.
+const TX_COMMON_FIELDS_WEIGHT: u64 = (4 + 4 + 1 + 1) * 4 + 2;
+/// The weight of an input, excluding its witness.
+const INPUT_BASE_WEIGHT: u64 = (32 + 4 + 1 + 4) * 4;
+/// The witness weight our counterparty pays for each of its inputs, which must all be P2WPKH.
+const P2WPKH_WITNESS_WEIGHT: u64 = 1 + 1 + 73 + 1 + 33;
+/// The weight of the P2WSH funding output, which is paid for by the channel initiator.
+const FUNDING_OUTPUT_WEIGHT: u64 = (8 + 1 + 34) * 4;
+/// The witness weight of the input spending the current funding output in a splice transaction,
+/// which is paid for by the splice initiator.
+pub(crate) const SHARED_INPUT_WITNESS_WEIGHT: u64 = 1 + 1 + 1 + 73 + 1 + 73 + 1 + 71;
+
+fn output_weight(script: &Script) -> u64 {
+	(8 + 1 + script.len() as u64) * 4
+}
+
+fn fee_for_weight(feerate_per_kw: u32, weight: u64) -> u64 {
+	feerate_per_kw as u64 * weight / 1000
+}
+
+/// A message we send while constructing a funding transaction.
+pub(crate) enum InteractiveTxMessage {
+	AddInput(msgs::TxAddInput),
+	AddOutput(msgs::TxAddOutput),
+	Complete(msgs::TxComplete),
+	/// Aborts the construction of a splice transaction.
+	Abort(msgs::TxAbort),
+}
+
+impl InteractiveTxMessage {
+	pub(crate) fn into_msg_send_event(self, node_id: PublicKey) -> MessageSendEvent {
+		match self {
+			InteractiveTxMessage::AddInput(msg) => MessageSendEvent::SendTxAddInput { node_id, msg },
+			InteractiveTxMessage::AddOutput(msg) => MessageSendEvent::SendTxAddOutput { node_id, msg },
+			InteractiveTxMessage::Complete(msg) => MessageSendEvent::SendTxComplete { node_id, msg },
+			InteractiveTxMessage::Abort(msg) => MessageSendEvent::SendTxAbort { node_id, msg },
+		}
+	}
+}
+
+/// The inputs and outputs we contribute to a funding or splice transaction.
+pub(crate) struct FundingContribution {
+	/// The amount we add to our channel balance from our inputs.
+	pub(crate) value_satoshis: u64,
+	/// The amount we take out of our channel balance when splicing out, including the fee for our
+	/// share of the splice transaction.
+	pub(crate) withdrawal_satoshis: u64,
+	pub(crate) feerate_per_kw: u32,
+	inputs: Vec<WalletUtxo>,
+	outputs: Vec<TxOut>,
+}
+
+impl FundingContribution {
+	/// A contribution of nothing, for when we accept a dual-funded channel without funding it.
+	pub(crate) fn empty(feerate_per_kw: u32) -> Self {
+		FundingContribution {
+			value_satoshis: 0,
+			withdrawal_satoshis: 0,
+			feerate_per_kw,
+			inputs: Vec::new(),
+			outputs: Vec::new(),
+		}
+	}
+
+	/// The change to our channel balance this contribution makes.
+	pub(crate) fn relative_satoshis(&self) -> i64 {
+		self.value_satoshis as i64 - self.withdrawal_satoshis as i64
+	}
+
+	/// Selects inputs from the wallet covering value_satoshis plus the fee for our inputs and
+	/// outputs at the given feerate. If we're the channel initiator we also pay for the common
+	/// transaction fields and the funding output.
+	pub(crate) fn new(wallet: &UtxoSource, value_satoshis: u64, feerate_per_kw: u32, is_initiator: bool) -> Result<Self, String> {
+		let weight = if is_initiator { TX_COMMON_FIELDS_WEIGHT + FUNDING_OUTPUT_WEIGHT } else { 0 };
+		Self::with_inputs(wallet, value_satoshis, feerate_per_kw, weight)
+	}
+
+	/// Selects inputs from the wallet covering value_satoshis plus the fee for a splice
+	/// transaction we initiate, ie including the common transaction fields, the input spending the
+	/// current funding output and the new funding output.
+	pub(crate) fn splice_in(wallet: &UtxoSource, value_satoshis: u64, feerate_per_kw: u32) -> Result<Self, String> {
+		let weight = TX_COMMON_FIELDS_WEIGHT + INPUT_BASE_WEIGHT + SHARED_INPUT_WITNESS_WEIGHT + FUNDING_OUTPUT_WEIGHT;
+		Self::with_inputs(wallet, value_satoshis, feerate_per_kw, weight)
+	}
+
+	/// An output paying value_satoshis to script_pubkey out of our channel balance, in a splice
+	/// transaction we initiate. The fee for the whole splice transaction is taken from our channel
+	/// balance as well.
+	pub(crate) fn splice_out(value_satoshis: u64, script_pubkey: Script, feerate_per_kw: u32) -> Result<Self, String> {
+		if value_satoshis < MIN_OUTPUT_VALUE_SATOSHIS {
+			return Err(format!("Cannot splice out a dust output of {} sat", value_satoshis));
+		}
+		let weight = TX_COMMON_FIELDS_WEIGHT + INPUT_BASE_WEIGHT + SHARED_INPUT_WITNESS_WEIGHT + FUNDING_OUTPUT_WEIGHT + output_weight(&script_pubkey);
+		Ok(FundingContribution {
+			value_satoshis: 0,
+			withdrawal_satoshis: value_satoshis + fee_for_weight(feerate_per_kw, weight),
+			feerate_per_kw,
+			inputs: Vec::new(),
+			outputs: vec![TxOut { script_pubkey, value: value_satoshis }],
+		})
+	}
+
+	fn with_inputs(wallet: &UtxoSource, value_satoshis: u64, feerate_per_kw: u32, mut weight: u64) -> Result<Self, String> {
+		let change_script = wallet.get_change_script();
+		let change_weight = output_weight(&change_script);
+
+		// Guess a single input for the wallet's benefit, the actual fee is computed below.
+		let target_value = value_satoshis + fee_for_weight(feerate_per_kw, weight + INPUT_BASE_WEIGHT + P2WPKH_WITNESS_WEIGHT + change_weight);
+		let inputs = wallet.get_utxos(target_value);
+		if inputs.len() > MAX_INPUTS_OUTPUTS_COUNT {
+			return Err("Funding wallet provided too many inputs".to_owned());
+		}
+		let mut input_value = 0;
+		for input in inputs.iter() {
+			match input.prev_tx.output.get(input.prev_vout as usize) {
+				Some(prev_output) if prev_output.script_pubkey.is_witness_program() => {
+					input_value += prev_output.value;
+				},
+				_ => return Err("Funding wallet provided an input which is missing or not SegWit".to_owned()),
+			}
+			weight += INPUT_BASE_WEIGHT + input.satisfaction_weight;
+		}
+
+		let fee_with_change = fee_for_weight(feerate_per_kw, weight + change_weight);
+		let mut outputs = Vec::new();
+		if input_value >= value_satoshis + fee_with_change + MIN_OUTPUT_VALUE_SATOSHIS {
+			outputs.push(TxOut {
+				script_pubkey: change_script,
+				value: input_value - value_satoshis - fee_with_change,
+			});
+		} else if input_value < value_satoshis + fee_for_weight(feerate_per_kw, weight) {
+			return Err(format!("Funding wallet provided {} sat, which is not enough to contribute {} sat at a feerate of {} sat/kw", input_value, value_satoshis, feerate_per_kw));
+		};
+
+		// Otherwise change would be dust, leave it to the fee instead
+
+		Ok(FundingContribution {
+			value_satoshis,
+			withdrawal_satoshis: 0,
+			feerate_per_kw,
+			inputs,
+			outputs,
+		})
+	}
+}
+
+/// Tracks the inputs and outputs added by each peer to a funding transaction under construction
+/// and the messages we have yet to send.
+pub(crate) struct InteractiveTxConstructor {
+	channel_id: [u8; 32],
+	is_initiator: bool,
+	feerate_per_kw: u32,
+	locktime: u32,
+	funding_script: Script,
+	funding_value_satoshis: u64,
+	counterparty_relative_satoshis: i64,
+	shared_outpoint: Option<BitcoinOutPoint>,
+
+	pending_holder_msgs: VecDeque<InteractiveTxMessage>,
+	holder_funding_inputs: Vec<(u64, WalletUtxo)>,
+	holder_inputs: Vec<msgs::TxAddInput>,
+	holder_outputs: Vec<msgs::TxAddOutput>,
+	counterparty_inputs: Vec<msgs::TxAddInput>,
+	counterparty_outputs: Vec<msgs::TxAddOutput>,
+	received_tx_add_input_count: u16,
+	received_tx_add_output_count: u16,
+
+	holder_turn: bool,
+	sent_complete: bool,
+	received_complete: bool,
+}
+
+impl InteractiveTxConstructor {
+	/// Starts constructing a funding transaction paying funding_value_satoshis to funding_script,
+	/// of which counterparty_relative_satoshis are contributed by our counterparty and the rest by
+	/// holder_contribution. The initiator adds the funding output and sends the first message.
+	///
+	/// When splicing, shared_input spends the current funding output, which the initiator adds
+	/// and pays for, and the contributions are relative to the current channel balances.
+	pub(crate) fn new(channel_id: [u8; 32], is_initiator: bool, locktime: u32, funding_script: Script, funding_value_satoshis: u64, counterparty_relative_satoshis: i64, shared_input: Option<WalletUtxo>, holder_contribution: FundingContribution) -> Self {
+		let mut serial_id = if is_initiator { 0 } else { 1 };
+		let mut pending_holder_msgs = VecDeque::new();
+		let shared_outpoint = shared_input.as_ref().map(|input| BitcoinOutPoint { txid: input.prev_tx.txid(), vout: input.prev_vout });
+		if is_initiator {
+			if let Some(input) = shared_input {
+				pending_holder_msgs.push_back(InteractiveTxMessage::AddInput(msgs::TxAddInput {
+					channel_id,
+					serial_id,
+					prevtx: input.prev_tx,
+					prevtx_out: input.prev_vout,
+					sequence: FUNDING_INPUT_SEQUENCE,
+				}));
+				serial_id += 2;
+			}
+		}
+		let mut holder_funding_inputs = Vec::with_capacity(holder_contribution.inputs.len());
+		for input in holder_contribution.inputs {
+			pending_holder_msgs.push_back(InteractiveTxMessage::AddInput(msgs::TxAddInput {
+				channel_id,
+				serial_id,
+				prevtx: input.prev_tx.clone(),
+				prevtx_out: input.prev_vout,
+				sequence: FUNDING_INPUT_SEQUENCE,
+			}));
+			holder_funding_inputs.push((serial_id, input));
+			serial_id += 2;
+		}
+		let mut outputs = Vec::new();
+		if is_initiator {
+			outputs.push(TxOut { script_pubkey: funding_script.clone(), value: funding_value_satoshis });
+		}
+		outputs.extend(holder_contribution.outputs);
+		for output in outputs {
+			pending_holder_msgs.push_back(InteractiveTxMessage::AddOutput(msgs::TxAddOutput {
+				channel_id,
+				serial_id,
+				sats: output.value,
+				script: output.script_pubkey,
+			}));
+			serial_id += 2;
+		}
+
+		InteractiveTxConstructor {
+			channel_id,
+			is_initiator,
+			feerate_per_kw: holder_contribution.feerate_per_kw,
+			locktime,
+			funding_script,
+			funding_value_satoshis,
+			counterparty_relative_satoshis,
+			shared_outpoint,
+
+			pending_holder_msgs,
+			holder_funding_inputs,
+			holder_inputs: Vec::new(),
+			holder_outputs: Vec::new(),
+			counterparty_inputs: Vec::new(),
+			counterparty_outputs: Vec::new(),
+			received_tx_add_input_count: 0,
+			received_tx_add_output_count: 0,
+
+			holder_turn: is_initiator,
+			sent_complete: false,
+			received_complete: false,
+		}
+	}
+
+	/// Gets the next message to send, which must only be called when it's our turn.
+	pub(crate) fn next_message(&mut self) -> InteractiveTxMessage {
+		assert!(self.holder_turn);
+		self.holder_turn = false;
+		match self.pending_holder_msgs.pop_front() {
+			Some(msg) => {
+				self.sent_complete = false;
+				match msg {
+					InteractiveTxMessage::AddInput(ref msg) => self.holder_inputs.push(msg.clone()),
+					InteractiveTxMessage::AddOutput(ref msg) => self.holder_outputs.push(msg.clone()),
+					InteractiveTxMessage::Complete(_) | InteractiveTxMessage::Abort(_) => unreachable!(),
+				}
+				msg
+			},
+			None => {
+				self.sent_complete = true;
+				InteractiveTxMessage::Complete(msgs::TxComplete { channel_id: self.channel_id })
+			},
+		}
+	}
+
+	fn is_complete(&self) -> bool {
+		self.sent_complete && self.received_complete
+	}
+
+	/// Replies to a message from our counterparty, returning whether construction is complete.
+	fn respond(&mut self) -> (Option<InteractiveTxMessage>, bool) {
+		self.holder_turn = true;
+		if self.is_complete() {
+			return (None, true);
+		}
+		let msg = self.next_message();
+		(Some(msg), self.is_complete())
+	}
+
+	fn check_counterparty_turn(&self) -> Result<(), String> {
+		if self.holder_turn || self.is_complete() {
+			return Err("Peer sent a funding transaction construction message out of turn".to_owned());
+		}
+		Ok(())
+	}
+
+	fn check_counterparty_serial_id(&self, serial_id: u64) -> Result<(), String> {
+		self.check_counterparty_turn()?;
+		if (serial_id % 2 == 1) != self.is_initiator {
+			return Err(format!("Peer used a serial_id of the wrong parity ({})", serial_id));
+		}
+		if self.counterparty_inputs.iter().any(|input| input.serial_id == serial_id) || self.counterparty_outputs.iter().any(|output| output.serial_id == serial_id) {
+			return Err(format!("Peer used duplicate serial_id {}", serial_id));
+		}
+		Ok(())
+	}
+
+	/// Handles a tx_add_input from our counterparty, returning our reply, if any, and whether
+	/// construction is complete.
+	pub(crate) fn handle_tx_add_input(&mut self, msg: &msgs::TxAddInput) -> Result<(Option<InteractiveTxMessage>, bool), String> {
+		self.check_counterparty_serial_id(msg.serial_id)?;
+		if self.received_tx_add_input_count >= MAX_RECEIVED_TX_ADD_INPUT_OUTPUT_COUNT {
+			return Err("Peer sent too many tx_add_input messages".to_owned());
+		}
+		self.received_tx_add_input_count += 1;
+		if self.counterparty_inputs.len() >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err("Peer added too many inputs to the funding transaction".to_owned());
+		}
+		let prev_txid = msg.prevtx.txid();
+		let is_shared_input = self.shared_outpoint == Some(BitcoinOutPoint { txid: prev_txid, vout: msg.prevtx_out });
+		if is_shared_input && self.is_initiator {
+			return Err("Peer added the input spending the funding output of a splice it didn't initiate".to_owned());
+		}
+		// We can only check that our counterparty pays for the weight of its inputs before it
+		// signs them if we know their witness weight, so only P2WPKH inputs are accepted.
+		match msg.prevtx.output.get(msg.prevtx_out as usize) {
+			Some(prev_output) if (is_shared_input || prev_output.script_pubkey.is_v0_p2wpkh()) && prev_output.value <= MAX_VALUE_SATOSHIS => {},
+			_ => return Err("Peer added an input spending a missing, non-P2WPKH or invalid output".to_owned()),
+		}
+		if self.holder_inputs.iter().chain(self.counterparty_inputs.iter()).any(|input| input.prevtx_out == msg.prevtx_out && input.prevtx.txid() == prev_txid) {
+			return Err("Peer added an input spending an output which is already spent by the funding transaction".to_owned());
+		}
+		self.counterparty_inputs.push(msg.clone());
+		self.received_complete = false;
+		Ok(self.respond())
+	}
+
+	/// Handles a tx_add_output from our counterparty, returning our reply, if any, and whether
+	/// construction is complete.
+	pub(crate) fn handle_tx_add_output(&mut self, msg: &msgs::TxAddOutput) -> Result<(Option<InteractiveTxMessage>, bool), String> {
+		self.check_counterparty_serial_id(msg.serial_id)?;
+		if self.received_tx_add_output_count >= MAX_RECEIVED_TX_ADD_INPUT_OUTPUT_COUNT {
+			return Err("Peer sent too many tx_add_output messages".to_owned());
+		}
+		self.received_tx_add_output_count += 1;
+		if self.counterparty_outputs.len() >= MAX_INPUTS_OUTPUTS_COUNT {
+			return Err("Peer added too many outputs to the funding transaction".to_owned());
+		}
+		if msg.sats < MIN_OUTPUT_VALUE_SATOSHIS && msg.script != self.funding_script {
+			return Err(format!("Peer added a dust output of {} sat", msg.sats));
+		}
+		if msg.sats > MAX_VALUE_SATOSHIS {
+			return Err("Peer added an output with a value larger than the total supply".to_owned());
+		}
+		self.counterparty_outputs.push(msg.clone());
+		self.received_complete = false;
+		Ok(self.respond())
+	}
+
+	/// Handles a tx_remove_input from our counterparty, returning our reply, if any, and whether
+	/// construction is complete.
+	pub(crate) fn handle_tx_remove_input(&mut self, msg: &msgs::TxRemoveInput) -> Result<(Option<InteractiveTxMessage>, bool), String> {
+		self.check_counterparty_turn()?;
+		match self.counterparty_inputs.iter().position(|input| input.serial_id == msg.serial_id) {
+			Some(idx) => { self.counterparty_inputs.remove(idx); },
+			None => return Err(format!("Peer removed input {} which it didn't add", msg.serial_id)),
+		}
+		self.received_complete = false;
+		Ok(self.respond())
+	}
+
+	/// Handles a tx_remove_output from our counterparty, returning our reply, if any, and whether
+	/// construction is complete.
+	pub(crate) fn handle_tx_remove_output(&mut self, msg: &msgs::TxRemoveOutput) -> Result<(Option<InteractiveTxMessage>, bool), String> {
+		self.check_counterparty_turn()?;
+		match self.counterparty_outputs.iter().position(|output| output.serial_id == msg.serial_id) {
+			Some(idx) => { self.counterparty_outputs.remove(idx); },
+			None => return Err(format!("Peer removed output {} which it didn't add", msg.serial_id)),
+		}
+		self.received_complete = false;
+		Ok(self.respond())
+	}
+
+	/// Handles a tx_complete from our counterparty, returning our reply, if any, and whether
+	/// construction is complete.
+	pub(crate) fn handle_tx_complete(&mut self, _msg: &msgs::TxComplete) -> Result<(Option<InteractiveTxMessage>, bool), String> {
+		self.check_counterparty_turn()?;
+		self.received_complete = true;
+		Ok(self.respond())
+	}
+
+	/// Checks the constructed funding transaction once both peers have sent tx_complete, and
+	/// returns it to be signed.
+	pub(crate) fn finish(self) -> Result<FundingTxSigning, String> {
+		assert!(self.is_complete());
+
+		let mut funding_output_count = 0;
+		for output in self.holder_outputs.iter().chain(self.counterparty_outputs.iter()) {
+			if output.script == self.funding_script {
+				if output.sats != self.funding_value_satoshis {
+					return Err(format!("Funding output has a value of {} sat, expected {} sat", output.sats, self.funding_value_satoshis));
+				}
+				funding_output_count += 1;
+			}
+		}
+		if funding_output_count != 1 {
+			return Err("Funding transaction must have exactly one funding output".to_owned());
+		}
+
+		let shared_outpoint = self.shared_outpoint;
+		let is_shared_input = |input: &msgs::TxAddInput| shared_outpoint == Some(BitcoinOutPoint { txid: input.prevtx.txid(), vout: input.prevtx_out });
+		if shared_outpoint.is_some() && !self.holder_inputs.iter().chain(self.counterparty_inputs.iter()).any(|input| is_shared_input(input)) {
+			return Err("Splice transaction must spend the current funding output".to_owned());
+		}
+
+		// Check our counterparty pays for its share of the transaction, our own share was checked
+		// when selecting our inputs. The value of the shared input belongs to the channel, not the
+		// peer which added it.
+		let mut counterparty_input_value = 0;
+		let mut counterparty_weight = if self.is_initiator { 0 } else { TX_COMMON_FIELDS_WEIGHT };
+		for input in self.counterparty_inputs.iter() {
+			if is_shared_input(input) {
+				counterparty_weight += INPUT_BASE_WEIGHT + SHARED_INPUT_WITNESS_WEIGHT;
+			} else {
+				counterparty_input_value += input.prevtx.output[input.prevtx_out as usize].value;
+				counterparty_weight += INPUT_BASE_WEIGHT + P2WPKH_WITNESS_WEIGHT;
+			}
+		}
+		let mut counterparty_output_value = 0;
+		for output in self.counterparty_outputs.iter() {
+			if output.script != self.funding_script {
+				counterparty_output_value += output.sats;
+			}
+			counterparty_weight += output_weight(&output.script);
+		}
+		let counterparty_required_value = counterparty_output_value as i64 + self.counterparty_relative_satoshis + fee_for_weight(self.feerate_per_kw, counterparty_weight) as i64;
+		if (counterparty_input_value as i64) < counterparty_required_value {
+			return Err(format!("Peer's inputs to the funding transaction total {} sat, but its contribution, outputs and fee require {} sat", counterparty_input_value, counterparty_required_value));
+		}
+
+		let mut inputs: Vec<&msgs::TxAddInput> = self.holder_inputs.iter().chain(self.counterparty_inputs.iter()).collect();
+		inputs.sort_unstable_by_key(|input| input.serial_id);
+		let mut outputs: Vec<&msgs::TxAddOutput> = self.holder_outputs.iter().chain(self.counterparty_outputs.iter()).collect();
+		outputs.sort_unstable_by_key(|output| output.serial_id);
+
+		let mut holder_inputs = Vec::with_capacity(self.holder_funding_inputs.len());
+		for &(serial_id, ref funding_input) in self.holder_funding_inputs.iter() {
+			let idx = inputs.iter().position(|input| input.serial_id == serial_id).unwrap();
+			holder_inputs.push((idx as u32, funding_input.clone()));
+		}
+		let funding_output_index = outputs.iter().position(|output| output.script == self.funding_script).unwrap() as u16;
+		let shared_input_index = inputs.iter().position(|input| is_shared_input(input)).map(|idx| idx as u32);
+
+		let tx = Transaction {
+			version: 2,
+			lock_time: self.locktime,
+			input: inputs.iter().map(|input| TxIn {
+				previous_output: BitcoinOutPoint { txid: input.prevtx.txid(), vout: input.prevtx_out },
+				script_sig: Script::new(),
+				sequence: input.sequence,
+				witness: Vec::new(),
+			}).collect(),
+			output: outputs.iter().map(|output| TxOut {
+				script_pubkey: output.script.clone(),
+				value: output.sats,
+			}).collect(),
+		};
+
+		let holder_input_value: u64 = self.holder_inputs.iter().filter(|input| !is_shared_input(input)).map(|input| input.prevtx.output[input.prevtx_out as usize].value).sum();
+		// The peer contributing the least sends its signatures first, so that it doesn't risk its
+		// inputs being spent by a transaction it can't broadcast.
+		let holder_sends_first = holder_input_value < counterparty_input_value || (holder_input_value == counterparty_input_value && self.is_initiator);
+
+		Ok(FundingTxSigning {
+			tx,
+			funding_output_index,
+			holder_inputs,
+			holder_sends_first,
+			holder_signed: false,
+			counterparty_signed: false,
+			shared_input_index,
+			holder_shared_input_signature: None,
+			counterparty_shared_input_signature: None,
+		})
+	}
+}
+
+/// A constructed funding transaction, for which we are exchanging signatures with our
+/// counterparty. Both peers' witnesses are filled in as we get them, except for the input spending
+/// the current funding output of a spliced channel, whose witness is built by the channel from
+/// both peers' signatures.
+pub(crate) struct FundingTxSigning {
+	pub(crate) tx: Transaction,
+	pub(crate) funding_output_index: u16,
+	holder_inputs: Vec<(u32, WalletUtxo)>,
+	holder_sends_first: bool,
+	holder_signed: bool,
+	counterparty_signed: bool,
+	shared_input_index: Option<u32>,
+	holder_shared_input_signature: Option<Signature>,
+	counterparty_shared_input_signature: Option<Signature>,
+}
+
+impl FundingTxSigning {
+	/// Returns whether we should sign our inputs, ie once we sent our signature for the initial
+	/// commitment transaction if we send our signatures first, or else once we got our
+	/// counterparty's.
+	pub(crate) fn should_sign(&self) -> bool {
+		!self.holder_signed && (self.holder_sends_first || self.counterparty_signed)
+	}
+
+	pub(crate) fn is_holder_signed(&self) -> bool {
+		self.holder_signed
+	}
+
+	pub(crate) fn is_fully_signed(&self) -> bool {
+		self.holder_signed && self.counterparty_signed
+	}
+
+	/// The index of the input spending the current funding output, if this is a splice
+	/// transaction.
+	pub(crate) fn shared_input_index(&self) -> Option<usize> {
+		self.shared_input_index.map(|idx| idx as usize)
+	}
+
+	/// Both peers' signatures for the input spending the current funding output, once we have
+	/// them.
+	pub(crate) fn shared_input_signatures(&self) -> Option<(Signature, Signature)> {
+		match (self.holder_shared_input_signature, self.counterparty_shared_input_signature) {
+			(Some(holder_sig), Some(counterparty_sig)) => Some((holder_sig, counterparty_sig)),
+			_ => None,
+		}
+	}
+
+	/// Signs our inputs with the wallet. shared_input_signature must be our signature for the
+	/// input spending the current funding output if this is a splice transaction.
+	pub(crate) fn sign(&mut self, wallet: Option<&UtxoSource>, shared_input_signature: Option<Signature>) -> Result<(), String> {
+		assert!(!self.holder_signed);
+		assert_eq!(self.shared_input_index.is_some(), shared_input_signature.is_some());
+		if !self.holder_inputs.is_empty() {
+			let wallet = match wallet {
+				Some(wallet) => wallet,
+				None => return Err("No funding wallet to sign our inputs to the funding transaction".to_owned()),
+			};
+			let mut witnesses = Vec::with_capacity(self.holder_inputs.len());
+			for &(idx, ref input) in self.holder_inputs.iter() {
+				match wallet.sign_input(&self.tx, idx as usize, input) {
+					Ok(witness) => witnesses.push((idx as usize, witness)),
+					Err(_) => return Err("Funding wallet failed to sign our input to the funding transaction".to_owned()),
+				}
+			}
+			for (idx, witness) in witnesses {
+				self.tx.input[idx].witness = witness;
+			}
+		}
+		self.holder_shared_input_signature = shared_input_signature;
+		self.holder_signed = true;
+		Ok(())
+	}
+
+	/// Gets our tx_signatures, once we've signed our inputs.
+	pub(crate) fn get_tx_signatures(&self, channel_id: [u8; 32]) -> Option<msgs::TxSignatures> {
+		if !self.holder_signed {
+			return None;
+		}
+		Some(msgs::TxSignatures {
+			channel_id,
+			txid: self.tx.txid(),
+			witnesses: self.holder_inputs.iter().map(|&(idx, _)| self.tx.input[idx as usize].witness.clone()).collect(),
+			shared_input_signature: self.holder_shared_input_signature,
+		})
+	}
+
+	/// Fills in the witnesses of our counterparty's inputs from its tx_signatures. Its signature
+	/// for the input spending the current funding output must have been checked by the caller.
+	pub(crate) fn provide_counterparty_signatures(&mut self, msg: &msgs::TxSignatures) -> Result<(), String> {
+		if self.counterparty_signed {
+			return Err("Peer sent duplicate tx_signatures".to_owned());
+		}
+		if self.holder_sends_first && !self.holder_signed {
+			return Err("Peer sent tx_signatures before we sent ours".to_owned());
+		}
+		if msg.txid != self.tx.txid() {
+			return Err("Peer sent tx_signatures for a different funding transaction".to_owned());
+		}
+		let shared_input_signature = match (self.shared_input_index, msg.shared_input_signature) {
+			(Some(_), Some(sig)) => Some(sig),
+			(None, None) => None,
+			_ => return Err("Peer sent tx_signatures with a missing or unexpected signature for the shared input".to_owned()),
+		};
+		let counterparty_input_idxs: Vec<usize> = (0..self.tx.input.len()).filter(|idx| Some(*idx as u32) != self.shared_input_index && !self.holder_inputs.iter().any(|&(holder_idx, _)| holder_idx as usize == *idx)).collect();
+		if msg.witnesses.len() != counterparty_input_idxs.len() {
+			return Err(format!("Peer sent {} witnesses for its {} inputs to the funding transaction", msg.witnesses.len(), counterparty_input_idxs.len()));
+		}
+		if msg.witnesses.iter().any(|witness| witness.is_empty()) {
+			return Err("Peer sent an empty witness in tx_signatures".to_owned());
+		}
+		for (idx, witness) in counterparty_input_idxs.iter().zip(msg.witnesses.iter()) {
+			self.tx.input[*idx].witness = witness.clone();
+		}
+		self.counterparty_shared_input_signature = shared_input_signature;
+		self.counterparty_signed = true;
+		Ok(())
+	}
+}
+
+impl Writeable for FundingTxSigning {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.tx.write(writer)?;
+		self.funding_output_index.write(writer)?;
+		(self.holder_inputs.len() as u16).write(writer)?;
+		for &(idx, ref input) in self.holder_inputs.iter() {
+			idx.write(writer)?;
+			input.prev_tx.write(writer)?;
+			input.prev_vout.write(writer)?;
+			input.satisfaction_weight.write(writer)?;
+		}
+		self.holder_sends_first.write(writer)?;
+		self.holder_signed.write(writer)?;
+		self.counterparty_signed.write(writer)?;
+		self.shared_input_index.write(writer)?;
+		self.holder_shared_input_signature.write(writer)?;
+		self.counterparty_shared_input_signature.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for FundingTxSigning {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		let tx = Readable::read(reader)?;
+		let funding_output_index = Readable::read(reader)?;
+		let holder_inputs_count: u16 = Readable::read(reader)?;
+		let mut holder_inputs = Vec::with_capacity(cmp::min(holder_inputs_count as usize, MAX_INPUTS_OUTPUTS_COUNT));
+		for _ in 0..holder_inputs_count {
+			let idx: u32 = Readable::read(reader)?;
+			holder_inputs.push((idx, WalletUtxo {
+				prev_tx: Readable::read(reader)?,
+				prev_vout: Readable::read(reader)?,
+				satisfaction_weight: Readable::read(reader)?,
+			}));
+		}
+		Ok(FundingTxSigning {
+			tx,
+			funding_output_index,
+			holder_inputs,
+			holder_sends_first: Readable::read(reader)?,
+			holder_signed: Readable::read(reader)?,
+			counterparty_signed: Readable::read(reader)?,
+			shared_input_index: Readable::read(reader)?,
+			holder_shared_input_signature: Readable::read(reader)?,
+			counterparty_shared_input_signature: Readable::read(reader)?,
+		})
+	}
+}
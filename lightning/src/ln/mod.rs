@@ -19,6 +19,7 @@
 //! call into your NetGraphMsgHandler.
 
 pub mod channelmanager;
+pub mod payment_retry;
 pub mod msgs;
 pub mod peer_handler;
 pub mod chan_utils;
@@ -31,6 +32,7 @@ pub mod peer_channel_encryptor;
 pub(crate) mod peer_channel_encryptor;
 
 mod channel;
+mod interactivetx;
 mod onion_utils;
 mod wire;
 
@@ -28,6 +28,8 @@ use bitcoin::secp256k1::key::PublicKey;
 use bitcoin::secp256k1::Signature;
 use bitcoin::secp256k1;
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
 use bitcoin::hash_types::{Txid, BlockHash};
 
 use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
@@ -36,13 +38,20 @@ use std::{cmp, fmt};
 use std::io::Read;
 
 use util::events;
-use util::ser::{Readable, Writeable, Writer, FixedLengthReader, HighZeroBytesDroppedVarInt};
+use util::ser::{Readable, Writeable, Writer, FixedLengthReader, HighZeroBytesDroppedVarInt, BigSize, LengthCalculatingWriter};
 
 use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
 
 /// 21 million * 10^8 * 1000
 pub(crate) const MAX_VALUE_MSAT: u64 = 21_000_000_0000_0000_000;
 
+/// The TLV record type carrying the payment preimage of a spontaneous (keysend) payment.
+pub(crate) const KEYSEND_PREIMAGE_TLV_TYPE: u64 = 5482373484;
+
+/// The lowest TLV record type which may be used for application-specific records in the final
+/// hop's onion payload. Lower types are reserved for the Lightning specification itself.
+pub const CUSTOM_TLV_TYPE_MIN: u64 = 65536;
+
 /// An error in decoding a message or struct.
 #[derive(Debug)]
 pub enum DecodeError {
@@ -84,6 +93,19 @@ pub struct ErrorMessage {
 	pub data: String,
 }
 
+/// A warning message to be sent or received from a peer. Unlike an [`ErrorMessage`], a warning
+/// doesn't require the channel to be closed.
+#[derive(Clone)]
+pub struct WarningMessage {
+	/// The channel ID involved in the warning, or all zeros if it applies to all channels
+	pub channel_id: [u8; 32],
+	/// A possibly human-readable warning description.
+	/// The string should be sanitized before it is used (e.g. emitted to logs
+	/// or printed to stdout).  Otherwise, a well crafted warning message may trigger a security
+	/// vulnerability in the terminal emulator or the logging subsystem.
+	pub data: String,
+}
+
 /// A ping message to be sent or received from a peer
 #[derive(Clone)]
 pub struct Ping {
@@ -180,6 +202,217 @@ pub struct AcceptChannel {
 	pub shutdown_scriptpubkey: OptionalField<Script>,
 }
 
+/// An open_channel2 message to be sent or received from a peer, opening a channel whose funding
+/// transaction is built interactively by both peers, see TxAddInput and TxAddOutput.
+#[derive(Clone)]
+pub struct OpenChannelV2 {
+	/// The genesis hash of the blockchain where the channel is to be opened
+	pub chain_hash: BlockHash,
+	/// A temporary channel ID, until the funding outpoint is announced
+	pub temporary_channel_id: [u8; 32],
+	/// The feerate per 1000-weight of the funding transaction
+	pub funding_feerate_perkw: u32,
+	/// The feerate per 1000-weight of sender generated commitment transactions, until updated by
+	/// update_fee
+	pub commitment_feerate_perkw: u32,
+	/// The amount the sender will contribute to the channel
+	pub funding_satoshis: u64,
+	/// The threshold below which outputs on transactions broadcast by sender will be omitted
+	pub dust_limit_satoshis: u64,
+	/// The maximum inbound HTLC value in flight towards sender, in milli-satoshi
+	pub max_htlc_value_in_flight_msat: u64,
+	/// The minimum HTLC size incoming to sender, in milli-satoshi
+	pub htlc_minimum_msat: u64,
+	/// The number of blocks which the counterparty will have to wait to claim on-chain funds if they broadcast a commitment transaction
+	pub to_self_delay: u16,
+	/// The maximum number of inbound HTLCs towards sender
+	pub max_accepted_htlcs: u16,
+	/// The locktime of the funding transaction
+	pub locktime: u32,
+	/// The sender's key controlling the funding transaction
+	pub funding_pubkey: PublicKey,
+	/// Used to derive a revocation key for transactions broadcast by counterparty
+	pub revocation_basepoint: PublicKey,
+	/// A payment key to sender for transactions broadcast by counterparty
+	pub payment_point: PublicKey,
+	/// Used to derive a payment key to sender for transactions broadcast by sender
+	pub delayed_payment_basepoint: PublicKey,
+	/// Used to derive an HTLC payment key to sender
+	pub htlc_basepoint: PublicKey,
+	/// The first to-be-broadcast-by-sender transaction's per commitment point
+	pub first_per_commitment_point: PublicKey,
+	/// The second to-be-broadcast-by-sender transaction's per commitment point
+	pub second_per_commitment_point: PublicKey,
+	/// Channel flags
+	pub channel_flags: u8,
+	/// Optionally, a request to pre-set the to-sender output's scriptPubkey for when we collaboratively close
+	pub shutdown_scriptpubkey: Option<Script>,
+}
+
+/// An accept_channel2 message to be sent or received from a peer
+#[derive(Clone)]
+pub struct AcceptChannelV2 {
+	/// A temporary channel ID, until the funding outpoint is announced
+	pub temporary_channel_id: [u8; 32],
+	/// The amount the sender will contribute to the channel, which may be 0
+	pub funding_satoshis: u64,
+	/// The threshold below which outputs on transactions broadcast by sender will be omitted
+	pub dust_limit_satoshis: u64,
+	/// The maximum inbound HTLC value in flight towards sender, in milli-satoshi
+	pub max_htlc_value_in_flight_msat: u64,
+	/// The minimum HTLC size incoming to sender, in milli-satoshi
+	pub htlc_minimum_msat: u64,
+	/// Minimum depth of the funding transaction before the channel is considered open
+	pub minimum_depth: u32,
+	/// The number of blocks which the counterparty will have to wait to claim on-chain funds if they broadcast a commitment transaction
+	pub to_self_delay: u16,
+	/// The maximum number of inbound HTLCs towards sender
+	pub max_accepted_htlcs: u16,
+	/// The sender's key controlling the funding transaction
+	pub funding_pubkey: PublicKey,
+	/// Used to derive a revocation key for transactions broadcast by counterparty
+	pub revocation_basepoint: PublicKey,
+	/// A payment key to sender for transactions broadcast by counterparty
+	pub payment_point: PublicKey,
+	/// Used to derive a payment key to sender for transactions broadcast by sender
+	pub delayed_payment_basepoint: PublicKey,
+	/// Used to derive an HTLC payment key to sender for transactions broadcast by counterparty
+	pub htlc_basepoint: PublicKey,
+	/// The first to-be-broadcast-by-sender transaction's per commitment point
+	pub first_per_commitment_point: PublicKey,
+	/// The second to-be-broadcast-by-sender transaction's per commitment point
+	pub second_per_commitment_point: PublicKey,
+	/// Optionally, a request to pre-set the to-sender output's scriptPubkey for when we collaboratively close
+	pub shutdown_scriptpubkey: Option<Script>,
+}
+
+/// A tx_add_input message to be sent or received from a peer, adding an input to the funding
+/// transaction under construction
+#[derive(Clone, PartialEq)]
+pub struct TxAddInput {
+	/// The (temporary) channel ID
+	pub channel_id: [u8; 32],
+	/// A unique identifier for this input, which also determines its position in the transaction.
+	/// Even for inputs added by the channel initiator, odd otherwise.
+	pub serial_id: u64,
+	/// The transaction containing the output being spent
+	pub prevtx: Transaction,
+	/// The index of the output being spent in prevtx
+	pub prevtx_out: u32,
+	/// The nSequence of the input
+	pub sequence: u32,
+}
+
+/// A tx_add_output message to be sent or received from a peer, adding an output to the funding
+/// transaction under construction
+#[derive(Clone, PartialEq)]
+pub struct TxAddOutput {
+	/// The (temporary) channel ID
+	pub channel_id: [u8; 32],
+	/// A unique identifier for this output, which also determines its position in the
+	/// transaction. Even for outputs added by the channel initiator, odd otherwise.
+	pub serial_id: u64,
+	/// The value of the output
+	pub sats: u64,
+	/// The scriptPubKey of the output
+	pub script: Script,
+}
+
+/// A tx_remove_input message to be sent or received from a peer, removing an input it previously
+/// added to the funding transaction under construction
+#[derive(Clone, PartialEq)]
+pub struct TxRemoveInput {
+	/// The (temporary) channel ID
+	pub channel_id: [u8; 32],
+	/// The serial_id of the input to remove
+	pub serial_id: u64,
+}
+
+/// A tx_remove_output message to be sent or received from a peer, removing an output it
+/// previously added to the funding transaction under construction
+#[derive(Clone, PartialEq)]
+pub struct TxRemoveOutput {
+	/// The (temporary) channel ID
+	pub channel_id: [u8; 32],
+	/// The serial_id of the output to remove
+	pub serial_id: u64,
+}
+
+/// A tx_complete message to be sent or received from a peer, indicating the sender has nothing
+/// more to add to the funding transaction under construction
+#[derive(Clone, PartialEq)]
+pub struct TxComplete {
+	/// The (temporary) channel ID
+	pub channel_id: [u8; 32],
+}
+
+/// A tx_signatures message to be sent or received from a peer, providing the witnesses of the
+/// sender's inputs to the funding transaction
+#[derive(Clone, PartialEq)]
+pub struct TxSignatures {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The txid of the funding transaction
+	pub txid: Txid,
+	/// The witness of each of the sender's inputs, in the order they appear in the transaction
+	pub witnesses: Vec<Vec<Vec<u8>>>,
+	/// When splicing, the sender's signature for the input spending the current funding output
+	pub shared_input_signature: Option<Signature>,
+}
+
+/// A tx_abort message to be sent or received from a peer, aborting the construction or signing of
+/// a splice transaction without closing the channel
+#[derive(Clone, PartialEq)]
+pub struct TxAbort {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// A possibly human-readable reason for aborting
+	pub data: String,
+}
+
+/// An stfu message to be sent or received from a peer, indicating the sender won't add any more
+/// updates to the channel so that it can become quiescent before a splice
+#[derive(Clone, PartialEq)]
+pub struct Stfu {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// 1 if the sender wishes to initiate the splice once the channel is quiescent, 0 otherwise
+	pub initiator: u8,
+}
+
+/// A splice message to be sent or received from a peer, proposing to splice funds in or out of a
+/// quiescent channel by spending its funding output in a new funding transaction
+#[derive(Clone, PartialEq)]
+pub struct Splice {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The amount the sender adds to (or, if negative, removes from) its channel balance
+	pub relative_satoshis: i64,
+	/// The feerate per 1000-weight of the splice transaction
+	pub funding_feerate_perkw: u32,
+	/// The locktime of the splice transaction
+	pub locktime: u32,
+}
+
+/// A splice_ack message to be sent or received from a peer, accepting a splice
+#[derive(Clone, PartialEq)]
+pub struct SpliceAck {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The amount the sender adds to (or, if negative, removes from) its channel balance
+	pub relative_satoshis: i64,
+}
+
+/// A splice_locked message to be sent or received from a peer, indicating the splice transaction
+/// has reached the channel's minimum depth
+#[derive(Clone, PartialEq)]
+pub struct SpliceLocked {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+	/// The txid of the splice transaction
+	pub splice_txid: Txid,
+}
+
 /// A funding_created message to be sent or received from a peer
 #[derive(Clone)]
 pub struct FundingCreated {
@@ -209,6 +442,9 @@ pub struct FundingLocked {
 	pub channel_id: [u8; 32],
 	/// The per-commitment point of the second commitment transaction
 	pub next_per_commitment_point: PublicKey,
+	/// An alias the sender may be paid through in route hints before (or instead of) the real
+	/// short channel id, e.g. for zero-conf channels which have no confirmed funding yet.
+	pub short_channel_id_alias: Option<u64>,
 }
 
 /// A shutdown message to be sent or received from a peer
@@ -587,6 +823,17 @@ pub struct QueryChannelRange {
 	pub number_of_blocks: u32,
 }
 
+impl QueryChannelRange {
+	/// Calculates the overflow safe ending block height for the query.
+	/// Overflow returns `0xffffffff`, otherwise returns `first_blocknum + number_of_blocks`
+	pub fn end_blocknum(&self) -> u32 {
+		match self.first_blocknum.checked_add(self.number_of_blocks) {
+			Some(block) => block,
+			None => u32::max_value(),
+		}
+	}
+}
+
 /// A reply_channel_range message is a reply to a query_channel_range
 /// message. Multiple reply_channel_range messages can be sent in reply
 /// to a single query_channel_range message. The query recipient makes a
@@ -665,6 +912,12 @@ pub enum ErrorAction {
 		/// An error message which we should make an effort to send before we disconnect.
 		msg: Option<ErrorMessage>
 	},
+	/// The peer did something we can't process right now, but which doesn't warrant closing the
+	/// channel. Warn them and disconnect, dropping any uncommitted updates.
+	DisconnectPeerWithWarning {
+		/// A warning message which we should make an effort to send before we disconnect.
+		msg: WarningMessage
+	},
 	/// The peer did something harmless that we weren't able to process, just log and ignore
 	IgnoreError,
 	/// The peer did something incorrect. Tell them.
@@ -752,6 +1005,24 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	fn handle_open_channel(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &OpenChannel);
 	/// Handle an incoming accept_channel message from the given peer.
 	fn handle_accept_channel(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &AcceptChannel);
+	/// Handle an incoming open_channel2 message from the given peer.
+	fn handle_open_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &OpenChannelV2);
+	/// Handle an incoming accept_channel2 message from the given peer.
+	fn handle_accept_channel_v2(&self, their_node_id: &PublicKey, their_features: InitFeatures, msg: &AcceptChannelV2);
+	/// Handle an incoming tx_add_input message from the given peer.
+	fn handle_tx_add_input(&self, their_node_id: &PublicKey, msg: &TxAddInput);
+	/// Handle an incoming tx_add_output message from the given peer.
+	fn handle_tx_add_output(&self, their_node_id: &PublicKey, msg: &TxAddOutput);
+	/// Handle an incoming tx_remove_input message from the given peer.
+	fn handle_tx_remove_input(&self, their_node_id: &PublicKey, msg: &TxRemoveInput);
+	/// Handle an incoming tx_remove_output message from the given peer.
+	fn handle_tx_remove_output(&self, their_node_id: &PublicKey, msg: &TxRemoveOutput);
+	/// Handle an incoming tx_complete message from the given peer.
+	fn handle_tx_complete(&self, their_node_id: &PublicKey, msg: &TxComplete);
+	/// Handle an incoming tx_signatures message from the given peer.
+	fn handle_tx_signatures(&self, their_node_id: &PublicKey, msg: &TxSignatures);
+	/// Handle an incoming tx_abort message from the given peer.
+	fn handle_tx_abort(&self, their_node_id: &PublicKey, msg: &TxAbort);
 	/// Handle an incoming funding_created message from the given peer.
 	fn handle_funding_created(&self, their_node_id: &PublicKey, msg: &FundingCreated);
 	/// Handle an incoming funding_signed message from the given peer.
@@ -765,6 +1036,16 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	/// Handle an incoming closing_signed message from the given peer.
 	fn handle_closing_signed(&self, their_node_id: &PublicKey, msg: &ClosingSigned);
 
+	// Splicing:
+	/// Handle an incoming stfu message from the given peer.
+	fn handle_stfu(&self, their_node_id: &PublicKey, msg: &Stfu);
+	/// Handle an incoming splice message from the given peer.
+	fn handle_splice(&self, their_node_id: &PublicKey, msg: &Splice);
+	/// Handle an incoming splice_ack message from the given peer.
+	fn handle_splice_ack(&self, their_node_id: &PublicKey, msg: &SpliceAck);
+	/// Handle an incoming splice_locked message from the given peer.
+	fn handle_splice_locked(&self, their_node_id: &PublicKey, msg: &SpliceLocked);
+
 	// HTLC handling:
 	/// Handle an incoming update_add_htlc message from the given peer.
 	fn handle_update_add_htlc(&self, their_node_id: &PublicKey, msg: &UpdateAddHTLC);
@@ -785,6 +1066,10 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 	// Channel-to-announce:
 	/// Handle an incoming announcement_signatures message from the given peer.
 	fn handle_announcement_signatures(&self, their_node_id: &PublicKey, msg: &AnnouncementSignatures);
+	/// Handle an incoming channel_update message from the given peer, which may be for one of our
+	/// channels. Note that this is called for every channel_update, not just those concerning
+	/// our channels, in addition to RoutingMessageHandler::handle_channel_update.
+	fn handle_channel_update(&self, their_node_id: &PublicKey, msg: &ChannelUpdate);
 
 	// Connection loss/reestablish:
 	/// Indicates a connection to the peer failed/an existing connection was lost. If no connection
@@ -795,6 +1080,13 @@ pub trait ChannelMessageHandler : events::MessageSendEventsProvider + Send + Syn
 
 	/// Handle a peer reconnecting, possibly generating channel_reestablish message(s).
 	fn peer_connected(&self, their_node_id: &PublicKey, msg: &Init);
+	/// Gets the features to advertise to peers in our Init message.
+	///
+	/// Defaults to all features we know about, less any which are only advertised on an opt-in
+	/// basis.
+	fn provided_init_features(&self) -> InitFeatures {
+		InitFeatures::known().clear_anchors_zero_fee_htlc_tx().clear_dual_fund().clear_splicing()
+	}
 	/// Handle an incoming channel_reestablish message from the given peer.
 	fn handle_channel_reestablish(&self, their_node_id: &PublicKey, msg: &ChannelReestablish);
 
@@ -850,10 +1142,16 @@ pub trait RoutingMessageHandler : Send + Sync + events::MessageSendEventsProvide
 	/// Handles when a peer asks us to send routing gossip messages for a
 	/// list of short_channel_ids.
 	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: QueryShortChannelIds) -> Result<(), LightningError>;
+	/// Gets the next messages replying to the query_short_channel_ids the given peer sent us,
+	/// stopping once at least batch_amount messages are returned or no replies remain. Each
+	/// query's replies end with a reply_short_channel_ids_end.
+	/// This is called as the peer's outbound buffer drains, so replies should be queued here
+	/// rather than returned from get_and_clear_pending_msg_events all at once.
+	fn get_next_query_short_channel_ids_replies(&self, their_node_id: &PublicKey, batch_amount: u8) -> Vec<events::MessageSendEvent>;
 }
 
 mod fuzzy_internal_msgs {
-	use ln::channelmanager::PaymentSecret;
+	use ln::channelmanager::{PaymentPreimage, PaymentSecret};
 
 	// These types aren't intended to be pub, but are exposed for direct fuzzing (as we deserialize
 	// them from untrusted input):
@@ -874,6 +1172,12 @@ mod fuzzy_internal_msgs {
 		},
 		FinalNode {
 			payment_data: Option<FinalOnionHopData>,
+			/// The preimage of a spontaneous (keysend) payment, which the sender provides in lieu of
+			/// the recipient having generated it for an invoice.
+			keysend_preimage: Option<PaymentPreimage>,
+			/// Application-specific TLV records (with types of at least CUSTOM_TLV_TYPE_MIN), sorted
+			/// by type.
+			custom_tlvs: Vec<(u64, Vec<u8>)>,
 		},
 	}
 
@@ -1023,6 +1327,92 @@ impl_writeable_len_match!(AcceptChannel, {
 	shutdown_scriptpubkey
 });
 
+/// A script in a TLV record, which unlike a Script field isn't prefixed with its length as the
+/// length of the record already covers it.
+struct TlvScript(Script);
+
+impl Writeable for TlvScript {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.write_all(self.0.as_bytes())
+	}
+}
+
+impl Readable for TlvScript {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let mut script = Vec::new();
+		r.read_to_end(&mut script)?;
+		Ok(TlvScript(Script::from(script)))
+	}
+}
+
+impl Writeable for AcceptChannelV2 {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(303 + if let Some(ref script) = self.shutdown_scriptpubkey { 2 + script.len() } else { 0 });
+		self.temporary_channel_id.write(w)?;
+		self.funding_satoshis.write(w)?;
+		self.dust_limit_satoshis.write(w)?;
+		self.max_htlc_value_in_flight_msat.write(w)?;
+		self.htlc_minimum_msat.write(w)?;
+		self.minimum_depth.write(w)?;
+		self.to_self_delay.write(w)?;
+		self.max_accepted_htlcs.write(w)?;
+		self.funding_pubkey.write(w)?;
+		self.revocation_basepoint.write(w)?;
+		self.payment_point.write(w)?;
+		self.delayed_payment_basepoint.write(w)?;
+		self.htlc_basepoint.write(w)?;
+		self.first_per_commitment_point.write(w)?;
+		self.second_per_commitment_point.write(w)?;
+		if let Some(ref script) = self.shutdown_scriptpubkey {
+			let script = TlvScript(script.clone());
+			encode_tlv!(w, {
+				(0, script)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for AcceptChannelV2 {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let temporary_channel_id = Readable::read(r)?;
+		let funding_satoshis = Readable::read(r)?;
+		let dust_limit_satoshis = Readable::read(r)?;
+		let max_htlc_value_in_flight_msat = Readable::read(r)?;
+		let htlc_minimum_msat = Readable::read(r)?;
+		let minimum_depth = Readable::read(r)?;
+		let to_self_delay = Readable::read(r)?;
+		let max_accepted_htlcs = Readable::read(r)?;
+		let funding_pubkey = Readable::read(r)?;
+		let revocation_basepoint = Readable::read(r)?;
+		let payment_point = Readable::read(r)?;
+		let delayed_payment_basepoint = Readable::read(r)?;
+		let htlc_basepoint = Readable::read(r)?;
+		let first_per_commitment_point = Readable::read(r)?;
+		let second_per_commitment_point = Readable::read(r)?;
+		let mut shutdown_scriptpubkey: Option<TlvScript> = None;
+		decode_tlv!(&mut *r, {}, {(0, shutdown_scriptpubkey)});
+		Ok(AcceptChannelV2 {
+			temporary_channel_id,
+			funding_satoshis,
+			dust_limit_satoshis,
+			max_htlc_value_in_flight_msat,
+			htlc_minimum_msat,
+			minimum_depth,
+			to_self_delay,
+			max_accepted_htlcs,
+			funding_pubkey,
+			revocation_basepoint,
+			payment_point,
+			delayed_payment_basepoint,
+			htlc_basepoint,
+			first_per_commitment_point,
+			second_per_commitment_point,
+			shutdown_scriptpubkey: shutdown_scriptpubkey.map(|script| script.0),
+		})
+	}
+}
+
 impl_writeable!(AnnouncementSignatures, 32+8+64*2, {
 	channel_id,
 	short_channel_id,
@@ -1102,10 +1492,33 @@ impl_writeable!(FundingSigned, 32+64, {
 	signature
 });
 
-impl_writeable!(FundingLocked, 32+33, {
-	channel_id,
-	next_per_commitment_point
-});
+impl Writeable for FundingLocked {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 33 + if self.short_channel_id_alias.is_some() { 10 } else { 0 });
+		self.channel_id.write(w)?;
+		self.next_per_commitment_point.write(w)?;
+		if let Some(alias) = self.short_channel_id_alias {
+			encode_tlv!(w, {
+				(1, alias)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for FundingLocked {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let next_per_commitment_point = Readable::read(r)?;
+		let mut short_channel_id_alias: Option<u64> = None;
+		decode_tlv!(&mut *r, {}, {(1, short_channel_id_alias)});
+		Ok(FundingLocked {
+			channel_id,
+			next_per_commitment_point,
+			short_channel_id_alias,
+		})
+	}
+}
 
 impl Writeable for Init {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
@@ -1151,6 +1564,86 @@ impl_writeable_len_match!(OpenChannel, {
 	shutdown_scriptpubkey
 });
 
+impl Writeable for OpenChannelV2 {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(344 + if let Some(ref script) = self.shutdown_scriptpubkey { 2 + script.len() } else { 0 });
+		self.chain_hash.write(w)?;
+		self.temporary_channel_id.write(w)?;
+		self.funding_feerate_perkw.write(w)?;
+		self.commitment_feerate_perkw.write(w)?;
+		self.funding_satoshis.write(w)?;
+		self.dust_limit_satoshis.write(w)?;
+		self.max_htlc_value_in_flight_msat.write(w)?;
+		self.htlc_minimum_msat.write(w)?;
+		self.to_self_delay.write(w)?;
+		self.max_accepted_htlcs.write(w)?;
+		self.locktime.write(w)?;
+		self.funding_pubkey.write(w)?;
+		self.revocation_basepoint.write(w)?;
+		self.payment_point.write(w)?;
+		self.delayed_payment_basepoint.write(w)?;
+		self.htlc_basepoint.write(w)?;
+		self.first_per_commitment_point.write(w)?;
+		self.second_per_commitment_point.write(w)?;
+		self.channel_flags.write(w)?;
+		if let Some(ref script) = self.shutdown_scriptpubkey {
+			let script = TlvScript(script.clone());
+			encode_tlv!(w, {
+				(0, script)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for OpenChannelV2 {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let chain_hash = Readable::read(r)?;
+		let temporary_channel_id = Readable::read(r)?;
+		let funding_feerate_perkw = Readable::read(r)?;
+		let commitment_feerate_perkw = Readable::read(r)?;
+		let funding_satoshis = Readable::read(r)?;
+		let dust_limit_satoshis = Readable::read(r)?;
+		let max_htlc_value_in_flight_msat = Readable::read(r)?;
+		let htlc_minimum_msat = Readable::read(r)?;
+		let to_self_delay = Readable::read(r)?;
+		let max_accepted_htlcs = Readable::read(r)?;
+		let locktime = Readable::read(r)?;
+		let funding_pubkey = Readable::read(r)?;
+		let revocation_basepoint = Readable::read(r)?;
+		let payment_point = Readable::read(r)?;
+		let delayed_payment_basepoint = Readable::read(r)?;
+		let htlc_basepoint = Readable::read(r)?;
+		let first_per_commitment_point = Readable::read(r)?;
+		let second_per_commitment_point = Readable::read(r)?;
+		let channel_flags = Readable::read(r)?;
+		let mut shutdown_scriptpubkey: Option<TlvScript> = None;
+		decode_tlv!(&mut *r, {}, {(0, shutdown_scriptpubkey)});
+		Ok(OpenChannelV2 {
+			chain_hash,
+			temporary_channel_id,
+			funding_feerate_perkw,
+			commitment_feerate_perkw,
+			funding_satoshis,
+			dust_limit_satoshis,
+			max_htlc_value_in_flight_msat,
+			htlc_minimum_msat,
+			to_self_delay,
+			max_accepted_htlcs,
+			locktime,
+			funding_pubkey,
+			revocation_basepoint,
+			payment_point,
+			delayed_payment_basepoint,
+			htlc_basepoint,
+			first_per_commitment_point,
+			second_per_commitment_point,
+			channel_flags,
+			shutdown_scriptpubkey: shutdown_scriptpubkey.map(|script| script.0),
+		})
+	}
+}
+
 impl_writeable!(RevokeAndACK, 32+32+33, {
 	channel_id,
 	per_commitment_secret,
@@ -1164,6 +1657,179 @@ impl_writeable_len_match!(Shutdown, {
 	scriptpubkey
 });
 
+impl Writeable for TxAddInput {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		let prevtx = encode::serialize(&self.prevtx);
+		w.size_hint(32 + 8 + 2 + prevtx.len() + 4 + 4);
+		self.channel_id.write(w)?;
+		self.serial_id.write(w)?;
+		// The previous transaction is prefixed with its 16-bit length
+		prevtx.write(w)?;
+		self.prevtx_out.write(w)?;
+		self.sequence.write(w)?;
+		Ok(())
+	}
+}
+
+impl Readable for TxAddInput {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let serial_id = Readable::read(r)?;
+		let prevtx_bytes: Vec<u8> = Readable::read(r)?;
+		let prevtx = match encode::deserialize(&prevtx_bytes) {
+			Ok(tx) => tx,
+			Err(_) => return Err(DecodeError::InvalidValue),
+		};
+		Ok(Self {
+			channel_id,
+			serial_id,
+			prevtx,
+			prevtx_out: Readable::read(r)?,
+			sequence: Readable::read(r)?,
+		})
+	}
+}
+
+impl_writeable_len_match!(TxAddOutput, {
+		{ TxAddOutput { ref script, .. }, 32 + 8 + 8 + 2 + script.len() }
+	}, {
+	channel_id,
+	serial_id,
+	sats,
+	script
+});
+
+impl_writeable!(TxRemoveInput, 32 + 8, {
+	channel_id,
+	serial_id
+});
+
+impl_writeable!(TxRemoveOutput, 32 + 8, {
+	channel_id,
+	serial_id
+});
+
+impl_writeable!(TxComplete, 32, {
+	channel_id
+});
+
+impl Writeable for TxSignatures {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.channel_id.write(w)?;
+		self.txid.write(w)?;
+		(self.witnesses.len() as u16).write(w)?;
+		for witness in self.witnesses.iter() {
+			// Each witness is the consensus serialization of its stack, prefixed with its 16-bit
+			// length
+			encode::serialize(witness).write(w)?;
+		}
+		if let Some(sig) = self.shared_input_signature {
+			encode_tlv!(w, {
+				(0, sig)
+			});
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxSignatures {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let txid = Readable::read(r)?;
+		let witnesses_count: u16 = Readable::read(r)?;
+		let mut witnesses = Vec::with_capacity(cmp::min(witnesses_count as usize, 256));
+		for _ in 0..witnesses_count {
+			let witness_bytes: Vec<u8> = Readable::read(r)?;
+			match encode::deserialize(&witness_bytes) {
+				Ok(witness) => witnesses.push(witness),
+				Err(_) => return Err(DecodeError::InvalidValue),
+			}
+		}
+		let mut shared_input_signature: Option<Signature> = None;
+		decode_tlv!(&mut *r, {}, {(0, shared_input_signature)});
+		Ok(Self {
+			channel_id,
+			txid,
+			witnesses,
+			shared_input_signature,
+		})
+	}
+}
+
+impl Writeable for TxAbort {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 2 + self.data.len());
+		self.channel_id.write(w)?;
+		(self.data.len() as u16).write(w)?;
+		w.write_all(self.data.as_bytes())?;
+		Ok(())
+	}
+}
+
+impl Readable for TxAbort {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let channel_id = Readable::read(r)?;
+		let len: u16 = Readable::read(r)?;
+		let mut data = vec![0; len as usize];
+		r.read_exact(&mut data)?;
+		match String::from_utf8(data) {
+			Ok(data) => Ok(Self { channel_id, data }),
+			Err(_) => Err(DecodeError::InvalidValue),
+		}
+	}
+}
+
+impl_writeable!(Stfu, 32 + 1, {
+	channel_id,
+	initiator
+});
+
+impl Writeable for Splice {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 8 + 4 + 4);
+		self.channel_id.write(w)?;
+		// The relative amount is encoded as a two's complement 64-bit integer
+		(self.relative_satoshis as u64).write(w)?;
+		self.funding_feerate_perkw.write(w)?;
+		self.locktime.write(w)?;
+		Ok(())
+	}
+}
+
+impl Readable for Splice {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(Self {
+			channel_id: Readable::read(r)?,
+			relative_satoshis: <u64 as Readable>::read(r)? as i64,
+			funding_feerate_perkw: Readable::read(r)?,
+			locktime: Readable::read(r)?,
+		})
+	}
+}
+
+impl Writeable for SpliceAck {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 8);
+		self.channel_id.write(w)?;
+		(self.relative_satoshis as u64).write(w)?;
+		Ok(())
+	}
+}
+
+impl Readable for SpliceAck {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(Self {
+			channel_id: Readable::read(r)?,
+			relative_satoshis: <u64 as Readable>::read(r)? as i64,
+		})
+	}
+}
+
+impl_writeable!(SpliceLocked, 32 + 32, {
+	channel_id,
+	splice_txid
+});
+
 impl_writeable_len_match!(UpdateFailHTLC, {
 		{ UpdateFailHTLC { ref reason, .. }, 32 + 10 + reason.data.len() }
 	}, {
@@ -1272,19 +1938,34 @@ impl Writeable for OnionHopData {
 					(6, short_channel_id)
 				});
 			},
-			OnionHopDataFormat::FinalNode { payment_data: Some(ref final_data) } => {
-				if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
-				encode_varint_length_prefixed_tlv!(w, {
-					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
-					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value)),
-					(8, final_data)
-				});
-			},
-			OnionHopDataFormat::FinalNode { payment_data: None } => {
-				encode_varint_length_prefixed_tlv!(w, {
-					(2, HighZeroBytesDroppedVarInt(self.amt_to_forward)),
-					(4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value))
-				});
+			OnionHopDataFormat::FinalNode { ref payment_data, ref keysend_preimage, ref custom_tlvs } => {
+				// Custom records may sort anywhere after our own, so collect every record and write
+				// them out in increasing type order, as required.
+				let mut records: Vec<(u64, Vec<u8>)> = Vec::with_capacity(4 + custom_tlvs.len());
+				records.push((2, HighZeroBytesDroppedVarInt(self.amt_to_forward).encode()));
+				records.push((4, HighZeroBytesDroppedVarInt(self.outgoing_cltv_value).encode()));
+				if let &Some(ref final_data) = payment_data {
+					if final_data.total_msat > MAX_VALUE_MSAT { panic!("We should never be sending infinite/overflow onion payments"); }
+					records.push((8, final_data.encode()));
+				}
+				if let &Some(ref preimage) = keysend_preimage {
+					records.push((KEYSEND_PREIMAGE_TLV_TYPE, preimage.encode()));
+				}
+				records.extend_from_slice(&custom_tlvs[..]);
+				records.sort_by_key(|&(typ, _)| typ);
+
+				let mut len = LengthCalculatingWriter(0);
+				for &(typ, ref value) in records.iter() {
+					BigSize(typ).write(&mut len)?;
+					BigSize(value.len() as u64).write(&mut len)?;
+					len.0 += value.len();
+				}
+				BigSize(len.0 as u64).write(w)?;
+				for &(typ, ref value) in records.iter() {
+					BigSize(typ).write(w)?;
+					BigSize(value.len() as u64).write(w)?;
+					w.write_all(&value[..])?;
+				}
 			},
 		}
 		Ok(())
@@ -1306,16 +1987,22 @@ impl Readable for OnionHopData {
 			let mut cltv_value = HighZeroBytesDroppedVarInt(0u32);
 			let mut short_id: Option<u64> = None;
 			let mut payment_data: Option<FinalOnionHopData> = None;
+			let mut keysend_preimage: Option<PaymentPreimage> = None;
+			let mut custom_tlvs: Vec<(u64, Vec<u8>)> = Vec::new();
+			// decode_tlv requires literal types, so 5482373484 here is KEYSEND_PREIMAGE_TLV_TYPE
 			decode_tlv!(&mut rd, {
 				(2, amt),
 				(4, cltv_value)
 			}, {
 				(6, short_id),
-				(8, payment_data)
-			});
+				(8, payment_data),
+				(5482373484, keysend_preimage)
+			}, custom_tlvs);
 			rd.eat_remaining().map_err(|_| DecodeError::ShortRead)?;
 			let format = if let Some(short_channel_id) = short_id {
 				if payment_data.is_some() { return Err(DecodeError::InvalidValue); }
+				if keysend_preimage.is_some() { return Err(DecodeError::InvalidValue); }
+				if !custom_tlvs.is_empty() { return Err(DecodeError::InvalidValue); }
 				OnionHopDataFormat::NonFinalNode {
 					short_channel_id,
 				}
@@ -1326,7 +2013,9 @@ impl Readable for OnionHopData {
 					}
 				}
 				OnionHopDataFormat::FinalNode {
-					payment_data
+					payment_data,
+					keysend_preimage,
+					custom_tlvs,
 				}
 			};
 			(format, amt.0, cltv_value.0)
@@ -1525,6 +2214,34 @@ impl Readable for ErrorMessage {
 	}
 }
 
+impl Writeable for WarningMessage {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		w.size_hint(32 + 2 + self.data.len());
+		self.channel_id.write(w)?;
+		(self.data.len() as u16).write(w)?;
+		w.write_all(self.data.as_bytes())?;
+		Ok(())
+	}
+}
+
+impl Readable for WarningMessage {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(Self {
+			channel_id: Readable::read(r)?,
+			data: {
+				let mut sz: usize = <u16 as Readable>::read(r)? as usize;
+				let mut data = vec![];
+				let data_len = r.read_to_end(&mut data)?;
+				sz = cmp::min(data_len, sz);
+				match String::from_utf8(data[..sz as usize].to_vec()) {
+					Ok(s) => s,
+					Err(_) => return Err(DecodeError::InvalidValue),
+				}
+			}
+		})
+	}
+}
+
 impl Writeable for UnsignedNodeAnnouncement {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		w.size_hint(64 + 76 + self.features.byte_count() + self.addresses.len()*38 + self.excess_address_data.len() + self.excess_data.len());
@@ -1817,6 +2534,8 @@ mod tests {
 	use bitcoin::network::constants::Network;
 	use bitcoin::blockdata::script::Builder;
 	use bitcoin::blockdata::opcodes;
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
 	use bitcoin::hash_types::{Txid, BlockHash};
 
 	use bitcoin::secp256k1::key::{PublicKey,SecretKey};
@@ -2203,6 +2922,93 @@ mod tests {
 		do_encoding_accept_channel(true);
 	}
 
+	fn do_encoding_open_channel_v2(shutdown: bool) {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let (_, pubkey_2) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
+		let shutdown_script = Address::p2pkh(&::bitcoin::PublicKey{compressed: true, key: pubkey_1}, Network::Testnet).script_pubkey();
+		let open_channel = msgs::OpenChannelV2 {
+			chain_hash: BlockHash::from_hex("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000").unwrap(),
+			temporary_channel_id: [2; 32],
+			funding_feerate_perkw: 253,
+			commitment_feerate_perkw: 821716,
+			funding_satoshis: 1311768467284833366,
+			dust_limit_satoshis: 3608586615801332854,
+			max_htlc_value_in_flight_msat: 8517154655701053848,
+			htlc_minimum_msat: 2316138423780173,
+			to_self_delay: 49340,
+			max_accepted_htlcs: 49340,
+			locktime: 500,
+			funding_pubkey: pubkey_1,
+			revocation_basepoint: pubkey_1,
+			payment_point: pubkey_1,
+			delayed_payment_basepoint: pubkey_1,
+			htlc_basepoint: pubkey_1,
+			first_per_commitment_point: pubkey_1,
+			second_per_commitment_point: pubkey_2,
+			channel_flags: 1,
+			shutdown_scriptpubkey: if shutdown { Some(shutdown_script.clone()) } else { None },
+		};
+		let encoded_value = open_channel.encode();
+		let mut target_value = hex::decode("024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d076601").unwrap();
+		if shutdown {
+			target_value.append(&mut hex::decode("001976a91479b000887626b294a914501a4cd226b58b23598388ac").unwrap());
+		}
+		assert_eq!(encoded_value.len(), 310 + target_value.len());
+		assert_eq!(encoded_value[310..], target_value[..]);
+		let decoded: msgs::OpenChannelV2 = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert_eq!(decoded.second_per_commitment_point, pubkey_2);
+		assert_eq!(decoded.channel_flags, 1);
+		assert_eq!(decoded.shutdown_scriptpubkey, open_channel.shutdown_scriptpubkey);
+	}
+
+	#[test]
+	fn encoding_open_channel_v2() {
+		do_encoding_open_channel_v2(false);
+		do_encoding_open_channel_v2(true);
+	}
+
+	fn do_encoding_accept_channel_v2(shutdown: bool) {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let (_, pubkey_2) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
+		let shutdown_script = Address::p2pkh(&::bitcoin::PublicKey{compressed: true, key: pubkey_1}, Network::Testnet).script_pubkey();
+		let accept_channel = msgs::AcceptChannelV2 {
+			temporary_channel_id: [2; 32],
+			funding_satoshis: 0,
+			dust_limit_satoshis: 1311768467284833366,
+			max_htlc_value_in_flight_msat: 2536655962884945560,
+			htlc_minimum_msat: 2316138423780173,
+			minimum_depth: 821716,
+			to_self_delay: 49340,
+			max_accepted_htlcs: 49340,
+			funding_pubkey: pubkey_1,
+			revocation_basepoint: pubkey_1,
+			payment_point: pubkey_1,
+			delayed_payment_basepoint: pubkey_1,
+			htlc_basepoint: pubkey_1,
+			first_per_commitment_point: pubkey_1,
+			second_per_commitment_point: pubkey_2,
+			shutdown_scriptpubkey: if shutdown { Some(shutdown_script.clone()) } else { None },
+		};
+		let encoded_value = accept_channel.encode();
+		let mut target_value = hex::decode("024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766").unwrap();
+		if shutdown {
+			target_value.append(&mut hex::decode("001976a91479b000887626b294a914501a4cd226b58b23598388ac").unwrap());
+		}
+		assert_eq!(encoded_value.len(), 270 + target_value.len());
+		assert_eq!(encoded_value[270..], target_value[..]);
+		let decoded: msgs::AcceptChannelV2 = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert_eq!(decoded.second_per_commitment_point, pubkey_2);
+		assert_eq!(decoded.shutdown_scriptpubkey, accept_channel.shutdown_scriptpubkey);
+	}
+
+	#[test]
+	fn encoding_accept_channel_v2() {
+		do_encoding_accept_channel_v2(false);
+		do_encoding_accept_channel_v2(true);
+	}
+
 	#[test]
 	fn encoding_funding_created() {
 		let secp_ctx = Secp256k1::new();
@@ -2233,6 +3039,106 @@ mod tests {
 		assert_eq!(encoded_value, target_value);
 	}
 
+	#[test]
+	fn encoding_tx_add_input() {
+		let prevtx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn { previous_output: OutPoint { txid: Default::default(), vout: 1 }, script_sig: Script::new(), sequence: 0xffffffff, witness: Vec::new() }],
+			output: vec![TxOut { value: 100_000, script_pubkey: Builder::new().push_opcode(opcodes::OP_TRUE).into_script() }],
+		};
+		let tx_add_input = msgs::TxAddInput {
+			channel_id: [2; 32],
+			serial_id: 4,
+			prevtx: prevtx.clone(),
+			prevtx_out: 0,
+			sequence: 0xfffffffd,
+		};
+		let encoded_value = tx_add_input.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020000000000000004003d020000000100000000000000000000000000000000000000000000000000000000000000000100000000ffffffff01a08601000000000001510000000000000000fffffffd").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxAddInput = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_add_input);
+		assert_eq!(decoded.prevtx, prevtx);
+	}
+
+	#[test]
+	fn encoding_tx_remove_input_output() {
+		let tx_remove_input = msgs::TxRemoveInput {
+			channel_id: [2; 32],
+			serial_id: 4,
+		};
+		let encoded_value = tx_remove_input.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020000000000000004").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxRemoveInput = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_remove_input);
+
+		let tx_remove_output = msgs::TxRemoveOutput {
+			channel_id: [2; 32],
+			serial_id: 5,
+		};
+		let encoded_value = tx_remove_output.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202020000000000000005").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxRemoveOutput = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_remove_output);
+	}
+
+	#[test]
+	fn encoding_tx_signatures() {
+		let tx_signatures = msgs::TxSignatures {
+			channel_id: [2; 32],
+			txid: Txid::from_hex("c2d4449afa8d26140898dd54d3390b057ba2a5afcf03ba29d7dc0d8b9ffe966e").unwrap(),
+			witnesses: vec![vec![vec![1, 2], vec![3]]],
+			shared_input_signature: None,
+		};
+		let encoded_value = tx_signatures.encode();
+		let target_value = hex::decode("02020202020202020202020202020202020202020202020202020202020202026e96fe9f8b0ddcd729ba03cfafa5a27b050b39d354dd980814268dfa9a44d4c200010006020201020103").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxSignatures = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_signatures);
+
+		let secp_ctx = Secp256k1::new();
+		let (privkey, _) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let sig = get_sig_on!(privkey, secp_ctx, String::from("01010101010101010101010101010101"));
+		let tx_signatures = msgs::TxSignatures {
+			shared_input_signature: Some(sig),
+			..tx_signatures
+		};
+		let encoded_value = tx_signatures.encode();
+		let mut target_value = target_value;
+		target_value.append(&mut hex::decode("0040d977cb9b53d93a6ff64bb5f1e158b4094b66e798fb12911168a3ccdf80a83096340a6a95da0ae8d9f776528eecdbb747eb6b545495a4319ed5378e35b21e073a").unwrap());
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxSignatures = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_signatures);
+	}
+
+	#[test]
+	fn encoding_splice() {
+		let splice = msgs::Splice {
+			channel_id: [2; 32],
+			relative_satoshis: -100_000,
+			funding_feerate_perkw: 253,
+			locktime: 500,
+		};
+		let encoded_value = splice.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202fffffffffffe7960000000fd000001f4").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::Splice = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == splice);
+
+		let tx_abort = msgs::TxAbort {
+			channel_id: [2; 32],
+			data: String::from("rust-lightning"),
+		};
+		let encoded_value = tx_abort.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202000e727573742d6c696768746e696e67").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::TxAbort = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == tx_abort);
+	}
+
 	#[test]
 	fn encoding_funding_locked() {
 		let secp_ctx = Secp256k1::new();
@@ -2240,12 +3146,29 @@ mod tests {
 		let funding_locked = msgs::FundingLocked {
 			channel_id: [2; 32],
 			next_per_commitment_point: pubkey_1,
+			short_channel_id_alias: None,
 		};
 		let encoded_value = funding_locked.encode();
 		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f").unwrap();
 		assert_eq!(encoded_value, target_value);
 	}
 
+	#[test]
+	fn encoding_funding_locked_with_alias() {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1,) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let funding_locked = msgs::FundingLocked {
+			channel_id: [2; 32],
+			next_per_commitment_point: pubkey_1,
+			short_channel_id_alias: Some(0x0102030405060708),
+		};
+		let encoded_value = funding_locked.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f01080102030405060708").unwrap();
+		assert_eq!(encoded_value, target_value);
+		let decoded: msgs::FundingLocked = Readable::read(&mut Cursor::new(&encoded_value)).unwrap();
+		assert!(decoded == funding_locked);
+	}
+
 	fn do_encoding_shutdown(script_type: u8) {
 		let secp_ctx = Secp256k1::new();
 		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
@@ -2498,6 +3421,8 @@ mod tests {
 		let mut msg = msgs::OnionHopData {
 			format: OnionHopDataFormat::FinalNode {
 				payment_data: None,
+				keysend_preimage: None,
+				custom_tlvs: Vec::new(),
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2506,7 +3431,9 @@ mod tests {
 		let target_value = hex::decode("1002080badf00d010203040404ffffffff").unwrap();
 		assert_eq!(encoded_value, target_value);
 		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
-		if let OnionHopDataFormat::FinalNode { payment_data: None } = msg.format { } else { panic!(); }
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: None, ref custom_tlvs } = msg.format {
+			assert!(custom_tlvs.is_empty());
+		} else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
@@ -2520,6 +3447,8 @@ mod tests {
 					payment_secret: expected_payment_secret,
 					total_msat: 0x1badca1f
 				}),
+				keysend_preimage: None,
+				custom_tlvs: Vec::new(),
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -2532,14 +3461,75 @@ mod tests {
 			payment_data: Some(FinalOnionHopData {
 				payment_secret,
 				total_msat: 0x1badca1f
-			})
+			}),
+			keysend_preimage: None,
+			ref custom_tlvs,
 		} = msg.format {
+			assert!(custom_tlvs.is_empty());
 			assert_eq!(payment_secret, expected_payment_secret);
 		} else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
 
+	#[test]
+	fn encoding_final_onion_hop_data_with_keysend_preimage() {
+		let expected_preimage = PaymentPreimage([0x42u8; 32]);
+		let mut msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				keysend_preimage: Some(expected_preimage),
+				custom_tlvs: Vec::new(),
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let encoded_value = msg.encode();
+		let target_value = hex::decode("3a02080badf00d010203040404ffffffffff0000000146c6616c204242424242424242424242424242424242424242424242424242424242424242").unwrap();
+		assert_eq!(encoded_value, target_value);
+		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(preimage), ref custom_tlvs } = msg.format {
+			assert!(custom_tlvs.is_empty());
+			assert_eq!(preimage, expected_preimage);
+		} else { panic!(); }
+		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
+		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
+
+		// A keysend preimage is only valid in the final hop's payload
+		let target_value = hex::decode("4402080badf00d010203040404ffffffff06080102030405060708ff0000000146c6616c204242424242424242424242424242424242424242424242424242424242424242").unwrap();
+		if let Err(msgs::DecodeError::InvalidValue) = <msgs::OnionHopData as Readable>::read(&mut Cursor::new(&target_value[..])) { } else { panic!(); }
+	}
+
+	#[test]
+	fn encoding_final_onion_hop_data_with_custom_tlvs() {
+		// Custom records must be written in type order, interleaved with our own
+		let expected_preimage = PaymentPreimage([0x42u8; 32]);
+		let expected_custom_tlvs = vec![(65537, vec![0x01, 0x02]), (5482373485, vec![])];
+		let mut msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				keysend_preimage: Some(expected_preimage),
+				custom_tlvs: vec![(5482373485, vec![]), (65537, vec![0x01, 0x02])],
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let encoded_value = msg.encode();
+		let target_value = hex::decode("4c02080badf00d010203040404fffffffffe00010001020102ff0000000146c6616c204242424242424242424242424242424242424242424242424242424242424242ff0000000146c6616d00").unwrap();
+		assert_eq!(encoded_value, target_value);
+		msg = Readable::read(&mut Cursor::new(&target_value[..])).unwrap();
+		if let OnionHopDataFormat::FinalNode { payment_data: None, keysend_preimage: Some(preimage), ref custom_tlvs } = msg.format {
+			assert_eq!(preimage, expected_preimage);
+			assert_eq!(*custom_tlvs, expected_custom_tlvs);
+		} else { panic!(); }
+
+		// Unknown even custom records must be rejected, as must custom records on non-final hops
+		let target_value = hex::decode("1602080badf00d010203040404fffffffffe0001000000").unwrap();
+		if let Err(msgs::DecodeError::UnknownRequiredFeature) = <msgs::OnionHopData as Readable>::read(&mut Cursor::new(&target_value[..])) { } else { panic!(); }
+		let target_value = hex::decode("2002080badf00d010203040404ffffffff06080102030405060708fe0001000100").unwrap();
+		if let Err(msgs::DecodeError::InvalidValue) = <msgs::OnionHopData as Readable>::read(&mut Cursor::new(&target_value[..])) { } else { panic!(); }
+	}
+
 	#[test]
 	fn encoding_query_channel_range() {
 		let mut query_channel_range = msgs::QueryChannelRange {
@@ -2556,6 +3546,24 @@ mod tests {
 		assert_eq!(query_channel_range.number_of_blocks, 1500);
 	}
 
+	#[test]
+	fn query_channel_range_end_blocknum() {
+		let tests: Vec<(u32, u32, u32)> = vec![
+			(10000, 1500, 11500),
+			(0, 0xffffffff, 0xffffffff),
+			(1, 0xffffffff, 0xffffffff),
+		];
+
+		for (first_blocknum, number_of_blocks, expected) in tests.into_iter() {
+			let sut = msgs::QueryChannelRange {
+				chain_hash: BlockHash::from_hex("06226e46111a0b59caaf126043eb5bbf28c34f3a5e332a1fc7b2b73cf188910f").unwrap(),
+				first_blocknum,
+				number_of_blocks,
+			};
+			assert_eq!(sut.end_blocknum(), expected);
+		}
+	}
+
 	#[test]
 	fn encoding_reply_channel_range() {
 		do_encoding_reply_channel_range(0);
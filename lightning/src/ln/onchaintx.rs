@@ -24,12 +24,12 @@ use bitcoin::secp256k1;
 use ln::msgs::DecodeError;
 use ln::channelmanager::PaymentPreimage;
 use ln::chan_utils;
-use ln::chan_utils::{TxCreationKeys, ChannelTransactionParameters, HolderCommitmentTransaction};
-use chain::chaininterface::{FeeEstimator, BroadcasterInterface, ConfirmationTarget, MIN_RELAY_FEE_SAT_PER_1000_WEIGHT};
+use ln::chan_utils::{TxCreationKeys, ChannelTransactionParameters, HolderCommitmentTransaction, ANCHOR_OUTPUT_VALUE_SATOSHI};
+use chain::chaininterface::{FeeEstimator, BroadcasterInterface, ConfirmationTarget, UtxoSource, WalletUtxo, MIN_RELAY_FEE_SAT_PER_1000_WEIGHT};
 use chain::channelmonitor::{ANTI_REORG_DELAY, CLTV_SHARED_CLAIM_BUFFER, InputMaterial, ClaimRequest};
 use chain::keysinterface::{ChannelKeys, KeysInterface};
 use util::logger::Logger;
-use util::ser::{Readable, ReadableArgs, Writer, Writeable, VecWriter};
+use util::ser::{Readable, Writer, Writeable, VecWriter};
 use util::byte_utils;
 
 use std::collections::{HashMap, hash_map};
@@ -38,6 +38,12 @@ use std::ops::Deref;
 
 const MAX_ALLOC_SIZE: usize = 64*1024;
 
+// number_of_witness_elements + sig_length + sig + witness_script_length + witness_script
+const ANCHOR_INPUT_WITNESS_WEIGHT: u64 = 1 + 1 + 73 + 1 + 40;
+// The value under which we don't bother creating a change output in a CPFP transaction and give
+// up on bumping instead, as the child wouldn't relay.
+const CPFP_CHANGE_DUST_LIMIT_SATOSHIS: u64 = 546;
+
 /// Upon discovering of some classes of onchain tx by ChannelMonitor, we may have to take actions on it
 /// once they mature to enough confirmations (ANTI_REORG_DELAY)
 #[derive(Clone, PartialEq)]
@@ -101,6 +107,57 @@ impl Readable for ClaimTxBumpMaterial {
 	}
 }
 
+/// Cache of the state needed to bump a holder commitment transaction we broadcast with a
+/// child-pays-for-parent transaction spending our anchor output.
+#[derive(Clone, PartialEq)]
+struct HolderCommitmentCpfp {
+	commitment_txid: Txid,
+	// At every block tick, used to check if the commitment transaction is taking too much time for
+	// confirmation and we need to (re-)generate a child. None until we first tried to.
+	height_timer: Option<u32>,
+	// Package feerate and absolute fee of the previous child transaction, 0 if we didn't broadcast
+	// one yet. A new child replaces the previous one, so it has to comply with RBF rules.
+	feerate_previous: u32,
+	fee_previous: u64,
+}
+
+impl_writeable!(HolderCommitmentCpfp, 0, {
+	commitment_txid,
+	height_timer,
+	feerate_previous,
+	fee_previous
+});
+
+/// Cache of the state needed to claim an HTLC output on a holder commitment transaction of an
+/// option_anchors_zero_fee_htlc_tx channel. Such HTLC transactions are zero-fee, so we attach
+/// wallet UTXOs to them to pay the fee, bumping it on height timer expiration as other claims.
+#[derive(Clone, PartialEq)]
+struct HolderHTLCClaim {
+	preimage: Option<PaymentPreimage>,
+	// The HTLC output is 1 OP_CSV encumbered, so we can only broadcast a claim once the commitment
+	// transaction confirmed.
+	commitment_height: Option<u32>,
+	// At every block tick, used to check if the HTLC transaction is taking too much time for
+	// confirmation and we need to bump it. None until we first broadcast it.
+	height_timer: Option<u32>,
+	// Feerate and absolute fee of the previous HTLC transaction, 0 if we didn't broadcast one
+	// yet. A new transaction replaces the previous one, so it has to comply with RBF rules.
+	feerate_previous: u32,
+	fee_previous: u64,
+	// Set once a transaction spending the HTLC output confirmed, ours or not, to stop tracking
+	// the claim after ANTI_REORG_DELAY.
+	spent_height: Option<u32>,
+}
+
+impl_writeable!(HolderHTLCClaim, 0, {
+	preimage,
+	commitment_height,
+	height_timer,
+	feerate_previous,
+	fee_previous,
+	spent_height
+});
+
 #[derive(PartialEq, Clone, Copy)]
 pub(crate) enum InputDescriptors {
 	RevokedOfferedHTLC,
@@ -283,6 +340,15 @@ pub struct OnchainTxHandler<ChanSigner: ChannelKeys> {
 
 	latest_height: u32,
 
+	// Set once we broadcast a holder commitment transaction we may bump through CPFP, ie one with
+	// an anchor output of ours, until a transaction spending the funding output confirms.
+	holder_commitment_cpfp: Option<HolderCommitmentCpfp>,
+
+	// Claims of HTLC outputs on holder commitment transactions of option_anchors_zero_fee_htlc_tx
+	// channels, which we don't track in pending_claim_requests as they need wallet UTXOs to be
+	// broadcast, see bump_holder_htlc_claims.
+	holder_htlc_claims: HashMap<BitcoinOutPoint, HolderHTLCClaim>,
+
 	secp_ctx: Secp256k1<secp256k1::All>,
 }
 
@@ -335,12 +401,22 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			}
 		}
 		self.latest_height.write(writer)?;
+		self.holder_commitment_cpfp.write(writer)?;
+
+		writer.write_all(&byte_utils::be64_to_array(self.holder_htlc_claims.len() as u64))?;
+		for (ref outp, ref claim) in self.holder_htlc_claims.iter() {
+			outp.write(writer)?;
+			claim.write(writer)?;
+		}
 		Ok(())
 	}
 }
 
-impl<'a, K: KeysInterface> ReadableArgs<&'a K> for OnchainTxHandler<K::ChanKeySigner> {
-	fn read<R: ::std::io::Read>(reader: &mut R, keys_manager: &'a K) -> Result<Self, DecodeError> {
+impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
+	/// Reads an OnchainTxHandler written by a ChannelMonitor of the given serialization version.
+	/// Version 1 didn't include channel_value_satoshis in the channel parameters, so it is given
+	/// here, nor holder_commitment_cpfp and holder_htlc_claims, which we leave unset.
+	pub(crate) fn read<R: ::std::io::Read, K: KeysInterface<ChanKeySigner = ChanSigner>>(reader: &mut R, keys_manager: &K, monitor_version: u8, channel_value_satoshis: u64) -> Result<Self, DecodeError> {
 		let destination_script = Readable::read(reader)?;
 
 		let holder_commitment = Readable::read(reader)?;
@@ -348,7 +424,11 @@ impl<'a, K: KeysInterface> ReadableArgs<&'a K> for OnchainTxHandler<K::ChanKeySi
 		let prev_holder_commitment = Readable::read(reader)?;
 		let prev_holder_htlc_sigs = Readable::read(reader)?;
 
-		let channel_parameters = Readable::read(reader)?;
+		let channel_parameters = if monitor_version >= 2 { Readable::read(reader)? } else {
+			let mut channel_parameters = ChannelTransactionParameters::read_v1(reader)?;
+			channel_parameters.channel_value_satoshis = channel_value_satoshis;
+			channel_parameters
+		};
 
 		let keys_len: u32 = Readable::read(reader)?;
 		let mut keys_data = Vec::with_capacity(cmp::min(keys_len as usize, MAX_ALLOC_SIZE));
@@ -404,6 +484,15 @@ impl<'a, K: KeysInterface> ReadableArgs<&'a K> for OnchainTxHandler<K::ChanKeySi
 			onchain_events_waiting_threshold_conf.insert(height_target, events);
 		}
 		let latest_height = Readable::read(reader)?;
+		let holder_commitment_cpfp = if monitor_version >= 2 { Readable::read(reader)? } else { None };
+		let mut holder_htlc_claims = HashMap::new();
+		if monitor_version >= 2 {
+			let holder_htlc_claims_len: u64 = Readable::read(reader)?;
+			holder_htlc_claims.reserve(cmp::min(holder_htlc_claims_len as usize, MAX_ALLOC_SIZE / 128));
+			for _ in 0..holder_htlc_claims_len {
+				holder_htlc_claims.insert(Readable::read(reader)?, Readable::read(reader)?);
+			}
+		}
 
 		Ok(OnchainTxHandler {
 			destination_script,
@@ -417,6 +506,8 @@ impl<'a, K: KeysInterface> ReadableArgs<&'a K> for OnchainTxHandler<K::ChanKeySi
 			pending_claim_requests,
 			onchain_events_waiting_threshold_conf,
 			latest_height,
+			holder_commitment_cpfp,
+			holder_htlc_claims,
 			secp_ctx: Secp256k1::new(),
 		})
 	}
@@ -439,14 +530,20 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 			claimable_outpoints: HashMap::new(),
 			onchain_events_waiting_threshold_conf: HashMap::new(),
 			latest_height: 0,
+			holder_commitment_cpfp: None,
+			holder_htlc_claims: HashMap::new(),
 
 			secp_ctx: Secp256k1::new(),
 		}
 	}
 
-	pub(crate) fn get_witnesses_weight(inputs: &[InputDescriptors]) -> usize {
+	pub(crate) fn get_witnesses_weight(inputs: &[InputDescriptors], opt_anchors: bool) -> usize {
 		let mut tx_weight = 2; // count segwit flags
 		for inp in inputs {
+			// option_anchors_zero_fee_htlc_tx adds 1 OP_CSV OP_DROP to the HTLC witness scripts
+			if opt_anchors && *inp != InputDescriptors::RevokedOutput {
+				tx_weight += 3;
+			}
 			// We use expected weight (and not actual) as signatures and time lock delays may vary
 			tx_weight +=  match inp {
 				// number_of_witness_elements + sig_length + revocation_sig + pubkey_length + revocationpubkey + witness_script_length + witness_script
@@ -495,13 +592,20 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					L::Target: Logger,
 	{
 		if cached_claim_datas.per_input_material.len() == 0 { return None } // But don't prune pending claiming request yet, we may have to resurrect HTLCs
+		let opt_anchors = self.channel_transaction_parameters.opt_anchors;
 		let mut inputs = Vec::new();
-		for outp in cached_claim_datas.per_input_material.keys() {
+		for (outp, per_outp_material) in cached_claim_datas.per_input_material.iter() {
 			log_trace!(logger, "Outpoint {}:{}", outp.txid, outp.vout);
+			// With option_anchors_zero_fee_htlc_tx, the non-revocation paths of counterparty HTLC
+			// outputs are encumbered by 1 OP_CSV, which still signals RBF.
+			let sequence = match per_outp_material {
+				&InputMaterial::CounterpartyHTLC { .. } if opt_anchors => 1,
+				_ => 0xfffffffd,
+			};
 			inputs.push(TxIn {
 				previous_output: *outp,
 				script_sig: Script::new(),
-				sequence: 0xfffffffd,
+				sequence,
 				witness: Vec::new(),
 			});
 		}
@@ -563,11 +667,11 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		for per_outp_material in cached_claim_datas.per_input_material.values() {
 			match per_outp_material {
 				&InputMaterial::Revoked { ref input_descriptor, ref amount, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor]);
+					inputs_witnesses_weight += Self::get_witnesses_weight(&[*input_descriptor], opt_anchors);
 					amt += *amount;
 				},
 				&InputMaterial::CounterpartyHTLC { ref preimage, ref htlc, .. } => {
-					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] });
+					inputs_witnesses_weight += Self::get_witnesses_weight(if preimage.is_some() { &[InputDescriptors::OfferedHTLC] } else { &[InputDescriptors::ReceivedHTLC] }, opt_anchors);
 					amt += htlc.amount_msat / 1000;
 				},
 				&InputMaterial::HolderHTLC { .. } => {
@@ -605,7 +709,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						if let Ok(chan_keys) = TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, counterparty_delayed_payment_base_key, counterparty_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
 
 							let witness_script = if let Some(ref htlc) = *htlc {
-								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key)
+								chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, opt_anchors, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key)
 							} else {
 								chan_utils::get_revokeable_redeemscript(&chan_keys.revocation_key, *on_counterparty_tx_csv, &chan_keys.broadcaster_delayed_payment_key)
 							};
@@ -627,7 +731,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					},
 					&InputMaterial::CounterpartyHTLC { ref per_commitment_point, ref counterparty_delayed_payment_base_key, ref counterparty_htlc_base_key, ref preimage, ref htlc } => {
 						if let Ok(chan_keys) = TxCreationKeys::derive_new(&self.secp_ctx, &per_commitment_point, counterparty_delayed_payment_base_key, counterparty_htlc_base_key, &self.key_storage.pubkeys().revocation_basepoint, &self.key_storage.pubkeys().htlc_basepoint) {
-							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key);
+							let witness_script = chan_utils::get_htlc_redeemscript_with_explicit_keys(&htlc, opt_anchors, &chan_keys.broadcaster_htlc_key, &chan_keys.countersignatory_htlc_key, &chan_keys.revocation_key);
 
 							if !preimage.is_some() { bumped_tx.lock_time = htlc.cltv_expiry }; // Right now we don't aggregate time-locked transaction, if we do we should set lock_time before to avoid breaking hash computation
 							if let Ok(sig) = self.key_storage.sign_counterparty_htlc_transaction(&bumped_tx, i, &htlc.amount_msat / 1000, &per_commitment_point, htlc, &self.secp_ctx) {
@@ -657,7 +761,8 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 						let htlc_tx = self.get_fully_signed_htlc_tx(outp, preimage);
 						if let Some(htlc_tx) = htlc_tx {
 							let feerate = (amount - htlc_tx.output[0].value) * 1000 / htlc_tx.get_weight() as u64;
-							// Timer set to $NEVER given we can't bump tx without anchor outputs
+							// Timer set to $NEVER given we can't bump tx without anchor outputs, with
+							// which we go through bump_holder_htlc_claims instead.
 							log_trace!(logger, "Going to broadcast Holder HTLC-{} claiming HTLC output {} from {}...", if preimage.is_some() { "Success" } else { "Timeout" }, outp.vout, outp.txid);
 							return Some((None, feerate as u32, htlc_tx));
 						}
@@ -665,7 +770,9 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 					},
 					&InputMaterial::Funding { ref funding_redeemscript } => {
 						let signed_tx = self.get_fully_signed_holder_tx(funding_redeemscript).unwrap();
-						// Timer set to $NEVER given we can't bump tx without anchor outputs
+						// Timer set to $NEVER given we can't RBF-bump it, but we may CPFP-bump it
+						// through our anchor output, see bump_holder_commitment_cpfp.
+						self.track_holder_commitment_cpfp(&signed_tx);
 						log_trace!(logger, "Going to broadcast Holder Transaction {} claiming funding output {} from {}...", signed_tx.txid(), outp.vout, outp.txid);
 						return Some((None, self.holder_commitment.as_ref().unwrap().feerate_per_kw(), signed_tx));
 					}
@@ -697,6 +804,19 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		// Try to aggregate outputs if their timelock expiration isn't imminent (absolute_timelock
 		// <= CLTV_SHARED_CLAIM_BUFFER) and they don't require an immediate nLockTime (aggregable).
 		for req in claimable_outpoints {
+			// Zero-fee HTLC transactions of option_anchors_zero_fee_htlc_tx channels can't be
+			// broadcast as-is
+			if let InputMaterial::HolderHTLC { ref preimage, .. } = req.witness_data {
+				if self.channel_transaction_parameters.opt_anchors {
+					if self.holder_htlc_claims.contains_key(&req.outpoint) {
+						log_trace!(logger, "Bouncing off outpoint {}:{}, already registered its claiming request", req.outpoint.txid, req.outpoint.vout);
+					} else {
+						log_trace!(logger, "Registering claiming request for {}:{}, to be broadcast with wallet inputs", req.outpoint.txid, req.outpoint.vout);
+						self.holder_htlc_claims.insert(req.outpoint, HolderHTLCClaim { preimage: *preimage, commitment_height: None, height_timer: None, feerate_previous: 0, fee_previous: 0, spent_height: None });
+					}
+					continue;
+				}
+			}
 			// Don't claim a outpoint twice that would be bad for privacy and may uselessly lock a CPFP input for a while
 			if let Some(_) = self.claimable_outpoints.get(&req.outpoint) { log_trace!(logger, "Bouncing off outpoint {}:{}, already registered its claiming request", req.outpoint.txid, req.outpoint.vout); } else {
 				log_trace!(logger, "Test if outpoint can be aggregated with expiration {} against {}", req.absolute_timelock, height + CLTV_SHARED_CLAIM_BUFFER);
@@ -734,6 +854,22 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 
 		let mut bump_candidates = HashMap::new();
 		for tx in txn_matched {
+			// Once the funding output is spent on-chain, by our holder commitment transaction or
+			// any other, there is nothing left to CPFP-bump.
+			if let Some(funding_outpoint) = self.channel_transaction_parameters.funding_outpoint {
+				if tx.input.iter().any(|inp| inp.previous_output == funding_outpoint.into_bitcoin_outpoint()) {
+					self.holder_commitment_cpfp = None;
+				}
+			}
+			let txid = tx.txid();
+			for (outp, claim) in self.holder_htlc_claims.iter_mut() {
+				if outp.txid == txid {
+					claim.commitment_height = Some(height);
+				}
+				if tx.input.iter().any(|inp| inp.previous_output == *outp) {
+					claim.spent_height = Some(height);
+				}
+			}
 			// Scan all input to verify is one of the outpoint spent is of interest for us
 			let mut claimed_outputs_material = Vec::new();
 			for inp in &tx.input {
@@ -832,6 +968,7 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 				}
 			}
 		}
+		self.holder_htlc_claims.retain(|_, claim| claim.spent_height.map(|h| h + ANTI_REORG_DELAY - 1 > height).unwrap_or(true));
 
 		// Check if any pending claim request must be rescheduled
 		for (first_claim_txid, ref claim_data) in self.pending_claim_requests.iter() {
@@ -891,6 +1028,17 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		for (ancestor_claim_txid, claim_material) in bump_candidates.drain() {
 			self.pending_claim_requests.insert(ancestor_claim_txid.0, claim_material);
 		}
+		// Our HTLC transactions were reorged out along with the block, rebroadcast them at next tick
+		for claim in self.holder_htlc_claims.values_mut() {
+			if claim.commitment_height == Some(height) {
+				claim.commitment_height = None;
+				claim.height_timer = None;
+			}
+			if claim.spent_height == Some(height) {
+				claim.spent_height = None;
+				claim.height_timer = None;
+			}
+		}
 		//TODO: if we implement cross-block aggregated claim transaction we need to refresh set of outpoints and regenerate tx but
 		// right now if one of the outpoint get disconnected, just erase whole pending claim request.
 		let mut remove_request = Vec::new();
@@ -904,11 +1052,332 @@ impl<ChanSigner: ChannelKeys> OnchainTxHandler<ChanSigner> {
 		}
 	}
 
+	/// Starts tracking a holder commitment transaction we broadcast, so that it may be bumped
+	/// through CPFP by bump_holder_commitment_cpfp until it confirms. Only commitment transactions
+	/// with option_anchors_zero_fee_htlc_tx have an output of ours which is spendable before they
+	/// confirm.
+	pub(crate) fn track_holder_commitment_cpfp(&mut self, commitment_tx: &Transaction) {
+		if !self.channel_transaction_parameters.opt_anchors { return; }
+		let commitment_txid = commitment_tx.txid();
+		if let Some(ref cpfp) = self.holder_commitment_cpfp {
+			if cpfp.commitment_txid == commitment_txid { return; }
+		}
+		self.holder_commitment_cpfp = Some(HolderCommitmentCpfp { commitment_txid, height_timer: None, feerate_previous: 0, fee_previous: 0 });
+	}
+
+	/// A holder commitment transaction pays the feerate agreed with our counterparty, which may be
+	/// too low to confirm in time during fee spikes, and as we can't RBF-bump it, we bump it by
+	/// broadcasting a child transaction spending our anchor output along with wallet UTXOs. As for
+	/// claim transactions, the child is (re-)generated at height timer expiration, each time
+	/// replacing the previous one at a higher package feerate, until the commitment confirms.
+	pub(crate) fn bump_holder_commitment_cpfp<U: Deref, B: Deref, F: Deref, L: Deref>(&mut self, height: u32, utxo_source: &U, broadcaster: &B, fee_estimator: &F, logger: &L)
+		where U::Target: UtxoSource,
+		      B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let cpfp = match self.holder_commitment_cpfp {
+			Some(ref cpfp) if cpfp.height_timer.map(|h| h <= height).unwrap_or(true) => cpfp.clone(),
+			_ => return,
+		};
+		let funding_redeemscript = chan_utils::make_funding_redeemscript(&self.channel_transaction_parameters.holder_pubkeys.funding_pubkey,
+			&self.channel_transaction_parameters.counterparty_parameters.as_ref().unwrap().pubkeys.funding_pubkey);
+		let commitment_tx = match self.get_fully_signed_holder_tx(&funding_redeemscript) {
+			Some(tx) => tx,
+			None => return,
+		};
+		if commitment_tx.txid() != cpfp.commitment_txid {
+			log_error!(logger, "Holder commitment transaction {} to CPFP-bump isn't our latest one anymore", cpfp.commitment_txid);
+			self.holder_commitment_cpfp = None;
+			return;
+		}
+		// The soonest HTLC expiration gives us the deadline for the commitment transaction to confirm
+		let soonest_timelock = self.holder_commitment.as_ref().unwrap().htlcs().iter().map(|htlc| htlc.cltv_expiry).min().unwrap_or(::std::u32::MAX);
+		let new_timer = Some(Self::get_height_timer(height, soonest_timelock));
+
+		let mut new_cpfp = cpfp.clone();
+		new_cpfp.height_timer = new_timer;
+		if let Some((new_feerate, new_fee, child_tx)) = self.generate_cpfp_tx(&commitment_tx, &cpfp, utxo_source, fee_estimator, logger) {
+			log_trace!(logger, "Going to broadcast CPFP Transaction {} bumping holder commitment transaction {} to package feerate {}...", child_tx.txid(), cpfp.commitment_txid, new_feerate);
+			log_trace!(logger, "...with timer {}", new_timer.unwrap());
+			// The commitment transaction may have been evicted from mempools, rebroadcast it first.
+			broadcaster.broadcast_transaction(&commitment_tx);
+			log_trace!(logger, "Broadcast onchain {}", log_tx!(child_tx));
+			broadcaster.broadcast_transaction(&child_tx);
+			new_cpfp.feerate_previous = new_feerate;
+			new_cpfp.fee_previous = new_fee;
+		}
+		self.holder_commitment_cpfp = Some(new_cpfp);
+	}
+
+	/// Gets the feerate at which to (re-)broadcast a transaction we pay the fee of with wallet
+	/// UTXOs, given the feerate of the transaction it replaces, if any.
+	fn get_bumped_feerate<F: Deref>(feerate_previous: u32, fee_estimator: &F) -> u64
+		where F::Target: FeeEstimator,
+	{
+		// If old feerate inferior to actual one given back by Fee Estimator, use it to compute new fee...
+		let high_prio_feerate = fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority);
+		if feerate_previous < high_prio_feerate {
+			high_prio_feerate as u64
+		// ...else just increase the previous feerate by 25% (because that's a nice number)
+		} else {
+			feerate_previous as u64 * 1250 / 1000
+		}
+	}
+
+	/// Builds and signs a child of the given holder commitment transaction, spending our anchor
+	/// output and wallet UTXOs, and returns its package feerate, its fee and the child itself.
+	fn generate_cpfp_tx<U: Deref, F: Deref, L: Deref>(&self, commitment_tx: &Transaction, cpfp: &HolderCommitmentCpfp, utxo_source: &U, fee_estimator: &F, logger: &L) -> Option<(u32, u64, Transaction)>
+		where U::Target: UtxoSource,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let anchor_redeemscript = chan_utils::get_anchor_redeemscript(&self.channel_transaction_parameters.holder_pubkeys.funding_pubkey);
+		let anchor_script_pubkey = anchor_redeemscript.to_v0_p2wsh();
+		let anchor_vout = match commitment_tx.output.iter().position(|outp| outp.script_pubkey == anchor_script_pubkey) {
+			Some(vout) => vout,
+			None => {
+				log_trace!(logger, "Can't CPFP-bump holder commitment transaction {}, it has no anchor output of ours", cpfp.commitment_txid);
+				return None;
+			}
+		};
+
+		let new_feerate = Self::get_bumped_feerate(cpfp.feerate_previous, fee_estimator);
+		let commitment_feerate = self.holder_commitment.as_ref().unwrap().feerate_per_kw() as u64;
+		if cpfp.fee_previous == 0 && new_feerate <= commitment_feerate {
+			log_trace!(logger, "Not CPFP-bumping holder commitment transaction {} as its feerate {} is high enough", cpfp.commitment_txid, commitment_feerate);
+			return None;
+		}
+
+		let mut child_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: BitcoinOutPoint { txid: cpfp.commitment_txid, vout: anchor_vout as u32 },
+				script_sig: Script::new(),
+				sequence: 0xfffffffd,
+				witness: Vec::new(),
+			}],
+			output: vec![TxOut {
+				script_pubkey: utxo_source.get_change_script(),
+				value: 0,
+			}],
+		};
+		let commitment_weight = commitment_tx.get_weight() as u64;
+		let commitment_fee = commitment_feerate * commitment_weight / 1000;
+		// Count segwit flags and our anchor input witness, each wallet input adds its own weight
+		let child_base_weight = child_tx.get_weight() as u64 + 2 + ANCHOR_INPUT_WITNESS_WEIGHT;
+		let child_fee_for = |utxos: &[WalletUtxo]| -> (u64, u64) {
+			let child_weight = child_base_weight + utxos.iter().map(|utxo| (32 + 4 + 1 + 4) * 4 + utxo.satisfaction_weight).sum::<u64>();
+			let package_fee = new_feerate * (commitment_weight + child_weight) / 1000;
+			// BIP 125 Opt-in Full Replace-by-Fee Signaling
+			// 	* 3. The replacement transaction pays an absolute fee of at least the sum paid by the original transactions.
+			//	* 4. The replacement transaction must also pay for its own bandwidth at or above the rate set by the node's minimum relay fee setting.
+			let min_fee = if cpfp.fee_previous != 0 { cpfp.fee_previous + MIN_RELAY_FEE_SAT_PER_1000_WEIGHT * child_weight / 1000 } else { 0 };
+			(cmp::max(package_fee.saturating_sub(commitment_fee), min_fee), child_weight)
+		};
+
+		// Wallet inputs add weight to the child, so we may have to ask for UTXOs a second time.
+		let mut utxos = Vec::new();
+		let (mut child_fee, mut child_weight) = child_fee_for(&utxos);
+		let mut utxos_value = 0;
+		for _ in 0..2 {
+			let target_value = (child_fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS).saturating_sub(ANCHOR_OUTPUT_VALUE_SATOSHI);
+			utxos = utxo_source.get_utxos(target_value);
+			let (fee, weight) = child_fee_for(&utxos);
+			child_fee = fee;
+			child_weight = weight;
+			utxos_value = utxos.iter().map(|utxo| utxo.output().value).sum::<u64>();
+			if utxos_value + ANCHOR_OUTPUT_VALUE_SATOSHI >= child_fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS { break; }
+		}
+		if utxos_value + ANCHOR_OUTPUT_VALUE_SATOSHI < child_fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS {
+			log_warn!(logger, "Can't CPFP-bump holder commitment transaction {}, wallet UTXOs ({} sat) are not enough to pay a fee of {} sat", cpfp.commitment_txid, utxos_value, child_fee);
+			return None;
+		}
+
+		for utxo in utxos.iter() {
+			child_tx.input.push(TxIn {
+				previous_output: utxo.outpoint(),
+				script_sig: Script::new(),
+				sequence: 0xfffffffd,
+				witness: Vec::new(),
+			});
+		}
+		child_tx.output[0].value = utxos_value + ANCHOR_OUTPUT_VALUE_SATOSHI - child_fee;
+
+		let mut wallet_witnesses = Vec::with_capacity(utxos.len());
+		for (i, utxo) in utxos.iter().enumerate() {
+			match utxo_source.sign_input(&child_tx, i + 1, utxo) {
+				Ok(witness) => wallet_witnesses.push(witness),
+				Err(_) => {
+					log_error!(logger, "Failed to sign wallet input {}:{} of CPFP transaction", utxo.prev_tx.txid(), utxo.prev_vout);
+					return None;
+				}
+			}
+		}
+		if let Ok(sig) = self.key_storage.sign_holder_anchor_input(&child_tx, 0, &self.secp_ctx) {
+			child_tx.input[0].witness.push(sig.serialize_der().to_vec());
+			child_tx.input[0].witness[0].push(SigHashType::All as u8);
+			child_tx.input[0].witness.push(anchor_redeemscript.into_bytes());
+		} else { return None; }
+		for (inp, witness) in child_tx.input.iter_mut().skip(1).zip(wallet_witnesses.drain(..)) {
+			inp.witness = witness;
+		}
+
+		let package_feerate = (commitment_fee + child_fee) * 1000 / (commitment_weight + child_weight);
+		Some((package_feerate as u32, child_fee, child_tx))
+	}
+
+	/// HTLC transactions of option_anchors_zero_fee_htlc_tx channels are zero-fee, so we pay their
+	/// fee by adding wallet UTXOs and a change output to them, which our counterparty's
+	/// SIGHASH_SINGLE|SIGHASH_ANYONECANPAY signature allows. As for claim transactions, they are
+	/// (re-)generated at height timer expiration, each time replacing the previous one at a higher
+	/// feerate, until one confirms.
+	pub(crate) fn bump_holder_htlc_claims<U: Deref, B: Deref, F: Deref, L: Deref>(&mut self, height: u32, utxo_source: &U, broadcaster: &B, fee_estimator: &F, logger: &L)
+		where U::Target: UtxoSource,
+		      B::Target: BroadcasterInterface,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let mut bump_candidates = Vec::new();
+		for (outp, claim) in self.holder_htlc_claims.iter() {
+			if claim.spent_height.is_none() && claim.commitment_height.is_some() && claim.height_timer.map(|h| h <= height).unwrap_or(true) {
+				bump_candidates.push((*outp, claim.clone()));
+			}
+		}
+		for (outp, claim) in bump_candidates.drain(..) {
+			let (offered, cltv_expiry) = match self.get_holder_htlc(&outp) {
+				Some((holder_commitment, htlc_idx)) => {
+					let htlc = &holder_commitment.htlcs()[htlc_idx];
+					(htlc.offered, htlc.cltv_expiry)
+				},
+				None => continue,
+			};
+			// An HTLC-Timeout transaction can't be broadcast before the HTLC expires
+			if offered && cltv_expiry > height { continue; }
+			let new_timer = Some(Self::get_height_timer(height, cltv_expiry));
+
+			let mut new_claim = claim.clone();
+			new_claim.height_timer = new_timer;
+			if let Some((new_feerate, new_fee, htlc_tx)) = self.generate_holder_htlc_tx(&outp, &claim, utxo_source, fee_estimator, logger) {
+				log_trace!(logger, "Going to broadcast Holder HTLC-{} {} claiming HTLC output {} from {} with new feerate {}...", if offered { "Timeout" } else { "Success" }, htlc_tx.txid(), outp.vout, outp.txid, new_feerate);
+				log_trace!(logger, "...with timer {}", new_timer.unwrap());
+				log_trace!(logger, "Broadcast onchain {}", log_tx!(htlc_tx));
+				broadcaster.broadcast_transaction(&htlc_tx);
+				new_claim.feerate_previous = new_feerate;
+				new_claim.fee_previous = new_fee;
+			}
+			self.holder_htlc_claims.insert(outp, new_claim);
+		}
+	}
+
+	/// Builds and signs an HTLC transaction claiming the given HTLC output on a holder commitment
+	/// transaction, spending wallet UTXOs to pay its fee, and returns its feerate, its fee and the
+	/// transaction itself.
+	fn generate_holder_htlc_tx<U: Deref, F: Deref, L: Deref>(&mut self, outp: &BitcoinOutPoint, claim: &HolderHTLCClaim, utxo_source: &U, fee_estimator: &F, logger: &L) -> Option<(u32, u64, Transaction)>
+		where U::Target: UtxoSource,
+		      F::Target: FeeEstimator,
+		      L::Target: Logger,
+	{
+		let mut htlc_tx = self.get_fully_signed_htlc_tx(outp, &claim.preimage)?;
+		htlc_tx.output.push(TxOut {
+			script_pubkey: utxo_source.get_change_script(),
+			value: 0,
+		});
+
+		let new_feerate = Self::get_bumped_feerate(claim.feerate_previous, fee_estimator);
+		// The signed HTLC input is accounted for, each wallet input adds its own weight
+		let base_weight = htlc_tx.get_weight() as u64;
+		let fee_for = |utxos: &[WalletUtxo]| -> (u64, u64) {
+			let weight = base_weight + utxos.iter().map(|utxo| (32 + 4 + 1 + 4) * 4 + utxo.satisfaction_weight).sum::<u64>();
+			// BIP 125 Opt-in Full Replace-by-Fee Signaling
+			// 	* 3. The replacement transaction pays an absolute fee of at least the sum paid by the original transactions.
+			//	* 4. The replacement transaction must also pay for its own bandwidth at or above the rate set by the node's minimum relay fee setting.
+			let min_fee = if claim.fee_previous != 0 { claim.fee_previous + MIN_RELAY_FEE_SAT_PER_1000_WEIGHT * weight / 1000 } else { 0 };
+			(cmp::max(new_feerate * weight / 1000, min_fee), weight)
+		};
+
+		// Wallet inputs add weight to the transaction, so we may have to ask for UTXOs a second time.
+		let mut utxos = Vec::new();
+		let (mut fee, mut weight) = fee_for(&utxos);
+		let mut utxos_value = 0;
+		for _ in 0..2 {
+			utxos = utxo_source.get_utxos(fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS);
+			let (new_fee, new_weight) = fee_for(&utxos);
+			fee = new_fee;
+			weight = new_weight;
+			utxos_value = utxos.iter().map(|utxo| utxo.output().value).sum::<u64>();
+			if utxos_value >= fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS { break; }
+		}
+		if utxos_value < fee + CPFP_CHANGE_DUST_LIMIT_SATOSHIS {
+			log_warn!(logger, "Can't broadcast HTLC transaction claiming {}:{}, wallet UTXOs ({} sat) are not enough to pay a fee of {} sat", outp.txid, outp.vout, utxos_value, fee);
+			return None;
+		}
+
+		for utxo in utxos.iter() {
+			htlc_tx.input.push(TxIn {
+				previous_output: utxo.outpoint(),
+				script_sig: Script::new(),
+				sequence: 0xfffffffd,
+				witness: Vec::new(),
+			});
+		}
+		htlc_tx.output[1].value = utxos_value - fee;
+
+		let mut wallet_witnesses = Vec::with_capacity(utxos.len());
+		for (i, utxo) in utxos.iter().enumerate() {
+			match utxo_source.sign_input(&htlc_tx, i + 1, utxo) {
+				Ok(witness) => wallet_witnesses.push(witness),
+				Err(_) => {
+					log_error!(logger, "Failed to sign wallet input {}:{} of HTLC transaction", utxo.prev_tx.txid(), utxo.prev_vout);
+					return None;
+				}
+			}
+		}
+		// Our signature over the HTLC transaction we got from get_fully_signed_htlc_tx doesn't
+		// commit to the wallet inputs and the change output, sign it again.
+		let (holder_commitment, htlc_idx) = self.get_holder_htlc(outp)?;
+		if let Ok(sig) = self.key_storage.sign_holder_htlc_input(&htlc_tx, 0, holder_commitment, htlc_idx, &self.secp_ctx) {
+			let counterparty_htlc_sig = holder_commitment.counterparty_htlc_sigs[htlc_idx];
+			htlc_tx.input[0].witness = holder_commitment.trust()
+				.get_htlc_input_witness(&self.channel_transaction_parameters.as_holder_broadcastable(), htlc_idx, &counterparty_htlc_sig, &sig, &claim.preimage);
+		} else { return None; }
+		for (inp, witness) in htlc_tx.input.iter_mut().skip(1).zip(wallet_witnesses.drain(..)) {
+			inp.witness = witness;
+		}
+
+		Some(((fee * 1000 / weight) as u32, fee, htlc_tx))
+	}
+
+	/// Gets the holder commitment transaction the given HTLC output is on, along with the index
+	/// of the HTLC in its set of HTLCs.
+	fn get_holder_htlc(&self, outp: &BitcoinOutPoint) -> Option<(&HolderCommitmentTransaction, usize)> {
+		for holder_commitment in self.holder_commitment.iter().chain(self.prev_holder_commitment.iter()) {
+			if holder_commitment.trust().txid() == outp.txid {
+				return holder_commitment.htlcs().iter().position(|htlc| htlc.transaction_output_index == Some(outp.vout))
+					.map(|htlc_idx| (holder_commitment, htlc_idx));
+			}
+		}
+		None
+	}
+
 	pub(crate) fn provide_latest_holder_tx(&mut self, tx: HolderCommitmentTransaction) {
 		self.prev_holder_commitment = self.holder_commitment.take();
 		self.holder_commitment = Some(tx);
 	}
 
+	/// Moves our holder commitment transactions to the funding output of a confirmed splice
+	/// transaction. Commitment transactions spending the previous funding output can't confirm
+	/// anymore, so they are forgotten.
+	pub(crate) fn splice_funding(&mut self, channel_parameters: &ChannelTransactionParameters, tx: HolderCommitmentTransaction) {
+		self.key_storage.splice_channel(channel_parameters);
+		self.channel_transaction_parameters = channel_parameters.clone();
+		self.holder_commitment = Some(tx);
+		self.holder_htlc_sigs = None;
+		self.prev_holder_commitment = None;
+		self.prev_holder_htlc_sigs = None;
+	}
+
 	fn sign_latest_holder_htlcs(&mut self) {
 		if let Some(ref holder_commitment) = self.holder_commitment {
 			if let Ok(sigs) = self.key_storage.sign_holder_commitment_htlc_transactions(holder_commitment, &self.secp_ctx) {
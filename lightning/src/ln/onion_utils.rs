@@ -7,7 +7,7 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
-use ln::channelmanager::{PaymentHash, PaymentSecret, HTLCSource};
+use ln::channelmanager::{PaymentHash, PaymentPreimage, PaymentSecret, HTLCSource};
 use ln::msgs;
 use routing::router::RouteHop;
 use util::byte_utils;
@@ -117,7 +117,7 @@ pub(super) fn construct_onion_keys<T: secp256k1::Signing>(secp_ctx: &Secp256k1<T
 }
 
 /// returns the hop data, as well as the first-hop value_msat and CLTV value we should send.
-pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
+pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, payment_secret_option: &Option<PaymentSecret>, keysend_preimage: &Option<PaymentPreimage>, custom_tlvs: &[(u64, Vec<u8>)], starting_htlc_offset: u32) -> Result<(Vec<msgs::OnionHopData>, u64, u32), APIError> {
 	let mut cur_value_msat = 0u64;
 	let mut cur_cltv = starting_htlc_offset;
 	let mut last_short_channel_id = 0;
@@ -139,6 +139,8 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 								total_msat,
 							})
 						} else { None },
+						keysend_preimage: *keysend_preimage,
+						custom_tlvs: custom_tlvs.to_vec(),
 					}
 				} else {
 					msgs::OnionHopDataFormat::NonFinalNode {
@@ -146,6 +148,12 @@ pub(super) fn build_onion_payloads(path: &Vec<RouteHop>, total_msat: u64, paymen
 					}
 				}
 			} else {
+				if idx == 0 && keysend_preimage.is_some() {
+					return Err(APIError::RouteError{err: "Spontaneous payments require the recipient to support variable-length onions"});
+				}
+				if idx == 0 && !custom_tlvs.is_empty() {
+					return Err(APIError::RouteError{err: "Custom TLV records require the recipient to support variable-length onions"});
+				}
 				msgs::OnionHopDataFormat::Legacy {
 					short_channel_id: last_short_channel_id,
 				}
@@ -323,16 +331,48 @@ pub(super) fn build_first_hop_failure_packet(shared_secret: &[u8], failure_type:
 	encrypt_failure_packet(shared_secret, &failure_packet.encode()[..])
 }
 
+/// Decodes the channel_update embedded in the data of an UPDATE failure, which follows the
+/// failure's debug field and is prefixed with its length.
+pub(super) fn decode_failure_channel_update(error_code: u16, failure_data: &[u8]) -> Option<msgs::ChannelUpdate> {
+	if error_code & 0x1000 == 0 {
+		return None;
+	}
+	let (_, debug_field_size) = errors::get_onion_debug_field(error_code);
+	let update_len_slice = failure_data.get(debug_field_size..debug_field_size + 2)?;
+	let update_len = byte_utils::slice_to_be16(&update_len_slice) as usize;
+	let update_slice = failure_data.get(debug_field_size + 2..debug_field_size + 2 + update_len)?;
+	msgs::ChannelUpdate::read(&mut Cursor::new(&update_slice)).ok()
+}
+
+/// What we could learn from a failure we got back from upstream on a payment we sent.
+pub(super) struct DecodedOnionFailure {
+	/// The update to apply to our network graph, if any.
+	pub(super) network_update: Option<msgs::HTLCFailChannelUpdate>,
+	/// False if the payment itself failed, rather than just the path we tried.
+	pub(super) payment_retryable: bool,
+	pub(super) error_code: Option<u16>,
+	pub(super) error_data: Option<Vec<u8>>,
+	/// The index in the path of the hop which sent the failure, if we could authenticate it.
+	pub(super) failing_hop_index: Option<usize>,
+	/// The channel the failure relates to, unless it blamed a node as a whole.
+	pub(super) short_channel_id: Option<u64>,
+	/// The channel_update embedded in the failure, if any, even if we didn't trust it enough to
+	/// apply it to our network graph.
+	pub(super) channel_update: Option<msgs::ChannelUpdate>,
+}
+
 /// Process failure we got back from upstream on a payment we sent (implying htlc_source is an
 /// OutboundRoute).
-/// Returns update, a boolean indicating that the payment itself failed, and the error code.
 #[inline]
-pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> (Option<msgs::HTLCFailChannelUpdate>, bool, Option<u16>, Option<Vec<u8>>) where L::Target: Logger {
-	if let &HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat } = htlc_source {
+pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &Secp256k1<T>, logger: &L, htlc_source: &HTLCSource, mut packet_decrypted: Vec<u8>) -> DecodedOnionFailure where L::Target: Logger {
+	if let &HTLCSource::OutboundRoute { ref path, ref session_priv, ref first_hop_htlc_msat, .. } = htlc_source {
 		let mut res = None;
 		let mut htlc_msat = *first_hop_htlc_msat;
 		let mut error_code_ret = None;
 		let mut error_packet_ret = None;
+		let mut failing_hop_index = None;
+		let mut short_channel_id = None;
+		let mut channel_update_ret = None;
 		let mut next_route_hop_ix = 0;
 		let mut is_from_final_node = false;
 
@@ -360,6 +400,11 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 				hmac.input(&err_packet.encode()[32..]);
 
 				if fixed_time_eq(&Hmac::from_engine(hmac).into_inner(), &err_packet.hmac) {
+					failing_hop_index = Some(next_route_hop_ix - 1);
+					// Channel-level failures from a forwarding node relate to the channel it was
+					// asked to forward over, while the final node can only refer to the channel
+					// the HTLC reached it by.
+					let failing_channel = path[next_route_hop_ix - if next_route_hop_ix == path.len() { 1 } else { 0 }].short_channel_id;
 					if let Some(error_code_slice) = err_packet.failuremsg.get(0..2) {
 						const PERM: u16 = 0x4000;
 						const NODE: u16 = 0x2000;
@@ -381,48 +426,48 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 
 						let mut fail_channel_update = None;
 
+						if error_code & NODE != NODE {
+							short_channel_id = Some(failing_channel);
+						}
+
 						if error_code & NODE == NODE {
 							fail_channel_update = Some(msgs::HTLCFailChannelUpdate::NodeFailure { node_id: route_hop.pubkey, is_permanent: error_code & PERM == PERM });
 						}
 						else if error_code & PERM == PERM {
 							fail_channel_update = if payment_failed {None} else {Some(msgs::HTLCFailChannelUpdate::ChannelClosed {
-								short_channel_id: path[next_route_hop_ix - if next_route_hop_ix == path.len() { 1 } else { 0 }].short_channel_id,
+								short_channel_id: failing_channel,
 								is_permanent: true,
 							})};
 						}
 						else if error_code & UPDATE == UPDATE {
-							if let Some(update_len_slice) = err_packet.failuremsg.get(debug_field_size+2..debug_field_size+4) {
-								let update_len = byte_utils::slice_to_be16(&update_len_slice) as usize;
-								if let Some(update_slice) = err_packet.failuremsg.get(debug_field_size + 4..debug_field_size + 4 + update_len) {
-									if let Ok(chan_update) = msgs::ChannelUpdate::read(&mut Cursor::new(&update_slice)) {
-										// if channel_update should NOT have caused the failure:
-										// MAY treat the channel_update as invalid.
-										let is_chan_update_invalid = match error_code & 0xff {
-											7 => false,
-											11 => amt_to_forward > chan_update.contents.htlc_minimum_msat,
-											12 => {
-												let new_fee = amt_to_forward.checked_mul(chan_update.contents.fee_proportional_millionths as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(chan_update.contents.fee_base_msat as u64) });
-												new_fee.is_some() && route_hop.fee_msat >= new_fee.unwrap()
-											}
-											13 => route_hop.cltv_expiry_delta as u16 >= chan_update.contents.cltv_expiry_delta,
-											14 => false, // expiry_too_soon; always valid?
-											20 => chan_update.contents.flags & 2 == 0,
-											_ => false, // unknown error code; take channel_update as valid
-										};
-										fail_channel_update = if is_chan_update_invalid {
-											// This probably indicates the node which forwarded
-											// to the node in question corrupted something.
-											Some(msgs::HTLCFailChannelUpdate::ChannelClosed {
-												short_channel_id: route_hop.short_channel_id,
-												is_permanent: true,
-											})
-										} else {
-											Some(msgs::HTLCFailChannelUpdate::ChannelUpdateMessage {
-												msg: chan_update,
-											})
-										};
+							if let Some(chan_update) = decode_failure_channel_update(error_code, &err_packet.failuremsg[2..]) {
+								// if channel_update should NOT have caused the failure:
+								// MAY treat the channel_update as invalid.
+								let is_chan_update_invalid = match error_code & 0xff {
+									7 => false,
+									11 => amt_to_forward > chan_update.contents.htlc_minimum_msat,
+									12 => {
+										let new_fee = amt_to_forward.checked_mul(chan_update.contents.fee_proportional_millionths as u64).and_then(|prop_fee| { (prop_fee / 1000000).checked_add(chan_update.contents.fee_base_msat as u64) });
+										new_fee.is_some() && route_hop.fee_msat >= new_fee.unwrap()
 									}
-								}
+									13 => route_hop.cltv_expiry_delta as u16 >= chan_update.contents.cltv_expiry_delta,
+									14 => false, // expiry_too_soon; always valid?
+									20 => chan_update.contents.flags & 2 == 0,
+									_ => false, // unknown error code; take channel_update as valid
+								};
+								channel_update_ret = Some(chan_update.clone());
+								fail_channel_update = if is_chan_update_invalid {
+									// This probably indicates the node which forwarded
+									// to the node in question corrupted something.
+									Some(msgs::HTLCFailChannelUpdate::ChannelClosed {
+										short_channel_id: route_hop.short_channel_id,
+										is_permanent: true,
+									})
+								} else {
+									Some(msgs::HTLCFailChannelUpdate::ChannelUpdateMessage {
+										msg: chan_update,
+									})
+								};
 							}
 							if fail_channel_update.is_none() {
 								// They provided an UPDATE which was obviously bogus, not worth
@@ -465,12 +510,20 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 				}
 			}
 		}).expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
-		if let Some((channel_update, payment_retryable)) = res {
-			(channel_update, payment_retryable, error_code_ret, error_packet_ret)
-		} else {
+		let (network_update, payment_retryable) = match res {
+			Some(res) => res,
 			// only not set either packet unparseable or hmac does not match with any
 			// payment not retryable only when garbage is from the final node
-			(None, !is_from_final_node, None, None)
+			None => (None, !is_from_final_node),
+		};
+		DecodedOnionFailure {
+			network_update,
+			payment_retryable,
+			error_code: error_code_ret,
+			error_data: error_packet_ret,
+			failing_hop_index,
+			short_channel_id,
+			channel_update: channel_update_ret,
 		}
 	} else { unreachable!(); }
 }
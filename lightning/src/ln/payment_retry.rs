@@ -0,0 +1,483 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A utility for making payments which are automatically retried over different routes when some
+//! of their paths fail.
+//!
+//! Create a PaymentRetrier wrapping your ChannelManager, a Router (eg your NetGraphMsgHandler)
+//! and a Score, start payments with PaymentRetrier::pay and pass every Event you get from your
+//! ChannelManager through PaymentRetrier::handle_event before handling it yourself.
+
+use bitcoin::secp256k1::key::PublicKey;
+
+use chain;
+use chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use chain::keysinterface::{ChannelKeys, KeysInterface};
+use ln::channelmanager::{ChannelDetails, ChannelManager, PaymentHash, PaymentId, PaymentSecret, PaymentSendFailure};
+use ln::msgs::LightningError;
+use routing::router::{Route, RouteConstraints, RouteHint, RouteHop};
+use routing::scorer::Score;
+use util::errors::APIError;
+use util::events::Event;
+use util::logger::Logger;
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The node making payments, implemented by ChannelManager.
+pub trait Payer {
+	/// Returns our node_id, the source of every route.
+	fn node_id(&self) -> PublicKey;
+
+	/// Returns the channels we may currently send payments over.
+	fn first_hops(&self) -> Vec<ChannelDetails>;
+
+	/// Sends a payment over the given route, see ChannelManager::send_payment.
+	fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<PaymentId, PaymentSendFailure>;
+
+	/// Retries failed paths of a payment over the given route, see ChannelManager::retry_payment.
+	fn retry_payment(&self, route: &Route, payment_id: PaymentId) -> Result<(), PaymentSendFailure>;
+
+	/// Gives up on retrying a payment, see ChannelManager::abandon_payment.
+	fn abandon_payment(&self, payment_id: PaymentId);
+}
+
+/// A source of routes for payments, implemented by NetGraphMsgHandler.
+pub trait Router {
+	/// Finds a route from payer for the payment described by params, see get_route.
+	fn find_route<S: Score>(&self, payer: &PublicKey, params: &RouteParameters, first_hops: Option<&[&ChannelDetails]>, scorer: &S) -> Result<Route, LightningError>;
+}
+
+/// The parameters of a payment needed to find routes for it.
+#[derive(Clone)]
+pub struct RouteParameters {
+	/// The node_id of the recipient.
+	pub payee: PublicKey,
+	/// Route hints to the recipient, eg for its private channels.
+	pub last_hops: Vec<RouteHint>,
+	/// The amount, in msat, to deliver to the recipient.
+	pub final_value_msat: u64,
+	/// The CLTV expiry delta the recipient requires for the final hop.
+	pub final_cltv: u32,
+	/// The constraints every route must satisfy. The fee budget covers the payment as a whole, so
+	/// routes used to retry some of its value may only pay what the paths which are pending or
+	/// succeeded left of it. The other constraints apply to each route separately.
+	pub constraints: RouteConstraints,
+}
+
+/// An error returned by PaymentRetrier::pay.
+#[derive(Debug)]
+pub enum PaymentError {
+	/// No route to the recipient could be found. Nothing was sent.
+	Routing(LightningError),
+	/// The payment failed to send, see PaymentSendFailure for whether any of it was.
+	///
+	/// In the case of PaymentSendFailure::PartialFailure, the paths which were sent are not
+	/// retried but are still tracked, so a final PaymentSent or PaymentFailed event will be
+	/// returned by PaymentRetrier::handle_event for the payment.
+	Sending(PaymentSendFailure),
+}
+
+/// The state of a payment made via PaymentRetrier::pay.
+struct RetriedPayment {
+	params: RouteParameters,
+	started_at: Instant,
+	attempts: usize,
+	/// Channels which failed a path of this payment and are avoided when retrying it.
+	failed_channels: HashSet<u64>,
+	/// The number of paths we expect a PaymentSent or PaymentFailed event for.
+	pending_paths: usize,
+	/// The fees, in msat, of the paths which are pending or succeeded, which count against the
+	/// fee budget of retries.
+	committed_fees_msat: u64,
+	succeeded: bool,
+	gave_up: bool,
+}
+
+/// Makes payments, retrying the value of failed paths over routes which avoid the channels which
+/// failed, until the payment succeeds, the recipient rejects it, or the retry budget or timeout is
+/// exhausted.
+///
+/// Every Event generated by the Payer must be passed through handle_event, which swallows the
+/// PaymentSent and PaymentFailed events of individual paths of payments made via pay, returning
+/// exactly one of them per payment once its outcome is final. Retries are made via
+/// ChannelManager::retry_payment, which never sends more than the value of the payment, so
+/// the recipient cannot be paid twice.
+///
+/// Payments in flight are only tracked in memory. After a restart, events for payments made
+/// before it are returned by handle_event unchanged, and their failed paths are not retried,
+/// though the ChannelManager still refuses to overpay them if you retry them yourself.
+pub struct PaymentRetrier<P: Deref, R: Deref, S: Score, L: Deref>
+	where P::Target: Payer,
+	      R::Target: Router,
+	      L::Target: Logger,
+{
+	payer: P,
+	router: R,
+	scorer: Mutex<S>,
+	logger: L,
+	retry_attempts: usize,
+	retry_timeout: Duration,
+	payments: Mutex<HashMap<PaymentId, RetriedPayment>>,
+}
+
+impl<P: Deref, R: Deref, S: Score, L: Deref> PaymentRetrier<P, R, S, L>
+	where P::Target: Payer,
+	      R::Target: Router,
+	      L::Target: Logger,
+{
+	/// Creates a new PaymentRetrier which retries each payment at most retry_attempts times, and
+	/// not at all once retry_timeout has elapsed since it was started.
+	///
+	/// The scorer is updated with the outcome of every path of every payment passed to
+	/// handle_event, and is used for finding all routes.
+	pub fn new(payer: P, router: R, scorer: S, logger: L, retry_attempts: usize, retry_timeout: Duration) -> Self {
+		Self {
+			payer,
+			router,
+			scorer: Mutex::new(scorer),
+			logger,
+			retry_attempts,
+			retry_timeout,
+			payments: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Gets the scorer, eg to persist it.
+	pub fn scorer(&self) -> MutexGuard<S> {
+		self.scorer.lock().unwrap()
+	}
+
+	/// Finds a route for and sends a payment, returning the PaymentId of the final PaymentSent or
+	/// PaymentFailed event handle_event will return for it.
+	pub fn pay(&self, payment_hash: PaymentHash, payment_secret: Option<PaymentSecret>, params: RouteParameters) -> Result<PaymentId, PaymentError> {
+		let route = {
+			let first_hops = self.payer.first_hops();
+			let first_hops = first_hops.iter().collect::<Vec<_>>();
+			let scorer = self.scorer.lock().unwrap();
+			self.router.find_route(&self.payer.node_id(), &params, Some(&first_hops), &*scorer)
+				.map_err(|e| PaymentError::Routing(e))?
+		};
+
+		// Hold the lock while sending, so that events for the payment can't be handled before we
+		// track it.
+		let mut payments = self.payments.lock().unwrap();
+		let (payment_id, (pending_paths, committed_fees_msat), res) = match self.payer.send_payment(&route, payment_hash, &payment_secret) {
+			Ok(payment_id) => (payment_id, count_sent_paths(&route, None), Ok(payment_id)),
+			Err(PaymentSendFailure::PartialFailure { results, payment_id }) => {
+				log_trace!(self.logger, "Giving up on payment with id {} after it partially failed to send", log_bytes!(payment_id.0));
+				self.payer.abandon_payment(payment_id);
+				let sent_paths = count_sent_paths(&route, Some(&results));
+				(payment_id, sent_paths, Err(PaymentError::Sending(PaymentSendFailure::PartialFailure { results, payment_id })))
+			},
+			Err(e) => return Err(PaymentError::Sending(e)),
+		};
+		payments.insert(payment_id, RetriedPayment {
+			params,
+			started_at: Instant::now(),
+			attempts: 0,
+			failed_channels: HashSet::new(),
+			pending_paths,
+			committed_fees_msat,
+			succeeded: false,
+			gave_up: res.is_err(),
+		});
+		res
+	}
+
+	/// Handles an event generated by the Payer, returning it if it should be handled by the
+	/// caller. See the struct-level documentation for which events are swallowed.
+	pub fn handle_event(&self, event: Event) -> Option<Event> {
+		let mut payments = self.payments.lock().unwrap();
+		match event {
+			Event::PaymentSent { payment_id, ref path, .. } => {
+				let payment = match payments.get_mut(&payment_id) {
+					Some(payment) => payment,
+					None => return Some(event),
+				};
+				self.scorer.lock().unwrap().payment_path_successful(path);
+				payment.pending_paths = payment.pending_paths.saturating_sub(1);
+				let first_success = !payment.succeeded;
+				payment.succeeded = true;
+				if payment.pending_paths == 0 {
+					payments.remove(&payment_id);
+				}
+				if first_success { Some(event) } else { None }
+			},
+			Event::PaymentFailed { payment_id, rejected_by_dest, short_channel_id, ref path, .. } => {
+				let payment = match payments.get_mut(&payment_id) {
+					Some(payment) => payment,
+					None => return Some(event),
+				};
+				if let Some(short_channel_id) = short_channel_id {
+					self.scorer.lock().unwrap().payment_path_failed(path, short_channel_id);
+					payment.failed_channels.insert(short_channel_id);
+				}
+				payment.pending_paths = payment.pending_paths.saturating_sub(1);
+				payment.committed_fees_msat = payment.committed_fees_msat.saturating_sub(path_fee_msat(path));
+
+				if !payment.succeeded && !payment.gave_up {
+					let retry_value_msat = path.last().map(|hop| hop.fee_msat).unwrap_or(0);
+					if rejected_by_dest {
+						log_trace!(self.logger, "Not retrying payment with id {} as the recipient rejected it", log_bytes!(payment_id.0));
+					} else if payment.attempts >= self.retry_attempts {
+						log_trace!(self.logger, "Not retrying payment with id {} after {} attempts", log_bytes!(payment_id.0), payment.attempts);
+					} else if payment.started_at.elapsed() > self.retry_timeout {
+						log_trace!(self.logger, "Not retrying payment with id {} as it timed out", log_bytes!(payment_id.0));
+					} else {
+						payment.attempts += 1;
+						match self.retry_path(payment_id, payment, retry_value_msat) {
+							Ok((sent_paths, fees_msat)) => {
+								payment.pending_paths += sent_paths;
+								payment.committed_fees_msat += fees_msat;
+								return None;
+							},
+							Err((sent_paths, fees_msat)) => {
+								payment.pending_paths += sent_paths;
+								payment.committed_fees_msat += fees_msat;
+							},
+						}
+					}
+					payment.gave_up = true;
+					self.payer.abandon_payment(payment_id);
+				}
+
+				if payment.pending_paths == 0 {
+					let succeeded = payment.succeeded;
+					payments.remove(&payment_id);
+					if !succeeded {
+						return Some(event);
+					}
+				}
+				None
+			},
+			_ => Some(event),
+		}
+	}
+
+	/// Retries retry_value_msat of the given payment over a route avoiding the channels which
+	/// failed it and paying at most what's left of its fee budget. Returns the number of paths
+	/// sent and their fees, as an Err if the payment should be given up on.
+	fn retry_path(&self, payment_id: PaymentId, payment: &RetriedPayment, retry_value_msat: u64) -> Result<(usize, u64), (usize, u64)> {
+		let mut params = RouteParameters {
+			final_value_msat: retry_value_msat,
+			..payment.params.clone()
+		};
+		params.constraints.avoid_channels.extend(payment.failed_channels.iter());
+		let constraints = &payment.params.constraints;
+		if constraints.max_total_fee_msat.is_some() || constraints.max_fee_proportional_millionths.is_some() {
+			let max_fee_msat = constraints.max_fee_msat(payment.params.final_value_msat);
+			params.constraints.max_total_fee_msat = Some(max_fee_msat.saturating_sub(payment.committed_fees_msat));
+			params.constraints.max_fee_proportional_millionths = None;
+		}
+		let route = {
+			let first_hops = self.payer.first_hops();
+			let first_hops = first_hops.iter().collect::<Vec<_>>();
+			let scorer = self.scorer.lock().unwrap();
+			match self.router.find_route(&self.payer.node_id(), &params, Some(&first_hops), &*scorer) {
+				Ok(route) => route,
+				Err(e) => {
+					log_trace!(self.logger, "Failed to find a route to retry payment with id {}: {}", log_bytes!(payment_id.0), e.err);
+					return Err((0, 0));
+				},
+			}
+		};
+		match self.payer.retry_payment(&route, payment_id) {
+			Ok(()) => {
+				log_trace!(self.logger, "Retried {} msat of payment with id {} over {} paths", retry_value_msat, log_bytes!(payment_id.0), route.paths.len());
+				Ok(count_sent_paths(&route, None))
+			},
+			Err(PaymentSendFailure::PartialFailure { results, .. }) => {
+				log_trace!(self.logger, "Retry of payment with id {} partially failed to send", log_bytes!(payment_id.0));
+				Err(count_sent_paths(&route, Some(&results)))
+			},
+			Err(e) => {
+				log_trace!(self.logger, "Failed to retry payment with id {}: {:?}", log_bytes!(payment_id.0), e);
+				Err((0, 0))
+			},
+		}
+	}
+}
+
+/// Counts the paths of a route which were (or will be) sent and sums their fees, given the results
+/// of a PartialFailure if only some of them were.
+fn count_sent_paths(route: &Route, results: Option<&[Result<(), APIError>]>) -> (usize, u64) {
+	let mut sent_paths = 0;
+	let mut fees_msat = 0;
+	for (idx, path) in route.paths.iter().enumerate() {
+		match results.and_then(|results| results.get(idx)) {
+			None | Some(&Ok(())) | Some(&Err(APIError::MonitorUpdateFailed)) => {
+				sent_paths += 1;
+				fees_msat += path_fee_msat(path);
+			},
+			Some(&Err(_)) => {},
+		}
+	}
+	(sent_paths, fees_msat)
+}
+
+/// The fee paid to the intermediary nodes of a path, ie the fee_msat of every hop but the last.
+fn path_fee_msat(path: &[RouteHop]) -> u64 {
+	path.split_last().map(|(_, hops)| hops.iter().map(|hop| hop.fee_msat).sum()).unwrap_or(0)
+}
+
+impl<ChanSigner: ChannelKeys, M: Deref, T: Deref, K: Deref, F: Deref, L: Deref> Payer for ChannelManager<ChanSigner, M, T, K, F, L>
+	where M::Target: chain::Watch<Keys=ChanSigner>,
+	      T::Target: BroadcasterInterface,
+	      K::Target: KeysInterface<ChanKeySigner = ChanSigner>,
+	      F::Target: FeeEstimator,
+	      L::Target: Logger,
+{
+	fn node_id(&self) -> PublicKey {
+		self.get_our_node_id()
+	}
+
+	fn first_hops(&self) -> Vec<ChannelDetails> {
+		self.list_usable_channels()
+	}
+
+	fn send_payment(&self, route: &Route, payment_hash: PaymentHash, payment_secret: &Option<PaymentSecret>) -> Result<PaymentId, PaymentSendFailure> {
+		self.send_payment(route, payment_hash, payment_secret, &[])
+	}
+
+	fn retry_payment(&self, route: &Route, payment_id: PaymentId) -> Result<(), PaymentSendFailure> {
+		self.retry_payment(route, payment_id)
+	}
+
+	fn abandon_payment(&self, payment_id: PaymentId) {
+		self.abandon_payment(payment_id)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Payer, PaymentRetrier, RouteParameters, Router};
+
+	use ln::channelmanager::{ChannelDetails, PaymentHash, PaymentId, PaymentPreimage, PaymentSecret, PaymentSendFailure};
+	use ln::features::{ChannelFeatures, NodeFeatures};
+	use ln::msgs::LightningError;
+	use routing::router::{Route, RouteConstraints, RouteHop};
+	use routing::scorer::{Score, Scorer};
+	use util::events::Event;
+	use util::test_utils::TestLogger;
+
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::Secp256k1;
+
+	use std::collections::VecDeque;
+	use std::sync::Mutex;
+	use std::time::Duration;
+
+	const PAYMENT_ID: PaymentId = PaymentId([42; 32]);
+
+	fn node_id(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	/// A path paying fee_msat to an intermediary node to deliver value_msat to node_id(3).
+	fn path(short_channel_id: u64, fee_msat: u64, value_msat: u64) -> Vec<RouteHop> {
+		vec![RouteHop {
+			pubkey: node_id(2),
+			node_features: NodeFeatures::empty(),
+			short_channel_id,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat,
+			cltv_expiry_delta: 40,
+		}, RouteHop {
+			pubkey: node_id(3),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: short_channel_id + 100,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: value_msat,
+			cltv_expiry_delta: 40,
+		}]
+	}
+
+	fn path_failed(path: Vec<RouteHop>) -> Event {
+		Event::PaymentFailed {
+			payment_hash: PaymentHash([0; 32]),
+			payment_id: PAYMENT_ID,
+			all_paths_failed: false,
+			rejected_by_dest: false,
+			error_code: None,
+			error_data: None,
+			failing_hop_index: Some(0),
+			failing_node_id: Some(node_id(2)),
+			short_channel_id: Some(path[0].short_channel_id),
+			channel_update: None,
+			path,
+		}
+	}
+
+	/// A Payer which successfully sends every payment.
+	struct TestPayer;
+
+	impl Payer for TestPayer {
+		fn node_id(&self) -> PublicKey { node_id(1) }
+		fn first_hops(&self) -> Vec<ChannelDetails> { Vec::new() }
+		fn send_payment(&self, _route: &Route, _payment_hash: PaymentHash, _payment_secret: &Option<PaymentSecret>) -> Result<PaymentId, PaymentSendFailure> {
+			Ok(PAYMENT_ID)
+		}
+		fn retry_payment(&self, _route: &Route, _payment_id: PaymentId) -> Result<(), PaymentSendFailure> { Ok(()) }
+		fn abandon_payment(&self, _payment_id: PaymentId) {}
+	}
+
+	/// A Router which returns the given routes in order, recording the fee budget each was found
+	/// for.
+	struct TestRouter {
+		routes: Mutex<VecDeque<Route>>,
+		max_total_fees_msat: Mutex<Vec<Option<u64>>>,
+	}
+
+	impl Router for TestRouter {
+		fn find_route<S: Score>(&self, _payer: &PublicKey, params: &RouteParameters, _first_hops: Option<&[&ChannelDetails]>, _scorer: &S) -> Result<Route, LightningError> {
+			self.max_total_fees_msat.lock().unwrap().push(params.constraints.max_total_fee_msat);
+			Ok(self.routes.lock().unwrap().pop_front().unwrap())
+		}
+	}
+
+	#[test]
+	fn retries_share_fee_budget() {
+		// The fee budget applies to the payment as a whole: the fees of pending paths are
+		// subtracted from the budget of retries, while those of failed paths are given back.
+		let routes = vec![
+			Route { paths: vec![path(1, 1000, 500_000), path(2, 1000, 500_000)] },
+			Route { paths: vec![path(3, 1500, 500_000)] },
+			Route { paths: vec![path(4, 1800, 500_000)] },
+			Route { paths: vec![path(5, 1000, 500_000)] },
+		];
+		let router = TestRouter { routes: Mutex::new(routes.into_iter().collect()), max_total_fees_msat: Mutex::new(Vec::new()) };
+		let logger = TestLogger::new();
+		let retrier = PaymentRetrier::new(&TestPayer, &router, Scorer::default(), &logger, 3, Duration::from_secs(60));
+
+		// The lower of both budgets, 3000 msat, applies.
+		let mut constraints = RouteConstraints::default();
+		constraints.max_total_fee_msat = Some(5000);
+		constraints.max_fee_proportional_millionths = Some(3000);
+		let params = RouteParameters { payee: node_id(3), last_hops: Vec::new(), final_value_msat: 1_000_000, final_cltv: 40, constraints };
+		assert_eq!(retrier.pay(PaymentHash([0; 32]), None, params).unwrap(), PAYMENT_ID);
+
+		// With path 1 pending, retries may pay at most 2000 msat, whether the path they replace
+		// is the original one or a failed retry.
+		assert!(retrier.handle_event(path_failed(path(2, 1000, 500_000))).is_none());
+		assert!(retrier.handle_event(path_failed(path(3, 1500, 500_000))).is_none());
+		// Once path 1 fails with the 1800 msat path 4 pending, its retry may pay 1200 msat.
+		assert!(retrier.handle_event(path_failed(path(1, 1000, 500_000))).is_none());
+		assert_eq!(*router.max_total_fees_msat.lock().unwrap(), vec![Some(5000), Some(2000), Some(2000), Some(1200)]);
+
+		let payment_preimage = PaymentPreimage([0; 32]);
+		match retrier.handle_event(Event::PaymentSent { payment_preimage, payment_id: PAYMENT_ID, path: path(4, 1800, 500_000), fee_paid_msat: 1800 }) {
+			Some(Event::PaymentSent { payment_id, .. }) => assert_eq!(payment_id, PAYMENT_ID),
+			_ => panic!("Unexpected event"),
+		}
+		assert!(retrier.handle_event(Event::PaymentSent { payment_preimage, payment_id: PAYMENT_ID, path: path(5, 1000, 500_000), fee_paid_msat: 1000 }).is_none());
+	}
+}
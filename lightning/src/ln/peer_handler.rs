@@ -113,6 +113,15 @@ impl error::Error for PeerHandleError {
 	}
 }
 
+/// When the outbound buffer has this many messages, we'll stop reading bytes from the peer until
+/// we have fewer than this many messages in the outbound buffer again.
+/// We also use this as the target number of outbound gossip messages to keep in the write buffer,
+/// refilled as we send bytes.
+const OUTBOUND_BUFFER_LIMIT_READ_PAUSE: usize = 10;
+/// When the outbound buffer has this many messages, we'll simply skip relaying gossip messages to
+/// the peer.
+const OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP: usize = 20;
+
 enum InitSyncTracker{
 	NoSyncRequested,
 	ChannelsSyncing(u64),
@@ -134,6 +143,8 @@ struct Peer {
 	pending_read_is_header: bool,
 
 	sync_status: InitSyncTracker,
+	/// The gossip_timestamp_filter most recently received from this peer, if any.
+	gossip_timestamp_filter: Option<msgs::GossipTimestampFilter>,
 
 	awaiting_pong: bool,
 }
@@ -161,6 +172,25 @@ impl Peer {
 			InitSyncTracker::NodesSyncing(pk) => pk < node_id,
 		}
 	}
+
+	/// Returns true if gossip we did not originate with the given timestamp (or None for
+	/// channel_announcements, which carry no timestamp) should be relayed to this peer.
+	/// Per BOLT 7, peers which negotiated gossip_queries only receive relayed gossip once they
+	/// send us a gossip_timestamp_filter, and then only gossip within the requested time window.
+	fn should_relay_gossip(&self, timestamp: Option<u32>) -> bool {
+		match self.their_features {
+			Some(ref features) if features.supports_gossip_queries() => {},
+			_ => return true,
+		}
+		match self.gossip_timestamp_filter {
+			None => false,
+			Some(ref filter) => match timestamp {
+				None => filter.timestamp_range != 0,
+				Some(timestamp) => timestamp >= filter.first_timestamp &&
+					(timestamp as u64) < filter.first_timestamp as u64 + filter.timestamp_range as u64,
+			},
+		}
+	}
 }
 
 struct PeerHolder<Descriptor: SocketDescriptor> {
@@ -328,6 +358,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_timestamp_filter: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -365,6 +396,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			pending_read_is_header: false,
 
 			sync_status: InitSyncTracker::NoSyncRequested,
+			gossip_timestamp_filter: None,
 
 			awaiting_pong: false,
 		}).is_some() {
@@ -382,13 +414,12 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 			}
 		}
-		const MSG_BUFF_SIZE: usize = 10;
 		while !peer.awaiting_write_event {
-			if peer.pending_outbound_buffer.len() < MSG_BUFF_SIZE {
+			if peer.pending_outbound_buffer.len() < OUTBOUND_BUFFER_LIMIT_READ_PAUSE {
 				match peer.sync_status {
 					InitSyncTracker::NoSyncRequested => {},
 					InitSyncTracker::ChannelsSyncing(c) if c < 0xffff_ffff_ffff_ffff => {
-						let steps = ((MSG_BUFF_SIZE - peer.pending_outbound_buffer.len() + 2) / 3) as u8;
+						let steps = ((OUTBOUND_BUFFER_LIMIT_READ_PAUSE - peer.pending_outbound_buffer.len() + 2) / 3) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_channel_announcements(c, steps);
 						for &(ref announce, ref update_a_option, ref update_b_option) in all_messages.iter() {
 							encode_and_send_msg!(announce);
@@ -405,7 +436,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						}
 					},
 					InitSyncTracker::ChannelsSyncing(c) if c == 0xffff_ffff_ffff_ffff => {
-						let steps = (MSG_BUFF_SIZE - peer.pending_outbound_buffer.len()) as u8;
+						let steps = (OUTBOUND_BUFFER_LIMIT_READ_PAUSE - peer.pending_outbound_buffer.len()) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_node_announcements(None, steps);
 						for msg in all_messages.iter() {
 							encode_and_send_msg!(msg);
@@ -417,7 +448,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					},
 					InitSyncTracker::ChannelsSyncing(_) => unreachable!(),
 					InitSyncTracker::NodesSyncing(key) => {
-						let steps = (MSG_BUFF_SIZE - peer.pending_outbound_buffer.len()) as u8;
+						let steps = (OUTBOUND_BUFFER_LIMIT_READ_PAUSE - peer.pending_outbound_buffer.len()) as u8;
 						let all_messages = self.message_handler.route_handler.get_next_node_announcements(Some(&key), steps);
 						for msg in all_messages.iter() {
 							encode_and_send_msg!(msg);
@@ -429,6 +460,20 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					},
 				}
 			}
+			if peer.pending_outbound_buffer.len() < OUTBOUND_BUFFER_LIMIT_READ_PAUSE {
+				if let Some(their_node_id) = peer.their_node_id {
+					let steps = (OUTBOUND_BUFFER_LIMIT_READ_PAUSE - peer.pending_outbound_buffer.len()) as u8;
+					for reply in self.message_handler.route_handler.get_next_query_short_channel_ids_replies(&their_node_id, steps) {
+						match reply {
+							MessageSendEvent::SendChannelAnnouncement { ref msg, .. } => encode_and_send_msg!(msg),
+							MessageSendEvent::SendChannelUpdate { ref msg, .. } => encode_and_send_msg!(msg),
+							MessageSendEvent::SendNodeAnnouncement { ref msg, .. } => encode_and_send_msg!(msg),
+							MessageSendEvent::SendReplyShortChannelIdsEnd { ref msg, .. } => encode_and_send_msg!(msg),
+							_ => debug_assert!(false, "Unexpected reply to query_short_channel_ids"),
+						}
+					}
+				}
+			}
 
 			if {
 				let next_buff = match peer.pending_outbound_buffer.front() {
@@ -436,7 +481,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					Some(buff) => buff,
 				};
 
-				let should_be_reading = peer.pending_outbound_buffer.len() < MSG_BUFF_SIZE;
+				let should_be_reading = peer.pending_outbound_buffer.len() < OUTBOUND_BUFFER_LIMIT_READ_PAUSE;
 				let pending = &next_buff[peer.pending_outbound_buffer_first_msg_offset..];
 				let data_sent = descriptor.send_data(pending, should_be_reading);
 				peer.pending_outbound_buffer_first_msg_offset += data_sent;
@@ -509,6 +554,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 		let pause_read = {
 			let mut peers_lock = self.peers.lock().unwrap();
 			let peers = &mut *peers_lock;
+			let mut msgs_to_forward = Vec::new();
+			let mut peer_node_id = None;
 			let pause_read = match peers.peers.get_mut(peer_descriptor) {
 				None => panic!("Descriptor for read_event is not already known to PeerManager"),
 				Some(peer) => {
@@ -538,6 +585,11 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 													log_trace!(self.logger, "Got Err handling message, disconnecting peer because {}", e.err);
 													return Err(PeerHandleError{ no_connection_possible: false });
 												},
+												msgs::ErrorAction::DisconnectPeerWithWarning { msg: _ } => {
+													//TODO: Try to push msg
+													log_trace!(self.logger, "Got Err handling message, disconnecting peer with a warning because {}", e.err);
+													return Err(PeerHandleError{ no_connection_possible: false });
+												},
 												msgs::ErrorAction::IgnoreError => {
 													log_trace!(self.logger, "Got Err handling message, ignoring because {}", e.err);
 													continue;
@@ -584,7 +636,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 									peer.their_node_id = Some(their_node_id);
 									insert_node_id!();
-									let features = InitFeatures::known();
+									let features = self.message_handler.chan_handler.provided_init_features();
 									let resp = msgs::Init { features };
 									self.enqueue_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), &resp);
 								},
@@ -637,13 +689,18 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 											}
 										};
 
-										if let Err(handling_error) = self.handle_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), message){
-											match handling_error {
+										match self.handle_message(&mut peers.peers_needing_send, peer, peer_descriptor.clone(), message) {
+											Err(handling_error) => match handling_error {
 												MessageHandlingError::PeerHandleError(e) => { return Err(e) },
 												MessageHandlingError::LightningError(e) => {
 													try_potential_handleerror!(Err(e));
 												},
-											}
+											},
+											Ok(Some(msg)) => {
+												peer_node_id = peer.their_node_id;
+												msgs_to_forward.push(msg);
+											},
+											Ok(None) => {},
 										}
 									}
 								}
@@ -653,10 +710,14 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 					self.do_attempt_write_data(peer_descriptor, peer);
 
-					peer.pending_outbound_buffer.len() > 10 // pause_read
+					peer.pending_outbound_buffer.len() > OUTBOUND_BUFFER_LIMIT_READ_PAUSE // pause_read
 				}
 			};
 
+			for msg in msgs_to_forward.drain(..) {
+				self.forward_broadcast_msg(peers, &msg, peer_node_id.as_ref());
+			}
+
 			pause_read
 		};
 
@@ -664,7 +725,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 	}
 
 	/// Process an incoming message and return a decision (ok, lightning error, peer handling error) regarding the next action with the peer
-	fn handle_message(&self, peers_needing_send: &mut HashSet<Descriptor>, peer: &mut Peer, peer_descriptor: Descriptor, message: wire::Message) -> Result<(), MessageHandlingError> {
+	/// Returns the message back if it needs to be broadcasted to all other peers.
+	fn handle_message(&self, peers_needing_send: &mut HashSet<Descriptor>, peer: &mut Peer, peer_descriptor: Descriptor, message: wire::Message) -> Result<Option<wire::Message>, MessageHandlingError> {
 		log_trace!(self.logger, "Received message of type {} from {}", message.type_id(), log_pubkey!(peer.their_node_id.unwrap()));
 
 		// Need an Init as first message
@@ -709,7 +771,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				}
 
 				if !peer.outbound {
-					let features = InitFeatures::known();
+					let features = self.message_handler.chan_handler.provided_init_features();
 					let resp = msgs::Init { features };
 					self.enqueue_message(peers_needing_send, peer, peer_descriptor.clone(), &resp);
 				}
@@ -738,6 +800,21 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 					return Err(PeerHandleError{ no_connection_possible: true }.into());
 				}
 			},
+			wire::Message::Warning(msg) => {
+				let mut data_is_printable = true;
+				for b in msg.data.bytes() {
+					if b < 32 || b > 126 {
+						data_is_printable = false;
+						break;
+					}
+				}
+
+				if data_is_printable {
+					log_debug!(self.logger, "Got warning message from {}: {}", log_pubkey!(peer.their_node_id.unwrap()), msg.data);
+				} else {
+					log_debug!(self.logger, "Got warning message from {} with non-ASCII warning message", log_pubkey!(peer.their_node_id.unwrap()));
+				}
+			},
 
 			wire::Message::Ping(msg) => {
 				if msg.ponglen < 65532 {
@@ -756,6 +833,33 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			wire::Message::AcceptChannel(msg) => {
 				self.message_handler.chan_handler.handle_accept_channel(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
 			},
+			wire::Message::OpenChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_open_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
+			wire::Message::AcceptChannelV2(msg) => {
+				self.message_handler.chan_handler.handle_accept_channel_v2(&peer.their_node_id.unwrap(), peer.their_features.clone().unwrap(), &msg);
+			},
+			wire::Message::TxAddInput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_input(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAddOutput(msg) => {
+				self.message_handler.chan_handler.handle_tx_add_output(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxRemoveInput(msg) => {
+				self.message_handler.chan_handler.handle_tx_remove_input(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxRemoveOutput(msg) => {
+				self.message_handler.chan_handler.handle_tx_remove_output(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxComplete(msg) => {
+				self.message_handler.chan_handler.handle_tx_complete(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxSignatures(msg) => {
+				self.message_handler.chan_handler.handle_tx_signatures(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::TxAbort(msg) => {
+				self.message_handler.chan_handler.handle_tx_abort(&peer.their_node_id.unwrap(), &msg);
+			},
 
 			wire::Message::FundingCreated(msg) => {
 				self.message_handler.chan_handler.handle_funding_created(&peer.their_node_id.unwrap(), &msg);
@@ -774,6 +878,19 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				self.message_handler.chan_handler.handle_closing_signed(&peer.their_node_id.unwrap(), &msg);
 			},
 
+			wire::Message::Stfu(msg) => {
+				self.message_handler.chan_handler.handle_stfu(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::Splice(msg) => {
+				self.message_handler.chan_handler.handle_splice(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceAck(msg) => {
+				self.message_handler.chan_handler.handle_splice_ack(&peer.their_node_id.unwrap(), &msg);
+			},
+			wire::Message::SpliceLocked(msg) => {
+				self.message_handler.chan_handler.handle_splice_locked(&peer.their_node_id.unwrap(), &msg);
+			},
+
 			// Commitment messages:
 			wire::Message::UpdateAddHTLC(msg) => {
 				self.message_handler.chan_handler.handle_update_add_htlc(&peer.their_node_id.unwrap(), &msg);
@@ -806,33 +923,19 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				self.message_handler.chan_handler.handle_announcement_signatures(&peer.their_node_id.unwrap(), &msg);
 			},
 			wire::Message::ChannelAnnouncement(msg) => {
-				let should_forward = match self.message_handler.route_handler.handle_channel_announcement(&msg) {
-					Ok(v) => v,
-					Err(e) => { return Err(e.into()); },
-				};
-
-				if should_forward {
-					// TODO: forward msg along to all our other peers!
+				if self.message_handler.route_handler.handle_channel_announcement(&msg)? {
+					return Ok(Some(wire::Message::ChannelAnnouncement(msg)));
 				}
 			},
 			wire::Message::NodeAnnouncement(msg) => {
-				let should_forward = match self.message_handler.route_handler.handle_node_announcement(&msg) {
-					Ok(v) => v,
-					Err(e) => { return Err(e.into()); },
-				};
-
-				if should_forward {
-					// TODO: forward msg along to all our other peers!
+				if self.message_handler.route_handler.handle_node_announcement(&msg)? {
+					return Ok(Some(wire::Message::NodeAnnouncement(msg)));
 				}
 			},
 			wire::Message::ChannelUpdate(msg) => {
-				let should_forward = match self.message_handler.route_handler.handle_channel_update(&msg) {
-					Ok(v) => v,
-					Err(e) => { return Err(e.into()); },
-				};
-
-				if should_forward {
-					// TODO: forward msg along to all our other peers!
+				self.message_handler.chan_handler.handle_channel_update(&peer.their_node_id.unwrap(), &msg);
+				if self.message_handler.route_handler.handle_channel_update(&msg)? {
+					return Ok(Some(wire::Message::ChannelUpdate(msg)));
 				}
 			},
 			wire::Message::QueryShortChannelIds(msg) => {
@@ -847,8 +950,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 			wire::Message::ReplyChannelRange(msg) => {
 				self.message_handler.route_handler.handle_reply_channel_range(&peer.their_node_id.unwrap(), msg)?;
 			},
-			wire::Message::GossipTimestampFilter(_msg) => {
-				// TODO: handle message
+			wire::Message::GossipTimestampFilter(msg) => {
+				log_trace!(self.logger, "Received gossip_timestamp_filter from {} with first_timestamp={} timestamp_range={}", log_pubkey!(peer.their_node_id.unwrap()), msg.first_timestamp, msg.timestamp_range);
+				peer.gossip_timestamp_filter = Some(msg);
 			},
 
 			// Unknown messages:
@@ -861,7 +965,69 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 				log_trace!(self.logger, "Received unknown odd message of type {}, ignoring", msg_type);
 			}
 		};
-		Ok(())
+		Ok(None)
+	}
+
+	/// Queues the given gossip message to every peer which has completed its handshake, except
+	/// the one identified by except_node (ie the peer we received it from), any peer whose initial
+	/// routing table sync will include the message anyway, and any peer with a full outbound
+	/// buffer.
+	///
+	/// Gossip relayed on behalf of another peer (ie where except_node is set) is additionally
+	/// subject to each recipient's gossip_timestamp_filter, whereas our own gossip is always sent.
+	///
+	/// Duplicate gossip is filtered by the RoutingMessageHandler, which only asks us to forward
+	/// messages it hasn't seen before. Will *not* call back into send_data on any descriptors,
+	/// instead marking the peers as needing a send.
+	fn forward_broadcast_msg(&self, peers: &mut PeerHolder<Descriptor>, msg: &wire::Message, except_node: Option<&PublicKey>) {
+		macro_rules! forward_to_peers {
+			($msg: expr, $should_skip_peer: expr) => {{
+				let encoded_msg = encode_msg!($msg);
+				for (descriptor, peer) in peers.peers.iter_mut() {
+					if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() {
+						continue;
+					}
+					if except_node.is_some() && peer.their_node_id.as_ref() == except_node {
+						continue;
+					}
+					if $should_skip_peer(&*peer) {
+						continue;
+					}
+					if peer.pending_outbound_buffer.len() > OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP {
+						log_trace!(self.logger, "Skipping broadcast of message of type {} to {} as its outbound buffer is full", $msg.type_id(), log_pubkey!(peer.their_node_id.unwrap()));
+						continue;
+					}
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
+					peers.peers_needing_send.insert(descriptor.clone());
+				}
+			}}
+		}
+		match msg {
+			&wire::Message::ChannelAnnouncement(ref msg) => {
+				log_trace!(self.logger, "Forwarding channel_announcement for short channel id {} to all peers except the announced channel's counterparties", msg.contents.short_channel_id);
+				forward_to_peers!(msg, |peer: &Peer| {
+					!peer.should_forward_channel_announcement(msg.contents.short_channel_id) ||
+						(except_node.is_some() && !peer.should_relay_gossip(None)) ||
+						peer.their_node_id.as_ref() == Some(&msg.contents.node_id_1) ||
+						peer.their_node_id.as_ref() == Some(&msg.contents.node_id_2)
+				});
+			},
+			&wire::Message::NodeAnnouncement(ref msg) => {
+				log_trace!(self.logger, "Forwarding node_announcement for node {} to all peers", log_pubkey!(msg.contents.node_id));
+				forward_to_peers!(msg, |peer: &Peer| {
+					!peer.should_forward_node_announcement(msg.contents.node_id) ||
+						(except_node.is_some() && !peer.should_relay_gossip(Some(msg.contents.timestamp)))
+				});
+			},
+			&wire::Message::ChannelUpdate(ref msg) => {
+				log_trace!(self.logger, "Forwarding channel_update for short channel id {} to all peers", msg.contents.short_channel_id);
+				forward_to_peers!(msg, |peer: &Peer| {
+					!peer.should_forward_channel_announcement(msg.contents.short_channel_id) ||
+						(except_node.is_some() && !peer.should_relay_gossip(Some(msg.contents.timestamp)))
+				});
+			},
+			_ => debug_assert!(false, "We shouldn't attempt to forward anything but gossip messages"),
+		}
 	}
 
 	/// Checks for any events generated by our handlers and processes them. Includes sending most
@@ -922,14 +1088,85 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
+					MessageSendEvent::SendAcceptChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendAcceptChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendOpenChannelV2 { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendOpenChannelV2 event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.temporary_channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddInput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddInput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAddOutput { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAddOutput event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxComplete { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxComplete event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Drop the pending channel? (or just let it timeout, but that sucks)
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxSignatures { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxSignatures event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendTxAbort { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendTxAbort event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
 					MessageSendEvent::SendFundingCreated { ref node_id, ref msg } => {
 						log_trace!(self.logger, "Handling SendFundingCreated event in peer_handler for node {} for channel {} (which becomes {})",
 								log_pubkey!(node_id),
 								log_bytes!(msg.temporary_channel_id),
 								log_funding_channel_id!(msg.funding_txid, msg.funding_output_index));
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
-								//TODO: generate a DiscardFunding event indicating to the wallet that
-								//they should just throw away this funding transaction
+								// The channel is dropped, generating a DiscardFunding event for
+								// the funding transaction, once ChannelManager learns of the
+								// disconnection.
 							});
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
@@ -939,8 +1176,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 								log_pubkey!(node_id),
 								log_bytes!(msg.channel_id));
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
-								//TODO: generate a DiscardFunding event indicating to the wallet that
-								//they should just throw away this funding transaction
+								// The funding transaction isn't ours to discard. The channel is kept
+								// and times out if the funding transaction never confirms.
 							});
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
@@ -1014,6 +1251,46 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
+					MessageSendEvent::SendStfu { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendStfu event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSplice { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSplice event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceAck { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceAck event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendSpliceLocked { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendSpliceLocked event in peer_handler for node {} for channel {}",
+								log_pubkey!(node_id),
+								log_bytes!(msg.channel_id));
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {
+								//TODO: Do whatever we're gonna do for handling dropped messages
+							});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
 					MessageSendEvent::SendShutdown { ref node_id, ref msg } => {
 						log_trace!(self.logger, "Handling Shutdown event in peer_handler for node {} for channel {}",
 								log_pubkey!(node_id),
@@ -1034,59 +1311,23 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
 					},
-					MessageSendEvent::BroadcastChannelAnnouncement { ref msg, ref update_msg } => {
+					MessageSendEvent::BroadcastChannelAnnouncement { msg, update_msg } => {
 						log_trace!(self.logger, "Handling BroadcastChannelAnnouncement event in peer_handler for short channel id {}", msg.contents.short_channel_id);
-						if self.message_handler.route_handler.handle_channel_announcement(msg).is_ok() && self.message_handler.route_handler.handle_channel_update(update_msg).is_ok() {
-							let encoded_msg = encode_msg!(msg);
-							let encoded_update_msg = encode_msg!(update_msg);
-
-							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
-								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id) {
-									continue
-								}
-								match peer.their_node_id {
-									None => continue,
-									Some(their_node_id) => {
-										if their_node_id == msg.contents.node_id_1 || their_node_id == msg.contents.node_id_2 {
-											continue
-										}
-									}
-								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_update_msg[..]));
-								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
-							}
+						if self.message_handler.route_handler.handle_channel_announcement(&msg).is_ok() && self.message_handler.route_handler.handle_channel_update(&update_msg).is_ok() {
+							self.forward_broadcast_msg(peers, &wire::Message::ChannelAnnouncement(msg), None);
+							self.forward_broadcast_msg(peers, &wire::Message::ChannelUpdate(update_msg), None);
 						}
 					},
-					MessageSendEvent::BroadcastNodeAnnouncement { ref msg } => {
+					MessageSendEvent::BroadcastNodeAnnouncement { msg } => {
 						log_trace!(self.logger, "Handling BroadcastNodeAnnouncement event in peer_handler");
-						if self.message_handler.route_handler.handle_node_announcement(msg).is_ok() {
-							let encoded_msg = encode_msg!(msg);
-
-							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
-								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_node_announcement(msg.contents.node_id) {
-									continue
-								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
-								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
-							}
+						if self.message_handler.route_handler.handle_node_announcement(&msg).is_ok() {
+							self.forward_broadcast_msg(peers, &wire::Message::NodeAnnouncement(msg), None);
 						}
 					},
-					MessageSendEvent::BroadcastChannelUpdate { ref msg } => {
+					MessageSendEvent::BroadcastChannelUpdate { msg } => {
 						log_trace!(self.logger, "Handling BroadcastChannelUpdate event in peer_handler for short channel id {}", msg.contents.short_channel_id);
-						if self.message_handler.route_handler.handle_channel_update(msg).is_ok() {
-							let encoded_msg = encode_msg!(msg);
-
-							for (ref descriptor, ref mut peer) in peers.peers.iter_mut() {
-								if !peer.channel_encryptor.is_ready_for_encryption() || peer.their_features.is_none() ||
-										!peer.should_forward_channel_announcement(msg.contents.short_channel_id)  {
-									continue
-								}
-								peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encoded_msg[..]));
-								self.do_attempt_write_data(&mut (*descriptor).clone(), peer);
-							}
+						if self.message_handler.route_handler.handle_channel_update(&msg).is_ok() {
+							self.forward_broadcast_msg(peers, &wire::Message::ChannelUpdate(msg), None);
 						}
 					},
 					MessageSendEvent::PaymentFailureNetworkUpdate { ref update } => {
@@ -1114,6 +1355,22 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 									self.message_handler.chan_handler.peer_disconnected(&node_id, false);
 								}
 							},
+							msgs::ErrorAction::DisconnectPeerWithWarning { ref msg } => {
+								if let Some(mut descriptor) = peers.node_id_to_descriptor.remove(node_id) {
+									peers.peers_needing_send.remove(&descriptor);
+									if let Some(mut peer) = peers.peers.remove(&descriptor) {
+										log_trace!(self.logger, "Handling DisconnectPeerWithWarning HandleError event in peer_handler for node {} with message {}",
+												log_pubkey!(node_id),
+												msg.data);
+										peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+										// This isn't guaranteed to work, but if there is enough free
+										// room in the send buffer, put the warning message there...
+										self.do_attempt_write_data(&mut descriptor, &mut peer);
+									}
+									descriptor.disconnect_socket();
+									self.message_handler.chan_handler.peer_disconnected(&node_id, false);
+								}
+							},
 							msgs::ErrorAction::IgnoreError => {},
 							msgs::ErrorAction::SendErrorMessage { ref msg } => {
 								log_trace!(self.logger, "Handling SendErrorMessage HandleError event in peer_handler for node {} with message {}",
@@ -1136,6 +1393,48 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
 						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
 						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendReplyChannelRange { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendReplyChannelRange event in peer_handler for node {} with num_scids={} first_blocknum={} number_of_blocks={}",
+								log_pubkey!(node_id),
+								msg.short_channel_ids.len(),
+								msg.first_blocknum,
+								msg.number_of_blocks);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendChannelAnnouncement { ref node_id, ref msg } => {
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendChannelUpdate { ref node_id, ref msg } => {
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendNodeAnnouncement { ref node_id, ref msg } => {
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendReplyShortChannelIdsEnd { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendReplyShortChannelIdsEnd event in peer_handler for node {} with full_information={}",
+								log_pubkey!(node_id),
+								msg.full_information);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
+					},
+					MessageSendEvent::SendGossipTimestampFilter { ref node_id, ref msg } => {
+						log_trace!(self.logger, "Handling SendGossipTimestampFilter event in peer_handler for node {} with first_timestamp={} timestamp_range={}",
+								log_pubkey!(node_id),
+								msg.first_timestamp,
+								msg.timestamp_range);
+						let (mut descriptor, peer) = get_peer_for_forwarding!(node_id, {});
+						peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(&encode_msg!(msg)));
+						self.do_attempt_write_data(&mut descriptor, peer);
 					}
 				}
 			}
@@ -1238,7 +1537,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, L: Deref> PeerManager<D
 
 #[cfg(test)]
 mod tests {
-	use ln::peer_handler::{PeerManager, MessageHandler, SocketDescriptor};
+	use ln::peer_handler::{PeerManager, MessageHandler, SocketDescriptor, OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP};
 	use ln::msgs;
 	use util::events;
 	use util::test_utils;
@@ -1248,7 +1547,9 @@ mod tests {
 
 	use std;
 	use std::sync::{Arc, Mutex};
-	use std::sync::atomic::Ordering;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	static NEXT_FD: AtomicUsize = AtomicUsize::new(1);
 
 	#[derive(Clone)]
 	struct FileDescriptor {
@@ -1313,8 +1614,9 @@ mod tests {
 	fn establish_connection<'a>(peer_a: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, peer_b: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>) -> (FileDescriptor, FileDescriptor) {
 		let secp_ctx = Secp256k1::new();
 		let a_id = PublicKey::from_secret_key(&secp_ctx, &peer_a.our_node_secret);
-		let mut fd_a = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
-		let mut fd_b = FileDescriptor { fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())) };
+		let fd = NEXT_FD.fetch_add(1, Ordering::AcqRel) as u16;
+		let mut fd_a = FileDescriptor { fd, outbound_data: Arc::new(Mutex::new(Vec::new())) };
+		let mut fd_b = FileDescriptor { fd, outbound_data: Arc::new(Mutex::new(Vec::new())) };
 		let initial_data = peer_b.new_outbound_connection(a_id, fd_b.clone()).unwrap();
 		peer_a.new_inbound_connection(fd_a.clone()).unwrap();
 		assert_eq!(peer_a.read_event(&mut fd_a, &initial_data).unwrap(), false);
@@ -1391,4 +1693,146 @@ mod tests {
 		assert_eq!(cfgs[1].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 100);
 		assert_eq!(cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 50);
 	}
+
+	fn deliver_all_pending<'a>(peer_a: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, fd_a: &mut FileDescriptor, peer_b: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, fd_b: &mut FileDescriptor) {
+		loop {
+			peer_a.process_events();
+			peer_b.process_events();
+			let a_to_b = fd_a.outbound_data.lock().unwrap().split_off(0);
+			let b_to_a = fd_b.outbound_data.lock().unwrap().split_off(0);
+			if a_to_b.is_empty() && b_to_a.is_empty() { break; }
+			peer_b.read_event(fd_b, &a_to_b).unwrap();
+			peer_a.read_event(fd_a, &b_to_a).unwrap();
+		}
+	}
+
+	fn send_gossip_timestamp_filter<'a>(cfg: &PeerManagerCfg, peer: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, recipient: &PeerManager<FileDescriptor, &'a test_utils::TestChannelMessageHandler, &'a test_utils::TestRoutingMessageHandler, &'a test_utils::TestLogger>, first_timestamp: u32, timestamp_range: u32) {
+		let secp_ctx = Secp256k1::new();
+		cfg.chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::SendGossipTimestampFilter {
+			node_id: PublicKey::from_secret_key(&secp_ctx, &recipient.our_node_secret),
+			msg: msgs::GossipTimestampFilter {
+				chain_hash: test_utils::get_dummy_channel_update(0).contents.chain_hash,
+				first_timestamp,
+				timestamp_range,
+			},
+		});
+		peer.process_events();
+	}
+
+	#[test]
+	fn test_gossip_relay() {
+		// Connect peers[0] to both peers[1] and peers[2] and check that new gossip peers[0] receives
+		// from peers[1] is relayed to peers[2], but not back to peers[1].
+		let cfgs = create_peermgr_cfgs(3);
+		let peers = create_network(3, &cfgs);
+		let (mut fd_0_1, mut fd_1_0) = establish_connection(&peers[0], &peers[1]);
+		let (mut fd_0_2, mut fd_2_0) = establish_connection(&peers[0], &peers[2]);
+		// Complete the Init exchange and initial routing table syncs before we start counting.
+		deliver_all_pending(&peers[0], &mut fd_0_1, &peers[1], &mut fd_1_0);
+		deliver_all_pending(&peers[0], &mut fd_0_2, &peers[2], &mut fd_2_0);
+		for cfg in cfgs.iter() {
+			cfg.routing_handler.chan_upds_recvd.store(0, Ordering::Release);
+			cfg.routing_handler.relay_gossip.store(true, Ordering::Release);
+		}
+		// As peers[2] supports gossip_queries, we only relay gossip to it once it asks for it.
+		send_gossip_timestamp_filter(&cfgs[2], &peers[2], &peers[0], 0, 0xffff_ffff);
+		peers[0].read_event(&mut fd_0_2, &fd_2_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+
+		cfgs[1].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::BroadcastChannelUpdate {
+			msg: test_utils::get_dummy_channel_update(42),
+		});
+		peers[1].process_events();
+		peers[0].read_event(&mut fd_0_1, &fd_1_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+
+		// Forwarded messages are only sent once we process events.
+		assert!(fd_0_2.outbound_data.lock().unwrap().is_empty());
+		peers[0].process_events();
+		assert!(fd_0_1.outbound_data.lock().unwrap().is_empty());
+		peers[2].read_event(&mut fd_2_0, &fd_0_2.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		assert_eq!(cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 1);
+
+		// Gossip the handler doesn't consider new isn't relayed.
+		cfgs[0].routing_handler.relay_gossip.store(false, Ordering::Release);
+		cfgs[1].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::BroadcastChannelUpdate {
+			msg: test_utils::get_dummy_channel_update(43),
+		});
+		peers[1].process_events();
+		peers[0].read_event(&mut fd_0_1, &fd_1_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 2);
+		peers[0].process_events();
+		assert!(fd_0_2.outbound_data.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_gossip_relay_full_buffer() {
+		// Check that we don't relay gossip to a peer whose outbound buffer is already full.
+		let cfgs = create_peermgr_cfgs(3);
+		let peers = create_network(3, &cfgs);
+		let (mut fd_0_1, mut fd_1_0) = establish_connection(&peers[0], &peers[1]);
+		let (mut fd_0_2, mut fd_2_0) = establish_connection(&peers[0], &peers[2]);
+		deliver_all_pending(&peers[0], &mut fd_0_1, &peers[1], &mut fd_1_0);
+		deliver_all_pending(&peers[0], &mut fd_0_2, &peers[2], &mut fd_2_0);
+		for cfg in cfgs.iter() {
+			cfg.routing_handler.chan_upds_recvd.store(0, Ordering::Release);
+			cfg.routing_handler.relay_gossip.store(true, Ordering::Release);
+		}
+		// As peers[2] supports gossip_queries, we only relay gossip to it once it asks for it.
+		send_gossip_timestamp_filter(&cfgs[2], &peers[2], &peers[0], 0, 0xffff_ffff);
+		peers[0].read_event(&mut fd_0_2, &fd_2_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+
+		// Have peers[1] send 30 channel_updates, which peers[0] relays to peers[2] while
+		// peers[2] isn't accepting any data.
+		for i in 0..30 {
+			cfgs[1].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::BroadcastChannelUpdate {
+				msg: test_utils::get_dummy_channel_update(i),
+			});
+			peers[1].process_events();
+		}
+		peers[0].peers.lock().unwrap().peers.get_mut(&fd_0_2).unwrap().awaiting_write_event = true;
+		peers[0].read_event(&mut fd_0_1, &fd_1_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+		assert_eq!(cfgs[0].routing_handler.chan_upds_recvd.load(Ordering::Acquire), 30);
+		peers[0].process_events();
+		assert_eq!(peers[0].peers.lock().unwrap().peers.get(&fd_0_2).unwrap().pending_outbound_buffer.len(), OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP + 1);
+	}
+
+	#[test]
+	fn test_gossip_relay_timestamp_filter() {
+		// Check that we only relay gossip to peers which support gossip_queries once they've sent us
+		// a gossip_timestamp_filter, and then only gossip within the requested time window.
+		let cfgs = create_peermgr_cfgs(3);
+		let peers = create_network(3, &cfgs);
+		let (mut fd_0_1, mut fd_1_0) = establish_connection(&peers[0], &peers[1]);
+		let (mut fd_0_2, mut fd_2_0) = establish_connection(&peers[0], &peers[2]);
+		deliver_all_pending(&peers[0], &mut fd_0_1, &peers[1], &mut fd_1_0);
+		deliver_all_pending(&peers[0], &mut fd_0_2, &peers[2], &mut fd_2_0);
+		for cfg in cfgs.iter() {
+			cfg.routing_handler.chan_upds_recvd.store(0, Ordering::Release);
+			cfg.routing_handler.relay_gossip.store(true, Ordering::Release);
+		}
+
+		macro_rules! relay_update_with_timestamp {
+			($short_channel_id: expr, $timestamp: expr) => {{
+				let mut msg = test_utils::get_dummy_channel_update($short_channel_id);
+				msg.contents.timestamp = $timestamp;
+				cfgs[1].chan_handler.pending_events.lock().unwrap().push(events::MessageSendEvent::BroadcastChannelUpdate { msg });
+				peers[1].process_events();
+				peers[0].read_event(&mut fd_0_1, &fd_1_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+				peers[0].process_events();
+				peers[2].read_event(&mut fd_2_0, &fd_0_2.outbound_data.lock().unwrap().split_off(0)).unwrap();
+				cfgs[2].routing_handler.chan_upds_recvd.load(Ordering::Acquire)
+			}}
+		}
+
+		// Without a filter, nothing is relayed.
+		assert_eq!(relay_update_with_timestamp!(1, 150), 0);
+
+		send_gossip_timestamp_filter(&cfgs[2], &peers[2], &peers[0], 100, 100);
+		peers[0].read_event(&mut fd_0_2, &fd_2_0.outbound_data.lock().unwrap().split_off(0)).unwrap();
+
+		assert_eq!(relay_update_with_timestamp!(2, 99), 0);
+		assert_eq!(relay_update_with_timestamp!(3, 100), 1);
+		assert_eq!(relay_update_with_timestamp!(4, 199), 2);
+		assert_eq!(relay_update_with_timestamp!(5, 200), 2);
+	}
 }
@@ -34,15 +34,29 @@ use util::ser::{Readable, Writeable, Writer};
 pub enum Message {
 	Init(msgs::Init),
 	Error(msgs::ErrorMessage),
+	Warning(msgs::WarningMessage),
 	Ping(msgs::Ping),
 	Pong(msgs::Pong),
 	OpenChannel(msgs::OpenChannel),
 	AcceptChannel(msgs::AcceptChannel),
+	OpenChannelV2(msgs::OpenChannelV2),
+	AcceptChannelV2(msgs::AcceptChannelV2),
+	TxAddInput(msgs::TxAddInput),
+	TxAddOutput(msgs::TxAddOutput),
+	TxRemoveInput(msgs::TxRemoveInput),
+	TxRemoveOutput(msgs::TxRemoveOutput),
+	TxComplete(msgs::TxComplete),
+	TxSignatures(msgs::TxSignatures),
+	TxAbort(msgs::TxAbort),
 	FundingCreated(msgs::FundingCreated),
 	FundingSigned(msgs::FundingSigned),
 	FundingLocked(msgs::FundingLocked),
 	Shutdown(msgs::Shutdown),
 	ClosingSigned(msgs::ClosingSigned),
+	Stfu(msgs::Stfu),
+	Splice(msgs::Splice),
+	SpliceAck(msgs::SpliceAck),
+	SpliceLocked(msgs::SpliceLocked),
 	UpdateAddHTLC(msgs::UpdateAddHTLC),
 	UpdateFulfillHTLC(msgs::UpdateFulfillHTLC),
 	UpdateFailHTLC(msgs::UpdateFailHTLC),
@@ -74,15 +88,29 @@ impl Message {
 		match self {
 			&Message::Init(ref msg) => msg.type_id(),
 			&Message::Error(ref msg) => msg.type_id(),
+			&Message::Warning(ref msg) => msg.type_id(),
 			&Message::Ping(ref msg) => msg.type_id(),
 			&Message::Pong(ref msg) => msg.type_id(),
 			&Message::OpenChannel(ref msg) => msg.type_id(),
 			&Message::AcceptChannel(ref msg) => msg.type_id(),
+			&Message::OpenChannelV2(ref msg) => msg.type_id(),
+			&Message::AcceptChannelV2(ref msg) => msg.type_id(),
+			&Message::TxAddInput(ref msg) => msg.type_id(),
+			&Message::TxAddOutput(ref msg) => msg.type_id(),
+			&Message::TxRemoveInput(ref msg) => msg.type_id(),
+			&Message::TxRemoveOutput(ref msg) => msg.type_id(),
+			&Message::TxComplete(ref msg) => msg.type_id(),
+			&Message::TxSignatures(ref msg) => msg.type_id(),
+			&Message::TxAbort(ref msg) => msg.type_id(),
 			&Message::FundingCreated(ref msg) => msg.type_id(),
 			&Message::FundingSigned(ref msg) => msg.type_id(),
 			&Message::FundingLocked(ref msg) => msg.type_id(),
 			&Message::Shutdown(ref msg) => msg.type_id(),
 			&Message::ClosingSigned(ref msg) => msg.type_id(),
+			&Message::Stfu(ref msg) => msg.type_id(),
+			&Message::Splice(ref msg) => msg.type_id(),
+			&Message::SpliceAck(ref msg) => msg.type_id(),
+			&Message::SpliceLocked(ref msg) => msg.type_id(),
 			&Message::UpdateAddHTLC(ref msg) => msg.type_id(),
 			&Message::UpdateFulfillHTLC(ref msg) => msg.type_id(),
 			&Message::UpdateFailHTLC(ref msg) => msg.type_id(),
@@ -133,6 +161,9 @@ pub fn read<R: ::std::io::Read>(buffer: &mut R) -> Result<Message, msgs::DecodeE
 		msgs::ErrorMessage::TYPE => {
 			Ok(Message::Error(Readable::read(buffer)?))
 		},
+		msgs::WarningMessage::TYPE => {
+			Ok(Message::Warning(Readable::read(buffer)?))
+		},
 		msgs::Ping::TYPE => {
 			Ok(Message::Ping(Readable::read(buffer)?))
 		},
@@ -145,6 +176,33 @@ pub fn read<R: ::std::io::Read>(buffer: &mut R) -> Result<Message, msgs::DecodeE
 		msgs::AcceptChannel::TYPE => {
 			Ok(Message::AcceptChannel(Readable::read(buffer)?))
 		},
+		msgs::OpenChannelV2::TYPE => {
+			Ok(Message::OpenChannelV2(Readable::read(buffer)?))
+		},
+		msgs::AcceptChannelV2::TYPE => {
+			Ok(Message::AcceptChannelV2(Readable::read(buffer)?))
+		},
+		msgs::TxAddInput::TYPE => {
+			Ok(Message::TxAddInput(Readable::read(buffer)?))
+		},
+		msgs::TxAddOutput::TYPE => {
+			Ok(Message::TxAddOutput(Readable::read(buffer)?))
+		},
+		msgs::TxRemoveInput::TYPE => {
+			Ok(Message::TxRemoveInput(Readable::read(buffer)?))
+		},
+		msgs::TxRemoveOutput::TYPE => {
+			Ok(Message::TxRemoveOutput(Readable::read(buffer)?))
+		},
+		msgs::TxComplete::TYPE => {
+			Ok(Message::TxComplete(Readable::read(buffer)?))
+		},
+		msgs::TxSignatures::TYPE => {
+			Ok(Message::TxSignatures(Readable::read(buffer)?))
+		},
+		msgs::TxAbort::TYPE => {
+			Ok(Message::TxAbort(Readable::read(buffer)?))
+		},
 		msgs::FundingCreated::TYPE => {
 			Ok(Message::FundingCreated(Readable::read(buffer)?))
 		},
@@ -160,6 +218,18 @@ pub fn read<R: ::std::io::Read>(buffer: &mut R) -> Result<Message, msgs::DecodeE
 		msgs::ClosingSigned::TYPE => {
 			Ok(Message::ClosingSigned(Readable::read(buffer)?))
 		},
+		msgs::Stfu::TYPE => {
+			Ok(Message::Stfu(Readable::read(buffer)?))
+		},
+		msgs::Splice::TYPE => {
+			Ok(Message::Splice(Readable::read(buffer)?))
+		},
+		msgs::SpliceAck::TYPE => {
+			Ok(Message::SpliceAck(Readable::read(buffer)?))
+		},
+		msgs::SpliceLocked::TYPE => {
+			Ok(Message::SpliceLocked(Readable::read(buffer)?))
+		},
 		msgs::UpdateAddHTLC::TYPE => {
 			Ok(Message::UpdateAddHTLC(Readable::read(buffer)?))
 		},
@@ -245,6 +315,10 @@ pub trait Encode {
 	}
 }
 
+impl Encode for msgs::WarningMessage {
+	const TYPE: u16 = 1;
+}
+
 impl Encode for msgs::Init {
 	const TYPE: u16 = 16;
 }
@@ -269,6 +343,42 @@ impl Encode for msgs::AcceptChannel {
 	const TYPE: u16 = 33;
 }
 
+impl Encode for msgs::OpenChannelV2 {
+	const TYPE: u16 = 64;
+}
+
+impl Encode for msgs::AcceptChannelV2 {
+	const TYPE: u16 = 65;
+}
+
+impl Encode for msgs::TxAddInput {
+	const TYPE: u16 = 66;
+}
+
+impl Encode for msgs::TxAddOutput {
+	const TYPE: u16 = 67;
+}
+
+impl Encode for msgs::TxRemoveInput {
+	const TYPE: u16 = 68;
+}
+
+impl Encode for msgs::TxRemoveOutput {
+	const TYPE: u16 = 69;
+}
+
+impl Encode for msgs::TxComplete {
+	const TYPE: u16 = 70;
+}
+
+impl Encode for msgs::TxSignatures {
+	const TYPE: u16 = 71;
+}
+
+impl Encode for msgs::TxAbort {
+	const TYPE: u16 = 74;
+}
+
 impl Encode for msgs::FundingCreated {
 	const TYPE: u16 = 34;
 }
@@ -289,6 +399,22 @@ impl Encode for msgs::ClosingSigned {
 	const TYPE: u16 = 39;
 }
 
+impl Encode for msgs::Stfu {
+	const TYPE: u16 = 2;
+}
+
+impl Encode for msgs::Splice {
+	const TYPE: u16 = 80;
+}
+
+impl Encode for msgs::SpliceAck {
+	const TYPE: u16 = 81;
+}
+
+impl Encode for msgs::SpliceLocked {
+	const TYPE: u16 = 77;
+}
+
 impl Encode for msgs::UpdateAddHTLC {
 	const TYPE: u16 = 128;
 }
@@ -11,3 +11,4 @@
 
 pub mod router;
 pub mod network_graph;
+pub mod scorer;
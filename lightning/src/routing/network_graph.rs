@@ -22,22 +22,28 @@ use bitcoin::hash_types::BlockHash;
 
 use chain;
 use chain::Access;
+use ln::channelmanager::ChannelDetails;
 use ln::features::{ChannelFeatures, NodeFeatures};
 use ln::msgs::{DecodeError, ErrorAction, Init, LightningError, RoutingMessageHandler, NetAddress, MAX_VALUE_MSAT};
 use ln::msgs::{ChannelAnnouncement, ChannelUpdate, NodeAnnouncement, OptionalField};
-use ln::msgs::{QueryChannelRange, ReplyChannelRange, QueryShortChannelIds, ReplyShortChannelIdsEnd};
+use ln::msgs::{QueryChannelRange, ReplyChannelRange, QueryShortChannelIds, ReplyShortChannelIdsEnd, GossipTimestampFilter};
 use ln::msgs;
+use ln::payment_retry::{Router, RouteParameters};
+use routing::router::{get_route_with_constraints, Route};
+use routing::scorer::Score;
 use util::ser::{Writeable, Readable, Writer};
 use util::logger::Logger;
 use util::events;
+use util::scid_utils::{block_from_scid, scid_from_parts};
 
 use std::{cmp, fmt};
 use std::sync::{RwLock, RwLockReadGuard};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::collections::btree_map::Entry as BtreeEntry;
-use std::ops::Deref;
+use std::ops::{Bound, Deref};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use bitcoin::hashes::hex::ToHex;
 
 /// Represents the network as nodes and channels between them
@@ -48,6 +54,44 @@ pub struct NetworkGraph {
 	nodes: BTreeMap<PublicKey, NodeInfo>,
 }
 
+/// The maximum number of short_channel_ids we include in a single reply_channel_range. At 8 bytes
+/// per short_channel_id this keeps the reply within the 65535 byte lightning message limit.
+const MAX_SCIDS_PER_REPLY: usize = 8000;
+
+/// How far, in seconds, before the latest channel_update we know of we ask peers to start sending
+/// us gossip from. This covers updates which were made while we were offline but reached us out of
+/// order, or were timestamped by a node whose clock is behind.
+const GOSSIP_TIMESTAMP_FILTER_MARGIN_SECS: u32 = 60 * 60 * 24;
+
+/// The length of time, in seconds, for which a peer's gossip query allowance is tracked before
+/// it is reset.
+const GOSSIP_QUERY_ALLOWANCE_PERIOD_SECS: u64 = 60 * 60;
+
+/// The number of query_channel_range messages we will answer from a single peer per
+/// GOSSIP_QUERY_ALLOWANCE_PERIOD_SECS.
+const MAX_CHANNEL_RANGE_QUERIES_PER_PERIOD: usize = 16;
+
+/// The number of short_channel_ids a single peer may query per GOSSIP_QUERY_ALLOWANCE_PERIOD_SECS in
+/// addition to the number of channels in our graph. This allows each peer to pull our full graph
+/// roughly once per period, but not to make us stream it over and over.
+const EXTRA_QUERIED_SCIDS_PER_PERIOD: usize = MAX_SCIDS_PER_REPLY;
+
+/// Tracks how many gossip queries we've answered for a given peer in the current period.
+struct GossipQueryAllowance {
+	period_start: Instant,
+	channel_range_queries: usize,
+	queried_short_channel_ids: usize,
+}
+
+/// A query_short_channel_ids which we're still replying to, as we only send replies as the
+/// querying peer's outbound buffer drains.
+struct PendingShortChannelIdsQuery {
+	chain_hash: BlockHash,
+	full_information: bool,
+	short_channel_ids: VecDeque<u64>,
+	sent_node_announcements: HashSet<PublicKey>,
+}
+
 /// A simple newtype for RwLockReadGuard<'a, NetworkGraph>.
 /// This exists only to make accessing a RwLock<NetworkGraph> possible from
 /// the C bindings, as it can be done directly in Rust code.
@@ -65,6 +109,8 @@ pub struct NetGraphMsgHandler<C: Deref, L: Deref> where C::Target: chain::Access
 	chain_access: Option<C>,
 	full_syncs_requested: AtomicUsize,
 	pending_events: Mutex<Vec<events::MessageSendEvent>>,
+	gossip_query_allowances: Mutex<HashMap<PublicKey, GossipQueryAllowance>>,
+	pending_short_channel_ids_queries: Mutex<HashMap<PublicKey, VecDeque<PendingShortChannelIdsQuery>>>,
 	logger: L,
 }
 
@@ -81,6 +127,8 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: chain::Access
 			full_syncs_requested: AtomicUsize::new(0),
 			chain_access,
 			pending_events: Mutex::new(vec![]),
+			gossip_query_allowances: Mutex::new(HashMap::new()),
+			pending_short_channel_ids_queries: Mutex::new(HashMap::new()),
 			logger,
 		}
 	}
@@ -94,6 +142,8 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: chain::Access
 			full_syncs_requested: AtomicUsize::new(0),
 			chain_access,
 			pending_events: Mutex::new(vec![]),
+			gossip_query_allowances: Mutex::new(HashMap::new()),
+			pending_short_channel_ids_queries: Mutex::new(HashMap::new()),
 			logger,
 		}
 	}
@@ -117,6 +167,28 @@ impl<C: Deref, L: Deref> NetGraphMsgHandler<C, L> where C::Target: chain::Access
 			false
 		}
 	}
+
+	/// Checks whether the given peer may still have the given number of channel range queries
+	/// and queried short_channel_ids answered in the current period, deducting them from its
+	/// allowance if so.
+	fn use_gossip_query_allowance(&self, their_node_id: &PublicKey, channel_range_queries: usize, queried_short_channel_ids: usize) -> bool {
+		let max_queried_short_channel_ids = self.network_graph.read().unwrap().channels.len() + EXTRA_QUERIED_SCIDS_PER_PERIOD;
+		let now = Instant::now();
+		let mut allowances = self.gossip_query_allowances.lock().unwrap();
+		allowances.retain(|_, allowance| now.duration_since(allowance.period_start) < Duration::from_secs(GOSSIP_QUERY_ALLOWANCE_PERIOD_SECS));
+		let allowance = allowances.entry(their_node_id.clone()).or_insert(GossipQueryAllowance {
+			period_start: now,
+			channel_range_queries: 0,
+			queried_short_channel_ids: 0,
+		});
+		if allowance.channel_range_queries + channel_range_queries > MAX_CHANNEL_RANGE_QUERIES_PER_PERIOD ||
+				allowance.queried_short_channel_ids + queried_short_channel_ids > max_queried_short_channel_ids {
+			return false;
+		}
+		allowance.channel_range_queries += channel_range_queries;
+		allowance.queried_short_channel_ids += queried_short_channel_ids;
+		true
+	}
 }
 
 impl<'a> LockedNetworkGraph<'a> {
@@ -225,16 +297,42 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 	///
 	/// We should expect one or more reply_channel_range messages in response
 	/// to our query_channel_range. Each reply will enqueue a query_scid message
-	/// to request gossip messages for each channel. The sync is considered complete
-	/// when the final reply_scids_end message is received, though we are not
-	/// tracking this directly.
+	/// to request gossip messages for each channel we don't yet know about. The
+	/// sync is considered complete when the final reply_scids_end message is
+	/// received, though we are not tracking this directly.
+	///
+	/// Because peers which support gossip_queries won't relay any gossip to us
+	/// until we ask them to, we also send every such peer a gossip_timestamp_filter.
+	/// As the range sync only fetches channels we don't know about, the filter
+	/// starts a margin before the latest channel_update we know of, so that
+	/// updates to known channels made while we were offline are sent to us. If
+	/// we know of no updates, it requests all gossip from now on.
 	fn sync_routing_table(&self, their_node_id: &PublicKey, init_msg: &Init) {
+		// Replies to queries from a previous connection with this peer will never be delivered.
+		self.pending_short_channel_ids_queries.lock().unwrap().remove(their_node_id);
 
 		// We will only perform a sync with peers that support gossip_queries.
 		if !init_msg.features.supports_gossip_queries() {
 			return ();
 		}
 
+		let first_timestamp = match self.network_graph.read().unwrap().get_latest_channel_update_timestamp() {
+			Some(timestamp) => timestamp.saturating_sub(GOSSIP_TIMESTAMP_FILTER_MARGIN_SECS),
+			None => match SystemTime::now().duration_since(UNIX_EPOCH) {
+				Ok(time) => cmp::min(time.as_secs(), u32::max_value() as u64) as u32,
+				Err(_) => 0,
+			},
+		};
+		log_debug!(self.logger, "Sending gossip_timestamp_filter peer={}, first_timestamp={}", log_pubkey!(their_node_id), first_timestamp);
+		self.pending_events.lock().unwrap().push(events::MessageSendEvent::SendGossipTimestampFilter {
+			node_id: their_node_id.clone(),
+			msg: GossipTimestampFilter {
+				chain_hash: self.network_graph.read().unwrap().genesis_hash,
+				first_timestamp,
+				timestamp_range: u32::max_value(),
+			},
+		});
+
 		// Check if we need to perform a full synchronization with this peer
 		if !self.should_request_full_sync(their_node_id) {
 			return ();
@@ -255,7 +353,8 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 	}
 
 	/// Statelessly processes a reply to a channel range query by immediately
-	/// sending an SCID query with SCIDs in the reply. To keep this handler
+	/// sending an SCID query for those SCIDs in the reply for which we don't
+	/// already have a channel_announcement. To keep this handler
 	/// stateless, it does not validate the sequencing of replies for multi-
 	/// reply ranges. It does not validate whether the reply(ies) cover the
 	/// queried range. It also does not filter SCIDs to only those in the
@@ -277,13 +376,29 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 			});
 		}
 
-		log_debug!(self.logger, "Sending query_short_channel_ids peer={}, batch_size={}", log_pubkey!(their_node_id), msg.short_channel_ids.len());
+		// Only query for channels we are missing, so that reconnecting to a peer (or restarting
+		// with a persisted graph) doesn't have us download the whole graph again.
+		let short_channel_ids: Vec<u64> = {
+			let network_graph = self.network_graph.read().unwrap();
+			msg.short_channel_ids.into_iter().filter(|scid| {
+				match network_graph.channels.get(scid) {
+					Some(chan) => chan.announcement_message.is_none(),
+					None => true,
+				}
+			}).collect()
+		};
+		if short_channel_ids.is_empty() {
+			log_debug!(self.logger, "Not sending query_short_channel_ids to peer={} as we already know all channels in its reply", log_pubkey!(their_node_id));
+			return Ok(());
+		}
+
+		log_debug!(self.logger, "Sending query_short_channel_ids peer={}, batch_size={}", log_pubkey!(their_node_id), short_channel_ids.len());
 		let mut pending_events = self.pending_events.lock().unwrap();
 		pending_events.push(events::MessageSendEvent::SendShortIdsQuery {
 			node_id: their_node_id.clone(),
 			msg: QueryShortChannelIds {
 				chain_hash: msg.chain_hash,
-				short_channel_ids: msg.short_channel_ids,
+				short_channel_ids,
 			}
 		});
 
@@ -310,20 +425,214 @@ impl<C: Deref + Sync + Send, L: Deref + Sync + Send> RoutingMessageHandler for N
 		Ok(())
 	}
 
-	fn handle_query_channel_range(&self, _their_node_id: &PublicKey, _msg: QueryChannelRange) -> Result<(), LightningError> {
-		// TODO
-		Err(LightningError {
-			err: String::from("Not implemented"),
-			action: ErrorAction::IgnoreError,
-		})
+	/// Replies to a query_channel_range with one or more reply_channel_range messages containing
+	/// the short_channel_ids of every announced channel we know of in the queried block range.
+	///
+	/// Channels are batched into replies of at most 8000 short_channel_ids. Per BOLT 7, the first
+	/// reply starts at the query's first_blocknum, each subsequent reply starts where the previous
+	/// one ended, and the final reply extends to the end of the queried range. As a reply covers
+	/// whole blocks, the channels of a single block are never split across replies (unless a
+	/// block has more channels than fit in one reply).
+	///
+	/// Each peer may only have a limited number of queries answered per hour. Once a peer
+	/// exceeds its allowance (or queries a chain we don't know about) we reply with a single empty
+	/// reply_channel_range with full_information unset.
+	fn handle_query_channel_range(&self, their_node_id: &PublicKey, msg: QueryChannelRange) -> Result<(), LightningError> {
+		log_debug!(self.logger, "Handling query_channel_range peer={}, first_blocknum={}, number_of_blocks={}", log_pubkey!(their_node_id), msg.first_blocknum, msg.number_of_blocks);
+
+		let network_graph = self.network_graph.read().unwrap();
+		let known_chain = msg.chain_hash == network_graph.genesis_hash;
+		drop(network_graph);
+
+		// Per spec, we must reply to a query. Send an empty message when we can't help.
+		if !known_chain || !self.use_gossip_query_allowance(their_node_id, 1, 0) {
+			let mut pending_events = self.pending_events.lock().unwrap();
+			pending_events.push(events::MessageSendEvent::SendReplyChannelRange {
+				node_id: their_node_id.clone(),
+				msg: ReplyChannelRange {
+					chain_hash: msg.chain_hash,
+					first_blocknum: msg.first_blocknum,
+					number_of_blocks: msg.number_of_blocks,
+					full_information: false,
+					short_channel_ids: vec![],
+				}
+			});
+			return Err(LightningError {
+				err: if known_chain {
+					String::from("Peer exceeded its query_channel_range allowance")
+				} else {
+					String::from("Received query_channel_range for an unknown chain")
+				},
+				action: ErrorAction::IgnoreError,
+			});
+		}
+
+		// Build up the batches of short_channel_ids which we will send. Queries whose end block
+		// overflows the SCID block height simply cover every channel from first_blocknum onwards,
+		// while a first_blocknum which overflows cannot contain any channels, leaving us with a
+		// single empty reply.
+		let mut batches: Vec<Vec<u64>> = vec![Vec::with_capacity(MAX_SCIDS_PER_REPLY)];
+		if let Ok(inclusive_start_scid) = scid_from_parts(msg.first_blocknum as u64, 0, 0) {
+			let end_bound = match scid_from_parts(msg.end_blocknum() as u64, 0, 0) {
+				Ok(exclusive_end_scid) => Bound::Excluded(exclusive_end_scid),
+				Err(_) => Bound::Unbounded,
+			};
+			let network_graph = self.network_graph.read().unwrap();
+			for (scid, chan) in network_graph.get_channels().range((Bound::Included(inclusive_start_scid), end_bound)) {
+				// We can only serve channels for which we have a full announcement.
+				if chan.announcement_message.is_none() { continue; }
+				if batches.last().unwrap().len() == MAX_SCIDS_PER_REPLY {
+					// Move any channels in the same block as this one to the next batch, so that
+					// the current reply can cover through the block of its last channel.
+					let last_batch = batches.last_mut().unwrap();
+					let split_idx = last_batch.iter().position(|batch_scid| block_from_scid(batch_scid) == block_from_scid(scid)).unwrap_or(0);
+					let mut next_batch = if split_idx > 0 { last_batch.split_off(split_idx) } else { Vec::new() };
+					next_batch.reserve(MAX_SCIDS_PER_REPLY - next_batch.len());
+					batches.push(next_batch);
+				}
+				batches.last_mut().unwrap().push(*scid);
+			}
+		}
+
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let batch_count = batches.len();
+		let mut prev_batch_endblock = msg.first_blocknum;
+		for (batch_index, batch) in batches.into_iter().enumerate() {
+			// Each reply starts where the previous one ended (which for the first reply is the
+			// query's first_blocknum). While the spec only requires that first_blocknum never goes
+			// backwards, some implementations require that replies be exactly contiguous.
+			let first_blocknum = prev_batch_endblock;
+
+			// The final reply must cover through the end of the queried range, while other replies
+			// extend through the block containing the last short_channel_id in the batch, with the
+			// next reply starting at the block after it.
+			//
+			// Overflow safe as first_blocknum is always between msg.first_blocknum and the block of
+			// the last short_channel_id in the batch, which is less than msg.end_blocknum().
+			let number_of_blocks = if batch_index == batch_count - 1 {
+				msg.end_blocknum() - first_blocknum
+			} else {
+				block_from_scid(batch.last().unwrap()) + 1 - first_blocknum
+			};
+			prev_batch_endblock = first_blocknum + number_of_blocks;
+
+			pending_events.push(events::MessageSendEvent::SendReplyChannelRange {
+				node_id: their_node_id.clone(),
+				msg: ReplyChannelRange {
+					chain_hash: msg.chain_hash,
+					first_blocknum,
+					number_of_blocks,
+					full_information: true,
+					short_channel_ids: batch,
+				}
+			});
+		}
+
+		Ok(())
 	}
 
-	fn handle_query_short_channel_ids(&self, _their_node_id: &PublicKey, _msg: QueryShortChannelIds) -> Result<(), LightningError> {
-		// TODO
-		Err(LightningError {
-			err: String::from("Not implemented"),
-			action: ErrorAction::IgnoreError,
-		})
+	/// Replies to a query_short_channel_ids by sending, for each short_channel_id we have a full
+	/// announcement for, the channel_announcement, the latest channel_update in each direction and
+	/// the node_announcements of the channel's nodes (each node only once per query), followed by
+	/// a reply_short_channel_ids_end.
+	///
+	/// The replies are only queued here, and are then handed out by
+	/// get_next_query_short_channel_ids_replies as the peer's outbound buffer drains.
+	///
+	/// The number of short_channel_ids each peer may query per hour is limited to roughly the
+	/// size of our graph. Once a peer exceeds its allowance (or queries a chain we don't know
+	/// about) we only send a reply_short_channel_ids_end with full_information unset.
+	fn handle_query_short_channel_ids(&self, their_node_id: &PublicKey, msg: QueryShortChannelIds) -> Result<(), LightningError> {
+		log_debug!(self.logger, "Handling query_short_channel_ids peer={}, batch_size={}", log_pubkey!(their_node_id), msg.short_channel_ids.len());
+
+		let known_chain = msg.chain_hash == self.network_graph.read().unwrap().genesis_hash;
+		let full_information = known_chain && self.use_gossip_query_allowance(their_node_id, 0, msg.short_channel_ids.len());
+		self.pending_short_channel_ids_queries.lock().unwrap().entry(their_node_id.clone()).or_insert_with(VecDeque::new)
+			.push_back(PendingShortChannelIdsQuery {
+				chain_hash: msg.chain_hash,
+				full_information,
+				short_channel_ids: if full_information { msg.short_channel_ids.into_iter().collect() } else { VecDeque::new() },
+				sent_node_announcements: HashSet::new(),
+			});
+
+		if !full_information {
+			return Err(LightningError {
+				err: if known_chain {
+					String::from("Peer exceeded its query_short_channel_ids allowance")
+				} else {
+					String::from("Received query_short_channel_ids for an unknown chain")
+				},
+				action: ErrorAction::IgnoreError,
+			});
+		}
+
+		Ok(())
+	}
+
+	fn get_next_query_short_channel_ids_replies(&self, their_node_id: &PublicKey, batch_amount: u8) -> Vec<events::MessageSendEvent> {
+		let mut replies = Vec::new();
+		let mut pending_queries = self.pending_short_channel_ids_queries.lock().unwrap();
+		let peer_queries = match pending_queries.get_mut(their_node_id) {
+			Some(peer_queries) => peer_queries,
+			None => return replies,
+		};
+		let network_graph = self.network_graph.read().unwrap();
+		while replies.len() < batch_amount as usize {
+			let query = match peer_queries.front_mut() {
+				Some(query) => query,
+				None => break,
+			};
+			let scid = match query.short_channel_ids.pop_front() {
+				Some(scid) => scid,
+				None => {
+					replies.push(events::MessageSendEvent::SendReplyShortChannelIdsEnd {
+						node_id: their_node_id.clone(),
+						msg: ReplyShortChannelIdsEnd {
+							chain_hash: query.chain_hash,
+							full_information: query.full_information,
+						}
+					});
+					peer_queries.pop_front();
+					continue;
+				},
+			};
+			let chan = match network_graph.get_channels().get(&scid) {
+				Some(chan) => chan,
+				None => continue,
+			};
+			let chan_announcement = match chan.announcement_message {
+				Some(ref chan_announcement) => chan_announcement,
+				None => continue,
+			};
+			replies.push(events::MessageSendEvent::SendChannelAnnouncement {
+				node_id: their_node_id.clone(),
+				msg: chan_announcement.clone(),
+			});
+			for directional_info in [&chan.one_to_two, &chan.two_to_one].iter() {
+				if let Some(update_msg) = directional_info.as_ref().and_then(|info| info.last_update_message.as_ref()) {
+					replies.push(events::MessageSendEvent::SendChannelUpdate {
+						node_id: their_node_id.clone(),
+						msg: update_msg.clone(),
+					});
+				}
+			}
+			for node_id in [&chan.node_one, &chan.node_two].iter() {
+				if !query.sent_node_announcements.insert(**node_id) { continue; }
+				let node_announcement = network_graph.get_nodes().get(node_id)
+					.and_then(|node| node.announcement_info.as_ref())
+					.and_then(|info| info.announcement_message.as_ref());
+				if let Some(node_announcement) = node_announcement {
+					replies.push(events::MessageSendEvent::SendNodeAnnouncement {
+						node_id: their_node_id.clone(),
+						msg: node_announcement.clone(),
+					});
+				}
+			}
+		}
+		if peer_queries.is_empty() {
+			pending_queries.remove(their_node_id);
+		}
+		replies
 	}
 }
 
@@ -340,6 +649,14 @@ where
 	}
 }
 
+impl<C: Deref, L: Deref> Router for NetGraphMsgHandler<C, L> where C::Target: chain::Access, L::Target: Logger {
+	fn find_route<S: Score>(&self, payer: &PublicKey, params: &RouteParameters, first_hops: Option<&[&ChannelDetails]>, scorer: &S) -> Result<Route, LightningError> {
+		let last_hops = params.last_hops.iter().collect::<Vec<_>>();
+		get_route_with_constraints(payer, &*self.network_graph.read().unwrap(), &params.payee, first_hops, &last_hops,
+			params.final_value_msat, params.final_cltv, &params.constraints, &*self.logger, scorer)
+	}
+}
+
 #[derive(PartialEq, Debug)]
 /// Details about one direction of a channel. Received
 /// within a channel update.
@@ -638,6 +955,14 @@ impl NetworkGraph {
 	/// (C-not exported) because we have no mapping for `BTreeMap`s
 	pub fn get_nodes<'a>(&'a self) -> &'a BTreeMap<PublicKey, NodeInfo> { &self.nodes }
 
+	/// Gets the timestamp of the most recent channel_update we know of, if any.
+	fn get_latest_channel_update_timestamp(&self) -> Option<u32> {
+		self.channels.values()
+			.flat_map(|chan| chan.one_to_two.iter().chain(chan.two_to_one.iter()))
+			.map(|directional_info| directional_info.last_update)
+			.max()
+	}
+
 	/// Get network addresses by node id.
 	/// Returns None if the requested node is completely unknown,
 	/// or if node announcement for the node was never received.
@@ -1013,7 +1338,7 @@ impl NetworkGraph {
 mod tests {
 	use chain;
 	use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
-	use routing::network_graph::{NetGraphMsgHandler, NetworkGraph};
+	use routing::network_graph::{NetGraphMsgHandler, NetworkGraph, ChannelInfo, GOSSIP_TIMESTAMP_FILTER_MARGIN_SECS};
 	use ln::msgs::{Init, OptionalField, RoutingMessageHandler, UnsignedNodeAnnouncement, NodeAnnouncement,
 		UnsignedChannelAnnouncement, ChannelAnnouncement, UnsignedChannelUpdate, ChannelUpdate, HTLCFailChannelUpdate,
 		ReplyChannelRange, ReplyShortChannelIdsEnd, QueryChannelRange, QueryShortChannelIds, MAX_VALUE_MSAT};
@@ -1021,6 +1346,7 @@ mod tests {
 	use util::logger::Logger;
 	use util::ser::{Readable, Writeable};
 	use util::events::{MessageSendEvent, MessageSendEventsProvider};
+	use util::scid_utils::scid_from_parts;
 
 	use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 	use bitcoin::hashes::Hash;
@@ -1964,13 +2290,23 @@ mod tests {
 			assert_eq!(events.len(), 0);
 		}
 
-		// It should send a query_channel_message with the correct information
+		// It should send a gossip_timestamp_filter and a query_channel_message with the correct
+		// information
 		{
 			let init_msg = Init { features: InitFeatures::known() };
 			net_graph_msg_handler.sync_routing_table(&node_id_1, &init_msg);
 			let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
-			assert_eq!(events.len(), 1);
+			assert_eq!(events.len(), 2);
 			match &events[0] {
+				MessageSendEvent::SendGossipTimestampFilter{ node_id, msg } => {
+					assert_eq!(node_id, &node_id_1);
+					assert_eq!(msg.chain_hash, chain_hash);
+					assert!(msg.first_timestamp > 0);
+					assert_eq!(msg.timestamp_range, 0xffff_ffff);
+				},
+				_ => panic!("Expected MessageSendEvent::SendGossipTimestampFilter")
+			};
+			match &events[1] {
 				MessageSendEvent::SendChannelRangeQuery{ node_id, msg } => {
 					assert_eq!(node_id, &node_id_1);
 					assert_eq!(msg.chain_hash, chain_hash);
@@ -1983,7 +2319,8 @@ mod tests {
 
 		// It should not enqueue a query when should_request_full_sync return false.
 		// The initial implementation allows syncing with the first 5 peers after
-		// which should_request_full_sync will return false
+		// which should_request_full_sync will return false. We still send every
+		// peer a gossip_timestamp_filter.
 		{
 			let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
 			let init_msg = Init { features: InitFeatures::known() };
@@ -1993,15 +2330,45 @@ mod tests {
 				net_graph_msg_handler.sync_routing_table(&node_id, &init_msg);
 				let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
 				if n <= 5 {
-					assert_eq!(events.len(), 1);
+					assert_eq!(events.len(), 2);
 				} else {
-					assert_eq!(events.len(), 0);
+					assert_eq!(events.len(), 1);
 				}
 
 			}
 		}
 	}
 
+	#[test]
+	fn sync_routing_table_requests_missed_updates() {
+		// Once we know of channel_updates, we ask for gossip from a margin before the latest of
+		// them, to learn of updates to known channels made while we were offline.
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let peer_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[43; 32]).unwrap());
+
+		let announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 42);
+		assert!(net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		for &(privkey, flags, timestamp) in [(node_1_privkey, 0, 1_500_000_000), (node_2_privkey, 1, 1_600_000_000)].iter() {
+			let mut update = get_signed_channel_update(&secp_ctx, privkey, 42, flags);
+			update.contents.timestamp = timestamp;
+			let msghash = hash_to_message!(&Sha256dHash::hash(&update.contents.encode()[..])[..]);
+			update.signature = secp_ctx.sign(&msghash, privkey);
+			net_graph_msg_handler.handle_channel_update(&update).unwrap();
+		}
+
+		net_graph_msg_handler.sync_routing_table(&peer_node_id, &Init { features: InitFeatures::known() });
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		match &events[0] {
+			MessageSendEvent::SendGossipTimestampFilter { msg, .. } => {
+				assert_eq!(msg.first_timestamp, 1_600_000_000 - GOSSIP_TIMESTAMP_FILTER_MARGIN_SECS);
+				assert_eq!(msg.timestamp_range, 0xffff_ffff);
+			},
+			_ => panic!("Expected MessageSendEvent::SendGossipTimestampFilter"),
+		}
+	}
+
 	#[test]
 	fn handling_reply_channel_range() {
 		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
@@ -2049,6 +2416,44 @@ mod tests {
 			}
 		}
 
+		// Test that we only query for channels which we don't already have an announcement for,
+		// and don't send a query at all if we know about every channel in the reply
+		{
+			let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+			let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+			let known_scid = 0x0003e8_000000_0000; // 1000x0x0
+			let channel_announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, known_scid);
+			assert!(net_graph_msg_handler.handle_channel_announcement(&channel_announcement).is_ok());
+
+			let result = net_graph_msg_handler.handle_reply_channel_range(&node_id_1, ReplyChannelRange {
+				chain_hash,
+				full_information: true,
+				first_blocknum: 1000,
+				number_of_blocks: 100,
+				short_channel_ids: vec![known_scid, 0x0003e9_000000_0000],
+			});
+			assert!(result.is_ok());
+			let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+			assert_eq!(events.len(), 1);
+			match &events[0] {
+				MessageSendEvent::SendShortIdsQuery { node_id, msg } => {
+					assert_eq!(node_id, &node_id_1);
+					assert_eq!(msg.short_channel_ids, vec![0x0003e9_000000_0000]);
+				},
+				_ => panic!("expected MessageSendEvent::SendShortIdsQuery"),
+			}
+
+			let result = net_graph_msg_handler.handle_reply_channel_range(&node_id_1, ReplyChannelRange {
+				chain_hash,
+				full_information: true,
+				first_blocknum: 1000,
+				number_of_blocks: 1,
+				short_channel_ids: vec![known_scid],
+			});
+			assert!(result.is_ok());
+			assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+		}
+
 		// Test receipt of a reply that indicates the remote node does not maintain up-to-date
 		// information for the chain_hash. Because of discrepancies in implementation we use
 		// full_information=false and short_channel_ids=[] as the signal.
@@ -2095,20 +2500,283 @@ mod tests {
 		}
 	}
 
+	fn get_signed_channel_announcement(secp_ctx: &Secp256k1<All>, node_1_privkey: &SecretKey, node_2_privkey: &SecretKey, short_channel_id: u64) -> ChannelAnnouncement {
+		let node_1_btckey = &SecretKey::from_slice(&[40; 32]).unwrap();
+		let node_2_btckey = &SecretKey::from_slice(&[39; 32]).unwrap();
+		let unsigned_announcement = UnsignedChannelAnnouncement {
+			features: ChannelFeatures::known(),
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			short_channel_id,
+			node_id_1: PublicKey::from_secret_key(&secp_ctx, node_1_privkey),
+			node_id_2: PublicKey::from_secret_key(&secp_ctx, node_2_privkey),
+			bitcoin_key_1: PublicKey::from_secret_key(&secp_ctx, node_1_btckey),
+			bitcoin_key_2: PublicKey::from_secret_key(&secp_ctx, node_2_btckey),
+			excess_data: Vec::new(),
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+		ChannelAnnouncement {
+			node_signature_1: secp_ctx.sign(&msghash, node_1_privkey),
+			node_signature_2: secp_ctx.sign(&msghash, node_2_privkey),
+			bitcoin_signature_1: secp_ctx.sign(&msghash, node_1_btckey),
+			bitcoin_signature_2: secp_ctx.sign(&msghash, node_2_btckey),
+			contents: unsigned_announcement,
+		}
+	}
+
+	fn get_signed_channel_update(secp_ctx: &Secp256k1<All>, node_privkey: &SecretKey, short_channel_id: u64, flags: u8) -> ChannelUpdate {
+		let unsigned_channel_update = UnsignedChannelUpdate {
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			short_channel_id,
+			timestamp: 100,
+			flags,
+			cltv_expiry_delta: 144,
+			htlc_minimum_msat: 1000000,
+			htlc_maximum_msat: OptionalField::Absent,
+			fee_base_msat: 10000,
+			fee_proportional_millionths: 20,
+			excess_data: Vec::new()
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_channel_update.encode()[..])[..]);
+		ChannelUpdate {
+			signature: secp_ctx.sign(&msghash, node_privkey),
+			contents: unsigned_channel_update,
+		}
+	}
+
+	fn get_signed_node_announcement(secp_ctx: &Secp256k1<All>, node_privkey: &SecretKey) -> NodeAnnouncement {
+		let unsigned_announcement = UnsignedNodeAnnouncement {
+			features: NodeFeatures::known(),
+			timestamp: 100,
+			node_id: PublicKey::from_secret_key(&secp_ctx, node_privkey),
+			rgb: [0; 3],
+			alias: [0; 32],
+			addresses: Vec::new(),
+			excess_address_data: Vec::new(),
+			excess_data: Vec::new(),
+		};
+		let msghash = hash_to_message!(&Sha256dHash::hash(&unsigned_announcement.encode()[..])[..]);
+		NodeAnnouncement {
+			signature: secp_ctx.sign(&msghash, node_privkey),
+			contents: unsigned_announcement,
+		}
+	}
+
+	fn expect_reply_channel_ranges(net_graph_msg_handler: &NetGraphMsgHandler<Arc<test_utils::TestChainSource>, Arc<test_utils::TestLogger>>, node_id: &PublicKey, expected_replies: Vec<(u32, u32, bool, Vec<u64>)>) {
+		let events = net_graph_msg_handler.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), expected_replies.len());
+		for (event, (first_blocknum, number_of_blocks, full_information, short_channel_ids)) in events.iter().zip(expected_replies.into_iter()) {
+			match event {
+				MessageSendEvent::SendReplyChannelRange { node_id: ref reply_node_id, ref msg } => {
+					assert_eq!(reply_node_id, node_id);
+					assert_eq!(msg.first_blocknum, first_blocknum);
+					assert_eq!(msg.number_of_blocks, number_of_blocks);
+					assert_eq!(msg.full_information, full_information);
+					assert_eq!(msg.short_channel_ids, short_channel_ids);
+				},
+				_ => panic!("expected MessageSendEvent::SendReplyChannelRange"),
+			}
+		}
+	}
+
 	#[test]
 	fn handling_query_channel_range() {
 		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
 		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
 		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
 
 		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
 
+		let scids = vec![
+			0x0003e0_000000_0000, // 992x0x0
+			0x0003e8_000000_0000, // 1000x0x0
+			0x0003e8_000001_0000, // 1000x1x0
+			0x0003e9_000000_0000, // 1001x0x0
+			0x0003f0_000000_0000, // 1008x0x0
+			0x00044c_000000_0000, // 1100x0x0
+			0xffffff_ffffff_ffff, // 16777215x16777215x65535
+		];
+		for scid in scids.iter() {
+			let announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, *scid);
+			assert!(net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+		}
+
+		// A channel we only know of through an unsigned announcement can't be served
+		{
+			let mut announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 0x0003ea_000000_0000);
+			announcement.contents.excess_data.push(1);
+			net_graph_msg_handler.network_graph.write().unwrap().update_channel_from_unsigned_announcement::<Arc<test_utils::TestChainSource>>(&announcement.contents, &None).unwrap();
+		}
+
+		// Queries for an unknown chain get a single reply with no information
 		let result = net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
-			chain_hash,
+			chain_hash: genesis_block(Network::Bitcoin).header.block_hash(),
 			first_blocknum: 0,
 			number_of_blocks: 0xffff_ffff,
 		});
-		assert!(result.is_err());
+		assert_eq!(result.err().unwrap().err, "Received query_channel_range for an unknown chain");
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(0, 0xffff_ffff, false, vec![])]);
+
+		// Queries with no channels in range get a single empty reply covering the range
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 0,
+			number_of_blocks: 0,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(0, 0, true, vec![])]);
+
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 1200,
+			number_of_blocks: 100,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(1200, 100, true, vec![])]);
+
+		// The range is exclusive of first_blocknum + number_of_blocks
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 1000,
+			number_of_blocks: 100,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![
+			(1000, 100, true, vec![0x0003e8_000000_0000, 0x0003e8_000001_0000, 0x0003e9_000000_0000, 0x0003f0_000000_0000]),
+		]);
+
+		// Queries whose end overflows cover every channel through the maximum block height
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 1100,
+			number_of_blocks: 0xffff_ffff,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![
+			(1100, 0xffff_ffff - 1100, true, vec![0x00044c_000000_0000, 0xffffff_ffffff_ffff]),
+		]);
+
+		// Queries starting beyond the maximum SCID block height can't contain any channels
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 0x0100_0000,
+			number_of_blocks: 100,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(0x0100_0000, 100, true, vec![])]);
+	}
+
+	#[test]
+	fn handling_query_channel_range_multiple_replies() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
+
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+
+		// Add 8000 channels in block 100,000 and 1,000 more channels spread over blocks 100,001
+		// through 101,000, directly reusing a single announcement to avoid signing each one.
+		let announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 0);
+		let mut expected_scids = Vec::new();
+		{
+			let mut network_graph = net_graph_msg_handler.network_graph.write().unwrap();
+			for i in 0..9000 {
+				let scid = if i < 8000 { scid_from_parts(100_000, i, 0) } else { scid_from_parts(100_000 + i - 7999, 0, 0) }.unwrap();
+				network_graph.channels.insert(scid, ChannelInfo {
+					features: ChannelFeatures::known(),
+					node_one: announcement.contents.node_id_1,
+					one_to_two: None,
+					node_two: announcement.contents.node_id_2,
+					two_to_one: None,
+					capacity_sats: None,
+					announcement_message: Some(announcement.clone()),
+				});
+				expected_scids.push(scid);
+			}
+		}
+
+		// The first reply starts at the queried block and covers through the block of its last
+		// channel, the second starts after it and extends to the end of the query.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 90_000,
+			number_of_blocks: 20_000,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![
+			(90_000, 10_001, true, expected_scids[..8000].to_vec()),
+			(100_001, 9_999, true, expected_scids[8000..].to_vec()),
+		]);
+	}
+
+	#[test]
+	fn handling_query_channel_range_keeps_blocks_whole() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
+
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+
+		// Add 7999 channels in block 100,000 and two in block 100,001, such that a full batch would
+		// end in the middle of block 100,001.
+		let announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 0);
+		let mut expected_scids = Vec::new();
+		{
+			let mut network_graph = net_graph_msg_handler.network_graph.write().unwrap();
+			for i in 0..8001 {
+				let scid = if i < 7999 { scid_from_parts(100_000, i, 0) } else { scid_from_parts(100_001, i - 7999, 0) }.unwrap();
+				network_graph.channels.insert(scid, ChannelInfo {
+					features: ChannelFeatures::known(),
+					node_one: announcement.contents.node_id_1,
+					one_to_two: None,
+					node_two: announcement.contents.node_id_2,
+					two_to_one: None,
+					capacity_sats: None,
+					announcement_message: Some(announcement.clone()),
+				});
+				expected_scids.push(scid);
+			}
+		}
+
+		// Both channels in block 100,001 are sent in the second reply.
+		net_graph_msg_handler.handle_query_channel_range(&node_id, QueryChannelRange {
+			chain_hash,
+			first_blocknum: 100_000,
+			number_of_blocks: 10,
+		}).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![
+			(100_000, 1, true, expected_scids[..7999].to_vec()),
+			(100_001, 9, true, expected_scids[7999..].to_vec()),
+		]);
+	}
+
+	#[test]
+	fn query_channel_range_allowance() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let other_node_privkey = &SecretKey::from_slice(&[44; 32]).unwrap();
+		let other_node_id = PublicKey::from_secret_key(&secp_ctx, other_node_privkey);
+
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+		let query = QueryChannelRange {
+			chain_hash,
+			first_blocknum: 0,
+			number_of_blocks: 0xffff_ffff,
+		};
+
+		for _ in 0..16 {
+			net_graph_msg_handler.handle_query_channel_range(&node_id, query.clone()).unwrap();
+			expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(0, 0xffff_ffff, true, vec![])]);
+		}
+
+		// Once a peer has exhausted its allowance it only gets an empty reply...
+		let result = net_graph_msg_handler.handle_query_channel_range(&node_id, query.clone());
+		assert_eq!(result.err().unwrap().err, "Peer exceeded its query_channel_range allowance");
+		expect_reply_channel_ranges(&net_graph_msg_handler, &node_id, vec![(0, 0xffff_ffff, false, vec![])]);
+
+		// ...but other peers are unaffected.
+		net_graph_msg_handler.handle_query_channel_range(&other_node_id, query).unwrap();
+		expect_reply_channel_ranges(&net_graph_msg_handler, &other_node_id, vec![(0, 0xffff_ffff, true, vec![])]);
 	}
 
 	#[test]
@@ -2116,13 +2784,164 @@ mod tests {
 		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
 		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
 		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
+		let node_3_privkey = &SecretKey::from_slice(&[44; 32]).unwrap();
 
 		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
 
+		// Channel 1 is fully announced with updates in both directions and node announcements for
+		// both nodes, while channel 2 shares node 1 with channel 1 and has a single update.
+		let chan_1_announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 0x0003e8_000000_0000);
+		let chan_1_update_1 = get_signed_channel_update(&secp_ctx, node_1_privkey, 0x0003e8_000000_0000, 0);
+		let chan_1_update_2 = get_signed_channel_update(&secp_ctx, node_2_privkey, 0x0003e8_000000_0000, 1);
+		let chan_2_announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_3_privkey, 0x0003e9_000000_0000);
+		let chan_2_update_1 = get_signed_channel_update(&secp_ctx, node_1_privkey, 0x0003e9_000000_0000, 0);
+		let node_1_announcement = get_signed_node_announcement(&secp_ctx, node_1_privkey);
+		let node_2_announcement = get_signed_node_announcement(&secp_ctx, node_2_privkey);
+		assert!(net_graph_msg_handler.handle_channel_announcement(&chan_1_announcement).unwrap());
+		assert!(net_graph_msg_handler.handle_channel_update(&chan_1_update_1).unwrap());
+		assert!(net_graph_msg_handler.handle_channel_update(&chan_1_update_2).unwrap());
+		assert!(net_graph_msg_handler.handle_channel_announcement(&chan_2_announcement).unwrap());
+		assert!(net_graph_msg_handler.handle_channel_update(&chan_2_update_1).unwrap());
+		assert!(net_graph_msg_handler.handle_node_announcement(&node_1_announcement).unwrap());
+		assert!(net_graph_msg_handler.handle_node_announcement(&node_2_announcement).unwrap());
+
+		// Queries for an unknown chain only get a reply_short_channel_ids_end with no information
 		let result = net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash: genesis_block(Network::Bitcoin).header.block_hash(),
+			short_channel_ids: vec![0x0003e8_000000_0000],
+		});
+		assert_eq!(result.err().unwrap().err, "Received query_short_channel_ids for an unknown chain");
+		assert!(net_graph_msg_handler.get_and_clear_pending_msg_events().is_empty());
+		let events = net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255);
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			MessageSendEvent::SendReplyShortChannelIdsEnd { node_id: reply_node_id, msg } => {
+				assert_eq!(reply_node_id, &node_id);
+				assert!(!msg.full_information);
+			},
+			_ => panic!("expected MessageSendEvent::SendReplyShortChannelIdsEnd"),
+		}
+
+		// Unknown short_channel_ids are skipped and each node_announcement is sent only once
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![0x0003e8_000000_0000, 0x0003ea_000000_0000, 0x0003e9_000000_0000],
+		}).unwrap();
+		let events = net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255);
+		assert_eq!(events.len(), 8);
+		for event in events.iter() {
+			match event {
+				MessageSendEvent::SendChannelAnnouncement { node_id: reply_node_id, .. } |
+				MessageSendEvent::SendChannelUpdate { node_id: reply_node_id, .. } |
+				MessageSendEvent::SendNodeAnnouncement { node_id: reply_node_id, .. } |
+				MessageSendEvent::SendReplyShortChannelIdsEnd { node_id: reply_node_id, .. } => assert_eq!(reply_node_id, &node_id),
+				_ => panic!("Unexpected event"),
+			}
+		}
+		match &events[0] {
+			MessageSendEvent::SendChannelAnnouncement { msg, .. } => assert_eq!(msg.contents.short_channel_id, 0x0003e8_000000_0000),
+			_ => panic!("expected MessageSendEvent::SendChannelAnnouncement"),
+		}
+		match &events[1] {
+			MessageSendEvent::SendChannelUpdate { msg, .. } => assert_eq!(msg.contents.flags, 0),
+			_ => panic!("expected MessageSendEvent::SendChannelUpdate"),
+		}
+		match &events[2] {
+			MessageSendEvent::SendChannelUpdate { msg, .. } => assert_eq!(msg.contents.flags, 1),
+			_ => panic!("expected MessageSendEvent::SendChannelUpdate"),
+		}
+		match &events[3] {
+			MessageSendEvent::SendNodeAnnouncement { msg, .. } => assert_eq!(msg.contents.node_id, node_1_announcement.contents.node_id),
+			_ => panic!("expected MessageSendEvent::SendNodeAnnouncement"),
+		}
+		match &events[4] {
+			MessageSendEvent::SendNodeAnnouncement { msg, .. } => assert_eq!(msg.contents.node_id, node_2_announcement.contents.node_id),
+			_ => panic!("expected MessageSendEvent::SendNodeAnnouncement"),
+		}
+		match &events[5] {
+			MessageSendEvent::SendChannelAnnouncement { msg, .. } => assert_eq!(msg.contents.short_channel_id, 0x0003e9_000000_0000),
+			_ => panic!("expected MessageSendEvent::SendChannelAnnouncement"),
+		}
+		match &events[6] {
+			MessageSendEvent::SendChannelUpdate { msg, .. } => assert_eq!(msg.contents.short_channel_id, 0x0003e9_000000_0000),
+			_ => panic!("expected MessageSendEvent::SendChannelUpdate"),
+		}
+		match &events[7] {
+			MessageSendEvent::SendReplyShortChannelIdsEnd { msg, .. } => {
+				assert_eq!(msg.chain_hash, chain_hash);
+				assert!(msg.full_information);
+			},
+			_ => panic!("expected MessageSendEvent::SendReplyShortChannelIdsEnd"),
+		}
+		assert!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255).is_empty());
+
+		// Replies are handed out in batches of whole channels, as the peer's outbound buffer drains
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![0x0003e8_000000_0000, 0x0003ea_000000_0000, 0x0003e9_000000_0000],
+		}).unwrap();
+		assert_eq!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 1).len(), 5);
+		assert_eq!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 1).len(), 2);
+		let events = net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 1);
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			MessageSendEvent::SendReplyShortChannelIdsEnd { msg, .. } => assert!(msg.full_information),
+			_ => panic!("expected MessageSendEvent::SendReplyShortChannelIdsEnd"),
+		}
+		assert!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 1).is_empty());
+
+		// Replies still pending when the peer reconnects are dropped
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
 			chain_hash,
 			short_channel_ids: vec![0x0003e8_000000_0000],
+		}).unwrap();
+		net_graph_msg_handler.sync_routing_table(&node_id, &Init { features: InitFeatures::empty() });
+		assert!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255).is_empty());
+	}
+
+	#[test]
+	fn query_short_channel_ids_allowance() {
+		let (secp_ctx, net_graph_msg_handler) = create_net_graph_msg_handler();
+		let node_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let node_id = PublicKey::from_secret_key(&secp_ctx, node_privkey);
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
+
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+		let announcement = get_signed_channel_announcement(&secp_ctx, node_1_privkey, node_2_privkey, 0x0003e8_000000_0000);
+		assert!(net_graph_msg_handler.handle_channel_announcement(&announcement).unwrap());
+
+		// With a single channel in our graph, each peer may query 8001 short_channel_ids per period
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![0x0003e8_000000_0000; 8000],
+		}).unwrap();
+		let mut reply_count = 0;
+		loop {
+			let replies = net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255);
+			if replies.is_empty() { break; }
+			reply_count += replies.len();
+		}
+		assert_eq!(reply_count, 8001);
+
+		let result = net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![0x0003e8_000000_0000, 0x0003e8_000000_0000],
 		});
-		assert!(result.is_err());
+		assert_eq!(result.err().unwrap().err, "Peer exceeded its query_short_channel_ids allowance");
+		let events = net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255);
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			MessageSendEvent::SendReplyShortChannelIdsEnd { msg, .. } => assert!(!msg.full_information),
+			_ => panic!("expected MessageSendEvent::SendReplyShortChannelIdsEnd"),
+		}
+
+		net_graph_msg_handler.handle_query_short_channel_ids(&node_id, QueryShortChannelIds {
+			chain_hash,
+			short_channel_ids: vec![0x0003e8_000000_0000],
+		}).unwrap();
+		assert_eq!(net_graph_msg_handler.get_next_query_short_channel_ids_replies(&node_id, 255).len(), 2);
 	}
 }
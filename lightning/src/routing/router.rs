@@ -18,15 +18,16 @@ use ln::channelmanager::ChannelDetails;
 use ln::features::{ChannelFeatures, NodeFeatures};
 use ln::msgs::{DecodeError, ErrorAction, LightningError, MAX_VALUE_MSAT};
 use routing::network_graph::{NetworkGraph, RoutingFees};
+use routing::scorer::Score;
 use util::ser::{Writeable, Readable};
 use util::logger::Logger;
 
 use std::cmp;
-use std::collections::{HashMap,BinaryHeap};
+use std::collections::{HashMap,HashSet,BinaryHeap};
 use std::ops::Deref;
 
 /// A hop in a route
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RouteHop {
 	/// The node_id of the node at this hop.
 	pub pubkey: PublicKey,
@@ -125,6 +126,58 @@ pub struct RouteHint {
 	pub cltv_expiry_delta: u16,
 	/// The minimum value, in msat, which must be relayed to the next hop.
 	pub htlc_minimum_msat: u64,
+	/// The maximum value, in msat, which may be relayed to the next hop, if any.
+	pub htlc_maximum_msat: Option<u64>,
+}
+
+/// The maximum number of hops in a path which fit in an onion packet.
+pub const MAX_PATH_LENGTH: u8 = 20;
+
+/// Constraints which every path of a route returned by get_route_with_constraints must satisfy.
+///
+/// The default places no constraints beyond limiting paths to MAX_PATH_LENGTH hops.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteConstraints {
+	/// The maximum total fee, in msat, paid across all paths of the route.
+	pub max_total_fee_msat: Option<u64>,
+	/// The maximum total fee paid across all paths of the route, in millionths of the value sent
+	/// to the recipient. If max_total_fee_msat is also set, the lower of the two applies.
+	pub max_fee_proportional_millionths: Option<u32>,
+	/// The maximum total CLTV expiry delta of any path, including the final CLTV expiry delta, ie
+	/// the maximum number of blocks for which our funds may be locked up in an HTLC.
+	pub max_total_cltv_expiry_delta: u32,
+	/// The maximum number of hops in any path. Values above MAX_PATH_LENGTH are ignored.
+	pub max_path_length: u8,
+	/// Nodes which no path may go through. Routing to a node in this set fails.
+	pub avoid_nodes: HashSet<PublicKey>,
+	/// Channels, by short_channel_id, which no path may use.
+	pub avoid_channels: HashSet<u64>,
+}
+
+impl Default for RouteConstraints {
+	fn default() -> Self {
+		RouteConstraints {
+			max_total_fee_msat: None,
+			max_fee_proportional_millionths: None,
+			max_total_cltv_expiry_delta: u32::max_value(),
+			max_path_length: MAX_PATH_LENGTH,
+			avoid_nodes: HashSet::new(),
+			avoid_channels: HashSet::new(),
+		}
+	}
+}
+
+impl RouteConstraints {
+	/// Gets the maximum total fee, in msat, for a route delivering value_msat.
+	pub(crate) fn max_fee_msat(&self, value_msat: u64) -> u64 {
+		let proportional_max_fee_msat = self.max_fee_proportional_millionths
+			.map(|millionths| match value_msat.checked_mul(millionths as u64) {
+				Some(part) => part / 1_000_000,
+				None => value_msat / 1_000_000 * millionths as u64,
+			})
+			.unwrap_or(u64::max_value());
+		cmp::min(self.max_total_fee_msat.unwrap_or(u64::max_value()), proportional_max_fee_msat)
+	}
 }
 
 #[derive(Eq, PartialEq)]
@@ -132,6 +185,12 @@ struct RouteGraphNode {
 	pubkey: PublicKey,
 	lowest_fee_to_peer_through_node: u64,
 	lowest_fee_to_node: u64,
+	/// The sum of the channel penalties, as given by the Score, along the path to the target.
+	path_penalty_msat: u64,
+	/// The total CLTV expiry delta, including the final one, along the path to the target.
+	path_cltv_expiry_delta: u32,
+	/// The number of hops along the path to the target.
+	path_length: u8,
 }
 
 impl cmp::Ord for RouteGraphNode {
@@ -153,6 +212,48 @@ struct DummyDirectionalChannelInfo {
 	fees: RoutingFees,
 }
 
+/// The maximum number of paths we will split a single payment across.
+const MAX_PATH_COUNT: usize = 10;
+
+/// The channel used to reach a hop in a path we're building, along with the constraints on how
+/// much value may be sent over it.
+#[derive(Clone)]
+struct HopLiquidity {
+	short_channel_id: u64,
+	src_node_id: PublicKey,
+	/// The value, in msat, which may still be sent over this channel, after deducting anything
+	/// used by paths we've already selected.
+	available_liquidity_msat: u64,
+	htlc_minimum_msat: u64,
+	/// The fees charged by src_node_id to forward over this channel.
+	fees: RoutingFees,
+}
+
+/// Calculates the value, in msat, which must be sent over each channel in path in order to deliver
+/// value_msat to the destination, including the fees charged by each hop along the way. The fees
+/// on the first channel (which is ours) are ignored.
+///
+/// Returns the index of the first channel (from the destination backwards) which cannot carry the
+/// required value, either because it lacks the liquidity or because its fees overflow, on failure.
+fn get_path_amounts_msat(path: &[HopLiquidity], value_msat: u64) -> Result<Vec<u64>, usize> {
+	let mut amounts_msat = vec![0; path.len()];
+	let mut amount_msat = value_msat;
+	for (idx, hop) in path.iter().enumerate().rev() {
+		if amount_msat > hop.available_liquidity_msat {
+			return Err(idx);
+		}
+		amounts_msat[idx] = amount_msat;
+		if idx == 0 { break; }
+		// The previous hop charges fees for forwarding over this channel
+		amount_msat = match amount_msat.checked_mul(hop.fees.proportional_millionths as u64)
+			.and_then(|part| (hop.fees.base_msat as u64).checked_add(part / 1000000))
+			.and_then(|fee| amount_msat.checked_add(fee)) {
+				Some(amount) => amount,
+				None => return Err(idx - 1),
+			};
+	}
+	Ok(amounts_msat)
+}
 
 /// Gets a route from us to the given target node.
 ///
@@ -162,22 +263,54 @@ struct DummyDirectionalChannelInfo {
 /// If some channels aren't announced, it may be useful to fill in a first_hops with the
 /// results from a local ChannelManager::list_usable_channels() call. If it is filled in, our
 /// view of our local channels (from net_graph_msg_handler) will be ignored, and only those in first_hops
-/// will be used.
+/// will be used, limited to their outbound_capacity_msat.
+///
+/// If the target supports basic_mpp (per its node_announcement, or its Init features if it is a
+/// first hop) and no single path can carry final_value_msat, the payment is split across several
+/// paths. The value sent over each channel is limited by its capacity and htlc_maximum_msat, if
+/// known, including across paths.
 ///
-/// Panics if first_hops contains channels without short_channel_ids
-/// (ChannelManager::list_usable_channels will never include such channels).
+/// Panics if first_hops contains channels without short_channel_ids or scid_aliases
+/// (ChannelManager::list_usable_channels will never include such channels). Channels whose
+/// funding transaction hasn't confirmed yet (i.e. zero-conf channels) are used through their
+/// scid_alias.
 ///
 /// The fees on channels from us to next-hops are ignored (as they are assumed to all be
 /// equal), however the enabled/disabled bit on such channels as well as the htlc_minimum_msat
 /// *is* checked as they may change based on the receiving node.
-pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[&ChannelDetails]>,
-	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, logger: L) -> Result<Route, LightningError> where L::Target: Logger {
-	// TODO: Obviously *only* using total fee cost sucks. We should consider weighting by
-	// uptime/success in using a node in the past.
+///
+/// Paths are selected by their total fee plus the channel penalties given by scorer, allowing
+/// channels which have failed payments in the past (see routing::scorer::Scorer) to be avoided.
+/// Penalties are only used to compare paths and are not added to the fees paid.
+///
+/// Paths are limited to MAX_PATH_LENGTH hops, see get_route_with_constraints to constrain the
+/// route further.
+///
+/// Routes to ourselves are rejected, see get_rebalance_route for circular routes.
+pub fn get_route<L: Deref, S: Score>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[&ChannelDetails]>,
+	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	get_route_with_constraints(our_node_id, network, target, first_hops, last_hops, final_value_msat, final_cltv, &RouteConstraints::default(), logger, scorer)
+}
+
+/// Gets a route from us to the given target node, as get_route does, whose paths all satisfy the
+/// given constraints.
+///
+/// Candidate paths which would break a constraint are discarded during the search. If no route
+/// can be found as a result, the error names the constraints which discarded candidates.
+pub fn get_route_with_constraints<L: Deref, S: Score>(our_node_id: &PublicKey, network: &NetworkGraph, target: &PublicKey, first_hops: Option<&[&ChannelDetails]>,
+	last_hops: &[&RouteHint], final_value_msat: u64, final_cltv: u32, constraints: &RouteConstraints, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
 	if *target == *our_node_id {
 		return Err(LightningError{err: "Cannot generate a route to ourselves".to_owned(), action: ErrorAction::IgnoreError});
 	}
 
+	if constraints.avoid_nodes.contains(target) {
+		return Err(LightningError{err: "Cannot generate a route to a node we were asked to avoid".to_owned(), action: ErrorAction::IgnoreError});
+	}
+
+	if final_cltv > constraints.max_total_cltv_expiry_delta {
+		return Err(LightningError{err: "Cannot generate a route with a final CLTV expiry delta above the maximum total CLTV expiry delta".to_owned(), action: ErrorAction::IgnoreError});
+	}
+
 	if final_value_msat > MAX_VALUE_MSAT {
 		return Err(LightningError{err: "Cannot generate a route of more value than all existing satoshis".to_owned(), action: ErrorAction::IgnoreError});
 	}
@@ -187,6 +320,10 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 	// TODO: There are a few tweaks we could do, including possibly pre-calculating more stuff
 	// to use as the A* heuristic beyond just the cost to get one node further than the current
 	// one.
+	//
+	// If the path we find can't carry the full value, we either send what it can carry over it
+	// and search again for the remainder (if the target supports MPP), or exclude its bottleneck
+	// channel and search again.
 
 	let dummy_directional_info = DummyDirectionalChannelInfo { // used for first_hops routes
 		cltv_expiry_delta: 0,
@@ -197,226 +334,487 @@ pub fn get_route<L: Deref>(our_node_id: &PublicKey, network: &NetworkGraph, targ
 		}
 	};
 
-	let mut targets = BinaryHeap::new(); //TODO: Do we care about switching to eg Fibbonaci heap?
-	let mut dist = HashMap::with_capacity(network.get_nodes().len());
+	let mut allow_mpp = match network.get_nodes().get(target).and_then(|node| node.announcement_info.as_ref()) {
+		Some(node_info) => node_info.features.supports_basic_mpp(),
+		None => false,
+	};
 
 	let mut first_hop_targets = HashMap::with_capacity(if first_hops.is_some() { first_hops.as_ref().unwrap().len() } else { 0 });
 	if let Some(hops) = first_hops {
 		for chan in hops {
-			let short_channel_id = chan.short_channel_id.expect("first_hops should be filled in with usable channels, not pending ones");
+			let short_channel_id = chan.get_outbound_payment_scid().expect("first_hops should be filled in with usable channels, not pending ones");
 			if chan.remote_network_id == *target {
-				return Ok(Route {
-					paths: vec![vec![RouteHop {
-						pubkey: chan.remote_network_id,
-						node_features: chan.counterparty_features.to_context(),
-						short_channel_id,
-						channel_features: chan.counterparty_features.to_context(),
-						fee_msat: final_value_msat,
-						cltv_expiry_delta: final_cltv,
-					}]],
-				});
+				if chan.outbound_capacity_msat >= final_value_msat && !constraints.avoid_channels.contains(&short_channel_id) &&
+						scorer.channel_penalty_msat(short_channel_id, final_value_msat, our_node_id, target) != u64::max_value() {
+					return Ok(Route {
+						paths: vec![vec![RouteHop {
+							pubkey: chan.remote_network_id,
+							node_features: chan.counterparty_features.to_context(),
+							short_channel_id,
+							channel_features: chan.counterparty_features.to_context(),
+							fee_msat: final_value_msat,
+							cltv_expiry_delta: final_cltv,
+						}]],
+					});
+				}
+				allow_mpp |= chan.counterparty_features.supports_basic_mpp();
 			}
-			first_hop_targets.insert(chan.remote_network_id, (short_channel_id, chan.counterparty_features.clone()));
+			first_hop_targets.entry(chan.remote_network_id).or_insert_with(Vec::new)
+				.push((short_channel_id, chan.counterparty_features.clone(), chan.outbound_capacity_msat));
 		}
 		if first_hop_targets.is_empty() {
 			return Err(LightningError{err: "Cannot route when there are no outbound routes away from us".to_owned(), action: ErrorAction::IgnoreError});
 		}
 	}
 
-	macro_rules! add_entry {
-		// Adds entry which goes from $src_node_id to $dest_node_id
-		// over the channel with id $chan_id with fees described in
-		// $directional_info.
-		( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr ) => {
-			//TODO: Explore simply adding fee to hit htlc_minimum_msat
-			if $starting_fee_msat as u64 + final_value_msat >= $directional_info.htlc_minimum_msat {
-				let proportional_fee_millions = ($starting_fee_msat + final_value_msat).checked_mul($directional_info.fees.proportional_millionths as u64);
-				if let Some(new_fee) = proportional_fee_millions.and_then(|part| {
-						($directional_info.fees.base_msat as u64).checked_add(part / 1000000) })
-				{
-					let mut total_fee = $starting_fee_msat as u64;
-					let hm_entry = dist.entry(&$src_node_id);
-					let old_entry = hm_entry.or_insert_with(|| {
-						let mut fee_base_msat = u32::max_value();
-						let mut fee_proportional_millionths = u32::max_value();
-						if let Some(fees) = network.get_nodes().get(&$src_node_id).and_then(|node| node.lowest_inbound_channel_fees) {
-							fee_base_msat = fees.base_msat;
-							fee_proportional_millionths = fees.proportional_millionths;
+	// The value, in msat, already sent over each channel (in the direction from the given node)
+	// by the paths we've selected so far. Channels we've decided not to use are set to
+	// u64::max_value().
+	let mut used_liquidity_msat: HashMap<(u64, PublicKey), u64> = HashMap::new();
+	let mut paths = Vec::new();
+	let mut value_to_find_msat = final_value_msat;
+
+	let max_path_length = cmp::min(constraints.max_path_length, MAX_PATH_LENGTH);
+	let mut fee_budget_msat = constraints.max_fee_msat(final_value_msat);
+	// Which constraints discarded candidate paths, to explain a failure to find a route.
+	let mut hit_fee_limit = false;
+	let mut hit_cltv_limit = false;
+	let mut hit_length_limit = false;
+	let mut hit_exclusions = false;
+
+	'paths_search: while value_to_find_msat > 0 {
+		let mut targets = BinaryHeap::new(); //TODO: Do we care about switching to eg Fibbonaci heap?
+		let mut dist = HashMap::with_capacity(network.get_nodes().len());
+
+		macro_rules! add_entry {
+			// Adds entry which goes from $src_node_id to $dest_node_id
+			// over the channel with id $chan_id with fees described in
+			// $directional_info, which may carry at most $liquidity_msat in total.
+			// $path_penalty_msat, $path_cltv_expiry_delta and $path_length are the sum of the
+			// channel penalties, the total CLTV expiry delta and the number of hops from
+			// $dest_node_id to the target.
+			( $chan_id: expr, $src_node_id: expr, $dest_node_id: expr, $directional_info: expr, $chan_features: expr, $starting_fee_msat: expr, $path_penalty_msat: expr, $path_cltv_expiry_delta: expr, $path_length: expr, $liquidity_msat: expr ) => {
+				let short_channel_id: u64 = $chan_id.clone();
+				// The CLTV expiry delta of our own channel is never used
+				let path_cltv_expiry_delta = if $src_node_id != *our_node_id {
+					($path_cltv_expiry_delta as u32).saturating_add($directional_info.cltv_expiry_delta as u32)
+				} else { $path_cltv_expiry_delta as u32 };
+				let path_length = ($path_length as u8).saturating_add(1);
+				let excluded = constraints.avoid_channels.contains(&short_channel_id) || constraints.avoid_nodes.contains(&$src_node_id);
+				hit_exclusions |= excluded;
+				hit_cltv_limit |= path_cltv_expiry_delta > constraints.max_total_cltv_expiry_delta;
+				hit_length_limit |= path_length > max_path_length;
+				let available_liquidity_msat = match used_liquidity_msat.get(&(short_channel_id, $src_node_id)) {
+					Some(used_msat) => ($liquidity_msat as u64).saturating_sub(*used_msat),
+					None => $liquidity_msat as u64,
+				};
+				let channel_penalty_msat = scorer.channel_penalty_msat(short_channel_id,
+					$starting_fee_msat as u64 + value_to_find_msat, &$src_node_id, &$dest_node_id);
+				//TODO: Explore simply adding fee to hit htlc_minimum_msat
+				if $starting_fee_msat as u64 + value_to_find_msat >= $directional_info.htlc_minimum_msat &&
+						available_liquidity_msat >= cmp::max($directional_info.htlc_minimum_msat, 1) &&
+						channel_penalty_msat != u64::max_value() && !excluded &&
+						path_cltv_expiry_delta <= constraints.max_total_cltv_expiry_delta && path_length <= max_path_length {
+					let proportional_fee_millions = ($starting_fee_msat + value_to_find_msat).checked_mul($directional_info.fees.proportional_millionths as u64);
+					let new_fee = proportional_fee_millions.and_then(|part| {
+						($directional_info.fees.base_msat as u64).checked_add(part / 1000000) });
+					// The fees we'd pay if this was the path to the target, which only grow as
+					// hops are added.
+					let path_fee_msat = if $src_node_id != *our_node_id {
+						new_fee.and_then(|fee| fee.checked_add($starting_fee_msat as u64))
+					} else { Some($starting_fee_msat as u64) };
+					if path_fee_msat.map(|fee| fee > fee_budget_msat).unwrap_or(false) {
+						hit_fee_limit = true;
+					} else if let Some(new_fee) = new_fee {
+						let mut total_fee = $starting_fee_msat as u64;
+						let path_penalty_msat = ($path_penalty_msat as u64).saturating_add(channel_penalty_msat);
+						let hm_entry = dist.entry(&$src_node_id);
+						let old_entry = hm_entry.or_insert_with(|| {
+							let mut fee_base_msat = u32::max_value();
+							let mut fee_proportional_millionths = u32::max_value();
+							if let Some(fees) = network.get_nodes().get(&$src_node_id).and_then(|node| node.lowest_inbound_channel_fees) {
+								fee_base_msat = fees.base_msat;
+								fee_proportional_millionths = fees.proportional_millionths;
+							}
+							(u64::max_value(),
+								fee_base_msat,
+								fee_proportional_millionths,
+								RouteHop {
+									pubkey: $dest_node_id.clone(),
+									node_features: NodeFeatures::empty(),
+									short_channel_id: 0,
+									channel_features: $chan_features.clone(),
+									fee_msat: 0,
+									cltv_expiry_delta: 0,
+								},
+								HopLiquidity {
+									short_channel_id: 0,
+									src_node_id: $src_node_id,
+									available_liquidity_msat: 0,
+									htlc_minimum_msat: 0,
+									fees: $directional_info.fees,
+								},
+							)
+						});
+						if $src_node_id != *our_node_id {
+							// Ignore new_fee for channel-from-us as we assume all channels-from-us
+							// will have the same effective-fee
+							total_fee += new_fee;
+							if let Some(fee_inc) = value_to_find_msat.checked_add(total_fee).and_then(|inc| { (old_entry.2 as u64).checked_mul(inc) }) {
+								total_fee += fee_inc / 1000000 + (old_entry.1 as u64);
+							} else {
+								// max_value means we'll always fail the old_entry.0 > total_fee check
+								total_fee = u64::max_value();
+							}
 						}
-						(u64::max_value(),
-							fee_base_msat,
-							fee_proportional_millionths,
-							RouteHop {
+						// Paths are compared by their fees plus the penalties of their channels.
+						let total_cost = total_fee.saturating_add(path_penalty_msat);
+						let new_graph_node = RouteGraphNode {
+							pubkey: $src_node_id,
+							lowest_fee_to_peer_through_node: total_cost,
+							lowest_fee_to_node: $starting_fee_msat as u64 + new_fee,
+							path_penalty_msat,
+							path_cltv_expiry_delta,
+							path_length,
+						};
+						if old_entry.0 > total_cost {
+							targets.push(new_graph_node);
+							old_entry.0 = total_cost;
+							old_entry.3 = RouteHop {
 								pubkey: $dest_node_id.clone(),
 								node_features: NodeFeatures::empty(),
-								short_channel_id: 0,
+								short_channel_id,
 								channel_features: $chan_features.clone(),
-								fee_msat: 0,
-								cltv_expiry_delta: 0,
-							},
-						)
-					});
-					if $src_node_id != *our_node_id {
-						// Ignore new_fee for channel-from-us as we assume all channels-from-us
-						// will have the same effective-fee
-						total_fee += new_fee;
-						if let Some(fee_inc) = final_value_msat.checked_add(total_fee).and_then(|inc| { (old_entry.2 as u64).checked_mul(inc) }) {
-							total_fee += fee_inc / 1000000 + (old_entry.1 as u64);
-						} else {
-							// max_value means we'll always fail the old_entry.0 > total_fee check
-							total_fee = u64::max_value();
+								fee_msat: new_fee, // This field is ignored on the last-hop anyway
+								cltv_expiry_delta: $directional_info.cltv_expiry_delta as u32,
+							};
+							old_entry.4 = HopLiquidity {
+								short_channel_id,
+								src_node_id: $src_node_id,
+								available_liquidity_msat,
+								htlc_minimum_msat: $directional_info.htlc_minimum_msat,
+								fees: $directional_info.fees,
+							};
 						}
 					}
-					let new_graph_node = RouteGraphNode {
-						pubkey: $src_node_id,
-						lowest_fee_to_peer_through_node: total_fee,
-						lowest_fee_to_node: $starting_fee_msat as u64 + new_fee,
-					};
-					if old_entry.0 > total_fee {
-						targets.push(new_graph_node);
-						old_entry.0 = total_fee;
-						old_entry.3 = RouteHop {
-							pubkey: $dest_node_id.clone(),
-							node_features: NodeFeatures::empty(),
-							short_channel_id: $chan_id.clone(),
-							channel_features: $chan_features.clone(),
-							fee_msat: new_fee, // This field is ignored on the last-hop anyway
-							cltv_expiry_delta: $directional_info.cltv_expiry_delta as u32,
-						}
+				}
+			};
+		}
+
+		macro_rules! add_first_hop_entries {
+			( $node_id: expr, $fee_to_target_msat: expr, $path_penalty_msat: expr, $path_cltv_expiry_delta: expr, $path_length: expr ) => {
+				if let Some(first_hop_chans) = first_hop_targets.get(&$node_id) {
+					for &(ref first_hop, ref features, outbound_capacity_msat) in first_hop_chans.iter() {
+						add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat, $path_penalty_msat, $path_cltv_expiry_delta, $path_length, outbound_capacity_msat);
 					}
 				}
-			}
-		};
-	}
+			};
+		}
 
-	macro_rules! add_entries_to_cheapest_to_target_node {
-		( $node: expr, $node_id: expr, $fee_to_target_msat: expr ) => {
-			if first_hops.is_some() {
-				if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&$node_id) {
-					add_entry!(first_hop, *our_node_id, $node_id, dummy_directional_info, features.to_context(), $fee_to_target_msat);
+		macro_rules! add_entries_to_cheapest_to_target_node {
+			( $node: expr, $node_id: expr, $fee_to_target_msat: expr, $path_penalty_msat: expr, $path_cltv_expiry_delta: expr, $path_length: expr ) => {
+				if first_hops.is_some() {
+					add_first_hop_entries!(*$node_id, $fee_to_target_msat, $path_penalty_msat, $path_cltv_expiry_delta, $path_length);
 				}
-			}
 
-			let features;
-			if let Some(node_info) = $node.announcement_info.as_ref() {
-				features = node_info.features.clone();
-			} else {
-				features = NodeFeatures::empty();
-			}
+				let features;
+				if let Some(node_info) = $node.announcement_info.as_ref() {
+					features = node_info.features.clone();
+				} else {
+					features = NodeFeatures::empty();
+				}
 
-			if !features.requires_unknown_bits() {
-				for chan_id in $node.channels.iter() {
-					let chan = network.get_channels().get(chan_id).unwrap();
-					if !chan.features.requires_unknown_bits() {
-						if chan.node_one == *$node_id {
-							// ie $node is one, ie next hop in A* is two, via the two_to_one channel
-							if first_hops.is_none() || chan.node_two != *our_node_id {
-								if let Some(two_to_one) = chan.two_to_one.as_ref() {
-									if two_to_one.enabled {
-										add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat);
+				if !features.requires_unknown_bits() {
+					for chan_id in $node.channels.iter() {
+						let chan = network.get_channels().get(chan_id).unwrap();
+						if !chan.features.requires_unknown_bits() {
+							let mut chan_liquidity_msat = match chan.capacity_sats {
+								Some(capacity_sats) => cmp::min(capacity_sats.saturating_mul(1000), MAX_VALUE_MSAT),
+								None => MAX_VALUE_MSAT,
+							};
+							if chan.node_one == *$node_id {
+								// ie $node is one, ie next hop in A* is two, via the two_to_one channel
+								if first_hops.is_none() || chan.node_two != *our_node_id {
+									if let Some(two_to_one) = chan.two_to_one.as_ref() {
+										if two_to_one.enabled {
+											if let Some(htlc_maximum_msat) = two_to_one.htlc_maximum_msat {
+												chan_liquidity_msat = cmp::min(chan_liquidity_msat, htlc_maximum_msat);
+											}
+											add_entry!(chan_id, chan.node_two, chan.node_one, two_to_one, chan.features, $fee_to_target_msat, $path_penalty_msat, $path_cltv_expiry_delta, $path_length, chan_liquidity_msat);
+										}
 									}
 								}
-							}
-						} else {
-							if first_hops.is_none() || chan.node_one != *our_node_id {
-								if let Some(one_to_two) = chan.one_to_two.as_ref() {
-									if one_to_two.enabled {
-										add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat);
+							} else {
+								if first_hops.is_none() || chan.node_one != *our_node_id {
+									if let Some(one_to_two) = chan.one_to_two.as_ref() {
+										if one_to_two.enabled {
+											if let Some(htlc_maximum_msat) = one_to_two.htlc_maximum_msat {
+												chan_liquidity_msat = cmp::min(chan_liquidity_msat, htlc_maximum_msat);
+											}
+											add_entry!(chan_id, chan.node_one, chan.node_two, one_to_two, chan.features, $fee_to_target_msat, $path_penalty_msat, $path_cltv_expiry_delta, $path_length, chan_liquidity_msat);
+										}
 									}
-								}
 
+								}
 							}
 						}
 					}
 				}
-			}
-		};
-	}
+			};
+		}
 
-	match network.get_nodes().get(target) {
-		None => {},
-		Some(node) => {
-			add_entries_to_cheapest_to_target_node!(node, target, 0);
-		},
-	}
+		match network.get_nodes().get(target) {
+			None => {
+				// If the target isn't in our network graph we still need to consider our direct
+				// channels to it.
+				add_first_hop_entries!(*target, 0, 0, final_cltv, 0);
+			},
+			Some(node) => {
+				add_entries_to_cheapest_to_target_node!(node, target, 0, 0, final_cltv, 0);
+			},
+		}
 
-	for hop in last_hops.iter() {
-		let have_hop_src_in_graph =
-			if let Some(&(ref first_hop, ref features)) = first_hop_targets.get(&hop.src_node_id) {
-				// If this hop connects to a node with which we have a direct channel, ignore the
-				// network graph and add both the hop and our direct channel to the candidate set:
-				//
-				// Currently there are no channel-context features defined, so we are a
-				// bit lazy here. In the future, we should pull them out via our
-				// ChannelManager, but there's no reason to waste the space until we
-				// need them.
-				add_entry!(first_hop, *our_node_id , hop.src_node_id, dummy_directional_info, features.to_context(), 0);
-				true
-			} else {
-				// In any other case, only add the hop if the source is in the regular network
-				// graph:
-				network.get_nodes().get(&hop.src_node_id).is_some()
-			};
-		if have_hop_src_in_graph {
-			// BOLT 11 doesn't allow inclusion of features for the last hop hints, which
-			// really sucks, cause we're gonna need that eventually.
-			add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0);
+		for hop in last_hops.iter() {
+			let have_hop_src_in_graph =
+				if first_hop_targets.contains_key(&hop.src_node_id) {
+					// If this hop connects to a node with which we have a direct channel, ignore the
+					// network graph and add both the hop and our direct channel to the candidate set:
+					//
+					// Currently there are no channel-context features defined, so we are a
+					// bit lazy here. In the future, we should pull them out via our
+					// ChannelManager, but there's no reason to waste the space until we
+					// need them.
+					add_first_hop_entries!(hop.src_node_id, 0, 0, final_cltv, 0);
+					true
+				} else {
+					// In any other case, only add the hop if the source is in the regular network
+					// graph:
+					network.get_nodes().get(&hop.src_node_id).is_some()
+				};
+			if have_hop_src_in_graph {
+				// BOLT 11 doesn't allow inclusion of features for the last hop hints, which
+				// really sucks, cause we're gonna need that eventually.
+				add_entry!(hop.short_channel_id, hop.src_node_id, target, hop, ChannelFeatures::empty(), 0, 0, final_cltv, 0, hop.htlc_maximum_msat.unwrap_or(MAX_VALUE_MSAT));
+			}
 		}
-	}
 
-	while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, .. }) = targets.pop() {
-		if pubkey == *our_node_id {
-			let mut res = vec!(dist.remove(&our_node_id).unwrap().3);
-			loop {
-				if let Some(&(_, ref features)) = first_hop_targets.get(&res.last().unwrap().pubkey) {
-					res.last_mut().unwrap().node_features = features.to_context();
-				} else if let Some(node) = network.get_nodes().get(&res.last().unwrap().pubkey) {
-					if let Some(node_info) = node.announcement_info.as_ref() {
-						res.last_mut().unwrap().node_features = node_info.features.clone();
+		let mut found_path = None;
+		while let Some(RouteGraphNode { pubkey, lowest_fee_to_node, path_penalty_msat, path_cltv_expiry_delta, path_length, .. }) = targets.pop() {
+			if pubkey == *our_node_id {
+				let first_entry = dist.remove(&our_node_id).unwrap();
+				let mut res = vec!(first_entry.3);
+				let mut res_liquidity = vec!(first_entry.4);
+				loop {
+					if let Some(&(_, ref features, _)) = first_hop_targets.get(&res.last().unwrap().pubkey).and_then(|chans| chans.first()) {
+						res.last_mut().unwrap().node_features = features.to_context();
+					} else if let Some(node) = network.get_nodes().get(&res.last().unwrap().pubkey) {
+						if let Some(node_info) = node.announcement_info.as_ref() {
+							res.last_mut().unwrap().node_features = node_info.features.clone();
+						} else {
+							res.last_mut().unwrap().node_features = NodeFeatures::empty();
+						}
 					} else {
-						res.last_mut().unwrap().node_features = NodeFeatures::empty();
+						// We should be able to fill in features for everything except the last
+						// hop, if the last hop was provided via a BOLT 11 invoice (though we
+						// should be able to extend it further as BOLT 11 does have feature
+						// flags for the last hop node itself).
+						assert!(res.last().unwrap().pubkey == *target);
 					}
-				} else {
-					// We should be able to fill in features for everything except the last
-					// hop, if the last hop was provided via a BOLT 11 invoice (though we
-					// should be able to extend it further as BOLT 11 does have feature
-					// flags for the last hop node itself).
-					assert!(res.last().unwrap().pubkey == *target);
-				}
-				if res.last().unwrap().pubkey == *target {
-					break;
+					if res.last().unwrap().pubkey == *target {
+						break;
+					}
+
+					let new_entry = match dist.remove(&res.last().unwrap().pubkey) {
+						Some(hop) => hop,
+						None => return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination".to_owned(), action: ErrorAction::IgnoreError}),
+					};
+					res.last_mut().unwrap().cltv_expiry_delta = new_entry.3.cltv_expiry_delta;
+					res.push(new_entry.3);
+					res_liquidity.push(new_entry.4);
 				}
+				res.last_mut().unwrap().cltv_expiry_delta = final_cltv;
+				found_path = Some((res, res_liquidity));
+				break;
+			}
 
-				let new_entry = match dist.remove(&res.last().unwrap().pubkey) {
-					Some(hop) => hop.3,
-					None => return Err(LightningError{err: "Failed to find a non-fee-overflowing path to the given destination".to_owned(), action: ErrorAction::IgnoreError}),
-				};
-				res.last_mut().unwrap().fee_msat = new_entry.fee_msat;
-				res.last_mut().unwrap().cltv_expiry_delta = new_entry.cltv_expiry_delta;
-				res.push(new_entry);
+			match network.get_nodes().get(&pubkey) {
+				None => {},
+				Some(node) => {
+					add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node, path_penalty_msat, path_cltv_expiry_delta, path_length);
+				},
 			}
-			res.last_mut().unwrap().fee_msat = final_value_msat;
-			res.last_mut().unwrap().cltv_expiry_delta = final_cltv;
-			let route = Route { paths: vec![res] };
-			log_trace!(logger, "Got route: {}", log_route!(route));
-			return Ok(route);
 		}
 
-		match network.get_nodes().get(&pubkey) {
-			None => {},
-			Some(node) => {
-				add_entries_to_cheapest_to_target_node!(node, &pubkey, lowest_fee_to_node);
+		let (mut path, path_liquidity) = match found_path {
+			Some(path) => path,
+			None => break 'paths_search,
+		};
+
+		// Find the largest value, up to what we still need to send, which this path can carry.
+		let (path_value_msat, bottleneck_idx) = match get_path_amounts_msat(&path_liquidity, value_to_find_msat) {
+			Ok(_) => (value_to_find_msat, None),
+			Err(mut bottleneck_idx) => {
+				let (mut feasible_msat, mut infeasible_msat) = (0, value_to_find_msat);
+				while infeasible_msat - feasible_msat > 1 {
+					let mid_msat = feasible_msat + (infeasible_msat - feasible_msat) / 2;
+					match get_path_amounts_msat(&path_liquidity, mid_msat) {
+						Ok(_) => feasible_msat = mid_msat,
+						Err(idx) => { infeasible_msat = mid_msat; bottleneck_idx = idx; },
+					}
+				}
+				(feasible_msat, Some(bottleneck_idx))
 			},
+		};
+
+		macro_rules! exclude_channel {
+			( $idx: expr ) => {
+				let hop_liquidity = &path_liquidity[$idx];
+				used_liquidity_msat.insert((hop_liquidity.short_channel_id, hop_liquidity.src_node_id), u64::max_value());
+			};
+		}
+
+		if path_value_msat == 0 || (path_value_msat < value_to_find_msat && !allow_mpp) {
+			exclude_channel!(bottleneck_idx.unwrap());
+			continue;
+		}
+		let amounts_msat = get_path_amounts_msat(&path_liquidity, path_value_msat).unwrap();
+		if let Some(idx) = (0..path.len()).find(|idx| amounts_msat[*idx] < path_liquidity[*idx].htlc_minimum_msat) {
+			exclude_channel!(idx);
+			continue;
+		}
+
+		for (idx, hop_liquidity) in path_liquidity.iter().enumerate() {
+			let used_msat = used_liquidity_msat.entry((hop_liquidity.short_channel_id, hop_liquidity.src_node_id)).or_insert(0);
+			*used_msat = used_msat.saturating_add(amounts_msat[idx]);
+		}
+		if let Some(idx) = bottleneck_idx {
+			// Whatever is left in the bottleneck channel isn't enough to be useful
+			exclude_channel!(idx);
+		}
+		for idx in 0..path.len() - 1 {
+			path[idx].fee_msat = amounts_msat[idx] - amounts_msat[idx + 1];
+		}
+		path.last_mut().unwrap().fee_msat = path_value_msat;
+		// Fees were estimated during the search, so check the exact fees against what is left of
+		// our budget now.
+		let path_fee_msat = amounts_msat[0] - path_value_msat;
+		if path_fee_msat > fee_budget_msat {
+			return Err(LightningError{err: "Failed to find a route to the given destination within the maximum total fee".to_owned(), action: ErrorAction::IgnoreError});
+		}
+		fee_budget_msat -= path_fee_msat;
+		paths.push(path);
+		value_to_find_msat -= path_value_msat;
+
+		if value_to_find_msat > 0 && paths.len() == MAX_PATH_COUNT {
+			break;
 		}
 	}
 
-	Err(LightningError{err: "Failed to find a path to the given destination".to_owned(), action: ErrorAction::IgnoreError})
+	if paths.is_empty() || value_to_find_msat > 0 {
+		let mut err = if paths.is_empty() {
+			"Failed to find a path to the given destination".to_owned()
+		} else {
+			"Failed to find a sufficient route to the given destination".to_owned()
+		};
+		let hit_constraints = [
+			(hit_fee_limit, "exceeded the maximum total fee"),
+			(hit_cltv_limit, "exceeded the maximum total CLTV expiry delta"),
+			(hit_length_limit, "exceeded the maximum path length"),
+			(hit_exclusions, "used nodes or channels to avoid"),
+		].iter().filter(|&&(hit, _)| hit).map(|&(_, constraint)| constraint).collect::<Vec<_>>();
+		if !hit_constraints.is_empty() {
+			err += &format!(" (candidate paths {})", hit_constraints.join(", "));
+		}
+		return Err(LightningError{err, action: ErrorAction::IgnoreError});
+	}
+
+	let route = Route { paths };
+	log_trace!(logger, "Got route: {}", log_route!(route));
+	Ok(route)
+}
+
+/// Gets a circular route from us back to ourselves which moves amount_msat of liquidity out of
+/// the channel with short_channel_id outbound_scid and into the channel with short_channel_id
+/// inbound_scid, for use with ChannelManager::send_rebalance.
+///
+/// Both channels must be in first_hops, which should be filled in with the results of a
+/// ChannelManager::list_usable_channels() call. The route leaves us over the outbound channel,
+/// goes through the network to the counterparty of the inbound channel and then back to us over
+/// the inbound channel, paying the fees our counterparty announced for it (see
+/// ChannelDetails::counterparty_forwarding_info). The fee for a rebalance is the sum of the
+/// fee_msat of every hop but the last.
+///
+/// The route consists of a single path satisfying the given constraints, with the fee paid to
+/// the inbound channel's counterparty counted against the fee budget.
+pub fn get_rebalance_route<L: Deref, S: Score>(our_node_id: &PublicKey, network: &NetworkGraph, first_hops: &[&ChannelDetails],
+	outbound_scid: u64, inbound_scid: u64, amount_msat: u64, final_cltv: u32, constraints: &RouteConstraints, logger: L, scorer: &S) -> Result<Route, LightningError> where L::Target: Logger {
+	if outbound_scid == inbound_scid {
+		return Err(LightningError{err: "Cannot rebalance a channel into itself".to_owned(), action: ErrorAction::IgnoreError});
+	}
+	let outbound_chan = match first_hops.iter().find(|chan| chan.get_outbound_payment_scid() == Some(outbound_scid)) {
+		Some(chan) => *chan,
+		None => return Err(LightningError{err: "Cannot rebalance from a channel which isn't in first_hops".to_owned(), action: ErrorAction::IgnoreError}),
+	};
+	let inbound_chan = match first_hops.iter().find(|chan| chan.get_outbound_payment_scid() == Some(inbound_scid)) {
+		Some(chan) => *chan,
+		None => return Err(LightningError{err: "Cannot rebalance into a channel which isn't in first_hops".to_owned(), action: ErrorAction::IgnoreError}),
+	};
+	let inbound_info = match inbound_chan.counterparty_forwarding_info {
+		Some(ref info) => info,
+		None => return Err(LightningError{err: "Cannot rebalance into a channel whose counterparty forwarding policy is unknown".to_owned(), action: ErrorAction::IgnoreError}),
+	};
+	if amount_msat > inbound_chan.inbound_capacity_msat || amount_msat < inbound_info.htlc_minimum_msat ||
+			inbound_info.htlc_maximum_msat.map(|max| amount_msat > max).unwrap_or(false) {
+		return Err(LightningError{err: "Cannot rebalance an amount the inbound channel cannot carry".to_owned(), action: ErrorAction::IgnoreError});
+	}
+
+	let inbound_fee_msat = match amount_msat.checked_mul(inbound_info.fee_proportional_millionths as u64)
+		.and_then(|part| (inbound_info.fee_base_msat as u64).checked_add(part / 1000000)) {
+			Some(fee) => fee,
+			None => return Err(LightningError{err: "Cannot rebalance an amount whose fees overflow".to_owned(), action: ErrorAction::IgnoreError}),
+		};
+	let max_fee_msat = constraints.max_fee_msat(amount_msat);
+	if inbound_fee_msat > max_fee_msat {
+		return Err(LightningError{err: "Cannot rebalance into a channel whose fee exceeds the maximum total fee".to_owned(), action: ErrorAction::IgnoreError});
+	}
+	let max_total_cltv_expiry_delta = match constraints.max_total_cltv_expiry_delta.checked_sub(final_cltv) {
+		Some(delta) => delta,
+		None => return Err(LightningError{err: "Cannot generate a route with a final CLTV expiry delta above the maximum total CLTV expiry delta".to_owned(), action: ErrorAction::IgnoreError}),
+	};
+
+	// Route to the inbound channel's counterparty through the outbound channel only, as if it
+	// were the recipient of the value it forwards back to us, then append the hop back to us.
+	let path_constraints = RouteConstraints {
+		max_total_fee_msat: Some(max_fee_msat - inbound_fee_msat),
+		max_fee_proportional_millionths: None,
+		max_total_cltv_expiry_delta,
+		max_path_length: cmp::min(constraints.max_path_length, MAX_PATH_LENGTH).saturating_sub(1),
+		avoid_nodes: constraints.avoid_nodes.clone(),
+		avoid_channels: constraints.avoid_channels.clone(),
+	};
+	let mut route = get_route_with_constraints(our_node_id, network, &inbound_chan.remote_network_id, Some(&[outbound_chan]), &[],
+		amount_msat + inbound_fee_msat, inbound_info.cltv_expiry_delta as u32, &path_constraints, logger, scorer)?;
+	if route.paths.len() != 1 {
+		return Err(LightningError{err: "Failed to find a single path for the rebalance".to_owned(), action: ErrorAction::IgnoreError});
+	}
+	let path = &mut route.paths[0];
+	path.last_mut().unwrap().fee_msat = inbound_fee_msat;
+	path.push(RouteHop {
+		pubkey: *our_node_id,
+		node_features: NodeFeatures::known(),
+		short_channel_id: inbound_scid,
+		channel_features: inbound_chan.counterparty_features.to_context(),
+		fee_msat: amount_msat,
+		cltv_expiry_delta: final_cltv,
+	});
+	Ok(route)
 }
 
 #[cfg(test)]
 mod tests {
-	use routing::router::{get_route, RouteHint, RoutingFees};
+	use routing::router::{get_route, get_route_with_constraints, RouteConstraints, RouteHint, RoutingFees};
 	use routing::network_graph::{NetworkGraph, NetGraphMsgHandler};
+	use routing::scorer::{Score, Scorer, ScoringParameters};
 	use ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 	use ln::msgs::{ErrorAction, LightningError, OptionalField, UnsignedChannelAnnouncement, ChannelAnnouncement, RoutingMessageHandler,
 	   NodeAnnouncement, UnsignedNodeAnnouncement, ChannelUpdate, UnsignedChannelUpdate};
@@ -435,6 +833,7 @@ mod tests {
 	use bitcoin::secp256k1::{Secp256k1, All};
 
 	use std::sync::Arc;
+	use std::time::Duration;
 
 	// Using the same keys for LN and BTC ids
 	fn add_channel(net_graph_msg_handler: &NetGraphMsgHandler<Arc<test_utils::TestChainSource>, Arc<test_utils::TestLogger>>, secp_ctx: &Secp256k1<All>, node_1_privkey: &SecretKey,
@@ -840,10 +1239,11 @@ mod tests {
 	#[test]
 	fn simple_route_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Simple route to 3 via 2
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -861,9 +1261,113 @@ mod tests {
 		assert_eq!(route.paths[0][1].channel_features.le_flags(), &id_to_feature_flags(4));
 	}
 
+	#[test]
+	fn scored_route_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let mut scorer = Scorer::new(ScoringParameters {
+			base_penalty_msat: 0,
+			amount_penalty_millionths: 0,
+			failure_penalty_msat: 1000,
+			failure_penalty_half_life: Duration::from_secs(3600),
+		});
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+
+		// Route to 3 via 2 is the cheapest...
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0].len(), 2);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+
+		// ...but once channel 4 fails a payment, paying 100 msat more in fees via 8 is preferable to
+		// its penalty, which isn't included in the fees paid.
+		scorer.payment_path_failed(&route.paths[0], 4);
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0].len(), 2);
+		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
+		assert_eq!(route.paths[0][0].short_channel_id, 12);
+		assert_eq!(route.paths[0][0].fee_msat, 200);
+		assert_eq!(route.paths[0][1].short_channel_id, 13);
+
+		// A fixed per-channel penalty doesn't change which of two equal length paths is used.
+		let scorer = Scorer::with_fixed_penalty(1000);
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+		assert_eq!(route.paths[0][0].fee_msat, 100);
+	}
+
+	#[test]
+	fn constrained_route_test() {
+		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let network_graph = net_graph_msg_handler.network_graph.read().unwrap();
+
+		macro_rules! get_constrained_route {
+			($constraints: expr) => {
+				get_route_with_constraints(&our_id, &network_graph, &nodes[2], None, &Vec::new(), 100, 42, &$constraints, Arc::clone(&logger), &scorer)
+			}
+		}
+		macro_rules! assert_route_err {
+			($constraints: expr, $err: expr) => {
+				match get_constrained_route!($constraints) {
+					Err(LightningError { err, .. }) => assert_eq!(err, $err),
+					Ok(_) => panic!("Expected routing to fail"),
+				}
+			}
+		}
+
+		// Without constraints, the route goes through nodes[1] over channels 2 and 4, paying 100
+		// msat in fees and with a total CLTV expiry delta of 1025 + 42.
+		let route = get_constrained_route!(RouteConstraints::default()).unwrap();
+		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
+		assert_eq!(route.paths[0][1].short_channel_id, 4);
+		assert_eq!(route.paths[0][0].fee_msat, 100);
+		assert_eq!(route.paths[0].iter().map(|hop| hop.cltv_expiry_delta).sum::<u32>(), 1067);
+
+		// The constraints the route satisfies don't change it.
+		let route_constraints = RouteConstraints {
+			max_total_fee_msat: Some(100),
+			max_fee_proportional_millionths: Some(1_000_000),
+			max_total_cltv_expiry_delta: 1067,
+			max_path_length: 2,
+			..RouteConstraints::default()
+		};
+		assert!(get_constrained_route!(route_constraints).unwrap() == route);
+
+		// Avoiding channel 4 or nodes[1] moves the route to channels 12 and 13 through nodes[7].
+		let mut avoid_channel = RouteConstraints::default();
+		avoid_channel.avoid_channels.insert(4);
+		let mut avoid_node = RouteConstraints::default();
+		avoid_node.avoid_nodes.insert(nodes[1]);
+		for constraints in [avoid_channel.clone(), avoid_node].iter() {
+			let route = get_constrained_route!(constraints).unwrap();
+			assert_eq!(route.paths[0][0].pubkey, nodes[7]);
+			assert_eq!(route.paths[0][0].fee_msat, 200);
+			assert_eq!(route.paths[0][1].short_channel_id, 13);
+		}
+
+		// Each constraint which the remaining route breaks is named in the error.
+		assert_route_err!(RouteConstraints { max_total_fee_msat: Some(199), ..avoid_channel.clone() },
+			"Failed to find a path to the given destination (candidate paths exceeded the maximum total fee, used nodes or channels to avoid)");
+		assert_route_err!(RouteConstraints { max_total_cltv_expiry_delta: 2016, ..avoid_channel.clone() },
+			"Failed to find a path to the given destination (candidate paths exceeded the maximum total CLTV expiry delta, used nodes or channels to avoid)");
+
+		// Fee limits may also be proportional to the value sent.
+		assert_route_err!(RouteConstraints { max_fee_proportional_millionths: Some(999_999), ..RouteConstraints::default() },
+			"Failed to find a path to the given destination (candidate paths exceeded the maximum total fee)");
+		assert_route_err!(RouteConstraints { max_path_length: 1, ..RouteConstraints::default() },
+			"Failed to find a path to the given destination (candidate paths exceeded the maximum path length)");
+
+		let mut avoid_target = RouteConstraints::default();
+		avoid_target.avoid_nodes.insert(nodes[2]);
+		assert_route_err!(avoid_target, "Cannot generate a route to a node we were asked to avoid");
+		assert_route_err!(RouteConstraints { max_total_cltv_expiry_delta: 41, ..RouteConstraints::default() },
+			"Cannot generate a route with a final CLTV expiry delta above the maximum total CLTV expiry delta");
+	}
+
 	#[test]
 	fn disable_channels_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (our_privkey, our_id, privkeys, nodes) = get_nodes(&secp_ctx);
 
 		// // Disable channels 4 and 12 by flags=2
@@ -893,7 +1397,7 @@ mod tests {
 		});
 
 		// If all the channels require some features we don't understand, route should fail
-		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer) {
 			assert_eq!(err, "Failed to find a path to the given destination");
 		} else { panic!(); }
 
@@ -901,15 +1405,29 @@ mod tests {
 		let our_chans = vec![channelmanager::ChannelDetails {
 			channel_id: [0; 32],
 			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
 			remote_network_id: nodes[7].clone(),
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 100000,
 			inbound_capacity_msat: 0,
 			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()),  &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()),  &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -930,6 +1448,7 @@ mod tests {
 	#[test]
 	fn disable_node_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (_, our_id, privkeys, nodes) = get_nodes(&secp_ctx);
 
 		// Disable nodes 1, 2, and 8 by requiring unknown feature bits
@@ -940,7 +1459,7 @@ mod tests {
 		add_or_update_node(&net_graph_msg_handler, &secp_ctx, &privkeys[7], unknown_features.clone(), 1);
 
 		// If all nodes require some features we don't understand, route should fail
-		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger)) {
+		if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer) {
 			assert_eq!(err, "Failed to find a path to the given destination");
 		} else { panic!(); }
 
@@ -948,15 +1467,29 @@ mod tests {
 		let our_chans = vec![channelmanager::ChannelDetails {
 			channel_id: [0; 32],
 			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
 			remote_network_id: nodes[7].clone(),
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 100000,
 			inbound_capacity_msat: 0,
 			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -981,10 +1514,11 @@ mod tests {
 	#[test]
 	fn our_chans_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Route to 1 via 2 and 3 because our channel to 1 is disabled
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0], None, &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[0], None, &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 3);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1012,15 +1546,29 @@ mod tests {
 		let our_chans = vec![channelmanager::ChannelDetails {
 			channel_id: [0; 32],
 			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
 			remote_network_id: nodes[7].clone(),
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 100000,
 			inbound_capacity_msat: 0,
 			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
 		}];
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[7]);
@@ -1049,6 +1597,7 @@ mod tests {
 			fees: zero_fees,
 			cltv_expiry_delta: (8 << 8) | 1,
 			htlc_minimum_msat: 0,
+			htlc_maximum_msat: None,
 		}, RouteHint {
 			src_node_id: nodes[4].clone(),
 			short_channel_id: 9,
@@ -1058,22 +1607,25 @@ mod tests {
 			},
 			cltv_expiry_delta: (9 << 8) | 1,
 			htlc_minimum_msat: 0,
+			htlc_maximum_msat: None,
 		}, RouteHint {
 			src_node_id: nodes[5].clone(),
 			short_channel_id: 10,
 			fees: zero_fees,
 			cltv_expiry_delta: (10 << 8) | 1,
 			htlc_minimum_msat: 0,
+			htlc_maximum_msat: None,
 		})
 	}
 
 	#[test]
 	fn last_hops_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Simple test across 2, 3, 5, and 4 via a last_hop channel
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops(&nodes).iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops(&nodes).iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1117,22 +1669,37 @@ mod tests {
 	#[test]
 	fn our_chans_last_hop_connect_test() {
 		let (secp_ctx, net_graph_msg_handler, logger) = build_graph();
+		let scorer = Scorer::with_fixed_penalty(0);
 		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
 
 		// Simple test with outbound channel to 4 to test that last_hops and first_hops connect
 		let our_chans = vec![channelmanager::ChannelDetails {
 			channel_id: [0; 32],
 			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
 			remote_network_id: nodes[3].clone(),
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 0,
 			user_id: 0,
-			outbound_capacity_msat: 0,
+			outbound_capacity_msat: 100000,
 			inbound_capacity_msat: 0,
 			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
 		}];
 		let mut last_hops = last_hops(&nodes);
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 2);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[3]);
@@ -1152,7 +1719,7 @@ mod tests {
 		last_hops[0].fees.base_msat = 1000;
 
 		// Revert to via 6 as the fee on 8 goes up
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 4);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1186,7 +1753,7 @@ mod tests {
 		assert_eq!(route.paths[0][3].channel_features.le_flags(), &Vec::<u8>::new()); // We can't learn any flags from invoices, sadly
 
 		// ...but still use 8 for larger payments as 6 has a variable feerate
-		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 2000, 42, Arc::clone(&logger)).unwrap();
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[6], None, &last_hops.iter().collect::<Vec<_>>(), 2000, 42, Arc::clone(&logger), &scorer).unwrap();
 		assert_eq!(route.paths[0].len(), 5);
 
 		assert_eq!(route.paths[0][0].pubkey, nodes[1]);
@@ -1246,10 +1813,13 @@ mod tests {
 			},
 			cltv_expiry_delta: (8 << 8) | 1,
 			htlc_minimum_msat: 0,
+			htlc_maximum_msat: None,
 		}];
 		let our_chans = vec![channelmanager::ChannelDetails {
 			channel_id: [0; 32],
 			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
 			remote_network_id: middle_node_id,
 			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
 			channel_value_satoshis: 100000,
@@ -1257,8 +1827,21 @@ mod tests {
 			outbound_capacity_msat: 100000,
 			inbound_capacity_msat: 100000,
 			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
 		}];
-		let route = get_route(&source_node_id, &NetworkGraph::new(genesis_block(Network::Testnet).header.block_hash()), &target_node_id, Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::new(test_utils::TestLogger::new())).unwrap();
+		let scorer = Scorer::with_fixed_penalty(0);
+		let route = get_route(&source_node_id, &NetworkGraph::new(genesis_block(Network::Testnet).header.block_hash()), &target_node_id, Some(&our_chans.iter().collect::<Vec<_>>()), &last_hops.iter().collect::<Vec<_>>(), 100, 42, Arc::new(test_utils::TestLogger::new()), &scorer).unwrap();
 
 		assert_eq!(route.paths[0].len(), 2);
 
@@ -1276,4 +1859,209 @@ mod tests {
 		assert_eq!(route.paths[0][1].node_features.le_flags(), &[0; 0]); // We dont pass flags in from invoices yet
 		assert_eq!(route.paths[0][1].channel_features.le_flags(), &[0; 0]); // We can't learn any flags from invoices, sadly
 	}
+
+	fn build_mpp_graph(target_features: NodeFeatures) -> (NetGraphMsgHandler<std::sync::Arc<crate::util::test_utils::TestChainSource>, std::sync::Arc<crate::util::test_utils::TestLogger>>, std::sync::Arc<test_utils::TestLogger>) {
+		let secp_ctx = Secp256k1::new();
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let net_graph_msg_handler = NetGraphMsgHandler::new(genesis_block(Network::Testnet).header.block_hash(), None, Arc::clone(&logger));
+		// Build a network from our_id to node2 with two paths, each of which can only carry
+		// 60_000 msat:
+		//
+		//        -1(1)2-  node0  -1(3)2-
+		//       /                       \
+		// our_id                         node2
+		//       \                       /
+		//        -1(2)2-  node1  -1(4)2-
+		//
+		// chan1 1-to-2: enabled, ignored fee
+		// chan2 1-to-2: enabled, ignored fee
+		// chan3 1-to-2: enabled, 0 fee, 60_000 msat htlc_maximum_msat
+		// chan4 1-to-2: enabled, 100 msat + 0.1% fee, 60_000 msat htlc_maximum_msat
+		let (our_privkey, _, privkeys, _) = get_nodes(&secp_ctx);
+
+		for &(src_privkey, dst_privkey, short_channel_id, ref htlc_maximum_msat, fee_base_msat, fee_proportional_millionths) in [
+				(&our_privkey, &privkeys[0], 1, OptionalField::Absent, 0, 0),
+				(&our_privkey, &privkeys[1], 2, OptionalField::Absent, 0, 0),
+				(&privkeys[0], &privkeys[2], 3, OptionalField::Present(60_000), 0, 0),
+				(&privkeys[1], &privkeys[2], 4, OptionalField::Present(60_000), 100, 1000),
+			].iter() {
+			add_channel(&net_graph_msg_handler, &secp_ctx, src_privkey, dst_privkey, ChannelFeatures::empty(), short_channel_id);
+			update_channel(&net_graph_msg_handler, &secp_ctx, src_privkey, UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+				short_channel_id,
+				timestamp: 1,
+				flags: 0,
+				cltv_expiry_delta: 0,
+				htlc_minimum_msat: 0,
+				htlc_maximum_msat: htlc_maximum_msat.clone(),
+				fee_base_msat,
+				fee_proportional_millionths,
+				excess_data: Vec::new()
+			});
+		}
+		add_or_update_node(&net_graph_msg_handler, &secp_ctx, &privkeys[0], NodeFeatures::empty(), 0);
+		add_or_update_node(&net_graph_msg_handler, &secp_ctx, &privkeys[1], NodeFeatures::empty(), 0);
+		add_or_update_node(&net_graph_msg_handler, &secp_ctx, &privkeys[2], target_features, 0);
+
+		(net_graph_msg_handler, logger)
+	}
+
+	#[test]
+	fn multi_path_route_test() {
+		let (_, our_id, _, nodes) = get_nodes(&Secp256k1::new());
+
+		{
+			let (net_graph_msg_handler, logger) = build_mpp_graph(NodeFeatures::known());
+			let scorer = Scorer::with_fixed_penalty(0);
+
+			// A payment which fits in one path uses the cheapest one
+			let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 50_000, 42, Arc::clone(&logger), &scorer).unwrap();
+			assert_eq!(route.paths.len(), 1);
+			assert_eq!(route.paths[0][0].short_channel_id, 1);
+			assert_eq!(route.paths[0][1].short_channel_id, 3);
+			assert_eq!(route.paths[0][1].fee_msat, 50_000);
+
+			// A payment which doesn't is split across both paths, with the right fees
+			let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100_000, 42, Arc::clone(&logger), &scorer).unwrap();
+			assert_eq!(route.paths.len(), 2);
+
+			assert_eq!(route.paths[0][0].pubkey, nodes[0]);
+			assert_eq!(route.paths[0][0].short_channel_id, 1);
+			assert_eq!(route.paths[0][0].fee_msat, 0);
+			assert_eq!(route.paths[0][1].pubkey, nodes[2]);
+			assert_eq!(route.paths[0][1].short_channel_id, 3);
+			assert_eq!(route.paths[0][1].fee_msat, 60_000);
+			assert_eq!(route.paths[0][1].cltv_expiry_delta, 42);
+
+			assert_eq!(route.paths[1][0].pubkey, nodes[1]);
+			assert_eq!(route.paths[1][0].short_channel_id, 2);
+			assert_eq!(route.paths[1][0].fee_msat, 140);
+			assert_eq!(route.paths[1][1].pubkey, nodes[2]);
+			assert_eq!(route.paths[1][1].short_channel_id, 4);
+			assert_eq!(route.paths[1][1].fee_msat, 40_000);
+			assert_eq!(route.paths[1][1].cltv_expiry_delta, 42);
+
+			// But we can't send more than both paths can carry
+			if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 120_001, 42, Arc::clone(&logger), &scorer) {
+				assert_eq!(err, "Failed to find a sufficient route to the given destination");
+			} else { panic!(); };
+		}
+
+		{
+			// If the target doesn't support MPP, we only look for single paths with enough capacity
+			let (net_graph_msg_handler, logger) = build_mpp_graph(NodeFeatures::empty());
+			let scorer = Scorer::with_fixed_penalty(0);
+
+			let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 60_000, 42, Arc::clone(&logger), &scorer).unwrap();
+			assert_eq!(route.paths.len(), 1);
+			assert_eq!(route.paths[0][1].fee_msat, 60_000);
+
+			if let Err(LightningError{err, action: ErrorAction::IgnoreError}) = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], None, &Vec::new(), 100_000, 42, Arc::clone(&logger), &scorer) {
+				assert_eq!(err, "Failed to find a path to the given destination");
+			} else { panic!(); };
+		}
+	}
+
+	#[test]
+	fn first_hop_capacity_test() {
+		let (net_graph_msg_handler, logger) = build_mpp_graph(NodeFeatures::known());
+		let scorer = Scorer::with_fixed_penalty(0);
+		let (_, our_id, _, nodes) = get_nodes(&Secp256k1::new());
+
+		// Our channel to node0 can only carry 30_000 msat, so we send the rest through node1
+		let our_chans = vec![channelmanager::ChannelDetails {
+			channel_id: [0; 32],
+			short_channel_id: Some(42),
+			scid_alias: None,
+			counterparty_scid_alias: None,
+			remote_network_id: nodes[0].clone(),
+			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+			channel_value_satoshis: 100,
+			user_id: 0,
+			outbound_capacity_msat: 30_000,
+			inbound_capacity_msat: 0,
+			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
+		}, channelmanager::ChannelDetails {
+			channel_id: [1; 32],
+			short_channel_id: Some(43),
+			scid_alias: None,
+			counterparty_scid_alias: None,
+			remote_network_id: nodes[1].clone(),
+			counterparty_features: InitFeatures::from_le_bytes(vec![0b11]),
+			channel_value_satoshis: 100,
+			user_id: 0,
+			outbound_capacity_msat: 100_000,
+			inbound_capacity_msat: 0,
+			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
+		}];
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&our_chans.iter().collect::<Vec<_>>()), &Vec::new(), 50_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+
+		assert_eq!(route.paths[0][0].short_channel_id, 42);
+		assert_eq!(route.paths[0][0].fee_msat, 0);
+		assert_eq!(route.paths[0][1].short_channel_id, 3);
+		assert_eq!(route.paths[0][1].fee_msat, 30_000);
+
+		assert_eq!(route.paths[1][0].short_channel_id, 43);
+		assert_eq!(route.paths[1][0].fee_msat, 120);
+		assert_eq!(route.paths[1][1].short_channel_id, 4);
+		assert_eq!(route.paths[1][1].fee_msat, 20_000);
+
+		// A direct channel without enough capacity for the whole payment can be combined with
+		// other paths too
+		let direct_chans = vec![channelmanager::ChannelDetails {
+			channel_id: [2; 32],
+			short_channel_id: Some(44),
+			scid_alias: None,
+			counterparty_scid_alias: None,
+			remote_network_id: nodes[2].clone(),
+			counterparty_features: InitFeatures::known(),
+			channel_value_satoshis: 100,
+			user_id: 0,
+			outbound_capacity_msat: 10_000,
+			inbound_capacity_msat: 0,
+			is_live: true,
+			balance_msat: 0,
+			counterparty_balance_msat: 0,
+			holder_channel_reserve_satoshis: Some(0),
+			counterparty_channel_reserve_satoshis: 0,
+			confirmations: 6,
+			confirmations_required: Some(6),
+			is_public: true,
+			is_funding_locked: true,
+			feerate_per_kw: 253,
+			counterparty_forwarding_info: None,
+			holder_to_self_delay: Some(144),
+			counterparty_to_self_delay: 144,
+		}, our_chans[0].clone()];
+		let route = get_route(&our_id, &net_graph_msg_handler.network_graph.read().unwrap(), &nodes[2], Some(&direct_chans.iter().collect::<Vec<_>>()), &Vec::new(), 40_000, 42, Arc::clone(&logger), &scorer).unwrap();
+		assert_eq!(route.paths.len(), 2);
+		let total_value_msat: u64 = route.paths.iter().map(|path| path.last().unwrap().fee_msat).sum();
+		assert_eq!(total_value_msat, 40_000);
+		assert!(route.paths.iter().any(|path| path.len() == 1 && path[0].short_channel_id == 44 && path[0].fee_msat == 10_000));
+	}
 }
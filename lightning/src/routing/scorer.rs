@@ -0,0 +1,394 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Channel scoring used by get_route to avoid channels which are likely to fail a payment.
+//!
+//! A Score implementation may be passed to get_route to penalize channels in addition to the fees
+//! they charge. You probably want to use Scorer, which learns from the results of past payment
+//! attempts, and feed it the paths from PaymentFailed events and successful payments.
+
+use bitcoin::secp256k1::key::PublicKey;
+
+use ln::msgs::DecodeError;
+use routing::router::RouteHop;
+use util::ser::{Writeable, Readable, Writer};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An interface used to score payment channels for path finding.
+///
+/// Penalties are expressed in msat and are added to the fees of a candidate path when comparing
+/// it against others, but are never actually paid.
+pub trait Score {
+	/// Returns the fee, in msat, we're willing to pay to avoid sending send_amt_msat over the given
+	/// channel in the direction from source to target.
+	///
+	/// A penalty of u64::max_value() prevents the channel from being used at all.
+	fn channel_penalty_msat(&self, short_channel_id: u64, send_amt_msat: u64, source: &PublicKey, target: &PublicKey) -> u64;
+
+	/// Updates channel penalties after a payment failed along the given path at the channel with
+	/// the given short_channel_id (ie the short_channel_id provided in Event::PaymentFailed).
+	fn payment_path_failed(&mut self, path: &[RouteHop], short_channel_id: u64);
+
+	/// Updates channel penalties after a payment succeeded along the given path.
+	fn payment_path_successful(&mut self, path: &[RouteHop]);
+}
+
+/// A source of the current time, used by Scorer to decay channel penalties.
+pub trait Time {
+	/// Returns the current time as a duration since the UNIX epoch.
+	fn duration_since_epoch() -> Duration;
+}
+
+/// A Time implementation which uses the system clock.
+pub struct SystemClock;
+
+impl Time for SystemClock {
+	fn duration_since_epoch() -> Duration {
+		SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0))
+	}
+}
+
+/// A Score implementation which applies a fixed penalty to every channel and an additional penalty
+/// to channels which recently failed to forward a payment.
+///
+/// Each failure adds failure_penalty_msat to the penalty of the failing channel, which then halves
+/// every failure_penalty_half_life. Each successful payment through a channel halves its
+/// remaining penalty, so channels which only fail occasionally are forgiven quickly.
+pub type Scorer = ScorerUsingTime<SystemClock>;
+
+/// Scorer, generic over the source of the current time. You probably want to use Scorer instead.
+pub struct ScorerUsingTime<T: Time> {
+	params: ScoringParameters,
+	channel_penalties: HashMap<u64, ChannelPenalty>,
+	_time: PhantomData<T>,
+}
+
+/// Parameters for configuring Scorer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoringParameters {
+	/// A fixed penalty, in msat, applied to every channel. This makes shorter paths preferable to
+	/// longer ones of similar fee.
+	///
+	/// Default value: 500 msat
+	pub base_penalty_msat: u64,
+	/// A penalty, in millionths of the amount sent over a channel, applied to every channel. This
+	/// makes shorter paths increasingly preferable as the amount sent grows.
+	///
+	/// Default value: 0
+	pub amount_penalty_millionths: u32,
+	/// The penalty, in msat, added to a channel each time it fails to forward a payment.
+	///
+	/// Default value: 1,024,000 msat
+	pub failure_penalty_msat: u64,
+	/// The time it takes for the failure penalty of a channel to halve. A value of zero means
+	/// failures are forgotten immediately.
+	///
+	/// Default value: 1 hour
+	pub failure_penalty_half_life: Duration,
+}
+
+impl Default for ScoringParameters {
+	fn default() -> Self {
+		Self {
+			base_penalty_msat: 500,
+			amount_penalty_millionths: 0,
+			failure_penalty_msat: 1024 * 1000,
+			failure_penalty_half_life: Duration::from_secs(3600),
+		}
+	}
+}
+
+impl_writeable!(ScoringParameters, 8 + 4 + 8 + 12, {
+	base_penalty_msat,
+	amount_penalty_millionths,
+	failure_penalty_msat,
+	failure_penalty_half_life
+});
+
+/// The failure penalty of a channel as of the last time it was updated.
+struct ChannelPenalty {
+	undecayed_penalty_msat: u64,
+	/// The time at which undecayed_penalty_msat was set, as a duration since the UNIX epoch.
+	last_updated: Duration,
+}
+
+impl_writeable!(ChannelPenalty, 8 + 12, {
+	undecayed_penalty_msat,
+	last_updated
+});
+
+impl ChannelPenalty {
+	fn decayed_penalty_msat(&self, half_life: Duration, now: Duration) -> u64 {
+		let elapsed = now.checked_sub(self.last_updated).unwrap_or(Duration::from_secs(0));
+		match elapsed.as_secs().checked_div(half_life.as_secs()) {
+			Some(half_lives) if half_lives < 64 => self.undecayed_penalty_msat >> half_lives,
+			_ => 0,
+		}
+	}
+}
+
+impl<T: Time> ScorerUsingTime<T> {
+	/// Creates a new scorer using the given parameters.
+	pub fn new(params: ScoringParameters) -> Self {
+		Self {
+			params,
+			channel_penalties: HashMap::new(),
+			_time: PhantomData,
+		}
+	}
+
+	/// Creates a new scorer which applies the given penalty to every channel and never learns from
+	/// payment results. Useful for tests or when only fees should be considered.
+	pub fn with_fixed_penalty(penalty_msat: u64) -> Self {
+		Self::new(ScoringParameters {
+			base_penalty_msat: penalty_msat,
+			amount_penalty_millionths: 0,
+			failure_penalty_msat: 0,
+			failure_penalty_half_life: Duration::from_secs(0),
+		})
+	}
+
+	/// Gets the parameters this scorer was created with.
+	pub fn get_params(&self) -> &ScoringParameters {
+		&self.params
+	}
+
+	/// Gets the current failure penalty, in msat, of the given channel.
+	pub fn channel_failure_penalty_msat(&self, short_channel_id: u64) -> u64 {
+		match self.channel_penalties.get(&short_channel_id) {
+			Some(penalty) => penalty.decayed_penalty_msat(self.params.failure_penalty_half_life, T::duration_since_epoch()),
+			None => 0,
+		}
+	}
+
+	/// Drops penalties which have fully decayed, eg to limit the size of the scorer on disk. Should
+	/// be called occasionally, eg when persisting the scorer.
+	pub fn remove_decayed_penalties(&mut self) {
+		let half_life = self.params.failure_penalty_half_life;
+		let now = T::duration_since_epoch();
+		self.channel_penalties.retain(|_, penalty| penalty.decayed_penalty_msat(half_life, now) != 0);
+	}
+}
+
+impl<T: Time> Default for ScorerUsingTime<T> {
+	fn default() -> Self {
+		Self::new(ScoringParameters::default())
+	}
+}
+
+impl<T: Time> Score for ScorerUsingTime<T> {
+	fn channel_penalty_msat(&self, short_channel_id: u64, send_amt_msat: u64, _source: &PublicKey, _target: &PublicKey) -> u64 {
+		let amount_penalty_msat = send_amt_msat.saturating_mul(self.params.amount_penalty_millionths as u64) / 1_000_000;
+		self.params.base_penalty_msat
+			.saturating_add(amount_penalty_msat)
+			.saturating_add(self.channel_failure_penalty_msat(short_channel_id))
+	}
+
+	fn payment_path_failed(&mut self, _path: &[RouteHop], short_channel_id: u64) {
+		if self.params.failure_penalty_msat == 0 {
+			return;
+		}
+		let half_life = self.params.failure_penalty_half_life;
+		let now = T::duration_since_epoch();
+		let penalty = self.channel_penalties.entry(short_channel_id).or_insert(ChannelPenalty {
+			undecayed_penalty_msat: 0,
+			last_updated: now,
+		});
+		penalty.undecayed_penalty_msat = penalty.decayed_penalty_msat(half_life, now).saturating_add(self.params.failure_penalty_msat);
+		penalty.last_updated = now;
+	}
+
+	fn payment_path_successful(&mut self, path: &[RouteHop]) {
+		let half_life = self.params.failure_penalty_half_life;
+		let now = T::duration_since_epoch();
+		for hop in path.iter() {
+			let mut forgiven = false;
+			if let Some(penalty) = self.channel_penalties.get_mut(&hop.short_channel_id) {
+				penalty.undecayed_penalty_msat = penalty.decayed_penalty_msat(half_life, now) / 2;
+				penalty.last_updated = now;
+				forgiven = penalty.undecayed_penalty_msat == 0;
+			}
+			if forgiven {
+				self.channel_penalties.remove(&hop.short_channel_id);
+			}
+		}
+	}
+}
+
+impl<T: Time> Writeable for ScorerUsingTime<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.params.write(writer)?;
+		(self.channel_penalties.len() as u64).write(writer)?;
+		for (short_channel_id, penalty) in self.channel_penalties.iter() {
+			short_channel_id.write(writer)?;
+			penalty.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl<T: Time> Readable for ScorerUsingTime<T> {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		let params = Readable::read(reader)?;
+		let penalties_count: u64 = Readable::read(reader)?;
+		let mut channel_penalties = HashMap::new();
+		for _ in 0..penalties_count {
+			let short_channel_id: u64 = Readable::read(reader)?;
+			let penalty = Readable::read(reader)?;
+			channel_penalties.insert(short_channel_id, penalty);
+		}
+		Ok(Self {
+			params,
+			channel_penalties,
+			_time: PhantomData,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Score, ScorerUsingTime, ScoringParameters, Time};
+
+	use routing::router::RouteHop;
+	use ln::features::{ChannelFeatures, NodeFeatures};
+	use util::ser::{Writeable, Readable};
+
+	use bitcoin::secp256k1::key::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::Secp256k1;
+
+	use std::cell::Cell;
+	use std::io::Cursor;
+	use std::time::Duration;
+
+	thread_local! {
+		static ELAPSED: Cell<Duration> = Cell::new(Duration::from_secs(1_000_000));
+	}
+
+	/// A Time implementation which only moves forward when told to, so tests can control decay.
+	struct SinceEpoch;
+
+	impl SinceEpoch {
+		fn advance(duration: Duration) {
+			ELAPSED.with(|elapsed| elapsed.set(elapsed.get() + duration));
+		}
+	}
+
+	impl Time for SinceEpoch {
+		fn duration_since_epoch() -> Duration {
+			ELAPSED.with(|elapsed| elapsed.get())
+		}
+	}
+
+	type Scorer = ScorerUsingTime<SinceEpoch>;
+
+	fn node_id(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn path(short_channel_ids: &[u64]) -> Vec<RouteHop> {
+		short_channel_ids.iter().enumerate().map(|(idx, short_channel_id)| RouteHop {
+			pubkey: node_id(idx as u8 + 2),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: *short_channel_id,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: 1000,
+			cltv_expiry_delta: 18,
+		}).collect()
+	}
+
+	fn params() -> ScoringParameters {
+		ScoringParameters {
+			base_penalty_msat: 1000,
+			amount_penalty_millionths: 0,
+			failure_penalty_msat: 512,
+			failure_penalty_half_life: Duration::from_secs(10),
+		}
+	}
+
+	#[test]
+	fn penalizes_by_amount() {
+		let scorer = Scorer::new(ScoringParameters { amount_penalty_millionths: 1000, ..params() });
+		let (source, target) = (node_id(1), node_id(2));
+		assert_eq!(scorer.channel_penalty_msat(42, 0, &source, &target), 1000);
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000_000, &source, &target), 2000);
+		assert_eq!(scorer.channel_penalty_msat(42, u64::max_value(), &source, &target), 1000 + u64::max_value() / 1_000_000);
+	}
+
+	#[test]
+	fn failure_penalty_accumulates_and_decays() {
+		let mut scorer = Scorer::new(params());
+		let (source, target) = (node_id(1), node_id(2));
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 1000);
+
+		scorer.payment_path_failed(&path(&[41, 42]), 42);
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 1512);
+		assert_eq!(scorer.channel_penalty_msat(41, 1_000, &source, &target), 1000);
+
+		scorer.payment_path_failed(&path(&[41, 42]), 42);
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 2024);
+
+		SinceEpoch::advance(Duration::from_secs(9));
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 2024);
+		SinceEpoch::advance(Duration::from_secs(1));
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 1512);
+		SinceEpoch::advance(Duration::from_secs(10 * 7));
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 1004);
+		SinceEpoch::advance(Duration::from_secs(10 * 3));
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &source, &target), 1000);
+
+		scorer.remove_decayed_penalties();
+		assert!(scorer.channel_penalties.is_empty());
+	}
+
+	#[test]
+	fn success_halves_failure_penalty() {
+		let mut scorer = Scorer::new(params());
+		scorer.payment_path_failed(&path(&[42]), 42);
+		scorer.payment_path_failed(&path(&[43]), 43);
+		assert_eq!(scorer.channel_failure_penalty_msat(42), 512);
+
+		scorer.payment_path_successful(&path(&[41, 42]));
+		assert_eq!(scorer.channel_failure_penalty_msat(42), 256);
+		assert_eq!(scorer.channel_failure_penalty_msat(43), 512);
+
+		for _ in 0..9 {
+			scorer.payment_path_successful(&path(&[42]));
+		}
+		assert_eq!(scorer.channel_failure_penalty_msat(42), 0);
+		assert!(!scorer.channel_penalties.contains_key(&42));
+	}
+
+	#[test]
+	fn fixed_penalty_scorer_does_not_learn() {
+		let mut scorer = Scorer::with_fixed_penalty(0);
+		scorer.payment_path_failed(&path(&[42]), 42);
+		assert_eq!(scorer.channel_penalty_msat(42, 1_000, &node_id(1), &node_id(2)), 0);
+	}
+
+	#[test]
+	fn scorer_serialization_roundtrip() {
+		let mut scorer = Scorer::new(params());
+		scorer.payment_path_failed(&path(&[42]), 42);
+		SinceEpoch::advance(Duration::from_secs(10));
+
+		let mut cursor = Cursor::new(scorer.encode());
+		let mut read_scorer: Scorer = Readable::read(&mut cursor).unwrap();
+		assert_eq!(read_scorer.get_params(), &params());
+		assert_eq!(read_scorer.channel_failure_penalty_msat(42), 256);
+
+		// Decay continues from the time of the last failure, not from when the scorer was read.
+		SinceEpoch::advance(Duration::from_secs(10));
+		assert_eq!(read_scorer.channel_failure_penalty_msat(42), 128);
+		read_scorer.payment_path_failed(&path(&[42]), 42);
+		assert_eq!(read_scorer.channel_failure_penalty_msat(42), 640);
+	}
+}
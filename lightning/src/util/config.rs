@@ -10,7 +10,9 @@
 //! Various user-configurable channel limits and settings which ChannelManager
 //! applies for you.
 
-use ln::channelmanager::{BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT};
+use ln::channelmanager::{BREAKDOWN_TIMEOUT, MAX_LOCAL_BREAKDOWN_TIMEOUT, CLTV_EXPIRY_DELTA};
+use ln::msgs::DecodeError;
+use util::ser::Readable;
 
 /// Configuration we set when applicable.
 ///
@@ -44,6 +46,49 @@ pub struct ChannelHandshakeConfig {
 	/// Default value: 1. If the value is less than 1, it is ignored and set to 1, as is required
 	/// by the protocol.
 	pub our_htlc_minimum_msat: u64,
+	/// If set, we advertise support for option_anchors_zero_fee_htlc_tx and use the anchor outputs
+	/// commitment format, with zero-fee HTLC transactions, for any channel opened with a peer which
+	/// advertises it as well.
+	///
+	/// Anchor outputs allow commitment and HTLC transactions to be fee-bumped with CPFP once
+	/// broadcast, but the funder pays for two additional 330 sat outputs in every commitment
+	/// transaction.
+	///
+	/// As this changes the features we advertise to all peers, only the value in
+	/// ChannelManager's default UserConfig is used, any per-channel override is ignored.
+	///
+	/// Default value: false.
+	pub negotiate_anchor_outputs: bool,
+	/// If set, we advertise support for option_dual_fund, allowing channels to be opened with
+	/// ChannelManager::create_dual_funded_channel and accepting dual-funded channels from peers
+	/// which advertise it as well.
+	///
+	/// As this changes the features we advertise to all peers, only the value in
+	/// ChannelManager's default UserConfig is used, any per-channel override is ignored.
+	///
+	/// Default value: false.
+	pub negotiate_dual_fund: bool,
+	/// If set, we advertise support for splicing, allowing channels to be spliced with
+	/// ChannelManager::splice_in and ChannelManager::splice_out and accepting splices from peers
+	/// which advertise it as well. Note that the splice message format has not been finalized,
+	/// so splicing may only work with peers running this version.
+	///
+	/// As this changes the features we advertise to all peers, only the value in
+	/// ChannelManager's default UserConfig is used, any per-channel override is ignored.
+	///
+	/// Default value: false.
+	pub negotiate_splicing: bool,
+	/// The amount we contribute to the funding transaction of dual-funded channels opened by our
+	/// peers, using inputs from KeysInterface::get_utxo_source.
+	///
+	/// If we have no funding wallet, if it cannot provide enough funds, or if our contribution
+	/// would take the channel value above the maximum we accept, we reject the channel rather
+	/// than accepting it without contributing to it. Applied only for inbound channels, the
+	/// amount contributed to channels we open is given to
+	/// ChannelManager::create_dual_funded_channel directly.
+	///
+	/// Default value: 0.
+	pub dual_funding_contribution_satoshis: u64,
 }
 
 impl Default for ChannelHandshakeConfig {
@@ -52,6 +97,10 @@ impl Default for ChannelHandshakeConfig {
 			minimum_depth: 6,
 			our_to_self_delay: BREAKDOWN_TIMEOUT,
 			our_htlc_minimum_msat: 1,
+			negotiate_anchor_outputs: false,
+			negotiate_dual_fund: false,
+			negotiate_splicing: false,
+			dual_funding_contribution_satoshis: 0,
 		}
 	}
 }
@@ -74,6 +123,16 @@ pub struct ChannelHandshakeLimits {
 	///
 	/// Default value: 0.
 	pub min_funding_satoshis: u64,
+	/// Maximum allowed satoshis when a channel is funded, this is supplied by the sender and so
+	/// only applies to inbound channels.
+	///
+	/// Channels larger than 2^24 - 1 satoshis are only ever accepted from peers which support
+	/// `option_support_large_channel` (aka wumbo), regardless of this value. Large channels also
+	/// require more confirmations of their funding transaction than our configured
+	/// ChannelHandshakeConfig::minimum_depth, scaled with the channel value.
+	///
+	/// Default value: 2^24 - 1, the largest channel allowed without wumbo.
+	pub max_funding_satoshis: u64,
 	/// The remote node sets a limit on the minimum size of HTLCs we can send to them. This allows
 	/// you to limit the maximum minimum-size they can require.
 	///
@@ -138,6 +197,7 @@ impl Default for ChannelHandshakeLimits {
 	fn default() -> Self {
 		ChannelHandshakeLimits {
 			min_funding_satoshis: 0,
+			max_funding_satoshis: (1 << 24) - 1,
 			max_htlc_minimum_msat: <u64>::max_value(),
 			min_max_htlc_value_in_flight_msat: 0,
 			max_channel_reserve_satoshis: <u64>::max_value(),
@@ -156,11 +216,61 @@ impl Default for ChannelHandshakeLimits {
 #[derive(Copy, Clone, Debug)]
 pub struct ChannelConfig {
 	/// Amount (in millionths of a satoshi) the channel will charge per transferred satoshi.
-	/// This may be allowed to change at runtime in a later update, however doing so must result in
-	/// update messages sent to notify all nodes of our updated relay fee.
+	/// This may be changed at runtime with ChannelManager::update_channel_config, which notifies
+	/// all nodes of our updated relay fee.
 	///
 	/// Default value: 0.
 	pub fee_proportional_millionths: u32,
+	/// Amount (in millisatoshis) the channel will charge per forwarded HTLC, on top of
+	/// fee_proportional_millionths. If None, we charge what it would cost us to claim the HTLC
+	/// on-chain at the channel's current feerate.
+	///
+	/// Like all forwarding policy fields, this may be changed at runtime with
+	/// ChannelManager::update_channel_config.
+	///
+	/// Default value: None.
+	pub fee_base_msat: Option<u32>,
+	/// The difference in CLTV expiry we require between an HTLC we receive and the HTLC we forward
+	/// over this channel. This must leave us enough time to claim the inbound HTLC on-chain if the
+	/// outbound one is claimed late, so values below 36 blocks are treated as 36.
+	///
+	/// Default value: 72.
+	pub cltv_expiry_delta: u16,
+	/// The smallest HTLC (in millisatoshis) we will forward over this channel. The minimum our
+	/// counterparty requires applies instead if it is larger.
+	///
+	/// Default value: 0.
+	pub htlc_minimum_msat: u64,
+	/// The largest HTLC (in millisatoshis) we will forward over this channel. We never forward
+	/// HTLCs above 90% of the channel value or above our maximum value in flight, whichever is
+	/// lower, even if this is None or larger.
+	///
+	/// Default value: None.
+	pub htlc_maximum_msat: Option<u64>,
+	/// If set and we funded the channel, we send update_fee from
+	/// ChannelManager::timer_chan_freshness_every_min whenever our FeeEstimator's Normal
+	/// estimate has moved far enough from the channel's feerate, keeping our commitment
+	/// transactions likely to confirm in a timely manner if we have to broadcast them.
+	///
+	/// Default value: false.
+	pub auto_update_fee: bool,
+	/// The percentage by which our feerate estimate must exceed the channel's feerate before
+	/// auto_update_fee raises it.
+	///
+	/// Default value: 10.
+	pub update_fee_min_increase_percent: u8,
+	/// The percentage by which our feerate estimate must be below the channel's feerate before
+	/// auto_update_fee lowers it. Lowering it is less urgent than raising it, hence the larger
+	/// default, which avoids sending update_fee every time the estimate fluctuates.
+	///
+	/// Default value: 50.
+	pub update_fee_min_decrease_percent: u8,
+	/// The highest feerate (in satoshis per 1000 weight) auto_update_fee will set, protecting our
+	/// balance against feerate spikes. Channels already above it aren't lowered because of it
+	/// alone.
+	///
+	/// Default value: None.
+	pub max_update_feerate_per_kw: Option<u32>,
 	/// Set to announce the channel publicly and notify all nodes that they can route via this
 	/// channel.
 	///
@@ -192,6 +302,14 @@ impl Default for ChannelConfig {
 	fn default() -> Self {
 		ChannelConfig {
 			fee_proportional_millionths: 0,
+			fee_base_msat: None,
+			cltv_expiry_delta: CLTV_EXPIRY_DELTA,
+			htlc_minimum_msat: 0,
+			htlc_maximum_msat: None,
+			auto_update_fee: false,
+			update_fee_min_increase_percent: 10,
+			update_fee_min_decrease_percent: 50,
+			max_update_feerate_per_kw: None,
 			announced_channel: false,
 			commit_upfront_shutdown_pubkey: true,
 		}
@@ -199,12 +317,34 @@ impl Default for ChannelConfig {
 }
 
 //Add write and readable traits to channelconfig
-impl_writeable!(ChannelConfig, 8+1+1, {
+impl_writeable!(ChannelConfig, 4+5+2+8+9+1+1+1+5+1+1, {
 	fee_proportional_millionths,
+	fee_base_msat,
+	cltv_expiry_delta,
+	htlc_minimum_msat,
+	htlc_maximum_msat,
+	auto_update_fee,
+	update_fee_min_increase_percent,
+	update_fee_min_decrease_percent,
+	max_update_feerate_per_kw,
 	announced_channel,
 	commit_upfront_shutdown_pubkey
 });
 
+impl ChannelConfig {
+	/// Reads a ChannelConfig as written by version 1 Channels, which only included
+	/// fee_proportional_millionths, announced_channel and commit_upfront_shutdown_pubkey. The
+	/// remaining fields take their default values.
+	pub(crate) fn read_v1<R: ::std::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		Ok(ChannelConfig {
+			fee_proportional_millionths: Readable::read(reader)?,
+			announced_channel: Readable::read(reader)?,
+			commit_upfront_shutdown_pubkey: Readable::read(reader)?,
+			..Default::default()
+		})
+	}
+}
+
 /// Top-level config which holds ChannelHandshakeLimits and ChannelConfig.
 ///
 /// Default::default() provides sane defaults for most configurations
@@ -217,6 +357,13 @@ pub struct UserConfig {
 	pub peer_channel_config_limits: ChannelHandshakeLimits,
 	/// Channel config which affects behavior during channel lifetime.
 	pub channel_options: ChannelConfig,
+	/// If this is set to true, inbound channel requests which pass peer_channel_config_limits are
+	/// not accepted right away. Instead, an Event::OpenChannelRequest is generated and the channel
+	/// is only opened once ChannelManager::accept_inbound_channel is called for it (or rejected
+	/// with ChannelManager::reject_inbound_channel).
+	///
+	/// Default value: false.
+	pub manually_accept_inbound_channels: bool,
 }
 
 impl Default for UserConfig {
@@ -225,6 +372,7 @@ impl Default for UserConfig {
 			own_channel_config: ChannelHandshakeConfig::default(),
 			peer_channel_config_limits: ChannelHandshakeLimits::default(),
 			channel_options: ChannelConfig::default(),
+			manually_accept_inbound_channels: false,
 		}
 	}
 }
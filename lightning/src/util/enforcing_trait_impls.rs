@@ -14,7 +14,7 @@ use chain::keysinterface::{ChannelKeys, InMemoryChannelKeys};
 use std::cmp;
 use std::sync::{Mutex, Arc};
 
-use bitcoin::blockdata::transaction::{Transaction, SigHashType};
+use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::util::bip143;
 
 use bitcoin::secp256k1;
@@ -89,21 +89,27 @@ impl ChannelKeys for EnforcingChannelKeys {
 		let trusted_tx = self.verify_holder_commitment_tx(commitment_tx, secp_ctx);
 		let commitment_txid = trusted_tx.txid();
 		let holder_csv = self.inner.counterparty_selected_contest_delay();
+		let opt_anchors = self.inner.opt_anchors();
 
 		for (this_htlc, sig) in trusted_tx.htlcs().iter().zip(&commitment_tx.counterparty_htlc_sigs) {
 			assert!(this_htlc.transaction_output_index.is_some());
 			let keys = trusted_tx.keys();
-			let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, trusted_tx.feerate_per_kw(), holder_csv, &this_htlc, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+			let htlc_tx = chan_utils::build_htlc_transaction(&commitment_txid, trusted_tx.feerate_per_kw(), holder_csv, &this_htlc, opt_anchors, &keys.broadcaster_delayed_payment_key, &keys.revocation_key);
 
-			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&this_htlc, &keys);
+			let htlc_redeemscript = chan_utils::get_htlc_redeemscript(&this_htlc, opt_anchors, &keys);
 
-			let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.amount_msat / 1000, SigHashType::All)[..]);
+			let sighash = hash_to_message!(&bip143::SigHashCache::new(&htlc_tx).signature_hash(0, &htlc_redeemscript, this_htlc.amount_msat / 1000, chan_utils::htlc_tx_counterparty_sighash_type(opt_anchors))[..]);
 			secp_ctx.verify(&sighash, sig, &keys.countersignatory_htlc_key).unwrap();
 		}
 
 		Ok(self.inner.sign_holder_commitment_htlc_transactions(commitment_tx, secp_ctx).unwrap())
 	}
 
+	fn sign_holder_htlc_input<T: secp256k1::Signing + secp256k1::Verification>(&self, htlc_tx: &Transaction, input: usize, commitment_tx: &HolderCommitmentTransaction, htlc_index: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		self.verify_holder_commitment_tx(commitment_tx, secp_ctx);
+		Ok(self.inner.sign_holder_htlc_input(htlc_tx, input, commitment_tx, htlc_index, secp_ctx).unwrap())
+	}
+
 	fn sign_justice_transaction<T: secp256k1::Signing + secp256k1::Verification>(&self, justice_tx: &Transaction, input: usize, amount: u64, per_commitment_key: &SecretKey, htlc: &Option<HTLCOutputInCommitment>, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		Ok(self.inner.sign_justice_transaction(justice_tx, input, amount, per_commitment_key, htlc, secp_ctx).unwrap())
 	}
@@ -112,6 +118,10 @@ impl ChannelKeys for EnforcingChannelKeys {
 		Ok(self.inner.sign_counterparty_htlc_transaction(htlc_tx, input, amount, per_commitment_point, htlc, secp_ctx).unwrap())
 	}
 
+	fn sign_holder_anchor_input<T: secp256k1::Signing>(&self, anchor_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		Ok(self.inner.sign_holder_anchor_input(anchor_tx, input, secp_ctx).unwrap())
+	}
+
 	fn sign_closing_transaction<T: secp256k1::Signing>(&self, closing_tx: &Transaction, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
 		Ok(self.inner.sign_closing_transaction(closing_tx, secp_ctx).unwrap())
 	}
@@ -123,6 +133,14 @@ impl ChannelKeys for EnforcingChannelKeys {
 	fn ready_channel(&mut self, channel_parameters: &ChannelTransactionParameters) {
 		self.inner.ready_channel(channel_parameters)
 	}
+
+	fn sign_splice_funding_input<T: secp256k1::Signing>(&self, splice_tx: &Transaction, input: usize, secp_ctx: &Secp256k1<T>) -> Result<Signature, ()> {
+		Ok(self.inner.sign_splice_funding_input(splice_tx, input, secp_ctx).unwrap())
+	}
+
+	fn splice_channel(&mut self, channel_parameters: &ChannelTransactionParameters) {
+		self.inner.splice_channel(channel_parameters)
+	}
 }
 
 
@@ -15,17 +15,98 @@
 //! few other things.
 
 use ln::msgs;
-use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
+use ln::features::InitFeatures;
+use ln::channelmanager::{PaymentId, PaymentPreimage, PaymentHash, PaymentSecret};
+use routing::router::RouteHop;
 use chain::transaction::OutPoint;
 use chain::keysinterface::SpendableOutputDescriptor;
 use util::ser::{Writeable, Writer, MaybeReadable, Readable};
 
 use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::Txid;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
 
 use bitcoin::secp256k1::key::PublicKey;
 
 use std::time::Duration;
 
+/// The reason a channel was closed, as reported in Event::ChannelClosed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClosureReason {
+	/// The channel was closed cooperatively, or with ChannelManager::close_channel before it was
+	/// funded.
+	CooperativeClosure,
+	/// We force-closed the channel, either through ChannelManager::force_close_channel (or
+	/// ChannelManager::abandon_unfunded_channel) or because our ChannelMonitor went on-chain to
+	/// claim an HTLC.
+	HolderForceClosed,
+	/// Our counterparty force-closed the channel by sending us an error message.
+	CounterpartyForceClosed {
+		/// The error message our counterparty sent. Note that this is untrusted data and should
+		/// be sanitized before being displayed.
+		peer_msg: String,
+	},
+	/// A transaction spending the funding output, most likely a commitment transaction of our
+	/// counterparty, was confirmed on-chain.
+	CommitmentTxConfirmed,
+	/// The channel was never funded: either funding_created/funding_signed weren't exchanged
+	/// before enough calls to ChannelManager::timer_chan_freshness_every_min timed it out, or we
+	/// were the fundee and the funding transaction failed to confirm within
+	/// FUNDING_CONF_DEADLINE_BLOCKS blocks of us starting to wait for it.
+	FundingTimedOut,
+	/// We closed the channel because of an error, such as our counterparty violating the
+	/// protocol or a failure to persist the ChannelMonitor.
+	ProcessingError {
+		/// A description of the error.
+		err: String,
+	},
+	/// Our counterparty disconnected before the channel was funded, upon which it is forgotten,
+	/// or it was disconnected with no further connection possible.
+	DisconnectedPeer,
+}
+
+impl Writeable for ClosureReason {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		match self {
+			&ClosureReason::CooperativeClosure => 0u8.write(writer)?,
+			&ClosureReason::HolderForceClosed => 1u8.write(writer)?,
+			&ClosureReason::CounterpartyForceClosed { ref peer_msg } => {
+				2u8.write(writer)?;
+				peer_msg.as_bytes().to_vec().write(writer)?;
+			},
+			&ClosureReason::CommitmentTxConfirmed => 3u8.write(writer)?,
+			&ClosureReason::FundingTimedOut => 4u8.write(writer)?,
+			&ClosureReason::ProcessingError { ref err } => {
+				5u8.write(writer)?;
+				err.as_bytes().to_vec().write(writer)?;
+			},
+			&ClosureReason::DisconnectedPeer => 6u8.write(writer)?,
+		}
+		Ok(())
+	}
+}
+impl Readable for ClosureReason {
+	fn read<R: ::std::io::Read>(reader: &mut R) -> Result<Self, msgs::DecodeError> {
+		macro_rules! read_string {
+			() => {
+				String::from_utf8(Readable::read(reader)?).map_err(|_| msgs::DecodeError::InvalidValue)?
+			}
+		}
+		match <u8 as Readable>::read(reader)? {
+			0 => Ok(ClosureReason::CooperativeClosure),
+			1 => Ok(ClosureReason::HolderForceClosed),
+			2 => Ok(ClosureReason::CounterpartyForceClosed { peer_msg: read_string!() }),
+			3 => Ok(ClosureReason::CommitmentTxConfirmed),
+			4 => Ok(ClosureReason::FundingTimedOut),
+			5 => Ok(ClosureReason::ProcessingError { err: read_string!() }),
+			6 => Ok(ClosureReason::DisconnectedPeer),
+			_ => Err(msgs::DecodeError::InvalidValue),
+		}
+	}
+}
+
 /// An Event which you should probably take some action in response to.
 ///
 /// Note that while Writeable and Readable are implemented for Event, you probably shouldn't use
@@ -49,6 +130,26 @@ pub enum Event {
 		/// The value passed in to ChannelManager::create_channel
 		user_channel_id: u64,
 	},
+	/// Indicates a peer requested to open a channel with us, which should be either accepted with
+	/// ChannelManager::accept_inbound_channel or rejected with
+	/// ChannelManager::reject_inbound_channel.
+	/// Only generated if UserConfig::manually_accept_inbound_channels is set, after the request
+	/// passed UserConfig::peer_channel_config_limits.
+	/// Note that the request is dropped if the peer disconnects before it is accepted.
+	OpenChannelRequest {
+		/// The temporary_channel_id picked by the counterparty, which you'll need to pass into
+		/// ChannelManager::accept_inbound_channel or ChannelManager::reject_inbound_channel.
+		temporary_channel_id: [u8; 32],
+		/// The node_id of the counterparty requesting the channel.
+		counterparty_node_id: PublicKey,
+		/// The value, in satoshis, the counterparty funds the channel with.
+		funding_satoshis: u64,
+		/// The value, in thousandths of a satoshi, the counterparty pushes to us when the channel
+		/// is opened.
+		push_msat: u64,
+		/// The features the counterparty sent in its init message.
+		counterparty_features: InitFeatures,
+	},
 	/// Used to indicate that the client may now broadcast the funding transaction it created for a
 	/// channel. Broadcasting such a transaction prior to this event may lead to our counterparty
 	/// trivially stealing all funds in the funding transaction!
@@ -82,6 +183,14 @@ pub enum Event {
 		/// Note that, in any case, this value must be passed as-is to any fail or claim calls as
 		/// the HTLC index includes this value.
 		payment_secret: Option<PaymentSecret>,
+		/// The preimage to payment_hash, set only if this is a spontaneous (keysend) payment for
+		/// which the sender included the preimage in the onion. In that case there is no need to
+		/// look up the preimage: simply hand this one to ChannelManager::claim_funds.
+		payment_preimage: Option<PaymentPreimage>,
+		/// Application-specific (type, value) TLV records the sender included in the onion, sorted
+		/// by type. Only odd types of at least msgs::CUSTOM_TLV_TYPE_MIN are accepted, HTLCs
+		/// carrying unknown even types are failed back as the spec requires.
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 		/// The value, in thousandths of a satoshi, that this payment is for. Note that you must
 		/// compare this to the expected value before accepting the payment (as otherwise you are
 		/// providing proof-of-payment for less than the value you expected!).
@@ -89,29 +198,63 @@ pub enum Event {
 	},
 	/// Indicates an outbound payment we made succeeded (ie it made it all the way to its target
 	/// and we got back the payment preimage for it).
-	/// Note that duplicative PaymentSent Events may be generated - it is your responsibility to
-	/// deduplicate them by payment_preimage (which MUST be unique)!
+	/// One PaymentSent event is generated for each path of the payment which is claimed, thus
+	/// duplicative PaymentSent Events may be generated - it is your responsibility to
+	/// deduplicate them by payment_id (or by payment_preimage, which MUST be unique)!
+	/// ln::payment_retry::PaymentRetrier does so for the payments it makes.
 	PaymentSent {
 		/// The preimage to the hash given to ChannelManager::send_payment.
 		/// Note that this serves as a payment receipt, if you wish to have such a thing, you must
 		/// store it somehow!
 		payment_preimage: PaymentPreimage,
+		/// The id returned by ChannelManager::send_payment for the payment.
+		payment_id: PaymentId,
+		/// The path which was claimed.
+		path: Vec<RouteHop>,
+		/// The fee paid to the intermediary nodes along the claimed path, ie the sum of the
+		/// fee_msat of every hop but the last. For rebalances sent via
+		/// ChannelManager::send_rebalance this is the cost of the rebalance.
+		fee_paid_msat: u64,
 	},
-	/// Indicates an outbound payment we made failed. Probably some intermediary node dropped
-	/// something. You may wish to retry with a different route.
-	/// Note that duplicative PaymentFailed Events may be generated - it is your responsibility to
-	/// deduplicate them by payment_hash (which MUST be unique)!
+	/// Indicates a path of an outbound payment we made failed. Probably some intermediary node
+	/// dropped something. You may wish to retry the value of the failed path with a different
+	/// route via ChannelManager::retry_payment, or give up on the payment via
+	/// ChannelManager::abandon_payment.
+	/// One PaymentFailed event is generated for each failed path of a payment, see
+	/// all_paths_failed.
 	PaymentFailed {
 		/// The hash which was given to ChannelManager::send_payment.
 		payment_hash: PaymentHash,
+		/// The id returned by ChannelManager::send_payment for the payment.
+		payment_id: PaymentId,
+		/// Set if no other paths of the payment are still pending, ie if the recipient will not
+		/// receive any part of the payment unless it is retried.
+		all_paths_failed: bool,
 		/// Indicates the payment was rejected for some reason by the recipient. This implies that
 		/// the payment has failed, not just the route in question. If this is not set, you may
 		/// retry the payment via a different route.
 		rejected_by_dest: bool,
-#[cfg(test)]
+		/// The BOLT 4 failure code, if the failure could be decoded. Note that the sending node
+		/// may not have been honest about the reason.
 		error_code: Option<u16>,
-#[cfg(test)]
+		/// The data following the failure code, if any, eg the channel_update of an UPDATE
+		/// failure.
 		error_data: Option<Vec<u8>>,
+		/// The index in path of the hop which reported the failure. None if the HTLC never left
+		/// us or if the failure couldn't be attributed to any hop.
+		failing_hop_index: Option<usize>,
+		/// The node_id of the hop which reported the failure, see failing_hop_index.
+		failing_node_id: Option<PublicKey>,
+		/// The short_channel_id of the channel the failure relates to, if it isn't blamed on a
+		/// node as a whole. For forwarding nodes, this is the channel they were asked to forward
+		/// the HTLC over.
+		short_channel_id: Option<u64>,
+		/// The channel_update embedded in the failure, if any. It is also applied to the network
+		/// graph through MessageSendEvent::PaymentFailureNetworkUpdate if it is consistent with
+		/// the failure.
+		channel_update: Option<msgs::ChannelUpdate>,
+		/// The path the failed HTLC was sent over.
+		path: Vec<RouteHop>,
 	},
 	/// Used to indicate that ChannelManager::process_pending_htlc_forwards should be called at a
 	/// time in the future.
@@ -130,6 +273,36 @@ pub enum Event {
 		/// The outputs which you should store as spendable by you.
 		outputs: Vec<SpendableOutputDescriptor>,
 	},
+	/// Indicates a channel was closed, or dropped before it was funded, and will no longer be
+	/// returned by ChannelManager::list_channels. Any funds in the channel are claimed on-chain
+	/// by our ChannelMonitor, resulting in SpendableOutputs events.
+	/// Not generated for inbound channels which were never accepted, see
+	/// Event::OpenChannelRequest.
+	ChannelClosed {
+		/// The channel_id of the channel, which is its temporary_channel_id if it was dropped
+		/// before the funding transaction was known.
+		channel_id: [u8; 32],
+		/// The value passed in to ChannelManager::create_channel, or to
+		/// ChannelManager::accept_inbound_channel, for the channel. Inbound channels which were
+		/// accepted automatically have a user_id of 0.
+		user_id: u64,
+		/// Why the channel was closed.
+		reason: ClosureReason,
+	},
+	/// Used to indicate that a channel was dropped before its funding transaction was broadcast,
+	/// so that the wallet funding it can reuse the inputs it had set aside for it. The funding
+	/// transaction must not be broadcast anymore.
+	DiscardFunding {
+		/// The channel_id of the dropped channel.
+		channel_id: [u8; 32],
+		/// The txid of the funding transaction (or of the splice transaction, if the channel
+		/// was dropped while splicing).
+		funding_txid: Txid,
+		/// The transaction, if we built it with our counterparty for a dual-funded channel or a
+		/// splice. For other channels, this is None and funding_txid is the txid of the outpoint
+		/// which was given to ChannelManager::funding_transaction_generated.
+		transaction: Option<Transaction>,
+	},
 }
 
 impl Writeable for Event {
@@ -140,34 +313,47 @@ impl Writeable for Event {
 				// We never write out FundingGenerationReady events as, upon disconnection, peers
 				// drop any channels which have not yet exchanged funding_signed.
 			},
+			&Event::OpenChannelRequest { .. } => {
+				0u8.write(writer)?;
+				// Similarly, channel requests are dropped on disconnection, so are never written.
+			},
 			&Event::FundingBroadcastSafe { ref funding_txo, ref user_channel_id } => {
 				1u8.write(writer)?;
 				funding_txo.write(writer)?;
 				user_channel_id.write(writer)?;
 			},
-			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref amt } => {
-				2u8.write(writer)?;
+			&Event::PaymentReceived { ref payment_hash, ref payment_secret, ref payment_preimage, ref custom_tlvs, ref amt } => {
+				// Types 2, 3 and 4 are read for events written before the payment fields were
+				// extended, see MaybeReadable.
+				9u8.write(writer)?;
 				payment_hash.write(writer)?;
 				payment_secret.write(writer)?;
+				payment_preimage.write(writer)?;
+				custom_tlvs.write(writer)?;
 				amt.write(writer)?;
 			},
-			&Event::PaymentSent { ref payment_preimage } => {
-				3u8.write(writer)?;
+			&Event::PaymentSent { ref payment_preimage, ref payment_id, ref path, ref fee_paid_msat } => {
+				10u8.write(writer)?;
 				payment_preimage.write(writer)?;
+				payment_id.write(writer)?;
+				path.write(writer)?;
+				fee_paid_msat.write(writer)?;
 			},
-			&Event::PaymentFailed { ref payment_hash, ref rejected_by_dest,
-				#[cfg(test)]
-				ref error_code,
-				#[cfg(test)]
-				ref error_data,
+			&Event::PaymentFailed { ref payment_hash, ref payment_id, ref all_paths_failed, ref rejected_by_dest, ref error_code,
+				ref error_data, ref failing_hop_index, ref failing_node_id, ref short_channel_id, ref channel_update, ref path,
 			} => {
-				4u8.write(writer)?;
+				11u8.write(writer)?;
 				payment_hash.write(writer)?;
+				payment_id.write(writer)?;
+				all_paths_failed.write(writer)?;
 				rejected_by_dest.write(writer)?;
-				#[cfg(test)]
 				error_code.write(writer)?;
-				#[cfg(test)]
 				error_data.write(writer)?;
+				failing_hop_index.map(|idx| idx as u64).write(writer)?;
+				failing_node_id.write(writer)?;
+				short_channel_id.write(writer)?;
+				channel_update.write(writer)?;
+				path.write(writer)?;
 			},
 			&Event::PendingHTLCsForwardable { time_forwardable: _ } => {
 				5u8.write(writer)?;
@@ -181,6 +367,18 @@ impl Writeable for Event {
 					output.write(writer)?;
 				}
 			},
+			&Event::ChannelClosed { ref channel_id, ref user_id, ref reason } => {
+				7u8.write(writer)?;
+				channel_id.write(writer)?;
+				user_id.write(writer)?;
+				reason.write(writer)?;
+			},
+			&Event::DiscardFunding { ref channel_id, ref funding_txid, ref transaction } => {
+				8u8.write(writer)?;
+				channel_id.write(writer)?;
+				funding_txid.write(writer)?;
+				transaction.write(writer)?;
+			},
 		}
 		Ok(())
 	}
@@ -193,22 +391,47 @@ impl MaybeReadable for Event {
 					funding_txo: Readable::read(reader)?,
 					user_channel_id: Readable::read(reader)?,
 				})),
+			// Payment events written before payment ids, paths and custom TLVs were added, for
+			// which we identify the payment by its hash.
 			2u8 => Ok(Some(Event::PaymentReceived {
 					payment_hash: Readable::read(reader)?,
 					payment_secret: Readable::read(reader)?,
+					payment_preimage: None,
+					custom_tlvs: Vec::new(),
 					amt: Readable::read(reader)?,
 				})),
-			3u8 => Ok(Some(Event::PaymentSent {
-					payment_preimage: Readable::read(reader)?,
-				})),
-			4u8 => Ok(Some(Event::PaymentFailed {
-					payment_hash: Readable::read(reader)?,
+			3u8 => {
+				let payment_preimage: PaymentPreimage = Readable::read(reader)?;
+				Ok(Some(Event::PaymentSent {
+					payment_id: PaymentId(Sha256::hash(&payment_preimage.0[..]).into_inner()),
+					payment_preimage,
+					path: Vec::new(),
+					fee_paid_msat: 0,
+				}))
+			},
+			4u8 => {
+				let payment_hash: PaymentHash = Readable::read(reader)?;
+				Ok(Some(Event::PaymentFailed {
+					payment_id: PaymentId(payment_hash.0),
+					payment_hash,
+					all_paths_failed: true,
 					rejected_by_dest: Readable::read(reader)?,
+					// Only tests wrote the error out at the time
 					#[cfg(test)]
 					error_code: Readable::read(reader)?,
 					#[cfg(test)]
 					error_data: Readable::read(reader)?,
-				})),
+					#[cfg(not(test))]
+					error_code: None,
+					#[cfg(not(test))]
+					error_data: None,
+					failing_hop_index: None,
+					failing_node_id: None,
+					short_channel_id: None,
+					channel_update: None,
+					path: Vec::new(),
+				}))
+			},
 			5u8 => Ok(Some(Event::PendingHTLCsForwardable {
 					time_forwardable: Duration::from_secs(0)
 				})),
@@ -220,6 +443,45 @@ impl MaybeReadable for Event {
 				}
 				Ok(Some(Event::SpendableOutputs { outputs }))
 			},
+			7u8 => Ok(Some(Event::ChannelClosed {
+					channel_id: Readable::read(reader)?,
+					user_id: Readable::read(reader)?,
+					reason: Readable::read(reader)?,
+				})),
+			8u8 => Ok(Some(Event::DiscardFunding {
+					channel_id: Readable::read(reader)?,
+					funding_txid: Readable::read(reader)?,
+					transaction: Readable::read(reader)?,
+				})),
+			9u8 => Ok(Some(Event::PaymentReceived {
+					payment_hash: Readable::read(reader)?,
+					payment_secret: Readable::read(reader)?,
+					payment_preimage: Readable::read(reader)?,
+					custom_tlvs: Readable::read(reader)?,
+					amt: Readable::read(reader)?,
+				})),
+			10u8 => Ok(Some(Event::PaymentSent {
+					payment_preimage: Readable::read(reader)?,
+					payment_id: Readable::read(reader)?,
+					path: Readable::read(reader)?,
+					fee_paid_msat: Readable::read(reader)?,
+				})),
+			11u8 => Ok(Some(Event::PaymentFailed {
+					payment_hash: Readable::read(reader)?,
+					payment_id: Readable::read(reader)?,
+					all_paths_failed: Readable::read(reader)?,
+					rejected_by_dest: Readable::read(reader)?,
+					error_code: Readable::read(reader)?,
+					error_data: Readable::read(reader)?,
+					failing_hop_index: {
+						let idx: Option<u64> = Readable::read(reader)?;
+						idx.map(|idx| idx as usize)
+					},
+					failing_node_id: Readable::read(reader)?,
+					short_channel_id: Readable::read(reader)?,
+					channel_update: Readable::read(reader)?,
+					path: Readable::read(reader)?,
+				})),
 			_ => Err(msgs::DecodeError::InvalidValue)
 		}
 	}
@@ -246,6 +508,57 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::OpenChannel,
 	},
+	/// Used to indicate that we've accepted a dual-funded channel open and should send the
+	/// accept_channel2 message provided to the given peer.
+	SendAcceptChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::AcceptChannelV2,
+	},
+	/// Used to indicate that we've initiated a dual-funded channel open and should send the
+	/// open_channel2 message provided to the given peer.
+	SendOpenChannelV2 {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::OpenChannelV2,
+	},
+	/// Used to indicate that a tx_add_input message should be sent to the peer with the given node_id.
+	SendTxAddInput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddInput,
+	},
+	/// Used to indicate that a tx_add_output message should be sent to the peer with the given node_id.
+	SendTxAddOutput {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAddOutput,
+	},
+	/// Used to indicate that a tx_complete message should be sent to the peer with the given node_id.
+	SendTxComplete {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxComplete,
+	},
+	/// Used to indicate that a tx_signatures message should be sent to the peer with the given node_id.
+	SendTxSignatures {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxSignatures,
+	},
+	/// Used to indicate that a tx_abort message should be sent to the peer with the given node_id.
+	SendTxAbort {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::TxAbort,
+	},
 	/// Used to indicate that a funding_created message should be sent to the peer with the given node_id.
 	SendFundingCreated {
 		/// The node_id of the node which should receive this message
@@ -296,6 +609,34 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::ClosingSigned,
 	},
+	/// Used to indicate that an stfu message should be sent to the peer with the given node_id.
+	SendStfu {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::Stfu,
+	},
+	/// Used to indicate that a splice message should be sent to the peer with the given node_id.
+	SendSplice {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::Splice,
+	},
+	/// Used to indicate that a splice_ack message should be sent to the peer with the given node_id.
+	SendSpliceAck {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceAck,
+	},
+	/// Used to indicate that a splice_locked message should be sent to the peer with the given node_id.
+	SendSpliceLocked {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::SpliceLocked,
+	},
 	/// Used to indicate that a shutdown message should be sent to the peer with the given node_id.
 	SendShutdown {
 		/// The node_id of the node which should receive this message
@@ -362,6 +703,52 @@ pub enum MessageSendEvent {
 		/// The query_short_channel_ids which should be sent.
 		msg: msgs::QueryShortChannelIds,
 	},
+	/// Sends a reply to a channel range query. This may be one of several reply_channel_range
+	/// messages sent in response to a single query_channel_range.
+	SendReplyChannelRange {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The reply_channel_range which should be sent.
+		msg: msgs::ReplyChannelRange,
+	},
+	/// Sends a channel_announcement to a single peer, eg in response to a
+	/// query_short_channel_ids.
+	SendChannelAnnouncement {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The channel_announcement which should be sent.
+		msg: msgs::ChannelAnnouncement,
+	},
+	/// Sends a channel_update to a single peer, eg in response to a query_short_channel_ids.
+	SendChannelUpdate {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The channel_update which should be sent.
+		msg: msgs::ChannelUpdate,
+	},
+	/// Sends a node_announcement to a single peer, eg in response to a query_short_channel_ids.
+	SendNodeAnnouncement {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The node_announcement which should be sent.
+		msg: msgs::NodeAnnouncement,
+	},
+	/// Sends a reply_short_channel_ids_end, indicating that we have finished replying to a
+	/// query_short_channel_ids.
+	SendReplyShortChannelIdsEnd {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The reply_short_channel_ids_end which should be sent.
+		msg: msgs::ReplyShortChannelIdsEnd,
+	},
+	/// Sends a gossip_timestamp_filter to a peer, asking it to relay gossip within the given time
+	/// window to us.
+	SendGossipTimestampFilter {
+		/// The node_id of this message recipient
+		node_id: PublicKey,
+		/// The gossip_timestamp_filter which should be sent.
+		msg: msgs::GossipTimestampFilter,
+	},
 }
 
 /// A trait indicating an object may generate message send events
@@ -97,23 +97,25 @@ macro_rules! log_route {
 pub(crate) struct DebugTx<'a>(pub &'a Transaction);
 impl<'a> std::fmt::Display for DebugTx<'a> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+		// Inputs spending the HTLC outputs of option_anchors_zero_fee_htlc_tx channels have an
+		// nSequence of 1
 		if self.0.input.len() >= 1 && self.0.input.iter().any(|i| !i.witness.is_empty()) {
 			if self.0.input.len() == 1 && self.0.input[0].witness.last().unwrap().len() == 71 &&
 					(self.0.input[0].sequence >> 8*3) as u8 == 0x80 {
 				write!(f, "commitment tx")?;
 			} else if self.0.input.len() == 1 && self.0.input[0].witness.last().unwrap().len() == 71 {
 				write!(f, "closing tx")?;
-			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len()) == Some(HTLCType::OfferedHTLC) &&
+			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len(), self.0.input[0].sequence == 1) == Some(HTLCType::OfferedHTLC) &&
 					self.0.input[0].witness.len() == 5 {
 				write!(f, "HTLC-timeout tx")?;
-			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len()) == Some(HTLCType::AcceptedHTLC) &&
+			} else if self.0.input.len() == 1 && HTLCType::scriptlen_to_htlctype(self.0.input[0].witness.last().unwrap().len(), self.0.input[0].sequence == 1) == Some(HTLCType::AcceptedHTLC) &&
 					self.0.input[0].witness.len() == 5 {
 				write!(f, "HTLC-success tx")?;
 			} else {
 				for inp in &self.0.input {
 					if !inp.witness.is_empty() {
-						if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len()) == Some(HTLCType::OfferedHTLC) { write!(f, "preimage-")?; break }
-						else if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len()) == Some(HTLCType::AcceptedHTLC) { write!(f, "timeout-")?; break }
+						if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len(), inp.sequence == 1) == Some(HTLCType::OfferedHTLC) { write!(f, "preimage-")?; break }
+						else if HTLCType::scriptlen_to_htlctype(inp.witness.last().unwrap().len(), inp.sequence == 1) == Some(HTLCType::AcceptedHTLC) { write!(f, "timeout-")?; break }
 					}
 				}
 				write!(f, "tx")?;
@@ -15,6 +15,7 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::sync::Mutex;
 use std::cmp;
+use std::time::Duration;
 
 use bitcoin::secp256k1::Signature;
 use bitcoin::secp256k1::key::{PublicKey, SecretKey};
@@ -26,7 +27,7 @@ use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::hash_types::{Txid, BlockHash};
 use std::marker::Sized;
 use ln::msgs::DecodeError;
-use ln::channelmanager::{PaymentPreimage, PaymentHash, PaymentSecret};
+use ln::channelmanager::{PaymentId, PaymentPreimage, PaymentHash, PaymentSecret};
 use util::byte_utils;
 
 use util::byte_utils::{be64_to_array, be48_to_array, be32_to_array, be16_to_array, slice_to_be16, slice_to_be32, slice_to_be48, slice_to_be64};
@@ -394,6 +395,25 @@ impl Readable for bool {
 	}
 }
 
+impl Writeable for Duration {
+	#[inline]
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ::std::io::Error> {
+		self.as_secs().write(writer)?;
+		self.subsec_nanos().write(writer)
+	}
+}
+impl Readable for Duration {
+	#[inline]
+	fn read<R: Read>(reader: &mut R) -> Result<Duration, DecodeError> {
+		let secs: u64 = Readable::read(reader)?;
+		let nanos: u32 = Readable::read(reader)?;
+		if nanos >= 1_000_000_000 {
+			return Err(DecodeError::InvalidValue);
+		}
+		Ok(Duration::new(secs, nanos))
+	}
+}
+
 // u8 arrays
 macro_rules! impl_array {
 	( $size:expr ) => (
@@ -504,6 +524,28 @@ impl Readable for Vec<Signature> {
 	}
 }
 
+// For custom TLV records, as (type, value) pairs
+impl Writeable for Vec<(u64, Vec<u8>)> {
+	#[inline]
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		(self.len() as u16).write(w)?;
+		for e in self.iter() {
+			e.write(w)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Vec<(u64, Vec<u8>)> {
+	#[inline]
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let len: u16 = Readable::read(r)?;
+		let mut ret = Vec::new();
+		for _ in 0..len { ret.push(Readable::read(r)?); }
+		Ok(ret)
+	}
+}
+
 impl Writeable for Script {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		(self.len() as u16).write(w)?;
@@ -624,6 +666,19 @@ impl Readable for PaymentSecret {
 	}
 }
 
+impl Writeable for PaymentId {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
+		self.0.write(w)
+	}
+}
+
+impl Readable for PaymentId {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let buf: [u8; 32] = Readable::read(r)?;
+		Ok(PaymentId(buf))
+	}
+}
+
 impl<T: Writeable> Writeable for Option<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), ::std::io::Error> {
 		match *self {
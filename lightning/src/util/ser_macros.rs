@@ -42,7 +42,15 @@ macro_rules! encode_varint_length_prefixed_tlv {
 }
 
 macro_rules! decode_tlv {
-	($stream: expr, {$(($reqtype: expr, $reqfield: ident)),*}, {$(($type: expr, $field: ident)),*}) => { {
+	($stream: expr, {$(($reqtype: expr, $reqfield: ident)),*}, {$(($type: expr, $field: ident)),*}) => {
+		decode_tlv!(@internal $stream, {$(($reqtype, $reqfield)),*}, {$(($type, $field)),*}, {})
+	};
+	// Odd types of at least ln::msgs::CUSTOM_TLV_TYPE_MIN which we don't otherwise know are pushed
+	// as raw (type, value) pairs onto the given Vec<(u64, Vec<u8>)> instead of being ignored.
+	($stream: expr, {$(($reqtype: expr, $reqfield: ident)),*}, {$(($type: expr, $field: ident)),*}, $custom_tlvs: ident) => {
+		decode_tlv!(@internal $stream, {$(($reqtype, $reqfield)),*}, {$(($type, $field)),*}, {$custom_tlvs})
+	};
+	(@internal $stream: expr, {$(($reqtype: expr, $reqfield: ident)),*}, {$(($type: expr, $field: ident)),*}, {$($custom_tlvs: ident)*}) => { {
 		use ln::msgs::DecodeError;
 		let mut last_seen_type: Option<u64> = None;
 		'tlv_read: loop {
@@ -99,6 +107,11 @@ macro_rules! decode_tlv {
 						Err(DecodeError::InvalidValue)?
 					}
 				},)*
+				$(x if x % 2 == 1 && x >= ::ln::msgs::CUSTOM_TLV_TYPE_MIN => {
+					let mut value = Vec::new();
+					::std::io::Read::read_to_end(&mut s, &mut value)?;
+					$custom_tlvs.push((x, value));
+				},)*
 				x if x % 2 == 0 => {
 					Err(DecodeError::UnknownRequiredFeature)?
 				},
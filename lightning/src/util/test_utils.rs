@@ -24,12 +24,17 @@ use util::logger::{Logger, Level, Record};
 use util::ser::{Readable, ReadableArgs, Writer, Writeable};
 
 use bitcoin::blockdata::constants::genesis_block;
-use bitcoin::blockdata::transaction::{Transaction, TxOut};
+use bitcoin::blockdata::transaction::{Transaction, TxIn, TxOut, SigHashType};
+use bitcoin::blockdata::transaction::OutPoint as BitcoinOutPoint;
 use bitcoin::blockdata::script::{Builder, Script};
 use bitcoin::blockdata::opcodes;
 use bitcoin::network::constants::Network;
-use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::hash_types::{BlockHash, Txid, WPubkeyHash};
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::util::bip143;
 
+use bitcoin::secp256k1;
 use bitcoin::secp256k1::{SecretKey, PublicKey, Secp256k1, Signature};
 
 use regex;
@@ -52,11 +57,11 @@ impl Writer for TestVecWriter {
 }
 
 pub struct TestFeeEstimator {
-	pub sat_per_kw: u32,
+	pub sat_per_kw: Mutex<u32>,
 }
 impl chaininterface::FeeEstimator for TestFeeEstimator {
 	fn get_est_sat_per_1000_weight(&self, _confirmation_target: ConfirmationTarget) -> u32 {
-		self.sat_per_kw
+		*self.sat_per_kw.lock().unwrap()
 	}
 }
 
@@ -78,18 +83,18 @@ impl keysinterface::KeysInterface for OnlyReadsKeysInterface {
 pub struct TestChainMonitor<'a> {
 	pub added_monitors: Mutex<Vec<(OutPoint, channelmonitor::ChannelMonitor<EnforcingChannelKeys>)>>,
 	pub latest_monitor_update_id: Mutex<HashMap<[u8; 32], (OutPoint, u64)>>,
-	pub chain_monitor: chainmonitor::ChainMonitor<EnforcingChannelKeys, &'a TestChainSource, &'a chaininterface::BroadcasterInterface, &'a TestFeeEstimator, &'a TestLogger, &'a channelmonitor::Persist<EnforcingChannelKeys>>,
+	pub chain_monitor: chainmonitor::ChainMonitor<EnforcingChannelKeys, &'a TestChainSource, &'a chaininterface::BroadcasterInterface, &'a TestFeeEstimator, &'a TestLogger, &'a channelmonitor::Persist<EnforcingChannelKeys>, &'a TestUtxoSource>,
 	pub update_ret: Mutex<Option<Result<(), channelmonitor::ChannelMonitorUpdateErr>>>,
 	// If this is set to Some(), after the next return, we'll always return this until update_ret
 	// is changed:
 	pub next_update_ret: Mutex<Option<Result<(), channelmonitor::ChannelMonitorUpdateErr>>>,
 }
 impl<'a> TestChainMonitor<'a> {
-	pub fn new(chain_source: Option<&'a TestChainSource>, broadcaster: &'a chaininterface::BroadcasterInterface, logger: &'a TestLogger, fee_estimator: &'a TestFeeEstimator, persister: &'a channelmonitor::Persist<EnforcingChannelKeys>) -> Self {
+	pub fn new(chain_source: Option<&'a TestChainSource>, broadcaster: &'a chaininterface::BroadcasterInterface, logger: &'a TestLogger, fee_estimator: &'a TestFeeEstimator, persister: &'a channelmonitor::Persist<EnforcingChannelKeys>, utxo_source: Option<&'a TestUtxoSource>) -> Self {
 		Self {
 			added_monitors: Mutex::new(Vec::new()),
 			latest_monitor_update_id: Mutex::new(HashMap::new()),
-			chain_monitor: chainmonitor::ChainMonitor::new(chain_source, broadcaster, logger, fee_estimator, persister),
+			chain_monitor: chainmonitor::ChainMonitor::with_utxo_source(chain_source, broadcaster, logger, fee_estimator, persister, utxo_source),
 			update_ret: Mutex::new(None),
 			next_update_ret: Mutex::new(None),
 		}
@@ -181,6 +186,82 @@ impl channelmonitor::Persist<EnforcingChannelKeys> for TestPersister {
 	}
 }
 
+pub struct TestUtxoSource {
+	secp_ctx: Secp256k1<secp256k1::All>,
+	wallet_key: SecretKey,
+	pub utxos: Mutex<Vec<chaininterface::WalletUtxo>>,
+}
+impl TestUtxoSource {
+	pub fn new(seed: &[u8; 32]) -> Self {
+		Self {
+			secp_ctx: Secp256k1::new(),
+			wallet_key: SecretKey::from_slice(&Sha256::hash(seed).into_inner()).unwrap(),
+			utxos: Mutex::new(Vec::new()),
+		}
+	}
+
+	pub fn wallet_script(&self) -> Script {
+		let pubkey_hash = WPubkeyHash::hash(&PublicKey::from_secret_key(&self.secp_ctx, &self.wallet_key).serialize());
+		Builder::new().push_opcode(opcodes::all::OP_PUSHBYTES_0).push_slice(&pubkey_hash[..]).into_script()
+	}
+
+	/// Adds a P2WPKH output of the given value to the wallet, in a new transaction as if it had
+	/// been confirmed.
+	pub fn add_utxo(&self, value: u64) -> chaininterface::WalletUtxo {
+		let mut utxos = self.utxos.lock().unwrap();
+		let prev_tx = Transaction {
+			version: 2,
+			lock_time: 0,
+			input: vec![TxIn {
+				previous_output: BitcoinOutPoint { txid: Default::default(), vout: utxos.len() as u32 },
+				script_sig: Script::new(),
+				sequence: 0xffffffff,
+				witness: Vec::new(),
+			}],
+			output: vec![TxOut { script_pubkey: self.wallet_script(), value }],
+		};
+		let utxo = chaininterface::WalletUtxo {
+			prev_tx,
+			prev_vout: 0,
+			// number_of_witness_elements + sig_length + sig + pubkey_length + pubkey
+			satisfaction_weight: 1 + 1 + 73 + 1 + 33,
+		};
+		utxos.push(utxo.clone());
+		utxo
+	}
+}
+impl chaininterface::UtxoSource for TestUtxoSource {
+	fn get_utxos(&self, target_value_sat: u64) -> Vec<chaininterface::WalletUtxo> {
+		let mut selected = Vec::new();
+		let mut selected_value = 0;
+		for utxo in self.utxos.lock().unwrap().iter() {
+			if selected_value >= target_value_sat { break; }
+			selected_value += utxo.output().value;
+			selected.push(utxo.clone());
+		}
+		selected
+	}
+
+	fn get_change_script(&self) -> Script {
+		self.wallet_script()
+	}
+
+	fn sign_input(&self, tx: &Transaction, input_idx: usize, utxo: &chaininterface::WalletUtxo) -> Result<Vec<Vec<u8>>, ()> {
+		Ok(sign_p2wpkh_input(&self.secp_ctx, &self.wallet_key, tx, input_idx, utxo.output().value))
+	}
+}
+
+fn sign_p2wpkh_input(secp_ctx: &Secp256k1<secp256k1::All>, key: &SecretKey, tx: &Transaction, input_idx: usize, value: u64) -> Vec<Vec<u8>> {
+	let pubkey = PublicKey::from_secret_key(secp_ctx, key);
+	let script_code = Builder::new().push_opcode(opcodes::all::OP_DUP).push_opcode(opcodes::all::OP_HASH160)
+		.push_slice(&WPubkeyHash::hash(&pubkey.serialize())[..]).push_opcode(opcodes::all::OP_EQUALVERIFY).push_opcode(opcodes::all::OP_CHECKSIG).into_script();
+	let sighash = secp256k1::Message::from_slice(&bip143::SigHashCache::new(tx)
+		.signature_hash(input_idx, &script_code, value, SigHashType::All)[..]).unwrap();
+	let mut sig = secp_ctx.sign(&sighash, key).serialize_der().to_vec();
+	sig.push(SigHashType::All as u8);
+	vec![sig, pubkey.serialize().to_vec()]
+}
+
 pub struct TestBroadcaster {
 	pub txn_broadcasted: Mutex<Vec<Transaction>>,
 }
@@ -205,11 +286,24 @@ impl TestChannelMessageHandler {
 impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_open_channel(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::OpenChannel) {}
 	fn handle_accept_channel(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::AcceptChannel) {}
+	fn handle_open_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::OpenChannelV2) {}
+	fn handle_accept_channel_v2(&self, _their_node_id: &PublicKey, _their_features: InitFeatures, _msg: &msgs::AcceptChannelV2) {}
+	fn handle_tx_add_input(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddInput) {}
+	fn handle_tx_add_output(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAddOutput) {}
+	fn handle_tx_remove_input(&self, _their_node_id: &PublicKey, _msg: &msgs::TxRemoveInput) {}
+	fn handle_tx_remove_output(&self, _their_node_id: &PublicKey, _msg: &msgs::TxRemoveOutput) {}
+	fn handle_tx_complete(&self, _their_node_id: &PublicKey, _msg: &msgs::TxComplete) {}
+	fn handle_tx_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::TxSignatures) {}
+	fn handle_tx_abort(&self, _their_node_id: &PublicKey, _msg: &msgs::TxAbort) {}
 	fn handle_funding_created(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingCreated) {}
 	fn handle_funding_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingSigned) {}
 	fn handle_funding_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::FundingLocked) {}
 	fn handle_shutdown(&self, _their_node_id: &PublicKey, _msg: &msgs::Shutdown) {}
 	fn handle_closing_signed(&self, _their_node_id: &PublicKey, _msg: &msgs::ClosingSigned) {}
+	fn handle_stfu(&self, _their_node_id: &PublicKey, _msg: &msgs::Stfu) {}
+	fn handle_splice(&self, _their_node_id: &PublicKey, _msg: &msgs::Splice) {}
+	fn handle_splice_ack(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceAck) {}
+	fn handle_splice_locked(&self, _their_node_id: &PublicKey, _msg: &msgs::SpliceLocked) {}
 	fn handle_update_add_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateAddHTLC) {}
 	fn handle_update_fulfill_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFulfillHTLC) {}
 	fn handle_update_fail_htlc(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFailHTLC) {}
@@ -218,6 +312,7 @@ impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_revoke_and_ack(&self, _their_node_id: &PublicKey, _msg: &msgs::RevokeAndACK) {}
 	fn handle_update_fee(&self, _their_node_id: &PublicKey, _msg: &msgs::UpdateFee) {}
 	fn handle_announcement_signatures(&self, _their_node_id: &PublicKey, _msg: &msgs::AnnouncementSignatures) {}
+	fn handle_channel_update(&self, _their_node_id: &PublicKey, _msg: &msgs::ChannelUpdate) {}
 	fn handle_channel_reestablish(&self, _their_node_id: &PublicKey, _msg: &msgs::ChannelReestablish) {}
 	fn peer_disconnected(&self, _their_node_id: &PublicKey, _no_connection_possible: bool) {}
 	fn peer_connected(&self, _their_node_id: &PublicKey, _msg: &msgs::Init) {}
@@ -233,7 +328,7 @@ impl events::MessageSendEventsProvider for TestChannelMessageHandler {
 	}
 }
 
-fn get_dummy_channel_announcement(short_chan_id: u64) -> msgs::ChannelAnnouncement {
+pub fn get_dummy_channel_announcement(short_chan_id: u64) -> msgs::ChannelAnnouncement {
 	use bitcoin::secp256k1::ffi::Signature as FFISignature;
 	let secp_ctx = Secp256k1::new();
 	let network = Network::Testnet;
@@ -261,7 +356,7 @@ fn get_dummy_channel_announcement(short_chan_id: u64) -> msgs::ChannelAnnounceme
 	}
 }
 
-fn get_dummy_channel_update(short_chan_id: u64) -> msgs::ChannelUpdate {
+pub fn get_dummy_channel_update(short_chan_id: u64) -> msgs::ChannelUpdate {
 	use bitcoin::secp256k1::ffi::Signature as FFISignature;
 	let network = Network::Testnet;
 	msgs::ChannelUpdate {
@@ -286,6 +381,9 @@ pub struct TestRoutingMessageHandler {
 	pub chan_anns_recvd: AtomicUsize,
 	pub chan_anns_sent: AtomicUsize,
 	pub request_full_sync: AtomicBool,
+	/// If set, channel_announcements and channel_updates are treated as new, valid gossip which
+	/// should be relayed to our other peers.
+	pub relay_gossip: AtomicBool,
 }
 
 impl TestRoutingMessageHandler {
@@ -295,6 +393,7 @@ impl TestRoutingMessageHandler {
 			chan_anns_recvd: AtomicUsize::new(0),
 			chan_anns_sent: AtomicUsize::new(0),
 			request_full_sync: AtomicBool::new(false),
+			relay_gossip: AtomicBool::new(false),
 		}
 	}
 }
@@ -304,10 +403,12 @@ impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
 	}
 	fn handle_channel_announcement(&self, _msg: &msgs::ChannelAnnouncement) -> Result<bool, msgs::LightningError> {
 		self.chan_anns_recvd.fetch_add(1, Ordering::AcqRel);
+		if self.relay_gossip.load(Ordering::Acquire) { return Ok(true); }
 		Err(msgs::LightningError { err: "".to_owned(), action: msgs::ErrorAction::IgnoreError })
 	}
 	fn handle_channel_update(&self, _msg: &msgs::ChannelUpdate) -> Result<bool, msgs::LightningError> {
 		self.chan_upds_recvd.fetch_add(1, Ordering::AcqRel);
+		if self.relay_gossip.load(Ordering::Acquire) { return Ok(true); }
 		Err(msgs::LightningError { err: "".to_owned(), action: msgs::ErrorAction::IgnoreError })
 	}
 	fn handle_htlc_fail_channel_update(&self, _update: &msgs::HTLCFailChannelUpdate) {}
@@ -348,6 +449,10 @@ impl msgs::RoutingMessageHandler for TestRoutingMessageHandler {
 	fn handle_query_short_channel_ids(&self, _their_node_id: &PublicKey, _msg: msgs::QueryShortChannelIds) -> Result<(), msgs::LightningError> {
 		Ok(())
 	}
+
+	fn get_next_query_short_channel_ids_replies(&self, _their_node_id: &PublicKey, _batch_amount: u8) -> Vec<events::MessageSendEvent> {
+		Vec::new()
+	}
 }
 
 impl events::MessageSendEventsProvider for TestRoutingMessageHandler {
@@ -419,6 +524,7 @@ pub struct TestKeysInterface {
 	backing: keysinterface::KeysManager,
 	pub override_session_priv: Mutex<Option<[u8; 32]>>,
 	pub override_channel_id_priv: Mutex<Option<[u8; 32]>>,
+	pub utxo_source: TestUtxoSource,
 }
 
 impl keysinterface::KeysInterface for TestKeysInterface {
@@ -449,6 +555,10 @@ impl keysinterface::KeysInterface for TestKeysInterface {
 	fn read_chan_signer(&self, reader: &[u8]) -> Result<Self::ChanKeySigner, msgs::DecodeError> {
 		EnforcingChannelKeys::read(&mut std::io::Cursor::new(reader))
 	}
+
+	fn get_utxo_source(&self) -> Option<&chaininterface::UtxoSource> {
+		Some(&self.utxo_source)
+	}
 }
 
 impl TestKeysInterface {
@@ -458,11 +568,15 @@ impl TestKeysInterface {
 			backing: keysinterface::KeysManager::new(seed, network, now.as_secs(), now.subsec_nanos()),
 			override_session_priv: Mutex::new(None),
 			override_channel_id_priv: Mutex::new(None),
+			utxo_source: TestUtxoSource::new(seed),
 		}
 	}
 	pub fn derive_channel_keys(&self, channel_value_satoshis: u64, user_id_1: u64, user_id_2: u64) -> EnforcingChannelKeys {
 		EnforcingChannelKeys::new(self.backing.derive_channel_keys(channel_value_satoshis, user_id_1, user_id_2))
 	}
+	pub fn spend_spendable_outputs(&self, descriptors: &[&keysinterface::SpendableOutputDescriptor], outputs: Vec<TxOut>, change_destination_script: Script, feerate_sat_per_1000_weight: u32) -> Result<Transaction, ()> {
+		self.backing.spend_spendable_outputs(descriptors, outputs, change_destination_script, feerate_sat_per_1000_weight, &Secp256k1::new())
+	}
 }
 
 pub struct TestChainSource {